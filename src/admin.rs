@@ -0,0 +1,905 @@
+//! Administrative operations.
+//!
+//! `Admin:HealthCheck` checks that the deployment is ready to crawl: the required environment variables are set, and
+//! the S3 bucket, DynamoDB tables, and each subsystem's SSM parameters are reachable. It is meant to be sent once after
+//! a deployment as a smoke test, before any real crawl starts.
+//!
+//! `Admin:ListCoverage` publishes the [coverage registry][crate::coverage] to S3 so operators and the frontend can see
+//! which portals are crawled.
+//!
+//! `Admin:AuditCrawl` reports the domains a crawl contacted, with request and byte counts, from the tallies the
+//! [request audit][crate::httpext::RequestAudit] stored with the crawl, for compliance reviews. The crawl id is taken
+//! from the request's crawl parameters. Domains the crawl tried to contact but its clients' allowlists refused are
+//! called out.
+//!
+//! `Admin:CompareCrawls` compares the crawl in the request's crawl parameters (A) against the crawl named by
+//! `CompareTo` (B), from their [journals][crate::journal] and audits: the pages fetched and opportunities parsed by one
+//! but not the other, and the differences in bytes received and errors. It is meant for validating a parser or pacing
+//! change on a trial crawl before switching schedules to the new configuration.
+//!
+//! `Admin:CleanUpAttachments` finds [attachments][crate::attachment] no opportunity links to any longer and tags them
+//! for the bucket's lifecycle policy to expire. It is meant to be run on a schedule.
+//!
+//! `Admin:Requeue` re-enqueues the requests whose [failure records][crate::dead_letter] match the crawl in the
+//! request's crawl parameters, `Subsystem`, and `ErrorClass`, each optional, with their attempts reset. It is meant for
+//! recovering the work that failed under old code once a fix is deployed. The records of requeued requests are removed;
+//! their original messages stay in the dead-letter queue until it is purged.
+//!
+//! `Admin:Redact` masks the `RedactPatterns` in the stored bodies named by `S3Keys` and those fetched from URLs
+//! matching `UrlPattern`, and records an audit item for each body changed; see [redaction][crate::redact]. It is meant
+//! for data subject requests.
+//!
+//! `Admin:ReplaySpilledWrites` writes the request log items the [log writer][crate::httpext::DdbBatchWriter] spilled
+//! to S3 while the log table was throttling writes, once the table has capacity again.
+//!
+//! `Admin:SetUpSearch` creates the [search][crate::search] index with its mappings, if it doesn't exist, and stores the
+//! keyword search template. It is meant to be sent once after `OPENSEARCH_ENDPOINT` is first set, and again after the
+//! template changes.
+//!
+//! `Admin:TraceLinks` reports from the [link graph][crate::link_graph] the links that led to the request's URL, within
+//! the crawl in the request's crawl parameters or, without one, across every crawl. Given only a crawl, it reports the
+//! pages the crawl's operations worked on that no link led to, other than the page it started from.
+//!
+//! `Admin:PauseCrawl` pauses the crawl in the request's crawl parameters, whose messages are then
+//! [held back][crate::middleware::CrawlPause] on delivery, and exports its [frontier][crate::frontier] to S3.
+//! `Admin:ResumeCrawl` resumes it, letting the held-back messages run. Given the `FrontierKey` of an export instead,
+//! it resumes the exported crawl, which may come from another deployment, re-enqueueing the export's outstanding
+//! requests.
+use {
+    crate::{
+        attachment, bidnet, canary,
+        cost::Usage,
+        coverage,
+        dead_letter::FailureFilter,
+        frontier,
+        health::publish_alert,
+        httpext::{aws_err_str, DomainTraffic, LogConfig, REQUIRED_ENV_VARS},
+        journal::CrawlEvent,
+        link_graph::{self, Link},
+        publicpurchase,
+        redact::{self, RedactionTarget},
+        shapes::{normalize_url, CrawlParameters, NextRequest, Operation, Request, Response},
+        webs,
+    },
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::BTreeSet,
+        env,
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+const OP_AUDIT_CRAWL: &str = "AuditCrawl";
+const OP_CLEAN_UP_ATTACHMENTS: &str = "CleanUpAttachments";
+const OP_COMPARE_CRAWLS: &str = "CompareCrawls";
+const OP_HEALTH_CHECK: &str = "HealthCheck";
+const OP_LIST_COVERAGE: &str = "ListCoverage";
+const OP_PAUSE_CRAWL: &str = "PauseCrawl";
+const OP_REDACT: &str = "Redact";
+const OP_REPLAY_SPILLED_WRITES: &str = "ReplaySpilledWrites";
+const OP_REQUEUE: &str = "Requeue";
+const OP_RESUME_CRAWL: &str = "ResumeCrawl";
+const OP_SET_UP_SEARCH: &str = "SetUpSearch";
+const OP_TRACE_LINKS: &str = "TraceLinks";
+
+/// The most requests `Admin:Requeue` re-enqueues at once, so a broad filter can't flood the queue. Running it again
+/// picks up where it left off, since the records of requeued requests are removed.
+const MAX_REQUEUE: usize = 1000;
+
+/// Possible administrative operations.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum AdminOperation {
+    /// Report the domains a crawl contacted.
+    AuditCrawl,
+
+    /// Tag orphaned attachments for expiry.
+    CleanUpAttachments,
+
+    /// Compare what two crawls found.
+    CompareCrawls,
+
+    /// Check that the deployment is configured correctly.
+    HealthCheck,
+
+    /// Publish the registry of supported portals.
+    ListCoverage,
+
+    /// Pause a crawl and export its frontier.
+    PauseCrawl,
+
+    /// Mask text in stored bodies.
+    Redact,
+
+    /// Write the log items spilled while the log table was throttling writes.
+    ReplaySpilledWrites,
+
+    /// Re-enqueue failed requests.
+    Requeue,
+
+    /// Resume a paused or exported crawl.
+    ResumeCrawl,
+
+    /// Create the search index and store its keyword template.
+    SetUpSearch,
+
+    /// Report the links that led to a page, or the pages of a crawl no link led to.
+    TraceLinks,
+}
+
+/// The outcome of a single readiness check.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ReadinessCheck {
+    /// What was checked, e.g. `DynamoDbTable:crawl-log`.
+    pub name: String,
+
+    /// Whether the check passed.
+    pub passed: bool,
+
+    /// Details of the failure, if the check did not pass.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The outcomes of all readiness checks.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ReadinessReport {
+    /// The individual checks, in the order they were run.
+    pub checks: Vec<ReadinessCheck>,
+}
+
+/// The domains a crawl contacted.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CrawlAudit {
+    /// The crawl audited.
+    pub crawl_id: String,
+
+    /// The requests made to each domain, ordered by domain.
+    pub domains: Vec<DomainTraffic>,
+
+    /// The total number of requests sent.
+    pub requests: u64,
+
+    /// The total number of response body bytes received.
+    pub bytes: u64,
+}
+
+impl CrawlAudit {
+    /// Summarize the tallies of a crawl's domains.
+    pub fn new(crawl_id: impl Into<String>, domains: Vec<DomainTraffic>) -> Self {
+        Self {
+            crawl_id: crawl_id.into(),
+            requests: domains.iter().map(|traffic| traffic.requests).sum(),
+            bytes: domains.iter().map(|traffic| traffic.bytes).sum(),
+            domains,
+        }
+    }
+
+    /// Return the domains requests were refused for.
+    pub fn refused(&self) -> impl Iterator<Item = &DomainTraffic> {
+        self.domains.iter().filter(|traffic| traffic.refused > 0)
+    }
+}
+
+/// What a crawl found, summarized from its journal and audit.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CrawlSummary {
+    /// The crawl summarized.
+    pub crawl_id: String,
+
+    /// The canonical URLs of the pages fetched.
+    #[serde(skip)]
+    pub pages: BTreeSet<String>,
+
+    /// The URLs of the opportunities parsed.
+    #[serde(skip)]
+    pub opportunities: BTreeSet<String>,
+
+    /// The number of distinct pages fetched.
+    pub page_count: usize,
+
+    /// The number of distinct opportunities parsed.
+    pub opportunity_count: usize,
+
+    /// The number of response body bytes received, if the crawl was audited.
+    pub bytes: u64,
+
+    /// The number of fetches answered with an HTTP error status.
+    pub http_errors: u64,
+
+    /// The number of fetches that failed before a complete response was received.
+    pub fetch_failures: u64,
+
+    /// The number of pages that could not be parsed.
+    pub parse_failures: u64,
+
+    /// The number of operations that failed.
+    pub operation_failures: u64,
+
+    /// The resources the crawl used and their estimated cost.
+    pub usage: Usage,
+}
+
+/// The differences between two crawls.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CrawlComparison {
+    /// The first crawl (A).
+    pub a: CrawlSummary,
+
+    /// The second crawl (B).
+    pub b: CrawlSummary,
+
+    /// Pages fetched by A but not B.
+    pub pages_only_in_a: Vec<String>,
+
+    /// Pages fetched by B but not A.
+    pub pages_only_in_b: Vec<String>,
+
+    /// Opportunities parsed by A but not B.
+    pub opportunities_only_in_a: Vec<String>,
+
+    /// Opportunities parsed by B but not A.
+    pub opportunities_only_in_b: Vec<String>,
+
+    /// Bytes received by B less those received by A.
+    pub bytes_delta: i64,
+
+    /// Errors of every kind in B less those in A.
+    pub error_delta: i64,
+}
+
+/// The provenance of a page, or the pages of a crawl no link led to.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LinkTrace {
+    /// The crawl traced, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crawl_id: Option<String>,
+
+    /// The page traced, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+
+    /// The links that led to the page.
+    pub referrers: Vec<Link>,
+
+    /// The pages the crawl worked on that no link led to.
+    pub unlinked_pages: Vec<String>,
+}
+
+impl CrawlSummary {
+    /// Summarize a crawl from its journal events and the bytes its audit recorded.
+    pub fn new(crawl_id: impl Into<String>, events: &[CrawlEvent], bytes: u64) -> Self {
+        let mut summary = Self {
+            crawl_id: crawl_id.into(),
+            bytes,
+            ..Self::default()
+        };
+
+        for event in events {
+            match event {
+                CrawlEvent::PageFetched {
+                    url,
+                    status,
+                    ..
+                } => {
+                    summary.pages.insert(normalize_url(url).unwrap_or_else(|_| url.clone()));
+                    if *status >= 400 {
+                        summary.http_errors += 1;
+                    }
+                }
+                CrawlEvent::FetchFailed {
+                    ..
+                } => summary.fetch_failures += 1,
+                CrawlEvent::QualityAssessed {
+                    url,
+                    ..
+                } => {
+                    summary.opportunities.insert(url.clone());
+                }
+                CrawlEvent::ParseFailed {
+                    ..
+                } => summary.parse_failures += 1,
+                CrawlEvent::OperationFailed {
+                    ..
+                } => summary.operation_failures += 1,
+                _ => (),
+            }
+        }
+
+        summary.page_count = summary.pages.len();
+        summary.opportunity_count = summary.opportunities.len();
+        summary
+    }
+
+    /// Return the number of errors of every kind.
+    pub fn errors(&self) -> u64 {
+        self.http_errors + self.fetch_failures + self.parse_failures + self.operation_failures
+    }
+}
+
+impl CrawlComparison {
+    /// Compare crawl A against crawl B.
+    pub fn new(a: CrawlSummary, b: CrawlSummary) -> Self {
+        let difference = |x: &BTreeSet<String>, y: &BTreeSet<String>| x.difference(y).cloned().collect::<Vec<_>>();
+
+        Self {
+            pages_only_in_a: difference(&a.pages, &b.pages),
+            pages_only_in_b: difference(&b.pages, &a.pages),
+            opportunities_only_in_a: difference(&a.opportunities, &b.opportunities),
+            opportunities_only_in_b: difference(&b.opportunities, &a.opportunities),
+            bytes_delta: b.bytes as i64 - a.bytes as i64,
+            error_delta: b.errors() as i64 - a.errors() as i64,
+            a,
+            b,
+        }
+    }
+}
+
+impl FromStr for AdminOperation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            OP_AUDIT_CRAWL => Ok(Self::AuditCrawl),
+            OP_CLEAN_UP_ATTACHMENTS => Ok(Self::CleanUpAttachments),
+            OP_COMPARE_CRAWLS => Ok(Self::CompareCrawls),
+            OP_HEALTH_CHECK => Ok(Self::HealthCheck),
+            OP_LIST_COVERAGE => Ok(Self::ListCoverage),
+            OP_PAUSE_CRAWL => Ok(Self::PauseCrawl),
+            OP_REDACT => Ok(Self::Redact),
+            OP_REPLAY_SPILLED_WRITES => Ok(Self::ReplaySpilledWrites),
+            OP_REQUEUE => Ok(Self::Requeue),
+            OP_RESUME_CRAWL => Ok(Self::ResumeCrawl),
+            OP_SET_UP_SEARCH => Ok(Self::SetUpSearch),
+            OP_TRACE_LINKS => Ok(Self::TraceLinks),
+            _ => Err(format!("Unknown operation: {value}")),
+        }
+    }
+}
+
+impl Display for AdminOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.operation())
+    }
+}
+
+impl AdminOperation {
+    /// Handle a request.
+    pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+        match self {
+            Self::AuditCrawl => audit_crawl(log_config, req, context).await,
+            Self::CleanUpAttachments => clean_up_attachments(log_config, req, context).await,
+            Self::CompareCrawls => compare_crawls(log_config, req, context).await,
+            Self::HealthCheck => health_check(log_config, req, context).await,
+            Self::ListCoverage => list_coverage(log_config, req, context).await,
+            Self::PauseCrawl => pause_crawl(log_config, req, context).await,
+            Self::Redact => redact(log_config, req, context).await,
+            Self::ReplaySpilledWrites => replay_spilled_writes(log_config, req, context).await,
+            Self::Requeue => requeue(log_config, req, context).await,
+            Self::ResumeCrawl => resume_crawl(log_config, req, context).await,
+            Self::SetUpSearch => set_up_search(log_config, req, context).await,
+            Self::TraceLinks => trace_links(log_config, req, context).await,
+        }
+    }
+
+    /// Return the operation name within the subsystem.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::AuditCrawl => OP_AUDIT_CRAWL,
+            Self::CleanUpAttachments => OP_CLEAN_UP_ATTACHMENTS,
+            Self::CompareCrawls => OP_COMPARE_CRAWLS,
+            Self::HealthCheck => OP_HEALTH_CHECK,
+            Self::ListCoverage => OP_LIST_COVERAGE,
+            Self::PauseCrawl => OP_PAUSE_CRAWL,
+            Self::Redact => OP_REDACT,
+            Self::ReplaySpilledWrites => OP_REPLAY_SPILLED_WRITES,
+            Self::Requeue => OP_REQUEUE,
+            Self::ResumeCrawl => OP_RESUME_CRAWL,
+            Self::SetUpSearch => OP_SET_UP_SEARCH,
+            Self::TraceLinks => OP_TRACE_LINKS,
+        }
+    }
+}
+
+impl ReadinessReport {
+    /// Record the outcome of a check.
+    pub fn record<E: Display>(&mut self, name: impl Into<String>, result: Result<(), E>) {
+        let name = name.into();
+        let error = result.err().map(|e| e.to_string());
+        if let Some(error) = error.as_ref() {
+            warn!("Readiness check {name} failed: {error}");
+        }
+
+        self.checks.push(ReadinessCheck {
+            name,
+            passed: error.is_none(),
+            error,
+        });
+    }
+
+    /// Return the checks that did not pass.
+    pub fn failures(&self) -> impl Iterator<Item = &ReadinessCheck> {
+        self.checks.iter().filter(|check| !check.passed)
+    }
+
+    /// Indicates whether every check passed.
+    pub fn is_ready(&self) -> bool {
+        self.failures().next().is_none()
+    }
+}
+
+impl Display for ReadinessReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        for check in self.checks.iter() {
+            match check.error.as_ref() {
+                None => writeln!(f, "- {}: ok", check.name)?,
+                Some(error) => writeln!(f, "- {}: FAILED: {error}", check.name)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Check that the deployment is ready to crawl.
+///
+/// The report is logged as JSON. If any check fails, an alert is published and the operation fails so the smoke test
+/// is visibly red.
+async fn health_check(log_config: LogConfig, _req: Request, _context: Context) -> Result<Response, LambdaError> {
+    let mut report = ReadinessReport::default();
+
+    for var in REQUIRED_ENV_VARS {
+        report.record(format!("EnvVar:{var}"), env::var(var).map(|_| ()));
+    }
+
+    let result = log_config.s3_client.head_bucket().bucket(&log_config.s3_bucket).send().await;
+    report.record(format!("S3Bucket:{}", log_config.s3_bucket), result.map(|_| ()).map_err(|e| aws_err_str(&e)));
+
+    let tables: BTreeSet<String> = [
+        log_config.ddb_table.clone(),
+        log_config.journal.table_name().to_string(),
+        log_config.checkpoints.table_name().to_string(),
+        log_config.crawls.table_name().to_string(),
+        log_config.sessions.table_name().to_string(),
+        log_config.attachments.table_name().to_string(),
+        log_config.opportunities.table_name().to_string(),
+        log_config.failures.table_name().to_string(),
+        log_config.links.table_name().to_string(),
+        log_config.frontier.table_name().to_string(),
+        log_config.vendors.table_name().to_string(),
+        canary::targets_table(&log_config),
+    ]
+    .into();
+    for table in tables {
+        let result = log_config.ddb_client.describe_table().table_name(&table).send().await;
+        report.record(format!("DynamoDbTable:{table}"), result.map(|_| ()).map_err(|e| aws_err_str(&e)));
+    }
+
+    report.record("SsmParameters:Webs", webs::load_accounts(&log_config).await.map(|_| ()));
+    report.record("SsmParameters:PublicPurchase", publicpurchase::check_credentials(&log_config).await);
+    report.record("SsmParameters:BidNet", bidnet::check_credentials(&log_config).await);
+
+    info!("Readiness report: {}", serde_json::to_string(&report)?);
+
+    if !report.is_ready() {
+        let failed = report.failures().count();
+        let _ = publish_alert(&log_config, "GovScout deployment is not ready", &report.to_string()).await;
+        return Err(format!("{failed} of {} readiness checks failed:\n{report}", report.checks.len()).into());
+    }
+
+    info!("All {} readiness checks passed", report.checks.len());
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Log the coverage registry and publish it to S3.
+async fn list_coverage(log_config: LogConfig, _req: Request, _context: Context) -> Result<Response, LambdaError> {
+    let registry = coverage::registry();
+    info!("Coverage: {}", serde_json::to_string(&registry)?);
+
+    let key = coverage::publish(&log_config).await?;
+    info!("Published coverage of {} portals to s3://{}/{key}", registry.len(), log_config.s3_bucket);
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Write the log items spilled to S3 while the log table was throttling writes.
+async fn replay_spilled_writes(
+    log_config: LogConfig,
+    _req: Request,
+    _context: Context,
+) -> Result<Response, LambdaError> {
+    let replayed = log_config.ddb_writer.replay_spills().await?;
+    info!("Replayed {replayed} spilled item(s) to {}", log_config.ddb_writer.table_name());
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Log the domains a crawl contacted.
+async fn audit_crawl(log_config: LogConfig, req: Request, _context: Context) -> Result<Response, LambdaError> {
+    let Some(crawl_id) = req.crawl.crawl_id.as_deref() else {
+        return Err("AuditCrawl requires a crawl id".into());
+    };
+
+    let audit = CrawlAudit::new(crawl_id, log_config.crawls.audit_report(crawl_id).await?);
+    info!("Crawl audit: {}", serde_json::to_string(&audit)?);
+
+    if audit.domains.is_empty() {
+        warn!("No requests audited for crawl {crawl_id}; is CRAWL_AUDIT enabled?");
+    }
+
+    for traffic in audit.refused() {
+        warn!("Crawl {crawl_id} tried to contact {} {} time(s) outside its allowlist", traffic.domain, traffic.refused);
+    }
+
+    info!(
+        "Crawl {crawl_id} made {} request(s) to {} domain(s), receiving {} bytes",
+        audit.requests,
+        audit.domains.len(),
+        audit.bytes
+    );
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Summarize a crawl from its journal and audit.
+async fn summarize_crawl(log_config: &LogConfig, crawl_id: &str) -> Result<CrawlSummary, LambdaError> {
+    let events = log_config.journal.events(crawl_id).await?;
+    let audit = CrawlAudit::new(crawl_id, log_config.crawls.audit_report(crawl_id).await?);
+    if events.is_empty() {
+        warn!("No journal events found for crawl {crawl_id}");
+    }
+
+    Ok(CrawlSummary {
+        usage: log_config.crawls.usage(crawl_id).await?,
+        ..CrawlSummary::new(crawl_id, &events, audit.bytes)
+    })
+}
+
+/// Log the differences between two crawls.
+async fn compare_crawls(log_config: LogConfig, req: Request, _context: Context) -> Result<Response, LambdaError> {
+    let (Some(crawl_a), Some(crawl_b)) = (req.crawl.crawl_id.as_deref(), req.compare_to.as_deref()) else {
+        return Err("CompareCrawls requires a crawl id and a crawl to compare it to".into());
+    };
+
+    let a = summarize_crawl(&log_config, crawl_a).await?;
+    let b = summarize_crawl(&log_config, crawl_b).await?;
+    let comparison = CrawlComparison::new(a, b);
+    info!("Crawl comparison: {}", serde_json::to_string(&comparison)?);
+
+    info!(
+        "Crawl {crawl_a} found {} opportunities and {crawl_b} found {}; {} only in {crawl_a}, {} only in {crawl_b}; \
+         bytes delta {}, error delta {}",
+        comparison.a.opportunity_count,
+        comparison.b.opportunity_count,
+        comparison.opportunities_only_in_a.len(),
+        comparison.opportunities_only_in_b.len(),
+        comparison.bytes_delta,
+        comparison.error_delta
+    );
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Remove the records of orphaned attachments and tag their objects for expiry.
+async fn clean_up_attachments(
+    log_config: LogConfig,
+    _req: Request,
+    _context: Context,
+) -> Result<Response, LambdaError> {
+    let cleaned = attachment::clean_up_orphans(&log_config).await?;
+    info!("Tagged {cleaned} orphaned attachment(s) for expiry");
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Create the search index and store its keyword template.
+async fn set_up_search(log_config: LogConfig, _req: Request, _context: Context) -> Result<Response, LambdaError> {
+    let search = log_config.search.as_ref().ok_or("Search is not configured; OPENSEARCH_ENDPOINT is not set")?;
+    search.set_up().await?;
+    info!("Set up search index {}", search.index());
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Re-enqueue the failed requests matching the request's filter, with their attempts reset.
+async fn requeue(log_config: LogConfig, req: Request, _context: Context) -> Result<Response, LambdaError> {
+    let filter = FailureFilter {
+        crawl_id: req.crawl.crawl_id.clone(),
+        subsystem: req.subsystem.clone(),
+        error_class: req.error_class.clone(),
+    };
+
+    let records = log_config.failures.find(&filter).await?;
+    if records.len() > MAX_REQUEUE {
+        warn!("{} failed request(s) match {filter:?}; requeueing the first {MAX_REQUEUE}", records.len());
+    }
+
+    let mut next_requests = Vec::new();
+    for record in records.into_iter().take(MAX_REQUEUE) {
+        let operation = match Operation::from_str(&record.request.operation) {
+            Ok(operation) => operation,
+            Err(e) => {
+                warn!("Not requeueing message {}: {e}", record.message_id);
+                continue;
+            }
+        };
+
+        log_config.failures.clear(record.request.crawl.crawl_id.as_deref(), &record.message_id).await?;
+        info!(
+            "Requeueing {} {:?} (message {}, {})",
+            operation, record.request.url, record.message_id, record.error_class
+        );
+        next_requests.push(NextRequest {
+            operation,
+            url: record.request.url,
+            link_text: None,
+            crawl: CrawlParameters {
+                attempt: 0,
+                failed_attempts: vec![],
+                ..record.request.crawl
+            },
+            delay_seconds: 0,
+        });
+    }
+
+    info!("Requeued {} failed request(s) matching {filter:?}", next_requests.len());
+    Ok(Response {
+        next_requests,
+    })
+}
+
+/// Mask the request's patterns in the stored bodies it selects, by S3 key or URL pattern.
+async fn redact(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    if req.redact_patterns.iter().all(|pattern| pattern.is_empty()) {
+        return Err("Redact requires at least one pattern to redact".into());
+    }
+
+    if req.s3_keys.is_empty() && req.url_pattern.is_none() {
+        return Err("Redact requires S3 keys or a URL pattern".into());
+    }
+
+    let mut targets: BTreeSet<RedactionTarget> = req
+        .s3_keys
+        .iter()
+        .map(|key| RedactionTarget {
+            bucket: log_config.s3_bucket.clone(),
+            key: key.clone(),
+        })
+        .collect();
+
+    if let Some(url_pattern) = req.url_pattern.as_deref() {
+        targets.extend(redact::find_by_url(&log_config, url_pattern).await?);
+    }
+
+    let mut redacted = 0;
+    for target in &targets {
+        let matches = redact::redact_body(&log_config, target, &req.redact_patterns, &context.request_id).await?;
+        if matches > 0 {
+            info!("Redacted {matches} match(es) from s3://{}/{}", target.bucket, target.key);
+            redacted += 1;
+        }
+    }
+
+    info!("Redacted {redacted} of {} stored bod(ies)", targets.len());
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Pause the request's crawl and export its frontier to S3.
+async fn pause_crawl(log_config: LogConfig, req: Request, _context: Context) -> Result<Response, LambdaError> {
+    let Some(crawl_id) = req.crawl.crawl_id.as_deref() else {
+        return Err("PauseCrawl requires a crawl id".into());
+    };
+
+    log_config.crawls.set_paused(crawl_id, true).await?;
+    let key = frontier::export(&log_config, crawl_id).await?;
+    info!("Paused crawl {crawl_id}; resume it with FrontierKey {key}");
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Resume a crawl, re-enqueueing the outstanding requests of a frontier export if one is given.
+///
+/// A paused crawl's own messages keep circulating while it is paused, so resuming it in place re-enqueues nothing.
+async fn resume_crawl(log_config: LogConfig, req: Request, _context: Context) -> Result<Response, LambdaError> {
+    let (crawl_id, outstanding) = match (req.frontier_key.as_deref(), req.crawl.crawl_id.as_deref()) {
+        (Some(key), _) => {
+            let export = frontier::import(&log_config, key).await?;
+            (export.crawl.crawl_id, export.outstanding)
+        }
+        (None, Some(crawl_id)) => (crawl_id.to_string(), vec![]),
+        (None, None) => return Err("ResumeCrawl requires a crawl id or a frontier export".into()),
+    };
+
+    log_config.crawls.set_paused(&crawl_id, false).await?;
+    info!("Resumed crawl {crawl_id}; re-enqueueing {} outstanding request(s)", outstanding.len());
+
+    Ok(Response {
+        next_requests: outstanding,
+    })
+}
+
+/// Log the links that led to the request's URL, or the pages of the request's crawl no link led to.
+async fn trace_links(log_config: LogConfig, req: Request, _context: Context) -> Result<Response, LambdaError> {
+    let crawl_id = req.crawl.crawl_id.clone();
+    let url = req.url.as_deref().map(|url| normalize_url(url).unwrap_or_else(|_| url.to_string()));
+    let mut trace = LinkTrace {
+        crawl_id: crawl_id.clone(),
+        url: url.clone(),
+        ..LinkTrace::default()
+    };
+
+    match (crawl_id.as_deref(), url.as_deref()) {
+        (Some(crawl_id), Some(url)) => trace.referrers = log_config.links.links(crawl_id, Some(url)).await?,
+        (None, Some(url)) => trace.referrers = log_config.links.referrers(url).await?,
+        (Some(crawl_id), None) => {
+            let links = log_config.links.links(crawl_id, None).await?;
+            let pages: Vec<String> = log_config
+                .journal
+                .events(crawl_id)
+                .await?
+                .into_iter()
+                .filter_map(|event| match event {
+                    CrawlEvent::OperationStarted {
+                        url,
+                        ..
+                    } => url,
+                    _ => None,
+                })
+                .collect();
+
+            // The crawl was started by its first operation.
+            let entry_points: Vec<&str> = pages.first().map(String::as_str).into_iter().collect();
+            trace.unlinked_pages = link_graph::unlinked_pages(&links, pages.iter().map(String::as_str), &entry_points)
+                .into_iter()
+                .collect();
+        }
+        (None, None) => return Err("TraceLinks requires a URL or a crawl id".into()),
+    }
+
+    info!("Link trace: {}", serde_json::to_string(&trace)?);
+    for link in trace.referrers.iter() {
+        info!(
+            "Crawl {} reached {} from {} {} (position {}, text {:?})",
+            link.crawl_id,
+            link.child_url,
+            link.parent_operation,
+            link.parent_url.as_deref().unwrap_or("(no page)"),
+            link.position,
+            link.link_text.as_deref().unwrap_or_default()
+        );
+    }
+
+    for page in trace.unlinked_pages.iter() {
+        warn!("Crawl {} worked on {page} without any link to it", crawl_id.as_deref().unwrap_or_default());
+    }
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{CrawlAudit, CrawlComparison, CrawlSummary, ReadinessReport},
+        crate::{
+            httpext::{DomainTraffic, FetchErrorKind},
+            journal::CrawlEvent,
+        },
+    };
+
+    #[test]
+    fn crawl_comparison() {
+        let fetched = |url: &str, status| CrawlEvent::PageFetched {
+            url: url.to_string(),
+            status,
+            request_id: "request".to_string(),
+        };
+        let assessed = |url: &str| CrawlEvent::QualityAssessed {
+            url: url.to_string(),
+            parser: "Webs:OpportunityDetail".to_string(),
+            score: 100,
+            issues: vec![],
+        };
+
+        let a = CrawlSummary::new(
+            "a",
+            &[
+                fetched("https://example.com/bids?page=1", 200),
+                fetched("https://example.com/bid/1", 200),
+                fetched("https://example.com/bid/2", 500),
+                assessed("https://example.com/bid/1"),
+            ],
+            3000,
+        );
+        let b = CrawlSummary::new(
+            "b",
+            &[
+                fetched("https://example.com/bids?page=1#top", 200),
+                fetched("https://example.com/bid/1", 200),
+                fetched("https://example.com/bid/3", 200),
+                CrawlEvent::FetchFailed {
+                    url: "https://example.com/bid/4".to_string(),
+                    error_kind: FetchErrorKind::ReadTimeout,
+                    error: "operation timed out".to_string(),
+                    attempt: 0,
+                    request_id: "request".to_string(),
+                },
+                assessed("https://example.com/bid/1"),
+                assessed("https://example.com/bid/3"),
+            ],
+            2500,
+        );
+        assert_eq!((a.page_count, a.opportunity_count, a.errors()), (3, 1, 1));
+        assert_eq!((b.page_count, b.fetch_failures, b.errors()), (3, 1, 1));
+
+        let comparison = CrawlComparison::new(a, b);
+        assert_eq!(comparison.pages_only_in_a, vec!["https://example.com/bid/2"]);
+        assert_eq!(comparison.pages_only_in_b, vec!["https://example.com/bid/3"]);
+        assert!(comparison.opportunities_only_in_a.is_empty());
+        assert_eq!(comparison.opportunities_only_in_b, vec!["https://example.com/bid/3"]);
+        assert_eq!((comparison.bytes_delta, comparison.error_delta), (-500, 0));
+    }
+
+    #[test]
+    fn crawl_audit() {
+        let traffic = |domain: &str, requests, refused, bytes| DomainTraffic {
+            domain: domain.to_string(),
+            requests,
+            failures: 0,
+            refused,
+            bytes,
+        };
+        let audit = CrawlAudit::new(
+            "crawl",
+            vec![traffic("bids.example.gov", 10, 0, 5000), traffic("tracker.example.com", 0, 2, 0)],
+        );
+        assert_eq!((audit.requests, audit.bytes), (10, 5000));
+        assert_eq!(audit.refused().map(|t| t.domain.as_str()).collect::<Vec<_>>(), vec!["tracker.example.com"]);
+        assert!(serde_json::to_string(&audit).unwrap().starts_with(
+            r#"{"CrawlId":"crawl","Domains":[{"Domain":"bids.example.gov","Requests":10,"Failures":0,"Refused":0"#
+        ));
+    }
+
+    #[test]
+    fn readiness_report() {
+        let mut report = ReadinessReport::default();
+        report.record("EnvVar:A", Ok::<(), String>(()));
+        assert!(report.is_ready());
+
+        report.record("EnvVar:B", Err("not set"));
+        assert!(!report.is_ready());
+        assert_eq!(report.failures().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!["EnvVar:B"]);
+        assert_eq!(report.to_string(), "- EnvVar:A: ok\n- EnvVar:B: FAILED: not set\n");
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"Checks":[{"Name":"EnvVar:A","Passed":true},{"Name":"EnvVar:B","Passed":false,"Error":"not set"}]}"#
+        );
+    }
+}
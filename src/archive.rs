@@ -0,0 +1,435 @@
+//! Request/response types for backfilling historical opportunities from the Internet Archive's Wayback Machine.
+//!
+//! Opportunities published before GovScout crawled a portal often survive in the Wayback Machine's captures of it. The
+//! `Archive:Backfill` operation recovers them in three steps, each a request of its own:
+//!
+//! 1. Without a URL, it schedules itself for every target listed under `Targets` in the `Archive` configuration
+//!    document.
+//! 2. Given a portal's URL, it queries the [CDX API] for the distinct captures of that URL, or of every URL under it
+//!    for a target with `Prefix` set, between the target's `From` and `To` timestamps, and schedules itself for each.
+//! 3. Given a capture's URL, it fetches the page as originally archived and runs the parser of the portal the page
+//!    came from over it. A listing's links are scheduled as in step 2, so the pages it linked to are backfilled too; a
+//!    solicitation's page is recorded as an opportunity stamped with when it was captured.
+//!
+//! ```json
+//! {
+//!     "Targets": [{
+//!         "Url": "https://www.merx.com/public/solicitations/open",
+//!         "Prefix": true,
+//!         "From": "2015",
+//!         "To": "20191231"
+//!     }],
+//!     "CaptureLimit": 500
+//! }
+//! ```
+//!
+//! A page's portal is recognized by its host: first the [covered][crate::coverage] portals whose parsers can read an
+//! archived page on their own (MERX and PublicPurchase), then the portals with [extraction rules][crate::rules].
+//! Captures of pages saying the opportunity is gone are skipped. Backfilled opportunities aren't
+//! [recorded as seen][crate::closure::record_seen], since they closed long ago.
+//!
+//! [CDX API]: https://github.com/internetarchive/wayback/tree/master/wayback-cdx-server
+use {
+    crate::{
+        closure,
+        config::load_subsystem_config,
+        contacts, coverage,
+        httpext::{ContentKind, HostAllowlist, LogConfig, ResponseExt},
+        journal::CrawlEvent,
+        merx,
+        opportunity::{DateLocale, Opportunity},
+        publicpurchase, quality, rules,
+        shapes::{NextRequest, Operation, Request, Response, SUBSYS_ARCHIVE, SUBSYS_MERX, SUBSYS_PUBLIC_PURCHASE},
+        BoxError,
+    },
+    chrono::{DateTime, NaiveDateTime, Utc},
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    reqwest::Url,
+    serde::{Deserialize, Serialize},
+    std::{
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+const OP_BACKFILL: &str = "Backfill";
+
+/// The host serving captures and the CDX API.
+const WAYBACK_HOST: &str = "web.archive.org";
+
+/// The CDX API endpoint listing captures.
+const CDX_API_URL: &str = "https://web.archive.org/cdx/search/cdx";
+
+/// The prefix of capture URLs, followed by the capture's timestamp and the original URL.
+const CAPTURE_URL_PREFIX: &str = "https://web.archive.org/web/";
+
+/// The suffix of a capture's timestamp that asks for the page as originally archived, without the Wayback Machine's
+/// toolbar or rewritten links.
+const ORIGINAL_CONTENT_FLAG: &str = "id_/";
+
+/// The format of capture timestamps.
+const TIMESTAMP_FORMAT: &str = "%Y%m%d%H%M%S";
+
+/// The most captures a CDX query returns unless the configuration says otherwise.
+const DEFAULT_CAPTURE_LIMIT: usize = 1000;
+
+/// The `Archive` configuration document.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+struct ArchiveConfig {
+    /// The portal URLs to backfill.
+    targets: Vec<BackfillTarget>,
+
+    /// The most captures each CDX query returns. Defaults to 1,000.
+    capture_limit: Option<usize>,
+}
+
+/// A portal URL to backfill.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BackfillTarget {
+    /// The URL to find captures of, usually a listing.
+    url: String,
+
+    /// Whether to find captures of every URL starting with `url`, such as a listing's other pages, too.
+    #[serde(default)]
+    prefix: bool,
+
+    /// The earliest capture to backfill, as a timestamp prefix such as `2015` or `20150601`.
+    #[serde(default)]
+    from: Option<String>,
+
+    /// The latest capture to backfill, as a timestamp prefix.
+    #[serde(default)]
+    to: Option<String>,
+}
+
+/// A capture of a page.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Capture {
+    /// When the page was captured.
+    captured_at: DateTime<Utc>,
+
+    /// The page's original URL.
+    original: Url,
+}
+
+/// A page read from a capture by the parser of its portal.
+pub(crate) struct ArchivedPage {
+    /// The parser that read the page, e.g. `Merx:Solicitation`.
+    pub parser: String,
+
+    /// The version of the parser.
+    pub parser_version: u32,
+
+    /// What the parser read from the page.
+    pub content: Result<ArchivedContent, BoxError>,
+}
+
+/// What an archived page holds.
+pub(crate) enum ArchivedContent {
+    /// A listing's links to solicitations' pages or further listings.
+    Links(Vec<Url>),
+
+    /// A solicitation.
+    Opportunity(Box<Opportunity>),
+}
+
+/// Possible operations for the Internet Archive backfill.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum ArchiveOperation {
+    /// Schedule every configured target, find the captures of the portal page given as the URL, or parse the capture
+    /// given as the URL.
+    Backfill,
+}
+
+impl FromStr for ArchiveOperation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            OP_BACKFILL => Ok(Self::Backfill),
+            _ => Err(format!("Unknown operation: {value}")),
+        }
+    }
+}
+
+impl Display for ArchiveOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.operation())
+    }
+}
+
+impl ArchiveOperation {
+    /// Handle a request.
+    pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+        match self {
+            Self::Backfill => backfill(log_config, req, context).await,
+        }
+    }
+
+    /// Return the operation name within the subsystem.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::Backfill => OP_BACKFILL,
+        }
+    }
+}
+
+impl Capture {
+    /// Read a capture from its URL, e.g. `https://web.archive.org/web/20160314092653id_/https://www.merx.com/...`.
+    /// Returns `None` for any other URL.
+    fn from_url(url: &Url) -> Option<Self> {
+        let (timestamp, original) = url.as_str().strip_prefix(CAPTURE_URL_PREFIX)?.split_once(ORIGINAL_CONTENT_FLAG)?;
+        Some(Self {
+            captured_at: NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?.and_utc(),
+            original: Url::parse(original).ok()?,
+        })
+    }
+
+    /// Return the URL the page is fetched from as originally archived.
+    fn url(&self) -> String {
+        format!(
+            "{CAPTURE_URL_PREFIX}{}{ORIGINAL_CONTENT_FLAG}{}",
+            self.captured_at.format(TIMESTAMP_FORMAT),
+            self.original
+        )
+    }
+}
+
+/// Return the CDX query for the distinct captures of `url` returned with a `200 OK` status as HTML, limited by the
+/// target `url` is, if any.
+fn cdx_query(url: &Url, target: Option<&BackfillTarget>, limit: usize) -> Url {
+    let mut query = Url::parse(CDX_API_URL).expect("CDX_API_URL is a valid URL");
+    {
+        let mut pairs = query.query_pairs_mut();
+        pairs
+            .append_pair("url", url.as_str())
+            .append_pair("output", "json")
+            .append_pair("fl", "timestamp,original")
+            .append_pair("filter", "statuscode:200")
+            .append_pair("filter", "mimetype:text/html")
+            .append_pair("collapse", "digest")
+            .append_pair("limit", &limit.to_string());
+
+        if let Some(target) = target {
+            if target.prefix {
+                pairs.append_pair("matchType", "prefix");
+            }
+
+            if let Some(from) = target.from.as_deref() {
+                pairs.append_pair("from", from);
+            }
+
+            if let Some(to) = target.to.as_deref() {
+                pairs.append_pair("to", to);
+            }
+        }
+    }
+
+    query
+}
+
+/// Read the captures from a CDX API response: a JSON array of rows, the first naming the fields.
+fn parse_cdx_response(body: &[u8]) -> Result<Vec<Capture>, BoxError> {
+    let rows: Vec<Vec<String>> = serde_json::from_slice(body).map_err(|e| format!("Invalid CDX API response: {e}"))?;
+    let mut captures = vec![];
+
+    for row in rows.iter().skip(1) {
+        let [timestamp, original] = row.as_slice() else {
+            return Err(format!("CDX API returned a row without the requested fields: {row:?}").into());
+        };
+
+        let capture = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok().zip(Url::parse(original).ok());
+        match capture {
+            Some((captured_at, original)) => captures.push(Capture {
+                captured_at: captured_at.and_utc(),
+                original,
+            }),
+            None => warn!("Skipping unreadable capture {timestamp} of {original}"),
+        }
+    }
+
+    Ok(captures)
+}
+
+/// Read an archived page with the parser of the portal it came from, or return `None` if no parser reads it.
+async fn parse_archived_page(
+    log_config: &LogConfig,
+    text: &str,
+    page_url: &Url,
+    date_locale: DateLocale,
+) -> Result<Option<ArchivedPage>, BoxError> {
+    let host = page_url.host_str().unwrap_or_default();
+    let subsystem = coverage::registry()
+        .into_iter()
+        .find(|portal| {
+            Url::parse(&portal.url).is_ok_and(|url| url.host_str().is_some_and(|h| h.eq_ignore_ascii_case(host)))
+        })
+        .map(|portal| portal.subsystem);
+
+    match subsystem.as_deref() {
+        Some(SUBSYS_MERX) => Ok(merx::parse_archived_page(text, page_url)),
+        Some(SUBSYS_PUBLIC_PURCHASE) => Ok(publicpurchase::parse_archived_page(text, page_url, date_locale)),
+        Some(_) => Ok(None),
+        None => rules::parse_archived_page(log_config, text, page_url, date_locale).await,
+    }
+}
+
+/// Return a request to backfill a URL.
+fn backfill_request(req: &Request, url: String) -> NextRequest {
+    NextRequest {
+        operation: Operation::Archive(ArchiveOperation::Backfill),
+        url: Some(url),
+        link_text: None,
+        crawl: req.crawl.child(),
+        delay_seconds: 0,
+    }
+}
+
+/// Schedule every configured target, find the captures of a portal page, or parse a capture, depending on the URL.
+async fn backfill(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let config: ArchiveConfig = load_subsystem_config(&log_config, SUBSYS_ARCHIVE).await?;
+
+    let Some(url) = req.url.as_deref() else {
+        return Ok(Response {
+            next_requests: config.targets.into_iter().map(|target| backfill_request(&req, target.url)).collect(),
+        });
+    };
+
+    let url = Url::parse(url)?;
+    let client = req
+        .crawl
+        .build_client(log_config.clone(), &context)
+        .portal(SUBSYS_ARCHIVE)
+        .allowed_hosts(HostAllowlist::new([WAYBACK_HOST]))
+        .build()?;
+
+    let Some(capture) = Capture::from_url(&url) else {
+        let target = config.targets.iter().find(|target| Url::parse(&target.url).is_ok_and(|target| target == url));
+        let query = cdx_query(&url, target, config.capture_limit.unwrap_or(DEFAULT_CAPTURE_LIMIT));
+        let response = match client.get(query).send().await.error_for_status() {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Failed to query captures of {url}: {e}");
+                return Err(e);
+            }
+        };
+
+        let captures = parse_cdx_response(&response.bytes())?;
+        info!("Found {} captures of {url}", captures.len());
+        return Ok(Response {
+            next_requests: captures.iter().map(|capture| backfill_request(&req, capture.url())).collect(),
+        });
+    };
+
+    let response = match client.get(url.clone()).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch capture {url}: {e}");
+            return Err(e);
+        }
+    };
+
+    let empty = Response {
+        next_requests: vec![],
+    };
+
+    if response.content_kind() != ContentKind::Html {
+        warn!("Skipping capture {url}, which isn't an HTML page");
+        return Ok(empty);
+    }
+
+    let text = response.text()?;
+    if let Some(reason) = closure::soft_not_found(text, &[], &req.crawl.locale.not_found_markers) {
+        info!("Skipping capture {url}: {reason}");
+        return Ok(empty);
+    }
+
+    let Some(page) = parse_archived_page(&log_config, text, &capture.original, req.crawl.locale.dates).await? else {
+        warn!("No parser reads archived pages from {}", capture.original);
+        return Ok(empty);
+    };
+
+    let event = match &page.content {
+        Ok(content) => CrawlEvent::ParseSucceeded {
+            url: url.to_string(),
+            parser: page.parser.clone(),
+            items: match content {
+                ArchivedContent::Links(links) => links.len(),
+                ArchivedContent::Opportunity(_) => 1,
+            },
+        },
+        Err(e) => CrawlEvent::ParseFailed {
+            url: url.to_string(),
+            parser: page.parser.clone(),
+            error: e.to_string(),
+        },
+    };
+    log_config.journal.record(&client.crawl_id, event).await;
+
+    match page.content? {
+        ArchivedContent::Links(links) => Ok(Response {
+            next_requests: links.into_iter().map(|link| backfill_request(&req, link.to_string())).collect(),
+        }),
+        ArchivedContent::Opportunity(opportunity) => {
+            let mut opportunity =
+                (*opportunity).with_parser(&page.parser, page.parser_version).with_tenant(client.tenant.as_deref());
+            opportunity.archived_at = Some(capture.captured_at);
+            contacts::record_contacts(&log_config, &client, &mut opportunity, text).await;
+            quality::record_quality(&log_config, &client, &mut opportunity).await;
+            info!("Archive opportunity: {}", serde_json::to_string(&opportunity)?);
+            Ok(empty)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{cdx_query, parse_cdx_response, ArchiveConfig, Capture},
+        chrono::NaiveDate,
+        reqwest::Url,
+    };
+
+    #[test]
+    fn captures() {
+        let config: ArchiveConfig = serde_json::from_str(
+            r#"{"Targets": [{"Url": "https://www.merx.com/public/solicitations/open", "Prefix": true, "From": "2015"}]}"#,
+        )
+        .unwrap();
+        let target = &config.targets[0];
+        let listing = Url::parse(&target.url).unwrap();
+
+        let query = cdx_query(&listing, Some(target), 500);
+        assert_eq!(
+            query.as_str(),
+            "https://web.archive.org/cdx/search/cdx?url=https%3A%2F%2Fwww.merx.com%2Fpublic%2Fsolicitations%2Fopen\
+             &output=json&fl=timestamp%2Coriginal&filter=statuscode%3A200&filter=mimetype%3Atext%2Fhtml\
+             &collapse=digest&limit=500&matchType=prefix&from=2015"
+        );
+        assert!(!cdx_query(&listing, None, 500).as_str().contains("matchType"));
+
+        let captures = parse_cdx_response(
+            br#"[["timestamp","original"],
+                ["20160314092653","https://www.merx.com/public/solicitations/open?pageNumber=2"],
+                ["2016031409","https://www.merx.com/public/solicitations/open"]]"#,
+        )
+        .unwrap();
+        assert_eq!(captures.len(), 1);
+        assert_eq!(
+            captures[0].captured_at,
+            NaiveDate::from_ymd_opt(2016, 3, 14).unwrap().and_hms_opt(9, 26, 53).unwrap().and_utc()
+        );
+
+        let url = captures[0].url();
+        assert_eq!(
+            url,
+            "https://web.archive.org/web/20160314092653id_/https://www.merx.com/public/solicitations/open?pageNumber=2"
+        );
+        assert_eq!(Capture::from_url(&Url::parse(&url).unwrap()).as_ref(), Some(&captures[0]));
+        assert!(Capture::from_url(&listing).is_none());
+        assert!(parse_cdx_response(b"[]").unwrap().is_empty());
+    }
+}
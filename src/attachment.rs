@@ -0,0 +1,778 @@
+//! Deduplicated storage of opportunity attachments.
+//!
+//! Attachments are archived like any other response body, so each distinct attachment is stored once in S3 under its
+//! SHA-256 digest no matter how many amendments or portals publish it. This module tracks which opportunities
+//! reference each stored attachment:
+//!
+//! * An attachment record (`Attachment#{sha256}` / `Record`) holds the S3 location and a count of the links to it.
+//! * A link item (`Attachment#{sha256}` / `Link#{opportunity url}#{document url}`) ties the attachment to a document
+//!   of an opportunity.
+//! * An opportunity item (`AttachmentLinks#{opportunity url}` / `{document url}`) records which attachment each of
+//!   the opportunity's documents currently resolves to, so links can be dropped when an amendment replaces or removes
+//!   a document.
+//! * A contacts item (`AttachmentLinks#{opportunity url}` / `Contacts`) holds the [contacts][crate::contacts] found in
+//!   the opportunity's attachments, merged across amendments.
+//! * An orphan item (`AttachmentOrphans` / `{sha256}`) copies the S3 location of an attachment whose count has
+//!   dropped to zero.
+//!
+//! Attachments whose count has dropped to zero are orphans. `Admin:CleanUpAttachments` queries the orphan items,
+//! removes the records of attachments that are still unlinked, and tags their S3 objects with [`ORPHANED_TAG_KEY`],
+//! for the bucket's lifecycle policy to expire.
+use {
+    crate::{
+        contacts::{self, merge_contact},
+        extract,
+        httpext::{aws_err_str, log_aws_err, ContentKind, ContentMismatch, LogConfig, Response as HttpResponse},
+        journal::CrawlEvent,
+        opportunity::Contact,
+        search,
+        shapes::{normalize_url, CrawlParameters},
+        BoxError,
+    },
+    aws_sdk_dynamodb::{
+        operation::{delete_item::DeleteItemError, put_item::PutItemError, update_item::UpdateItemError},
+        types::{AttributeValue, ReturnValue},
+        Client as DynamoDbClient,
+    },
+    aws_sdk_s3::types::{Tag, Tagging},
+    aws_smithy_runtime_api::client::result::SdkError,
+    chrono::Utc,
+    log::*,
+    std::collections::HashMap,
+};
+
+const DDB_KEY_CRAWL_ID: &str = "CrawlId";
+const DDB_KEY_REQUEST_ID: &str = "RequestId";
+const DDB_KEY_S3_BUCKET: &str = "S3Bucket";
+const DDB_KEY_S3_KEY: &str = "S3Key";
+const DDB_KEY_CONTENT_LENGTH: &str = "ContentLength";
+const DDB_KEY_REF_COUNT: &str = "RefCount";
+const DDB_KEY_FIRST_LINKED_AT: &str = "FirstLinkedAt";
+const DDB_KEY_LAST_LINKED_AT: &str = "LastLinkedAt";
+const DDB_KEY_SHA256: &str = "Sha256";
+const DDB_KEY_OPPORTUNITY_URL: &str = "OpportunityUrl";
+const DDB_KEY_DOCUMENT_URL: &str = "DocumentUrl";
+const DDB_KEY_CONTACTS: &str = "Contacts";
+
+/// Partition key prefix for attachment records and their links.
+const ATTACHMENT_PARTITION_PREFIX: &str = "Attachment#";
+
+/// Partition key prefix for the attachments an opportunity links to.
+const OPPORTUNITY_PARTITION_PREFIX: &str = "AttachmentLinks#";
+
+/// Partition key of the orphan items.
+const ORPHAN_PARTITION_KEY: &str = "AttachmentOrphans";
+
+/// Sort key of the attachment record within its partition.
+const ATTACHMENT_RECORD_SORT_KEY: &str = "Record";
+
+/// Sort key of the contacts item within an opportunity's partition.
+const CONTACTS_SORT_KEY: &str = "Contacts";
+
+/// Sort key prefix of link items within an attachment's partition.
+const LINK_SORT_PREFIX: &str = "Link#";
+
+/// S3 object tag marking an orphaned attachment for the bucket's lifecycle policy.
+pub const ORPHANED_TAG_KEY: &str = "GovScoutOrphaned";
+
+/// Value of [`ORPHANED_TAG_KEY`] on orphaned attachments.
+const ORPHANED_TAG_VALUE: &str = "true";
+
+/// An attachment stored in S3.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StoredAttachment {
+    /// Hex-encoded SHA-256 digest of the attachment.
+    pub sha256: String,
+
+    /// The S3 bucket holding the attachment.
+    pub bucket: String,
+
+    /// The S3 key of the attachment.
+    pub key: String,
+
+    /// The size of the attachment, in bytes.
+    pub content_length: usize,
+}
+
+/// A document of an opportunity whose link to an attachment is recorded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LinkedDocument {
+    /// The document's URL.
+    pub document_url: String,
+
+    /// Hex-encoded SHA-256 digest of the attachment the document resolved to.
+    pub sha256: String,
+}
+
+/// Storage for attachment records and links in DynamoDB.
+#[derive(Clone, Debug)]
+pub struct AttachmentStore {
+    ddb_client: DynamoDbClient,
+    table_name: String,
+}
+
+impl AttachmentStore {
+    /// Create an attachment store using the given table.
+    pub fn new<S: Into<String>>(ddb_client: DynamoDbClient, table_name: S) -> Self {
+        Self {
+            ddb_client,
+            table_name: table_name.into(),
+        }
+    }
+
+    /// Return the name of the table attachments are recorded in.
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    /// Record that a document of an opportunity resolved to a stored attachment.
+    ///
+    /// If the document previously resolved to a different attachment (the portal replaced it in an amendment), that
+    /// link is dropped. Returns `true` if a new link was added.
+    pub async fn link(
+        &self,
+        attachment: &StoredAttachment,
+        opportunity_url: &str,
+        document_url: &str,
+    ) -> Result<bool, BoxError> {
+        let previous = self
+            .ddb_client
+            .put_item()
+            .table_name(&self.table_name)
+            .item(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{OPPORTUNITY_PARTITION_PREFIX}{opportunity_url}")))
+            .item(DDB_KEY_REQUEST_ID, AttributeValue::S(document_url.to_string()))
+            .item(DDB_KEY_SHA256, AttributeValue::S(attachment.sha256.clone()))
+            .return_values(ReturnValue::AllOld)
+            .send()
+            .await;
+        let previous = match previous {
+            Ok(output) => output.attributes.and_then(|item| string_attr(&item, DDB_KEY_SHA256)),
+            Err(e) => {
+                error!("PutItem attachment link for {opportunity_url}: {}", aws_err_str(&e));
+                return Err(e.into());
+            }
+        };
+
+        match previous {
+            Some(sha256) if sha256 == attachment.sha256 => return Ok(false),
+            Some(sha256) => {
+                info!("Document {document_url} of {opportunity_url} changed from attachment {sha256}");
+                self.remove_link(&sha256, opportunity_url, document_url).await?;
+            }
+            None => (),
+        }
+
+        let now = Utc::now().to_rfc3339();
+        let result = self
+            .ddb_client
+            .put_item()
+            .table_name(&self.table_name)
+            .item(DDB_KEY_CRAWL_ID, AttributeValue::S(attachment_partition_key(&attachment.sha256)))
+            .item(DDB_KEY_REQUEST_ID, AttributeValue::S(link_sort_key(opportunity_url, document_url)))
+            .item(DDB_KEY_OPPORTUNITY_URL, AttributeValue::S(opportunity_url.to_string()))
+            .item(DDB_KEY_DOCUMENT_URL, AttributeValue::S(document_url.to_string()))
+            .item(DDB_KEY_LAST_LINKED_AT, AttributeValue::S(now.clone()))
+            .condition_expression("attribute_not_exists(#pk)")
+            .expression_attribute_names("#pk", DDB_KEY_CRAWL_ID)
+            .send()
+            .await;
+        match result {
+            Ok(_) => (),
+            Err(SdkError::ServiceError(ref e))
+                if matches!(e.err(), PutItemError::ConditionalCheckFailedException(_)) =>
+            {
+                debug!("Attachment {} is already linked to {opportunity_url}", attachment.sha256);
+                return Ok(false);
+            }
+            Err(e) => {
+                error!("PutItem attachment {} link: {}", attachment.sha256, aws_err_str(&e));
+                return Err(e.into());
+            }
+        }
+
+        let result = self
+            .ddb_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(attachment_partition_key(&attachment.sha256)))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(ATTACHMENT_RECORD_SORT_KEY.to_string()))
+            .update_expression(
+                "ADD #count :one SET #bucket = :bucket, #key = :key, #length = :length, #last = :now, \
+                 #first = if_not_exists(#first, :now)",
+            )
+            .expression_attribute_names("#count", DDB_KEY_REF_COUNT)
+            .expression_attribute_names("#bucket", DDB_KEY_S3_BUCKET)
+            .expression_attribute_names("#key", DDB_KEY_S3_KEY)
+            .expression_attribute_names("#length", DDB_KEY_CONTENT_LENGTH)
+            .expression_attribute_names("#first", DDB_KEY_FIRST_LINKED_AT)
+            .expression_attribute_names("#last", DDB_KEY_LAST_LINKED_AT)
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(":bucket", AttributeValue::S(attachment.bucket.clone()))
+            .expression_attribute_values(":key", AttributeValue::S(attachment.key.clone()))
+            .expression_attribute_values(":length", AttributeValue::N(attachment.content_length.to_string()))
+            .expression_attribute_values(":now", AttributeValue::S(now))
+            .send()
+            .await;
+        if let Err(e) = result {
+            error!("UpdateItem attachment {} record: {}", attachment.sha256, aws_err_str(&e));
+            return Err(e.into());
+        }
+
+        Ok(true)
+    }
+
+    /// Return the documents of an opportunity that have been linked to attachments.
+    pub async fn linked_documents(&self, opportunity_url: &str) -> Result<Vec<LinkedDocument>, BoxError> {
+        let mut documents = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .ddb_client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("#pk = :pk")
+                .expression_attribute_names("#pk", DDB_KEY_CRAWL_ID)
+                .expression_attribute_values(
+                    ":pk",
+                    AttributeValue::S(format!("{OPPORTUNITY_PARTITION_PREFIX}{opportunity_url}")),
+                )
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await;
+            let output = match result {
+                Ok(output) => output,
+                Err(e) => {
+                    error!("Query attachment links for {opportunity_url}: {}", aws_err_str(&e));
+                    return Err(e.into());
+                }
+            };
+
+            for item in output.items.unwrap_or_default() {
+                if let (Some(document_url), Some(sha256)) =
+                    (string_attr(&item, DDB_KEY_REQUEST_ID), string_attr(&item, DDB_KEY_SHA256))
+                {
+                    documents.push(LinkedDocument {
+                        document_url,
+                        sha256,
+                    });
+                }
+            }
+
+            start_key = output.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(documents);
+            }
+        }
+    }
+
+    /// Return the contacts found in an opportunity's attachments.
+    pub async fn contacts(&self, opportunity_url: &str) -> Result<Vec<Contact>, BoxError> {
+        let result = self
+            .ddb_client
+            .get_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{OPPORTUNITY_PARTITION_PREFIX}{opportunity_url}")))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(CONTACTS_SORT_KEY.to_string()))
+            .send()
+            .await;
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => {
+                error!("GetItem attachment contacts for {opportunity_url}: {}", aws_err_str(&e));
+                return Err(e.into());
+            }
+        };
+
+        match output.item.as_ref().and_then(|item| string_attr(item, DDB_KEY_CONTACTS)) {
+            Some(json) => Ok(serde_json::from_str(&json)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Add contacts found in an attachment of an opportunity to those found in its other attachments, merging those
+    /// already there.
+    pub async fn add_contacts(&self, opportunity_url: &str, found: Vec<Contact>) -> Result<(), BoxError> {
+        let mut contacts = self.contacts(opportunity_url).await?;
+        let known = contacts.clone();
+        for contact in found {
+            merge_contact(&mut contacts, contact);
+        }
+
+        if contacts == known {
+            return Ok(());
+        }
+
+        let result = self
+            .ddb_client
+            .put_item()
+            .table_name(&self.table_name)
+            .item(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{OPPORTUNITY_PARTITION_PREFIX}{opportunity_url}")))
+            .item(DDB_KEY_REQUEST_ID, AttributeValue::S(CONTACTS_SORT_KEY.to_string()))
+            .item(DDB_KEY_CONTACTS, AttributeValue::S(serde_json::to_string(&contacts)?))
+            .send()
+            .await;
+        if let Err(e) = result {
+            error!("PutItem attachment contacts for {opportunity_url}: {}", aws_err_str(&e));
+            return Err(e.into());
+        }
+
+        info!("Found {} contact(s) in the attachments of {opportunity_url}", contacts.len());
+        Ok(())
+    }
+
+    /// Drop the links of an opportunity's documents that are no longer among `current_documents`, returning the
+    /// number dropped.
+    pub async fn prune(&self, opportunity_url: &str, current_documents: &[String]) -> Result<usize, BoxError> {
+        let linked = self.linked_documents(opportunity_url).await?;
+        let stale = stale_documents(&linked, current_documents);
+
+        for document in stale.iter() {
+            self.remove_link(&document.sha256, opportunity_url, &document.document_url).await?;
+
+            let result = self
+                .ddb_client
+                .delete_item()
+                .table_name(&self.table_name)
+                .key(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{OPPORTUNITY_PARTITION_PREFIX}{opportunity_url}")))
+                .key(DDB_KEY_REQUEST_ID, AttributeValue::S(document.document_url.clone()))
+                .send()
+                .await;
+            if let Err(e) = result {
+                error!("DeleteItem attachment link for {opportunity_url}: {}", aws_err_str(&e));
+                return Err(e.into());
+            }
+        }
+
+        if !stale.is_empty() {
+            info!("Dropped {} stale attachment link(s) of {opportunity_url}", stale.len());
+        }
+
+        Ok(stale.len())
+    }
+
+    /// Remove the link between an attachment and a document of an opportunity, decrementing the attachment's count if
+    /// the link existed.
+    async fn remove_link(&self, sha256: &str, opportunity_url: &str, document_url: &str) -> Result<(), BoxError> {
+        let result = self
+            .ddb_client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(attachment_partition_key(sha256)))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(link_sort_key(opportunity_url, document_url)))
+            .return_values(ReturnValue::AllOld)
+            .send()
+            .await;
+        let removed = match result {
+            Ok(output) => output.attributes.is_some(),
+            Err(e) => {
+                error!("DeleteItem attachment {sha256} link: {}", aws_err_str(&e));
+                return Err(e.into());
+            }
+        };
+
+        if !removed {
+            return Ok(());
+        }
+
+        let result = self
+            .ddb_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(attachment_partition_key(sha256)))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(ATTACHMENT_RECORD_SORT_KEY.to_string()))
+            .update_expression("ADD #count :minus_one")
+            .condition_expression("attribute_exists(#pk)")
+            .expression_attribute_names("#count", DDB_KEY_REF_COUNT)
+            .expression_attribute_names("#pk", DDB_KEY_CRAWL_ID)
+            .expression_attribute_values(":minus_one", AttributeValue::N("-1".to_string()))
+            .return_values(ReturnValue::AllNew)
+            .send()
+            .await;
+        match result {
+            Ok(output) => {
+                let record = output.attributes.unwrap_or_default();
+                let count = record.get(DDB_KEY_REF_COUNT).and_then(|v| v.as_n().ok()).and_then(|n| n.parse().ok());
+                match parse_record(&record) {
+                    Some(attachment) if count.is_some_and(|count: i64| count <= 0) => {
+                        self.mark_orphan(&attachment).await
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Err(SdkError::ServiceError(ref e))
+                if matches!(e.err(), UpdateItemError::ConditionalCheckFailedException(_)) =>
+            {
+                warn!("Attachment {sha256} had a link but no record");
+                Ok(())
+            }
+            Err(e) => {
+                error!("UpdateItem attachment {sha256} record: {}", aws_err_str(&e));
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Record that no opportunity links to an attachment any longer, so cleanup can find it without a scan.
+    async fn mark_orphan(&self, attachment: &StoredAttachment) -> Result<(), BoxError> {
+        let result = self
+            .ddb_client
+            .put_item()
+            .table_name(&self.table_name)
+            .item(DDB_KEY_CRAWL_ID, AttributeValue::S(ORPHAN_PARTITION_KEY.to_string()))
+            .item(DDB_KEY_REQUEST_ID, AttributeValue::S(attachment.sha256.clone()))
+            .item(DDB_KEY_S3_BUCKET, AttributeValue::S(attachment.bucket.clone()))
+            .item(DDB_KEY_S3_KEY, AttributeValue::S(attachment.key.clone()))
+            .item(DDB_KEY_CONTENT_LENGTH, AttributeValue::N(attachment.content_length.to_string()))
+            .send()
+            .await;
+
+        if let Err(e) = result {
+            error!("PutItem attachment {} orphan: {}", attachment.sha256, aws_err_str(&e));
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    /// Return the attachments no opportunity links to, as of the last time a link to each was removed.
+    ///
+    /// Some may have been linked again since; [`remove_orphan`][Self::remove_orphan] checks before removing them.
+    pub async fn orphans(&self) -> Result<Vec<StoredAttachment>, BoxError> {
+        let mut orphans = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .ddb_client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("#pk = :pk")
+                .expression_attribute_names("#pk", DDB_KEY_CRAWL_ID)
+                .expression_attribute_values(":pk", AttributeValue::S(ORPHAN_PARTITION_KEY.to_string()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await;
+            let output = match result {
+                Ok(output) => output,
+                Err(e) => {
+                    error!("Query orphaned attachments: {}", aws_err_str(&e));
+                    return Err(e.into());
+                }
+            };
+
+            orphans.extend(output.items.unwrap_or_default().iter().filter_map(parse_orphan));
+
+            start_key = output.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(orphans);
+            }
+        }
+    }
+
+    /// Remove an attachment's record if it is still orphaned, returning `true` if it was removed. Its orphan item is
+    /// removed either way; the attachment is marked again if it is later unlinked again.
+    ///
+    /// The check guards against a link added since the orphan was found.
+    pub async fn remove_orphan(&self, sha256: &str) -> Result<bool, BoxError> {
+        let result = self
+            .ddb_client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(attachment_partition_key(sha256)))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(ATTACHMENT_RECORD_SORT_KEY.to_string()))
+            .condition_expression("#count <= :zero")
+            .expression_attribute_names("#count", DDB_KEY_REF_COUNT)
+            .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+            .send()
+            .await;
+
+        let removed = match result {
+            Ok(_) => true,
+            Err(SdkError::ServiceError(ref e))
+                if matches!(e.err(), DeleteItemError::ConditionalCheckFailedException(_)) =>
+            {
+                debug!("Attachment {sha256} was linked again before cleanup");
+                false
+            }
+            Err(e) => {
+                error!("DeleteItem attachment {sha256} record: {}", aws_err_str(&e));
+                return Err(e.into());
+            }
+        };
+
+        let result = self
+            .ddb_client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(ORPHAN_PARTITION_KEY.to_string()))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(sha256.to_string()))
+            .send()
+            .await;
+        if let Err(e) = result {
+            error!("DeleteItem attachment {sha256} orphan: {}", aws_err_str(&e));
+            return Err(e.into());
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Record that a document fetched for the opportunity named in `crawl` resolved to the stored body of `response`, and
+/// keep the [contacts][crate::contacts] found in it if it is plain text. `subsystem` is the one fetching the document,
+/// whose settings apply to contact extraction.
+///
+/// Linking is best-effort: failures are logged rather than failing the download. Documents fetched without an
+/// opportunity URL, or by a client that doesn't store bodies, are not linked. Neither are documents whose body turns
+/// out to be an HTML page, typically a login or error page served under the document's name; the mismatch is recorded
+/// in the journal instead.
+pub async fn record_attachment(
+    log_config: &LogConfig,
+    crawl: &CrawlParameters,
+    subsystem: &str,
+    document_url: &str,
+    response: &HttpResponse,
+) {
+    if let Some(mismatch) = record_mismatch(log_config, crawl, response).await {
+        if mismatch.actual == ContentKind::Html {
+            warn!("Not linking document {document_url}: {mismatch}");
+            return;
+        }
+    }
+
+    let Some(opportunity_url) = crawl.opportunity_url.as_deref() else {
+        return;
+    };
+
+    link_attachment(log_config, opportunity_url, document_url, response).await;
+
+    let Some(text) = extract::attachment_text(log_config, response).await else {
+        return;
+    };
+
+    contacts::record_attachment_contacts(log_config, subsystem, opportunity_url, document_url, &text).await;
+    search::record_attachment_text(log_config, opportunity_url, document_url, &text).await;
+}
+
+/// Return `response` if it holds a page for the HTML parser, or route it to the document pipeline if it doesn't.
+///
+/// Misconfigured portals sometimes serve a PDF or Office document labelled as HTML. A mismatch between the declared
+/// `Content-Type` and the body is recorded in the journal. If the body is actually a document, it is linked as an
+/// attachment of `opportunity_url` (when the page belongs to an opportunity) and `None` is returned, so binary data is
+/// never parsed as HTML.
+pub async fn html_page(
+    log_config: &LogConfig,
+    crawl: &CrawlParameters,
+    response: HttpResponse,
+    opportunity_url: Option<&str>,
+) -> Option<HttpResponse> {
+    record_mismatch(log_config, crawl, &response).await;
+
+    let kind = response.content_kind();
+    if !kind.is_document() {
+        return Some(response);
+    }
+
+    warn!("Expected an HTML page at {} but received a {kind} document", response.url());
+    if let Some(opportunity_url) = opportunity_url {
+        link_attachment(log_config, opportunity_url, response.url().as_str(), &response).await;
+    }
+
+    None
+}
+
+/// Record a mismatch between the declared `Content-Type` of `response` and its body in the journal, returning it.
+async fn record_mismatch(
+    log_config: &LogConfig,
+    crawl: &CrawlParameters,
+    response: &HttpResponse,
+) -> Option<ContentMismatch> {
+    let mismatch = response.content_mismatch()?;
+    warn!("Content type mismatch at {}: {mismatch}", response.url());
+
+    if let Some(crawl_id) = crawl.crawl_id.as_deref() {
+        let event = CrawlEvent::ContentTypeMismatch {
+            url: response.url().to_string(),
+            declared: mismatch.declared.to_string(),
+            actual: mismatch.actual.to_string(),
+        };
+        log_config.journal.record(crawl_id, event).await;
+    }
+
+    Some(mismatch)
+}
+
+/// Link the stored body of `response` to a document of an opportunity. Responses from a client that doesn't store
+/// bodies are not linked.
+async fn link_attachment(log_config: &LogConfig, opportunity_url: &str, document_url: &str, response: &HttpResponse) {
+    let Some(stored) = response.stored() else {
+        return;
+    };
+
+    let attachment = StoredAttachment {
+        sha256: response.sha256().to_string(),
+        bucket: stored.bucket.clone(),
+        key: stored.key.clone(),
+        content_length: response.content_length(),
+    };
+
+    match log_config.attachments.link(&attachment, opportunity_url, document_url).await {
+        Ok(true) => info!("Linked attachment {} to {opportunity_url}", attachment.sha256),
+        Ok(false) => (),
+        Err(e) => warn!("Failed to link attachment {} to {opportunity_url}: {e}", attachment.sha256),
+    }
+}
+
+/// Drop the attachment links of an opportunity's documents that are no longer published.
+///
+/// Document URLs are canonicalized as they are when the documents are requested. Pruning is best-effort: failures are
+/// logged rather than failing the operation.
+pub async fn prune_attachments(log_config: &LogConfig, opportunity_url: &str, document_urls: &[&str]) {
+    let current_documents: Vec<String> =
+        document_urls.iter().map(|url| normalize_url(url).unwrap_or_else(|_| url.to_string())).collect();
+
+    if let Err(e) = log_config.attachments.prune(opportunity_url, &current_documents).await {
+        warn!("Failed to prune attachment links of {opportunity_url}: {e}");
+    }
+}
+
+/// Remove the records of orphaned attachments and tag their S3 objects for the lifecycle policy to expire, returning
+/// the number cleaned up.
+pub async fn clean_up_orphans(log_config: &LogConfig) -> Result<usize, BoxError> {
+    let orphans = log_config.attachments.orphans().await?;
+    let mut cleaned = 0;
+
+    for orphan in orphans.iter() {
+        if !log_config.attachments.remove_orphan(&orphan.sha256).await? {
+            continue;
+        }
+
+        let tagging = Tagging::builder()
+            .tag_set(Tag::builder().key(ORPHANED_TAG_KEY).value(ORPHANED_TAG_VALUE).build()?)
+            .build()?;
+        log_aws_err(
+            log_config
+                .s3_client
+                .put_object_tagging()
+                .bucket(&orphan.bucket)
+                .key(&orphan.key)
+                .tagging(tagging)
+                .send()
+                .await,
+            &format!("PutObjectTagging s3://{}/{}", orphan.bucket, orphan.key),
+        )?;
+
+        debug!("Tagged orphaned attachment s3://{}/{}", orphan.bucket, orphan.key);
+        cleaned += 1;
+    }
+
+    Ok(cleaned)
+}
+
+/// Return the linked documents that are not among `current_documents`.
+pub fn stale_documents<'a>(linked: &'a [LinkedDocument], current_documents: &[String]) -> Vec<&'a LinkedDocument> {
+    linked.iter().filter(|document| !current_documents.contains(&document.document_url)).collect()
+}
+
+/// Return the partition key of an attachment's record and links.
+fn attachment_partition_key(sha256: &str) -> String {
+    format!("{ATTACHMENT_PARTITION_PREFIX}{sha256}")
+}
+
+/// Return the sort key of the link between an attachment and a document of an opportunity.
+fn link_sort_key(opportunity_url: &str, document_url: &str) -> String {
+    format!("{LINK_SORT_PREFIX}{opportunity_url}#{document_url}")
+}
+
+/// Return a string attribute of an item.
+fn string_attr(item: &HashMap<String, AttributeValue>, name: &str) -> Option<String> {
+    item.get(name).and_then(|v| v.as_s().ok()).cloned()
+}
+
+/// Parse an attachment record item, skipping incomplete ones.
+fn parse_record(item: &HashMap<String, AttributeValue>) -> Option<StoredAttachment> {
+    let sha256 = string_attr(item, DDB_KEY_CRAWL_ID)?.strip_prefix(ATTACHMENT_PARTITION_PREFIX)?.to_string();
+    parse_location(sha256, item)
+}
+
+/// Parse an orphan item, skipping incomplete ones.
+fn parse_orphan(item: &HashMap<String, AttributeValue>) -> Option<StoredAttachment> {
+    parse_location(string_attr(item, DDB_KEY_REQUEST_ID)?, item)
+}
+
+/// Parse the S3 location of an attachment from an item.
+fn parse_location(sha256: String, item: &HashMap<String, AttributeValue>) -> Option<StoredAttachment> {
+    let Some(bucket) = string_attr(item, DDB_KEY_S3_BUCKET) else {
+        warn!("Attachment record {sha256} has no {DDB_KEY_S3_BUCKET}");
+        return None;
+    };
+    let key = string_attr(item, DDB_KEY_S3_KEY)?;
+    let content_length =
+        item.get(DDB_KEY_CONTENT_LENGTH).and_then(|v| v.as_n().ok()).and_then(|n| n.parse().ok()).unwrap_or(0);
+
+    Some(StoredAttachment {
+        sha256,
+        bucket,
+        key,
+        content_length,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{link_sort_key, parse_orphan, parse_record, stale_documents, LinkedDocument, StoredAttachment},
+        aws_sdk_dynamodb::types::AttributeValue,
+        std::collections::HashMap,
+    };
+
+    #[test]
+    fn links_and_records() {
+        let linked = vec![
+            LinkedDocument {
+                document_url: "https://example.com/docs/1".to_string(),
+                sha256: "aa".to_string(),
+            },
+            LinkedDocument {
+                document_url: "https://example.com/docs/2".to_string(),
+                sha256: "bb".to_string(),
+            },
+        ];
+
+        let stale = stale_documents(&linked, &["https://example.com/docs/2".to_string()]);
+        assert_eq!(stale, vec![&linked[0]]);
+        assert!(stale_documents(&linked, &[linked[0].document_url.clone(), linked[1].document_url.clone()]).is_empty());
+
+        assert_eq!(
+            link_sort_key("https://example.com/bid/7", "https://example.com/docs/1"),
+            "Link#https://example.com/bid/7#https://example.com/docs/1"
+        );
+
+        let item = HashMap::from([
+            ("CrawlId".to_string(), AttributeValue::S("Attachment#abcd".to_string())),
+            ("RequestId".to_string(), AttributeValue::S("Record".to_string())),
+            ("S3Bucket".to_string(), AttributeValue::S("bucket".to_string())),
+            ("S3Key".to_string(), AttributeValue::S("logs/abcd".to_string())),
+            ("ContentLength".to_string(), AttributeValue::N("2048".to_string())),
+            ("RefCount".to_string(), AttributeValue::N("0".to_string())),
+        ]);
+        assert_eq!(
+            parse_record(&item),
+            Some(StoredAttachment {
+                sha256: "abcd".to_string(),
+                bucket: "bucket".to_string(),
+                key: "logs/abcd".to_string(),
+                content_length: 2048,
+            })
+        );
+
+        let mut incomplete = item.clone();
+        incomplete.remove("S3Bucket");
+        assert_eq!(parse_record(&incomplete), None);
+
+        let mut orphan = item.clone();
+        orphan.insert("CrawlId".to_string(), AttributeValue::S("AttachmentOrphans".to_string()));
+        orphan.insert("RequestId".to_string(), AttributeValue::S("abcd".to_string()));
+        assert_eq!(parse_orphan(&orphan), parse_record(&item));
+    }
+}
@@ -0,0 +1,252 @@
+//! Awards read from the pages of awarded solicitations.
+//!
+//! Most portals keep a solicitation's detail page once it is awarded and add who won the contract, for how much, when,
+//! and under what contract number. Each subsystem's detail parser reads these with [`page_awards`] into
+//! [`Opportunity::awards`][crate::opportunity::Opportunity::awards], so each award stays linked to the opportunity it
+//! was awarded from and win/loss analysis can join awards with the opportunities they were bid on.
+//!
+//! Awards are read from the visible text of the page, either as labelled fields:
+//!
+//! ```text
+//! Awarded To: Acme Paving LLC
+//! Award Amount: $1,250,000.00
+//! Award Date: 05/02/2023
+//! Contract Number: K-2023-117
+//! ```
+//!
+//! where each awardee label starts another award, or as a table with an awardee column, one award per row. Labels are
+//! matched in English and French, ignoring case. An award is only kept if it names the awardee, since contract
+//! numbers and amounts also appear on open solicitations.
+use {
+    crate::{
+        contacts::document_lines,
+        opportunity::{clean_text, parse_amount, parse_date_time, Award, DateLocale},
+    },
+    markup5ever_rcdom::RcDom,
+};
+
+/// The currency of award amounts on US portals that don't name one.
+pub const US_DOLLARS: &str = "USD";
+
+/// Labels of the supplier a contract was awarded to.
+const AWARDEE_LABELS: &[&str] = &[
+    "awarded to",
+    "award to",
+    "awardee",
+    "awarded vendor",
+    "awarded contractor",
+    "awarded supplier",
+    "successful bidder",
+    "successful proposer",
+    "successful vendor",
+    "adjudicataire",
+    "attribué à",
+];
+
+/// Labels of the value of an awarded contract.
+const AMOUNT_LABELS: &[&str] = &[
+    "award amount",
+    "awarded amount",
+    "amount awarded",
+    "award value",
+    "contract amount",
+    "contract value",
+    "total award",
+    "montant du contrat",
+    "montant adjugé",
+    "valeur du contrat",
+];
+
+/// Labels of the date a contract was awarded.
+const DATE_LABELS: &[&str] = &[
+    "award date",
+    "date awarded",
+    "awarded on",
+    "awarded date",
+    "date of award",
+    "date d'attribution",
+    "date d'adjudication",
+];
+
+/// Labels of the number of an awarded contract.
+const CONTRACT_LABELS: &[&str] = &[
+    "contract number",
+    "contract no",
+    "contract no.",
+    "contract #",
+    "contract id",
+    "agreement number",
+    "numéro de contrat",
+    "no de contrat",
+];
+
+/// Column headers that, in a table with an awardee column, name the same fields as the labels above.
+const TABLE_HEADERS: &[(&str, AwardField)] = &[
+    ("amount", AwardField::Amount),
+    ("value", AwardField::Amount),
+    ("montant", AwardField::Amount),
+    ("date", AwardField::AwardedOn),
+    ("contract", AwardField::ContractNumber),
+    ("contrat", AwardField::ContractNumber),
+];
+
+/// A field of an award.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum AwardField {
+    Awardee,
+    Amount,
+    AwardedOn,
+    ContractNumber,
+}
+
+impl AwardField {
+    /// Return the field a label names, if any. In a table header, shorter generic labels are also recognized.
+    fn from_label(label: &str, header: bool) -> Option<Self> {
+        let label = label.trim().trim_end_matches(':').trim_end().to_lowercase();
+        let fields = [
+            (AWARDEE_LABELS, Self::Awardee),
+            (AMOUNT_LABELS, Self::Amount),
+            (DATE_LABELS, Self::AwardedOn),
+            (CONTRACT_LABELS, Self::ContractNumber),
+        ];
+
+        if let Some((_, field)) = fields.iter().find(|(labels, _)| labels.contains(&label.as_str())) {
+            return Some(*field);
+        }
+
+        if !header {
+            return None;
+        }
+        TABLE_HEADERS.iter().find(|(header, _)| label == *header).map(|(_, field)| *field)
+    }
+
+    /// Set the field of an award from the text of its value, leaving it unset if the text can't be read.
+    fn set(self, award: &mut Award, value: &str, date_locale: DateLocale, default_currency: &str) {
+        match self {
+            Self::Awardee => award.awardee = clean_text(value).unwrap_or_default(),
+            Self::Amount => award.amount = parse_amount(value, default_currency),
+            Self::AwardedOn => award.awarded_on = parse_date_time(value, date_locale).map(|awarded| awarded.date()),
+            Self::ContractNumber => award.contract_number = clean_text(value),
+        }
+    }
+}
+
+/// Return the field a line labels and the value following the label, if it starts with an award label.
+fn labelled(line: &str) -> Option<(AwardField, &str)> {
+    let (label, value) = line.split_once(['\t', ':'])?;
+    Some((AwardField::from_label(label, false)?, value.trim_start_matches([':', '\t', ' '])))
+}
+
+/// Return the awards in lines of text, with table cells separated by tabs.
+pub fn awards(lines: &[String], date_locale: DateLocale, default_currency: &str) -> Vec<Award> {
+    let mut awards = vec![];
+    let mut current: Option<Award> = None;
+    let mut lines = lines.iter().peekable();
+
+    while let Some(line) = lines.next() {
+        let cells: Vec<&str> = line.split('\t').collect();
+        let columns: Vec<Option<AwardField>> = cells.iter().map(|cell| AwardField::from_label(cell, true)).collect();
+        // A header names the awardee and at least one other field; a labelled field in a table row names only one.
+        if columns.contains(&Some(AwardField::Awardee)) && columns.iter().flatten().count() > 1 {
+            while let Some(row) = lines.next_if(|row| row.split('\t').count() == cells.len()) {
+                let mut award = Award::default();
+                for (field, value) in columns.iter().zip(row.split('\t')) {
+                    if let Some(field) = field {
+                        field.set(&mut award, value, date_locale, default_currency);
+                    }
+                }
+                awards.push(award);
+            }
+            continue;
+        }
+
+        let Some((field, value)) = labelled(line) else {
+            continue;
+        };
+
+        if field == AwardField::Awardee && current.as_ref().is_some_and(|award| !award.awardee.is_empty()) {
+            awards.extend(current.take());
+        }
+        field.set(current.get_or_insert_with(Award::default), value, date_locale, default_currency);
+    }
+
+    awards.extend(current);
+    awards.retain(|award| !award.awardee.is_empty());
+    awards
+}
+
+/// Return the awards on a solicitation's page, with dates in the given language and amounts that don't name a currency
+/// in `default_currency`.
+pub fn page_awards(document: &RcDom, date_locale: DateLocale, default_currency: &str) -> Vec<Award> {
+    awards(&document_lines(document), date_locale, default_currency)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{page_awards, US_DOLLARS},
+        crate::{
+            opportunity::{Amount, Award, DateLocale},
+            soup::parse_html_str,
+        },
+        chrono::NaiveDate,
+    };
+
+    #[test]
+    fn awards() {
+        let page = r#"<html><body>
+            <h1>Road resurfacing</h1>
+            <p>The contract will be awarded to the lowest responsive bidder.</p>
+            <table>
+              <tr><td>Contract Number:</td><td>K-2023-117</td></tr>
+              <tr><td>Awarded To:</td><td>Acme Paving LLC</td></tr>
+              <tr><td>Award Amount:</td><td>$1,250,000.00</td></tr>
+              <tr><td>Award Date:</td><td>05/02/2023</td></tr>
+            </table>
+            <p>Awarded To: Northwest Striping Inc.</p>
+            <p>Award Amount: TBD</p>
+        </body></html>"#;
+        assert_eq!(
+            page_awards(&parse_html_str(page), DateLocale::English, US_DOLLARS),
+            vec![
+                Award {
+                    awardee: "Acme Paving LLC".to_string(),
+                    amount: Some(Amount {
+                        minor_units: 125_000_000,
+                        currency: "USD".to_string(),
+                    }),
+                    awarded_on: NaiveDate::from_ymd_opt(2023, 5, 2),
+                    contract_number: Some("K-2023-117".to_string()),
+                },
+                Award {
+                    awardee: "Northwest Striping Inc.".to_string(),
+                    ..Award::default()
+                },
+            ]
+        );
+
+        // A table of awards, one per row, ending at the first row of another shape.
+        let table = r#"<table>
+            <tr><th>Awardee</th><th>Contract</th><th>Amount</th></tr>
+            <tr><td>Acme Paving LLC</td><td>K-117</td><td>$900,000</td></tr>
+            <tr><td>Cascade Concrete</td><td>K-118</td><td>$350,000</td></tr>
+            <tr><td colspan="3">Total: $1,250,000</td></tr>
+        </table>"#;
+        let awards = page_awards(&parse_html_str(table), DateLocale::English, US_DOLLARS);
+        assert_eq!(
+            awards.iter().map(|award| (award.awardee.as_str(), award.contract_number.as_deref())).collect::<Vec<_>>(),
+            vec![("Acme Paving LLC", Some("K-117")), ("Cascade Concrete", Some("K-118"))]
+        );
+        assert_eq!(awards[1].amount.as_ref().map(|amount| amount.minor_units), Some(35_000_000));
+
+        // Contract details without an awardee aren't an award, and French labels are read.
+        let open = "<p>Contract Number: K-2023-117</p><p>Contract Value: $1,000,000</p>";
+        assert_eq!(page_awards(&parse_html_str(open), DateLocale::English, US_DOLLARS), vec![]);
+        let french = "<p>Adjudicataire : Pavage Québec inc.</p><p>Date d'attribution : 2 mai 2023</p>";
+        let awards = page_awards(&parse_html_str(french), DateLocale::French, "CAD");
+        assert_eq!(
+            (awards[0].awardee.as_str(), awards[0].awarded_on),
+            ("Pavage Québec inc.", NaiveDate::from_ymd_opt(2023, 5, 2))
+        );
+    }
+}
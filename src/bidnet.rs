@@ -0,0 +1,507 @@
+//! Request/response types for BidNet Direct (bidnetdirect.com), which hosts regional purchasing groups of local
+//! agencies.
+//!
+//! Which regions and purchasing groups are crawled is set by the BidNet configuration document (see
+//! [`config`][crate::config]), for example:
+//!
+//! ```json
+//! {"Regions": [{"Region": "washington"}, {"Region": "oregon", "Groups": ["cityofportland"]}]}
+//! ```
+//!
+//! A crawl logs in, walks the open solicitations of each configured region or group, parses each solicitation page,
+//! and downloads the documents listed with it.
+mod listing;
+mod login;
+mod solicitation;
+
+pub(crate) use login::check_credentials;
+
+use {
+    crate::{
+        attachment, closure,
+        config::load_subsystem_config,
+        contacts,
+        flags::Flag,
+        httpext::{Client, LogConfig, Response as HttpResponse, ResponseExt},
+        journal::CrawlEvent,
+        lifecycle,
+        opportunity::Opportunity,
+        quality,
+        retry::{RetryDelay, RetryableError, MAX_DELAY},
+        shapes::{NextRequest, Operation, Request, Response, SUBSYS_BIDNET},
+        soup::parse_html_str,
+        BoxError,
+    },
+    chrono::Utc,
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    reqwest::Url,
+    serde::{Deserialize, Serialize},
+    std::{
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+pub(crate) const DEFAULT_BIDNET_BASE_URL: &str = "https://www.bidnetdirect.com/";
+const OPEN_BIDS_PATH: &str = "solicitations/open-bids";
+const DEFAULT_REGION: &str = "washington";
+
+const OP_START_CRAWL: &str = "StartCrawl";
+const OP_FETCH_LISTING_PAGE: &str = "FetchListingPage";
+const OP_FETCH_SOLICITATION_PAGE: &str = "FetchSolicitationPage";
+const OP_FETCH_DOCUMENT: &str = "FetchDocument";
+
+/// Version of this subsystem's parsers. Bump it whenever a parser change alters what is extracted.
+pub(crate) const PARSER_VERSION: u32 = 1;
+
+const PARSER_LISTING: &str = "BidNet:Listing";
+const PARSER_SOLICITATION: &str = "BidNet:Solicitation";
+
+/// Phrases shown in place of an opportunity the portal no longer publishes; see [`closure`][crate::closure].
+const NOT_FOUND_MARKERS: &[&str] = &["this solicitation is no longer available", "solicitation not found"];
+
+/// Condition name for a login refused because the account logged in too recently and has no session to reuse.
+const CONDITION_LOGIN_THROTTLED: &str = "BidNetLoginThrottled";
+
+/// The BidNet configuration document.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, rename_all = "PascalCase")]
+pub(crate) struct BidNetConfig {
+    /// The regions to crawl.
+    pub regions: Vec<BidNetRegion>,
+}
+
+/// A BidNet region to crawl.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct BidNetRegion {
+    /// The region's path segment, e.g. `washington`.
+    pub region: String,
+
+    /// The path segments of the purchasing groups within the region to crawl. If empty, every open solicitation in
+    /// the region is crawled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+}
+
+impl Default for BidNetConfig {
+    fn default() -> Self {
+        Self {
+            regions: vec![BidNetRegion {
+                region: DEFAULT_REGION.to_string(),
+                groups: vec![],
+            }],
+        }
+    }
+}
+
+impl BidNetConfig {
+    /// Return the URLs of the first page of open solicitations for each configured region or group.
+    pub(crate) fn listing_urls(&self, base_url: &Url) -> Result<Vec<Url>, BoxError> {
+        let mut urls = Vec::new();
+
+        for region in self.regions.iter() {
+            if region.groups.is_empty() {
+                urls.push(base_url.join(&format!("{}/{OPEN_BIDS_PATH}", region.region))?);
+            }
+
+            for group in region.groups.iter() {
+                urls.push(base_url.join(&format!("{}/{group}/{OPEN_BIDS_PATH}", region.region))?);
+            }
+        }
+
+        Ok(urls)
+    }
+}
+
+/// Possible operations for BidNet Direct.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum BidNetOperation {
+    /// Log in and start a crawl of the configured regions and groups, or of the listing given as the URL.
+    StartCrawl,
+
+    /// Fetch a page of open solicitations.
+    FetchListingPage,
+
+    /// Fetch a solicitation page.
+    FetchSolicitationPage,
+
+    /// Download a document published with a solicitation.
+    FetchDocument,
+}
+
+impl FromStr for BidNetOperation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            OP_START_CRAWL => Ok(Self::StartCrawl),
+            OP_FETCH_LISTING_PAGE => Ok(Self::FetchListingPage),
+            OP_FETCH_SOLICITATION_PAGE => Ok(Self::FetchSolicitationPage),
+            OP_FETCH_DOCUMENT => Ok(Self::FetchDocument),
+            _ => Err(format!("Unknown operation: {value}")),
+        }
+    }
+}
+
+impl Display for BidNetOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.operation())
+    }
+}
+
+impl BidNetOperation {
+    /// Handle a request.
+    pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+        match self {
+            Self::StartCrawl => start_crawl(log_config, req, context).await,
+            Self::FetchListingPage => fetch_listing_page(log_config, req, context).await,
+            Self::FetchSolicitationPage => fetch_solicitation_page(log_config, req, context).await,
+            Self::FetchDocument => fetch_document(log_config, req, context).await,
+        }
+    }
+
+    /// Return the operation name within the subsystem.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::StartCrawl => OP_START_CRAWL,
+            Self::FetchListingPage => OP_FETCH_LISTING_PAGE,
+            Self::FetchSolicitationPage => OP_FETCH_SOLICITATION_PAGE,
+            Self::FetchDocument => OP_FETCH_DOCUMENT,
+        }
+    }
+}
+
+/// Log in and schedule the first page of open solicitations for each configured region or group, or the listing page
+/// given as the URL.
+async fn start_crawl(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let base_url = Url::parse(DEFAULT_BIDNET_BASE_URL)?;
+    let listing_urls = match req.url.as_deref() {
+        Some(url) => vec![Url::parse(url)?],
+        None => {
+            let config: BidNetConfig = load_subsystem_config(&log_config, SUBSYS_BIDNET).await?;
+            config.listing_urls(&base_url)?
+        }
+    };
+
+    if listing_urls.is_empty() {
+        warn!("No BidNet regions are configured; nothing to crawl");
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    }
+
+    let client = log_in(&log_config, &req, &context, &listing_urls[0]).await?;
+    let mut cookies = client.cookie_store.snapshot();
+    cookies.retain_domain(&listing_urls[0]);
+
+    let next_requests = listing_urls
+        .into_iter()
+        .map(|url| NextRequest {
+            operation: Operation::BidNet(BidNetOperation::FetchListingPage),
+            url: Some(url.to_string()),
+            link_text: None,
+            crawl: req.crawl.child().with_cookies(cookies.clone()).with_account(client.account.clone()),
+            delay_seconds: 0,
+        })
+        .collect();
+
+    Ok(Response {
+        next_requests,
+    })
+}
+
+/// Log in to BidNet, returning the logged-in client.
+///
+/// If the account logged in within the last [`login_min_interval`][LogConfig::login_min_interval], its session is
+/// reused instead. If that session can't be reused, the crawl is deferred until a fresh login is allowed.
+async fn log_in(log_config: &LogConfig, req: &Request, context: &Context, url: &Url) -> Result<Client, BoxError> {
+    let sessions = &log_config.sessions;
+    let interval = log_config.login_min_interval;
+
+    if let Some(session) = sessions.load(SUBSYS_BIDNET, login::ACCOUNT_ID).await? {
+        if let Some(cookies) = session.reusable_cookies(Utc::now(), interval) {
+            info!("Reusing BidNet session from {}", session.last_login_at);
+            return Ok(req
+                .crawl
+                .clone()
+                .with_cookies(cookies.clone())
+                .portal_client(log_config, context, SUBSYS_BIDNET, DEFAULT_BIDNET_BASE_URL, url)?
+                .account(login::ACCOUNT_ID)
+                .build()?);
+        }
+    }
+
+    if !sessions.claim_login(SUBSYS_BIDNET, login::ACCOUNT_ID, interval).await? {
+        warn!("BidNet account logged in less than {interval} ago; deferring the crawl");
+        return Err(RetryableError {
+            condition: CONDITION_LOGIN_THROTTLED,
+            url: url.to_string(),
+            delay: RetryDelay::Fixed(interval.to_std().unwrap_or(MAX_DELAY)),
+        }
+        .into());
+    }
+
+    let client = req
+        .crawl
+        .portal_client(log_config, context, SUBSYS_BIDNET, DEFAULT_BIDNET_BASE_URL, url)?
+        .account(login::ACCOUNT_ID)
+        .build()?;
+    let response = match client.get(url.join(login::LOGIN_PATH)?).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch BidNet login page: {e}");
+            return Err(e);
+        }
+    };
+
+    info!("Submitting BidNet login");
+    let _ = login::submit_login(&client, log_config, response).await?;
+
+    if let Err(e) = sessions.save_cookies(SUBSYS_BIDNET, login::ACCOUNT_ID, &client.cookie_store.snapshot()).await {
+        warn!("Failed to save BidNet session: {e}");
+    }
+
+    Ok(client)
+}
+
+/// Fetch a page with the crawl's session, failing if BidNet asks to log in again.
+async fn fetch_page(client: &Client, url: &Url, what: &str) -> Result<HttpResponse, BoxError> {
+    let response = match client.get(url.clone()).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch BidNet {what}: {e}");
+            return Err(e);
+        }
+    };
+
+    if login::login_required(response.text().unwrap_or_default()) {
+        return Err(format!("BidNet session expired fetching {what} {url}").into());
+    }
+
+    Ok(response)
+}
+
+/// Fetch a page of open solicitations, returning requests for the solicitations and the next page.
+async fn fetch_listing_page(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let url = req.required_url()?;
+    let client = req
+        .crawl
+        .portal_client(&log_config, &context, SUBSYS_BIDNET, DEFAULT_BIDNET_BASE_URL, &url)?
+        .account(login::ACCOUNT_ID)
+        .build()?;
+    let response = fetch_page(&client, &url, "listing").await?;
+
+    let document = parse_html_str(response.text()?);
+    let result = listing::parse_listing_page(&document, response.url());
+    let event = match &result {
+        Ok(urls) => CrawlEvent::ParseSucceeded {
+            url: response.url().to_string(),
+            parser: PARSER_LISTING.to_string(),
+            items: urls.len(),
+        },
+        Err(e) => CrawlEvent::ParseFailed {
+            url: response.url().to_string(),
+            parser: PARSER_LISTING.to_string(),
+            error: e.to_string(),
+        },
+    };
+    log_config.journal.record(&client.crawl_id, event).await;
+
+    let mut next_requests: Vec<NextRequest> = result?
+        .into_iter()
+        .map(|url| NextRequest {
+            operation: Operation::BidNet(BidNetOperation::FetchSolicitationPage),
+            url: Some(url.to_string()),
+            link_text: None,
+            crawl: req.crawl.child(),
+            delay_seconds: 0,
+        })
+        .collect();
+
+    next_requests.extend(listing::PAGINATION.continuation(
+        Operation::BidNet(BidNetOperation::FetchListingPage),
+        &document,
+        response.url(),
+        &req.crawl,
+    ));
+
+    Ok(Response {
+        next_requests,
+    })
+}
+
+/// Fetch a solicitation page, parse it into the normalized opportunity model, and return requests for its documents.
+async fn fetch_solicitation_page(
+    log_config: LogConfig,
+    req: Request,
+    context: Context,
+) -> Result<Response, LambdaError> {
+    let url = req.required_url()?;
+    let client = req
+        .crawl
+        .portal_client(&log_config, &context, SUBSYS_BIDNET, DEFAULT_BIDNET_BASE_URL, &url)?
+        .account(login::ACCOUNT_ID)
+        .build()?;
+    let response = fetch_page(&client, &url, "solicitation").await?;
+
+    let Some(response) = attachment::html_page(&log_config, &req.crawl, response, Some(url.as_str())).await else {
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    };
+
+    if let Some(reason) =
+        closure::soft_not_found(response.text()?, NOT_FOUND_MARKERS, &req.crawl.locale.not_found_markers)
+    {
+        closure::record_closed(&log_config, &client, SUBSYS_BIDNET, url.as_str(), &reason).await?;
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    }
+
+    let document = parse_html_str(response.text()?);
+    let result = solicitation::parse_solicitation_page(&document, response.url(), req.crawl.locale.dates);
+    let event = match &result {
+        Ok(_) => CrawlEvent::ParseSucceeded {
+            url: response.url().to_string(),
+            parser: PARSER_SOLICITATION.to_string(),
+            items: 1,
+        },
+        Err(e) => CrawlEvent::ParseFailed {
+            url: response.url().to_string(),
+            parser: PARSER_SOLICITATION.to_string(),
+            error: e.to_string(),
+        },
+    };
+    log_config.journal.record(&client.crawl_id, event).await;
+
+    let mut opportunity: Opportunity =
+        result?.with_parser(PARSER_SOLICITATION, PARSER_VERSION).with_tenant(client.tenant.as_deref());
+    contacts::record_contacts(&log_config, &client, &mut opportunity, response.text()?).await;
+    lifecycle::record_stage(&log_config, &client, &mut opportunity, response.text()?).await;
+    quality::record_quality(&log_config, &client, &mut opportunity).await;
+    closure::record_seen(&log_config, &client, &opportunity).await;
+    info!("BidNet opportunity: {}", serde_json::to_string(&opportunity)?);
+
+    let document_urls: Vec<&str> = opportunity.documents.iter().map(|document| document.url.as_str()).collect();
+    attachment::prune_attachments(&log_config, &opportunity.url, &document_urls).await;
+
+    let next_requests = opportunity
+        .documents
+        .iter()
+        .map(|document| NextRequest {
+            operation: Operation::BidNet(BidNetOperation::FetchDocument),
+            url: Some(document.url.clone()),
+            link_text: Some(document.name.clone()),
+            crawl: req.crawl.child().with_opportunity_url(&opportunity.url),
+            delay_seconds: 0,
+        })
+        .collect();
+
+    Ok(Response {
+        next_requests,
+    })
+}
+
+/// Download a document published with a solicitation. The client archives the body like any other response, and the
+/// archived body is linked to the solicitation as an attachment.
+async fn fetch_document(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let url = req.required_url()?;
+    if !log_config.flags.is_enabled(Flag::AttachmentDownloads) {
+        info!("Attachment downloads are disabled; not fetching BidNet document {url}");
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    }
+
+    let client = req
+        .crawl
+        .portal_client(&log_config, &context, SUBSYS_BIDNET, DEFAULT_BIDNET_BASE_URL, &url)?
+        .account(login::ACCOUNT_ID)
+        .build()?;
+    let response = fetch_page(&client, &url, "document").await?;
+    attachment::record_attachment(&log_config, &req.crawl, SUBSYS_BIDNET, url.as_str(), &response).await;
+    info!("Downloaded BidNet document {} ({} bytes)", response.url(), response.content_length());
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Parse a BidNet snapshot for golden-file testing. `parser` is the name of the snapshot directory.
+#[cfg(any(test, feature = "test-utils"))]
+pub fn parse_snapshot(parser: &str, text: &str) -> Result<serde_json::Value, BoxError> {
+    use {crate::opportunity::DateLocale, serde_json::json};
+
+    let base_url = Url::parse(DEFAULT_BIDNET_BASE_URL)?;
+    let document = parse_html_str(text);
+
+    match parser {
+        "listing" => {
+            let page_url = base_url.join(&format!("{DEFAULT_REGION}/{OPEN_BIDS_PATH}"))?;
+            let urls = listing::parse_listing_page(&document, &page_url)?;
+            let next_page = listing::PAGINATION.next_url(&document, &page_url);
+
+            Ok(json!({
+                "Solicitations": urls.iter().map(Url::as_str).collect::<Vec<_>>(),
+                "NextPage": next_page.as_ref().map(Url::as_str),
+            }))
+        }
+        "solicitation" => {
+            let page_url = base_url.join(&format!("{DEFAULT_REGION}/solicitations/Solicitation/0"))?;
+            Ok(serde_json::to_value(solicitation::parse_solicitation_page(
+                &document,
+                &page_url,
+                DateLocale::default(),
+            )?)?)
+        }
+        _ => Err(format!("Unknown BidNet snapshot parser {parser}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{parse_snapshot, BidNetConfig, DEFAULT_BIDNET_BASE_URL},
+        crate::{
+            golden::{check_snapshots, snapshot_dir},
+            shapes::SUBSYS_BIDNET,
+        },
+        reqwest::Url,
+    };
+
+    #[test_log::test]
+    fn golden_snapshots() {
+        let checked = check_snapshots(&snapshot_dir(&SUBSYS_BIDNET.to_lowercase()), parse_snapshot)
+            .unwrap_or_else(|e| panic!("Golden snapshot mismatch:\n{e}"));
+        assert!(checked >= 2);
+    }
+
+    #[test]
+    fn config_listing_urls() {
+        let base_url = Url::parse(DEFAULT_BIDNET_BASE_URL).unwrap();
+        let urls = |config: &BidNetConfig| {
+            config.listing_urls(&base_url).unwrap().into_iter().map(String::from).collect::<Vec<_>>()
+        };
+
+        assert_eq!(urls(&BidNetConfig::default()), ["https://www.bidnetdirect.com/washington/solicitations/open-bids"]);
+
+        let config: BidNetConfig = serde_json::from_str(
+            r#"{"Regions": [{"Region": "oregon", "Groups": ["cityofportland", "multnomahcounty"]}, {"Region": "idaho"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            urls(&config),
+            [
+                "https://www.bidnetdirect.com/oregon/cityofportland/solicitations/open-bids",
+                "https://www.bidnetdirect.com/oregon/multnomahcounty/solicitations/open-bids",
+                "https://www.bidnetdirect.com/idaho/solicitations/open-bids",
+            ]
+        );
+
+        let config: BidNetConfig = serde_json::from_str(r#"{"Regions": []}"#).unwrap();
+        assert!(urls(&config).is_empty());
+    }
+}
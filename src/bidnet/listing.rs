@@ -0,0 +1,101 @@
+//! BidNet Direct open solicitation listing handling.
+//!
+//! A region's or purchasing group's open solicitations are listed in a table, paged with a `pageNumber` query
+//! parameter. Solicitation pages live under `/<region>/<group>/solicitations/<title>/<id>`.
+use {
+    crate::{
+        pagination::{NextLink, Pagination},
+        soup::{NodeExt, QueryBuilderExt},
+        BoxError,
+    },
+    log::*,
+    markup5ever_rcdom::RcDom,
+    reqwest::Url,
+};
+
+/// Path segment preceding the title and id of a solicitation page.
+const SOLICITATIONS_SEGMENT: &str = "solicitations";
+
+/// Each page of results links to the next with a link of class `next`.
+pub(crate) const PAGINATION: Pagination = Pagination::NextLink(&[NextLink::Class("next")]);
+
+/// Return the URLs of the solicitations on a page of open solicitations, in order.
+pub(crate) fn parse_listing_page(document: &RcDom, page_url: &Url) -> Result<Vec<Url>, BoxError> {
+    let Some(table) = document.tag("table").attr("id", "solicitationsTable").find() else {
+        return Err(format!("BidNet solicitations table not found on {page_url}").into());
+    };
+
+    let mut urls: Vec<Url> = Vec::new();
+    for a in table.tag("a").find_all() {
+        let Some(href) = a.get("href") else {
+            continue;
+        };
+
+        let url = match page_url.join(&href) {
+            Ok(url) => url,
+            Err(e) => {
+                warn!("Ignoring unparseable BidNet solicitation link {href}: {e}");
+                continue;
+            }
+        };
+
+        if !is_solicitation_url(&url) {
+            debug!("Ignoring non-solicitation link {href} in BidNet listing");
+            continue;
+        }
+
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+
+    Ok(urls)
+}
+
+/// Indicates whether a URL is a solicitation page: `.../solicitations/<title>/<numeric id>`.
+fn is_solicitation_url(url: &Url) -> bool {
+    let Some(segments) = url.path_segments() else {
+        return false;
+    };
+
+    let segments: Vec<&str> = segments.collect();
+    match segments.as_slice() {
+        [.., solicitations, _, id] => {
+            *solicitations == SOLICITATIONS_SEGMENT && !id.is_empty() && id.chars().all(|c| c.is_ascii_digit())
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{is_solicitation_url, parse_listing_page, PAGINATION},
+        crate::soup::parse_html_str,
+        reqwest::Url,
+    };
+
+    #[test]
+    fn listing_page() {
+        let page_url = Url::parse("https://www.bidnetdirect.com/washington/solicitations/open-bids").unwrap();
+        let document = parse_html_str(include_str!("bidnet-open-bids-page1.html"));
+
+        let urls = parse_listing_page(&document, &page_url).unwrap();
+        assert_eq!(urls.len(), 3);
+        assert_eq!(
+            urls[1].as_str(),
+            "https://www.bidnetdirect.com/washington/cityofspokane/solicitations/Snow-Plow-Blades/0000291902"
+        );
+        assert_eq!(PAGINATION.next_url(&document, &page_url).unwrap().query(), Some("pageNumber=2"));
+
+        assert!(parse_listing_page(&parse_html_str("<p>Error</p>"), &page_url).is_err());
+    }
+
+    #[test]
+    fn solicitation_urls() {
+        let url = |path: &str| Url::parse("https://www.bidnetdirect.com/").unwrap().join(path).unwrap();
+        assert!(is_solicitation_url(&url("/washington/kitsapcounty/solicitations/Fender-Repairs/0000291837")));
+        assert!(!is_solicitation_url(&url("/washington/solicitations/open-bids")));
+        assert!(!is_solicitation_url(&url("/washington/solicitations/open-bids?pageNumber=2")));
+    }
+}
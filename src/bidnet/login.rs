@@ -0,0 +1,117 @@
+//! BidNet Direct login handling.
+//!
+//! Solicitation documents are only offered to logged-in suppliers. The credentials are stored in SSM under the SSM
+//! prefix as `BidNet/Username` and `BidNet/Password`.
+use {
+    crate::{
+        httpext::{aws_err_str, Client, Form, LogConfig, Response as HttpResponse, ResponseExt},
+        BoxError,
+    },
+    log::*,
+};
+
+const SSM_USERNAME_PARAM: &str = "BidNet/Username";
+const SSM_PASSWORD_PARAM: &str = "BidNet/Password";
+const FORM_NAME_LOGIN: &str = "loginForm";
+const FORM_FIELD_USERNAME: &str = "j_username";
+const FORM_FIELD_PASSWORD: &str = "j_password";
+
+/// The path of the login page.
+pub(crate) const LOGIN_PATH: &str = "/public/authentication/login";
+
+/// The account id sessions are stored under; BidNet crawls use a single account.
+pub(crate) const ACCOUNT_ID: &str = "default";
+
+/// Phrases on the page returned by a login attempt that indicate the credentials were refused.
+const LOGIN_FAILED_PHRASES: &[&str] = &["invalid username or password", "your account is locked"];
+
+/// Check that the BidNet credentials are configured in SSM.
+pub(crate) async fn check_credentials(log_config: &LogConfig) -> Result<(), BoxError> {
+    let result = log_config
+        .ssm_client
+        .get_parameters()
+        .names(format!("{}{SSM_USERNAME_PARAM}", log_config.ssm_prefix))
+        .names(format!("{}{SSM_PASSWORD_PARAM}", log_config.ssm_prefix))
+        .send()
+        .await;
+
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => {
+            error!("GetParameters BidNet credentials: {}", aws_err_str(&e));
+            return Err(e.into());
+        }
+    };
+
+    if !output.invalid_parameters().is_empty() {
+        return Err(format!("BidNet credentials missing: {}", output.invalid_parameters().join(", ")).into());
+    }
+
+    Ok(())
+}
+
+/// Indicates whether a page shows the login form or a refused login rather than what was requested. After a login
+/// attempt, this means the login failed; later in a crawl, it means the session has expired.
+pub(crate) fn login_required(text: &str) -> bool {
+    let lower = text.to_lowercase();
+    LOGIN_FAILED_PHRASES.iter().any(|phrase| lower.contains(phrase))
+        || lower.contains(&format!(r#"name="{}""#, FORM_NAME_LOGIN.to_lowercase()))
+}
+
+/// Fill in and submit the login form on the login page in `response`.
+pub(crate) async fn submit_login(
+    client: &Client,
+    log_config: &LogConfig,
+    response: HttpResponse,
+) -> Result<HttpResponse, BoxError> {
+    let url = response.url().clone();
+    let mut form = match Form::from_unparsed_form_name(&url, response.text()?, FORM_NAME_LOGIN) {
+        Ok(form) => form,
+        Err(e) => {
+            error!("Failed to parse BidNet login form: {e}");
+            return Err(e);
+        }
+    };
+
+    form.set(FORM_FIELD_USERNAME, log_config.get_parameter(SSM_USERNAME_PARAM).await?);
+    form.set(FORM_FIELD_PASSWORD, log_config.get_parameter(SSM_PASSWORD_PARAM).await?);
+
+    let response = match client.request(form.method, form.url).form(&form.fields).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to submit BidNet login form: {e}");
+            return Err(e);
+        }
+    };
+
+    if login_required(response.text().unwrap_or_default()) {
+        return Err("BidNet refused the login; check the configured credentials".into());
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{login_required, FORM_FIELD_PASSWORD, FORM_FIELD_USERNAME, FORM_NAME_LOGIN, LOGIN_PATH},
+        crate::httpext::Form,
+        reqwest::Url,
+    };
+
+    #[test]
+    fn login_page() {
+        let text = include_str!("bidnet-login.html");
+        let url = Url::parse("https://www.bidnetdirect.com").unwrap().join(LOGIN_PATH).unwrap();
+        let form = Form::from_unparsed_form_name(&url, text, FORM_NAME_LOGIN).unwrap();
+
+        assert_eq!(form.url.as_str(), "https://www.bidnetdirect.com/j_spring_security_check");
+        assert!(form.fields.contains_key(FORM_FIELD_USERNAME));
+        assert!(form.fields.contains_key(FORM_FIELD_PASSWORD));
+        assert!(form.fields.contains_key("_csrf"));
+
+        assert!(login_required(text));
+        assert!(login_required("<div class='mets-alert'>Invalid username or password.</div>"));
+        assert!(!login_required(include_str!("bidnet-solicitation.html")));
+    }
+}
@@ -0,0 +1,188 @@
+//! BidNet Direct solicitation page handling.
+//!
+//! A solicitation page is a series of labelled fields (`<div class="mets-field-label">` followed by
+//! `<div class="mets-field-body">`), then a table of documents. Dates carry a trailing time zone abbreviation, e.g.
+//! `04/20/2023 02:00 PM PDT`, which is dropped since due dates are kept in the portal's local time.
+use {
+    crate::{
+        award::{page_awards, US_DOLLARS},
+        mets::{field_description, labelled_fields},
+        opportunity::{clean_text, parse_date_time, text_lines, Contact, DateLocale, Document, Opportunity},
+        shapes::SUBSYS_BIDNET,
+        soup::{NodeExt, QueryBuilderExt},
+        BoxError,
+    },
+    log::*,
+    markup5ever_rcdom::RcDom,
+    reqwest::Url,
+};
+
+const LABEL_REFERENCE: &str = "Reference Number";
+const LABEL_ORGANIZATION: &str = "Issuing Organization";
+const LABEL_LOCATION: &str = "Location";
+const LABEL_PUBLISHED: &str = "Publication Date";
+const LABEL_CLOSING: &str = "Closing Date";
+const LABEL_CONTACT: &str = "Contact Information";
+const LABEL_CATEGORIES: &str = "Categories";
+const LABEL_DESCRIPTION: &str = "Description";
+
+/// Separator between a commodity code and its description.
+const COMMODITY_CODE_SEPARATOR: &str = " - ";
+
+/// Suffix of a location naming a county, e.g. `Kitsap County, WA`.
+const COUNTY_SUFFIX: &str = " County";
+
+/// Path segment of document download links.
+const DOWNLOAD_SEGMENT: &str = "/download/";
+
+/// Parse a solicitation page into the normalized model.
+///
+/// A page without a title or a reference number is not a solicitation (usually an error or login page) and is an
+/// error.
+pub(crate) fn parse_solicitation_page(
+    document: &RcDom,
+    page_url: &Url,
+    date_locale: DateLocale,
+) -> Result<Opportunity, BoxError> {
+    let Some(title) = document.tag("h1").attr("class", "mets-page-title").find().and_then(|h1| clean_text(&h1.text()))
+    else {
+        return Err(format!("BidNet solicitation title not found on {page_url}").into());
+    };
+
+    let fields = labelled_fields(document);
+    let text = |label: &str| fields.get(label).and_then(|body| clean_text(&body.text()));
+    let Some(reference) = text(LABEL_REFERENCE) else {
+        return Err(format!("BidNet solicitation reference number not found on {page_url}").into());
+    };
+    let lines = |label: &str| fields.get(label).map(text_lines).unwrap_or_default();
+    let date_time = |label: &str| {
+        let value = text(label)?;
+        let date_time = parse_date_time(strip_time_zone(&value), date_locale);
+        if date_time.is_none() {
+            warn!("Unparseable BidNet {label} {value:?} on {page_url}");
+        }
+        date_time
+    };
+
+    let commodity_codes = lines(LABEL_CATEGORIES)
+        .into_iter()
+        .map(|line| match line.split_once(COMMODITY_CODE_SEPARATOR) {
+            Some((code, _)) => code.to_string(),
+            None => line,
+        })
+        .collect();
+
+    let counties = text(LABEL_LOCATION)
+        .and_then(|location| {
+            let place = location.split(',').next()?.trim();
+            place.strip_suffix(COUNTY_SUFFIX).map(str::to_string)
+        })
+        .into_iter()
+        .collect();
+
+    Ok(Opportunity {
+        portal: SUBSYS_BIDNET.to_string(),
+        url: page_url.to_string(),
+        title,
+        reference: Some(reference),
+        organization: text(LABEL_ORGANIZATION),
+        contact: Contact::from_lines(&lines(LABEL_CONTACT)),
+        published_on: date_time(LABEL_PUBLISHED).map(|published| published.date()),
+        due_at: date_time(LABEL_CLOSING),
+        description: fields.get(LABEL_DESCRIPTION).and_then(field_description),
+        commodity_codes,
+        counties,
+        documents: parse_documents(document, page_url),
+        awards: page_awards(document, date_locale, US_DOLLARS),
+        ..Opportunity::default()
+    })
+}
+
+/// Remove a trailing time zone abbreviation, e.g. `PDT`, from a date and time.
+fn strip_time_zone(text: &str) -> &str {
+    match text.rsplit_once(' ') {
+        Some((date_time, zone)) if (3..=4).contains(&zone.len()) && zone.chars().all(|c| c.is_ascii_uppercase()) => {
+            date_time
+        }
+        _ => text,
+    }
+}
+
+/// Return the documents listed with the solicitation.
+fn parse_documents(document: &RcDom, page_url: &Url) -> Vec<Document> {
+    let Some(table) = document.tag("table").attr("id", "documentsTable").find() else {
+        return vec![];
+    };
+
+    table
+        .tag("a")
+        .find_all()
+        .filter_map(|a| {
+            let href = a.get("href")?;
+            let url = match page_url.join(&href) {
+                Ok(url) => url,
+                Err(e) => {
+                    warn!("Ignoring unparseable BidNet document link {href}: {e}");
+                    return None;
+                }
+            };
+
+            if !url.path().contains(DOWNLOAD_SEGMENT) {
+                return None;
+            }
+
+            Some(Document {
+                name: clean_text(&a.text()).unwrap_or_else(|| url.to_string()),
+                url: url.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{parse_solicitation_page, strip_time_zone},
+        crate::{opportunity::DateLocale, soup::parse_html_str},
+        chrono::NaiveDate,
+        reqwest::Url,
+    };
+
+    #[test]
+    fn solicitation_page() {
+        let url = Url::parse(
+            "https://www.bidnetdirect.com/washington/kitsapcounty/solicitations/Ferry-Terminal-Fender-Repairs/0000291837",
+        )
+        .unwrap();
+        let opportunity = parse_solicitation_page(
+            &parse_html_str(include_str!("bidnet-solicitation.html")),
+            &url,
+            DateLocale::English,
+        )
+        .unwrap();
+
+        assert_eq!(opportunity.title, "Ferry Terminal Fender Repairs");
+        assert_eq!(opportunity.reference.as_deref(), Some("RFB 23-041"));
+        assert_eq!(opportunity.organization.as_deref(), Some("Kitsap County"));
+        assert_eq!(opportunity.contact.name.as_deref(), Some("Robin Salas"));
+        assert_eq!(opportunity.contact.email.as_deref(), Some("rsalas@kitsap.gov"));
+        assert_eq!(opportunity.contact.phone.as_deref(), Some("(360) 337-4788"));
+        assert_eq!(opportunity.published_on, NaiveDate::from_ymd_opt(2023, 3, 28));
+        assert_eq!(opportunity.due_at, NaiveDate::from_ymd_opt(2023, 4, 20).unwrap().and_hms_opt(14, 0, 0));
+        assert_eq!(opportunity.commodity_codes, vec!["72141100", "30103600"]);
+        assert_eq!(opportunity.counties, vec!["Kitsap"]);
+        assert_eq!(opportunity.description.unwrap().lines().count(), 2);
+        assert_eq!(opportunity.documents.len(), 3);
+        assert_eq!(opportunity.documents[2].name, "Bid Form.docx");
+
+        assert!(parse_solicitation_page(&parse_html_str(include_str!("bidnet-login.html")), &url, DateLocale::English)
+            .is_err());
+    }
+
+    #[test]
+    fn time_zones() {
+        assert_eq!(strip_time_zone("04/20/2023 02:00 PM PDT"), "04/20/2023 02:00 PM");
+        assert_eq!(strip_time_zone("04/20/2023 02:00 PM"), "04/20/2023 02:00 PM");
+        assert_eq!(strip_time_zone("04/20/2023"), "04/20/2023");
+    }
+}
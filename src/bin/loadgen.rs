@@ -0,0 +1,461 @@
+//! Load-test driver producing synthetic crawl traffic.
+//!
+//! `loadgen` seeds the crawler's SQS queue with synthetic crawls and measures how the deployment absorbs them, to size
+//! the SQS batch settings, Lambda concurrency, and DynamoDB capacity before rolling out to more portals. Each crawl is
+//! either a `TestPortal:StartCrawl` against a deployed test portal, or a replay of archived request messages (one JSON
+//! request per line, as logged by the crawler) under a fresh crawl id.
+//!
+//! Once the seed messages are sent, the queue is polled until it drains. The report, printed as JSON, gives the send
+//! rate and SQS call rate of the driver itself, how long the crawler took to work through the fan-out, and the peak
+//! number of messages waiting and in flight; the latter approximates the concurrency the crawler reached.
+//!
+//! It is configured with environment variables:
+//! - `SQS_QUEUE_URL`: the crawler's queue. Required unless `LOADGEN_DRY_RUN` is set.
+//! - `LOADGEN_PORTAL_URL`: the base URL of the test portal to crawl.
+//! - `LOADGEN_REPLAY_FILE`: a file of archived requests to replay instead.
+//! - `LOADGEN_CRAWLS`: the number of crawls to start (default 10).
+//! - `LOADGEN_RATE`: the most messages to send per second, or 0 for no limit (default 0).
+//! - `LOADGEN_BATCH_SIZE`: the number of messages per `SendMessageBatch` call, at most 10 (default 10).
+//! - `LOADGEN_POLL_SECS`: how often to poll the queue while it drains (default 10).
+//! - `LOADGEN_TIMEOUT_SECS`: how long to wait for the queue to drain (default 3600).
+//! - `LOADGEN_DRY_RUN`: print the messages instead of sending them.
+use {
+    aws_sdk_sqs::{
+        types::{MessageAttributeValue, QueueAttributeName, SendMessageBatchRequestEntry},
+        Client as SqsClient,
+    },
+    govscout_backend::BoxError,
+    log::*,
+    serde::Serialize,
+    serde_json::{json, Value},
+    std::{
+        env, fs,
+        time::{Duration, Instant},
+    },
+    tokio::time::sleep,
+    uuid::{NoContext, Timestamp, Uuid},
+};
+
+const ENV_SQS_QUEUE_URL: &str = "SQS_QUEUE_URL";
+const ENV_LOADGEN_PORTAL_URL: &str = "LOADGEN_PORTAL_URL";
+const ENV_LOADGEN_REPLAY_FILE: &str = "LOADGEN_REPLAY_FILE";
+const ENV_LOADGEN_CRAWLS: &str = "LOADGEN_CRAWLS";
+const ENV_LOADGEN_RATE: &str = "LOADGEN_RATE";
+const ENV_LOADGEN_BATCH_SIZE: &str = "LOADGEN_BATCH_SIZE";
+const ENV_LOADGEN_POLL_SECS: &str = "LOADGEN_POLL_SECS";
+const ENV_LOADGEN_TIMEOUT_SECS: &str = "LOADGEN_TIMEOUT_SECS";
+const ENV_LOADGEN_DRY_RUN: &str = "LOADGEN_DRY_RUN";
+
+const DEFAULT_CRAWLS: usize = 10;
+const DEFAULT_POLL_SECS: u64 = 10;
+const DEFAULT_TIMEOUT_SECS: u64 = 3600;
+const MAX_SQS_BATCH_SIZE: usize = 10;
+
+const OP_TEST_PORTAL_START_CRAWL: &str = "TestPortal:StartCrawl";
+const MSG_ATTR_SUBSYSTEM: &str = "Subsystem";
+const MSG_ATTR_OPERATION: &str = "Operation";
+const MSG_DATA_TYPE_STRING: &str = "String";
+
+/// Request fields describing a delivery rather than the work, dropped from replayed requests.
+const DELIVERY_FIELDS: &[&str] = &["Attempt", "FailedAttempts"];
+
+/// The number of consecutive empty polls after which the queue is taken to have drained. SQS queue counts are
+/// approximate and lag behind, so a single empty poll isn't trusted.
+const EMPTY_POLLS_TO_DRAIN: usize = 2;
+
+/// Where the seed requests of each crawl come from.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Source {
+    /// Start a crawl of the test portal at this base URL.
+    TestPortal(String),
+
+    /// Replay the archived requests in this file.
+    Replay(String),
+}
+
+/// Settings read from environment variables.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Settings {
+    queue_url: Option<String>,
+    source: Source,
+    crawls: usize,
+    rate: u32,
+    batch_size: usize,
+    poll: Duration,
+    timeout: Duration,
+}
+
+impl Settings {
+    /// Read and validate the settings, looking variables up with `lookup`.
+    fn from_lookup<F: Fn(&str) -> Option<String>>(lookup: F) -> Result<Self, String> {
+        let number = |var: &str, default: u64| match lookup(var) {
+            None => Ok(default),
+            Some(value) => value.parse::<u64>().map_err(|e| format!("{var}: invalid value {value:?}: {e}")),
+        };
+
+        let source = match (lookup(ENV_LOADGEN_PORTAL_URL), lookup(ENV_LOADGEN_REPLAY_FILE)) {
+            (Some(url), None) => Source::TestPortal(url),
+            (None, Some(path)) => Source::Replay(path),
+            _ => {
+                return Err(format!(
+                    "Exactly one of {ENV_LOADGEN_PORTAL_URL} and {ENV_LOADGEN_REPLAY_FILE} must be set"
+                ))
+            }
+        };
+
+        let dry_run = lookup(ENV_LOADGEN_DRY_RUN).is_some_and(|value| !value.is_empty());
+        let queue_url = lookup(ENV_SQS_QUEUE_URL).filter(|url| !url.is_empty());
+        if queue_url.is_none() && !dry_run {
+            return Err(format!("{ENV_SQS_QUEUE_URL} must be set unless {ENV_LOADGEN_DRY_RUN} is"));
+        }
+
+        let batch_size = number(ENV_LOADGEN_BATCH_SIZE, MAX_SQS_BATCH_SIZE as u64)? as usize;
+        if !(1..=MAX_SQS_BATCH_SIZE).contains(&batch_size) {
+            return Err(format!("{ENV_LOADGEN_BATCH_SIZE} must be between 1 and {MAX_SQS_BATCH_SIZE}"));
+        }
+
+        Ok(Self {
+            queue_url: if dry_run {
+                None
+            } else {
+                queue_url
+            },
+            source,
+            crawls: number(ENV_LOADGEN_CRAWLS, DEFAULT_CRAWLS as u64)? as usize,
+            rate: number(ENV_LOADGEN_RATE, 0)? as u32,
+            batch_size,
+            poll: Duration::from_secs(number(ENV_LOADGEN_POLL_SECS, DEFAULT_POLL_SECS)?.max(1)),
+            timeout: Duration::from_secs(number(ENV_LOADGEN_TIMEOUT_SECS, DEFAULT_TIMEOUT_SECS)?),
+        })
+    }
+}
+
+/// The outcome of a load test.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Report {
+    /// The prefix of the crawl ids of this run.
+    run_id: String,
+
+    /// The number of crawls started.
+    crawls: usize,
+
+    /// The number of seed messages sent.
+    messages_sent: usize,
+
+    /// The number of `SendMessageBatch` calls made.
+    send_calls: usize,
+
+    /// How long sending the seed messages took, in seconds.
+    send_seconds: f64,
+
+    /// Seed messages sent per second.
+    send_rate: f64,
+
+    /// `SendMessageBatch` calls made per second.
+    send_call_rate: f64,
+
+    /// The number of `GetQueueAttributes` calls made while waiting for the queue to drain.
+    poll_calls: usize,
+
+    /// Whether the queue drained before the timeout.
+    drained: bool,
+
+    /// How long from the first message sent until the queue drained (or the timeout), in seconds.
+    elapsed_seconds: f64,
+
+    /// Crawls completed per second, end to end.
+    crawls_per_second: f64,
+
+    /// The most messages seen waiting to be received.
+    peak_visible: u64,
+
+    /// The most messages seen in flight, received but not yet deleted.
+    peak_in_flight: u64,
+}
+
+/// The approximate message counts of a queue.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+struct QueueDepth {
+    visible: u64,
+    in_flight: u64,
+    delayed: u64,
+}
+
+impl QueueDepth {
+    /// Return the total number of messages in the queue.
+    fn total(&self) -> u64 {
+        self.visible + self.in_flight + self.delayed
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), BoxError> {
+    env_logger::init();
+    let settings = Settings::from_lookup(|var| env::var(var).ok())?;
+    let archive = match &settings.source {
+        Source::TestPortal(_) => vec![],
+        Source::Replay(path) => read_archive(&fs::read_to_string(path)?)?,
+    };
+
+    let run_id = format!("loadgen-{}", Uuid::new_v7(Timestamp::now(NoContext)));
+    let mut requests = Vec::new();
+    for n in 0..settings.crawls {
+        requests.extend(seed_requests(&settings.source, &format!("{run_id}-{n}"), &archive));
+    }
+
+    let Some(queue_url) = settings.queue_url.as_deref() else {
+        for request in requests.iter() {
+            println!("{}", serde_json::to_string(request)?);
+        }
+        return Ok(());
+    };
+
+    let sqs_client = SqsClient::new(&aws_config::load_from_env().await);
+    let mut report = Report {
+        run_id,
+        crawls: settings.crawls,
+        ..Report::default()
+    };
+
+    let started = Instant::now();
+    send_requests(&sqs_client, queue_url, &settings, &requests, &mut report).await?;
+    report.send_seconds = started.elapsed().as_secs_f64();
+    report.send_rate = report.messages_sent as f64 / report.send_seconds.max(f64::EPSILON);
+    report.send_call_rate = report.send_calls as f64 / report.send_seconds.max(f64::EPSILON);
+    info!("Sent {} message(s) in {:.1}s", report.messages_sent, report.send_seconds);
+
+    wait_for_drain(&sqs_client, queue_url, &settings, started, &mut report).await?;
+    report.elapsed_seconds = started.elapsed().as_secs_f64();
+    report.crawls_per_second = report.crawls as f64 / report.elapsed_seconds.max(f64::EPSILON);
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+/// Parse an archive of requests, one JSON object per line. Blank lines are ignored.
+fn read_archive(text: &str) -> Result<Vec<Value>, BoxError> {
+    let mut archive = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str::<Value>(line) {
+            Ok(request) if request.get("Operation").is_some_and(Value::is_string) => archive.push(request),
+            Ok(_) => return Err(format!("Archived request on line {} has no operation", n + 1).into()),
+            Err(e) => return Err(format!("Unparseable archived request on line {}: {e}", n + 1).into()),
+        }
+    }
+
+    Ok(archive)
+}
+
+/// Return the seed requests of one crawl.
+fn seed_requests(source: &Source, crawl_id: &str, archive: &[Value]) -> Vec<Value> {
+    match source {
+        Source::TestPortal(url) => vec![json!({
+            "Operation": OP_TEST_PORTAL_START_CRAWL,
+            "Url": url,
+            "CrawlId": crawl_id,
+        })],
+        Source::Replay(_) => archive
+            .iter()
+            .cloned()
+            .map(|mut request| {
+                if let Some(fields) = request.as_object_mut() {
+                    for field in DELIVERY_FIELDS {
+                        fields.remove(*field);
+                    }
+                    fields.insert("CrawlId".to_string(), Value::String(crawl_id.to_string()));
+                }
+                request
+            })
+            .collect(),
+    }
+}
+
+/// Convert a request into an SQS message, with the attributes the crawler sets on the messages it sends.
+fn message_entry(request: &Value, id: Uuid) -> Result<SendMessageBatchRequestEntry, BoxError> {
+    let operation = request.get("Operation").and_then(Value::as_str).unwrap_or_default();
+    let (subsystem, operation) = operation.split_once(':').ok_or_else(|| format!("Invalid operation {operation}"))?;
+    let attribute =
+        |value: &str| MessageAttributeValue::builder().string_value(value).data_type(MSG_DATA_TYPE_STRING).build();
+
+    Ok(SendMessageBatchRequestEntry::builder()
+        .id(id)
+        .message_body(serde_json::to_string(request)?)
+        .message_attributes(MSG_ATTR_SUBSYSTEM, attribute(subsystem)?)
+        .message_attributes(MSG_ATTR_OPERATION, attribute(operation)?)
+        .build()?)
+}
+
+/// Send the seed requests in batches, holding to the configured rate.
+async fn send_requests(
+    sqs_client: &SqsClient,
+    queue_url: &str,
+    settings: &Settings,
+    requests: &[Value],
+    report: &mut Report,
+) -> Result<(), BoxError> {
+    let timestamp = Timestamp::now(NoContext);
+    let started = Instant::now();
+
+    for batch in requests.chunks(settings.batch_size) {
+        // Wait until sending this batch keeps the average at or under the rate.
+        if settings.rate > 0 {
+            let due = Duration::from_secs_f64(report.messages_sent as f64 / settings.rate as f64);
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                sleep(wait).await;
+            }
+        }
+
+        let entries = batch
+            .iter()
+            .map(|request| message_entry(request, Uuid::new_v7(timestamp)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let output = sqs_client.send_message_batch().queue_url(queue_url).set_entries(Some(entries)).send().await?;
+        report.send_calls += 1;
+
+        for failed in output.failed() {
+            warn!("Failed to send message {}: {}", failed.id(), failed.message().unwrap_or(failed.code()));
+        }
+        report.messages_sent += output.successful().len();
+    }
+
+    Ok(())
+}
+
+/// Return the approximate message counts of the queue.
+async fn queue_depth(sqs_client: &SqsClient, queue_url: &str) -> Result<QueueDepth, BoxError> {
+    let output = sqs_client
+        .get_queue_attributes()
+        .queue_url(queue_url)
+        .attribute_names(QueueAttributeName::ApproximateNumberOfMessages)
+        .attribute_names(QueueAttributeName::ApproximateNumberOfMessagesNotVisible)
+        .attribute_names(QueueAttributeName::ApproximateNumberOfMessagesDelayed)
+        .send()
+        .await?;
+
+    let count = |name: QueueAttributeName| {
+        output.attributes().and_then(|attributes| attributes.get(&name)).and_then(|n| n.parse().ok()).unwrap_or(0)
+    };
+
+    Ok(QueueDepth {
+        visible: count(QueueAttributeName::ApproximateNumberOfMessages),
+        in_flight: count(QueueAttributeName::ApproximateNumberOfMessagesNotVisible),
+        delayed: count(QueueAttributeName::ApproximateNumberOfMessagesDelayed),
+    })
+}
+
+/// Poll the queue until it drains or the timeout passes, recording the peak depths seen.
+///
+/// The queue is shared with any other crawls running at the time, so the load test is only meaningful on a quiet
+/// deployment.
+async fn wait_for_drain(
+    sqs_client: &SqsClient,
+    queue_url: &str,
+    settings: &Settings,
+    started: Instant,
+    report: &mut Report,
+) -> Result<(), BoxError> {
+    let mut empty_polls = 0;
+
+    while started.elapsed() < settings.timeout {
+        let depth = queue_depth(sqs_client, queue_url).await?;
+        report.poll_calls += 1;
+        report.peak_visible = report.peak_visible.max(depth.visible);
+        report.peak_in_flight = report.peak_in_flight.max(depth.in_flight);
+        info!(
+            "{:.0}s: {} visible, {} in flight, {} delayed",
+            started.elapsed().as_secs_f64(),
+            depth.visible,
+            depth.in_flight,
+            depth.delayed
+        );
+
+        empty_polls = if depth.total() == 0 {
+            empty_polls + 1
+        } else {
+            0
+        };
+        if empty_polls == EMPTY_POLLS_TO_DRAIN {
+            report.drained = true;
+            return Ok(());
+        }
+
+        sleep(settings.poll).await;
+    }
+
+    warn!("Queue didn't drain within {:?}", settings.timeout);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{read_archive, seed_requests, Settings, Source},
+        std::{collections::HashMap, time::Duration},
+    };
+
+    fn lookup(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |var| vars.get(var).cloned()
+    }
+
+    #[test]
+    fn settings() {
+        let settings = Settings::from_lookup(lookup(&[
+            ("SQS_QUEUE_URL", "https://sqs.us-west-2.amazonaws.com/123456789012/crawl"),
+            ("LOADGEN_PORTAL_URL", "https://testportal.example.com/"),
+            ("LOADGEN_RATE", "50"),
+        ]))
+        .unwrap();
+        assert_eq!(settings.source, Source::TestPortal("https://testportal.example.com/".to_string()));
+        assert_eq!((settings.crawls, settings.rate, settings.batch_size), (10, 50, 10));
+        assert_eq!(settings.poll, Duration::from_secs(10));
+
+        // A dry run doesn't need a queue.
+        let dry_run =
+            Settings::from_lookup(lookup(&[("LOADGEN_REPLAY_FILE", "requests.jsonl"), ("LOADGEN_DRY_RUN", "1")]))
+                .unwrap();
+        assert_eq!(dry_run.queue_url, None);
+
+        assert!(Settings::from_lookup(lookup(&[("LOADGEN_PORTAL_URL", "https://testportal.example.com/")])).is_err());
+        assert!(Settings::from_lookup(lookup(&[
+            ("LOADGEN_PORTAL_URL", "https://testportal.example.com/"),
+            ("LOADGEN_REPLAY_FILE", "requests.jsonl"),
+            ("LOADGEN_DRY_RUN", "1"),
+        ]))
+        .is_err());
+        assert!(Settings::from_lookup(lookup(&[
+            ("LOADGEN_PORTAL_URL", "https://testportal.example.com/"),
+            ("LOADGEN_BATCH_SIZE", "11"),
+            ("LOADGEN_DRY_RUN", "1"),
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn seeds() {
+        let portal = seed_requests(&Source::TestPortal("https://testportal.example.com/".to_string()), "run-0", &[]);
+        assert_eq!(portal.len(), 1);
+        assert_eq!(portal[0]["Operation"], "TestPortal:StartCrawl");
+        assert_eq!(portal[0]["CrawlId"], "run-0");
+
+        let archive = read_archive(
+            r#"{"Operation":"Webs:FetchBidPage","Url":"https://example.com/bid/1","CrawlId":"old","Attempt":2}
+
+{"Operation":"Merx:StartCrawl"}"#,
+        )
+        .unwrap();
+        let replayed = seed_requests(&Source::Replay("requests.jsonl".to_string()), "run-1", &archive);
+        assert_eq!(replayed.len(), 2);
+        assert_eq!(replayed[0]["CrawlId"], "run-1");
+        assert_eq!(replayed[0]["Url"], "https://example.com/bid/1");
+        assert!(replayed[0].get("Attempt").is_none());
+
+        assert!(read_archive(r#"{"Url":"https://example.com/"}"#).is_err());
+        assert!(read_archive("not json").is_err());
+    }
+}
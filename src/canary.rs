@@ -0,0 +1,334 @@
+//! Parser regression canary.
+//!
+//! The canary fetches a small set of known-stable portal pages, runs the corresponding parser over each, and compares
+//! a few key extracted fields against expected values stored in DynamoDB. Drift means a portal has changed its markup
+//! and the next crawl is likely to silently produce garbage, so it is alerted on immediately.
+use {
+    crate::{
+        health::publish_alert,
+        httpext::{aws_err_str, HostAllowlist, LogConfig},
+        journal::CrawlEvent,
+        metrics,
+        shapes::{Request, Response, SUBSYS_CANARY, SUBSYS_WEBS},
+        webs, BoxError,
+    },
+    aws_sdk_dynamodb::types::AttributeValue,
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    reqwest::Url,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{BTreeMap, HashMap},
+        env,
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+const OP_CHECK: &str = "Check";
+
+const ENV_CANARY_DYNAMODB_TABLE: &str = "CANARY_DYNAMODB_TABLE";
+
+/// Partition key value for canary target items.
+const CANARY_PARTITION_KEY: &str = "Canary#Targets";
+
+const DDB_KEY_CRAWL_ID: &str = "CrawlId";
+const DDB_KEY_REQUEST_ID: &str = "RequestId";
+const DDB_KEY_URL: &str = "Url";
+const DDB_KEY_PARSER: &str = "Parser";
+const DDB_KEY_EXPECTED: &str = "Expected";
+
+const METRIC_CANARY_DRIFT: &str = "CanaryDrift";
+const DIMENSION_TARGET: &str = "Target";
+
+/// Possible operations for the canary.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum CanaryOperation {
+    /// Check all canary targets for parser drift.
+    Check,
+}
+
+/// A page the canary checks.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CanaryTarget {
+    /// The name of the target.
+    pub name: String,
+
+    /// The URL of the page.
+    pub url: String,
+
+    /// The parser to run over the page, e.g. `Webs:OpportunityListing`.
+    pub parser: String,
+
+    /// The expected values of the extracted fields.
+    pub expected: BTreeMap<String, String>,
+}
+
+/// A difference between an expected and an extracted field.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FieldDrift {
+    /// The name of the field.
+    pub field: String,
+
+    /// The expected value.
+    pub expected: String,
+
+    /// The extracted value, or `None` if the field could not be extracted.
+    pub actual: Option<String>,
+}
+
+impl FromStr for CanaryOperation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            OP_CHECK => Ok(Self::Check),
+            _ => Err(format!("Unknown operation: {value}")),
+        }
+    }
+}
+
+impl Display for CanaryOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.operation())
+    }
+}
+
+impl Display for FieldDrift {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match &self.actual {
+            Some(actual) => write!(f, "{}: expected {:?}, got {:?}", self.field, self.expected, actual),
+            None => write!(f, "{}: expected {:?}, but the field was not extracted", self.field, self.expected),
+        }
+    }
+}
+
+impl CanaryOperation {
+    /// Handle a request.
+    pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+        match self {
+            Self::Check => check(log_config, req, context).await,
+        }
+    }
+
+    /// Return the operation name within the subsystem.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::Check => OP_CHECK,
+        }
+    }
+}
+
+impl CanaryTarget {
+    /// Convert a DynamoDB item into a canary target.
+    fn from_item(item: &HashMap<String, AttributeValue>) -> Result<Self, BoxError> {
+        let get_str = |name: &str| -> Result<String, BoxError> {
+            match item.get(name).and_then(|v| v.as_s().ok()) {
+                Some(value) => Ok(value.clone()),
+                None => Err(format!("Canary target is missing string attribute {name}").into()),
+            }
+        };
+
+        let Some(expected) = item.get(DDB_KEY_EXPECTED).and_then(|v| v.as_m().ok()) else {
+            return Err(format!("Canary target is missing map attribute {DDB_KEY_EXPECTED}").into());
+        };
+
+        let expected = expected
+            .iter()
+            .filter_map(|(k, v)| match v {
+                AttributeValue::S(s) => Some((k.clone(), s.clone())),
+                AttributeValue::N(n) => Some((k.clone(), n.clone())),
+                _ => {
+                    warn!("Ignoring non-scalar expected value for canary field {k}");
+                    None
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            name: get_str(DDB_KEY_REQUEST_ID)?,
+            url: get_str(DDB_KEY_URL)?,
+            parser: get_str(DDB_KEY_PARSER)?,
+            expected,
+        })
+    }
+}
+
+/// Compare expected fields against extracted fields. Extra extracted fields are ignored.
+pub fn compare(expected: &BTreeMap<String, String>, actual: &BTreeMap<String, String>) -> Vec<FieldDrift> {
+    expected
+        .iter()
+        .filter(|(field, value)| actual.get(*field) != Some(*value))
+        .map(|(field, value)| FieldDrift {
+            field: field.clone(),
+            expected: value.clone(),
+            actual: actual.get(field).cloned(),
+        })
+        .collect()
+}
+
+/// Extract the key fields from a page using the named parser.
+fn extract_fields(parser: &str, url: &Url, text: &str) -> Result<BTreeMap<String, String>, BoxError> {
+    match parser.split_once(':') {
+        Some((SUBSYS_WEBS, _)) => webs::extract_canary_fields(parser, url, text),
+        _ => Err(format!("Unknown canary parser {parser}").into()),
+    }
+}
+
+/// Return the name of the table canary targets are stored in.
+pub fn targets_table(log_config: &LogConfig) -> String {
+    env::var(ENV_CANARY_DYNAMODB_TABLE).unwrap_or_else(|_| log_config.ddb_table.clone())
+}
+
+/// Load the canary targets from DynamoDB.
+async fn load_targets(log_config: &LogConfig) -> Result<Vec<CanaryTarget>, BoxError> {
+    let table = targets_table(log_config);
+    let mut targets = Vec::new();
+    let mut exclusive_start_key = None;
+
+    loop {
+        let result = log_config
+            .ddb_client
+            .query()
+            .table_name(&table)
+            .key_condition_expression("#pk = :pk")
+            .expression_attribute_names("#pk", DDB_KEY_CRAWL_ID)
+            .expression_attribute_values(":pk", AttributeValue::S(CANARY_PARTITION_KEY.to_string()))
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await;
+
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => {
+                error!("Query canary targets in {table}: {}", aws_err_str(&e));
+                return Err(e.into());
+            }
+        };
+
+        for item in output.items() {
+            match CanaryTarget::from_item(item) {
+                Ok(target) => targets.push(target),
+                Err(e) => warn!("Skipping invalid canary target: {e}"),
+            }
+        }
+
+        exclusive_start_key = output.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+
+    Ok(targets)
+}
+
+/// Check every canary target, alerting on any drift.
+async fn check(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let targets = load_targets(&log_config).await?;
+    info!("Checking {} canary targets", targets.len());
+
+    let target_urls: Vec<Url> = targets.iter().filter_map(|target| Url::parse(&target.url).ok()).collect();
+    let client = req
+        .crawl
+        .build_client(log_config.clone(), &context)
+        .portal(SUBSYS_CANARY)
+        .allowed_hosts(HostAllowlist::from_urls(&target_urls))
+        .build()?;
+    let mut report = String::new();
+
+    for target in targets.iter() {
+        let result = match Url::parse(&target.url) {
+            Ok(url) => match client.get(url.clone()).send().await {
+                Ok(response) => match response.text() {
+                    Ok(text) => extract_fields(&target.parser, &url, text),
+                    Err(e) => Err(e.into()),
+                },
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e.into()),
+        };
+
+        let (drifts, event) = match result {
+            Ok(actual) => {
+                let drifts: Vec<String> = compare(&target.expected, &actual).iter().map(|d| d.to_string()).collect();
+                let event = CrawlEvent::ParseSucceeded {
+                    url: target.url.clone(),
+                    parser: target.parser.clone(),
+                    items: actual.len(),
+                };
+                (drifts, event)
+            }
+            Err(e) => {
+                let event = CrawlEvent::ParseFailed {
+                    url: target.url.clone(),
+                    parser: target.parser.clone(),
+                    error: e.to_string(),
+                };
+                (vec![format!("failed to check page: {e}")], event)
+            }
+        };
+
+        log_config.journal.record(&client.crawl_id, event).await;
+        metrics::registry().increment(METRIC_CANARY_DRIFT, &[(DIMENSION_TARGET, &target.name)], drifts.len() as u64);
+
+        if drifts.is_empty() {
+            info!("Canary target {} matches expected values", target.name);
+            continue;
+        }
+
+        report.push_str(&format!("{} ({} via {}):\n", target.name, target.url, target.parser));
+        for drift in drifts {
+            report.push_str(&format!("- {drift}\n"));
+        }
+    }
+
+    if !report.is_empty() {
+        let _ = publish_alert(&log_config, "GovScout canary detected parser drift", &report).await;
+    }
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{compare, CanaryTarget, FieldDrift},
+        aws_sdk_dynamodb::types::AttributeValue,
+        std::collections::{BTreeMap, HashMap},
+    };
+
+    #[test]
+    fn drift_detection() {
+        let item = HashMap::from([
+            ("CrawlId".to_string(), AttributeValue::S("Canary#Targets".to_string())),
+            ("RequestId".to_string(), AttributeValue::S("webs-home".to_string())),
+            ("Url".to_string(), AttributeValue::S("https://example.com/Home.aspx".to_string())),
+            ("Parser".to_string(), AttributeValue::S("Webs:Home".to_string())),
+            (
+                "Expected".to_string(),
+                AttributeValue::M(HashMap::from([
+                    ("SearchUrl".to_string(), AttributeValue::S("https://example.com/Search_Bid.aspx".to_string())),
+                    ("Links".to_string(), AttributeValue::N("3".to_string())),
+                ])),
+            ),
+        ]);
+        let target = CanaryTarget::from_item(&item).unwrap();
+        assert_eq!(target.name.as_str(), "webs-home");
+        assert_eq!(target.expected.len(), 2);
+
+        let actual = BTreeMap::from([
+            ("SearchUrl".to_string(), "https://example.com/Search_Bid.aspx".to_string()),
+            ("Extra".to_string(), "ignored".to_string()),
+        ]);
+        assert_eq!(
+            compare(&target.expected, &actual),
+            vec![FieldDrift {
+                field: "Links".to_string(),
+                expected: "3".to_string(),
+                actual: None,
+            }]
+        );
+    }
+}
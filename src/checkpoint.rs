@@ -0,0 +1,199 @@
+//! Checkpoints for resumable crawl operations.
+//!
+//! Long-running operations (such as paging through a listing) periodically save their progress so that, if the
+//! Lambda times out or crashes and SQS redelivers the message, the retry resumes where the previous attempt left off.
+//!
+//! Operations that mustn't run concurrently for the same crawl, such as two deliveries of one message both resuming a
+//! checkpoint, take a lease on the checkpoint's scope first; see [`acquire_lease`][CheckpointStore::acquire_lease].
+use {
+    crate::{httpext::aws_err_str, BoxError},
+    aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoDbClient},
+    chrono::{Duration, Utc},
+    log::*,
+    serde::{de::DeserializeOwned, Serialize},
+};
+
+const DDB_KEY_CRAWL_ID: &str = "CrawlId";
+const DDB_KEY_REQUEST_ID: &str = "RequestId";
+const DDB_KEY_STATE: &str = "State";
+const DDB_KEY_UPDATED_AT: &str = "UpdatedAt";
+const DDB_KEY_EXPIRES_AT: &str = "ExpiresAt";
+const DDB_KEY_HOLDER: &str = "Holder";
+
+/// Partition key prefix for checkpoint items, keeping them apart from request log items in a shared table.
+const CHECKPOINT_PARTITION_PREFIX: &str = "Checkpoint#";
+
+/// Sort key prefix for lease items, within a crawl's checkpoint partition.
+const LEASE_SORT_PREFIX: &str = "Lease#";
+
+/// How long an abandoned checkpoint is kept before the table's TTL removes it.
+const CHECKPOINT_TTL_DAYS: i64 = 7;
+
+/// Storage for operation checkpoints in DynamoDB.
+///
+/// Checkpoints are keyed by crawl id and a scope naming the operation, and hold arbitrary serializable state.
+#[derive(Clone, Debug)]
+pub struct CheckpointStore {
+    ddb_client: DynamoDbClient,
+    table_name: String,
+}
+
+impl CheckpointStore {
+    /// Create a checkpoint store using the given table.
+    pub fn new<S: Into<String>>(ddb_client: DynamoDbClient, table_name: S) -> Self {
+        Self {
+            ddb_client,
+            table_name: table_name.into(),
+        }
+    }
+
+    /// Return the name of the table checkpoints are stored in.
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    /// Load the checkpoint for an operation, if one exists.
+    pub async fn load<T: DeserializeOwned>(&self, crawl_id: &str, scope: &str) -> Result<Option<T>, BoxError> {
+        let result = self
+            .ddb_client
+            .get_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{CHECKPOINT_PARTITION_PREFIX}{crawl_id}")))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(scope.to_string()))
+            .consistent_read(true)
+            .send()
+            .await;
+
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => {
+                error!("GetItem checkpoint {scope} for crawl {crawl_id}: {}", aws_err_str(&e));
+                return Err(e.into());
+            }
+        };
+
+        let Some(state) = output.item.as_ref().and_then(|item| item.get(DDB_KEY_STATE)).and_then(|v| v.as_s().ok())
+        else {
+            return Ok(None);
+        };
+
+        match serde_json::from_str(state) {
+            Ok(state) => Ok(Some(state)),
+            Err(e) => {
+                warn!("Ignoring unreadable checkpoint {scope} for crawl {crawl_id}: {e}");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Save the checkpoint for an operation, replacing any previous checkpoint.
+    pub async fn save<T: Serialize>(&self, crawl_id: &str, scope: &str, state: &T) -> Result<(), BoxError> {
+        let now = Utc::now();
+        let expires_at = now + Duration::days(CHECKPOINT_TTL_DAYS);
+
+        let result = self
+            .ddb_client
+            .put_item()
+            .table_name(&self.table_name)
+            .item(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{CHECKPOINT_PARTITION_PREFIX}{crawl_id}")))
+            .item(DDB_KEY_REQUEST_ID, AttributeValue::S(scope.to_string()))
+            .item(DDB_KEY_STATE, AttributeValue::S(serde_json::to_string(state)?))
+            .item(DDB_KEY_UPDATED_AT, AttributeValue::N(now.timestamp().to_string()))
+            .item(DDB_KEY_EXPIRES_AT, AttributeValue::N(expires_at.timestamp().to_string()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("PutItem checkpoint {scope} for crawl {crawl_id}: {}", aws_err_str(&e));
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Take the lease on a scope of a crawl for `duration`, returning whether `holder` now holds it. A lease held by
+    /// someone else is only taken over once it has expired; `holder` may renew its own lease.
+    pub async fn acquire_lease(
+        &self,
+        crawl_id: &str,
+        scope: &str,
+        holder: &str,
+        duration: Duration,
+    ) -> Result<bool, BoxError> {
+        let now = Utc::now();
+        let expires_at = now + duration;
+
+        let result = self
+            .ddb_client
+            .put_item()
+            .table_name(&self.table_name)
+            .item(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{CHECKPOINT_PARTITION_PREFIX}{crawl_id}")))
+            .item(DDB_KEY_REQUEST_ID, AttributeValue::S(format!("{LEASE_SORT_PREFIX}{scope}")))
+            .item(DDB_KEY_HOLDER, AttributeValue::S(holder.to_string()))
+            .item(DDB_KEY_UPDATED_AT, AttributeValue::N(now.timestamp().to_string()))
+            .item(DDB_KEY_EXPIRES_AT, AttributeValue::N(expires_at.timestamp().to_string()))
+            .condition_expression("attribute_not_exists(#crawl_id) OR #expires_at < :now OR #holder = :holder")
+            .expression_attribute_names("#crawl_id", DDB_KEY_CRAWL_ID)
+            .expression_attribute_names("#expires_at", DDB_KEY_EXPIRES_AT)
+            .expression_attribute_names("#holder", DDB_KEY_HOLDER)
+            .expression_attribute_values(":now", AttributeValue::N(now.timestamp().to_string()))
+            .expression_attribute_values(":holder", AttributeValue::S(holder.to_string()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => Ok(false),
+            Err(e) => {
+                error!("PutItem lease {scope} for crawl {crawl_id}: {}", aws_err_str(&e));
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Give up a lease taken with [`acquire_lease`][Self::acquire_lease]. A lease that has since been taken over by
+    /// someone else is left alone.
+    pub async fn release_lease(&self, crawl_id: &str, scope: &str, holder: &str) -> Result<(), BoxError> {
+        let result = self
+            .ddb_client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{CHECKPOINT_PARTITION_PREFIX}{crawl_id}")))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(format!("{LEASE_SORT_PREFIX}{scope}")))
+            .condition_expression("#holder = :holder")
+            .expression_attribute_names("#holder", DDB_KEY_HOLDER)
+            .expression_attribute_values(":holder", AttributeValue::S(holder.to_string()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => Ok(()),
+            Err(e) => {
+                error!("DeleteItem lease {scope} for crawl {crawl_id}: {}", aws_err_str(&e));
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Remove the checkpoint for a completed operation.
+    pub async fn clear(&self, crawl_id: &str, scope: &str) -> Result<(), BoxError> {
+        let result = self
+            .ddb_client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{CHECKPOINT_PARTITION_PREFIX}{crawl_id}")))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(scope.to_string()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("DeleteItem checkpoint {scope} for crawl {crawl_id}: {}", aws_err_str(&e));
+                Err(e.into())
+            }
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Data classification of stored objects and items.
+//!
+//! Every S3 object and DynamoDB item the crawler writes is labelled with a [`Classification`] at write time, so bucket
+//! policies (on the `s3:ExistingObjectTag/Classification` condition key) and access analytics can tell raw crawl data
+//! from the opportunities extracted from it. S3 objects carry it as an object tag alongside the portal, crawl id, and
+//! tenant; DynamoDB items carry it as the `Classification` attribute.
+//!
+//! Bodies are stored once however many crawls fetch them, so an object's tags name the crawl that first stored it.
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// DynamoDB attribute holding an item's classification.
+pub const DDB_KEY_CLASSIFICATION: &str = "Classification";
+
+const TAG_CLASSIFICATION: &str = "Classification";
+const TAG_PORTAL: &str = "Portal";
+const TAG_CRAWL_ID: &str = "CrawlId";
+const TAG_TENANT: &str = "Tenant";
+
+/// What kind of data a stored object or item holds.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Classification {
+    /// Responses fetched from portals, and the records of fetching them.
+    RawCrawlData,
+
+    /// Opportunities, and the vendors registered with portals, extracted from portal pages.
+    ExtractedOpportunity,
+
+    /// Records about crawls and the crawler itself, such as crawl records and the coverage report.
+    CrawlMetadata,
+}
+
+impl Classification {
+    /// Return the name of the classification, as used in tags and attributes.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::RawCrawlData => "RawCrawlData",
+            Self::ExtractedOpportunity => "ExtractedOpportunity",
+            Self::CrawlMetadata => "CrawlMetadata",
+        }
+    }
+}
+
+impl Display for Classification {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The tags applied to an S3 object when it is written.
+#[derive(Clone, Copy, Debug)]
+pub struct ObjectTags<'a> {
+    /// What the object holds.
+    pub classification: Classification,
+
+    /// The portal (subsystem) the object came from, if any.
+    pub portal: Option<&'a str>,
+
+    /// The crawl that wrote the object, if any.
+    pub crawl_id: Option<&'a str>,
+
+    /// The team the crawl was run for, if any.
+    pub tenant: Option<&'a str>,
+}
+
+impl<'a> ObjectTags<'a> {
+    /// Create tags with only a classification.
+    pub fn new(classification: Classification) -> Self {
+        Self {
+            classification,
+            portal: None,
+            crawl_id: None,
+            tenant: None,
+        }
+    }
+
+    /// Return the tags in the URL-encoded form taken by the `x-amz-tagging` header of `PutObject`.
+    pub fn to_tagging(&self) -> String {
+        let tags = [
+            (TAG_CLASSIFICATION, Some(self.classification.as_str())),
+            (TAG_PORTAL, self.portal),
+            (TAG_CRAWL_ID, self.crawl_id),
+            (TAG_TENANT, self.tenant),
+        ];
+
+        tags.iter()
+            .filter_map(|(key, value)| Some(format!("{key}={}", url_encode(value.as_ref()?))))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+}
+
+/// Percent-encode everything but unreserved characters.
+fn url_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Classification, ObjectTags};
+
+    #[test]
+    fn tagging() {
+        assert_eq!(ObjectTags::new(Classification::CrawlMetadata).to_tagging(), "Classification=CrawlMetadata");
+
+        let tags = ObjectTags {
+            portal: Some("Webs"),
+            crawl_id: Some("0190c6c4-5e4b-7cc2"),
+            tenant: Some("Bids & Grants"),
+            ..ObjectTags::new(Classification::RawCrawlData)
+        };
+        assert_eq!(
+            tags.to_tagging(),
+            "Classification=RawCrawlData&Portal=Webs&CrawlId=0190c6c4-5e4b-7cc2&Tenant=Bids%20%26%20Grants"
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Detection of opportunities that portals no longer publish.
+//!
+//! Many portals answer a request for a withdrawn or expired opportunity with a success status and a page saying it
+//! can't be found ("soft 404s"). Parsed as a detail page, such a page either fails or yields an empty record, so each
+//! detail page is checked before it is parsed. A page is taken to be a soft 404 if it contains:
+//!
+//! * one of the subsystem's own markers, which are phrases its portal only shows for a missing opportunity;
+//! * one of the markers in the crawl's [locale][crate::config::Locale], for portals in other languages or new wording;
+//!   or
+//! * a generic "not found" phrase in its title, or anywhere in a page too short to be a real detail page.
+//!
+//! The opportunity is then recorded as [closed][OpportunityStatus::Closed] rather than stored as a valid detail page,
+//! and moved to the [closed stage][SolicitationStage::Closed] of its [lifecycle][crate::lifecycle].
+//! Opportunities that are parsed are [recorded as seen][record_seen], for [reconciliation][crate::reconcile] to
+//! close once they stop being listed.
+use {
+    crate::{
+        httpext::{Client, LogConfig},
+        journal::CrawlEvent,
+        lifecycle,
+        opportunity::{Opportunity, OpportunityStatus, SolicitationStage},
+        search, BoxError,
+    },
+    log::*,
+    std::fmt::{Display, Formatter, Result as FmtResult},
+};
+
+/// Phrases that mean a page is missing on most portals. They are only trusted in the page's title or on short pages,
+/// since a real opportunity's description may use them.
+const GENERIC_MARKERS: &[&str] = &[
+    "page not found",
+    "404 not found",
+    "could not be found",
+    "cannot be found",
+    "does not exist",
+    "no longer available",
+    "no longer exists",
+    "has been removed",
+];
+
+/// Pages shorter than this (in bytes of HTML) are too small to be a real detail page, so generic markers are trusted
+/// anywhere on them.
+const SHORT_PAGE_LENGTH: usize = 8192;
+
+/// Why a page was taken to be a soft 404.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SoftNotFound {
+    /// The marker found on the page.
+    pub marker: String,
+}
+
+impl Display for SoftNotFound {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "page says \"{}\"", self.marker)
+    }
+}
+
+/// Return why the page `text` is a soft 404, if it is.
+///
+/// `subsystem_markers` are the subsystem's own markers and `configured_markers` those of the crawl's locale; both are
+/// matched anywhere on the page, ignoring case.
+pub fn soft_not_found(text: &str, subsystem_markers: &[&str], configured_markers: &[String]) -> Option<SoftNotFound> {
+    let text = text.to_lowercase();
+    let found = |marker: &str| {
+        Some(SoftNotFound {
+            marker: marker.to_string(),
+        })
+    };
+
+    for marker in subsystem_markers.iter().copied().chain(configured_markers.iter().map(String::as_str)) {
+        if !marker.is_empty() && text.contains(&marker.to_lowercase()) {
+            return found(marker);
+        }
+    }
+
+    let title = page_title(&text).unwrap_or_default();
+    let short = text.len() < SHORT_PAGE_LENGTH;
+    GENERIC_MARKERS
+        .iter()
+        .find(|marker| title.contains(*marker) || (short && text.contains(*marker)))
+        .and_then(|m| found(m))
+}
+
+/// Return the contents of the page's `<title>` element, if it has one.
+fn page_title(text: &str) -> Option<&str> {
+    let start = text.find("<title")?;
+    let start = start + text[start..].find('>')? + 1;
+    let end = start + text[start..].find("</title")?;
+    Some(&text[start..end])
+}
+
+/// Record that the opportunity at `url` on `portal` is closed because its detail page is a soft 404.
+pub async fn record_closed(
+    log_config: &LogConfig,
+    client: &Client,
+    portal: &str,
+    url: &str,
+    reason: &SoftNotFound,
+) -> Result<(), BoxError> {
+    close(log_config, &client.crawl_id, portal, url, OpportunityStatus::Closed, &reason.to_string()).await?;
+    Ok(())
+}
+
+/// Mark the opportunity at `url` on `portal` closed or expired in the opportunity store, journal the closure, move it
+/// to the closed stage, and emit the closed record. Returns `false`, doing nothing else, if the opportunity was already
+/// closed.
+pub async fn close(
+    log_config: &LogConfig,
+    crawl_id: &str,
+    portal: &str,
+    url: &str,
+    status: OpportunityStatus,
+    reason: &str,
+) -> Result<bool, BoxError> {
+    if !log_config.opportunities.close(portal, url, status, reason).await? {
+        return Ok(false);
+    }
+
+    info!("{portal} opportunity {url} is {status:?}: {reason}");
+
+    let event = CrawlEvent::OpportunityClosed {
+        url: url.to_string(),
+        status,
+        reason: reason.to_string(),
+    };
+    log_config.journal.record(crawl_id, event).await;
+    lifecycle::advance(log_config, crawl_id, portal, url, SolicitationStage::Closed, reason).await;
+
+    let opportunity = Opportunity {
+        portal: portal.to_string(),
+        url: url.to_string(),
+        status,
+        ..Opportunity::default()
+    };
+    info!("{portal} opportunity: {}", serde_json::to_string(&opportunity)?);
+
+    Ok(true)
+}
+
+/// Record that a crawl parsed an open opportunity, so [reconciliation][crate::reconcile] knows it is still listed, and
+/// tally it towards its portal's change rate for the [scheduler][crate::schedule] if it is new or changed. The
+/// opportunity is also written to the [search index][crate::search], if one is configured.
+///
+/// Recording is best-effort: failures are logged rather than failing the operation.
+pub async fn record_seen(log_config: &LogConfig, client: &Client, opportunity: &Opportunity) {
+    record_sighting(log_config, &client.crawl_id, opportunity).await;
+}
+
+/// Record that the crawl `crawl_id` learned of an open opportunity other than by fetching its page, such as from an
+/// [email][crate::mail]; see [`record_seen`].
+pub async fn record_sighting(log_config: &LogConfig, crawl_id: &str, opportunity: &Opportunity) {
+    search::record_opportunity(log_config, opportunity).await;
+
+    let sighting = match log_config.opportunities.record_seen(opportunity, crawl_id).await {
+        Ok(sighting) => sighting,
+        Err(e) => {
+            warn!("Failed to record sighting of opportunity {}: {e}", opportunity.url);
+            return;
+        }
+    };
+
+    if !sighting.is_change() {
+        return;
+    }
+
+    debug!("Opportunity {} is {sighting:?}", opportunity.url);
+    if let Err(e) = log_config.schedules.record_changes(&opportunity.portal, 1).await {
+        warn!("Failed to record change to opportunity {}: {e}", opportunity.url);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::soft_not_found;
+
+    #[test]
+    fn soft_404s() {
+        let filler = "<p>Scope of work for the renovation.</p>".repeat(300);
+        let detail = format!("<html><head><title>Bid 123</title></head><body>{filler}</body></html>");
+        assert_eq!(soft_not_found(&detail, &[], &[]), None);
+
+        // A subsystem marker is trusted anywhere on a page, ignoring case.
+        let withdrawn =
+            format!("<html><head><title>Bid</title></head><body>{filler}<p>This Bid Has Been Withdrawn.</p>");
+        assert_eq!(
+            soft_not_found(&withdrawn, &["this bid has been withdrawn"], &[]).unwrap().marker,
+            "this bid has been withdrawn"
+        );
+        assert_eq!(soft_not_found(&withdrawn, &[], &[]), None);
+
+        // So is a configured marker.
+        let french = format!("<html><body>{filler}<p>Cet avis n'est plus disponible.</p></body></html>");
+        assert!(soft_not_found(&french, &[], &["n'est plus disponible".to_string()]).is_some());
+
+        // Generic markers are trusted in the title, or anywhere on a short page, but not in a long description.
+        let titled = format!("<html><head><title>Page Not Found</title></head><body>{filler}</body></html>");
+        assert_eq!(soft_not_found(&titled, &[], &[]).unwrap().marker, "page not found");
+
+        let short = "<html><body><h1>Sorry</h1><p>That opportunity is no longer available.</p></body></html>";
+        assert_eq!(soft_not_found(short, &[], &[]).unwrap().marker, "no longer available");
+
+        let mentioned = format!("<html><body>{filler}<p>The old roof has been removed.</p></body></html>");
+        assert_eq!(soft_not_found(&mentioned, &[], &[]), None);
+    }
+}
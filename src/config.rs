@@ -0,0 +1,319 @@
+//! Per-subsystem configuration documents.
+//!
+//! Settings that operators change more often than deployments, such as which regions of a portal to crawl, are kept
+//! in a JSON document per subsystem, stored as the SSM parameter `<Subsystem>/Config` under the SSM prefix. Each
+//! subsystem defines the shape of its own document; a subsystem without a document uses its defaults.
+//!
+//! Every subsystem's document may also carry [crawl settings][CrawlSettings], which are read when a crawl starts and
+//! carried in its [crawl parameters][crate::shapes::CrawlParameters]: a `Locale`, and `RefererChain` for portals that
+//! check each request's `Referer` against the page it was found on. `Contacts` configures [contact
+//! extraction][crate::contacts], which reads it as each detail page is parsed:
+//!
+//! ```json
+//! {"Locale": {"AcceptLanguage": "fr-CA,fr;q=0.9,en;q=0.8", "Dates": "French", "NotFoundMarkers": ["introuvable"]},
+//!  "RefererChain": true}
+//! ```
+//!
+//! Documents, including the [flags document][crate::flags], are read through a process-wide [`ConfigWatcher`], so a
+//! warm Lambda environment re-reads each from SSM at most once every `CONFIG_RELOAD_SECS` seconds (60 by default; 0
+//! reads on every load). A change takes effect within that interval rather than waiting for a cold start, and is
+//! logged when it is picked up.
+use {
+    crate::{
+        contacts::ContactSettings,
+        httpext::{aws_err_str, ConfigError, LogConfig},
+        opportunity::DateLocale,
+        BoxError,
+    },
+    lazy_static::lazy_static,
+    log::*,
+    parking_lot::Mutex,
+    serde::{de::DeserializeOwned, Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        env,
+        time::{Duration, Instant},
+    },
+};
+
+/// Name of the configuration document parameter within a subsystem's SSM path.
+const SSM_CONFIG_LEAF: &str = "Config";
+
+const ENV_CONFIG_RELOAD_SECS: &str = "CONFIG_RELOAD_SECS";
+const DEFAULT_CONFIG_RELOAD_SECS: u64 = 60;
+
+/// Read the reload interval for configuration documents, looking `CONFIG_RELOAD_SECS` up with `lookup`. An invalid
+/// value is recorded in `errors`, and the default returned.
+pub(crate) fn reload_interval<F: Fn(&str) -> Option<String>>(lookup: &F, errors: &mut ConfigError) -> Duration {
+    let secs = match lookup(ENV_CONFIG_RELOAD_SECS) {
+        Some(value) => value.parse().unwrap_or_else(|_| {
+            errors.push(ENV_CONFIG_RELOAD_SECS, format!("{value:?} is not a number of seconds"));
+            DEFAULT_CONFIG_RELOAD_SECS
+        }),
+        None => DEFAULT_CONFIG_RELOAD_SECS,
+    };
+    Duration::from_secs(secs)
+}
+
+lazy_static! {
+    static ref WATCHER: ConfigWatcher = ConfigWatcher::from_env();
+}
+
+/// A cache of the configuration documents read from SSM, re-reading each at most once per reload interval.
+///
+/// Concurrent operations that find a document stale may each re-read it; the last read wins.
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    reload_interval: Duration,
+    documents: Mutex<HashMap<String, WatchedDocument>>,
+}
+
+/// A document as last read from SSM.
+#[derive(Clone, Debug)]
+struct WatchedDocument {
+    /// The parameter's value, or `None` if there was no such parameter.
+    value: Option<String>,
+
+    /// When the parameter was read.
+    read_at: Instant,
+}
+
+impl ConfigWatcher {
+    /// Create a watcher re-reading each document once `reload_interval` has passed since it was last read.
+    pub fn new(reload_interval: Duration) -> Self {
+        Self {
+            reload_interval,
+            documents: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Create a watcher with the reload interval in `CONFIG_RELOAD_SECS`, or the default. An invalid interval is
+    /// reported by [`LogConfig::new`] at startup, so here it just falls back to the default.
+    fn from_env() -> Self {
+        Self::new(reload_interval(&|var| env::var(var).ok(), &mut ConfigError::default()))
+    }
+
+    /// Return the value of a parameter read less than the reload interval before `now`, or `None` if it must be read
+    /// again. The value is itself `None` if there was no such parameter.
+    pub fn current(&self, parameter_name: &str, now: Instant) -> Option<Option<String>> {
+        let documents = self.documents.lock();
+        let document = documents.get(parameter_name)?;
+        (now.saturating_duration_since(document.read_at) < self.reload_interval).then(|| document.value.clone())
+    }
+
+    /// Record the value of a parameter read at `now`, logging whether it changed since it was last read. Returns
+    /// whether it changed; the first read of a parameter isn't a change.
+    pub fn update(&self, parameter_name: &str, value: Option<String>, now: Instant) -> bool {
+        let previous = self.documents.lock().insert(
+            parameter_name.to_string(),
+            WatchedDocument {
+                value: value.clone(),
+                read_at: now,
+            },
+        );
+
+        match previous {
+            Some(previous) if previous.value != value => {
+                let change = match (&previous.value, &value) {
+                    (None, _) => "created",
+                    (_, None) => "deleted",
+                    _ => "changed",
+                };
+                info!("Configuration {parameter_name} {change}; reloaded after {:?}", now - previous.read_at);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The language a portal is crawled in.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, rename_all = "PascalCase")]
+pub struct Locale {
+    /// The `Accept-Language` header sent with every request, or `None` to send none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accept_language: Option<String>,
+
+    /// The language the portal writes month names in.
+    #[serde(skip_serializing_if = "DateLocale::is_english")]
+    pub dates: DateLocale,
+
+    /// Phrases the portal shows in place of an opportunity it no longer publishes, in addition to the subsystem's own
+    /// [soft 404 markers][crate::closure].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub not_found_markers: Vec<String>,
+}
+
+impl Locale {
+    /// Indicates whether this is the default locale: no `Accept-Language` header and English dates.
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The settings of a new crawl, read from any subsystem's configuration document by [`load_crawl_settings`].
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(default, rename_all = "PascalCase")]
+pub struct CrawlSettings {
+    /// The language the portal is crawled in.
+    pub locale: Locale,
+
+    /// Send each request with a `Referer` naming the page it was found on; see
+    /// [`CrawlParameters::referer`][crate::shapes::CrawlParameters::referer].
+    pub referer_chain: bool,
+
+    /// How [contacts][crate::contacts] are extracted from the portal's pages.
+    pub contacts: ContactSettings,
+}
+
+/// Return the SSM parameter name, relative to the SSM prefix, of a subsystem's configuration document.
+pub fn config_parameter(subsystem: &str) -> String {
+    format!("{subsystem}/{SSM_CONFIG_LEAF}")
+}
+
+/// Load and parse a subsystem's configuration document, or return the default configuration if there is none.
+///
+/// A document that exists but can't be parsed is an error rather than silently replaced by the defaults.
+pub async fn load_subsystem_config<T>(log_config: &LogConfig, subsystem: &str) -> Result<T, BoxError>
+where
+    T: DeserializeOwned + Default,
+{
+    load_config_document(log_config, &config_parameter(subsystem), subsystem).await
+}
+
+/// Load and parse the JSON document in an SSM parameter, named relative to the SSM prefix, or return the default if
+/// there is no such parameter. `what` names the document in log messages and errors.
+pub async fn load_config_document<T>(log_config: &LogConfig, parameter: &str, what: &str) -> Result<T, BoxError>
+where
+    T: DeserializeOwned + Default,
+{
+    let parameter_name = format!("{}{parameter}", log_config.ssm_prefix);
+    let value = match WATCHER.current(&parameter_name, Instant::now()) {
+        Some(value) => value,
+        None => {
+            let value = read_parameter(log_config, &parameter_name).await?;
+            WATCHER.update(&parameter_name, value.clone(), Instant::now());
+            value
+        }
+    };
+
+    match value {
+        Some(value) => parse_config(what, &value),
+        None => {
+            debug!("No {what} configuration at {parameter_name}; using defaults");
+            Ok(T::default())
+        }
+    }
+}
+
+/// Read an SSM parameter, returning `None` if there is no such parameter.
+async fn read_parameter(log_config: &LogConfig, parameter_name: &str) -> Result<Option<String>, BoxError> {
+    let result = log_config.ssm_client.get_parameter().name(parameter_name).with_decryption(true).send().await;
+
+    let output = match result {
+        Ok(output) => output,
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_parameter_not_found()) => return Ok(None),
+        Err(e) => {
+            error!("GetParameter {parameter_name}: {}", aws_err_str(&e));
+            return Err(e.into());
+        }
+    };
+
+    match output.parameter.and_then(|p| p.value) {
+        Some(value) => Ok(Some(value)),
+        None => Err(format!("Parameter {parameter_name} has no value").into()),
+    }
+}
+
+/// Load the crawl settings from a subsystem's configuration document, or return the defaults if there is none.
+pub async fn load_crawl_settings(log_config: &LogConfig, subsystem: &str) -> Result<CrawlSettings, BoxError> {
+    load_subsystem_config(log_config, subsystem).await
+}
+
+/// Parse a configuration document.
+pub(crate) fn parse_config<T: DeserializeOwned>(what: &str, document: &str) -> Result<T, BoxError> {
+    serde_json::from_str(document).map_err(|e| format!("Invalid {what} configuration: {e}").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{config_parameter, parse_config, ConfigWatcher, CrawlSettings, Locale},
+        crate::opportunity::DateLocale,
+        serde::Deserialize,
+        std::time::{Duration, Instant},
+    };
+
+    #[derive(Debug, Default, Deserialize, PartialEq)]
+    #[serde(default, rename_all = "PascalCase")]
+    struct ExampleConfig {
+        regions: Vec<String>,
+        enabled: bool,
+    }
+
+    #[test]
+    fn config_documents() {
+        assert_eq!(config_parameter("BidNet"), "BidNet/Config");
+
+        let config: ExampleConfig = parse_config("Example", r#"{"Regions": ["washington"]}"#).unwrap();
+        assert_eq!(
+            config,
+            ExampleConfig {
+                regions: vec!["washington".to_string()],
+                enabled: false,
+            }
+        );
+
+        let error = parse_config::<ExampleConfig>("Example", r#"{"Regions": "washington"}"#).unwrap_err();
+        assert!(error.to_string().starts_with("Invalid Example configuration"));
+
+        // The crawl settings are read from any subsystem's document, alongside its own settings.
+        let document: CrawlSettings = parse_config(
+            "Example",
+            r#"{"Regions": ["quebec"], "Locale": {"AcceptLanguage": "fr-CA,fr;q=0.9", "Dates": "French", "NotFoundMarkers": ["introuvable"]}, "RefererChain": true}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            document.locale,
+            Locale {
+                accept_language: Some("fr-CA,fr;q=0.9".to_string()),
+                dates: DateLocale::French,
+                not_found_markers: vec!["introuvable".to_string()],
+            }
+        );
+        assert!(document.referer_chain);
+
+        let document: CrawlSettings = parse_config("Example", r#"{"Regions": ["washington"]}"#).unwrap();
+        assert_eq!(document, CrawlSettings::default());
+        assert!(document.locale.is_default());
+        assert_eq!(serde_json::to_string(&document.locale).unwrap(), "{}");
+    }
+
+    #[test]
+    fn config_reloads() {
+        let watcher = ConfigWatcher::new(Duration::from_secs(60));
+        let start = Instant::now();
+        let document = Some(r#"{"Regions": ["washington"]}"#.to_string());
+        assert_eq!(watcher.current("/GovScout/BidNet/Config", start), None);
+
+        // The first read isn't a change, and is reused until the interval passes.
+        assert!(!watcher.update("/GovScout/BidNet/Config", document.clone(), start));
+        assert_eq!(watcher.current("/GovScout/BidNet/Config", start + Duration::from_secs(59)), Some(document.clone()));
+        assert_eq!(watcher.current("/GovScout/BidNet/Config", start + Duration::from_secs(60)), None);
+        assert_eq!(watcher.current("/GovScout/Webs/Config", start), None);
+
+        let later = start + Duration::from_secs(60);
+        assert!(!watcher.update("/GovScout/BidNet/Config", document.clone(), later));
+        let changed = Some(r#"{"Regions": ["oregon"]}"#.to_string());
+        assert!(watcher.update("/GovScout/BidNet/Config", changed.clone(), later + Duration::from_secs(60)));
+        assert_eq!(watcher.current("/GovScout/BidNet/Config", later + Duration::from_secs(61)), Some(changed));
+        assert!(watcher.update("/GovScout/BidNet/Config", None, later + Duration::from_secs(120)));
+        assert_eq!(watcher.current("/GovScout/BidNet/Config", later + Duration::from_secs(121)), Some(None));
+
+        // Without an interval, every load reads the document.
+        let watcher = ConfigWatcher::new(Duration::ZERO);
+        watcher.update("/GovScout/BidNet/Config", document, start);
+        assert_eq!(watcher.current("/GovScout/BidNet/Config", start), None);
+    }
+}
@@ -0,0 +1,486 @@
+//! Extraction of contact details from the text of detail pages and attachments.
+//!
+//! Parsers read the contact a portal labels as such, but many pages name further buyers, or give the contact's email
+//! address and phone number elsewhere in the description. After a detail page is parsed, [`record_contacts`] scans its
+//! text line by line for:
+//!
+//! * email addresses, skipping no-reply and help desk addresses;
+//! * phone numbers written in groups ending with three and four digits, e.g. `(360) 753-8325` or `+1 604 555 0100`,
+//!   on lines that don't mention a fax; and
+//! * names following a buyer label at the start of a line, e.g. `Procurement Officer: Jane Doe`.
+//!
+//! An email address or phone number joins the name or address found within the previous few lines, so details listed
+//! under a name form one contact. Contacts are deduplicated by email address, phone number, or name; the parser's
+//! contact comes first, and the others are stored in [`Opportunity::contacts`].
+//!
+//! Attachments are scanned when they are [linked][crate::attachment::record_attachment], from the text
+//! [extracted][crate::extract] from plain text and PDF attachments, and their contacts kept with the opportunity's
+//! attachment links, so every later sighting of the opportunity, amended or not, merges them in. Office attachments
+//! aren't scanned, since their text isn't extracted.
+//!
+//! Each subsystem's configuration document can add labels and ignored addresses under `Contacts`:
+//!
+//! ```json
+//! {
+//!     "Contacts": {
+//!         "Labels": ["Procurement Specialist"],
+//!         "Ignored": ["bids@example.gov", "portal.example.com"]
+//!     }
+//! }
+//! ```
+use {
+    crate::{
+        config::load_crawl_settings,
+        httpext::{Client, LogConfig},
+        opportunity::{clean_text, Contact, Opportunity},
+        soup::{parse_html_str, NodeExt, QueryBuilderExt},
+    },
+    log::*,
+    markup5ever_rcdom::{Handle, RcDom},
+    serde::{Deserialize, Serialize},
+};
+
+/// Labels introducing a buyer's name, matched at the start of a line, ignoring case.
+const DEFAULT_LABELS: &[&str] = &[
+    "buyer",
+    "contact",
+    "contact name",
+    "contact person",
+    "contracting officer",
+    "contract specialist",
+    "issuing officer",
+    "procurement contact",
+    "procurement officer",
+    "purchasing agent",
+    "acheteur",
+    "acheteuse",
+    "personne-ressource",
+];
+
+/// Local parts of addresses that never reach a buyer.
+const IGNORED_LOCAL_PARTS: &[&str] =
+    &["donotreply", "do-not-reply", "helpdesk", "no-reply", "noreply", "privacy", "support", "webmaster"];
+
+/// Words marking a line's phone numbers as fax numbers.
+const FAX_MARKERS: &[&str] = &["fax", "télécopieur"];
+
+/// Elements whose text is not shown.
+const HIDDEN_ELEMENTS: &[&str] = &["head", "script", "style", "noscript", "template"];
+
+/// Elements that start a new line of text.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address", "article", "br", "dd", "div", "dl", "dt", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6",
+    "header", "hr", "li", "ol", "p", "section", "table", "tr", "ul",
+];
+
+/// Elements whose text is separated from their neighbours' on the same line.
+const CELL_ELEMENTS: &[&str] = &["td", "th", "label", "span"];
+
+/// The number of lines after a contact's first detail within which further details join it.
+const CONTACT_WINDOW: usize = 3;
+
+/// The fewest and most words in a name, so a label followed by a single word, such as `Contact Us`, isn't one.
+const MIN_NAME_WORDS: usize = 2;
+const MAX_NAME_WORDS: usize = 5;
+
+/// The fewest and most digits in a phone number, excluding any extension.
+const MIN_PHONE_DIGITS: usize = 10;
+const MAX_PHONE_DIGITS: usize = 15;
+
+/// How contacts are extracted from a subsystem's pages, read from the `Contacts` section of its configuration
+/// document.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, rename_all = "PascalCase")]
+pub struct ContactSettings {
+    /// Labels introducing a buyer's name, in addition to the defaults.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+
+    /// Email addresses and domains never taken as contacts, such as the portal's own, in addition to no-reply and help
+    /// desk addresses.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ignored: Vec<String>,
+}
+
+impl ContactSettings {
+    /// Indicates whether an email address should be ignored.
+    fn ignores(&self, email: &str) -> bool {
+        let (local, domain) = email.split_once('@').unwrap_or_default();
+        IGNORED_LOCAL_PARTS.contains(&local)
+            || self
+                .ignored
+                .iter()
+                .any(|ignored| ignored.eq_ignore_ascii_case(email) || ignored.eq_ignore_ascii_case(domain))
+    }
+
+    /// Return the name labelled at the start of `line`, if any.
+    fn labelled_name(&self, line: &str) -> Option<String> {
+        let value = DEFAULT_LABELS
+            .iter()
+            .copied()
+            .chain(self.labels.iter().map(String::as_str))
+            .filter_map(|label| {
+                let labelled = line.get(..label.len())?.to_lowercase() == label.to_lowercase();
+                let rest = &line[label.len()..];
+                (labelled && rest.starts_with([':', '\t', ' '])).then_some(rest)
+            })
+            .min_by_key(|value| value.len())?;
+
+        let value = value.trim_start_matches([':', '\t', ' ']);
+        let name = value.split(['\t', ',', '|', '(', ';']).next()?.split(" - ").next()?;
+        let name = clean_text(name)?;
+        let words = name.split(' ').count();
+        let plausible = (MIN_NAME_WORDS..=MAX_NAME_WORDS).contains(&words)
+            && name.chars().all(|c| c.is_alphabetic() || " .'-".contains(c))
+            && name.chars().next().is_some_and(char::is_uppercase);
+        plausible.then_some(name)
+    }
+}
+
+/// Return the lines of visible text on an HTML page; see [`document_lines`].
+pub fn page_lines(html: &str) -> Vec<String> {
+    document_lines(&parse_html_str(html))
+}
+
+/// Return the lines of visible text in a parsed HTML document, split at block elements and table rows, with table cells
+/// separated by tabs.
+pub fn document_lines(document: &RcDom) -> Vec<String> {
+    fn collect(node: &Handle, text: &mut String) {
+        for child in node.children() {
+            if child.is_text() {
+                text.push_str(&child.text().replace(['\r', '\n', '\t'], " "));
+            } else if child.is_element() {
+                let name = child.name();
+                if HIDDEN_ELEMENTS.contains(&name) {
+                    continue;
+                }
+
+                let separator = if BLOCK_ELEMENTS.contains(&name) {
+                    Some('\n')
+                } else if CELL_ELEMENTS.contains(&name) {
+                    Some('\t')
+                } else {
+                    None
+                };
+
+                text.extend(separator);
+                collect(&child, text);
+                text.extend(separator);
+            } else {
+                collect(&child, text);
+            }
+        }
+    }
+
+    let mut text = String::new();
+    collect(&document.document, &mut text);
+    text.lines()
+        .filter_map(|line| {
+            let cells: Vec<String> = line.split('\t').filter_map(clean_text).collect();
+            (!cells.is_empty()).then(|| cells.join("\t"))
+        })
+        .collect()
+}
+
+/// Return the email addresses in a line, lowercased.
+fn emails(line: &str) -> Vec<String> {
+    line.split(|c: char| c.is_whitespace() || "<>()[],;:\"'".contains(c))
+        .filter_map(|token| {
+            let token = token.trim_end_matches('.');
+            let (local, domain) = token.split_once('@')?;
+            let valid = !local.is_empty()
+                && local.chars().all(|c| c.is_ascii_alphanumeric() || "._%+-".contains(c))
+                && domain.contains('.')
+                && !domain.starts_with(['.', '-'])
+                && domain.chars().all(|c| c.is_ascii_alphanumeric() || ".-".contains(c));
+            valid.then(|| token.to_ascii_lowercase())
+        })
+        .collect()
+}
+
+/// Return the phone numbers in a line, as written.
+fn phones(line: &str) -> Vec<String> {
+    let lowercase = line.to_lowercase();
+    if FAX_MARKERS.iter().any(|marker| lowercase.contains(marker)) {
+        return vec![];
+    }
+
+    let mut phones = vec![];
+    let mut rest = line;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_digit() || c == '(' || c == '+') {
+        let run = &rest[start..];
+        let end = run.find(|c: char| !(c.is_ascii_digit() || " ()-.+".contains(c))).unwrap_or(run.len());
+        let candidate = run[..end].trim_end_matches([' ', '(', '-', '.', '+']);
+        rest = &run[end.max(1)..];
+
+        let groups: Vec<&str> =
+            candidate.split(|c: char| !c.is_ascii_digit()).filter(|group| !group.is_empty()).collect();
+        let digits: usize = groups.iter().map(|group| group.len()).sum();
+        let grouped = groups.len() >= 2 && groups[groups.len() - 1].len() == 4 && groups[groups.len() - 2].len() == 3;
+        if grouped && (MIN_PHONE_DIGITS..=MAX_PHONE_DIGITS).contains(&digits) {
+            phones.push(candidate.to_string());
+        }
+    }
+
+    phones
+}
+
+/// Return the digits of a phone number, without a leading North American country code, for comparison.
+fn phone_digits(phone: &str) -> String {
+    let digits: String = phone.chars().filter(char::is_ascii_digit).collect();
+    match digits.strip_prefix('1') {
+        Some(national) if national.len() == 10 => national.to_string(),
+        _ => digits,
+    }
+}
+
+/// Indicates whether two contacts are the same person, by email address, phone number, or name.
+fn same_contact(a: &Contact, b: &Contact) -> bool {
+    fn same(a: &Option<String>, b: &Option<String>, normalize: fn(&str) -> String) -> bool {
+        matches!((a, b), (Some(a), Some(b)) if normalize(a) == normalize(b))
+    }
+
+    same(&a.email, &b.email, str::to_lowercase)
+        || same(&a.phone, &b.phone, phone_digits)
+        || same(&a.name, &b.name, str::to_lowercase)
+}
+
+/// Add a contact to a list, filling in the details of the same contact if it's already there.
+pub fn merge_contact(contacts: &mut Vec<Contact>, contact: Contact) {
+    if contact == Contact::default() {
+        return;
+    }
+
+    match contacts.iter_mut().find(|existing| same_contact(existing, &contact)) {
+        Some(existing) => {
+            existing.name = existing.name.take().or(contact.name);
+            existing.email = existing.email.take().or(contact.email);
+            existing.phone = existing.phone.take().or(contact.phone);
+        }
+        None => contacts.push(contact),
+    }
+}
+
+/// Return the contacts in lines of text.
+pub fn extract_contacts(lines: &[String], settings: &ContactSettings) -> Vec<Contact> {
+    let mut contacts: Vec<Contact> = vec![];
+    // The contact details are being added to, and the line it started on.
+    let mut current: Option<(Contact, usize)> = None;
+
+    for (i, line) in lines.iter().enumerate() {
+        let name = settings.labelled_name(line);
+        let emails: Vec<String> = emails(line).into_iter().filter(|email| !settings.ignores(email)).collect();
+        let phones = phones(line);
+
+        if name.is_some() {
+            if let Some((contact, _)) = current.take() {
+                merge_contact(&mut contacts, contact);
+            }
+            current = Some((
+                Contact {
+                    name,
+                    ..Contact::default()
+                },
+                i,
+            ));
+        }
+
+        for email in emails {
+            match current.as_mut() {
+                Some((contact, start)) if contact.email.is_none() && i - *start <= CONTACT_WINDOW => {
+                    contact.email = Some(email)
+                }
+                _ => {
+                    if let Some((contact, _)) = current.take() {
+                        merge_contact(&mut contacts, contact);
+                    }
+                    current = Some((
+                        Contact {
+                            email: Some(email),
+                            ..Contact::default()
+                        },
+                        i,
+                    ));
+                }
+            }
+        }
+
+        for phone in phones {
+            match current.as_mut() {
+                Some((contact, start)) if contact.phone.is_none() && i - *start <= CONTACT_WINDOW => {
+                    contact.phone = Some(phone)
+                }
+                _ => {
+                    if let Some((contact, _)) = current.take() {
+                        merge_contact(&mut contacts, contact);
+                    }
+                    current = Some((
+                        Contact {
+                            phone: Some(phone),
+                            ..Contact::default()
+                        },
+                        i,
+                    ));
+                }
+            }
+        }
+    }
+
+    if let Some((contact, _)) = current {
+        merge_contact(&mut contacts, contact);
+    }
+
+    contacts
+}
+
+/// Load the contact settings of the subsystem a client crawls for, or the defaults if they can't be read.
+async fn load_settings(log_config: &LogConfig, client: &Client, opportunity: &Opportunity) -> ContactSettings {
+    let subsystem = client.portal.as_deref().unwrap_or(&opportunity.portal);
+    match load_crawl_settings(log_config, subsystem).await {
+        Ok(settings) => settings.contacts,
+        Err(e) => {
+            warn!("Failed to load the contact settings of {subsystem}: {e}");
+            ContactSettings::default()
+        }
+    }
+}
+
+/// Add the contacts on an opportunity's detail page, given as HTML, and those found in its attachments to the
+/// opportunity, deduplicating them against the contact its parser read.
+pub async fn record_contacts(log_config: &LogConfig, client: &Client, opportunity: &mut Opportunity, html: &str) {
+    let settings = load_settings(log_config, client, opportunity).await;
+    let mut contacts = vec![];
+    merge_contact(&mut contacts, opportunity.contact.clone());
+    for contact in opportunity.contacts.drain(..) {
+        merge_contact(&mut contacts, contact);
+    }
+
+    for contact in extract_contacts(&page_lines(html), &settings) {
+        merge_contact(&mut contacts, contact);
+    }
+
+    match log_config.attachments.contacts(&opportunity.url).await {
+        Ok(attachment_contacts) => {
+            for contact in attachment_contacts {
+                merge_contact(&mut contacts, contact);
+            }
+        }
+        Err(e) => warn!("Failed to read the attachment contacts of {}: {e}", opportunity.url),
+    }
+
+    let mut contacts = contacts.into_iter();
+    opportunity.contact = contacts.next().unwrap_or_default();
+    opportunity.contacts = contacts.collect();
+}
+
+/// Keep the contacts in the [extracted][crate::extract] text of an attachment of an opportunity with its attachment
+/// links, for later sightings of the opportunity to merge in. Failures are logged rather than failing the download.
+pub(crate) async fn record_attachment_contacts(
+    log_config: &LogConfig,
+    subsystem: &str,
+    opportunity_url: &str,
+    document_url: &str,
+    text: &str,
+) {
+    let settings = match load_crawl_settings(log_config, subsystem).await {
+        Ok(settings) => settings.contacts,
+        Err(e) => {
+            warn!("Failed to load the contact settings of {subsystem}: {e}");
+            ContactSettings::default()
+        }
+    };
+
+    let lines: Vec<String> = text.lines().filter_map(clean_text).collect();
+    let contacts = extract_contacts(&lines, &settings);
+    if contacts.is_empty() {
+        return;
+    }
+
+    if let Err(e) = log_config.attachments.add_contacts(opportunity_url, contacts).await {
+        warn!("Failed to keep the contacts in attachment {document_url} of {opportunity_url}: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{extract_contacts, merge_contact, page_lines, phones, ContactSettings},
+        crate::opportunity::Contact,
+    };
+
+    #[test]
+    fn contacts() {
+        let html = r#"<html><head><title>Bid 24-117</title><script>var help = "support@portal.example.com";</script>
+            </head><body>
+            <table>
+              <tr><th>Bid Number</th><td>2024-000117-01</td></tr>
+              <tr><th>Buyer</th><td>Jane Q. Doe, CPPB</td></tr>
+              <tr><td>jane.doe@olympiawa.gov</td></tr>
+              <tr><td>Phone: (360) 753-8325 &nbsp; Fax: (360) 753-8000</td></tr>
+            </table>
+            <p>Questions about the specifications may be sent to Facilities at
+               <a href="mailto:FACILITIES@olympiawa.gov">FACILITIES@olympiawa.gov</a>.</p>
+            <p>Personne-ressource : Marc Tremblay, 1-604-555-0100 poste 12</p>
+            <footer>Need help? Email support@portal.example.com or noreply@olympiawa.gov.</footer>
+            </body></html>"#;
+
+        let lines = page_lines(html);
+        assert_eq!(lines[0], "Bid Number\t2024-000117-01");
+        assert_eq!(lines[1], "Buyer\tJane Q. Doe, CPPB");
+        assert!(!lines.iter().any(|line| line.contains("var help")));
+
+        // The fax number is on the same line as the phone number, so neither is taken.
+        assert!(phones("Phone: (360) 753-8325   Fax: (360) 753-8000").is_empty());
+        assert_eq!(phones("Tel. +1 604 555 0100 ext. 12; RFP 2024-000117-01"), ["+1 604 555 0100"]);
+
+        let settings = ContactSettings {
+            labels: vec![],
+            ignored: vec!["portal.example.com".to_string()],
+        };
+        let contacts = extract_contacts(&lines, &settings);
+        assert_eq!(
+            contacts,
+            [
+                Contact {
+                    name: Some("Jane Q. Doe".to_string()),
+                    email: Some("jane.doe@olympiawa.gov".to_string()),
+                    phone: None,
+                },
+                Contact {
+                    name: None,
+                    email: Some("facilities@olympiawa.gov".to_string()),
+                    phone: None,
+                },
+                Contact {
+                    name: Some("Marc Tremblay".to_string()),
+                    email: None,
+                    phone: Some("1-604-555-0100".to_string()),
+                },
+            ]
+        );
+
+        // The same contact from an amendment fills in details rather than adding another.
+        let mut merged = contacts.clone();
+        merge_contact(
+            &mut merged,
+            Contact {
+                name: Some("Marc Tremblay".to_string()),
+                email: Some("m.tremblay@example.ca".to_string()),
+                phone: Some("(604) 555-0100".to_string()),
+            },
+        );
+        merge_contact(&mut merged, Contact::default());
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[2].email.as_deref(), Some("m.tremblay@example.ca"));
+        assert_eq!(merged[2].phone.as_deref(), Some("1-604-555-0100"));
+
+        let custom = ContactSettings {
+            labels: vec!["Project Manager".to_string()],
+            ignored: vec![],
+        };
+        let lines = ["Project Manager: Ana Ruiz".to_string(), "Project Manager: TBD 2024".to_string()];
+        assert_eq!(extract_contacts(&lines, &custom)[0].name.as_deref(), Some("Ana Ruiz"));
+        assert_eq!(extract_contacts(&lines, &custom).len(), 1);
+        assert!(extract_contacts(&lines, &ContactSettings::default()).is_empty());
+    }
+}
@@ -0,0 +1,308 @@
+//! Estimated cost of crawls, and monthly budgets per portal.
+//!
+//! Each operation tallies the billable resources it uses in its [`UsageMeter`]: S3 puts, DynamoDB writes, and
+//! response bytes received, to which its Lambda time is added when it finishes. The
+//! [`CostAccounting`][crate::middleware::CostAccounting] middleware then prices the tally with the deployment's
+//! [`Prices`], adds it to the crawl's record and to its portal's spend for the month, and emits the estimate as the
+//! `EstimatedCostMicros` metric, with a `Tenant` dimension for crawls run for a tenant.
+//!
+//! The tally is an estimate. It counts the writes made for every response and operation (the request log item, the
+//! stored body, and journal events) but not occasional ones such as checkpoints, and operations sharing an invocation
+//! each count their own duration.
+//!
+//! A portal can be given a monthly budget, in US dollars, in `CRAWL_MONTHLY_BUDGETS` (e.g. `Webs=25,BidNet=10`). Once
+//! a portal's spend for the month reaches its budget, the [scheduler][crate::schedule] stops dispatching crawls of it
+//! until the next month.
+use {
+    crate::{
+        httpext::{ConfigError, LogConfig},
+        metrics,
+    },
+    chrono::{DateTime, Utc},
+    log::*,
+    parking_lot::Mutex,
+    serde::{Deserialize, Serialize},
+    std::{collections::BTreeMap, env, sync::Arc, time::Duration},
+};
+
+const ENV_CRAWL_MONTHLY_BUDGETS: &str = "CRAWL_MONTHLY_BUDGETS";
+const ENV_COST_LAMBDA_GB_SECOND: &str = "COST_LAMBDA_GB_SECOND";
+const ENV_COST_S3_PUT: &str = "COST_S3_PUT";
+const ENV_COST_DYNAMODB_WRITE: &str = "COST_DYNAMODB_WRITE";
+const ENV_COST_TRANSFER_GB: &str = "COST_TRANSFER_GB";
+
+/// Set by Lambda to the function's memory size, in megabytes.
+const ENV_AWS_LAMBDA_FUNCTION_MEMORY_SIZE: &str = "AWS_LAMBDA_FUNCTION_MEMORY_SIZE";
+
+/// The memory size assumed outside of Lambda, in megabytes.
+const DEFAULT_MEMORY_MB: u64 = 128;
+
+/// Journal events written by every operation of a crawl: its start and its outcome.
+const OPERATION_JOURNAL_WRITES: u64 = 2;
+
+/// DynamoDB writes made for every response logged: the request log item and its journal event.
+const RESPONSE_WRITES: u64 = 2;
+
+const METRIC_ESTIMATED_COST_MICROS: &str = "EstimatedCostMicros";
+const DIMENSION_PORTAL: &str = "Portal";
+
+/// Billable resources used, and their estimated cost.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Usage {
+    /// Lambda time, in megabyte-milliseconds.
+    pub lambda_mb_ms: u64,
+
+    /// Objects put to S3.
+    pub s3_puts: u64,
+
+    /// Items written to DynamoDB.
+    pub ddb_writes: u64,
+
+    /// Response body bytes received.
+    pub bytes_received: u64,
+
+    /// The estimated cost, in millionths of a US dollar. Zero until the usage is [priced][Usage::priced].
+    pub cost_micros: u64,
+}
+
+impl Usage {
+    /// Add another tally to this one.
+    pub fn add(&mut self, other: &Usage) {
+        self.lambda_mb_ms += other.lambda_mb_ms;
+        self.s3_puts += other.s3_puts;
+        self.ddb_writes += other.ddb_writes;
+        self.bytes_received += other.bytes_received;
+        self.cost_micros += other.cost_micros;
+    }
+
+    /// Return the usage with its cost estimated at the given prices.
+    pub fn priced(self, prices: &Prices) -> Self {
+        let gb_seconds = self.lambda_mb_ms as f64 / 1024.0 / 1000.0;
+        let gb_received = self.bytes_received as f64 / (1024.0 * 1024.0 * 1024.0);
+        let dollars = gb_seconds * prices.lambda_gb_second
+            + self.s3_puts as f64 * prices.s3_put
+            + self.ddb_writes as f64 * prices.ddb_write
+            + gb_received * prices.transfer_gb;
+
+        Self {
+            cost_micros: (dollars * 1_000_000.0).round() as u64,
+            ..self
+        }
+    }
+
+    /// Return the estimated cost in US dollars.
+    pub fn cost(&self) -> f64 {
+        self.cost_micros as f64 / 1_000_000.0
+    }
+}
+
+/// Unit prices, in US dollars, used to estimate costs. The defaults are the on-demand list prices in `us-east-1`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Prices {
+    /// The price of a GB-second of Lambda time (x86).
+    pub lambda_gb_second: f64,
+
+    /// The price of an S3 PUT request (S3 Standard).
+    pub s3_put: f64,
+
+    /// The price of a DynamoDB write request unit (on-demand).
+    pub ddb_write: f64,
+
+    /// The price of a gigabyte received. Data transfer into AWS is free, so this is the NAT gateway's processing
+    /// charge.
+    pub transfer_gb: f64,
+}
+
+impl Default for Prices {
+    fn default() -> Self {
+        Self {
+            lambda_gb_second: 0.000_016_666_7,
+            s3_put: 0.000_005,
+            ddb_write: 0.000_001_25,
+            transfer_gb: 0.045,
+        }
+    }
+}
+
+impl Prices {
+    /// Read prices, looking variables up with `lookup`, falling back to the defaults. Invalid prices are recorded in
+    /// `errors`.
+    pub(crate) fn from_lookup<F: Fn(&str) -> Option<String>>(lookup: &F, errors: &mut ConfigError) -> Self {
+        let defaults = Self::default();
+
+        Self {
+            lambda_gb_second: price(lookup, ENV_COST_LAMBDA_GB_SECOND, defaults.lambda_gb_second, errors),
+            s3_put: price(lookup, ENV_COST_S3_PUT, defaults.s3_put, errors),
+            ddb_write: price(lookup, ENV_COST_DYNAMODB_WRITE, defaults.ddb_write, errors),
+            transfer_gb: price(lookup, ENV_COST_TRANSFER_GB, defaults.transfer_gb, errors),
+        }
+    }
+}
+
+/// The billable resources used by an operation so far.
+///
+/// Each operation is given its own by [`LogConfig::for_operation`]; clones share the tally.
+#[derive(Clone, Debug, Default)]
+pub struct UsageMeter {
+    usage: Arc<Mutex<Usage>>,
+}
+
+impl UsageMeter {
+    /// Record a logged response with a body of `bytes` bytes, which was put to S3 unless an identical body was
+    /// already stored.
+    pub fn record_response(&self, bytes: u64, stored: bool) {
+        let mut usage = self.usage.lock();
+        usage.bytes_received += bytes;
+        usage.ddb_writes += RESPONSE_WRITES;
+        if stored {
+            usage.s3_puts += 1;
+        }
+    }
+
+    /// Remove and return the tally.
+    pub fn take(&self) -> Usage {
+        std::mem::take(&mut *self.usage.lock())
+    }
+}
+
+/// Return the month spend is tallied under, e.g. `2024-05`.
+pub fn spend_month(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m").to_string()
+}
+
+/// Read the monthly budgets, in US dollars, by portal, looking `CRAWL_MONTHLY_BUDGETS` up with `lookup`. It is a
+/// list of `Portal=dollars` entries; invalid entries are recorded in `errors`.
+pub(crate) fn monthly_budgets<F: Fn(&str) -> Option<String>>(
+    lookup: &F,
+    errors: &mut ConfigError,
+) -> BTreeMap<String, f64> {
+    let mut budgets = BTreeMap::new();
+    let value = lookup(ENV_CRAWL_MONTHLY_BUDGETS).unwrap_or_default();
+    for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        match entry.split_once('=').map(|(portal, budget)| (portal.trim(), budget.trim().parse::<f64>())) {
+            Some((portal, Ok(budget))) if !portal.is_empty() && budget >= 0.0 => {
+                budgets.insert(portal.to_string(), budget);
+            }
+            _ => errors.push(ENV_CRAWL_MONTHLY_BUDGETS, format!("{entry:?} is not Portal=dollars")),
+        }
+    }
+
+    budgets
+}
+
+/// Price the usage of a finished operation, which ran for `elapsed` on behalf of `tenant`, and add it to the crawl's
+/// record and the portal's spend for the month.
+///
+/// Recording is best-effort: failures are logged rather than failing the operation.
+pub async fn record_operation(
+    log_config: &LogConfig,
+    portal: &str,
+    tenant: Option<&str>,
+    crawl_id: Option<&str>,
+    elapsed: Duration,
+) {
+    let mut usage = log_config.usage.take();
+    usage.lambda_mb_ms += elapsed.as_millis() as u64 * memory_mb();
+    if crawl_id.is_some() {
+        usage.ddb_writes += OPERATION_JOURNAL_WRITES;
+    }
+    let usage = usage.priced(&log_config.prices);
+
+    let dimensions = metrics::with_tenant(&[(DIMENSION_PORTAL, portal)], tenant);
+    metrics::registry().increment(METRIC_ESTIMATED_COST_MICROS, &dimensions, usage.cost_micros);
+    debug!("Operation on {portal} cost an estimated ${:.6}: {usage:?}", usage.cost());
+
+    if let Some(crawl_id) = crawl_id {
+        if let Err(e) = log_config.crawls.add_usage(crawl_id, &usage).await {
+            warn!("Failed to record usage of crawl {crawl_id}: {e}");
+        }
+    }
+
+    if usage.cost_micros > 0 {
+        let month = spend_month(Utc::now());
+        if let Err(e) = log_config.schedules.add_spend(portal, &month, usage.cost_micros).await {
+            warn!("Failed to record {portal} spend for {month}: {e}");
+        }
+    }
+}
+
+/// Return the Lambda function's memory size, in megabytes.
+fn memory_mb() -> u64 {
+    env::var(ENV_AWS_LAMBDA_FUNCTION_MEMORY_SIZE).ok().and_then(|value| value.parse().ok()).unwrap_or(DEFAULT_MEMORY_MB)
+}
+
+/// Read a price, returning the default if it is unset. A value that isn't a non-negative number is recorded in
+/// `errors`, and the default returned.
+fn price<F: Fn(&str) -> Option<String>>(lookup: &F, name: &str, default: f64, errors: &mut ConfigError) -> f64 {
+    let Some(value) = lookup(name) else {
+        return default;
+    };
+
+    match value.parse() {
+        Ok(price) if price >= 0.0 => price,
+        _ => {
+            errors.push(name, format!("{value:?} is not a price in US dollars"));
+            default
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{monthly_budgets, spend_month, Prices, Usage, UsageMeter},
+        crate::httpext::ConfigError,
+        chrono::{TimeZone, Utc},
+    };
+
+    #[test]
+    fn estimates() {
+        let meter = UsageMeter::default();
+        meter.record_response(512 * 1024 * 1024, true);
+        meter.record_response(512 * 1024 * 1024, false);
+
+        let mut usage = meter.take();
+        assert_eq!(meter.take(), Usage::default());
+        assert_eq!((usage.s3_puts, usage.ddb_writes, usage.bytes_received), (1, 4, 1024 * 1024 * 1024));
+
+        // 100 seconds at 1 GB.
+        usage.lambda_mb_ms = 1024 * 100_000;
+        let prices = Prices {
+            lambda_gb_second: 0.00001,
+            s3_put: 0.000005,
+            ddb_write: 0.00000125,
+            transfer_gb: 0.05,
+        };
+        let usage = usage.priced(&prices);
+        assert_eq!(usage.cost_micros, 1000 + 5 + 5 + 50_000);
+        assert!((usage.cost() - 0.05101).abs() < 1e-9);
+
+        let mut total = Usage::default();
+        total.add(&usage);
+        total.add(&usage);
+        assert_eq!((total.s3_puts, total.cost_micros), (2, 2 * usage.cost_micros));
+    }
+
+    #[test]
+    fn budgets() {
+        let mut errors = ConfigError::default();
+        let budgets =
+            monthly_budgets(&|_| Some(" Webs=25, BidNet = 10.5,,Merx=cheap,=3,Fl=-1,Webs=25$".into()), &mut errors);
+        assert_eq!(budgets.len(), 2);
+        assert_eq!(budgets["Webs"], 25.0);
+        assert_eq!(budgets["BidNet"], 10.5);
+        let invalid: Vec<&str> = errors.problems.iter().map(|problem| problem.message.as_str()).collect();
+        assert_eq!(
+            invalid,
+            [
+                r#""Merx=cheap" is not Portal=dollars"#,
+                r#""=3" is not Portal=dollars"#,
+                r#""Fl=-1" is not Portal=dollars"#,
+                r#""Webs=25$" is not Portal=dollars"#
+            ]
+        );
+
+        assert_eq!(spend_month(Utc.with_ymd_and_hms(2024, 5, 31, 23, 59, 59).unwrap()), "2024-05");
+    }
+}
@@ -0,0 +1,233 @@
+//! Registry of the portals GovScout crawls.
+//!
+//! The registry is the machine-readable answer to "what does GovScout cover?": one entry per portal (or per site, for
+//! subsystems covering several), with the jurisdiction it serves, whether crawling it needs an account, how often it
+//! is meant to be crawled, and the version of its parsers. `Admin:ListCoverage` publishes it as JSON to S3 for
+//! operators and the frontend.
+//!
+//! The parser versions here are also stamped, via [`parser_version`], on the journal items each parser produces.
+use {
+    crate::{
+        bidnet,
+        classification::{Classification, ObjectTags},
+        fl_vbs,
+        httpext::{log_aws_err, LogConfig},
+        merx, publicpurchase,
+        shapes::{SUBSYS_BIDNET, SUBSYS_FL_VBS, SUBSYS_MERX, SUBSYS_PUBLIC_PURCHASE, SUBSYS_WA_LOCAL, SUBSYS_WEBS},
+        wa_local, webs, BoxError,
+    },
+    aws_sdk_s3::primitives::ByteStream,
+    chrono::Duration,
+    serde::{Deserialize, Serialize},
+};
+
+/// Key of the published registry, relative to the S3 prefix.
+const COVERAGE_S3_KEY: &str = "coverage.json";
+
+/// What crawling a portal requires beyond plain HTTP requests.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Authentication {
+    /// The portal's listings and opportunities are public.
+    None,
+
+    /// The crawl logs in with a supplier account whose credentials are stored in SSM.
+    Login,
+}
+
+/// How often a portal is meant to be crawled.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Cadence {
+    /// Every hour, for portals where opportunities can close within days of being posted.
+    Hourly,
+
+    /// Once a day.
+    Daily,
+}
+
+impl Cadence {
+    /// Return the interval a portal with this cadence is crawled at until the [scheduler][crate::schedule] has tuned
+    /// it.
+    pub fn interval(&self) -> Duration {
+        match self {
+            Self::Hourly => Duration::hours(1),
+            Self::Daily => Duration::days(1),
+        }
+    }
+}
+
+/// A portal GovScout crawls.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PortalCoverage {
+    /// The subsystem crawling the portal, e.g. `Webs`.
+    pub subsystem: String,
+
+    /// The portal's name.
+    pub name: String,
+
+    /// The portal's home or listing page.
+    pub url: String,
+
+    /// The jurisdiction whose opportunities the portal publishes.
+    pub jurisdiction: String,
+
+    /// What crawling the portal requires.
+    pub authentication: Authentication,
+
+    /// How often the portal is meant to be crawled.
+    pub cadence: Cadence,
+
+    /// The version of the subsystem's parsers.
+    pub parser_version: u32,
+}
+
+/// Return the registry of supported portals, ordered by subsystem.
+pub fn registry() -> Vec<PortalCoverage> {
+    let portal =
+        |subsystem: &str, name: &str, url: &str, jurisdiction: &str, authentication, cadence, version| PortalCoverage {
+            subsystem: subsystem.to_string(),
+            name: name.to_string(),
+            url: url.to_string(),
+            jurisdiction: jurisdiction.to_string(),
+            authentication,
+            cadence,
+            parser_version: version,
+        };
+
+    let mut registry = vec![
+        portal(
+            SUBSYS_BIDNET,
+            "BidNet Direct",
+            bidnet::DEFAULT_BIDNET_BASE_URL,
+            "Regional purchasing groups (configured regions)",
+            Authentication::Login,
+            Cadence::Daily,
+            bidnet::PARSER_VERSION,
+        ),
+        portal(
+            SUBSYS_FL_VBS,
+            "Florida Vendor Bid System",
+            fl_vbs::DEFAULT_VBS_BASE_URL,
+            "State of Florida",
+            Authentication::None,
+            Cadence::Daily,
+            fl_vbs::PARSER_VERSION,
+        ),
+        portal(
+            SUBSYS_MERX,
+            "MERX",
+            merx::DEFAULT_MERX_BASE_URL,
+            "Canada (federal, provincial, and municipal)",
+            Authentication::None,
+            Cadence::Daily,
+            merx::PARSER_VERSION,
+        ),
+        portal(
+            SUBSYS_PUBLIC_PURCHASE,
+            "PublicPurchase",
+            publicpurchase::DEFAULT_PUBLIC_PURCHASE_BASE_URL,
+            "Participating local agencies",
+            Authentication::Login,
+            Cadence::Daily,
+            publicpurchase::PARSER_VERSION,
+        ),
+    ];
+
+    for site in wa_local::SITES {
+        registry.push(portal(
+            SUBSYS_WA_LOCAL,
+            site.id,
+            site.listing_url,
+            site.jurisdiction,
+            Authentication::None,
+            Cadence::Daily,
+            wa_local::PARSER_VERSION,
+        ));
+    }
+
+    registry.push(portal(
+        SUBSYS_WEBS,
+        "Washington's Electronic Business Solution (WEBS)",
+        webs::DEFAULT_WEBS_BASE_URL,
+        "State of Washington",
+        Authentication::Login,
+        Cadence::Hourly,
+        webs::PARSER_VERSION,
+    ));
+
+    registry
+}
+
+/// Return each crawled subsystem once, in the order of the registry.
+pub fn subsystems() -> Vec<String> {
+    let mut subsystems: Vec<String> = Vec::new();
+    for portal in registry() {
+        if !subsystems.contains(&portal.subsystem) {
+            subsystems.push(portal.subsystem);
+        }
+    }
+
+    subsystems
+}
+
+/// Return the version of a parser, given its name (e.g. `FlVbs:Advertisement`), or `None` for an unknown subsystem.
+///
+/// Parser names start with their subsystem, and a subsystem's parsers share its version.
+pub fn parser_version(parser: &str) -> Option<u32> {
+    let subsystem = parser.split(':').next()?;
+    registry().into_iter().find(|portal| portal.subsystem == subsystem).map(|portal| portal.parser_version)
+}
+
+/// Publish the registry as JSON to S3, returning the key it was written to.
+pub async fn publish(log_config: &LogConfig) -> Result<String, BoxError> {
+    let body = serde_json::to_vec_pretty(&registry())?;
+    let key = format!("{}{COVERAGE_S3_KEY}", log_config.s3_prefix);
+
+    log_aws_err(
+        log_config
+            .s3_client
+            .put_object()
+            .bucket(&log_config.s3_bucket)
+            .key(&key)
+            .content_type("application/json")
+            .tagging(ObjectTags::new(Classification::CrawlMetadata).to_tagging())
+            .body(ByteStream::from(body))
+            .send()
+            .await,
+        &format!("PutObject s3://{}/{key}", log_config.s3_bucket),
+    )?;
+
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{parser_version, registry, Authentication},
+        crate::shapes::{SUBSYS_WA_LOCAL, SUBSYS_WEBS},
+        std::collections::BTreeSet,
+    };
+
+    #[test]
+    fn registry_entries() {
+        let registry = registry();
+
+        let subsystems: BTreeSet<&str> = registry.iter().map(|p| p.subsystem.as_str()).collect();
+        assert_eq!(subsystems.len(), 6);
+        assert!(registry.windows(2).all(|pair| pair[0].subsystem <= pair[1].subsystem));
+        assert!(registry.iter().all(|p| p.url.starts_with("https://") && p.parser_version > 0));
+        assert_eq!(registry.iter().filter(|p| p.subsystem == SUBSYS_WA_LOCAL).count(), 2);
+
+        let webs = registry.iter().find(|p| p.subsystem == SUBSYS_WEBS).unwrap();
+        assert_eq!(webs.authentication, Authentication::Login);
+        assert_eq!(serde_json::to_value(webs).unwrap()["Cadence"], serde_json::Value::String("Hourly".to_string()));
+    }
+
+    #[test]
+    fn parser_versions() {
+        assert_eq!(parser_version("FlVbs:Advertisement"), Some(crate::fl_vbs::PARSER_VERSION));
+        assert_eq!(parser_version("WaLocal:Seattle:Detail"), Some(crate::wa_local::PARSER_VERSION));
+        assert_eq!(parser_version("Unknown:Listing"), None);
+        assert_eq!(parser_version(""), None);
+    }
+}
@@ -0,0 +1,624 @@
+//! Registry of crawls.
+//!
+//! Every crawl is registered in DynamoDB when it starts, recording the portal, start time, and the parameters it was
+//! started with. Operations for a crawl id that was never registered are rejected, so a stray or hand-crafted message
+//! can't silently start a disjoint crawl.
+//!
+//! When the [request audit][crate::httpext::RequestAudit] is enabled, the crawl's partition also holds an item per
+//! domain contacted, tallying the requests made and bytes received, so a compliance review can show which sites a
+//! crawl touched.
+//!
+//! The crawl record also tallies the resources the crawl's operations used and their [estimated cost][crate::cost].
+//!
+//! A crawl can be paused, which the record flags; the operations of a paused crawl are dropped on delivery, and its
+//! [frontier][crate::frontier] holds what it still had to do.
+//!
+//! Each portal also has a state item recording when its last successful crawl searched it, from which incremental
+//! crawls pick up.
+use {
+    crate::{
+        classification::{Classification, DDB_KEY_CLASSIFICATION},
+        cost::Usage,
+        httpext::{aws_err_str, DomainTraffic},
+        quality::QualityTally,
+        BoxError,
+    },
+    aws_sdk_dynamodb::{
+        types::{AttributeValue, ReturnValue},
+        Client as DynamoDbClient,
+    },
+    chrono::{DateTime, Utc},
+    log::*,
+    parking_lot::Mutex,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{HashMap, HashSet},
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        sync::Arc,
+    },
+    uuid::{NoContext, Timestamp, Uuid},
+};
+
+const DDB_KEY_CRAWL_ID: &str = "CrawlId";
+const DDB_KEY_REQUEST_ID: &str = "RequestId";
+const DDB_KEY_PORTAL: &str = "Portal";
+const DDB_KEY_OPERATION: &str = "Operation";
+const DDB_KEY_URL: &str = "Url";
+const DDB_KEY_USER_AGENT: &str = "UserAgent";
+const DDB_KEY_STARTED_AT: &str = "StartedAt";
+const DDB_KEY_QUALITY_COUNT: &str = "QualityCount";
+const DDB_KEY_QUALITY_TOTAL: &str = "QualityTotal";
+const DDB_KEY_DOMAIN: &str = "Domain";
+const DDB_KEY_REQUESTS: &str = "Requests";
+const DDB_KEY_FAILURES: &str = "Failures";
+const DDB_KEY_REFUSED: &str = "Refused";
+const DDB_KEY_BYTES: &str = "Bytes";
+const DDB_KEY_TENANT: &str = "Tenant";
+const DDB_KEY_LAMBDA_MB_MS: &str = "LambdaMbMs";
+const DDB_KEY_S3_PUTS: &str = "S3Puts";
+const DDB_KEY_DDB_WRITES: &str = "DynamoDbWrites";
+const DDB_KEY_BYTES_RECEIVED: &str = "BytesReceived";
+const DDB_KEY_COST_MICROS: &str = "CostMicros";
+const DDB_KEY_LAST_SUCCESS_AT: &str = "LastSuccessAt";
+const DDB_KEY_LAST_SUCCESS_CRAWL_ID: &str = "LastSuccessCrawlId";
+const DDB_KEY_PAUSED: &str = "Paused";
+
+/// Partition key prefix for crawl records, keeping them apart from request log items in a shared table.
+const CRAWL_PARTITION_PREFIX: &str = "Crawl#";
+
+/// Sort key of the crawl record within its partition.
+const CRAWL_RECORD_SORT_KEY: &str = "Record";
+
+/// Sort key prefix of the audit items within a crawl's partition, followed by the domain.
+const AUDIT_SORT_PREFIX: &str = "Audit#";
+
+/// Partition key prefix for portal state items, followed by the portal.
+const PORTAL_STATE_PARTITION_PREFIX: &str = "CrawlState#";
+
+/// Sort key of the portal's last successful crawl within its state partition.
+const LAST_SUCCESS_SORT_KEY: &str = "LastSuccess";
+
+/// The parameters a crawl was started with.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CrawlRecord {
+    /// The crawl id.
+    pub crawl_id: String,
+
+    /// The portal (subsystem) being crawled.
+    pub portal: String,
+
+    /// The operation that started the crawl.
+    pub operation: String,
+
+    /// The URL the crawl started from, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+
+    /// The user agent the crawl uses.
+    pub user_agent: String,
+
+    /// When the crawl was registered.
+    pub started_at: DateTime<Utc>,
+
+    /// The team the crawl is run for, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+/// An operation referenced a crawl id that was never registered.
+#[derive(Debug)]
+pub struct UnknownCrawlError {
+    /// The unregistered crawl id.
+    pub crawl_id: String,
+}
+
+impl Display for UnknownCrawlError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "Unknown crawl id: {}", self.crawl_id)
+    }
+}
+
+impl Error for UnknownCrawlError {}
+
+/// Storage for crawl records in DynamoDB.
+///
+/// Clones share a cache of crawl ids already known to exist, so a batch of messages for the same crawl looks the
+/// record up only once.
+#[derive(Clone, Debug)]
+pub struct CrawlRegistry {
+    ddb_client: DynamoDbClient,
+    table_name: String,
+    known: Arc<Mutex<HashSet<String>>>,
+}
+
+impl CrawlRegistry {
+    /// Create a crawl registry using the given table.
+    pub fn new<S: Into<String>>(ddb_client: DynamoDbClient, table_name: S) -> Self {
+        Self {
+            ddb_client,
+            table_name: table_name.into(),
+            known: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Return the name of the table crawls are registered in.
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    /// Register a new crawl, returning its record with a newly issued UUIDv7 crawl id.
+    pub async fn register(
+        &self,
+        portal: &str,
+        operation: &str,
+        url: Option<&str>,
+        user_agent: &str,
+        tenant: Option<&str>,
+    ) -> Result<CrawlRecord, BoxError> {
+        let record = CrawlRecord {
+            crawl_id: Uuid::new_v7(Timestamp::now(NoContext)).to_string(),
+            portal: portal.to_string(),
+            operation: operation.to_string(),
+            url: url.map(str::to_string),
+            user_agent: user_agent.to_string(),
+            started_at: Utc::now(),
+            tenant: tenant.map(str::to_string),
+        };
+
+        if !self.put_record(&record).await? {
+            return Err(format!("Crawl id {} is already registered", record.crawl_id).into());
+        }
+
+        info!("Registered crawl {} for {portal}", record.crawl_id);
+        Ok(record)
+    }
+
+    /// Register a crawl exported from another deployment under its original id, returning `false` if a crawl with
+    /// that id is already registered.
+    pub async fn restore(&self, record: &CrawlRecord) -> Result<bool, BoxError> {
+        let restored = self.put_record(record).await?;
+        if restored {
+            info!("Restored crawl {} for {}", record.crawl_id, record.portal);
+        }
+
+        Ok(restored)
+    }
+
+    /// Write a crawl record unless one with its id exists, returning whether it was written.
+    async fn put_record(&self, record: &CrawlRecord) -> Result<bool, BoxError> {
+        let mut put = self
+            .ddb_client
+            .put_item()
+            .table_name(&self.table_name)
+            .item(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{CRAWL_PARTITION_PREFIX}{}", record.crawl_id)))
+            .item(DDB_KEY_REQUEST_ID, AttributeValue::S(CRAWL_RECORD_SORT_KEY.to_string()))
+            .item(DDB_KEY_PORTAL, AttributeValue::S(record.portal.clone()))
+            .item(DDB_KEY_OPERATION, AttributeValue::S(record.operation.clone()))
+            .item(DDB_KEY_USER_AGENT, AttributeValue::S(record.user_agent.clone()))
+            .item(DDB_KEY_STARTED_AT, AttributeValue::S(record.started_at.to_rfc3339()))
+            .item(DDB_KEY_CLASSIFICATION, AttributeValue::S(Classification::CrawlMetadata.to_string()))
+            // UUIDv7 ids shouldn't collide, but never overwrite an existing crawl if one does.
+            .condition_expression("attribute_not_exists(#crawl_id)")
+            .expression_attribute_names("#crawl_id", DDB_KEY_CRAWL_ID);
+        if let Some(url) = record.url.as_ref() {
+            put = put.item(DDB_KEY_URL, AttributeValue::S(url.clone()));
+        }
+
+        if let Some(tenant) = record.tenant.as_ref() {
+            put = put.item(DDB_KEY_TENANT, AttributeValue::S(tenant.clone()));
+        }
+
+        match put.send().await {
+            Ok(_) => (),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => {
+                return Ok(false);
+            }
+            Err(e) => {
+                error!("PutItem crawl record {}: {}", record.crawl_id, aws_err_str(&e));
+                return Err(e.into());
+            }
+        }
+
+        self.known.lock().insert(record.crawl_id.clone());
+        Ok(true)
+    }
+
+    /// Return the record of a crawl, or `None` if it was never registered.
+    pub async fn record(&self, crawl_id: &str) -> Result<Option<CrawlRecord>, BoxError> {
+        let result = self
+            .ddb_client
+            .get_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{CRAWL_PARTITION_PREFIX}{crawl_id}")))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(CRAWL_RECORD_SORT_KEY.to_string()))
+            .consistent_read(true)
+            .send()
+            .await;
+
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => {
+                error!("GetItem crawl record {crawl_id}: {}", aws_err_str(&e));
+                return Err(e.into());
+            }
+        };
+
+        Ok(output.item.as_ref().and_then(|item| parse_record(crawl_id, item)))
+    }
+
+    /// Pause or resume a crawl.
+    pub async fn set_paused(&self, crawl_id: &str, paused: bool) -> Result<(), BoxError> {
+        let update = self
+            .ddb_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{CRAWL_PARTITION_PREFIX}{crawl_id}")))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(CRAWL_RECORD_SORT_KEY.to_string()))
+            .condition_expression("attribute_exists(#crawl_id)")
+            .expression_attribute_names("#crawl_id", DDB_KEY_CRAWL_ID)
+            .expression_attribute_names("#paused", DDB_KEY_PAUSED);
+        let update = if paused {
+            update
+                .update_expression("SET #paused = :paused")
+                .expression_attribute_values(":paused", AttributeValue::Bool(true))
+        } else {
+            update.update_expression("REMOVE #paused")
+        };
+
+        match update.send().await {
+            Ok(_) => Ok(()),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => {
+                Err(UnknownCrawlError {
+                    crawl_id: crawl_id.to_string(),
+                }
+                .into())
+            }
+            Err(e) => {
+                error!("UpdateItem crawl record {crawl_id} paused: {}", aws_err_str(&e));
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Indicates whether a crawl is paused.
+    pub async fn is_paused(&self, crawl_id: &str) -> Result<bool, BoxError> {
+        let result = self
+            .ddb_client
+            .get_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{CRAWL_PARTITION_PREFIX}{crawl_id}")))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(CRAWL_RECORD_SORT_KEY.to_string()))
+            .projection_expression("#paused")
+            .expression_attribute_names("#paused", DDB_KEY_PAUSED)
+            .consistent_read(true)
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => Ok(output
+                .item
+                .as_ref()
+                .and_then(|item| item.get(DDB_KEY_PAUSED))
+                .and_then(|value| value.as_bool().ok())
+                .is_some_and(|paused| *paused)),
+            Err(e) => {
+                error!("GetItem crawl record {crawl_id} paused: {}", aws_err_str(&e));
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Indicates whether a crawl with the given id has been registered.
+    pub async fn exists(&self, crawl_id: &str) -> Result<bool, BoxError> {
+        if self.known.lock().contains(crawl_id) {
+            return Ok(true);
+        }
+
+        let result = self
+            .ddb_client
+            .get_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{CRAWL_PARTITION_PREFIX}{crawl_id}")))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(CRAWL_RECORD_SORT_KEY.to_string()))
+            .projection_expression("#crawl_id")
+            .expression_attribute_names("#crawl_id", DDB_KEY_CRAWL_ID)
+            .consistent_read(true)
+            .send()
+            .await;
+
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => {
+                error!("GetItem crawl record {crawl_id}: {}", aws_err_str(&e));
+                return Err(e.into());
+            }
+        };
+
+        let exists = output.item.is_some();
+        if exists {
+            self.known.lock().insert(crawl_id.to_string());
+        }
+
+        Ok(exists)
+    }
+
+    /// Add an opportunity's quality score to the crawl's running tally, returning the updated tally.
+    ///
+    /// The tally is updated atomically, so operations running concurrently for the same crawl each see a distinct
+    /// count.
+    pub async fn add_quality_score(&self, crawl_id: &str, score: u32) -> Result<QualityTally, BoxError> {
+        let result = self
+            .ddb_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{CRAWL_PARTITION_PREFIX}{crawl_id}")))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(CRAWL_RECORD_SORT_KEY.to_string()))
+            .update_expression("ADD #count :one, #total :score")
+            .expression_attribute_names("#count", DDB_KEY_QUALITY_COUNT)
+            .expression_attribute_names("#total", DDB_KEY_QUALITY_TOTAL)
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(":score", AttributeValue::N(score.to_string()))
+            .return_values(ReturnValue::UpdatedNew)
+            .send()
+            .await;
+
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => {
+                error!("UpdateItem crawl record {crawl_id} quality: {}", aws_err_str(&e));
+                return Err(e.into());
+            }
+        };
+
+        let attributes = output.attributes.unwrap_or_default();
+        let number = |name: &str| attributes.get(name).and_then(|value| value.as_n().ok()?.parse().ok()).unwrap_or(0);
+        Ok(QualityTally {
+            opportunities: number(DDB_KEY_QUALITY_COUNT),
+            total_score: number(DDB_KEY_QUALITY_TOTAL),
+        })
+    }
+
+    /// Add the resources used by one of the crawl's operations to its tally.
+    pub async fn add_usage(&self, crawl_id: &str, usage: &Usage) -> Result<(), BoxError> {
+        let result = self
+            .ddb_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{CRAWL_PARTITION_PREFIX}{crawl_id}")))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(CRAWL_RECORD_SORT_KEY.to_string()))
+            .update_expression(
+                "ADD #lambda :lambda, #s3_puts :s3_puts, #ddb_writes :ddb_writes, #bytes :bytes, #cost :cost",
+            )
+            .expression_attribute_names("#lambda", DDB_KEY_LAMBDA_MB_MS)
+            .expression_attribute_names("#s3_puts", DDB_KEY_S3_PUTS)
+            .expression_attribute_names("#ddb_writes", DDB_KEY_DDB_WRITES)
+            .expression_attribute_names("#bytes", DDB_KEY_BYTES_RECEIVED)
+            .expression_attribute_names("#cost", DDB_KEY_COST_MICROS)
+            .expression_attribute_values(":lambda", AttributeValue::N(usage.lambda_mb_ms.to_string()))
+            .expression_attribute_values(":s3_puts", AttributeValue::N(usage.s3_puts.to_string()))
+            .expression_attribute_values(":ddb_writes", AttributeValue::N(usage.ddb_writes.to_string()))
+            .expression_attribute_values(":bytes", AttributeValue::N(usage.bytes_received.to_string()))
+            .expression_attribute_values(":cost", AttributeValue::N(usage.cost_micros.to_string()))
+            .send()
+            .await;
+
+        if let Err(e) = result {
+            error!("UpdateItem crawl record {crawl_id} usage: {}", aws_err_str(&e));
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    /// Return the resources the crawl used and their estimated cost.
+    pub async fn usage(&self, crawl_id: &str) -> Result<Usage, BoxError> {
+        let result = self
+            .ddb_client
+            .get_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{CRAWL_PARTITION_PREFIX}{crawl_id}")))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(CRAWL_RECORD_SORT_KEY.to_string()))
+            .send()
+            .await;
+
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => {
+                error!("GetItem crawl record {crawl_id} usage: {}", aws_err_str(&e));
+                return Err(e.into());
+            }
+        };
+
+        Ok(output.item.as_ref().map(parse_usage).unwrap_or_default())
+    }
+
+    /// Add a tally of the requests made to a domain to the crawl's audit.
+    pub async fn add_domain_traffic(&self, crawl_id: &str, traffic: &DomainTraffic) -> Result<(), BoxError> {
+        let result = self
+            .ddb_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{CRAWL_PARTITION_PREFIX}{crawl_id}")))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(format!("{AUDIT_SORT_PREFIX}{}", traffic.domain)))
+            .update_expression(
+                "SET #domain = :domain ADD #requests :requests, #failures :failures, #refused :refused, #bytes :bytes",
+            )
+            .expression_attribute_names("#domain", DDB_KEY_DOMAIN)
+            .expression_attribute_names("#requests", DDB_KEY_REQUESTS)
+            .expression_attribute_names("#failures", DDB_KEY_FAILURES)
+            .expression_attribute_names("#refused", DDB_KEY_REFUSED)
+            .expression_attribute_names("#bytes", DDB_KEY_BYTES)
+            .expression_attribute_values(":domain", AttributeValue::S(traffic.domain.clone()))
+            .expression_attribute_values(":requests", AttributeValue::N(traffic.requests.to_string()))
+            .expression_attribute_values(":failures", AttributeValue::N(traffic.failures.to_string()))
+            .expression_attribute_values(":refused", AttributeValue::N(traffic.refused.to_string()))
+            .expression_attribute_values(":bytes", AttributeValue::N(traffic.bytes.to_string()))
+            .send()
+            .await;
+
+        if let Err(e) = result {
+            error!("UpdateItem crawl {crawl_id} audit for {}: {}", traffic.domain, aws_err_str(&e));
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    /// Return the crawl's audit: the requests made to each domain it contacted, ordered by domain.
+    pub async fn audit_report(&self, crawl_id: &str) -> Result<Vec<DomainTraffic>, BoxError> {
+        let mut report = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .ddb_client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("#pk = :pk AND begins_with(#sk, :prefix)")
+                .expression_attribute_names("#pk", DDB_KEY_CRAWL_ID)
+                .expression_attribute_names("#sk", DDB_KEY_REQUEST_ID)
+                .expression_attribute_values(":pk", AttributeValue::S(format!("{CRAWL_PARTITION_PREFIX}{crawl_id}")))
+                .expression_attribute_values(":prefix", AttributeValue::S(AUDIT_SORT_PREFIX.to_string()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await;
+            let output = match result {
+                Ok(output) => output,
+                Err(e) => {
+                    error!("Query crawl {crawl_id} audit: {}", aws_err_str(&e));
+                    return Err(e.into());
+                }
+            };
+
+            report.extend(output.items.unwrap_or_default().iter().filter_map(parse_domain_traffic));
+
+            start_key = output.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(report);
+            }
+        }
+    }
+
+    /// Record that a crawl of the portal searched it successfully at `searched_at`.
+    ///
+    /// The record only moves forward, so a slow crawl finishing after a newer one doesn't rewind it.
+    pub async fn record_success(
+        &self,
+        portal: &str,
+        crawl_id: &str,
+        searched_at: DateTime<Utc>,
+    ) -> Result<(), BoxError> {
+        let result = self
+            .ddb_client
+            .put_item()
+            .table_name(&self.table_name)
+            .item(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{PORTAL_STATE_PARTITION_PREFIX}{portal}")))
+            .item(DDB_KEY_REQUEST_ID, AttributeValue::S(LAST_SUCCESS_SORT_KEY.to_string()))
+            .item(DDB_KEY_LAST_SUCCESS_AT, AttributeValue::N(searched_at.timestamp().to_string()))
+            .item(DDB_KEY_LAST_SUCCESS_CRAWL_ID, AttributeValue::S(crawl_id.to_string()))
+            .condition_expression("attribute_not_exists(#at) OR #at < :at")
+            .expression_attribute_names("#at", DDB_KEY_LAST_SUCCESS_AT)
+            .expression_attribute_values(":at", AttributeValue::N(searched_at.timestamp().to_string()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => {
+                info!("Recorded crawl {crawl_id} as the last successful crawl of {portal}");
+                Ok(())
+            }
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => {
+                info!(
+                    "Not recording crawl {crawl_id} as the last successful crawl of {portal}; a newer one is recorded"
+                );
+                Ok(())
+            }
+            Err(e) => {
+                error!("PutItem {portal} last successful crawl: {}", aws_err_str(&e));
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Return when the portal's last successful crawl searched it, or `None` if it has never been crawled
+    /// successfully.
+    pub async fn last_success(&self, portal: &str) -> Result<Option<DateTime<Utc>>, BoxError> {
+        let result = self
+            .ddb_client
+            .get_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{PORTAL_STATE_PARTITION_PREFIX}{portal}")))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(LAST_SUCCESS_SORT_KEY.to_string()))
+            .consistent_read(true)
+            .send()
+            .await;
+
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => {
+                error!("GetItem {portal} last successful crawl: {}", aws_err_str(&e));
+                return Err(e.into());
+            }
+        };
+
+        let searched_at = output
+            .item
+            .as_ref()
+            .and_then(|item| item.get(DDB_KEY_LAST_SUCCESS_AT)?.as_n().ok()?.parse().ok())
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0));
+        Ok(searched_at)
+    }
+
+    /// Return an error unless a crawl with the given id has been registered.
+    pub async fn require(&self, crawl_id: &str) -> Result<(), BoxError> {
+        if self.exists(crawl_id).await? {
+            Ok(())
+        } else {
+            Err(UnknownCrawlError {
+                crawl_id: crawl_id.to_string(),
+            }
+            .into())
+        }
+    }
+}
+
+/// Parse a crawl record, returning `None` if the item is incomplete.
+fn parse_record(crawl_id: &str, item: &HashMap<String, AttributeValue>) -> Option<CrawlRecord> {
+    let string = |name: &str| item.get(name).and_then(|value| value.as_s().ok()).cloned();
+
+    Some(CrawlRecord {
+        crawl_id: crawl_id.to_string(),
+        portal: string(DDB_KEY_PORTAL)?,
+        operation: string(DDB_KEY_OPERATION)?,
+        url: string(DDB_KEY_URL),
+        user_agent: string(DDB_KEY_USER_AGENT).unwrap_or_default(),
+        started_at: DateTime::parse_from_rfc3339(&string(DDB_KEY_STARTED_AT)?).ok()?.with_timezone(&Utc),
+        tenant: string(DDB_KEY_TENANT),
+    })
+}
+
+/// Parse the usage tallied on a crawl record.
+fn parse_usage(item: &HashMap<String, AttributeValue>) -> Usage {
+    let number = |name: &str| item.get(name).and_then(|value| value.as_n().ok()?.parse().ok()).unwrap_or(0);
+    Usage {
+        lambda_mb_ms: number(DDB_KEY_LAMBDA_MB_MS),
+        s3_puts: number(DDB_KEY_S3_PUTS),
+        ddb_writes: number(DDB_KEY_DDB_WRITES),
+        bytes_received: number(DDB_KEY_BYTES_RECEIVED),
+        cost_micros: number(DDB_KEY_COST_MICROS),
+    }
+}
+
+/// Parse an audit item, skipping ones without a domain.
+fn parse_domain_traffic(item: &HashMap<String, AttributeValue>) -> Option<DomainTraffic> {
+    let number = |name: &str| item.get(name).and_then(|value| value.as_n().ok()?.parse().ok()).unwrap_or(0);
+    Some(DomainTraffic {
+        domain: item.get(DDB_KEY_DOMAIN)?.as_s().ok()?.clone(),
+        requests: number(DDB_KEY_REQUESTS),
+        failures: number(DDB_KEY_FAILURES),
+        refused: number(DDB_KEY_REFUSED),
+        bytes: number(DDB_KEY_BYTES),
+    })
+}
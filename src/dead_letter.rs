@@ -0,0 +1,430 @@
+//! Records of failed requests, for triaging the dead-letter queue.
+//!
+//! SQS redelivers a message whose operation fails until the queue's redrive policy moves it to the dead-letter queue,
+//! carrying nothing but the original body. Each failed delivery therefore also writes a failure record keyed by the
+//! message id, which SQS keeps when dead-lettering, replacing the record of any earlier delivery. The record of a
+//! dead-lettered message holds its final error, where the body of the last response it fetched was stored, and how
+//! long the operation ran, alongside the request itself. A message that succeeds on redelivery has its record removed.
+//! Records are stored in the partition of their crawl and copied to an index partition holding every crawl's, so
+//! failures can be found without scanning the table whether or not a crawl is named.
+//!
+//! Each record also names the [class of error][error_class] it failed with, so `Admin:Requeue` can re-enqueue, say,
+//! every request of one subsystem that failed to parse once a parser fix is deployed.
+use {
+    crate::{
+        httpext::{aws_err_str, FetchErrorKind, HttpStatusError},
+        retry::{RetriesExhaustedError, RetryableError},
+        shapes::Request,
+        BoxError, CRATE_VERSION,
+    },
+    aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoDbClient},
+    chrono::{DateTime, Duration, Utc},
+    log::*,
+    std::{collections::HashMap, error::Error},
+};
+
+const DDB_KEY_CRAWL_ID: &str = "CrawlId";
+const DDB_KEY_REQUEST_ID: &str = "RequestId";
+const DDB_KEY_OPERATION: &str = "Operation";
+const DDB_KEY_URL: &str = "Url";
+const DDB_KEY_ERROR: &str = "Error";
+const DDB_KEY_ERROR_CLASS: &str = "ErrorClass";
+const DDB_KEY_RECEIVE_COUNT: &str = "ReceiveCount";
+const DDB_KEY_STARTED_AT: &str = "StartedAt";
+const DDB_KEY_ELAPSED_MS: &str = "ElapsedMs";
+const DDB_KEY_LAST_S3_BUCKET: &str = "LastS3Bucket";
+const DDB_KEY_LAST_S3_KEY: &str = "LastS3Key";
+const DDB_KEY_REQUEST: &str = "Request";
+const DDB_KEY_CRATE_VERSION: &str = "CrateVersion";
+const DDB_KEY_EXPIRES_AT: &str = "ExpiresAt";
+const DDB_KEY_MESSAGE_ID: &str = "MessageId";
+
+/// Partition key prefix for failure records, followed by the crawl id, keeping them apart from request log items in a
+/// shared table.
+const FAILURE_PARTITION_PREFIX: &str = "Failures#";
+
+/// Partition key of the copies of every crawl's failure records, sorted by crawl id and message id.
+const FAILURE_INDEX_PARTITION_KEY: &str = "FailureIndex";
+
+/// Stands in for the crawl id of requests that aren't part of a crawl, such as administrative operations.
+const NO_CRAWL: &str = "none";
+
+/// How long a failure record is kept before the table's TTL removes it. SQS keeps a message for at most 14 days, so
+/// a record never outlives its dead-lettered message by much.
+const FAILURE_TTL_DAYS: i64 = 14;
+
+/// The class of errors that hit an HTTP error status.
+const ERROR_CLASS_HTTP_STATUS: &str = "HttpStatus";
+
+/// The class of errors not otherwise classified, such as parse failures.
+const ERROR_CLASS_OTHER: &str = "Other";
+
+/// The context of a failed delivery of a request.
+#[derive(Clone, Debug)]
+pub struct FailureRecord {
+    /// The SQS message id of the request.
+    pub message_id: String,
+
+    /// The number of times SQS had delivered the message, including this delivery.
+    pub receive_count: u32,
+
+    /// The error the operation failed with.
+    pub error: String,
+
+    /// The class of the error, from [`error_class`].
+    pub error_class: String,
+
+    /// When the operation started.
+    pub started_at: DateTime<Utc>,
+
+    /// How long the operation ran before failing, in milliseconds.
+    pub elapsed_ms: u64,
+
+    /// The S3 bucket and key of the body of the last response the operation fetched, if any.
+    pub last_response: Option<(String, String)>,
+
+    /// The request that failed.
+    pub request: Request,
+}
+
+impl FailureRecord {
+    /// Return the DynamoDB item for this record.
+    fn item(&self, now: DateTime<Utc>) -> Result<HashMap<String, AttributeValue>, BoxError> {
+        let crawl_id = self.request.crawl.crawl_id.as_deref().unwrap_or(NO_CRAWL);
+        let expires_at = now + Duration::days(FAILURE_TTL_DAYS);
+        let mut item = HashMap::from([
+            (DDB_KEY_CRAWL_ID.to_string(), AttributeValue::S(format!("{FAILURE_PARTITION_PREFIX}{crawl_id}"))),
+            (DDB_KEY_REQUEST_ID.to_string(), AttributeValue::S(self.message_id.clone())),
+            (DDB_KEY_MESSAGE_ID.to_string(), AttributeValue::S(self.message_id.clone())),
+            (DDB_KEY_OPERATION.to_string(), AttributeValue::S(self.request.operation.clone())),
+            (DDB_KEY_ERROR.to_string(), AttributeValue::S(self.error.clone())),
+            (DDB_KEY_ERROR_CLASS.to_string(), AttributeValue::S(self.error_class.clone())),
+            (DDB_KEY_RECEIVE_COUNT.to_string(), AttributeValue::N(self.receive_count.to_string())),
+            (DDB_KEY_STARTED_AT.to_string(), AttributeValue::S(self.started_at.to_rfc3339())),
+            (DDB_KEY_ELAPSED_MS.to_string(), AttributeValue::N(self.elapsed_ms.to_string())),
+            (DDB_KEY_REQUEST.to_string(), AttributeValue::S(serde_json::to_string(&self.request)?)),
+            (DDB_KEY_CRATE_VERSION.to_string(), AttributeValue::S(CRATE_VERSION.to_string())),
+            (DDB_KEY_EXPIRES_AT.to_string(), AttributeValue::N(expires_at.timestamp().to_string())),
+        ]);
+
+        if let Some(url) = self.request.url.as_ref() {
+            item.insert(DDB_KEY_URL.to_string(), AttributeValue::S(url.clone()));
+        }
+
+        if let Some((bucket, key)) = self.last_response.as_ref() {
+            item.insert(DDB_KEY_LAST_S3_BUCKET.to_string(), AttributeValue::S(bucket.clone()));
+            item.insert(DDB_KEY_LAST_S3_KEY.to_string(), AttributeValue::S(key.clone()));
+        }
+
+        Ok(item)
+    }
+
+    /// Return the copy of this record's DynamoDB item in the index partition.
+    fn index_item(&self, now: DateTime<Utc>) -> Result<HashMap<String, AttributeValue>, BoxError> {
+        let crawl_id = self.request.crawl.crawl_id.as_deref().unwrap_or(NO_CRAWL);
+        let mut item = self.item(now)?;
+        item.insert(DDB_KEY_CRAWL_ID.to_string(), AttributeValue::S(FAILURE_INDEX_PARTITION_KEY.to_string()));
+        item.insert(DDB_KEY_REQUEST_ID.to_string(), AttributeValue::S(index_sort_key(crawl_id, &self.message_id)));
+        Ok(item)
+    }
+
+    /// Read a record from its DynamoDB item, returning `None` if the item is incomplete.
+    fn from_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        let string = |name: &str| item.get(name).and_then(|value| value.as_s().ok()).cloned();
+        let number = |name: &str| item.get(name).and_then(|value| value.as_n().ok());
+
+        let request = match serde_json::from_str(&string(DDB_KEY_REQUEST)?) {
+            Ok(request) => request,
+            Err(e) => {
+                warn!("Ignoring failure record with an unreadable request: {e}");
+                return None;
+            }
+        };
+
+        Some(Self {
+            message_id: string(DDB_KEY_MESSAGE_ID).or_else(|| string(DDB_KEY_REQUEST_ID))?,
+            receive_count: number(DDB_KEY_RECEIVE_COUNT).and_then(|n| n.parse().ok()).unwrap_or_default(),
+            error: string(DDB_KEY_ERROR).unwrap_or_default(),
+            error_class: string(DDB_KEY_ERROR_CLASS).unwrap_or_else(|| ERROR_CLASS_OTHER.to_string()),
+            started_at: DateTime::parse_from_rfc3339(&string(DDB_KEY_STARTED_AT)?).ok()?.with_timezone(&Utc),
+            elapsed_ms: number(DDB_KEY_ELAPSED_MS).and_then(|n| n.parse().ok()).unwrap_or_default(),
+            last_response: string(DDB_KEY_LAST_S3_BUCKET).zip(string(DDB_KEY_LAST_S3_KEY)),
+            request,
+        })
+    }
+
+    /// Return the subsystem of the failed request's operation.
+    pub fn subsystem(&self) -> &str {
+        self.request.operation.split(':').next().unwrap_or_default()
+    }
+}
+
+/// Return the class of an error an operation failed with.
+///
+/// A request that gave up retrying is classed by the condition it kept hitting (e.g. `WebsMaintenance`), a failed
+/// fetch by its [connection-level failure][FetchErrorKind] (e.g. `Dns`), and a fetch answered with an HTTP error
+/// status as `HttpStatus`. Anything else, such as a parse failure, is `Other`.
+pub fn error_class(error: &(dyn Error + 'static)) -> String {
+    let mut source = Some(error);
+    while let Some(e) = source {
+        if let Some(e) = e.downcast_ref::<RetriesExhaustedError>() {
+            return e.error.condition.to_string();
+        }
+
+        if let Some(e) = e.downcast_ref::<RetryableError>() {
+            return e.condition.to_string();
+        }
+
+        if e.is::<reqwest::Error>() {
+            return FetchErrorKind::classify(e).as_str().to_string();
+        }
+
+        if e.is::<HttpStatusError>() {
+            return ERROR_CLASS_HTTP_STATUS.to_string();
+        }
+
+        source = e.source();
+    }
+
+    ERROR_CLASS_OTHER.to_string()
+}
+
+/// Which failure records to act on. Criteria left unset match every record.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FailureFilter {
+    /// The crawl the failed request belongs to.
+    pub crawl_id: Option<String>,
+
+    /// The subsystem of the failed request's operation, e.g. `Webs`.
+    pub subsystem: Option<String>,
+
+    /// The class of error the request failed with, e.g. `Dns` or `WebsMaintenance`.
+    pub error_class: Option<String>,
+}
+
+impl FailureFilter {
+    /// Indicates whether a record matches the filter.
+    pub fn matches(&self, record: &FailureRecord) -> bool {
+        let matches = |criterion: &Option<String>, value: Option<&str>| {
+            criterion.as_deref().is_none_or(|criterion| Some(criterion) == value)
+        };
+
+        matches(&self.crawl_id, record.request.crawl.crawl_id.as_deref())
+            && matches(&self.subsystem, Some(record.subsystem()))
+            && matches(&self.error_class, Some(&record.error_class))
+    }
+}
+
+/// Storage for failure records in DynamoDB.
+#[derive(Clone, Debug)]
+pub struct FailureStore {
+    ddb_client: DynamoDbClient,
+    table_name: String,
+}
+
+impl FailureStore {
+    /// Create a failure store using the given table.
+    pub fn new<S: Into<String>>(ddb_client: DynamoDbClient, table_name: S) -> Self {
+        Self {
+            ddb_client,
+            table_name: table_name.into(),
+        }
+    }
+
+    /// Return the name of the table failure records are stored in.
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    /// Save the record of a failed delivery and its index copy, replacing those of any earlier delivery of the same
+    /// message.
+    pub async fn record(&self, record: &FailureRecord) -> Result<(), BoxError> {
+        let now = Utc::now();
+
+        for item in [record.item(now)?, record.index_item(now)?] {
+            let result = self.ddb_client.put_item().table_name(&self.table_name).set_item(Some(item)).send().await;
+            if let Err(e) = result {
+                error!("PutItem failure record for message {}: {}", record.message_id, aws_err_str(&e));
+                return Err(e.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return the failure records matching `filter`.
+    ///
+    /// Records of a single crawl are queried from its partition; otherwise they are queried from the index partition.
+    pub async fn find(&self, filter: &FailureFilter) -> Result<Vec<FailureRecord>, BoxError> {
+        let partition_key = match filter.crawl_id.as_deref() {
+            Some(crawl_id) => format!("{FAILURE_PARTITION_PREFIX}{crawl_id}"),
+            None => FAILURE_INDEX_PARTITION_KEY.to_string(),
+        };
+        let mut records = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .ddb_client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("#pk = :pk")
+                .expression_attribute_names("#pk", DDB_KEY_CRAWL_ID)
+                .expression_attribute_values(":pk", AttributeValue::S(partition_key.clone()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await;
+            let output = match result {
+                Ok(output) => output,
+                Err(e) => {
+                    error!("Query failure records in {partition_key}: {}", aws_err_str(&e));
+                    return Err(e.into());
+                }
+            };
+
+            records.extend(output.items().iter().filter_map(FailureRecord::from_item).filter(|r| filter.matches(r)));
+
+            start_key = output.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(records);
+            }
+        }
+    }
+
+    /// Remove the record, and its index copy, of an earlier failed delivery of a message that has now succeeded.
+    pub async fn clear(&self, crawl_id: Option<&str>, message_id: &str) -> Result<(), BoxError> {
+        let crawl_id = crawl_id.unwrap_or(NO_CRAWL);
+        let keys = [
+            (format!("{FAILURE_PARTITION_PREFIX}{crawl_id}"), message_id.to_string()),
+            (FAILURE_INDEX_PARTITION_KEY.to_string(), index_sort_key(crawl_id, message_id)),
+        ];
+
+        for (partition_key, sort_key) in keys {
+            let result = self
+                .ddb_client
+                .delete_item()
+                .table_name(&self.table_name)
+                .key(DDB_KEY_CRAWL_ID, AttributeValue::S(partition_key))
+                .key(DDB_KEY_REQUEST_ID, AttributeValue::S(sort_key))
+                .send()
+                .await;
+            if let Err(e) = result {
+                error!("DeleteItem failure record for message {message_id}: {}", aws_err_str(&e));
+                return Err(e.into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Return the sort key of a failure record's copy in the index partition.
+fn index_sort_key(crawl_id: &str, message_id: &str) -> String {
+    format!("{crawl_id}#{message_id}")
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{error_class, FailureFilter, FailureRecord},
+        crate::{
+            retry::{RetriesExhaustedError, RetryDelay, RetryableError},
+            shapes::{CrawlParameters, Request},
+        },
+        chrono::{DateTime, Utc},
+        std::time::Duration,
+    };
+
+    #[test]
+    fn failure_items() {
+        let started_at = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut record = FailureRecord {
+            message_id: "message-1".to_string(),
+            receive_count: 3,
+            error: "WebsMaintenance at https://example.com/".to_string(),
+            error_class: "WebsMaintenance".to_string(),
+            started_at,
+            elapsed_ms: 1500,
+            last_response: Some(("bucket".to_string(), "logs/abcd".to_string())),
+            request: Request {
+                operation: "Webs:FetchOpportunityDetailPage".to_string(),
+                url: Some("https://example.com/".to_string()),
+                compare_to: None,
+                subsystem: None,
+                error_class: None,
+                s3_keys: vec![],
+                url_pattern: None,
+                redact_patterns: vec![],
+                frontier_key: None,
+                jurisdiction: None,
+                seen_from: None,
+                seen_until: None,
+                incremental: false,
+                crawl: CrawlParameters {
+                    crawl_id: Some("crawl-1".to_string()),
+                    ..CrawlParameters::default()
+                },
+            },
+        };
+
+        let item = record.item(started_at).unwrap();
+        assert_eq!(item["CrawlId"].as_s().unwrap().as_str(), "Failures#crawl-1");
+        assert_eq!(item["RequestId"].as_s().unwrap().as_str(), "message-1");
+        assert_eq!(item["Operation"].as_s().unwrap().as_str(), "Webs:FetchOpportunityDetailPage");
+        assert_eq!(item["ReceiveCount"].as_n().unwrap().as_str(), "3");
+        assert_eq!(item["LastS3Key"].as_s().unwrap().as_str(), "logs/abcd");
+        assert_eq!(item["ExpiresAt"].as_n().unwrap().as_str(), "1701209600");
+        let read = FailureRecord::from_item(&item).unwrap();
+        assert_eq!((read.started_at, read.error_class.as_str()), (started_at, "WebsMaintenance"));
+        assert_eq!(read.last_response, record.last_response);
+        assert_eq!(read.request.url.as_deref(), Some("https://example.com/"));
+
+        // The index copy sorts by crawl, and reads back as the same record.
+        let index_item = record.index_item(started_at).unwrap();
+        assert_eq!(index_item["CrawlId"].as_s().unwrap().as_str(), "FailureIndex");
+        assert_eq!(index_item["RequestId"].as_s().unwrap().as_str(), "crawl-1#message-1");
+        assert_eq!(FailureRecord::from_item(&index_item).unwrap().message_id, "message-1");
+
+        // Records are filtered by crawl, subsystem, and error class.
+        assert!(FailureFilter::default().matches(&record));
+        let filter = FailureFilter {
+            crawl_id: Some("crawl-1".to_string()),
+            subsystem: Some("Webs".to_string()),
+            error_class: Some("WebsMaintenance".to_string()),
+        };
+        assert!(filter.matches(&record));
+        assert!(!FailureFilter {
+            subsystem: Some("Merx".to_string()),
+            ..filter.clone()
+        }
+        .matches(&record));
+        assert!(!FailureFilter {
+            error_class: Some("Dns".to_string()),
+            ..filter
+        }
+        .matches(&record));
+
+        // Requests outside a crawl share a partition, and a failure before any fetch has no last response.
+        record.request.crawl.crawl_id = None;
+        record.last_response = None;
+        let item = record.item(started_at).unwrap();
+        assert_eq!(item["CrawlId"].as_s().unwrap().as_str(), "Failures#none");
+        assert!(!item.contains_key("LastS3Key"));
+    }
+
+    #[test]
+    fn error_classes() {
+        let retryable = RetryableError {
+            condition: "WebsMaintenance",
+            url: "https://example.com/".to_string(),
+            delay: RetryDelay::Fixed(Duration::from_secs(900)),
+        };
+        assert_eq!(error_class(&retryable), "WebsMaintenance");
+        let exhausted = RetriesExhaustedError {
+            history: vec![],
+            error: retryable,
+        };
+        assert_eq!(error_class(&exhausted), "WebsMaintenance");
+        assert_eq!(error_class(&*crate::BoxError::from("no listing table")), "Other");
+    }
+}
@@ -0,0 +1,355 @@
+//! Clustering of near-duplicate opportunities published on several portals.
+//!
+//! Agencies often post one solicitation on a state portal and on one or more regional cooperative portals, so the same
+//! opportunity is stored once per portal. The scheduled `Duplicates:Cluster` operation reads the open opportunities
+//! from the [opportunity store][crate::reconcile] and links opportunities on different portals that are the same
+//! solicitation. Two opportunities are taken to be the same if their due dates, when both have one, fall on the same
+//! day and either:
+//!
+//! * one of their documents resolved to the same [stored attachment][crate::attachment], by SHA-256 digest; or
+//! * the shingles (runs of [`SHINGLE_WORDS`] words) of their titles, references, and descriptions overlap by at least
+//!   [`SIMILARITY_THRESHOLD`] (Jaccard similarity).
+//!
+//! Attachments and shingles shared by more than [`MAX_SHARING`] opportunities, such as a state's standard terms and
+//! conditions, are boilerplate and ignored.
+//!
+//! Linked opportunities form clusters. The one published first (then the first by portal and URL) is the cluster's
+//! canonical opportunity; every other member's `DuplicateOf` is set to its URL, so consumers can alert on the canonical
+//! opportunity alone. Links that no longer hold are removed, and only changed links are written.
+use {
+    crate::{
+        httpext::LogConfig,
+        opportunity::Opportunity,
+        reconcile::RecordFilter,
+        shapes::{Request, Response},
+    },
+    chrono::NaiveDate,
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{hash_map::DefaultHasher, BTreeSet, HashMap, HashSet},
+        fmt::{Display, Formatter, Result as FmtResult},
+        hash::{Hash, Hasher},
+        str::FromStr,
+    },
+};
+
+const OP_CLUSTER: &str = "Cluster";
+
+/// The number of words in each shingle.
+pub const SHINGLE_WORDS: usize = 3;
+
+/// The least Jaccard similarity of two opportunities' shingles for them to be taken to be the same.
+pub const SIMILARITY_THRESHOLD: f64 = 0.7;
+
+/// The fewest shingles an opportunity's text must have to be compared; shorter texts, such as a bare title, match too
+/// easily.
+const MIN_SHINGLES: usize = 8;
+
+/// Attachments and shingles shared by more opportunities than this are boilerplate.
+pub const MAX_SHARING: usize = 50;
+
+/// The number of items read from the opportunity store at a time.
+const READ_PAGE_ITEMS: i32 = 200;
+
+/// Possible duplicate clustering operations.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum DuplicatesOperation {
+    /// Link the open opportunities that duplicate one on another portal.
+    Cluster,
+}
+
+impl FromStr for DuplicatesOperation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            OP_CLUSTER => Ok(Self::Cluster),
+            _ => Err(format!("Unknown operation: {value}")),
+        }
+    }
+}
+
+impl Display for DuplicatesOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.operation())
+    }
+}
+
+impl DuplicatesOperation {
+    /// Handle a request.
+    pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+        match self {
+            Self::Cluster => cluster(log_config, req, context).await,
+        }
+    }
+
+    /// Return the operation name within the subsystem.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::Cluster => OP_CLUSTER,
+        }
+    }
+}
+
+/// An open opportunity, reduced to what clustering compares.
+#[derive(Clone, Debug, Default)]
+pub struct Member {
+    /// The portal (subsystem) the opportunity was found on.
+    pub portal: String,
+
+    /// The URL of the opportunity's page.
+    pub url: String,
+
+    /// When the opportunity was published.
+    pub published_on: Option<NaiveDate>,
+
+    /// The day responses are due.
+    pub due_on: Option<NaiveDate>,
+
+    /// The SHA-256 digests of the attachments its documents resolved to.
+    pub attachments: BTreeSet<String>,
+
+    /// The hashed shingles of its text.
+    pub shingles: HashSet<u64>,
+
+    /// The URL of the opportunity it is currently linked to as a duplicate.
+    pub duplicate_of: Option<String>,
+}
+
+impl Member {
+    /// Reduce an opportunity with the given attachment digests to a member.
+    pub fn new(opportunity: &Opportunity, attachments: BTreeSet<String>) -> Self {
+        let text = [opportunity.title.as_str(), opportunity.reference.as_deref().unwrap_or_default()]
+            .into_iter()
+            .chain(opportunity.description.as_deref())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        Self {
+            portal: opportunity.portal.clone(),
+            url: opportunity.url.clone(),
+            published_on: opportunity.published_on,
+            due_on: opportunity.due_at.map(|due_at| due_at.date()),
+            attachments,
+            shingles: shingles(&text),
+            duplicate_of: opportunity.duplicate_of.clone(),
+        }
+    }
+
+    /// Return the key ordering the members of a cluster, the first being canonical: earliest published, then by portal
+    /// and URL.
+    fn canonical_key(&self) -> (bool, Option<NaiveDate>, &str, &str) {
+        (self.published_on.is_none(), self.published_on, &self.portal, &self.url)
+    }
+}
+
+/// Return the hashed shingles of `text`: every run of [`SHINGLE_WORDS`] consecutive words, ignoring case and
+/// punctuation.
+pub fn shingles(text: &str) -> HashSet<u64> {
+    let lowercase = text.to_lowercase();
+    let words: Vec<&str> = lowercase.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()).collect();
+
+    words
+        .windows(SHINGLE_WORDS)
+        .map(|shingle| {
+            let mut hasher = DefaultHasher::new();
+            shingle.hash(&mut hasher);
+            hasher.finish()
+        })
+        .collect()
+}
+
+/// Return the Jaccard similarity of two sets of shingles, or 0 if either is too short to compare.
+pub fn similarity(a: &HashSet<u64>, b: &HashSet<u64>) -> f64 {
+    if a.len() < MIN_SHINGLES || b.len() < MIN_SHINGLES {
+        return 0.0;
+    }
+
+    let shared = a.intersection(b).count();
+    shared as f64 / (a.len() + b.len() - shared) as f64
+}
+
+/// Indicates whether two members on different portals are the same solicitation, given the attachments they share
+/// outside of boilerplate.
+fn is_duplicate(a: &Member, b: &Member, shares_attachment: bool) -> bool {
+    if a.portal == b.portal {
+        return false;
+    }
+
+    if let (Some(a_due), Some(b_due)) = (a.due_on, b.due_on) {
+        if a_due != b_due {
+            return false;
+        }
+    }
+
+    shares_attachment || similarity(&a.shingles, &b.shingles) >= SIMILARITY_THRESHOLD
+}
+
+/// Find the root of a member in a disjoint-set forest, compressing the path to it.
+fn find(parents: &mut [usize], mut member: usize) -> usize {
+    while parents[member] != member {
+        parents[member] = parents[parents[member]];
+        member = parents[member];
+    }
+
+    member
+}
+
+/// Return, for each member, the index of the canonical member of its cluster, or `None` if it is canonical or has no
+/// duplicates.
+pub fn clusters(members: &[Member]) -> Vec<Option<usize>> {
+    // Only members sharing an attachment or a shingle are compared, skipping those too common to tell anything.
+    let mut by_attachment: HashMap<&str, Vec<usize>> = HashMap::new();
+    let mut by_shingle: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, member) in members.iter().enumerate() {
+        for attachment in member.attachments.iter() {
+            by_attachment.entry(attachment).or_default().push(i);
+        }
+        for shingle in member.shingles.iter() {
+            by_shingle.entry(*shingle).or_default().push(i);
+        }
+    }
+
+    let mut candidates: HashMap<(usize, usize), bool> = HashMap::new();
+    let postings = by_attachment
+        .values()
+        .map(|postings| (postings, true))
+        .chain(by_shingle.values().map(|postings| (postings, false)));
+    for (postings, attachment) in postings.filter(|(postings, _)| postings.len() <= MAX_SHARING) {
+        for (n, &a) in postings.iter().enumerate() {
+            for &b in postings[n + 1..].iter() {
+                *candidates.entry((a, b)).or_default() |= attachment;
+            }
+        }
+    }
+
+    let mut parents: Vec<usize> = (0..members.len()).collect();
+    for (&(a, b), &shares_attachment) in candidates.iter() {
+        if is_duplicate(&members[a], &members[b], shares_attachment) {
+            let (a, b) = (find(&mut parents, a), find(&mut parents, b));
+            parents[a] = b;
+        }
+    }
+
+    let mut canonical: HashMap<usize, usize> = HashMap::new();
+    for i in 0..members.len() {
+        let root = find(&mut parents, i);
+        let current = canonical.entry(root).or_insert(i);
+        if members[i].canonical_key() < members[*current].canonical_key() {
+            *current = i;
+        }
+    }
+
+    (0..members.len())
+        .map(|i| {
+            let canonical = canonical[&find(&mut parents, i)];
+            (canonical != i).then_some(canonical)
+        })
+        .collect()
+}
+
+/// Link the open opportunities that duplicate one on another portal, and unlink those that no longer do.
+async fn cluster(log_config: LogConfig, _req: Request, _context: Context) -> Result<Response, LambdaError> {
+    let mut members = Vec::new();
+    let mut start_key = None;
+
+    loop {
+        let page = log_config.opportunities.records(&RecordFilter::default(), start_key, READ_PAGE_ITEMS).await?;
+        for opportunity in page.records.iter().filter(|opportunity| opportunity.status.is_open()) {
+            let attachments = log_config
+                .attachments
+                .linked_documents(&opportunity.url)
+                .await?
+                .into_iter()
+                .map(|document| document.sha256)
+                .collect();
+            members.push(Member::new(opportunity, attachments));
+        }
+
+        start_key = page.next_key;
+        if start_key.is_none() {
+            break;
+        }
+    }
+
+    let canonical = clusters(&members);
+    let mut duplicates = 0;
+    let mut updated = 0;
+    for (member, canonical) in members.iter().zip(canonical) {
+        let duplicate_of = canonical.map(|canonical| members[canonical].url.as_str());
+        duplicates += usize::from(duplicate_of.is_some());
+        if member.duplicate_of.as_deref() == duplicate_of {
+            continue;
+        }
+
+        if let Some(duplicate_of) = duplicate_of {
+            debug!("Opportunity {} duplicates {duplicate_of}", member.url);
+        }
+
+        if log_config.opportunities.set_duplicate_of(&member.portal, &member.url, duplicate_of).await? {
+            updated += 1;
+        }
+    }
+
+    info!("Found {duplicates} duplicates among {} open opportunities; updated {updated} links", members.len());
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{clusters, shingles, similarity, Member},
+        crate::opportunity::Opportunity,
+        chrono::NaiveDate,
+        std::collections::BTreeSet,
+    };
+
+    #[test]
+    fn clustering() {
+        let description = "Resurfacing of Harrison Avenue between Division Street and Cooper Point Road, including \
+                           curb ramps, striping, and traffic control.";
+        let opportunity = |portal: &str, url: &str, title: &str, published: u32, due: u32| Opportunity {
+            portal: portal.to_string(),
+            url: url.to_string(),
+            title: title.to_string(),
+            description: Some(description.to_string()),
+            published_on: NaiveDate::from_ymd_opt(2024, 5, published),
+            due_at: NaiveDate::from_ymd_opt(2024, 6, due).unwrap().and_hms_opt(14, 0, 0),
+            ..Opportunity::default()
+        };
+        let member = |opportunity: &Opportunity, attachments: &[&str]| {
+            Member::new(opportunity, attachments.iter().map(|digest| digest.to_string()).collect::<BTreeSet<_>>())
+        };
+
+        let state = opportunity("Webs", "https://webs/1", "Harrison Avenue Paving", 2, 10);
+        let coop = opportunity("WaLocal", "https://coop/7", "HARRISON AVE. PAVING", 1, 10);
+        let rules = opportunity("Rules", "https://bids/3", "Unrelated title", 3, 10);
+        let rebid = opportunity("Rules", "https://bids/4", "Harrison Avenue Paving", 3, 20);
+
+        assert!(similarity(&Member::new(&state, BTreeSet::new()).shingles, &shingles(description)) > 0.7);
+        assert_eq!(similarity(&shingles("Harrison Avenue Paving"), &shingles("Harrison Avenue Paving")), 0.0);
+
+        let members = [
+            member(&state, &[]),
+            member(&coop, &[]),
+            member(
+                &Opportunity {
+                    description: Some("See attached.".to_string()),
+                    ..rules
+                },
+                &["ab12"],
+            ),
+            member(&rebid, &[]),
+            member(&opportunity("Webs", "https://webs/2", "Harrison Avenue Paving", 1, 10), &["ab12"]),
+        ];
+
+        // The cooperative portal published first, so it is canonical. The rules portal's posting shares an attachment
+        // with a second WEBS posting that is due the same day; the rebid is due later.
+        assert_eq!(clusters(&members), [Some(1), None, Some(1), None, Some(1)]);
+    }
+}
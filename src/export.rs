@@ -0,0 +1,326 @@
+//! Bulk export of stored opportunities to S3 as newline-delimited JSON.
+//!
+//! `Export:Opportunities` reads the full records kept in the [opportunity store][crate::reconcile] a page at a time,
+//! querying a single portal's partition when the request names one (`Subsystem`) and each crawled portal's otherwise,
+//! and keeps those last seen within `SeenFrom`..=`SeenUntil` whose organization contains `Jurisdiction`, ignoring case.
+//! Records are streamed into part objects of at most [`PART_RECORDS`] lines each under
+//! `exports/opportunities/{export id}/`, and once every part is written, an [`ExportManifest`] listing them is written
+//! beside them as `manifest.json`. Batch consumers should wait for the manifest and read the parts it lists rather
+//! than reading DynamoDB themselves.
+use {
+    crate::{
+        classification::{Classification, ObjectTags},
+        httpext::{log_aws_err, LogConfig},
+        opportunity::Opportunity,
+        reconcile::RecordFilter,
+        shapes::{Request, Response},
+        BoxError,
+    },
+    aws_sdk_s3::primitives::ByteStream,
+    chrono::{DateTime, SecondsFormat, Utc},
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    serde::{Deserialize, Serialize},
+    std::{
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+const OP_OPPORTUNITIES: &str = "Opportunities";
+
+/// Prefix of opportunity exports, relative to the S3 prefix; the export id follows it.
+const EXPORT_S3_PREFIX: &str = "exports/opportunities/";
+
+/// Name of an export's manifest, within the export's prefix.
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// The most records written to one part object.
+pub const PART_RECORDS: usize = 10_000;
+
+/// The most items read from DynamoDB in one request. Small pages spread the export's reads out, leaving capacity for
+/// the crawlers.
+const READ_PAGE_ITEMS: i32 = 200;
+
+/// Possible export operations.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum ExportOperation {
+    /// Export stored opportunities to S3.
+    Opportunities,
+}
+
+impl FromStr for ExportOperation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            OP_OPPORTUNITIES => Ok(Self::Opportunities),
+            _ => Err(format!("Unknown operation: {value}")),
+        }
+    }
+}
+
+impl Display for ExportOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.operation())
+    }
+}
+
+impl ExportOperation {
+    /// Handle a request.
+    pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+        match self {
+            Self::Opportunities => export_opportunities(log_config, req, context).await,
+        }
+    }
+
+    /// Return the operation name within the subsystem.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::Opportunities => OP_OPPORTUNITIES,
+        }
+    }
+}
+
+/// Which opportunities an export holds.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ExportFilter {
+    /// The filter applied to the opportunity store.
+    #[serde(flatten)]
+    pub records: RecordFilter,
+
+    /// Only export opportunities whose organization contains this text, ignoring case.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jurisdiction: Option<String>,
+}
+
+impl ExportFilter {
+    /// Return the filter a request asks for.
+    pub fn from_request(req: &Request) -> Self {
+        Self {
+            records: RecordFilter {
+                portal: req.subsystem.clone(),
+                seen_from: req.seen_from,
+                seen_until: req.seen_until,
+            },
+            jurisdiction: req.jurisdiction.clone(),
+        }
+    }
+
+    /// Indicates whether a stored opportunity belongs in the export. The opportunity store has already applied
+    /// [`records`][Self::records].
+    pub fn matches(&self, opportunity: &Opportunity) -> bool {
+        let Some(jurisdiction) = self.jurisdiction.as_deref() else {
+            return true;
+        };
+
+        opportunity
+            .organization
+            .as_deref()
+            .is_some_and(|organization| organization.to_lowercase().contains(&jurisdiction.to_lowercase()))
+    }
+}
+
+/// The manifest of a completed export.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ExportManifest {
+    /// The export's id: when it started, in RFC 3339 form.
+    pub export_id: String,
+
+    /// When the last part was written.
+    pub completed_at: Option<DateTime<Utc>>,
+
+    /// Which opportunities the export holds.
+    pub filter: ExportFilter,
+
+    /// The part objects, in order.
+    pub parts: Vec<ExportPart>,
+
+    /// The number of records exported.
+    pub records: usize,
+
+    /// The number of stored opportunities without a full record, which couldn't be exported.
+    pub skipped: usize,
+}
+
+/// A part object of an export.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ExportPart {
+    /// The S3 key of the part.
+    pub key: String,
+
+    /// The number of records (lines) in the part.
+    pub records: usize,
+}
+
+impl ExportManifest {
+    /// Return the S3 key prefix of the export's objects, relative to the S3 prefix.
+    pub fn prefix(&self) -> String {
+        format!("{EXPORT_S3_PREFIX}{}/", self.export_id)
+    }
+
+    /// Return the S3 key, relative to the S3 prefix, of the next part to write.
+    fn next_part_key(&self) -> String {
+        format!("{}part-{:05}.ndjson", self.prefix(), self.parts.len())
+    }
+}
+
+/// Append an opportunity to the newline-delimited JSON of a part.
+fn append_line(part: &mut Vec<u8>, opportunity: &Opportunity) -> Result<(), BoxError> {
+    serde_json::to_writer(&mut *part, opportunity)?;
+    part.push(b'\n');
+    Ok(())
+}
+
+/// Write an object of an export to S3, returning its full key.
+async fn put_export_object(
+    log_config: &LogConfig,
+    key: &str,
+    content_type: &str,
+    body: Vec<u8>,
+) -> Result<String, BoxError> {
+    let key = format!("{}{key}", log_config.s3_prefix);
+    let tags = ObjectTags::new(Classification::ExtractedOpportunity);
+
+    log_aws_err(
+        log_config
+            .s3_client
+            .put_object()
+            .bucket(&log_config.s3_bucket)
+            .key(&key)
+            .content_type(content_type)
+            .tagging(tags.to_tagging())
+            .body(ByteStream::from(body))
+            .send()
+            .await,
+        &format!("PutObject s3://{}/{key}", log_config.s3_bucket),
+    )?;
+
+    Ok(key)
+}
+
+/// Write the records buffered in `part` as the export's next part, if there are any.
+async fn flush_part(
+    log_config: &LogConfig,
+    manifest: &mut ExportManifest,
+    part: &mut Vec<u8>,
+    records: &mut usize,
+) -> Result<(), BoxError> {
+    if *records == 0 {
+        return Ok(());
+    }
+
+    let key =
+        put_export_object(log_config, &manifest.next_part_key(), "application/x-ndjson", std::mem::take(part)).await?;
+    manifest.parts.push(ExportPart {
+        key,
+        records: *records,
+    });
+    *records = 0;
+    Ok(())
+}
+
+/// Export the stored opportunities matching the request's filter to S3.
+async fn export_opportunities(log_config: LogConfig, req: Request, _context: Context) -> Result<Response, LambdaError> {
+    let mut manifest = ExportManifest {
+        export_id: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        filter: ExportFilter::from_request(&req),
+        ..ExportManifest::default()
+    };
+
+    let filter = manifest.filter.clone();
+    let mut part = Vec::new();
+    let mut part_records = 0;
+    let mut start_key = None;
+
+    loop {
+        let page = log_config.opportunities.records(&filter.records, start_key, READ_PAGE_ITEMS).await?;
+        manifest.skipped += page.skipped;
+
+        for opportunity in page.records.iter().filter(|opportunity| filter.matches(opportunity)) {
+            append_line(&mut part, opportunity)?;
+            part_records += 1;
+            manifest.records += 1;
+
+            if part_records == PART_RECORDS {
+                flush_part(&log_config, &mut manifest, &mut part, &mut part_records).await?;
+            }
+        }
+
+        start_key = page.next_key;
+        if start_key.is_none() {
+            break;
+        }
+    }
+
+    flush_part(&log_config, &mut manifest, &mut part, &mut part_records).await?;
+    manifest.completed_at = Some(Utc::now());
+    let manifest_key = format!("{}{MANIFEST_NAME}", manifest.prefix());
+    let manifest_key =
+        put_export_object(&log_config, &manifest_key, "application/json", serde_json::to_vec(&manifest)?).await?;
+
+    info!(
+        "Exported {} opportunities in {} parts to s3://{}/{manifest_key} ({} without records skipped)",
+        manifest.records,
+        manifest.parts.len(),
+        log_config.s3_bucket,
+        manifest.skipped
+    );
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{append_line, ExportFilter, ExportManifest, ExportPart},
+        crate::{opportunity::Opportunity, reconcile::RecordFilter},
+        chrono::NaiveDate,
+    };
+
+    #[test]
+    fn manifests_and_parts() {
+        let filter = ExportFilter {
+            records: RecordFilter {
+                portal: Some("WaLocal".to_string()),
+                seen_from: NaiveDate::from_ymd_opt(2024, 5, 1),
+                seen_until: None,
+            },
+            jurisdiction: Some("olympia".to_string()),
+        };
+        let olympia = Opportunity {
+            title: "Paving".to_string(),
+            organization: Some("City of Olympia".to_string()),
+            ..Opportunity::default()
+        };
+        assert!(filter.matches(&olympia));
+        assert!(!filter.matches(&Opportunity::default()));
+        assert!(ExportFilter::default().matches(&Opportunity::default()));
+
+        let mut part = Vec::new();
+        append_line(&mut part, &olympia).unwrap();
+        append_line(&mut part, &olympia).unwrap();
+        let lines: Vec<Opportunity> =
+            String::from_utf8(part).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines, [olympia.clone(), olympia]);
+
+        let mut manifest = ExportManifest {
+            export_id: "2024-05-02T00:00:00.000Z".to_string(),
+            filter,
+            ..ExportManifest::default()
+        };
+        assert_eq!(manifest.next_part_key(), "exports/opportunities/2024-05-02T00:00:00.000Z/part-00000.ndjson");
+        manifest.parts.push(ExportPart::default());
+        assert!(manifest.next_part_key().ends_with("/part-00001.ndjson"));
+
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert_eq!(json["Filter"]["Portal"], "WaLocal");
+        assert_eq!(json["Filter"]["SeenFrom"], "2024-05-01");
+        assert_eq!(json["Filter"]["Jurisdiction"], "olympia");
+    }
+}
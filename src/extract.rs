@@ -0,0 +1,229 @@
+//! Extraction of the text of opportunity attachments.
+//!
+//! When an attachment is [linked][crate::attachment::record_attachment], its text is scanned for
+//! [contacts][crate::contacts] and added to the opportunity's [search document][crate::search]:
+//!
+//! * plain text attachments are read as they are;
+//! * PDFs are read from their text layer with `pdf-extract`; and
+//! * PDFs without a text layer, typically scanned solicitations, are sent to Amazon Textract for text detection if
+//!   `ATTACHMENT_OCR` is `true`. Textract reads the stored copy from the log bucket, so bodies stored compressed or
+//!   encrypted can't be read this way and are skipped. Detection is billed by the page, so it is off by default.
+//!
+//! Office documents and archives aren't read.
+use {
+    crate::{
+        httpext::{
+            aws_err_str, BodyCompression, ConfigError, ContentKind, LogConfig, Response as HttpResponse, StoredBody,
+        },
+        BoxError,
+    },
+    aws_sdk_textract::types::{BlockType, DocumentLocation, JobStatus, S3Object},
+    log::*,
+    std::time::Duration,
+};
+
+const ENV_ATTACHMENT_OCR: &str = "ATTACHMENT_OCR";
+
+/// The fewest letters and digits a PDF's text layer must have to be used; below this, the PDF is taken to be scanned.
+/// Scanned PDFs often carry a few stray characters such as page numbers or a stamp.
+const MIN_TEXT_LAYER_CHARS: usize = 64;
+
+/// How often to ask Textract whether text detection has finished.
+const OCR_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long to wait for Textract to finish before giving up on an attachment.
+const OCR_MAX_WAIT: Duration = Duration::from_secs(300);
+
+/// Read whether scanned PDFs are sent for text detection from `ATTACHMENT_OCR`, looking it up with `lookup`. An
+/// invalid value is recorded in `errors`.
+pub(crate) fn ocr_enabled<F: Fn(&str) -> Option<String>>(lookup: &F, errors: &mut ConfigError) -> bool {
+    match lookup(ENV_ATTACHMENT_OCR) {
+        Some(value) => value.parse().unwrap_or_else(|_| {
+            errors.push(ENV_ATTACHMENT_OCR, format!("{value:?} is not true or false"));
+            false
+        }),
+        None => false,
+    }
+}
+
+/// Return the text of an attachment, or `None` if it has none that can be read. Failures are logged.
+pub async fn attachment_text(log_config: &LogConfig, response: &HttpResponse) -> Option<String> {
+    match response.content_kind() {
+        ContentKind::Unknown => plain_text(response),
+        ContentKind::Pdf => {
+            let text = pdf_text(response).await;
+            if text.as_deref().is_some_and(has_text) {
+                return text;
+            }
+
+            let stored = response.stored().filter(|_| log_config.attachment_ocr)?;
+            match detect_text(log_config, stored).await {
+                Ok(text) => Some(text).filter(|text| has_text(text)),
+                Err(e) => {
+                    warn!("Failed to detect the text of {}: {e}", response.url());
+                    None
+                }
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Return the body of a plain text attachment, unless it is binary data.
+fn plain_text(response: &HttpResponse) -> Option<String> {
+    response.text().ok().filter(|text| !text.contains('\0')).map(str::to_string)
+}
+
+/// Return the text layer of a PDF. Malformed PDFs can make the extractor panic, so it runs on a blocking thread whose
+/// panic is reported as an error.
+async fn pdf_text(response: &HttpResponse) -> Option<String> {
+    let body = response.bytes();
+    match tokio::task::spawn_blocking(move || pdf_extract::extract_text_from_mem(&body)).await {
+        Ok(Ok(text)) => Some(text),
+        Ok(Err(e)) => {
+            warn!("Failed to read the text of PDF {}: {e}", response.url());
+            None
+        }
+        Err(e) => {
+            warn!("Failed to read the text of PDF {}: {e}", response.url());
+            None
+        }
+    }
+}
+
+/// Whether `text` has enough letters and digits to be a document's text rather than stray marks.
+fn has_text(text: &str) -> bool {
+    text.chars().filter(|c| c.is_alphanumeric()).nth(MIN_TEXT_LAYER_CHARS - 1).is_some()
+}
+
+/// Detect the text of a stored PDF with Textract, returning its lines.
+async fn detect_text(log_config: &LogConfig, stored: &StoredBody) -> Result<String, BoxError> {
+    if stored.compression != BodyCompression::None || stored.kms_key_id.is_some() {
+        return Err(format!("Stored body {} is compressed or encrypted", stored.key).into());
+    }
+
+    let location = DocumentLocation::builder()
+        .s3_object(S3Object::builder().bucket(&stored.bucket).name(&stored.key).build())
+        .build();
+    let result = log_config.textract_client.start_document_text_detection().document_location(location).send().await;
+    let job_id = match result {
+        Ok(output) => output.job_id.ok_or("Textract returned no job id")?,
+        Err(e) => {
+            error!("StartDocumentTextDetection for {}: {}", stored.key, aws_err_str(&e));
+            return Err(e.into());
+        }
+    };
+
+    let mut lines = Vec::new();
+    let mut next_token = None;
+    let mut waited = Duration::ZERO;
+    loop {
+        let result = log_config
+            .textract_client
+            .get_document_text_detection()
+            .job_id(&job_id)
+            .set_next_token(next_token.take())
+            .send()
+            .await;
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => {
+                error!("GetDocumentTextDetection for {}: {}", stored.key, aws_err_str(&e));
+                return Err(e.into());
+            }
+        };
+
+        match output.job_status() {
+            Some(JobStatus::InProgress) => {
+                if waited >= OCR_MAX_WAIT {
+                    return Err(format!("Text detection job {job_id} did not finish in {OCR_MAX_WAIT:?}").into());
+                }
+                tokio::time::sleep(OCR_POLL_INTERVAL).await;
+                waited += OCR_POLL_INTERVAL;
+                continue;
+            }
+            Some(JobStatus::Succeeded) => (),
+            Some(JobStatus::PartialSuccess) => {
+                warn!("Text detection job {job_id} only read part of {}", stored.key);
+            }
+            status => {
+                let message = output.status_message().unwrap_or_default();
+                return Err(format!("Text detection job {job_id} ended as {status:?}: {message}").into());
+            }
+        }
+
+        lines.extend(
+            output
+                .blocks()
+                .iter()
+                .filter(|block| block.block_type() == Some(&BlockType::Line))
+                .filter_map(|block| block.text())
+                .map(str::to_string),
+        );
+        match output.next_token() {
+            Some(token) => next_token = Some(token.to_string()),
+            None => break,
+        }
+    }
+
+    info!("Detected {} line(s) of text in {}", lines.len(), stored.key);
+    Ok(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{has_text, ocr_enabled},
+        crate::httpext::ConfigError,
+    };
+
+    /// Build a single-page PDF showing `text` in Helvetica.
+    fn pdf(text: &str) -> Vec<u8> {
+        let content = format!("BT /F1 12 Tf 72 720 Td ({text}) Tj ET");
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Contents 4 0 R \
+             /Resources << /Font << /F1 5 0 R >> >> >>"
+                .to_string(),
+            format!("<< /Length {} >>\nstream\n{content}\nendstream", content.len()),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+        ];
+
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = vec![];
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend(format!("{} 0 obj\n{object}\nendobj\n", i + 1).as_bytes());
+        }
+
+        let xref = pdf.len();
+        pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+        for offset in offsets {
+            pdf.extend(format!("{offset:010} 00000 n \n").as_bytes());
+        }
+        pdf.extend(
+            format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n", objects.len() + 1).as_bytes(),
+        );
+        pdf
+    }
+
+    #[test]
+    fn text_layers() {
+        let text = pdf_extract::extract_text_from_mem(&pdf("Asphalt paving of Main Street, Phase 2")).unwrap();
+        assert!(text.contains("Asphalt paving of Main Street, Phase 2"));
+
+        assert!(!has_text("  Page 1 of 12  "));
+        assert!(has_text(&"Bid schedule ".repeat(8)));
+    }
+
+    #[test]
+    fn ocr_setting() {
+        let mut errors = ConfigError::default();
+        assert!(!ocr_enabled(&|_| None, &mut errors));
+        assert!(ocr_enabled(&|_| Some("true".to_string()), &mut errors));
+        assert!(errors.problems.is_empty());
+        assert!(!ocr_enabled(&|_| Some("scanned".to_string()), &mut errors));
+        assert_eq!(errors.problems[0].var, "ATTACHMENT_OCR");
+    }
+}
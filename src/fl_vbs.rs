@@ -0,0 +1,319 @@
+//! Request/response types for the Florida Vendor Bid System (VBS), the public advertisement board of
+//! MyFloridaMarketPlace.
+//!
+//! VBS needs no login: the advertisement search is paged with plain GET requests, and each advertisement has its own
+//! page listing its details and downloadable files.
+mod advertisement;
+mod listing;
+
+use {
+    crate::{
+        attachment, closure, contacts, health,
+        httpext::{Client, LogConfig, ResponseExt},
+        journal::CrawlEvent,
+        lifecycle,
+        opportunity::Opportunity,
+        quality,
+        shapes::{CrawlParameters, ListingQuery, NextRequest, Operation, Request, Response, SUBSYS_FL_VBS},
+        soup::parse_html_str,
+        BoxError,
+    },
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    reqwest::Url,
+    serde::{Deserialize, Serialize},
+    std::{
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+pub(crate) const DEFAULT_VBS_BASE_URL: &str = "https://www.myflorida.com/apps/vbs/";
+const SEARCH_PATH: &str = "vbs_www.search_r2.matching_ads_page";
+const ADV_TYPE_PARAM: &str = "search_by_adv_type";
+const ALL_ADV_TYPES: &str = "ALL";
+const PAGE_PARAM: &str = "pi_page";
+pub(crate) const AD_PATH: &str = "vbs_www.ad_r2.view_ad";
+
+const OP_START_CRAWL: &str = "StartCrawl";
+const OP_FETCH_ADVERTISEMENT_LISTING_PAGE: &str = "FetchAdvertisementListingPage";
+const OP_FETCH_ADVERTISEMENT_PAGE: &str = "FetchAdvertisementPage";
+
+/// Version of this subsystem's parsers. Bump it whenever a parser change alters what is extracted.
+pub(crate) const PARSER_VERSION: u32 = 1;
+
+const PARSER_ADVERTISEMENT_LISTING: &str = "FlVbs:AdvertisementListing";
+const PARSER_ADVERTISEMENT: &str = "FlVbs:Advertisement";
+
+/// Phrases shown in place of an opportunity the portal no longer publishes; see [`closure`][crate::closure].
+const NOT_FOUND_MARKERS: &[&str] = &["advertisement not found", "this advertisement is no longer available"];
+
+/// Possible operations for the Florida Vendor Bid System.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum FlVbsOperation {
+    /// Start a crawl by fetching the first page of advertisement search results.
+    StartCrawl,
+
+    /// Fetch a subsequent page of advertisement search results.
+    FetchAdvertisementListingPage,
+
+    /// Fetch an advertisement page.
+    FetchAdvertisementPage,
+}
+
+impl FromStr for FlVbsOperation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            OP_START_CRAWL => Ok(Self::StartCrawl),
+            OP_FETCH_ADVERTISEMENT_LISTING_PAGE => Ok(Self::FetchAdvertisementListingPage),
+            OP_FETCH_ADVERTISEMENT_PAGE => Ok(Self::FetchAdvertisementPage),
+            _ => Err(format!("Unknown operation: {value}")),
+        }
+    }
+}
+
+impl Display for FlVbsOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.operation())
+    }
+}
+
+impl FlVbsOperation {
+    /// Handle a request.
+    pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+        match self {
+            Self::StartCrawl => start_crawl(log_config, req, context).await,
+            Self::FetchAdvertisementListingPage => {
+                let url = req.required_url()?;
+                fetch_listing_page(&log_config, &req.crawl, &context, &url).await.map(|(response, _)| response)
+            }
+            Self::FetchAdvertisementPage => fetch_advertisement_page(log_config, req, context).await,
+        }
+    }
+
+    /// Return the operation name within the subsystem.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::StartCrawl => OP_START_CRAWL,
+            Self::FetchAdvertisementListingPage => OP_FETCH_ADVERTISEMENT_LISTING_PAGE,
+            Self::FetchAdvertisementPage => OP_FETCH_ADVERTISEMENT_PAGE,
+        }
+    }
+}
+
+/// Return the default advertisement search URL, listing every open advertisement.
+fn default_search_url() -> Url {
+    let url = Url::parse(DEFAULT_VBS_BASE_URL)
+        .and_then(|base| base.join(SEARCH_PATH))
+        .expect("DEFAULT_VBS_BASE_URL and SEARCH_PATH form a valid URL");
+    ListingQuery::new().filter(ADV_TYPE_PARAM, ALL_ADV_TYPES).page(PAGE_PARAM, 1).url(&url)
+}
+
+/// Start a VBS crawl from the first page of search results.
+///
+/// A crawl whose first page lists no advertisements almost certainly means the markup has changed, so the crawl's
+/// health is checked here rather than after the last page.
+async fn start_crawl(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let url = match req.url.as_deref() {
+        Some(url) => Url::parse(url)?,
+        None => default_search_url(),
+    };
+
+    let (response, client) = fetch_listing_page(&log_config, &req.crawl, &context, &url).await?;
+    let advertisements = response
+        .next_requests
+        .iter()
+        .filter(|r| matches!(r.operation, Operation::FlVbs(FlVbsOperation::FetchAdvertisementPage)))
+        .count();
+
+    health::check_crawl_health(
+        &log_config,
+        SUBSYS_FL_VBS,
+        &client.crawl_id,
+        &client.stats.snapshot(),
+        &client.stats.host_latencies(),
+        advertisements,
+    )
+    .await;
+
+    Ok(response)
+}
+
+/// Fetch a page of search results, returning requests for its advertisements and the next page.
+async fn fetch_listing_page(
+    log_config: &LogConfig,
+    crawl: &CrawlParameters,
+    context: &Context,
+    url: &Url,
+) -> Result<(Response, Client), BoxError> {
+    let client = crawl.portal_client(log_config, context, SUBSYS_FL_VBS, DEFAULT_VBS_BASE_URL, url)?.build()?;
+
+    let response = match client.get(url.clone()).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch VBS search results: {e}");
+            return Err(e);
+        }
+    };
+
+    let document = parse_html_str(response.text()?);
+    let result = listing::parse_advertisement_listing_page(&document, response.url());
+    let event = match &result {
+        Ok(urls) => CrawlEvent::ParseSucceeded {
+            url: response.url().to_string(),
+            parser: PARSER_ADVERTISEMENT_LISTING.to_string(),
+            items: urls.len(),
+        },
+        Err(e) => CrawlEvent::ParseFailed {
+            url: response.url().to_string(),
+            parser: PARSER_ADVERTISEMENT_LISTING.to_string(),
+            error: e.to_string(),
+        },
+    };
+    log_config.journal.record(&client.crawl_id, event).await;
+
+    let mut next_requests: Vec<NextRequest> = result?
+        .into_iter()
+        .map(|url| NextRequest {
+            operation: Operation::FlVbs(FlVbsOperation::FetchAdvertisementPage),
+            url: Some(url.to_string()),
+            link_text: None,
+            crawl: crawl.child(),
+            delay_seconds: 0,
+        })
+        .collect();
+
+    next_requests.extend(listing::PAGINATION.continuation(
+        Operation::FlVbs(FlVbsOperation::FetchAdvertisementListingPage),
+        &document,
+        response.url(),
+        crawl,
+    ));
+
+    Ok((
+        Response {
+            next_requests,
+        },
+        client,
+    ))
+}
+
+/// Fetch an advertisement page and parse it into the normalized opportunity model.
+async fn fetch_advertisement_page(
+    log_config: LogConfig,
+    req: Request,
+    context: Context,
+) -> Result<Response, LambdaError> {
+    let url = req.required_url()?;
+    let client = req.crawl.portal_client(&log_config, &context, SUBSYS_FL_VBS, DEFAULT_VBS_BASE_URL, &url)?.build()?;
+
+    let response = match client.get(url.clone()).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch VBS advertisement: {e}");
+            return Err(e);
+        }
+    };
+
+    let Some(response) = attachment::html_page(&log_config, &req.crawl, response, Some(url.as_str())).await else {
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    };
+
+    if let Some(reason) =
+        closure::soft_not_found(response.text()?, NOT_FOUND_MARKERS, &req.crawl.locale.not_found_markers)
+    {
+        closure::record_closed(&log_config, &client, SUBSYS_FL_VBS, url.as_str(), &reason).await?;
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    }
+
+    let document = parse_html_str(response.text()?);
+    let result = advertisement::parse_advertisement_page(&document, response.url(), req.crawl.locale.dates);
+    let event = match &result {
+        Ok(_) => CrawlEvent::ParseSucceeded {
+            url: response.url().to_string(),
+            parser: PARSER_ADVERTISEMENT.to_string(),
+            items: 1,
+        },
+        Err(e) => CrawlEvent::ParseFailed {
+            url: response.url().to_string(),
+            parser: PARSER_ADVERTISEMENT.to_string(),
+            error: e.to_string(),
+        },
+    };
+    log_config.journal.record(&client.crawl_id, event).await;
+
+    let mut opportunity: Opportunity =
+        result?.with_parser(PARSER_ADVERTISEMENT, PARSER_VERSION).with_tenant(client.tenant.as_deref());
+    contacts::record_contacts(&log_config, &client, &mut opportunity, response.text()?).await;
+    lifecycle::record_stage(&log_config, &client, &mut opportunity, response.text()?).await;
+    quality::record_quality(&log_config, &client, &mut opportunity).await;
+    closure::record_seen(&log_config, &client, &opportunity).await;
+    info!("VBS opportunity: {}", serde_json::to_string(&opportunity)?);
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Parse a VBS snapshot for golden-file testing. `parser` is the name of the snapshot directory.
+#[cfg(any(test, feature = "test-utils"))]
+pub fn parse_snapshot(parser: &str, text: &str) -> Result<serde_json::Value, BoxError> {
+    use {crate::opportunity::DateLocale, serde_json::json};
+
+    let base_url = Url::parse(DEFAULT_VBS_BASE_URL)?;
+    let document = parse_html_str(text);
+
+    match parser {
+        "advertisement-listing" => {
+            let page_url = base_url.join(SEARCH_PATH)?;
+            let urls = listing::parse_advertisement_listing_page(&document, &page_url)?;
+            let next_page = listing::PAGINATION.next_url(&document, &page_url);
+
+            Ok(json!({
+                "Advertisements": urls.iter().map(Url::as_str).collect::<Vec<_>>(),
+                "NextPage": next_page.as_ref().map(Url::as_str),
+            }))
+        }
+        "advertisement" => {
+            let page_url = base_url.join(AD_PATH)?;
+            Ok(serde_json::to_value(advertisement::parse_advertisement_page(
+                &document,
+                &page_url,
+                DateLocale::default(),
+            )?)?)
+        }
+        _ => Err(format!("Unknown VBS snapshot parser {parser}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{default_search_url, parse_snapshot},
+        crate::{
+            golden::{check_snapshots, snapshot_dir},
+            shapes::SUBSYS_FL_VBS,
+        },
+    };
+
+    #[test_log::test]
+    fn golden_snapshots() {
+        let checked = check_snapshots(&snapshot_dir(&SUBSYS_FL_VBS.to_lowercase()), parse_snapshot)
+            .unwrap_or_else(|e| panic!("Golden snapshot mismatch:\n{e}"));
+        assert!(checked >= 2);
+    }
+
+    #[test]
+    fn search_url() {
+        assert_eq!(
+            default_search_url().as_str(),
+            "https://www.myflorida.com/apps/vbs/vbs_www.search_r2.matching_ads_page?pi_page=1&search_by_adv_type=ALL"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! VBS advertisement page handling.
+//!
+//! The advertisement is a table of labelled rows (`<th>Agency:</th><td>...</td>`), followed by a table of
+//! downloadable files. Multi-line values such as the agency contact and the commodity codes are separated by `<br>`
+//! tags.
+use {
+    crate::{
+        award::{page_awards, US_DOLLARS},
+        opportunity::{clean_text, parse_date_time, text_lines, Contact, DateLocale, Document, Opportunity},
+        shapes::SUBSYS_FL_VBS,
+        soup::{NodeExt, QueryBuilderExt},
+        BoxError,
+    },
+    log::*,
+    markup5ever_rcdom::{Handle, RcDom},
+    reqwest::Url,
+    std::collections::HashMap,
+};
+
+const LABEL_AGENCY: &str = "Agency";
+const LABEL_TITLE: &str = "Title";
+const LABEL_NUMBER: &str = "Advertisement Number";
+const LABEL_DESCRIPTION: &str = "Description";
+const LABEL_START: &str = "Start Date/Time";
+const LABEL_END: &str = "End Date/Time";
+const LABEL_CONTACT: &str = "Agency Contact";
+const LABEL_COMMODITY_CODES: &str = "Commodity Codes";
+
+/// Separator between a commodity code and its description.
+const COMMODITY_CODE_SEPARATOR: &str = " - ";
+
+/// Parse an advertisement page into the normalized model.
+///
+/// A page without a title is not an advertisement (usually an error page) and is an error.
+pub(crate) fn parse_advertisement_page(
+    document: &RcDom,
+    page_url: &Url,
+    date_locale: DateLocale,
+) -> Result<Opportunity, BoxError> {
+    let fields = labelled_fields(document);
+    let text = |label: &str| fields.get(label).and_then(|td| clean_text(&td.text()));
+    let lines = |label: &str| fields.get(label).map(text_lines).unwrap_or_default();
+
+    let Some(title) = text(LABEL_TITLE) else {
+        return Err(format!("VBS advertisement title not found on {page_url}").into());
+    };
+
+    let published_on =
+        text(LABEL_START).and_then(|start| parse_date_time(&start, date_locale)).map(|start| start.date());
+    let due_at = text(LABEL_END).and_then(|end| {
+        let due_at = parse_date_time(&end, date_locale);
+        if due_at.is_none() {
+            warn!("Unparseable VBS end date {end:?} on {page_url}");
+        }
+        due_at
+    });
+
+    let description = lines(LABEL_DESCRIPTION);
+    let commodity_codes = lines(LABEL_COMMODITY_CODES)
+        .into_iter()
+        .map(|line| match line.split_once(COMMODITY_CODE_SEPARATOR) {
+            Some((code, _)) => code.to_string(),
+            None => line,
+        })
+        .collect();
+
+    Ok(Opportunity {
+        portal: SUBSYS_FL_VBS.to_string(),
+        url: page_url.to_string(),
+        title,
+        reference: text(LABEL_NUMBER),
+        organization: text(LABEL_AGENCY),
+        contact: Contact::from_lines(&lines(LABEL_CONTACT)),
+        published_on,
+        due_at,
+        description: if description.is_empty() {
+            None
+        } else {
+            Some(description.join("\n"))
+        },
+        commodity_codes,
+        counties: vec![],
+        documents: parse_documents(document, page_url),
+        awards: page_awards(document, date_locale, US_DOLLARS),
+        ..Opportunity::default()
+    })
+}
+
+/// Return the value cells of the advertisement table, keyed by their label without the trailing colon.
+fn labelled_fields(document: &RcDom) -> HashMap<String, Handle> {
+    let mut fields = HashMap::new();
+    let Some(table) = document.tag("table").attr("summary", "Advertisement information").find() else {
+        return fields;
+    };
+
+    for tr in table.tag("tr").find_all() {
+        let (Some(th), Some(td)) = (tr.tag("th").find(), tr.tag("td").find()) else {
+            continue;
+        };
+
+        if let Some(label) = clean_text(&th.text()) {
+            fields.insert(label.trim_end_matches(':').to_string(), td);
+        }
+    }
+
+    fields
+}
+
+/// Return the downloadable files listed with the advertisement.
+fn parse_documents(document: &RcDom, page_url: &Url) -> Vec<Document> {
+    let Some(table) = document.tag("table").attr("summary", "Downloadable files").find() else {
+        return vec![];
+    };
+
+    table
+        .tag("a")
+        .find_all()
+        .filter_map(|a| {
+            let href = a.get("href")?;
+            let url = match page_url.join(&href) {
+                Ok(url) => url,
+                Err(e) => {
+                    warn!("Ignoring unparseable VBS document link {href}: {e}");
+                    return None;
+                }
+            };
+
+            Some(Document {
+                name: clean_text(&a.text()).unwrap_or_else(|| url.to_string()),
+                url: url.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::parse_advertisement_page,
+        crate::{opportunity::DateLocale, soup::parse_html_str},
+        chrono::NaiveDate,
+        reqwest::Url,
+    };
+
+    #[test]
+    fn advertisement_page() {
+        let url = Url::parse("https://www.myflorida.com/apps/vbs/vbs_www.ad_r2.view_ad?advertisement_key_num=168034")
+            .unwrap();
+        let opportunity = parse_advertisement_page(
+            &parse_html_str(include_str!("vbs-advertisement.html")),
+            &url,
+            DateLocale::English,
+        )
+        .unwrap();
+
+        assert_eq!(opportunity.title, "Janitorial Services - District Four");
+        assert_eq!(opportunity.reference.as_deref(), Some("RFQ-DOT-22/23-4002"));
+        assert_eq!(opportunity.organization.as_deref(), Some("Department of Transportation"));
+        assert_eq!(opportunity.contact.name.as_deref(), Some("Maria Lopez"));
+        assert_eq!(opportunity.contact.email.as_deref(), Some("maria.lopez@dot.state.fl.us"));
+        assert_eq!(opportunity.contact.phone.as_deref(), Some("(954) 777-4000"));
+        assert_eq!(opportunity.published_on, NaiveDate::from_ymd_opt(2022, 11, 1));
+        assert_eq!(opportunity.due_at, NaiveDate::from_ymd_opt(2022, 11, 15).unwrap().and_hms_opt(14, 0, 0));
+        assert_eq!(opportunity.description.unwrap().lines().count(), 2);
+        assert_eq!(opportunity.commodity_codes, vec!["76111500", "47131800"]);
+        assert_eq!(opportunity.documents.len(), 2);
+        assert_eq!(opportunity.documents[1].name, "Price Sheet.xlsx");
+        assert!(opportunity.documents[1].url.ends_with("view_ad_doc?advertisement_key_num=168034&doc_key_num=502"));
+
+        assert!(parse_advertisement_page(&parse_html_str("<p>Advertisement not found</p>"), &url, DateLocale::English)
+            .is_err());
+    }
+}
@@ -0,0 +1,74 @@
+//! VBS advertisement search results handling.
+//!
+//! Search results are a plain table with one advertisement per row, the title linking to the advertisement page. The
+//! results are paged with GET requests; each page links to the next with a "Next" link.
+use {
+    crate::{
+        fl_vbs::AD_PATH,
+        pagination::{NextLink, Pagination},
+        soup::{NodeExt, QueryBuilderExt},
+        BoxError,
+    },
+    log::*,
+    markup5ever_rcdom::RcDom,
+    reqwest::Url,
+};
+
+/// Each page of results links to the next with a "Next" link.
+pub(crate) const PAGINATION: Pagination = Pagination::NextLink(&[NextLink::Text(&["Next"])]);
+
+/// Return the URLs of the advertisements listed on a search results page, in order.
+pub(crate) fn parse_advertisement_listing_page(document: &RcDom, page_url: &Url) -> Result<Vec<Url>, BoxError> {
+    let Some(table) = document.tag("table").attr("summary", "Matching advertisements").find() else {
+        return Err(format!("VBS results table not found on {page_url}").into());
+    };
+
+    let mut urls = Vec::new();
+    for a in table.tag("a").find_all() {
+        let Some(href) = a.get("href") else {
+            continue;
+        };
+
+        if !href.contains(AD_PATH) {
+            debug!("Ignoring non-advertisement link {href} in VBS results");
+            continue;
+        }
+
+        match page_url.join(&href) {
+            Ok(url) => urls.push(url),
+            Err(e) => warn!("Ignoring unparseable VBS advertisement link {href}: {e}"),
+        }
+    }
+
+    Ok(urls)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{parse_advertisement_listing_page, PAGINATION},
+        crate::soup::parse_html_str,
+        reqwest::Url,
+    };
+
+    #[test]
+    fn listing_page() {
+        let page_url = Url::parse("https://www.myflorida.com/apps/vbs/vbs_www.search_r2.matching_ads_page").unwrap();
+        let document = parse_html_str(include_str!("vbs-search-results-page1.html"));
+
+        let urls = parse_advertisement_listing_page(&document, &page_url).unwrap();
+        assert_eq!(urls.len(), 4);
+        assert_eq!(
+            urls[0].as_str(),
+            "https://www.myflorida.com/apps/vbs/vbs_www.ad_r2.view_ad?advertisement_key_num=168034"
+        );
+
+        let next = PAGINATION.next_url(&document, &page_url).unwrap();
+        assert_eq!(next.query(), Some("search_by_adv_type=ALL&pi_page=2"));
+
+        let last_page = parse_html_str(r#"<table summary="Matching advertisements"></table><b>Page 15 of 15</b>"#);
+        assert!(parse_advertisement_listing_page(&last_page, &page_url).unwrap().is_empty());
+        assert!(PAGINATION.next_url(&last_page, &page_url).is_none());
+        assert!(parse_advertisement_listing_page(&parse_html_str("<p>Error</p>"), &page_url).is_err());
+    }
+}
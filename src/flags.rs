@@ -0,0 +1,166 @@
+//! Feature flags evaluated at dispatch time.
+//!
+//! Risky changes are shipped behind a [`Flag`] so they can be rolled out, and rolled back, without redeploying. The
+//! flags document is the JSON SSM parameter `Flags` under the SSM prefix; each section maps flag names to whether the
+//! flag is enabled:
+//!
+//! ```json
+//! {
+//!     "Defaults": {"AttachmentDownloads": true},
+//!     "Subsystems": {"Webs": {"WebsStructuredData": true}},
+//!     "Crawls": {"018f0c6e-...": {"AttachmentDownloads": false}}
+//! }
+//! ```
+//!
+//! A crawl's override wins over its subsystem's, which wins over the default section, which wins over the flag's
+//! built-in default. Names the code doesn't know are ignored, so a flag can be left in the document after the code
+//! reading it is removed.
+//!
+//! The [`FeatureFlagging`][crate::middleware::FeatureFlagging] middleware reads the document once per batch and
+//! resolves it for each operation as it is dispatched, storing the result in the operation's
+//! [`LogConfig::flags`][crate::httpext::LogConfig::flags]. The document is read through the [configuration
+//! watcher][crate::config::ConfigWatcher], so a warm environment picks up a change within its reload interval.
+use {
+    crate::{config::load_config_document, httpext::LogConfig, BoxError},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::BTreeMap,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+/// Name of the flags document parameter, relative to the SSM prefix.
+const SSM_FLAGS_PARAMETER: &str = "Flags";
+
+/// A feature that can be switched on or off at runtime.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Flag {
+    /// Download the documents published with opportunities. When disabled, `FetchDocument` operations return without
+    /// fetching anything. Enabled by default.
+    AttachmentDownloads,
+
+    /// Read WEBS opportunity detail pages' structured data before scraping them; see
+    /// [`parse_with_structured_data`][crate::opportunity::parse_with_structured_data]. Disabled by default.
+    WebsStructuredData,
+
+    /// While [`WebsStructuredData`][Self::WebsStructuredData] is disabled, run it in [shadow][crate::shadow]: parse
+    /// WEBS opportunity detail pages both ways, keep the current parser's output, and record how the two differ.
+    /// Disabled by default.
+    WebsStructuredDataShadow,
+
+    /// Crawl the portal's registered-vendor directory; see [`vendors`][crate::vendors]. Resolved for the portal whose
+    /// directory it is, so it is usually enabled in that portal's `Subsystems` section. Disabled by default.
+    VendorDirectories,
+}
+
+impl Flag {
+    /// Return the flag's name in the flags document.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::AttachmentDownloads => "AttachmentDownloads",
+            Self::WebsStructuredData => "WebsStructuredData",
+            Self::WebsStructuredDataShadow => "WebsStructuredDataShadow",
+            Self::VendorDirectories => "VendorDirectories",
+        }
+    }
+
+    /// Indicates whether the flag is enabled when the flags document doesn't mention it.
+    pub fn default_enabled(&self) -> bool {
+        match self {
+            Self::AttachmentDownloads => true,
+            Self::WebsStructuredData | Self::WebsStructuredDataShadow | Self::VendorDirectories => false,
+        }
+    }
+}
+
+impl Display for Flag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.name())
+    }
+}
+
+/// The flags document.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, rename_all = "PascalCase")]
+pub struct FlagsDocument {
+    /// Flag values for every operation.
+    pub defaults: BTreeMap<String, bool>,
+
+    /// Flag values for the operations of a subsystem, by subsystem.
+    pub subsystems: BTreeMap<String, BTreeMap<String, bool>>,
+
+    /// Flag values for the operations of a crawl, by crawl id.
+    pub crawls: BTreeMap<String, BTreeMap<String, bool>>,
+}
+
+impl FlagsDocument {
+    /// Return the flags in effect for an operation of `subsystem`, run as part of the crawl `crawl_id` if any.
+    pub fn resolve(&self, subsystem: &str, crawl_id: Option<&str>) -> FeatureFlags {
+        let mut values = self.defaults.clone();
+
+        if let Some(overrides) = self.subsystems.get(subsystem) {
+            values.extend(overrides.iter().map(|(name, enabled)| (name.clone(), *enabled)));
+        }
+
+        if let Some(overrides) = crawl_id.and_then(|crawl_id| self.crawls.get(crawl_id)) {
+            values.extend(overrides.iter().map(|(name, enabled)| (name.clone(), *enabled)));
+        }
+
+        FeatureFlags {
+            values,
+        }
+    }
+}
+
+/// The flags in effect for an operation.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FeatureFlags {
+    values: BTreeMap<String, bool>,
+}
+
+impl FeatureFlags {
+    /// Indicates whether a flag is enabled.
+    pub fn is_enabled(&self, flag: Flag) -> bool {
+        self.values.get(flag.name()).copied().unwrap_or_else(|| flag.default_enabled())
+    }
+}
+
+/// Load the flags document, or return an empty one if there is none.
+pub async fn load_flags(log_config: &LogConfig) -> Result<FlagsDocument, BoxError> {
+    load_config_document(log_config, SSM_FLAGS_PARAMETER, "feature flag").await
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{FeatureFlags, Flag, FlagsDocument},
+        crate::config::parse_config,
+    };
+
+    #[test]
+    fn resolution() {
+        let document: FlagsDocument = parse_config(
+            "feature flag",
+            r#"{"Defaults": {"WebsStructuredData": true, "Retired": true},
+                "Subsystems": {"Webs": {"AttachmentDownloads": false}, "BidNet": {"WebsStructuredData": false}},
+                "Crawls": {"crawl-1": {"AttachmentDownloads": true}}}"#,
+        )
+        .unwrap();
+
+        let flags = document.resolve("Webs", None);
+        assert!(flags.is_enabled(Flag::WebsStructuredData));
+        assert!(!flags.is_enabled(Flag::AttachmentDownloads));
+        assert!(document.resolve("Webs", Some("crawl-1")).is_enabled(Flag::AttachmentDownloads));
+        assert!(!document.resolve("Webs", Some("crawl-2")).is_enabled(Flag::AttachmentDownloads));
+        assert!(!document.resolve("BidNet", Some("crawl-1")).is_enabled(Flag::WebsStructuredData));
+
+        let defaults = FeatureFlags::default();
+        assert!(defaults.is_enabled(Flag::AttachmentDownloads));
+        assert!(!defaults.is_enabled(Flag::WebsStructuredData));
+        assert_eq!(FlagsDocument::default().resolve("Webs", Some("crawl-1")), defaults);
+
+        assert!(
+            parse_config::<FlagsDocument>("feature flag", r#"{"Defaults": {"AttachmentDownloads": "no"}}"#).is_err()
+        );
+    }
+}
@@ -0,0 +1,296 @@
+//! The opportunity representation the GovScout frontend consumes.
+//!
+//! The crawler's [`Opportunity`] records are free to change shape as parsers evolve; the types here are the contract
+//! with the frontend and change only deliberately. Field names are camelCase, dates and times are ISO 8601 strings
+//! (`2022-11-02`, and `2022-11-30T14:00:00` in the portal's local time), and the status is a lowercase enum. Fields
+//! without a value are omitted rather than sent as `null`, and scraper internals such as the parser, quality report,
+//! and tenant are left out.
+//!
+//! These types are only built with the `frontend` feature.
+use {
+    crate::opportunity::{Amount, Contact, Document, Opportunity, OpportunityStatus, Translation},
+    chrono::{NaiveDate, NaiveDateTime},
+    serde::{Deserialize, Serialize},
+};
+
+/// An opportunity as the frontend sees it.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrontendOpportunity {
+    /// The portal the opportunity was found on.
+    pub portal: String,
+
+    /// The URL of the opportunity's page on the portal, which also identifies it within the portal.
+    pub url: String,
+
+    /// The title of the opportunity.
+    pub title: String,
+
+    /// The reference or bid number assigned by the issuer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+
+    /// The organization issuing the opportunity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub organization: Option<String>,
+
+    /// The person to contact about the opportunity, if the portal names anyone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contact: Option<FrontendContact>,
+
+    /// The date the opportunity was published.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published_on: Option<NaiveDate>,
+
+    /// When responses are due, in the portal's local time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due_at: Option<NaiveDateTime>,
+
+    /// The description of the opportunity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// The commodity codes the opportunity is classified under.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commodity_codes: Vec<String>,
+
+    /// The counties the opportunity covers.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub counties: Vec<String>,
+
+    /// Documents published with the opportunity.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub documents: Vec<FrontendDocument>,
+
+    /// The language of the title and description as an ISO 639-1 code, e.g. `fr`, if the portal states it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+
+    /// The title and description in other languages.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub translations: Vec<FrontendTranslation>,
+
+    /// The issuer's estimate of the contract's value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub estimated_value: Option<FrontendAmount>,
+
+    /// Whether the opportunity is still published.
+    pub status: FrontendStatus,
+}
+
+/// Whether an opportunity is still published.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrontendStatus {
+    /// The opportunity is published on its portal.
+    #[default]
+    Open,
+
+    /// The portal no longer publishes the opportunity.
+    Closed,
+
+    /// The opportunity's due date has passed.
+    Expired,
+}
+
+/// A contact for an opportunity.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrontendContact {
+    /// The contact's name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// The contact's email address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+
+    /// The contact's phone number.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phone: Option<String>,
+}
+
+/// A document published with an opportunity.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrontendDocument {
+    /// The name of the document as shown on the portal.
+    pub name: String,
+
+    /// The URL the document can be downloaded from.
+    pub url: String,
+}
+
+/// The title and description of an opportunity in another language.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrontendTranslation {
+    /// The language as an ISO 639-1 code, e.g. `en`.
+    pub language: String,
+
+    /// The title in this language.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    /// The description in this language.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// An amount of money.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrontendAmount {
+    /// The amount in hundredths of the currency unit (e.g. cents), so it is exact.
+    pub minor_units: i64,
+
+    /// The ISO 4217 currency code, e.g. `CAD`.
+    pub currency: String,
+}
+
+impl From<Opportunity> for FrontendOpportunity {
+    fn from(opportunity: Opportunity) -> Self {
+        Self {
+            portal: opportunity.portal,
+            url: opportunity.url,
+            title: opportunity.title,
+            reference: opportunity.reference,
+            organization: opportunity.organization,
+            contact: Some(opportunity.contact).filter(|contact| *contact != Contact::default()).map(Into::into),
+            published_on: opportunity.published_on,
+            due_at: opportunity.due_at,
+            description: opportunity.description,
+            commodity_codes: opportunity.commodity_codes,
+            counties: opportunity.counties,
+            documents: opportunity.documents.into_iter().map(Into::into).collect(),
+            language: opportunity.language,
+            translations: opportunity.translations.into_iter().map(Into::into).collect(),
+            estimated_value: opportunity.estimated_value.map(Into::into),
+            status: opportunity.status.into(),
+        }
+    }
+}
+
+impl From<OpportunityStatus> for FrontendStatus {
+    fn from(status: OpportunityStatus) -> Self {
+        match status {
+            OpportunityStatus::Open => Self::Open,
+            OpportunityStatus::Closed => Self::Closed,
+            OpportunityStatus::Expired => Self::Expired,
+        }
+    }
+}
+
+impl From<FrontendStatus> for OpportunityStatus {
+    fn from(status: FrontendStatus) -> Self {
+        match status {
+            FrontendStatus::Open => Self::Open,
+            FrontendStatus::Closed => Self::Closed,
+            FrontendStatus::Expired => Self::Expired,
+        }
+    }
+}
+
+impl From<Contact> for FrontendContact {
+    fn from(contact: Contact) -> Self {
+        Self {
+            name: contact.name,
+            email: contact.email,
+            phone: contact.phone,
+        }
+    }
+}
+
+impl From<Document> for FrontendDocument {
+    fn from(document: Document) -> Self {
+        Self {
+            name: document.name,
+            url: document.url,
+        }
+    }
+}
+
+impl From<Translation> for FrontendTranslation {
+    fn from(translation: Translation) -> Self {
+        Self {
+            language: translation.language,
+            title: translation.title,
+            description: translation.description,
+        }
+    }
+}
+
+impl From<Amount> for FrontendAmount {
+    fn from(amount: Amount) -> Self {
+        Self {
+            minor_units: amount.minor_units,
+            currency: amount.currency,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{FrontendOpportunity, FrontendStatus},
+        crate::opportunity::{Amount, Contact, Document, Opportunity, OpportunityStatus, ParsedBy},
+        chrono::NaiveDate,
+        serde_json::json,
+    };
+
+    #[test]
+    fn frontend_contract() {
+        let opportunity = Opportunity {
+            portal: "WaLocal".to_string(),
+            url: "https://olympiawa.gov/bids/22-015".to_string(),
+            title: "Street Paving".to_string(),
+            reference: Some("22-015".to_string()),
+            contact: Contact {
+                email: Some("bids@example.gov".to_string()),
+                ..Contact::default()
+            },
+            published_on: NaiveDate::from_ymd_opt(2022, 11, 2),
+            due_at: NaiveDate::from_ymd_opt(2022, 11, 30).and_then(|date| date.and_hms_opt(14, 0, 0)),
+            documents: vec![Document {
+                name: "Plans".to_string(),
+                url: "https://olympiawa.gov/plans.pdf".to_string(),
+            }],
+            estimated_value: Some(Amount {
+                minor_units: 125_000_000,
+                currency: "USD".to_string(),
+            }),
+            parsed_by: Some(ParsedBy::default()),
+            tenant: Some("olympia".to_string()),
+            status: OpportunityStatus::Expired,
+            ..Opportunity::default()
+        };
+
+        let frontend = FrontendOpportunity::from(opportunity);
+        assert_eq!(
+            serde_json::to_value(&frontend).unwrap(),
+            json!({
+                "portal": "WaLocal",
+                "url": "https://olympiawa.gov/bids/22-015",
+                "title": "Street Paving",
+                "reference": "22-015",
+                "contact": {"email": "bids@example.gov"},
+                "publishedOn": "2022-11-02",
+                "dueAt": "2022-11-30T14:00:00",
+                "documents": [{"name": "Plans", "url": "https://olympiawa.gov/plans.pdf"}],
+                "estimatedValue": {"minorUnits": 125_000_000, "currency": "USD"},
+                "status": "expired",
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<FrontendOpportunity>(serde_json::to_value(&frontend).unwrap()).unwrap(),
+            frontend
+        );
+
+        let empty = serde_json::to_value(FrontendOpportunity::from(Opportunity::default())).unwrap();
+        assert_eq!(empty, json!({"portal": "", "url": "", "title": "", "status": "open"}));
+
+        for status in [OpportunityStatus::Open, OpportunityStatus::Closed, OpportunityStatus::Expired] {
+            assert_eq!(OpportunityStatus::from(FrontendStatus::from(status)), status);
+        }
+    }
+}
@@ -0,0 +1,426 @@
+//! Per-crawl index of visited and outstanding URLs, for pausing, resuming, and migrating crawls.
+//!
+//! Every next request sent for a crawl is recorded in the crawl's frontier as enqueued, along with its message body,
+//! and marked visited once an operation on it completes successfully; marking drops the body. The crawl's visited set
+//! can be queried at any time, and its outstanding frontier is whatever is still enqueued.
+//!
+//! `Admin:PauseCrawl` flags the [crawl record][crate::crawl] as paused, so the crawl's messages are held back on
+//! delivery, and exports the frontier to S3 as a [`FrontierExport`]. `Admin:ResumeCrawl` clears the flag, letting the
+//! held-back messages run. Given the `FrontierKey` of an export, it also re-enqueues the export's outstanding requests,
+//! registering the crawl and its visited set in a deployment that has never seen it, so a crawl can be moved between
+//! deployments; the messages still circulating in the old deployment should be purged there. Operations already
+//! running when a crawl is paused may still enqueue requests; pausing again re-exports the frontier with them.
+//!
+//! Requests without a URL, such as those starting a crawl, aren't tracked.
+use {
+    crate::{
+        classification::{Classification, ObjectTags, DDB_KEY_CLASSIFICATION},
+        crawl::{CrawlRecord, UnknownCrawlError},
+        httpext::{aws_err_str, log_aws_err, DdbBatchWriter, LogConfig},
+        shapes::NextRequest,
+        BoxError,
+    },
+    aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoDbClient},
+    aws_sdk_s3::primitives::ByteStream,
+    chrono::{DateTime, SecondsFormat, Utc},
+    log::*,
+    serde::{Deserialize, Serialize},
+    std::collections::{BTreeMap, HashMap},
+};
+
+const DDB_KEY_CRAWL_ID: &str = "CrawlId";
+const DDB_KEY_REQUEST_ID: &str = "RequestId";
+const DDB_KEY_URL: &str = "Url";
+const DDB_KEY_OPERATION: &str = "Operation";
+const DDB_KEY_STATE: &str = "State";
+const DDB_KEY_REQUEST: &str = "Request";
+const DDB_KEY_ENQUEUED_AT: &str = "EnqueuedAt";
+const DDB_KEY_VISITED_AT: &str = "VisitedAt";
+
+/// Partition key prefix for frontier items, followed by the crawl id, keeping them apart from request log items in a
+/// shared table.
+const FRONTIER_PARTITION_PREFIX: &str = "Frontier#";
+
+/// State of a URL sent to the queue but not yet completed.
+const STATE_ENQUEUED: &str = "Enqueued";
+
+/// State of a URL an operation completed.
+const STATE_VISITED: &str = "Visited";
+
+/// Prefix of frontier exports, relative to the S3 prefix; the crawl id and export time follow it.
+const EXPORT_S3_PREFIX: &str = "frontier/";
+
+/// A URL in a crawl's frontier.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct FrontierUrl {
+    /// The operation performed on the URL.
+    pub operation: String,
+
+    /// The URL.
+    pub url: String,
+}
+
+/// An entry of a crawl's frontier.
+#[derive(Clone, Debug)]
+pub struct FrontierEntry {
+    /// The operation and URL.
+    pub target: FrontierUrl,
+
+    /// The request still to be performed, or `None` if the URL has been visited.
+    pub request: Option<NextRequest>,
+}
+
+/// A crawl's frontier as exported to S3.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct FrontierExport {
+    /// The crawl exported.
+    pub crawl: CrawlRecord,
+
+    /// When the frontier was exported.
+    pub exported_at: DateTime<Utc>,
+
+    /// The URLs the crawl has visited.
+    pub visited: Vec<FrontierUrl>,
+
+    /// The requests the crawl has enqueued but not completed.
+    pub outstanding: Vec<NextRequest>,
+}
+
+/// Storage for crawl frontiers in DynamoDB.
+#[derive(Clone, Debug)]
+pub struct FrontierStore {
+    ddb_client: DynamoDbClient,
+    writer: DdbBatchWriter,
+}
+
+impl FrontierStore {
+    /// Create a frontier store using the given table.
+    pub fn new<S: Into<String>>(ddb_client: DynamoDbClient, table_name: S) -> Self {
+        Self {
+            writer: DdbBatchWriter::new(ddb_client.clone(), table_name),
+            ddb_client,
+        }
+    }
+
+    /// Return the name of the table frontiers are stored in.
+    pub fn table_name(&self) -> &str {
+        self.writer.table_name()
+    }
+
+    /// Record requests about to be sent to the queue as enqueued. A request repeated in the batch is recorded once.
+    pub async fn enqueue(&self, next_requests: &[NextRequest], now: DateTime<Utc>) -> Result<(), BoxError> {
+        // BatchWriteItem rejects a batch that writes the same key twice.
+        let mut items = BTreeMap::new();
+        for next_request in next_requests {
+            let Some(item) = enqueued_item(next_request, now)? else {
+                continue;
+            };
+
+            let key = [DDB_KEY_CRAWL_ID, DDB_KEY_REQUEST_ID].map(|name| item[name].as_s().cloned().unwrap_or_default());
+            items.insert(key, item);
+        }
+
+        for item in items.into_values() {
+            self.writer.put(item).await?;
+        }
+
+        self.writer.flush().await
+    }
+
+    /// Mark a URL as visited by a completed operation.
+    pub async fn mark_visited(&self, crawl_id: &str, operation: &str, url: &str) -> Result<(), BoxError> {
+        let result = self
+            .ddb_client
+            .update_item()
+            .table_name(self.writer.table_name())
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{FRONTIER_PARTITION_PREFIX}{crawl_id}")))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(entry_key(operation, url)))
+            .update_expression(
+                "SET #url = :url, #operation = :operation, #state = :state, #visited_at = :visited_at, \
+                 #classification = :classification REMOVE #request",
+            )
+            .expression_attribute_names("#url", DDB_KEY_URL)
+            .expression_attribute_names("#operation", DDB_KEY_OPERATION)
+            .expression_attribute_names("#state", DDB_KEY_STATE)
+            .expression_attribute_names("#visited_at", DDB_KEY_VISITED_AT)
+            .expression_attribute_names("#classification", DDB_KEY_CLASSIFICATION)
+            .expression_attribute_names("#request", DDB_KEY_REQUEST)
+            .expression_attribute_values(":url", AttributeValue::S(url.to_string()))
+            .expression_attribute_values(":operation", AttributeValue::S(operation.to_string()))
+            .expression_attribute_values(":state", AttributeValue::S(STATE_VISITED.to_string()))
+            .expression_attribute_values(":visited_at", AttributeValue::S(Utc::now().to_rfc3339()))
+            .expression_attribute_values(
+                ":classification",
+                AttributeValue::S(Classification::CrawlMetadata.to_string()),
+            )
+            .send()
+            .await;
+
+        if let Err(e) = result {
+            error!("UpdateItem frontier of crawl {crawl_id} for {url}: {}", aws_err_str(&e));
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    /// Record URLs as visited without a visit time, as when restoring an exported frontier.
+    pub async fn restore_visited(&self, crawl_id: &str, visited: &[FrontierUrl]) -> Result<(), BoxError> {
+        for target in visited {
+            let item = HashMap::from([
+                (DDB_KEY_CRAWL_ID.to_string(), AttributeValue::S(format!("{FRONTIER_PARTITION_PREFIX}{crawl_id}"))),
+                (DDB_KEY_REQUEST_ID.to_string(), AttributeValue::S(entry_key(&target.operation, &target.url))),
+                (DDB_KEY_URL.to_string(), AttributeValue::S(target.url.clone())),
+                (DDB_KEY_OPERATION.to_string(), AttributeValue::S(target.operation.clone())),
+                (DDB_KEY_STATE.to_string(), AttributeValue::S(STATE_VISITED.to_string())),
+                (DDB_KEY_CLASSIFICATION.to_string(), AttributeValue::S(Classification::CrawlMetadata.to_string())),
+            ]);
+            self.writer.put(item).await?;
+        }
+
+        self.writer.flush().await
+    }
+
+    /// Return the entries of a crawl's frontier, ordered by URL.
+    pub async fn entries(&self, crawl_id: &str) -> Result<Vec<FrontierEntry>, BoxError> {
+        let mut entries = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .ddb_client
+                .query()
+                .table_name(self.writer.table_name())
+                .key_condition_expression("#pk = :pk")
+                .expression_attribute_names("#pk", DDB_KEY_CRAWL_ID)
+                .expression_attribute_values(":pk", AttributeValue::S(format!("{FRONTIER_PARTITION_PREFIX}{crawl_id}")))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await;
+            let output = match result {
+                Ok(output) => output,
+                Err(e) => {
+                    error!("Query frontier of crawl {crawl_id}: {}", aws_err_str(&e));
+                    return Err(e.into());
+                }
+            };
+
+            entries.extend(output.items().iter().filter_map(parse_entry));
+
+            start_key = output.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(entries);
+            }
+        }
+    }
+}
+
+impl FrontierExport {
+    /// Return the S3 key of the export, relative to the S3 prefix.
+    pub fn key(&self) -> String {
+        format!(
+            "{EXPORT_S3_PREFIX}{}/{}.json",
+            self.crawl.crawl_id,
+            self.exported_at.to_rfc3339_opts(SecondsFormat::Millis, true)
+        )
+    }
+}
+
+/// Export a crawl's frontier to S3, returning the key of the export.
+pub async fn export(log_config: &LogConfig, crawl_id: &str) -> Result<String, BoxError> {
+    let Some(crawl) = log_config.crawls.record(crawl_id).await? else {
+        return Err(UnknownCrawlError {
+            crawl_id: crawl_id.to_string(),
+        }
+        .into());
+    };
+
+    let mut visited = Vec::new();
+    let mut outstanding = Vec::new();
+    for entry in log_config.frontier.entries(crawl_id).await? {
+        match entry.request {
+            Some(request) => outstanding.push(request),
+            None => visited.push(entry.target),
+        }
+    }
+
+    let export = FrontierExport {
+        crawl,
+        exported_at: Utc::now(),
+        visited,
+        outstanding,
+    };
+    let key = format!("{}{}", log_config.s3_prefix, export.key());
+    let tags = ObjectTags {
+        portal: Some(&export.crawl.portal),
+        crawl_id: Some(crawl_id),
+        tenant: export.crawl.tenant.as_deref(),
+        ..ObjectTags::new(Classification::CrawlMetadata)
+    };
+
+    log_aws_err(
+        log_config
+            .s3_client
+            .put_object()
+            .bucket(&log_config.s3_bucket)
+            .key(&key)
+            .content_type("application/json")
+            .tagging(tags.to_tagging())
+            .body(ByteStream::from(serde_json::to_vec(&export)?))
+            .send()
+            .await,
+        &format!("PutObject s3://{}/{key}", log_config.s3_bucket),
+    )?;
+
+    info!(
+        "Exported frontier of crawl {crawl_id} ({} visited, {} outstanding) to s3://{}/{key}",
+        export.visited.len(),
+        export.outstanding.len(),
+        log_config.s3_bucket
+    );
+    Ok(key)
+}
+
+/// Load a frontier export from S3, registering its crawl and visited set if this deployment hasn't seen the crawl.
+pub async fn import(log_config: &LogConfig, key: &str) -> Result<FrontierExport, BoxError> {
+    let output = log_aws_err(
+        log_config.s3_client.get_object().bucket(&log_config.s3_bucket).key(key).send().await,
+        &format!("GetObject s3://{}/{key}", log_config.s3_bucket),
+    )?;
+    let body = output.body.collect().await?.into_bytes();
+    let export: FrontierExport = serde_json::from_slice(&body)?;
+
+    if log_config.crawls.restore(&export.crawl).await? {
+        log_config.frontier.restore_visited(&export.crawl.crawl_id, &export.visited).await?;
+    }
+
+    Ok(export)
+}
+
+/// Return the sort key of a frontier entry.
+fn entry_key(operation: &str, url: &str) -> String {
+    format!("{url}#{operation}")
+}
+
+/// Return the frontier item of an enqueued request, or `None` if the request isn't tracked.
+fn enqueued_item(
+    next_request: &NextRequest,
+    now: DateTime<Utc>,
+) -> Result<Option<HashMap<String, AttributeValue>>, BoxError> {
+    let (Some(crawl_id), Some(url)) = (next_request.crawl.crawl_id.as_ref(), next_request.url.as_ref()) else {
+        return Ok(None);
+    };
+
+    let operation = next_request.operation.to_string();
+    Ok(Some(HashMap::from([
+        (DDB_KEY_CRAWL_ID.to_string(), AttributeValue::S(format!("{FRONTIER_PARTITION_PREFIX}{crawl_id}"))),
+        (DDB_KEY_REQUEST_ID.to_string(), AttributeValue::S(entry_key(&operation, url))),
+        (DDB_KEY_URL.to_string(), AttributeValue::S(url.clone())),
+        (DDB_KEY_OPERATION.to_string(), AttributeValue::S(operation)),
+        (DDB_KEY_STATE.to_string(), AttributeValue::S(STATE_ENQUEUED.to_string())),
+        (DDB_KEY_REQUEST.to_string(), AttributeValue::S(serde_json::to_string(next_request)?)),
+        (DDB_KEY_ENQUEUED_AT.to_string(), AttributeValue::S(now.to_rfc3339())),
+        (DDB_KEY_CLASSIFICATION.to_string(), AttributeValue::S(Classification::CrawlMetadata.to_string())),
+    ])))
+}
+
+/// Parse a frontier item, returning `None` if it is incomplete.
+fn parse_entry(item: &HashMap<String, AttributeValue>) -> Option<FrontierEntry> {
+    let string = |name: &str| item.get(name).and_then(|value| value.as_s().ok()).cloned();
+
+    let target = FrontierUrl {
+        operation: string(DDB_KEY_OPERATION)?,
+        url: string(DDB_KEY_URL)?,
+    };
+    let request = match string(DDB_KEY_STATE)?.as_str() {
+        STATE_ENQUEUED => match serde_json::from_str(&string(DDB_KEY_REQUEST)?) {
+            Ok(request) => Some(request),
+            Err(e) => {
+                warn!("Ignoring frontier entry for {} with an unreadable request: {e}", target.url);
+                return None;
+            }
+        },
+        _ => None,
+    };
+
+    Some(FrontierEntry {
+        target,
+        request,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{enqueued_item, parse_entry, FrontierExport, FrontierUrl},
+        crate::{
+            crawl::CrawlRecord,
+            shapes::{CrawlParameters, NextRequest, Operation},
+        },
+        aws_sdk_dynamodb::types::AttributeValue,
+        chrono::{TimeZone, Utc},
+        std::str::FromStr,
+    };
+
+    #[test]
+    fn frontier_entries() {
+        let now = Utc.with_ymd_and_hms(2024, 7, 1, 12, 0, 0).unwrap();
+        let mut next_request = NextRequest {
+            operation: Operation::from_str("Webs:FetchOpportunityDetailPage").unwrap(),
+            url: Some("https://pr-webs-vendor.des.wa.gov/Search_Bid_Detail.aspx?ID=1".to_string()),
+            link_text: None,
+            crawl: CrawlParameters::default(),
+            delay_seconds: 0,
+        };
+        assert!(enqueued_item(&next_request, now).unwrap().is_none());
+
+        next_request.crawl.crawl_id = Some("crawl".to_string());
+        let mut item = enqueued_item(&next_request, now).unwrap().unwrap();
+        assert_eq!(item["CrawlId"].as_s().unwrap(), "Frontier#crawl");
+        assert_eq!(
+            item["RequestId"].as_s().unwrap(),
+            "https://pr-webs-vendor.des.wa.gov/Search_Bid_Detail.aspx?ID=1#Webs:FetchOpportunityDetailPage"
+        );
+
+        let entry = parse_entry(&item).unwrap();
+        let request = entry.request.unwrap();
+        assert_eq!(request.url, next_request.url);
+        assert_eq!(request.crawl.crawl_id.as_deref(), Some("crawl"));
+
+        // Marking the URL visited drops the request.
+        item.insert("State".to_string(), AttributeValue::S("Visited".to_string()));
+        item.remove("Request");
+        let entry = parse_entry(&item).unwrap();
+        assert!(entry.request.is_none());
+        assert_eq!(entry.target.operation, "Webs:FetchOpportunityDetailPage");
+    }
+
+    #[test]
+    fn export_keys() {
+        let export = FrontierExport {
+            crawl: CrawlRecord {
+                crawl_id: "crawl".to_string(),
+                portal: "Webs".to_string(),
+                operation: "Webs:StartCrawl".to_string(),
+                url: None,
+                user_agent: "GovScout".to_string(),
+                started_at: Utc.with_ymd_and_hms(2024, 7, 1, 11, 0, 0).unwrap(),
+                tenant: None,
+            },
+            exported_at: Utc.with_ymd_and_hms(2024, 7, 1, 12, 0, 0).unwrap(),
+            visited: vec![FrontierUrl {
+                operation: "Webs:FetchOpportunityListingPage".to_string(),
+                url: "https://pr-webs-vendor.des.wa.gov/Search_Bid.aspx".to_string(),
+            }],
+            outstanding: vec![],
+        };
+        assert_eq!(export.key(), "frontier/crawl/2024-07-01T12:00:00.000Z.json");
+
+        let json = serde_json::to_string(&export).unwrap();
+        let parsed: FrontierExport = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.crawl, export.crawl);
+        assert_eq!(parsed.visited, export.visited);
+    }
+}
@@ -0,0 +1,84 @@
+//! Fuzzing entry points for the HTML parsers.
+//!
+//! Each function takes arbitrary bytes, as supplied by a `cargo-fuzz`/libFuzzer target, and feeds them to a parser.
+//! Parse errors are expected and ignored; the only failure a fuzzer should be able to find here is a panic, which
+//! would kill every record in the Lambda batch.
+use {
+    crate::{
+        httpext::Form,
+        shapes::CrawlParameters,
+        soup::parse_html_str,
+        webs::search_opportunities::{find_opportunity_next_pages, parse_opportunity_listing_page},
+    },
+    reqwest::Url,
+};
+
+/// Base URL used to resolve relative links in fuzzed documents.
+const FUZZ_BASE_URL: &str = "https://fuzz.example.com/Search_Bid.aspx";
+
+/// Form name looked up by [`fuzz_form_from_unparsed_form_name`].
+const FUZZ_FORM_NAME: &str = "Form1";
+
+fn base_url() -> Url {
+    Url::parse(FUZZ_BASE_URL).expect("FUZZ_BASE_URL is a valid URL")
+}
+
+/// Fuzz [`Form::from_unparsed_form_name`].
+pub fn fuzz_form_from_unparsed_form_name(data: &[u8]) {
+    let text = String::from_utf8_lossy(data);
+    let _ = Form::from_unparsed_form_name(&base_url(), &text, FUZZ_FORM_NAME);
+}
+
+/// Fuzz the WEBS opportunity listing pager parser.
+pub fn fuzz_find_opportunity_next_pages(data: &[u8]) {
+    let document = parse_html_str(&String::from_utf8_lossy(data));
+    let _ = find_opportunity_next_pages(&document);
+}
+
+/// Fuzz the WEBS opportunity listing parser.
+pub fn fuzz_parse_opportunity_listing_page(data: &[u8]) {
+    let document = parse_html_str(&String::from_utf8_lossy(data));
+    let crawl_parameters = CrawlParameters::default();
+    let mut next_requests = vec![];
+    let _ = parse_opportunity_listing_page(&document, &base_url(), &crawl_parameters, &mut next_requests);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        fuzz_find_opportunity_next_pages, fuzz_form_from_unparsed_form_name, fuzz_parse_opportunity_listing_page,
+    };
+
+    /// Run each entry point over truncations and byte-level corruptions of real pages. This is no substitute for a
+    /// coverage-guided fuzzer, but it keeps the obvious malformed-markup cases from regressing.
+    #[test]
+    fn malformed_pages_do_not_panic() {
+        const SEEDS: &[&str] = &[include_str!("webs/webs-search-bids-page1.html"), include_str!("webs/webs-home.html")];
+        const STEPS: usize = 4;
+
+        for seed in SEEDS {
+            let bytes = seed.as_bytes();
+            for step in 0..=STEPS {
+                let cut = bytes.len() * step / STEPS;
+                let truncated = &bytes[..cut];
+
+                // Corrupt the bytes around the cut point to produce broken tags and attributes.
+                let mut corrupted = bytes.to_vec();
+                for b in corrupted.iter_mut().skip(cut).take(64) {
+                    *b = match *b {
+                        b'<' => b'>',
+                        b'"' => b'\'',
+                        b'=' => b' ',
+                        other => other.wrapping_add(1),
+                    };
+                }
+
+                for data in [truncated, &corrupted[..]] {
+                    fuzz_form_from_unparsed_form_name(data);
+                    fuzz_find_opportunity_next_pages(data);
+                    fuzz_parse_opportunity_listing_page(data);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Golden-file snapshot testing for parsers.
+//!
+//! Archived HTML snapshots live under `testdata/snapshots/<subsystem>/<parser>/<name>.html`. Each snapshot is run
+//! through its parser and the JSON output is compared against `<name>.json` in the same directory.
+//!
+//! When a parser change is intentional, re-run the tests with `GOLDEN_BLESS=1` to rewrite the golden files, then review
+//! the diff before committing.
+//!
+//! Snapshots must be [sanitized][sanitize_html] of session tokens, form state, and email addresses before they are
+//! committed; a snapshot that isn't fails its check. To add a page captured in production, copy it into place and run
+//! the tests with `GOLDEN_BLESS=1`, which sanitizes it in place before writing its golden file.
+mod sanitize;
+
+pub use sanitize::sanitize_html;
+
+use {
+    crate::BoxError,
+    serde_json::Value,
+    std::{
+        env, fs,
+        path::{Path, PathBuf},
+    },
+};
+
+const ENV_GOLDEN_BLESS: &str = "GOLDEN_BLESS";
+const SNAPSHOT_EXTENSION: &str = "html";
+const GOLDEN_EXTENSION: &str = "json";
+
+/// Return the snapshot directory for a subsystem.
+pub fn snapshot_dir(subsystem: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata").join("snapshots").join(subsystem)
+}
+
+/// Indicates whether golden files should be rewritten instead of compared.
+pub fn blessing() -> bool {
+    env::var(ENV_GOLDEN_BLESS).map(|v| !v.is_empty() && v != "0").unwrap_or(false)
+}
+
+/// Run every snapshot under `dir` through `parse` and compare the output against the golden files.
+///
+/// `parse` is called with the name of the parser directory and the snapshot text. Returns the number of snapshots
+/// checked, or a description of every mismatch.
+pub fn check_snapshots<F>(dir: &Path, parse: F) -> Result<usize, String>
+where
+    F: Fn(&str, &str) -> Result<Value, BoxError>,
+{
+    let bless = blessing();
+    let mut checked = 0;
+    let mut failures = Vec::new();
+
+    for parser_dir in sorted_entries(dir)? {
+        if !parser_dir.is_dir() {
+            continue;
+        }
+
+        let parser = parser_dir.file_name().and_then(|s| s.to_str()).unwrap_or_default().to_string();
+
+        for snapshot in sorted_entries(&parser_dir)? {
+            if snapshot.extension().and_then(|s| s.to_str()) != Some(SNAPSHOT_EXTENSION) {
+                continue;
+            }
+
+            checked += 1;
+            if let Err(e) = check_snapshot(&parser, &snapshot, bless, &parse) {
+                failures.push(format!("{}: {e}", snapshot.display()));
+            }
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(checked)
+    } else {
+        Err(failures.join("\n"))
+    }
+}
+
+/// Check a single snapshot against its golden file.
+fn check_snapshot<F>(parser: &str, snapshot: &Path, bless: bool, parse: &F) -> Result<(), String>
+where
+    F: Fn(&str, &str) -> Result<Value, BoxError>,
+{
+    let mut text = fs::read_to_string(snapshot).map_err(|e| format!("failed to read snapshot: {e}"))?;
+    let sanitized = sanitize_html(&text);
+    if sanitized != text {
+        if !bless {
+            return Err(format!("snapshot is not sanitized; run with {ENV_GOLDEN_BLESS}=1 to sanitize it"));
+        }
+
+        fs::write(snapshot, &sanitized).map_err(|e| format!("failed to write sanitized snapshot: {e}"))?;
+        text = sanitized;
+    }
+
+    let actual = parse(parser, &text).map_err(|e| format!("parser {parser} failed: {e}"))?;
+    let golden_path = snapshot.with_extension(GOLDEN_EXTENSION);
+
+    if bless {
+        let mut rendered = serde_json::to_string_pretty(&actual).map_err(|e| e.to_string())?;
+        rendered.push('\n');
+        return fs::write(&golden_path, rendered).map_err(|e| format!("failed to write golden file: {e}"));
+    }
+
+    let golden = fs::read_to_string(&golden_path).map_err(|e| {
+        format!("failed to read golden file {} ({e}); run with {ENV_GOLDEN_BLESS}=1", golden_path.display())
+    })?;
+    let expected: Value = serde_json::from_str(&golden).map_err(|e| format!("invalid golden file: {e}"))?;
+
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!(
+            "output differs from golden file; run with {ENV_GOLDEN_BLESS}=1 to accept\nexpected: {}\nactual: {}",
+            serde_json::to_string_pretty(&expected).unwrap_or_default(),
+            serde_json::to_string_pretty(&actual).unwrap_or_default()
+        ))
+    }
+}
+
+/// Return the entries of a directory in sorted order.
+fn sorted_entries(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("failed to read {}: {e}", dir.display()))?;
+    let mut paths: Vec<PathBuf> = entries.filter_map(|e| e.ok().map(|e| e.path())).collect();
+    paths.sort();
+    Ok(paths)
+}
@@ -0,0 +1,225 @@
+//! Sanitizing captured portal pages before they are committed as snapshots.
+//!
+//! Pages fetched in production carry details that mustn't end up in the repository: ASP.NET view state and event
+//! validation blobs, which can embed server-side data; anti-forgery tokens; session ids in links; and the email
+//! addresses of real people. [`sanitize_html`] replaces each with a placeholder, leaving the markup the parsers rely on
+//! intact. Sanitizing is idempotent, so a raw capture can be told apart from a sanitized one by sanitizing it again.
+use {crate::shapes::SESSION_PARAMS, std::collections::HashMap};
+
+/// What secret values are replaced with.
+const PLACEHOLDER: &str = "SANITIZED";
+
+/// The domain of the addresses that stand in for real email addresses. Addresses in it are left alone.
+const PLACEHOLDER_EMAIL_DOMAIN: &str = "example.com";
+
+/// Tags whose named values can hold secrets, with the attribute holding the value.
+const SECRET_ATTRIBUTES: &[(&str, &str)] = &[("<input", "value"), ("<meta", "content")];
+
+/// Names of form fields, compared in lowercase, whose values are replaced.
+const STATE_FIELDS: &[&str] =
+    &["__eventvalidation", "__previouspage", "__requestdigest", "__viewstate", "__viewstategenerator"];
+
+/// Fragments of field names, compared in lowercase, marking fields whose values are replaced.
+const TOKEN_FIELD_MARKERS: &[&str] = &["csrf", "nonce", "token", "xsrf"];
+
+/// Link parameters, besides the [session parameters][SESSION_PARAMS] dropped from crawled URLs, whose values are
+/// replaced. `sid` is a session id on some portals and a solicitation id on others, so it is kept when crawling but
+/// not trusted in a snapshot.
+const SNAPSHOT_SESSION_PARAMS: &[&str] = &["sid"];
+
+/// Extensions of file names that look like email addresses, such as `logo@2x.png`, which are left alone.
+const FILE_EXTENSIONS: &[&str] = &["css", "gif", "jpeg", "jpg", "js", "png", "svg", "webp"];
+
+/// Return `text` with session tokens, form state, and email addresses replaced by placeholders.
+///
+/// Each distinct email address is replaced by its own placeholder address, so a page listing several contacts still
+/// lists several.
+pub fn sanitize_html(text: &str) -> String {
+    let mut text = text.to_string();
+    for (tag, attribute) in SECRET_ATTRIBUTES {
+        text = sanitize_fields(&text, tag, attribute);
+    }
+
+    sanitize_emails(&sanitize_session_params(&text))
+}
+
+/// Replace the `attribute` values of `tag` elements whose names mark them as secret.
+fn sanitize_fields(text: &str, tag: &str, attribute: &str) -> String {
+    // ASCII lowercasing keeps byte offsets, so positions found in `lower` apply to `text`.
+    let lower = text.to_ascii_lowercase();
+    let mut sanitized = String::with_capacity(text.len());
+    let mut copied = 0;
+
+    while let Some(start) = lower[copied..].find(tag).map(|i| copied + i) {
+        let end = lower[start..].find('>').map_or(text.len(), |i| start + i);
+        let element = &lower[start..end];
+        let secret = attribute_span(element, "name").is_some_and(|(s, e)| is_secret_field(&element[s..e]));
+
+        match attribute_span(element, attribute) {
+            Some((s, e)) if secret && s < e => {
+                sanitized.push_str(&text[copied..start + s]);
+                sanitized.push_str(PLACEHOLDER);
+                sanitized.push_str(&text[start + e..end]);
+            }
+            _ => sanitized.push_str(&text[copied..end]),
+        }
+
+        copied = end;
+    }
+
+    sanitized.push_str(&text[copied..]);
+    sanitized
+}
+
+/// Return the byte range of the value of an attribute within the text of an element, excluding any quotes.
+fn attribute_span(element: &str, name: &str) -> Option<(usize, usize)> {
+    let mut from = 0;
+    while let Some(i) = element[from..].find(name).map(|i| from + i) {
+        from = i + name.len();
+        if i == 0 || !element.as_bytes()[i - 1].is_ascii_whitespace() {
+            continue;
+        }
+
+        let Some(value) = element[from..].trim_start().strip_prefix('=') else {
+            continue;
+        };
+
+        let value = value.trim_start();
+        let start = element.len() - value.len();
+        return Some(match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let end = value[1..].find(quote).map_or(element.len(), |j| start + 1 + j);
+                (start + 1, end)
+            }
+            _ => (start, value.find(|c: char| c.is_ascii_whitespace()).map_or(element.len(), |j| start + j)),
+        });
+    }
+
+    None
+}
+
+/// Indicates whether a form field's name, in lowercase, marks its value as secret.
+fn is_secret_field(name: &str) -> bool {
+    STATE_FIELDS.contains(&name) || TOKEN_FIELD_MARKERS.iter().any(|marker| name.contains(marker))
+}
+
+/// Replace the values of session parameters in links, e.g. `?jsessionid=...` or `;jsessionid=...`.
+fn sanitize_session_params(text: &str) -> String {
+    let is_delimiter = |c: char| c.is_ascii_whitespace() || "&\"'#;<>?".contains(c);
+    let mut text = text.to_string();
+
+    for param in SESSION_PARAMS.iter().chain(SNAPSHOT_SESSION_PARAMS) {
+        let lower = text.to_ascii_lowercase();
+        let pattern = format!("{param}=");
+        let mut sanitized = String::with_capacity(text.len());
+        let mut copied = 0;
+
+        for (i, _) in lower.match_indices(&pattern) {
+            // `&amp;` ends with a semicolon, so it is covered along with `;` path parameters.
+            if i < copied || i == 0 || !matches!(lower.as_bytes()[i - 1], b'?' | b'&' | b';') {
+                continue;
+            }
+
+            let start = i + pattern.len();
+            let end = text[start..].find(is_delimiter).map_or(text.len(), |j| start + j);
+            if start < end {
+                sanitized.push_str(&text[copied..start]);
+                sanitized.push_str(PLACEHOLDER);
+                copied = end;
+            }
+        }
+
+        sanitized.push_str(&text[copied..]);
+        text = sanitized;
+    }
+
+    text
+}
+
+/// Replace email addresses with placeholder addresses, the same address always with the same placeholder.
+fn sanitize_emails(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let is_local = |b: u8| b.is_ascii_alphanumeric() || b"._%+-".contains(&b);
+    let is_domain = |b: u8| b.is_ascii_alphanumeric() || b == b'.' || b == b'-';
+    let mut placeholders: HashMap<String, String> = HashMap::new();
+    let mut sanitized = String::with_capacity(text.len());
+    let mut copied = 0;
+
+    for (at, _) in text.match_indices('@') {
+        if at < copied {
+            continue;
+        }
+
+        let mut start = at;
+        while start > copied && is_local(bytes[start - 1]) {
+            start -= 1;
+        }
+
+        let mut end = at + 1;
+        while end < bytes.len() && is_domain(bytes[end]) {
+            end += 1;
+        }
+        while end > at + 1 && matches!(bytes[end - 1], b'.' | b'-') {
+            end -= 1;
+        }
+
+        let domain = text[at + 1..end].to_ascii_lowercase();
+        let Some((_, tld)) = domain.rsplit_once('.') else {
+            continue;
+        };
+
+        if start == at
+            || tld.len() < 2
+            || !tld.bytes().all(|b| b.is_ascii_alphabetic())
+            || FILE_EXTENSIONS.contains(&tld)
+            || domain == PLACEHOLDER_EMAIL_DOMAIN
+            || domain.ends_with(&format!(".{PLACEHOLDER_EMAIL_DOMAIN}"))
+        {
+            continue;
+        }
+
+        let count = placeholders.len();
+        let placeholder = placeholders
+            .entry(text[start..end].to_ascii_lowercase())
+            .or_insert_with(|| format!("contact{}@{PLACEHOLDER_EMAIL_DOMAIN}", count + 1));
+        sanitized.push_str(&text[copied..start]);
+        sanitized.push_str(placeholder);
+        copied = end;
+    }
+
+    sanitized.push_str(&text[copied..]);
+    sanitized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sanitize_html;
+
+    #[test]
+    fn sanitize() {
+        let raw = r#"<form name="Form1" action="Search_Bid.aspx;jsessionid=8F3A21C0?page=2&amp;sid=abc123">
+<input type="hidden" name="__VIEWSTATE" id="__VIEWSTATE" value="/wEPDwUKMTcwNjAwODgyOQ8WAh4E" />
+<input type="hidden" name="__EVENTTARGET" value="" />
+<input type="hidden" value='9f86d081884c' name="authenticity_token">
+<input type="text" name="txtKeyword" value="janitorial">
+<meta name="csrf-token" content="Zm9vYmFy">
+<a href="mailto:Maria.Lopez@dot.state.fl.us">Maria.Lopez@dot.state.fl.us</a>
+<a href="/bid?id=7&amp;residence=1">Bid</a> purchasing@seattle.gov
+<img src="logo@2x.png"> pat@example.com
+</form>"#;
+
+        let sanitized = sanitize_html(raw);
+        assert!(sanitized.contains("Search_Bid.aspx;jsessionid=SANITIZED?page=2&amp;sid=SANITIZED"));
+        assert!(sanitized.contains(r#"name="__VIEWSTATE" id="__VIEWSTATE" value="SANITIZED" />"#));
+        assert!(sanitized.contains(r#"name="__EVENTTARGET" value="""#));
+        assert!(sanitized.contains(r#"value='SANITIZED' name="authenticity_token""#));
+        assert!(sanitized.contains(r#"name="txtKeyword" value="janitorial""#));
+        assert!(sanitized.contains(r#"<meta name="csrf-token" content="SANITIZED">"#));
+        assert!(sanitized.contains(r#"href="mailto:contact1@example.com">contact1@example.com</a>"#));
+        assert!(sanitized.contains("residence=1\">Bid</a> contact2@example.com"));
+        assert!(sanitized.contains(r#"<img src="logo@2x.png"> pat@example.com"#));
+
+        // Sanitizing is idempotent.
+        assert_eq!(sanitize_html(&sanitized), sanitized);
+    }
+}
@@ -0,0 +1,510 @@
+//! Crawl health checks and alerting.
+//!
+//! When a crawl operation finishes, its request statistics and the number of opportunities it found are checked
+//! against configurable thresholds. A portal that changes its markup usually doesn't fail outright; instead the
+//! parsers quietly find nothing, so these checks are the main signal that a parser needs attention.
+//!
+//! Response times are summarized per host as well, so portals that need longer timeouts or lighter concurrency stand
+//! out.
+//!
+//! Parsers that still find opportunities can degrade too, extracting them with fields missing. The average
+//! [quality score][crate::quality] of a crawl's opportunities is therefore checked each time another
+//! [`QUALITY_SAMPLE_SIZE`] opportunities have been scored.
+use {
+    crate::{
+        httpext::{aws_err_str, ConfigError, CrawlStatsSnapshot, HostLatency, LogConfig},
+        journal::CrawlEvent,
+        quality::QualityTally,
+        BoxError,
+    },
+    log::*,
+    std::fmt::{Display, Formatter, Result as FmtResult},
+};
+
+const ENV_HEALTH_MIN_OPPORTUNITIES: &str = "HEALTH_MIN_OPPORTUNITIES";
+const ENV_HEALTH_MAX_ERROR_RATE_PERCENT: &str = "HEALTH_MAX_ERROR_RATE_PERCENT";
+const ENV_HEALTH_MAX_UNCHANGED_PERCENT: &str = "HEALTH_MAX_UNCHANGED_PERCENT";
+const ENV_HEALTH_MAX_P95_RESPONSE_MS: &str = "HEALTH_MAX_P95_RESPONSE_MS";
+const ENV_HEALTH_MIN_AVERAGE_QUALITY: &str = "HEALTH_MIN_AVERAGE_QUALITY";
+const DEFAULT_MIN_OPPORTUNITIES: usize = 1;
+const DEFAULT_MAX_ERROR_RATE_PERCENT: f64 = 10.0;
+const DEFAULT_MIN_AVERAGE_QUALITY: f64 = 60.0;
+
+/// The number of scored opportunities between checks of a crawl's average quality score.
+pub const QUALITY_SAMPLE_SIZE: u64 = 25;
+
+/// SNS subjects are limited to 100 characters.
+const MAX_SNS_SUBJECT_LEN: usize = 100;
+
+/// Thresholds for crawl health checks. A threshold of `None` disables the corresponding check.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HealthThresholds {
+    /// The minimum number of opportunities a crawl is expected to find.
+    pub min_opportunities: Option<usize>,
+
+    /// The maximum percentage of requests that may fail.
+    pub max_error_rate_percent: Option<f64>,
+
+    /// The maximum percentage of responses that may be identical to previously stored bodies.
+    pub max_unchanged_percent: Option<f64>,
+
+    /// The maximum 95th percentile response time for any host, in milliseconds.
+    pub max_p95_response_ms: Option<u64>,
+
+    /// The minimum average quality score of a crawl's opportunities.
+    pub min_average_quality: Option<f64>,
+}
+
+/// A crawl health check that did not pass.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HealthViolation {
+    /// Too few opportunities were found.
+    TooFewOpportunities {
+        /// The number of opportunities found.
+        found: usize,
+
+        /// The minimum expected.
+        minimum: usize,
+    },
+
+    /// Too many requests failed.
+    ErrorRate {
+        /// The percentage of requests that failed.
+        percent: f64,
+
+        /// The maximum allowed.
+        maximum: f64,
+    },
+
+    /// Too many responses were unchanged from previous crawls.
+    UnchangedPages {
+        /// The percentage of responses that were unchanged.
+        percent: f64,
+
+        /// The maximum allowed.
+        maximum: f64,
+    },
+
+    /// A host responded too slowly.
+    SlowHost {
+        /// The host.
+        host: String,
+
+        /// The 95th percentile response time, in milliseconds.
+        p95_ms: u64,
+
+        /// The maximum allowed.
+        maximum_ms: u64,
+    },
+
+    /// The opportunities found had too low an average quality score.
+    LowQuality {
+        /// The average quality score.
+        average: f64,
+
+        /// The minimum allowed.
+        minimum: f64,
+
+        /// The number of opportunities scored.
+        opportunities: u64,
+    },
+}
+
+impl Default for HealthThresholds {
+    fn default() -> Self {
+        Self {
+            min_opportunities: Some(DEFAULT_MIN_OPPORTUNITIES),
+            max_error_rate_percent: Some(DEFAULT_MAX_ERROR_RATE_PERCENT),
+            max_unchanged_percent: None,
+            max_p95_response_ms: None,
+            min_average_quality: Some(DEFAULT_MIN_AVERAGE_QUALITY),
+        }
+    }
+}
+
+impl HealthThresholds {
+    /// Read thresholds, looking variables up with `lookup`, falling back to the defaults. Invalid values are
+    /// recorded in `errors`.
+    ///
+    /// Setting a variable to `off` disables the check.
+    pub(crate) fn from_lookup<F: Fn(&str) -> Option<String>>(lookup: &F, errors: &mut ConfigError) -> Self {
+        let defaults = Self::default();
+
+        Self {
+            min_opportunities: threshold(lookup, ENV_HEALTH_MIN_OPPORTUNITIES, defaults.min_opportunities, errors),
+            max_error_rate_percent: threshold(
+                lookup,
+                ENV_HEALTH_MAX_ERROR_RATE_PERCENT,
+                defaults.max_error_rate_percent,
+                errors,
+            ),
+            max_unchanged_percent: threshold(
+                lookup,
+                ENV_HEALTH_MAX_UNCHANGED_PERCENT,
+                defaults.max_unchanged_percent,
+                errors,
+            ),
+            max_p95_response_ms: threshold(
+                lookup,
+                ENV_HEALTH_MAX_P95_RESPONSE_MS,
+                defaults.max_p95_response_ms,
+                errors,
+            ),
+            min_average_quality: threshold(
+                lookup,
+                ENV_HEALTH_MIN_AVERAGE_QUALITY,
+                defaults.min_average_quality,
+                errors,
+            ),
+        }
+    }
+}
+
+impl Display for HealthViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::TooFewOpportunities {
+                found,
+                minimum,
+            } => write!(f, "found {found} opportunities; expected at least {minimum}"),
+            Self::ErrorRate {
+                percent,
+                maximum,
+            } => write!(f, "{percent:.1}% of requests failed; threshold is {maximum:.1}%"),
+            Self::UnchangedPages {
+                percent,
+                maximum,
+            } => write!(f, "{percent:.1}% of pages were unchanged; threshold is {maximum:.1}%"),
+            Self::SlowHost {
+                host,
+                p95_ms,
+                maximum_ms,
+            } => write!(f, "{host} p95 response time was {p95_ms} ms; threshold is {maximum_ms} ms"),
+            Self::LowQuality {
+                average,
+                minimum,
+                opportunities,
+            } => write!(
+                f,
+                "average quality score of {opportunities} opportunities was {average:.1}; threshold is {minimum:.1}"
+            ),
+        }
+    }
+}
+
+/// Read a threshold, returning `None` if it is `off` and the default if it is unset. An invalid value is recorded in
+/// `errors`, and the default returned.
+fn threshold<F: Fn(&str) -> Option<String>, T: std::str::FromStr + Copy>(
+    lookup: &F,
+    name: &str,
+    default: Option<T>,
+    errors: &mut ConfigError,
+) -> Option<T> {
+    let Some(value) = lookup(name) else {
+        return default;
+    };
+
+    if value.eq_ignore_ascii_case("off") {
+        return None;
+    }
+
+    match value.parse() {
+        Ok(threshold) => Some(threshold),
+        Err(_) => {
+            errors.push(name, format!("{value:?} is not a valid threshold or off"));
+            default
+        }
+    }
+}
+
+/// Evaluate the health checks for a completed crawl operation.
+pub fn evaluate(
+    stats: &CrawlStatsSnapshot,
+    opportunities: usize,
+    thresholds: &HealthThresholds,
+) -> Vec<HealthViolation> {
+    let mut violations = Vec::new();
+
+    if let Some(minimum) = thresholds.min_opportunities {
+        if opportunities < minimum {
+            violations.push(HealthViolation::TooFewOpportunities {
+                found: opportunities,
+                minimum,
+            });
+        }
+    }
+
+    // Rates are meaningless without any requests; a crawl that made none is caught by the opportunity check.
+    if stats.requests > 0 {
+        let error_percent = 100.0 * stats.errors as f64 / stats.requests as f64;
+        if let Some(maximum) = thresholds.max_error_rate_percent {
+            if error_percent > maximum {
+                violations.push(HealthViolation::ErrorRate {
+                    percent: error_percent,
+                    maximum,
+                });
+            }
+        }
+
+        let unchanged_percent = 100.0 * stats.unchanged as f64 / stats.requests as f64;
+        if let Some(maximum) = thresholds.max_unchanged_percent {
+            if unchanged_percent > maximum {
+                violations.push(HealthViolation::UnchangedPages {
+                    percent: unchanged_percent,
+                    maximum,
+                });
+            }
+        }
+    }
+
+    violations
+}
+
+/// Evaluate response times for a completed crawl operation, reporting hosts that are too slow.
+pub fn evaluate_latencies(latencies: &[HostLatency], thresholds: &HealthThresholds) -> Vec<HealthViolation> {
+    let Some(maximum_ms) = thresholds.max_p95_response_ms else {
+        return Vec::new();
+    };
+
+    latencies
+        .iter()
+        .filter(|latency| latency.p95_ms > maximum_ms)
+        .map(|latency| HealthViolation::SlowHost {
+            host: latency.host.clone(),
+            p95_ms: latency.p95_ms,
+            maximum_ms,
+        })
+        .collect()
+}
+
+/// Evaluate a crawl's running quality tally, reporting a violation if its average score is too low.
+///
+/// The average is only checked each time another [`QUALITY_SAMPLE_SIZE`] opportunities have been scored, so a few
+/// poor opportunities early in a crawl don't raise an alert and a persistently poor crawl doesn't raise one per
+/// opportunity.
+pub fn evaluate_quality(tally: &QualityTally, thresholds: &HealthThresholds) -> Option<HealthViolation> {
+    let minimum = thresholds.min_average_quality?;
+    if tally.opportunities == 0 || !tally.opportunities.is_multiple_of(QUALITY_SAMPLE_SIZE) {
+        return None;
+    }
+
+    let average = tally.average()?;
+    if average < minimum {
+        Some(HealthViolation::LowQuality {
+            average,
+            minimum,
+            opportunities: tally.opportunities,
+        })
+    } else {
+        None
+    }
+}
+
+/// Publish an alert.
+///
+/// If an alert topic is configured, the alert is published to that SNS topic; otherwise it is only logged.
+pub async fn publish_alert(log_config: &LogConfig, subject: &str, message: &str) -> Result<(), BoxError> {
+    error!("ALERT: {subject}: {message}");
+
+    let Some(topic_arn) = log_config.alert_topic_arn.as_deref() else {
+        return Ok(());
+    };
+
+    let subject: String = subject.chars().take(MAX_SNS_SUBJECT_LEN).collect();
+    match log_config.sns_client.publish().topic_arn(topic_arn).subject(subject).message(message).send().await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            error!("Failed to publish alert to {topic_arn}: {}", aws_err_str(&e));
+            Err(e.into())
+        }
+    }
+}
+
+/// Check the health of a completed crawl operation, alerting on any violations.
+///
+/// Alerting is best-effort: failures to publish are logged but do not fail the operation.
+pub async fn check_crawl_health(
+    log_config: &LogConfig,
+    portal: &str,
+    crawl_id: &str,
+    stats: &CrawlStatsSnapshot,
+    latencies: &[HostLatency],
+    opportunities: usize,
+) {
+    let mut response_times = String::new();
+    for latency in latencies {
+        info!(
+            "Crawl {crawl_id} on {portal}: {} responses from {}, p50 {} ms, p95 {} ms, max {} ms",
+            latency.requests, latency.host, latency.p50_ms, latency.p95_ms, latency.max_ms
+        );
+        response_times.push_str(&format!(
+            "{}: {} responses, p50 {} ms, p95 {} ms, max {} ms\n",
+            latency.host, latency.requests, latency.p50_ms, latency.p95_ms, latency.max_ms
+        ));
+        log_config
+            .journal
+            .record(
+                crawl_id,
+                CrawlEvent::HostResponseTimes {
+                    host: latency.host.clone(),
+                    requests: latency.requests,
+                    p50_ms: latency.p50_ms,
+                    p95_ms: latency.p95_ms,
+                    max_ms: latency.max_ms,
+                },
+            )
+            .await;
+    }
+
+    let mut violations = evaluate(stats, opportunities, &log_config.health_thresholds);
+    violations.extend(evaluate_latencies(latencies, &log_config.health_thresholds));
+    if violations.is_empty() {
+        info!(
+            "Crawl {crawl_id} on {portal} is healthy: {opportunities} opportunities, {} requests, {} errors, {} unchanged",
+            stats.requests, stats.errors, stats.unchanged
+        );
+        return;
+    }
+
+    let subject = format!("GovScout crawl health: {portal}");
+    let mut message = format!("Crawl {crawl_id} on {portal} failed {} health check(s):\n", violations.len());
+    for violation in violations.iter() {
+        message.push_str(&format!("- {violation}\n"));
+    }
+    message.push_str(&format!(
+        "\nRequests: {}\nErrors: {}\nUnchanged: {}\nOpportunities: {opportunities}\n",
+        stats.requests, stats.errors, stats.unchanged
+    ));
+    if !response_times.is_empty() {
+        message.push_str(&format!("\nResponse times:\n{response_times}"));
+    }
+
+    let _ = publish_alert(log_config, &subject, &message).await;
+}
+
+/// Check a crawl's running quality tally, alerting if its average score has dropped too low.
+///
+/// Alerting is best-effort: failures to publish are logged but do not fail the operation.
+pub async fn check_quality(log_config: &LogConfig, portal: &str, crawl_id: &str, tally: &QualityTally) {
+    let Some(violation) = evaluate_quality(tally, &log_config.health_thresholds) else {
+        return;
+    };
+
+    let subject = format!("GovScout data quality: {portal}");
+    let message = format!("Crawl {crawl_id} on {portal} failed a health check:\n- {violation}\n");
+    let _ = publish_alert(log_config, &subject, &message).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{evaluate, evaluate_latencies, evaluate_quality, HealthThresholds, HealthViolation},
+        crate::{
+            httpext::{CrawlStatsSnapshot, HostLatency},
+            quality::QualityTally,
+        },
+    };
+
+    #[test]
+    fn health_checks() {
+        let thresholds = HealthThresholds {
+            min_opportunities: Some(1),
+            max_error_rate_percent: Some(10.0),
+            max_unchanged_percent: Some(50.0),
+            max_p95_response_ms: Some(2000),
+            min_average_quality: Some(60.0),
+        };
+
+        let healthy = CrawlStatsSnapshot {
+            requests: 20,
+            errors: 2,
+            unchanged: 10,
+        };
+        assert!(evaluate(&healthy, 5, &thresholds).is_empty());
+
+        let unhealthy = CrawlStatsSnapshot {
+            requests: 20,
+            errors: 4,
+            unchanged: 15,
+        };
+        assert_eq!(
+            evaluate(&unhealthy, 0, &thresholds),
+            vec![
+                HealthViolation::TooFewOpportunities {
+                    found: 0,
+                    minimum: 1,
+                },
+                HealthViolation::ErrorRate {
+                    percent: 20.0,
+                    maximum: 10.0,
+                },
+                HealthViolation::UnchangedPages {
+                    percent: 75.0,
+                    maximum: 50.0,
+                },
+            ]
+        );
+
+        // Disabled checks never fire, and rates are skipped when no requests were made.
+        let disabled = HealthThresholds {
+            min_opportunities: None,
+            max_error_rate_percent: None,
+            max_unchanged_percent: None,
+            max_p95_response_ms: None,
+            min_average_quality: None,
+        };
+        assert!(evaluate(&unhealthy, 0, &disabled).is_empty());
+        assert!(evaluate(&CrawlStatsSnapshot::default(), 1, &thresholds).is_empty());
+
+        let latencies = [
+            HostLatency {
+                host: "slow.example.com".to_string(),
+                requests: 10,
+                p50_ms: 1500,
+                p95_ms: 4000,
+                max_ms: 4500,
+            },
+            HostLatency {
+                host: "fast.example.com".to_string(),
+                requests: 10,
+                p50_ms: 100,
+                p95_ms: 200,
+                max_ms: 300,
+            },
+        ];
+        assert_eq!(
+            evaluate_latencies(&latencies, &thresholds),
+            vec![HealthViolation::SlowHost {
+                host: "slow.example.com".to_string(),
+                p95_ms: 4000,
+                maximum_ms: 2000,
+            }]
+        );
+        assert!(evaluate_latencies(&latencies, &disabled).is_empty());
+
+        let poor = |opportunities| QualityTally {
+            opportunities,
+            total_score: opportunities * 50,
+        };
+        assert_eq!(
+            evaluate_quality(&poor(25), &thresholds),
+            Some(HealthViolation::LowQuality {
+                average: 50.0,
+                minimum: 60.0,
+                opportunities: 25,
+            })
+        );
+        assert_eq!(evaluate_quality(&poor(26), &thresholds), None);
+        assert_eq!(evaluate_quality(&poor(50), &disabled), None);
+        assert_eq!(
+            evaluate_quality(
+                &QualityTally {
+                    opportunities: 25,
+                    total_score: 25 * 90,
+                },
+                &thresholds
+            ),
+            None
+        );
+    }
+}
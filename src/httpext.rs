@@ -1,12 +1,29 @@
+mod allowlist;
+mod audit;
 mod awserr;
+mod batch_writer;
+mod body_crypto;
+mod body_index;
+mod body_memory;
+mod body_store;
 mod client;
 mod cookie_store;
+mod dns;
+mod fetch_error;
 mod form;
 mod logconfig;
+mod pacer;
 mod request;
 mod response;
+mod sniff;
+mod stats;
+mod transport;
 
-pub use {awserr::*, client::*, cookie_store::*, form::*, logconfig::*, request::*, response::*};
+pub use {
+    allowlist::*, audit::*, awserr::*, batch_writer::*, body_crypto::*, body_index::*, body_memory::*, body_store::*,
+    client::*, cookie_store::*, dns::*, fetch_error::*, form::*, logconfig::*, pacer::*, request::*, response::*,
+    sniff::*, stats::*, transport::*,
+};
 
 use reqwest::header::{HeaderMap, HeaderValue};
 
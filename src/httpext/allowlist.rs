@@ -0,0 +1,106 @@
+use {
+    reqwest::Url,
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+/// The hosts a client is permitted to contact during a crawl.
+///
+/// An empty allowlist permits every host. An entry beginning with `.` also permits subdomains of that domain.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct HostAllowlist {
+    hosts: Vec<String>,
+}
+
+impl HostAllowlist {
+    /// Create an allowlist of the given hosts.
+    pub fn new<I, S>(hosts: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut hosts: Vec<String> = hosts.into_iter().map(|host| host.as_ref().to_ascii_lowercase()).collect();
+        hosts.sort();
+        hosts.dedup();
+        Self {
+            hosts,
+        }
+    }
+
+    /// Create an allowlist of the hosts of the given URLs.
+    pub fn from_urls<'a, I>(urls: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Url>,
+    {
+        Self::new(urls.into_iter().filter_map(|url| url.host_str()))
+    }
+
+    /// Indicates whether this allowlist permits every host.
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    /// Indicates whether a request to `url` is permitted.
+    pub fn allows(&self, url: &Url) -> bool {
+        if self.hosts.is_empty() {
+            return true;
+        }
+
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+
+        self.hosts.iter().any(|allowed| match allowed.strip_prefix('.') {
+            Some(domain) => host == domain || host.strip_suffix(allowed.as_str()).is_some_and(|sub| !sub.is_empty()),
+            None => host == *allowed,
+        })
+    }
+}
+
+/// A request was refused because its host is not in the client's [`HostAllowlist`].
+#[derive(Debug)]
+pub struct HostNotAllowedError {
+    /// The URL that was refused.
+    pub url: Url,
+}
+
+impl HostNotAllowedError {
+    /// Create a new `HostNotAllowedError`.
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+        }
+    }
+}
+
+impl Display for HostNotAllowedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "Refusing request to {}: host is not in the crawl allowlist", self.url)
+    }
+}
+
+impl Error for HostNotAllowedError {}
+
+#[cfg(test)]
+mod tests {
+    use {super::HostAllowlist, reqwest::Url};
+
+    #[test]
+    fn allows() {
+        let url = |s: &str| Url::parse(s).unwrap();
+
+        let allowlist = HostAllowlist::new(["PR-WEBS-Vendor.des.wa.gov", ".example.com"]);
+        assert!(allowlist.allows(&url("https://pr-webs-vendor.des.wa.gov/Home.aspx")));
+        assert!(allowlist.allows(&url("https://example.com/")));
+        assert!(allowlist.allows(&url("https://www.example.com/")));
+        assert!(!allowlist.allows(&url("https://webs-vendor.des.wa.gov/")));
+        assert!(!allowlist.allows(&url("https://badexample.com/")));
+        assert!(!allowlist.allows(&url("https://pr-webs-vendor.des.wa.gov.attacker.net/")));
+        assert!(!allowlist.allows(&url("data:text/plain,hello")));
+
+        assert!(HostAllowlist::default().allows(&url("https://anywhere.net/")));
+    }
+}
@@ -0,0 +1,162 @@
+use {
+    crate::{crawl::CrawlRegistry, BoxError},
+    log::*,
+    parking_lot::Mutex,
+    reqwest::Url,
+    serde::{Deserialize, Serialize},
+    std::{collections::BTreeMap, sync::Arc},
+};
+
+/// Requests made to a single domain during a crawl.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DomainTraffic {
+    /// The domain (host) contacted.
+    pub domain: String,
+
+    /// The number of requests sent, including those that failed.
+    pub requests: u64,
+
+    /// The number of requests that failed before a complete response was received.
+    pub failures: u64,
+
+    /// The number of requests refused because the domain wasn't on the client's allowlist. These were never sent and
+    /// aren't counted in `requests`.
+    pub refused: u64,
+
+    /// The number of response body bytes received.
+    pub bytes: u64,
+}
+
+/// Audit of the domains contacted by each crawl, for compliance reviews.
+///
+/// When enabled, every outbound request made by a client with a [LogConfig][crate::httpext::LogConfig] is tallied by
+/// crawl and domain. The tallies are buffered and added to the crawl's record in the
+/// [crawl registry][CrawlRegistry] when [`flush`][RequestAudit::flush] is called at the end of an operation, so the
+/// report accumulates over every operation of the crawl.
+#[derive(Clone, Debug, Default)]
+pub struct RequestAudit {
+    /// Whether requests are tallied.
+    enabled: bool,
+
+    /// Buffered tallies, by crawl id and then domain.
+    pending: Arc<Mutex<BTreeMap<String, BTreeMap<String, DomainTraffic>>>>,
+}
+
+impl RequestAudit {
+    /// Create an audit, which records nothing unless `enabled` is set.
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            pending: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Record a response from `url` with a body of `bytes` bytes.
+    pub fn record_response(&self, crawl_id: &str, url: &Url, bytes: u64) {
+        self.record(crawl_id, url, |traffic| {
+            traffic.requests += 1;
+            traffic.bytes += bytes;
+        });
+    }
+
+    /// Record a request to `url` that failed before a complete response was received.
+    pub fn record_failure(&self, crawl_id: &str, url: &Url) {
+        self.record(crawl_id, url, |traffic| {
+            traffic.requests += 1;
+            traffic.failures += 1;
+        });
+    }
+
+    /// Record a request to `url` that was refused by the client's allowlist.
+    pub fn record_refused(&self, crawl_id: &str, url: &Url) {
+        self.record(crawl_id, url, |traffic| traffic.refused += 1);
+    }
+
+    fn record<F: FnOnce(&mut DomainTraffic)>(&self, crawl_id: &str, url: &Url, update: F) {
+        if !self.enabled {
+            return;
+        }
+
+        let domain = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        let mut pending = self.pending.lock();
+        let traffic =
+            pending.entry(crawl_id.to_string()).or_default().entry(domain.clone()).or_insert_with(|| DomainTraffic {
+                domain,
+                ..DomainTraffic::default()
+            });
+        update(traffic);
+    }
+
+    /// Remove and return the buffered tallies, by crawl id.
+    pub fn take(&self) -> BTreeMap<String, Vec<DomainTraffic>> {
+        let pending = std::mem::take(&mut *self.pending.lock());
+        pending.into_iter().map(|(crawl_id, domains)| (crawl_id, domains.into_values().collect())).collect()
+    }
+
+    /// Add the buffered tallies to the crawls' records.
+    ///
+    /// Every tally is attempted; if any fail, the last error is returned and the failed tallies are lost.
+    pub async fn flush(&self, crawls: &CrawlRegistry) -> Result<(), BoxError> {
+        let mut result = Ok(());
+        for (crawl_id, domains) in self.take() {
+            for traffic in domains.iter() {
+                if let Err(e) = crawls.add_domain_traffic(&crawl_id, traffic).await {
+                    warn!("Failed to audit {} traffic for crawl {crawl_id}: {e}", traffic.domain);
+                    result = Err(e);
+                }
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{DomainTraffic, RequestAudit},
+        reqwest::Url,
+    };
+
+    #[test]
+    fn tallies() {
+        let portal = Url::parse("https://Bids.Example.gov/listing").unwrap();
+        let other = Url::parse("https://cdn.example.com/app.js").unwrap();
+
+        let audit = RequestAudit::new(true);
+        audit.record_response("crawl", &portal, 100);
+        audit.record_response("crawl", &portal, 50);
+        audit.record_failure("crawl", &portal);
+        audit.record_refused("crawl", &other);
+        audit.record_response("other", &portal, 10);
+
+        let report = audit.take();
+        assert_eq!(
+            report["crawl"],
+            vec![
+                DomainTraffic {
+                    domain: "bids.example.gov".to_string(),
+                    requests: 3,
+                    failures: 1,
+                    refused: 0,
+                    bytes: 150,
+                },
+                DomainTraffic {
+                    domain: "cdn.example.com".to_string(),
+                    requests: 0,
+                    failures: 0,
+                    refused: 1,
+                    bytes: 0,
+                },
+            ]
+        );
+        assert_eq!(report["other"][0].bytes, 10);
+        assert!(audit.take().is_empty());
+
+        // A disabled audit records nothing.
+        let audit = RequestAudit::new(false);
+        audit.record_response("crawl", &portal, 100);
+        assert!(audit.take().is_empty());
+    }
+}
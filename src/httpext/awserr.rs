@@ -1,4 +1,41 @@
-use {aws_smithy_runtime_api::client::result::SdkError, log::error, std::fmt::Debug};
+use {
+    aws_smithy_runtime_api::client::result::SdkError,
+    log::error,
+    std::{
+        error::Error,
+        fmt::{Debug, Display, Formatter, Result as FmtResult},
+    },
+};
+
+/// Error returned when an AWS API response is missing a field we rely on.
+///
+/// Some S3-compatible services omit fields such as `ETag` that AWS always returns.
+#[derive(Debug)]
+pub struct MissingFieldError {
+    /// The API operation that was called.
+    pub operation: &'static str,
+
+    /// The name of the missing field.
+    pub field: &'static str,
+}
+
+impl MissingFieldError {
+    /// Create a new `MissingFieldError`.
+    pub fn new(operation: &'static str, field: &'static str) -> Self {
+        Self {
+            operation,
+            field,
+        }
+    }
+}
+
+impl Display for MissingFieldError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{} response is missing {}", self.operation, self.field)
+    }
+}
+
+impl Error for MissingFieldError {}
 
 /// If the result of an AWS API call is an error, log the result.
 ///
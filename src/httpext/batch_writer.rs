@@ -0,0 +1,670 @@
+use {
+    crate::{
+        classification::{Classification, ObjectTags},
+        httpext::{aws_err_str, log_aws_err},
+        metrics, BoxError,
+    },
+    aws_sdk_dynamodb::{
+        error::ProvideErrorMetadata,
+        primitives::Blob,
+        types::{AttributeValue, PutRequest, WriteRequest},
+        Client as DynamoDbClient,
+    },
+    aws_sdk_s3::{primitives::ByteStream, Client as S3Client},
+    aws_smithy_runtime_api::client::result::SdkError,
+    base64::prelude::*,
+    log::*,
+    parking_lot::Mutex,
+    serde_json::{json, Map, Value},
+    std::{collections::HashMap, sync::Arc, time::Duration},
+    uuid::{NoContext, Timestamp, Uuid},
+};
+
+/// Maximum number of items DynamoDB accepts in a single `BatchWriteItem` call.
+pub const MAX_BATCH_WRITE_ITEMS: usize = 25;
+
+/// Maximum number of times unprocessed items are resubmitted before giving up.
+const MAX_UNPROCESSED_RETRIES: u32 = 8;
+
+/// Initial delay before resubmitting unprocessed items. This doubles on each retry.
+const UNPROCESSED_RETRY_BASE_DELAY: Duration = Duration::from_millis(50);
+
+/// Pacing delay applied after the first throttling error; this doubles on each further throttle.
+const THROTTLE_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// Upper bound on the pacing delay between batches.
+const THROTTLE_MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// Maximum number of items held back in memory while a table keeps throttling writes. Beyond it, the held-back items
+/// are spilled to S3.
+const MAX_DEFERRED_ITEMS: usize = 1000;
+
+/// Prefix of objects holding spilled items, relative to the S3 prefix; the table name and a unique id follow it.
+const SPILL_S3_PREFIX: &str = "ddb-spill/";
+
+/// Error codes DynamoDB answers with when it is throttling requests: the table or index is over its provisioned
+/// throughput, the account is over its request quota, or the control plane is throttling.
+const THROTTLE_ERROR_CODES: &[&str] =
+    &["ProvisionedThroughputExceededException", "RequestLimitExceeded", "ThrottlingException"];
+
+const DDB_KEY_CRAWL_ID: &str = "CrawlId";
+const DDB_KEY_REQUEST_ID: &str = "RequestId";
+
+const METRIC_DYNAMODB_THROTTLES: &str = "DynamoDbThrottles";
+const METRIC_DYNAMODB_WRITES_DEFERRED: &str = "DynamoDbWritesDeferred";
+const METRIC_DYNAMODB_WRITES_SPILLED: &str = "DynamoDbWritesSpilled";
+const DIMENSION_TABLE: &str = "Table";
+
+/// Where a writer spills the items a throttling table kept refusing, so they can be
+/// [replayed][DdbBatchWriter::replay_spills] once it has capacity again.
+#[derive(Clone, Debug)]
+pub struct WriteSpill {
+    /// The S3 client to use.
+    s3_client: S3Client,
+
+    /// The bucket spilled items are written to.
+    bucket: String,
+
+    /// The S3 prefix spill objects are written under.
+    s3_prefix: String,
+}
+
+impl WriteSpill {
+    /// Spill items to `bucket`, under `s3_prefix`.
+    pub fn new<S: Into<String>>(s3_client: S3Client, bucket: S, s3_prefix: S) -> Self {
+        Self {
+            s3_client,
+            bucket: bucket.into(),
+            s3_prefix: s3_prefix.into(),
+        }
+    }
+}
+
+/// A buffered DynamoDB writer that groups `PutItem` requests into `BatchWriteItem` calls.
+///
+/// Clones of a writer share the same buffer, so every [`LogConfig`][crate::httpext::LogConfig] clone within a
+/// Lambda invocation feeds the same batch. Items are written when a full batch accumulates and when
+/// [`flush`][DdbBatchWriter::flush] is called at the end of an operation.
+///
+/// A throttling table is written to ever more slowly. If it keeps refusing a batch after every retry, the batch is held
+/// back in memory and tried again on the next flush; if it is still refused then, or too many items are held back, the
+/// items are spilled to S3 when the writer has a [`WriteSpill`], rather than failing the operation. Throttles,
+/// held-back items, and spilled items are counted in metrics by table. Items a write fails for any other reason aren't
+/// dropped either: a failed batch is held back for the next flush, and the items of a failed flush are spilled, or
+/// held back if there is nowhere to spill them, before the error is returned.
+#[derive(Clone, Debug)]
+pub struct DdbBatchWriter {
+    /// The DynamoDB client to use.
+    ddb_client: DynamoDbClient,
+
+    /// The table to write to.
+    table_name: String,
+
+    /// Items that have been queued but not yet written.
+    pending: Arc<Mutex<Vec<WriteRequest>>>,
+
+    /// Delay applied before each batch while the table is throttling us. This grows when DynamoDB reports
+    /// `ProvisionedThroughputExceeded` and decays as batches succeed.
+    pacing: Arc<Mutex<Duration>>,
+
+    /// Items the table kept refusing, to be tried again on the next flush.
+    deferred: Arc<Mutex<Vec<WriteRequest>>>,
+
+    /// Where items are spilled if the table keeps refusing them, or `None` to fail instead.
+    spill: Option<WriteSpill>,
+}
+
+impl DdbBatchWriter {
+    /// Create a new batch writer for the given table.
+    pub fn new<S: Into<String>>(ddb_client: DynamoDbClient, table_name: S) -> Self {
+        Self {
+            ddb_client,
+            table_name: table_name.into(),
+            pending: Arc::new(Mutex::new(Vec::with_capacity(MAX_BATCH_WRITE_ITEMS))),
+            pacing: Arc::new(Mutex::new(Duration::ZERO)),
+            deferred: Arc::new(Mutex::new(Vec::new())),
+            spill: None,
+        }
+    }
+
+    /// Spill items the table keeps refusing to S3 instead of failing.
+    pub fn with_spill(mut self, spill: WriteSpill) -> Self {
+        self.spill = Some(spill);
+        self
+    }
+
+    /// The table this writer writes to.
+    #[inline(always)]
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    /// Return the number of items queued or held back but not yet written.
+    pub fn len(&self) -> usize {
+        self.pending.lock().len() + self.deferred.lock().len()
+    }
+
+    /// Indicates whether there are no items waiting to be written.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Queue an item to be written. If a full batch has accumulated, it is written immediately.
+    ///
+    /// An item replaces any queued item with the same key, since DynamoDB refuses a batch that writes a key twice.
+    pub async fn put(&self, item: HashMap<String, AttributeValue>) -> Result<(), BoxError> {
+        let put_request = PutRequest::builder().set_item(Some(item)).build()?;
+        let write_request = WriteRequest::builder().put_request(put_request).build();
+
+        let batch = {
+            let mut pending = self.pending.lock();
+            if let Some(queued) = pending.iter_mut().find(|queued| same_key(queued, &write_request)) {
+                *queued = write_request;
+                return Ok(());
+            }
+            pending.push(write_request);
+            if pending.len() >= MAX_BATCH_WRITE_ITEMS {
+                pending.drain(..MAX_BATCH_WRITE_ITEMS).collect::<Vec<_>>()
+            } else {
+                return Ok(());
+            }
+        };
+
+        match self.write_batch(batch).await {
+            Ok(unwritten) => self.defer(unwritten).await,
+            Err((e, unwritten)) => {
+                // Hold the batch back for the next flush rather than lose it.
+                self.defer(unwritten).await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Write all queued and held-back items.
+    pub async fn flush(&self) -> Result<(), BoxError> {
+        let mut items = std::mem::take(&mut *self.deferred.lock());
+        items.append(&mut self.pending.lock());
+        let items = dedup_by_key(items);
+
+        let mut batches = items.chunks(MAX_BATCH_WRITE_ITEMS);
+        while let Some(batch) = batches.next() {
+            match self.write_batch(batch.to_vec()).await {
+                Ok(unwritten) if unwritten.is_empty() => (),
+                Ok(mut unwritten) => {
+                    // The table is still throttling; spill the rest rather than spend the invocation retrying it.
+                    unwritten.extend(batches.flatten().cloned());
+                    return self.spill(unwritten).await;
+                }
+                Err((e, mut unwritten)) => {
+                    unwritten.extend(batches.flatten().cloned());
+                    self.keep(unwritten).await?;
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the items spilled for this writer's table, deleting each spill object once its items are written, and
+    /// return the number of items replayed. Items the table refuses again are spilled again.
+    pub async fn replay_spills(&self) -> Result<usize, BoxError> {
+        let Some(spill) = self.spill.as_ref() else {
+            return Ok(0);
+        };
+
+        let prefix = self.spill_prefix(spill);
+        let mut keys = Vec::new();
+        let mut continuation_token = None;
+        loop {
+            let output = log_aws_err(
+                spill
+                    .s3_client
+                    .list_objects_v2()
+                    .bucket(&spill.bucket)
+                    .prefix(&prefix)
+                    .set_continuation_token(continuation_token)
+                    .send()
+                    .await,
+                &format!("ListObjectsV2 s3://{}/{prefix}", spill.bucket),
+            )?;
+            keys.extend(output.contents().iter().filter_map(|object| object.key()).map(str::to_string));
+            continuation_token = output.next_continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+
+        let mut replayed = 0;
+        for key in keys {
+            let output = log_aws_err(
+                spill.s3_client.get_object().bucket(&spill.bucket).key(&key).send().await,
+                &format!("GetObject s3://{}/{key}", spill.bucket),
+            )?;
+            let body = output.body.collect().await?.into_bytes();
+            for line in body.split(|b| *b == b'\n').filter(|line| !line.is_empty()) {
+                self.put(item_from_json(&serde_json::from_slice(line)?)?).await?;
+                replayed += 1;
+            }
+            self.flush().await?;
+
+            log_aws_err(
+                spill.s3_client.delete_object().bucket(&spill.bucket).key(&key).send().await,
+                &format!("DeleteObject s3://{}/{key}", spill.bucket),
+            )?;
+            info!("Replayed spilled items for {} from s3://{}/{key}", self.table_name, spill.bucket);
+        }
+
+        Ok(replayed)
+    }
+
+    /// Return the current pacing delay between batches.
+    pub fn pacing_delay(&self) -> Duration {
+        *self.pacing.lock()
+    }
+
+    /// Write a single batch of at most [`MAX_BATCH_WRITE_ITEMS`] items, resubmitting unprocessed items with
+    /// exponential backoff. Returns the items the table was still refusing after every retry, or on any other error,
+    /// the error along with the items not yet written.
+    async fn write_batch(
+        &self,
+        mut batch: Vec<WriteRequest>,
+    ) -> Result<Vec<WriteRequest>, (BoxError, Vec<WriteRequest>)> {
+        let mut delay = UNPROCESSED_RETRY_BASE_DELAY;
+
+        for attempt in 0..=MAX_UNPROCESSED_RETRIES {
+            if attempt > 0 {
+                debug!("Resubmitting {} unprocessed items to {} after {delay:?}", batch.len(), self.table_name);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+
+            let pacing = self.pacing_delay();
+            if !pacing.is_zero() {
+                tokio::time::sleep(pacing).await;
+            }
+
+            let item_count = batch.len();
+            let result = self.ddb_client.batch_write_item().request_items(&self.table_name, batch.clone()).send().await;
+
+            let output = match result {
+                Ok(output) => output,
+                Err(ref e) if is_throttle(e) => {
+                    record_throttle(&self.table_name);
+                    let pacing = self.increase_pacing();
+                    warn!("BatchWriteItem to {} throttled; pacing writes at {pacing:?}", self.table_name);
+                    continue;
+                }
+                Err(e) => {
+                    error!("BatchWriteItem: {}", aws_err_str(&e));
+                    return Err((e.into(), batch));
+                }
+            };
+
+            let unprocessed =
+                output.unprocessed_items.and_then(|mut items| items.remove(&self.table_name)).unwrap_or_default();
+
+            if unprocessed.is_empty() {
+                debug!("Wrote {item_count} items to {}", self.table_name);
+                self.decrease_pacing();
+                return Ok(vec![]);
+            }
+
+            // Unprocessed items are DynamoDB's signal that a partition is running hot.
+            record_throttle(&self.table_name);
+            self.increase_pacing();
+            batch = unprocessed;
+        }
+
+        warn!("BatchWriteItem to {} left {} items unprocessed after retries", self.table_name, batch.len());
+        Ok(batch)
+    }
+
+    /// Hold back items the table kept refusing until the next flush, spilling them once too many are held back.
+    async fn defer(&self, items: Vec<WriteRequest>) -> Result<(), BoxError> {
+        if items.is_empty() {
+            return Ok(());
+        }
+
+        metrics::registry().increment(
+            METRIC_DYNAMODB_WRITES_DEFERRED,
+            &[(DIMENSION_TABLE, &self.table_name)],
+            items.len() as u64,
+        );
+        let overflow = {
+            let mut deferred = self.deferred.lock();
+            deferred.extend(items);
+            if deferred.len() <= MAX_DEFERRED_ITEMS {
+                return Ok(());
+            }
+            std::mem::take(&mut *deferred)
+        };
+
+        self.spill(overflow).await
+    }
+
+    /// Keep items a failed write left unwritten: spill them if the writer can, otherwise hold them back for the next
+    /// flush.
+    async fn keep(&self, items: Vec<WriteRequest>) -> Result<(), BoxError> {
+        if self.spill.is_some() {
+            self.spill(items).await
+        } else {
+            self.deferred.lock().extend(items);
+            Ok(())
+        }
+    }
+
+    /// Return the prefix of the objects this writer's items are spilled to.
+    fn spill_prefix(&self, spill: &WriteSpill) -> String {
+        format!("{}{SPILL_S3_PREFIX}{}/", spill.s3_prefix, self.table_name)
+    }
+
+    /// Write items the table kept refusing to S3 for replay, or fail if the writer has nowhere to spill them.
+    async fn spill(&self, items: Vec<WriteRequest>) -> Result<(), BoxError> {
+        let Some(spill) = self.spill.as_ref() else {
+            return Err(format!(
+                "BatchWriteItem to {} left {} items unprocessed after retries",
+                self.table_name,
+                items.len()
+            )
+            .into());
+        };
+
+        let mut body = Vec::new();
+        for put_request in items.iter().filter_map(|item| item.put_request()) {
+            serde_json::to_writer(&mut body, &item_to_json(put_request.item())?)?;
+            body.push(b'\n');
+        }
+
+        let key = format!("{}{}.jsonl", self.spill_prefix(spill), Uuid::new_v7(Timestamp::now(NoContext)));
+        log_aws_err(
+            spill
+                .s3_client
+                .put_object()
+                .bucket(&spill.bucket)
+                .key(&key)
+                .content_type("application/x-ndjson")
+                .tagging(ObjectTags::new(Classification::CrawlMetadata).to_tagging())
+                .body(ByteStream::from(body))
+                .send()
+                .await,
+            &format!("PutObject s3://{}/{key}", spill.bucket),
+        )?;
+
+        metrics::registry().increment(
+            METRIC_DYNAMODB_WRITES_SPILLED,
+            &[(DIMENSION_TABLE, &self.table_name)],
+            items.len() as u64,
+        );
+        warn!("{} is throttling writes; spilled {} items to s3://{}/{key}", self.table_name, items.len(), spill.bucket);
+        Ok(())
+    }
+
+    /// Double the pacing delay (starting from [`THROTTLE_BASE_DELAY`]), returning the new value.
+    fn increase_pacing(&self) -> Duration {
+        let mut pacing = self.pacing.lock();
+        *pacing = if pacing.is_zero() {
+            THROTTLE_BASE_DELAY
+        } else {
+            (*pacing * 2).min(THROTTLE_MAX_DELAY)
+        };
+        *pacing
+    }
+
+    /// Halve the pacing delay, dropping it to zero once it falls below [`THROTTLE_BASE_DELAY`].
+    fn decrease_pacing(&self) {
+        let mut pacing = self.pacing.lock();
+        *pacing /= 2;
+        if *pacing < THROTTLE_BASE_DELAY {
+            *pacing = Duration::ZERO;
+        }
+    }
+}
+
+/// Indicates whether DynamoDB refused a call because it is throttling requests.
+pub fn is_throttle<E: ProvideErrorMetadata, R>(e: &SdkError<E, R>) -> bool {
+    match e {
+        SdkError::ServiceError(e) => e.err().code().is_some_and(|code| THROTTLE_ERROR_CODES.contains(&code)),
+        _ => false,
+    }
+}
+
+/// Indicates whether two write requests put items with the same key.
+fn same_key(a: &WriteRequest, b: &WriteRequest) -> bool {
+    put_key(a).is_some() && put_key(a) == put_key(b)
+}
+
+/// Return the key of the item a write request puts, or `None` if it isn't a put.
+fn put_key(request: &WriteRequest) -> Option<(Option<&AttributeValue>, Option<&AttributeValue>)> {
+    let item = request.put_request()?.item();
+    Some((item.get(DDB_KEY_CRAWL_ID), item.get(DDB_KEY_REQUEST_ID)))
+}
+
+/// Remove write requests whose key a later request writes again, keeping the order of the rest.
+fn dedup_by_key(items: Vec<WriteRequest>) -> Vec<WriteRequest> {
+    let mut unique: Vec<WriteRequest> = Vec::with_capacity(items.len());
+    for item in items {
+        match unique.iter_mut().find(|kept| same_key(kept, &item)) {
+            Some(kept) => *kept = item,
+            None => unique.push(item),
+        }
+    }
+
+    unique
+}
+
+/// Count a write throttled by `table`.
+pub fn record_throttle(table: &str) {
+    metrics::registry().increment(METRIC_DYNAMODB_THROTTLES, &[(DIMENSION_TABLE, table)], 1);
+}
+
+/// Return an item in DynamoDB's JSON format, e.g. `{"CrawlId": {"S": "crawl"}}`.
+fn item_to_json(item: &HashMap<String, AttributeValue>) -> Result<Value, BoxError> {
+    let mut object = Map::new();
+    for (name, value) in item {
+        object.insert(name.clone(), attribute_to_json(value)?);
+    }
+
+    Ok(Value::Object(object))
+}
+
+/// Return an attribute value in DynamoDB's JSON format.
+fn attribute_to_json(value: &AttributeValue) -> Result<Value, BoxError> {
+    let base64 = |blob: &Blob| BASE64_STANDARD.encode(blob.as_ref());
+
+    Ok(match value {
+        AttributeValue::S(s) => json!({"S": s}),
+        AttributeValue::N(n) => json!({"N": n}),
+        AttributeValue::B(b) => json!({"B": base64(b)}),
+        AttributeValue::Bool(b) => json!({"BOOL": b}),
+        AttributeValue::Null(is_null) => json!({"NULL": is_null}),
+        AttributeValue::Ss(ss) => json!({"SS": ss}),
+        AttributeValue::Ns(ns) => json!({"NS": ns}),
+        AttributeValue::Bs(bs) => json!({"BS": bs.iter().map(base64).collect::<Vec<_>>()}),
+        AttributeValue::L(l) => json!({"L": l.iter().map(attribute_to_json).collect::<Result<Vec<_>, _>>()?}),
+        AttributeValue::M(m) => json!({"M": item_to_json(m)?}),
+        _ => return Err(format!("Unsupported attribute value: {value:?}").into()),
+    })
+}
+
+/// Read an item in DynamoDB's JSON format.
+fn item_from_json(value: &Value) -> Result<HashMap<String, AttributeValue>, BoxError> {
+    let Some(object) = value.as_object() else {
+        return Err(format!("Invalid item: {value}").into());
+    };
+
+    object.iter().map(|(name, value)| Ok((name.clone(), attribute_from_json(value)?))).collect()
+}
+
+/// Read an attribute value in DynamoDB's JSON format.
+fn attribute_from_json(value: &Value) -> Result<AttributeValue, BoxError> {
+    let invalid = || -> BoxError { format!("Invalid attribute value: {value}").into() };
+    let Some((kind, inner)) =
+        value.as_object().filter(|object| object.len() == 1).and_then(|object| object.iter().next())
+    else {
+        return Err(invalid());
+    };
+
+    let string = |value: &Value| value.as_str().map(str::to_string).ok_or_else(invalid);
+    let blob = |value: &Value| -> Result<Blob, BoxError> { Ok(Blob::new(BASE64_STANDARD.decode(string(value)?)?)) };
+    let list = || inner.as_array().ok_or_else(invalid);
+
+    Ok(match kind.as_str() {
+        "S" => AttributeValue::S(string(inner)?),
+        "N" => AttributeValue::N(string(inner)?),
+        "B" => AttributeValue::B(blob(inner)?),
+        "BOOL" => AttributeValue::Bool(inner.as_bool().ok_or_else(invalid)?),
+        "NULL" => AttributeValue::Null(inner.as_bool().ok_or_else(invalid)?),
+        "SS" => AttributeValue::Ss(list()?.iter().map(string).collect::<Result<_, _>>()?),
+        "NS" => AttributeValue::Ns(list()?.iter().map(string).collect::<Result<_, _>>()?),
+        "BS" => AttributeValue::Bs(list()?.iter().map(blob).collect::<Result<_, _>>()?),
+        "L" => AttributeValue::L(list()?.iter().map(attribute_from_json).collect::<Result<_, _>>()?),
+        "M" => AttributeValue::M(item_from_json(inner)?),
+        _ => return Err(invalid()),
+    })
+}
+
+/// Return the partition key value to use for an item belonging to `crawl_id`.
+///
+/// With a single shard this is just the crawl id. Otherwise, a shard suffix derived from `shard_source` (typically
+/// the random bits of the request's UUID) is appended so that a single large crawl is spread across `shards`
+/// partitions.
+pub fn sharded_partition_key(crawl_id: &str, shard_source: u128, shards: u32) -> String {
+    if shards <= 1 {
+        crawl_id.to_string()
+    } else {
+        let shard = shard_source % u128::from(shards);
+        format!("{crawl_id}#{shard}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            dedup_by_key, is_throttle, item_from_json, item_to_json, sharded_partition_key, DdbBatchWriter,
+            MAX_BATCH_WRITE_ITEMS,
+        },
+        aws_sdk_dynamodb::{
+            config::{BehaviorVersion, Credentials, Region},
+            error::ErrorMetadata,
+            operation::batch_write_item::BatchWriteItemError,
+            primitives::Blob,
+            types::{AttributeValue, PutRequest, WriteRequest},
+            Client as DynamoDbClient, Config,
+        },
+        aws_sdk_s3::primitives::SdkBody,
+        aws_smithy_runtime_api::{
+            client::result::SdkError,
+            http::{Response as HttpResponse, StatusCode},
+        },
+        httpmock::{Method::POST, MockServer},
+        serde_json::json,
+        std::collections::HashMap,
+    };
+
+    #[test]
+    fn throttles() {
+        let error = |code: &str| {
+            SdkError::service_error(
+                BatchWriteItemError::generic(ErrorMetadata::builder().code(code).build()),
+                HttpResponse::new(StatusCode::try_from(400).unwrap(), SdkBody::empty()),
+            )
+        };
+
+        assert!(is_throttle(&error("ProvisionedThroughputExceededException")));
+        assert!(is_throttle(&error("RequestLimitExceeded")));
+        assert!(is_throttle(&error("ThrottlingException")));
+        assert!(!is_throttle(&error("ValidationException")));
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        let write = |request: &str, status: &str| {
+            let item = HashMap::from([
+                ("CrawlId".to_string(), AttributeValue::S("crawl".to_string())),
+                ("RequestId".to_string(), AttributeValue::S(request.to_string())),
+                ("Status".to_string(), AttributeValue::N(status.to_string())),
+            ]);
+            WriteRequest::builder().put_request(PutRequest::builder().set_item(Some(item)).build().unwrap()).build()
+        };
+
+        let items = dedup_by_key(vec![write("a", "500"), write("b", "200"), write("a", "503")]);
+        assert_eq!(items, vec![write("a", "503"), write("b", "200")]);
+    }
+
+    #[tokio::test]
+    async fn failed_writes() {
+        let server = MockServer::start_async().await;
+        let batch_write_item = server
+            .mock_async(|when, then| {
+                when.method(POST).header("x-amz-target", "DynamoDB_20120810.BatchWriteItem");
+                then.status(400).header("Content-Type", "application/x-amz-json-1.0").json_body(json!({
+                    "__type": "com.amazon.coral.validate#ValidationException",
+                    "message": "One or more parameter values were invalid",
+                }));
+            })
+            .await;
+        let client = DynamoDbClient::from_conf(
+            Config::builder()
+                .behavior_version(BehaviorVersion::latest())
+                .region(Region::new("us-west-2"))
+                .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+                .endpoint_url(server.base_url())
+                .build(),
+        );
+
+        let writer = DdbBatchWriter::new(client, "log");
+        for i in 0..MAX_BATCH_WRITE_ITEMS + 2 {
+            let item = HashMap::from([
+                ("CrawlId".to_string(), AttributeValue::S("crawl".to_string())),
+                ("RequestId".to_string(), AttributeValue::S(format!("request-{i}"))),
+            ]);
+            let result = writer.put(item).await;
+            assert_eq!(result.is_err(), i == MAX_BATCH_WRITE_ITEMS - 1);
+        }
+
+        // Neither the failed batch nor the items queued after it are lost, whether put or flush fails.
+        assert_eq!(writer.len(), MAX_BATCH_WRITE_ITEMS + 2);
+        assert!(writer.flush().await.is_err());
+        assert_eq!(writer.len(), MAX_BATCH_WRITE_ITEMS + 2);
+        assert_eq!(batch_write_item.hits_async().await, 2);
+    }
+
+    #[test]
+    fn spilled_items() {
+        let item = HashMap::from([
+            ("CrawlId".to_string(), AttributeValue::S("crawl".to_string())),
+            ("Timestamp".to_string(), AttributeValue::N("1714000000.5".to_string())),
+            ("Body".to_string(), AttributeValue::B(Blob::new(b"\x00gz".to_vec()))),
+            ("Redirected".to_string(), AttributeValue::Bool(false)),
+            ("Account".to_string(), AttributeValue::Null(true)),
+            ("Hosts".to_string(), AttributeValue::Ss(vec!["example.gov".to_string()])),
+            (
+                "Attempts".to_string(),
+                AttributeValue::L(vec![AttributeValue::M(HashMap::from([(
+                    "Codes".to_string(),
+                    AttributeValue::Ns(vec!["503".to_string()]),
+                )]))]),
+            ),
+        ]);
+
+        let json = item_to_json(&item).unwrap();
+        assert_eq!(json["CrawlId"], json!({"S": "crawl"}));
+        assert_eq!(json["Body"], json!({"B": "AGd6"}));
+        assert_eq!(json["Attempts"], json!({"L": [{"M": {"Codes": {"NS": ["503"]}}}]}));
+        assert_eq!(item_from_json(&json).unwrap(), item);
+
+        assert!(item_from_json(&json!({"CrawlId": {"S": "crawl", "N": "1"}})).is_err());
+        assert!(item_from_json(&json!({"CrawlId": {"X": "crawl"}})).is_err());
+        assert!(item_from_json(&json!({"Body": {"B": "not base64!"}})).is_err());
+    }
+
+    #[test]
+    fn partition_key_sharding() {
+        assert_eq!(sharded_partition_key("crawl", 12345, 1).as_str(), "crawl");
+        assert_eq!(sharded_partition_key("crawl", 12345, 0).as_str(), "crawl");
+        assert_eq!(sharded_partition_key("crawl", 12345, 4).as_str(), "crawl#1");
+        assert_eq!(sharded_partition_key("crawl", 12347, 4).as_str(), "crawl#3");
+    }
+}
@@ -0,0 +1,235 @@
+//! Client-side encryption of stored bodies.
+//!
+//! Some authenticated portals' terms require what is fetched from them to be encrypted by the crawler, not only by S3
+//! at rest. Bodies from a portal with a KMS key configured in `LOG_S3_ENCRYPTION_KEYS` (e.g.
+//! `PublicPurchase=alias/govscout-publicpurchase,BidNet=alias/govscout-bidnet`) are sealed with envelope encryption:
+//!
+//! * a fresh AES-256 data key is generated by KMS for every body, bound to the body's SHA-256 digest as the
+//!   encryption context;
+//! * the (compressed) body is sealed with AES-256-GCM under the data key, and stored as the nonce followed by the
+//!   ciphertext and tag;
+//! * the data key, encrypted by KMS, is kept in the object's metadata along with the key id and digest.
+//!
+//! Bodies are deduplicated before they are sealed, so a body already stored as it is (e.g. a public page also fetched
+//! by another portal's crawl) is not stored again. The key a body was sealed under is recorded as `KmsKeyId` on its log
+//! item and body index item.
+//!
+//! [`load_body`][crate::httpext::load_body] reverses this using KMS `Decrypt`, so reading a body back needs
+//! `kms:Decrypt` on its key. The KMS client is built from the S3 client's configuration, so the role that writes bodies
+//! is the one granted use of the keys.
+use {
+    crate::{httpext::log_aws_err, BoxError},
+    aws_sdk_kms::{primitives::Blob, types::DataKeySpec, Client as KmsClient},
+    base64::prelude::*,
+    bytes::Bytes,
+    ring::{
+        aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+        rand::{SecureRandom, SystemRandom},
+    },
+    std::{
+        collections::HashMap,
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+/// Object metadata holding the KMS key id a body was encrypted under.
+pub const META_KMS_KEY_ID: &str = "govscout-kms-key-id";
+
+/// Object metadata holding the base64-encoded data key, encrypted by KMS.
+pub const META_DATA_KEY: &str = "govscout-data-key";
+
+/// Object metadata holding the hex-encoded SHA-256 digest of the body, which is the encryption context.
+pub const META_SHA256: &str = "govscout-sha256";
+
+const ENCRYPTION_CONTEXT_KEY: &str = "Body";
+
+/// A body could not be sealed or opened.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BodyCryptoError {
+    /// What went wrong.
+    pub message: String,
+}
+
+impl BodyCryptoError {
+    fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for BodyCryptoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "Body encryption: {}", self.message)
+    }
+}
+
+impl Error for BodyCryptoError {}
+
+/// A data key generated by KMS.
+pub struct DataKey {
+    /// The data key, used to seal one body and then dropped.
+    pub plaintext: Vec<u8>,
+
+    /// The data key encrypted under the KMS key, stored with the body.
+    pub ciphertext: Vec<u8>,
+
+    /// The ARN of the KMS key that encrypted the data key.
+    pub key_id: String,
+}
+
+/// Generate a data key from the KMS key `key_id` for sealing the body with the given SHA-256 digest.
+async fn generate_data_key(kms: &KmsClient, key_id: &str, sha256_hex: &str) -> Result<DataKey, BoxError> {
+    let output = log_aws_err(
+        kms.generate_data_key()
+            .key_id(key_id)
+            .key_spec(DataKeySpec::Aes256)
+            .encryption_context(ENCRYPTION_CONTEXT_KEY, sha256_hex)
+            .send()
+            .await,
+        &format!("GenerateDataKey with {key_id}"),
+    )?;
+
+    let (Some(plaintext), Some(ciphertext), Some(arn)) = (output.plaintext, output.ciphertext_blob, output.key_id)
+    else {
+        return Err(BodyCryptoError::new(format!("GenerateDataKey with {key_id} returned no data key")).into());
+    };
+
+    Ok(DataKey {
+        plaintext: plaintext.into_inner(),
+        ciphertext: ciphertext.into_inner(),
+        key_id: arn,
+    })
+}
+
+/// Decrypt the data key a body with the given SHA-256 digest was sealed with.
+async fn decrypt_data_key(kms: &KmsClient, ciphertext: &[u8], sha256_hex: &str) -> Result<Vec<u8>, BoxError> {
+    let output = log_aws_err(
+        kms.decrypt()
+            .ciphertext_blob(Blob::new(ciphertext))
+            .encryption_context(ENCRYPTION_CONTEXT_KEY, sha256_hex)
+            .send()
+            .await,
+        &format!("Decrypt data key of body {sha256_hex}"),
+    )?;
+
+    match output.plaintext {
+        Some(plaintext) => Ok(plaintext.into_inner()),
+        None => {
+            Err(BodyCryptoError::new(format!("Decrypt of body {sha256_hex} data key returned no plaintext")).into())
+        }
+    }
+}
+
+/// A body sealed under a fresh data key, ready to be stored.
+pub struct SealedBody {
+    /// The nonce, ciphertext, and tag.
+    pub payload: Bytes,
+
+    /// Object metadata needed to open the body again.
+    pub metadata: HashMap<String, String>,
+
+    /// The ARN of the KMS key the body was sealed under.
+    pub key_id: String,
+}
+
+/// Seal a (compressed) body with a fresh data key from the KMS key `key_id`.
+pub async fn seal_body(
+    kms: &KmsClient,
+    key_id: &str,
+    payload: &Bytes,
+    sha256_hex: &str,
+) -> Result<SealedBody, BoxError> {
+    let data_key = generate_data_key(kms, key_id, sha256_hex).await?;
+    let payload = seal(&data_key.plaintext, payload, sha256_hex)?;
+    let metadata = HashMap::from([
+        (META_KMS_KEY_ID.to_string(), data_key.key_id.clone()),
+        (META_DATA_KEY.to_string(), BASE64_STANDARD.encode(&data_key.ciphertext)),
+        (META_SHA256.to_string(), sha256_hex.to_string()),
+    ]);
+
+    Ok(SealedBody {
+        payload,
+        metadata,
+        key_id: data_key.key_id,
+    })
+}
+
+/// Open a stored object if its metadata shows it was sealed, returning the (compressed) body. Objects stored without
+/// encryption are returned as they are.
+pub async fn open_body(kms: &KmsClient, payload: Bytes, metadata: &HashMap<String, String>) -> Result<Bytes, BoxError> {
+    let Some(data_key) = metadata.get(META_DATA_KEY) else {
+        return Ok(payload);
+    };
+
+    let sha256_hex = metadata.get(META_SHA256).ok_or_else(|| BodyCryptoError::new("object has no digest metadata"))?;
+    let data_key = decrypt_data_key(kms, &BASE64_STANDARD.decode(data_key)?, sha256_hex).await?;
+    Ok(open(&data_key, &payload, sha256_hex)?)
+}
+
+/// Seal a payload with AES-256-GCM, authenticating the body's digest as associated data.
+fn seal(key: &[u8], payload: &[u8], sha256_hex: &str) -> Result<Bytes, BodyCryptoError> {
+    let key = aead_key(key)?;
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new().fill(&mut nonce).map_err(|_| BodyCryptoError::new("failed to generate a nonce"))?;
+
+    let mut sealed = payload.to_vec();
+    key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(sha256_hex.as_bytes()), &mut sealed)
+        .map_err(|_| BodyCryptoError::new("failed to seal body"))?;
+
+    let mut result = Vec::with_capacity(NONCE_LEN + sealed.len());
+    result.extend_from_slice(&nonce);
+    result.extend_from_slice(&sealed);
+    Ok(result.into())
+}
+
+/// Open a payload sealed by [`seal`].
+fn open(key: &[u8], sealed: &[u8], sha256_hex: &str) -> Result<Bytes, BodyCryptoError> {
+    let key = aead_key(key)?;
+    if sealed.len() < NONCE_LEN {
+        return Err(BodyCryptoError::new("sealed body is truncated"));
+    }
+
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| BodyCryptoError::new("invalid nonce"))?;
+    let mut opened = ciphertext.to_vec();
+    let plaintext = key
+        .open_in_place(nonce, Aad::from(sha256_hex.as_bytes()), &mut opened)
+        .map_err(|_| BodyCryptoError::new("body failed authentication"))?;
+    Ok(Bytes::copy_from_slice(plaintext))
+}
+
+/// Return an AES-256-GCM key.
+fn aead_key(key: &[u8]) -> Result<LessSafeKey, BodyCryptoError> {
+    let key = UnboundKey::new(&AES_256_GCM, key).map_err(|_| BodyCryptoError::new("invalid data key"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{open, seal, NONCE_LEN};
+
+    #[test]
+    fn seal_and_open() {
+        let key = [7u8; 32];
+        let body = b"<html>Bid 24-001</html>";
+
+        let sealed = seal(&key, body, "abcd").unwrap();
+        assert_eq!(sealed.len(), NONCE_LEN + body.len() + 16);
+        assert!(!sealed.windows(body.len()).any(|window| window == body));
+        assert_eq!(open(&key, &sealed, "abcd").unwrap().as_ref(), body);
+
+        // Each body gets its own nonce.
+        assert_ne!(seal(&key, body, "abcd").unwrap(), sealed);
+
+        // The wrong key, digest, or a tampered payload fail authentication.
+        assert!(open(&[8u8; 32], &sealed, "abcd").is_err());
+        assert!(open(&key, &sealed, "abce").is_err());
+        let mut tampered = sealed.to_vec();
+        tampered[NONCE_LEN] ^= 1;
+        assert!(open(&key, &tampered, "abcd").is_err());
+        assert!(open(&key, &sealed[..4], "abcd").is_err());
+        assert!(open(&[7u8; 16], &sealed, "abcd").is_err());
+    }
+}
@@ -0,0 +1,459 @@
+use {
+    crate::{
+        classification::{Classification, DDB_KEY_CLASSIFICATION},
+        httpext::{aws_err_str, log_aws_err, BodyCompression, StoredBody},
+        metrics, BoxError,
+    },
+    aws_sdk_dynamodb::{
+        operation::put_item::PutItemError,
+        types::{AttributeValue, KeysAndAttributes},
+        Client as DynamoDbClient,
+    },
+    aws_smithy_runtime_api::client::result::SdkError,
+    log::*,
+    parking_lot::Mutex,
+    std::{
+        collections::{HashMap, HashSet},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    },
+    tokio::sync::oneshot,
+};
+
+/// Partition key prefix for the SHA-256 to S3 key index items in the log table.
+const BODY_INDEX_PREFIX: &str = "Body#";
+
+/// Sort key value for the SHA-256 to S3 key index items in the log table.
+const BODY_INDEX_SORT_KEY: &str = "Body";
+
+const DDB_KEY_CRAWL_ID: &str = "CrawlId";
+const DDB_KEY_REQUEST_ID: &str = "RequestId";
+const DDB_KEY_ETAG: &str = "Etag";
+const DDB_KEY_S3_BUCKET: &str = "S3Bucket";
+const DDB_KEY_S3_KEY: &str = "S3Key";
+const DDB_KEY_COMPRESSION: &str = "Compression";
+const DDB_KEY_KMS_KEY_ID: &str = "KmsKeyId";
+
+/// Bodies that may be pending at once before lookups are batched.
+pub const DEFAULT_BODY_LOOKUP_BATCH_THRESHOLD: usize = 4;
+
+/// How long a batched lookup waits for others to join it before it is sent.
+const BATCH_LINGER: Duration = Duration::from_millis(5);
+
+/// The most keys DynamoDB accepts in one BatchGetItem call.
+const MAX_BATCH_GET_KEYS: usize = 100;
+
+const METRIC_BODY_LOOKUPS_BATCHED: &str = "BodyLookupsBatched";
+
+/// A lookup waiting to be sent in a batch, and where to send its result.
+type PendingLookup = (String, oneshot::Sender<Result<Option<StoredBody>, String>>);
+
+/// The index of stored bodies by SHA-256 digest, kept in the log table.
+///
+/// The digest of a body stored for a portal with an encryption key is suffixed with a digest of the key id, so the
+/// index never returns a copy stored in plaintext or under another key; see [`store_body`][crate::httpext::store_body].
+///
+/// Each body's existing copy is normally looked up on its own. While more than the batch threshold of bodies are being
+/// stored at once, as when the records of an SQS batch fetch concurrently, lookups are gathered for a few milliseconds
+/// and made with one BatchGetItem call.
+#[derive(Clone, Debug)]
+pub struct BodyIndex {
+    ddb_client: DynamoDbClient,
+    table_name: String,
+    batch_threshold: usize,
+    pending_bodies: Arc<AtomicUsize>,
+    queued: Arc<Mutex<Vec<PendingLookup>>>,
+}
+
+/// A body being stored, counted as pending until it is dropped.
+#[derive(Debug)]
+pub struct PendingBody<'a> {
+    index: &'a BodyIndex,
+}
+
+impl BodyIndex {
+    /// Create a body index in the given table, batching lookups while more than
+    /// [`DEFAULT_BODY_LOOKUP_BATCH_THRESHOLD`] bodies are pending.
+    pub fn new(ddb_client: DynamoDbClient, table_name: impl Into<String>) -> Self {
+        Self {
+            ddb_client,
+            table_name: table_name.into(),
+            batch_threshold: DEFAULT_BODY_LOOKUP_BATCH_THRESHOLD,
+            pending_bodies: Arc::new(AtomicUsize::new(0)),
+            queued: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Batch lookups while more than `batch_threshold` bodies are pending.
+    pub fn with_batch_threshold(mut self, batch_threshold: usize) -> Self {
+        self.batch_threshold = batch_threshold;
+        self
+    }
+
+    /// The DynamoDB table the index is kept in.
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    /// Start storing a body. It counts towards the batch threshold until the returned guard is dropped.
+    pub fn pending(&self) -> PendingBody<'_> {
+        self.pending_bodies.fetch_add(1, Ordering::SeqCst);
+        PendingBody {
+            index: self,
+        }
+    }
+
+    /// Record the location of a stored body.
+    ///
+    /// If another invocation indexed the same body concurrently, its entry is kept.
+    pub async fn insert(&self, sha256_hex: &str, stored: &StoredBody) -> Result<(), BoxError> {
+        let mut put = self
+            .ddb_client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(index_key(sha256_hex)))
+            .item(DDB_KEY_S3_BUCKET, AttributeValue::S(stored.bucket.clone()))
+            .item(DDB_KEY_S3_KEY, AttributeValue::S(stored.key.clone()))
+            .item(DDB_KEY_ETAG, AttributeValue::S(stored.etag.clone()))
+            .item(DDB_KEY_COMPRESSION, AttributeValue::S(stored.compression.as_str().to_string()))
+            .item(DDB_KEY_CLASSIFICATION, AttributeValue::S(Classification::RawCrawlData.to_string()))
+            .condition_expression("attribute_not_exists(#pk)")
+            .expression_attribute_names("#pk", DDB_KEY_CRAWL_ID);
+        if let Some(kms_key_id) = stored.kms_key_id.as_ref() {
+            put = put.item(DDB_KEY_KMS_KEY_ID, AttributeValue::S(kms_key_id.clone()));
+        }
+
+        match put.send().await {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError(ref e))
+                if matches!(e.err(), PutItemError::ConditionalCheckFailedException(_)) =>
+            {
+                debug!("Body {sha256_hex} was indexed concurrently");
+                Ok(())
+            }
+            Err(e) => {
+                error!("PutItem body index: {}", aws_err_str(&e));
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Look up a single body.
+    async fn get(&self, sha256_hex: &str) -> Result<Option<StoredBody>, BoxError> {
+        let get_item = log_aws_err(
+            self.ddb_client
+                .get_item()
+                .table_name(&self.table_name)
+                .set_key(Some(index_key(sha256_hex)))
+                .consistent_read(true)
+                .send()
+                .await,
+            "GetItem body index",
+        )?;
+
+        match get_item.item {
+            Some(item) => indexed_body(sha256_hex, &item),
+            None => Ok(None),
+        }
+    }
+
+    /// Queue a lookup to be made in a batch, sending the batch if this lookup fills it or is still queued once the
+    /// others have had time to join.
+    async fn get_batched(&self, sha256_hex: &str) -> Result<Option<StoredBody>, BoxError> {
+        let (sender, receiver) = oneshot::channel();
+        let full = {
+            let mut queued = self.queued.lock();
+            queued.push((sha256_hex.to_string(), sender));
+            queued.len() >= MAX_BATCH_GET_KEYS
+        };
+
+        if !full {
+            tokio::time::sleep(BATCH_LINGER).await;
+        }
+
+        // Whichever lookup finds the queue non-empty sends everything in it; the rest just wait for their results.
+        let batch = std::mem::take(&mut *self.queued.lock());
+        if !batch.is_empty() {
+            self.get_batch(batch).await;
+        }
+
+        match receiver.await {
+            Ok(result) => result.map_err(Into::into),
+            Err(_) => Err("Batched body index lookup was abandoned".into()),
+        }
+    }
+
+    /// Look up a batch of bodies with BatchGetItem calls of at most [`MAX_BATCH_GET_KEYS`] keys, sending each lookup
+    /// its result. Keys DynamoDB leaves unprocessed are looked up on their own.
+    async fn get_batch(&self, batch: Vec<PendingLookup>) {
+        let digests: HashSet<&str> = batch.iter().map(|(sha256_hex, _)| sha256_hex.as_str()).collect();
+        let digests: Vec<&str> = digests.into_iter().collect();
+        metrics::registry().increment(METRIC_BODY_LOOKUPS_BATCHED, &[], batch.len() as u64);
+        debug!("Looking up {} bodies in batches", digests.len());
+
+        let mut found: HashMap<String, Result<Option<StoredBody>, String>> = HashMap::new();
+        for chunk in digests.chunks(MAX_BATCH_GET_KEYS) {
+            if let Err(e) = self.get_chunk(chunk, &mut found).await {
+                for sha256_hex in chunk {
+                    found.insert(sha256_hex.to_string(), Err(e.clone()));
+                }
+            }
+        }
+
+        for (sha256_hex, sender) in batch {
+            // Digests not in the response aren't indexed.
+            let _ = sender.send(found.get(&sha256_hex).cloned().unwrap_or(Ok(None)));
+        }
+    }
+
+    /// Look up at most [`MAX_BATCH_GET_KEYS`] bodies with one BatchGetItem call, recording what's found in `found`.
+    async fn get_chunk(
+        &self,
+        digests: &[&str],
+        found: &mut HashMap<String, Result<Option<StoredBody>, String>>,
+    ) -> Result<(), String> {
+        let keys = KeysAndAttributes::builder()
+            .set_keys(Some(digests.iter().map(|sha256_hex| index_key(sha256_hex)).collect()))
+            .consistent_read(true)
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        let output = log_aws_err(
+            self.ddb_client.batch_get_item().request_items(&self.table_name, keys).send().await,
+            "BatchGetItem body index",
+        )
+        .map_err(|e| aws_err_str(&e))?;
+
+        for item in output.responses.unwrap_or_default().remove(&self.table_name).unwrap_or_default() {
+            if let Some(sha256_hex) = item_digest(&item) {
+                found.insert(sha256_hex.to_string(), indexed_body(sha256_hex, &item).map_err(|e| e.to_string()));
+            }
+        }
+
+        let unprocessed: Vec<String> = output
+            .unprocessed_keys
+            .unwrap_or_default()
+            .remove(&self.table_name)
+            .map(|keys| keys.keys().iter().filter_map(item_digest).map(str::to_string).collect())
+            .unwrap_or_default();
+        for sha256_hex in unprocessed {
+            let result = self.get(&sha256_hex).await.map_err(|e| e.to_string());
+            found.insert(sha256_hex, result);
+        }
+
+        Ok(())
+    }
+}
+
+impl PendingBody<'_> {
+    /// Whether lookups are being batched, because more than the batch threshold of bodies are pending.
+    pub fn batched(&self) -> bool {
+        self.index.pending_bodies.load(Ordering::SeqCst) > self.index.batch_threshold
+    }
+
+    /// Look up where a body with the given SHA-256 digest is stored, if it has been indexed.
+    pub async fn find(&self, sha256_hex: &str) -> Result<Option<StoredBody>, BoxError> {
+        if self.batched() {
+            self.index.get_batched(sha256_hex).await
+        } else {
+            self.index.get(sha256_hex).await
+        }
+    }
+}
+
+impl Drop for PendingBody<'_> {
+    fn drop(&mut self) {
+        self.index.pending_bodies.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The key of the index item for a body.
+fn index_key(sha256_hex: &str) -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        (DDB_KEY_CRAWL_ID.to_string(), AttributeValue::S(format!("{BODY_INDEX_PREFIX}{sha256_hex}"))),
+        (DDB_KEY_REQUEST_ID.to_string(), AttributeValue::S(BODY_INDEX_SORT_KEY.to_string())),
+    ])
+}
+
+/// The digest of the body an index item or key is for.
+fn item_digest(item: &HashMap<String, AttributeValue>) -> Option<&str> {
+    item.get(DDB_KEY_CRAWL_ID)?.as_s().ok()?.strip_prefix(BODY_INDEX_PREFIX)
+}
+
+/// Read where a body is stored from its index item.
+fn indexed_body(sha256_hex: &str, item: &HashMap<String, AttributeValue>) -> Result<Option<StoredBody>, BoxError> {
+    let get_str = |name: &str| item.get(name).and_then(|v| v.as_s().ok()).cloned();
+
+    // Index items written before bodies could be compressed have no compression recorded.
+    let compression = match get_str(DDB_KEY_COMPRESSION) {
+        Some(compression) => compression.parse()?,
+        None => BodyCompression::None,
+    };
+
+    match (get_str(DDB_KEY_S3_BUCKET), get_str(DDB_KEY_S3_KEY), get_str(DDB_KEY_ETAG)) {
+        (Some(bucket), Some(key), Some(etag)) => Ok(Some(StoredBody {
+            bucket,
+            key,
+            etag,
+            existing: true,
+            compression,
+            kms_key_id: get_str(DDB_KEY_KMS_KEY_ID),
+        })),
+        _ => {
+            warn!("Body index item for {sha256_hex} is incomplete; storing body again");
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{BodyIndex, MAX_BATCH_GET_KEYS},
+        aws_sdk_dynamodb::{
+            config::{BehaviorVersion, Credentials, Region},
+            Client as DynamoDbClient, Config,
+        },
+        futures::future::join_all,
+        httpmock::{Method::POST, MockServer},
+        serde_json::json,
+        std::time::{Duration, Instant},
+        tokio::sync::oneshot,
+    };
+
+    const BATCH_GET_ITEM: &str = "DynamoDB_20120810.BatchGetItem";
+    const GET_ITEM: &str = "DynamoDB_20120810.GetItem";
+
+    /// A DynamoDB client sending its calls to a mock server.
+    fn mock_client(server: &MockServer) -> DynamoDbClient {
+        DynamoDbClient::from_conf(
+            Config::builder()
+                .behavior_version(BehaviorVersion::latest())
+                .region(Region::new("us-west-2"))
+                .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+                .endpoint_url(server.base_url())
+                .build(),
+        )
+    }
+
+    /// Store `count` bodies at once through the index, each holding its lookup for as long as a write would take.
+    async fn store_concurrently(index: &BodyIndex, count: usize) -> Vec<bool> {
+        join_all((0..count).map(|i| async move {
+            let pending = index.pending();
+            let found = pending.find(&format!("{i:064x}")).await.unwrap().is_some();
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            found
+        }))
+        .await
+    }
+
+    #[tokio::test]
+    async fn batched_lookups() {
+        let server = MockServer::start_async().await;
+        let get_item = server
+            .mock_async(|when, then| {
+                when.method(POST).header("x-amz-target", GET_ITEM);
+                then.status(200).header("Content-Type", "application/x-amz-json-1.0").body("{}");
+            })
+            .await;
+        let stored = json!({
+            "CrawlId": {"S": format!("Body#{:064x}", 3)},
+            "RequestId": {"S": "Body"},
+            "S3Bucket": {"S": "bucket"},
+            "S3Key": {"S": format!("{:064x}", 3)},
+            "Etag": {"S": "\"etag\""},
+            "Compression": {"S": "zstd"},
+        });
+        let batch_get_item = server
+            .mock_async(|when, then| {
+                when.method(POST).header("x-amz-target", BATCH_GET_ITEM);
+                then.status(200)
+                    .header("Content-Type", "application/x-amz-json-1.0")
+                    .json_body(json!({"Responses": {"log": [stored]}, "UnprocessedKeys": {}}));
+            })
+            .await;
+
+        // Up to the threshold, each body is looked up on its own.
+        let index = BodyIndex::new(mock_client(&server), "log").with_batch_threshold(2);
+        assert_eq!(store_concurrently(&index, 2).await, vec![false, false]);
+        assert_eq!(get_item.hits_async().await, 2);
+        assert_eq!(batch_get_item.hits_async().await, 0);
+
+        // Past it, the rest are looked up together.
+        let found = store_concurrently(&index, 8).await;
+        assert_eq!(found.iter().filter(|found| **found).count(), 1);
+        assert!(found[3]);
+        assert_eq!(get_item.hits_async().await, 4);
+        assert_eq!(batch_get_item.hits_async().await, 1);
+    }
+
+    #[tokio::test]
+    async fn large_batches() {
+        let server = MockServer::start_async().await;
+        let batch_get_item = server
+            .mock_async(|when, then| {
+                when.method(POST).header("x-amz-target", BATCH_GET_ITEM);
+                then.status(200)
+                    .header("Content-Type", "application/x-amz-json-1.0")
+                    .json_body(json!({"Responses": {"log": []}, "UnprocessedKeys": {}}));
+            })
+            .await;
+
+        // A batch of more lookups than BatchGetItem accepts keys is sent in several calls.
+        let index = BodyIndex::new(mock_client(&server), "log");
+        let (batch, receivers): (Vec<_>, Vec<_>) = (0..MAX_BATCH_GET_KEYS * 2 + 1)
+            .map(|i| {
+                let (sender, receiver) = oneshot::channel();
+                ((format!("{i:064x}"), sender), receiver)
+            })
+            .unzip();
+        index.get_batch(batch).await;
+
+        assert_eq!(batch_get_item.hits_async().await, 3);
+        for receiver in receivers {
+            assert_eq!(receiver.await.unwrap(), Ok(None));
+        }
+    }
+
+    /// Compare looking up each pending body on its own with batching lookups, against an index answering each call
+    /// after a delay like DynamoDB's.
+    ///
+    /// Run with `cargo test --release body_lookup_benchmark -- --ignored --nocapture`.
+    #[tokio::test]
+    #[ignore]
+    async fn body_lookup_benchmark() {
+        const BODIES: usize = 50;
+        const LATENCY: Duration = Duration::from_millis(8);
+
+        let server = MockServer::start_async().await;
+        for (name, threshold) in [("unbatched", usize::MAX), ("batched", 4)] {
+            let get_item = server
+                .mock_async(|when, then| {
+                    when.method(POST).header("x-amz-target", GET_ITEM);
+                    then.status(200).header("Content-Type", "application/x-amz-json-1.0").body("{}").delay(LATENCY);
+                })
+                .await;
+            let batch_get_item = server
+                .mock_async(|when, then| {
+                    when.method(POST).header("x-amz-target", BATCH_GET_ITEM);
+                    then.status(200)
+                        .header("Content-Type", "application/x-amz-json-1.0")
+                        .json_body(json!({"Responses": {"log": []}, "UnprocessedKeys": {}}))
+                        .delay(LATENCY);
+                })
+                .await;
+
+            let index = BodyIndex::new(mock_client(&server), "log").with_batch_threshold(threshold);
+            let started = Instant::now();
+            store_concurrently(&index, BODIES).await;
+            let elapsed = started.elapsed();
+            let calls = get_item.hits_async().await + batch_get_item.hits_async().await;
+            println!("{name}: {BODIES} lookups in {elapsed:?} ({:?} per body), {calls} calls", elapsed / BODIES as u32);
+            get_item.delete_async().await;
+            batch_get_item.delete_async().await;
+        }
+    }
+}
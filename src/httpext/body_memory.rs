@@ -0,0 +1,179 @@
+use {
+    log::*,
+    parking_lot::Mutex,
+    std::{sync::Arc, time::Duration},
+    tokio::{sync::Notify, time::Instant},
+};
+
+/// The longest a fetch waits for other response bodies to be released before going ahead anyway.
+///
+/// An operation may itself hold a response (such as a listing page) while fetching the pages it links to, so waiting
+/// indefinitely could deadlock an invocation whose bodies are all held by waiting operations.
+pub const MAX_ADMIT_WAIT: Duration = Duration::from_secs(30);
+
+/// Accounting of the response body bytes held in memory across the concurrent operations of an invocation.
+///
+/// Every [Response][crate::httpext::Response] from a client with a [LogConfig][crate::httpext::LogConfig] holds its
+/// body's bytes against the budget until it is dropped. Before a request is sent, [`admit`][BodyMemory::admit] waits
+/// while the bytes held are over the limit, so a batch of records that all hit large pages applies backpressure
+/// instead of exhausting the Lambda's memory.
+#[derive(Clone, Debug, Default)]
+pub struct BodyMemory {
+    /// The shared budget, or `None` if body memory is unlimited.
+    budget: Option<Arc<Budget>>,
+}
+
+#[derive(Debug)]
+struct Budget {
+    /// The number of bytes above which new fetches wait.
+    limit: usize,
+
+    /// The number of bytes currently held.
+    held: Mutex<usize>,
+
+    /// Notified whenever bytes are released.
+    released: Notify,
+}
+
+impl BodyMemory {
+    /// Create an account with the given limit in bytes, or an unlimited one if `limit` is `None`.
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            budget: limit.map(|limit| {
+                Arc::new(Budget {
+                    limit,
+                    held: Mutex::new(0),
+                    released: Notify::new(),
+                })
+            }),
+        }
+    }
+
+    /// Return the limit in bytes, or `None` if body memory is unlimited.
+    pub fn limit(&self) -> Option<usize> {
+        self.budget.as_ref().map(|budget| budget.limit)
+    }
+
+    /// Return the number of body bytes currently held.
+    pub fn held(&self) -> usize {
+        self.budget.as_ref().map(|budget| *budget.held.lock()).unwrap_or_default()
+    }
+
+    /// Wait until the bytes held are under the limit, or for at most [`MAX_ADMIT_WAIT`].
+    pub async fn admit(&self) {
+        self.admit_within(MAX_ADMIT_WAIT).await;
+    }
+
+    /// Wait until the bytes held are under the limit, or for at most `max_wait`. Returns `false` if the wait timed out.
+    async fn admit_within(&self, max_wait: Duration) -> bool {
+        let Some(budget) = self.budget.as_ref() else {
+            return true;
+        };
+
+        let deadline = Instant::now() + max_wait;
+        let mut waited = false;
+
+        loop {
+            // Register for the notification before checking, so a release in between isn't missed.
+            let released = budget.released.notified();
+            let held = *budget.held.lock();
+            if held < budget.limit {
+                return true;
+            }
+
+            if !waited {
+                debug!("{held} bytes of response bodies held (limit {}); waiting before fetching", budget.limit);
+                waited = true;
+            }
+
+            if tokio::time::timeout_at(deadline, released).await.is_err() {
+                warn!(
+                    "{} bytes of response bodies still held after {} s (limit {}); fetching anyway",
+                    *budget.held.lock(),
+                    max_wait.as_secs(),
+                    budget.limit
+                );
+                return false;
+            }
+        }
+    }
+
+    /// Start holding a response body, initially empty.
+    pub fn hold(&self) -> BodyHold {
+        BodyHold {
+            budget: self.budget.clone(),
+            bytes: 0,
+        }
+    }
+}
+
+/// Body bytes held against a [BodyMemory] budget, released when this is dropped.
+#[derive(Debug, Default)]
+pub struct BodyHold {
+    budget: Option<Arc<Budget>>,
+    bytes: usize,
+}
+
+impl BodyHold {
+    /// Hold another `bytes` bytes.
+    pub fn add(&mut self, bytes: usize) {
+        self.bytes += bytes;
+        if let Some(budget) = self.budget.as_ref() {
+            *budget.held.lock() += bytes;
+        }
+    }
+
+    /// Return the number of bytes held.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+impl Drop for BodyHold {
+    fn drop(&mut self) {
+        if let Some(budget) = self.budget.as_ref() {
+            let mut held = budget.held.lock();
+            *held = held.saturating_sub(self.bytes);
+            drop(held);
+            budget.released.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::BodyMemory, std::time::Duration};
+
+    #[tokio::test(start_paused = true)]
+    async fn backpressure() {
+        let memory = BodyMemory::new(Some(1000));
+        assert!(memory.admit_within(Duration::from_secs(1)).await);
+
+        let mut small = memory.hold();
+        small.add(400);
+        let mut large = memory.hold();
+        large.add(700);
+        assert_eq!(memory.held(), 1100);
+
+        // Over the limit, a fetch waits until enough is released...
+        let (admitted, _) = tokio::join!(memory.admit_within(Duration::from_secs(30)), async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            drop(large);
+        });
+        assert!(admitted);
+        assert_eq!(memory.held(), 400);
+
+        // ...but not forever.
+        small.add(600);
+        assert!(!memory.admit_within(Duration::from_secs(1)).await);
+        drop(small);
+        assert_eq!(memory.held(), 0);
+
+        // Unlimited memory never waits, and holds are free.
+        let unlimited = BodyMemory::default();
+        let mut hold = unlimited.hold();
+        hold.add(1 << 30);
+        assert_eq!(unlimited.held(), 0);
+        assert!(unlimited.admit_within(Duration::ZERO).await);
+    }
+}
@@ -0,0 +1,686 @@
+use {
+    crate::{
+        classification::{Classification, ObjectTags},
+        httpext::{log_aws_err, open_body, seal_body, LogConfig, MissingFieldError, META_KMS_KEY_ID},
+        metrics, BoxError,
+    },
+    aws_sdk_s3::{
+        error::ProvideErrorMetadata,
+        operation::head_object::HeadObjectError,
+        primitives::{ByteStream, SdkBody},
+        types::{ChecksumAlgorithm, ChecksumMode, Tagging},
+    },
+    aws_smithy_runtime_api::{client::result::SdkError, http::Response as HttpResponse},
+    bytes::Bytes,
+    chrono::{DateTime, Utc},
+    http_body_util::Full,
+    log::*,
+    serde::{Deserialize, Serialize},
+    sha2::{Digest, Sha256},
+    std::{
+        collections::HashMap,
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        future::Future,
+        str::FromStr,
+        time::Duration,
+    },
+};
+
+/// The default S3 key template: purely content-addressed by SHA-256.
+pub const DEFAULT_S3_KEY_TEMPLATE: &str = "{sha256}";
+
+/// Portal name substituted into key templates when the client does not know which portal it is crawling.
+const UNKNOWN_PORTAL: &str = "unknown";
+const SHARED_TENANT: &str = "shared";
+
+/// The zstd compression level for stored bodies. Low levels compress HTML well while keeping CPU time in the Lambda
+/// small.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Attempts made at storing a body while S3 asks us to slow down, the first included. These are on top of the SDK's own
+/// retries, so a burst of throttling outlasting those doesn't fail the request's log record.
+const S3_SLOW_DOWN_ATTEMPTS: u32 = 5;
+
+/// Delay before retrying a call S3 asked us to slow down for; this doubles on each further retry.
+const S3_SLOW_DOWN_BASE_DELAY: Duration = Duration::from_millis(250);
+
+/// The error code S3 throttles requests with.
+const S3_SLOW_DOWN_CODE: &str = "SlowDown";
+
+const METRIC_S3_SLOW_DOWNS: &str = "S3SlowDowns";
+
+/// Object metadata holding when a stored body was redacted; see [`replace_body`].
+pub const META_REDACTED_AT: &str = "govscout-redacted-at";
+
+/// How stored bodies are compressed at rest.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum BodyCompression {
+    /// Bodies are stored as received.
+    #[default]
+    None,
+
+    /// Bodies are compressed with Zstandard. The object's `Content-Encoding` is `zstd`.
+    Zstd,
+}
+
+impl BodyCompression {
+    /// Return the name of the compression, as recorded in DynamoDB and accepted in configuration.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    /// Return the `Content-Encoding` of objects stored with this compression, if any.
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Zstd => Some("zstd"),
+        }
+    }
+
+    /// Return the compression indicated by an object's `Content-Encoding`.
+    pub fn from_content_encoding(content_encoding: Option<&str>) -> Result<Self, String> {
+        match content_encoding {
+            None | Some("") | Some("identity") => Ok(Self::None),
+            Some(encoding) => encoding.parse(),
+        }
+    }
+
+    /// Compress a body.
+    pub fn compress(&self, body: &Bytes) -> Result<Bytes, BoxError> {
+        match self {
+            Self::None => Ok(body.clone()),
+            Self::Zstd => Ok(zstd::bulk::compress(body, ZSTD_LEVEL)?.into()),
+        }
+    }
+
+    /// Decompress a body compressed by [`compress`][Self::compress].
+    pub fn decompress(&self, payload: &Bytes) -> Result<Bytes, BoxError> {
+        match self {
+            Self::None => Ok(payload.clone()),
+            Self::Zstd => Ok(zstd::stream::decode_all(payload.as_ref())?.into()),
+        }
+    }
+}
+
+impl FromStr for BodyCompression {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(Self::None),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err(format!("Unknown body compression: {value}")),
+        }
+    }
+}
+
+impl Display for BodyCompression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The location of a body stored in S3.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StoredBody {
+    /// The S3 bucket holding the body.
+    pub bucket: String,
+
+    /// The S3 key of the body.
+    pub key: String,
+
+    /// The ETag S3 reported for the body.
+    pub etag: String,
+
+    /// Whether an identical body had already been stored.
+    pub existing: bool,
+
+    /// How the stored object is compressed. An identical body stored earlier keeps the compression it was stored
+    /// with.
+    pub compression: BodyCompression,
+
+    /// The KMS key the stored object is encrypted under, if it is; see [`seal_body`]. An identical body stored earlier
+    /// keeps the encryption it was stored with.
+    pub kms_key_id: Option<String>,
+}
+
+/// The SHA-256 checksum S3 holds for a stored body doesn't match the digest computed as the body was received.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChecksumMismatchError {
+    /// The S3 key of the body.
+    pub key: String,
+
+    /// Base64-encoded SHA-256 digest computed as the body was received.
+    pub expected: String,
+
+    /// Base64-encoded SHA-256 checksum S3 reported.
+    pub actual: String,
+}
+
+impl Display for ChecksumMismatchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "SHA-256 checksum of {} is {}; expected {}", self.key, self.actual, self.expected)
+    }
+}
+
+impl Error for ChecksumMismatchError {}
+
+/// Metadata about a body that is used to lay out and verify the stored object.
+#[derive(Clone, Copy, Debug)]
+pub struct BodyInfo<'a> {
+    /// The portal (subsystem) the body was fetched from, if known.
+    pub portal: Option<&'a str>,
+
+    /// The crawl id the body was fetched under.
+    pub crawl_id: &'a str,
+
+    /// The team the crawl was run for, if any.
+    pub tenant: Option<&'a str>,
+
+    /// When the body was fetched.
+    pub timestamp: DateTime<Utc>,
+
+    /// Hex-encoded SHA-256 digest of the body.
+    pub sha256_hex: &'a str,
+
+    /// Base64-encoded SHA-256 digest of the body.
+    pub sha256_b64: &'a str,
+}
+
+/// Render an S3 key template.
+///
+/// The following placeholders are replaced:
+/// * `{portal}`: the portal (subsystem) name, or `unknown`.
+/// * `{crawl_id}`: the crawl id.
+/// * `{tenant}`: the team the crawl was run for, or `shared`.
+/// * `{yyyy}`, `{mm}`, `{dd}`: the UTC date the body was fetched.
+/// * `{sha256}`: the hex-encoded SHA-256 digest of the body.
+pub fn render_s3_key(template: &str, info: &BodyInfo) -> String {
+    template
+        .replace("{portal}", info.portal.unwrap_or(UNKNOWN_PORTAL))
+        .replace("{crawl_id}", info.crawl_id)
+        .replace("{tenant}", info.tenant.unwrap_or(SHARED_TENANT))
+        .replace("{yyyy}", &info.timestamp.format("%Y").to_string())
+        .replace("{mm}", &info.timestamp.format("%m").to_string())
+        .replace("{dd}", &info.timestamp.format("%d").to_string())
+        .replace("{sha256}", info.sha256_hex)
+}
+
+/// Indicates whether a key template yields the same key for every copy of a body.
+///
+/// Content-addressed keys can be checked for existence directly in S3; other layouts rely on the DynamoDB index.
+/// Content-addressed bodies are indexed too, so that while many bodies are pending their existence can be checked with
+/// one batched index lookup instead of a `HeadObject` each. Listing the bucket can't stand in for the lookups: the keys
+/// are spread evenly over the digest space, so they share no useful prefix.
+pub fn is_content_addressed(template: &str) -> bool {
+    template == DEFAULT_S3_KEY_TEMPLATE
+}
+
+/// Return the address a body is deduplicated under: its hex-encoded SHA-256 digest, followed, for a portal with a key in
+/// [`s3_encryption_keys`][LogConfig::s3_encryption_keys], by a digest of the key id. A copy stored in plaintext, or
+/// under another key, is never reused for such a portal.
+fn content_address(info: &BodyInfo, encryption_key: Option<&str>) -> String {
+    match encryption_key {
+        Some(key_id) => format!("{}-{}", info.sha256_hex, &hex::encode(Sha256::digest(key_id))[..16]),
+        None => info.sha256_hex.to_string(),
+    }
+}
+
+/// Return the KMS key a body is encrypted under, if its portal has one.
+fn encryption_key<'a>(log_config: &'a LogConfig, info: &BodyInfo) -> Option<&'a str> {
+    info.portal.and_then(|portal| log_config.s3_encryption_keys.get(portal)).map(String::as_str)
+}
+
+/// Store a body in S3 unless an identical body has already been stored under the same encryption key, returning its
+/// location.
+///
+/// Looking the body up and writing it are retried with exponential backoff while S3 answers `SlowDown` or 503. The
+/// body is written with a single `PutObject`, so a failed write leaves no multipart upload behind to abort.
+///
+/// While more bodies than the body index's batch threshold are being stored at once, lookups are batched; see
+/// [`BodyIndex`].
+pub async fn store_body(log_config: &LogConfig, body: &Bytes, info: &BodyInfo<'_>) -> Result<StoredBody, BoxError> {
+    let bucket = log_config.s3_bucket.clone();
+    let pending = log_config.body_index.pending();
+    let address = content_address(info, encryption_key(log_config, info));
+
+    if is_content_addressed(&log_config.s3_key_template) {
+        let key = format!("{}{address}", log_config.s3_prefix);
+
+        // Bodies stored before content-addressed bodies were indexed are still found by HeadObject below.
+        if pending.batched() {
+            if let Some(stored) = pending.find(&address).await? {
+                debug!("Body {address} already stored at s3://{}/{}", stored.bucket, stored.key);
+                return Ok(stored);
+            }
+        }
+
+        // Does a body with this SHA256 checksum already exist?
+        let what = format!("HeadObject on s3://{bucket}/{key}");
+        match log_aws_err(
+            retry_slow_down(&what, || {
+                log_config
+                    .s3_client
+                    .head_object()
+                    .bucket(bucket.clone())
+                    .key(key.clone())
+                    .checksum_mode(ChecksumMode::Enabled)
+                    .send()
+            })
+            .await,
+            &what,
+        ) {
+            Ok(head_object) => {
+                let Some(etag) = head_object.e_tag else {
+                    return Err(MissingFieldError::new("HeadObject", "ETag").into());
+                };
+
+                let compression = BodyCompression::from_content_encoding(head_object.content_encoding.as_deref())?;
+                let metadata = head_object.metadata.unwrap_or_default();
+                let kms_key_id = metadata.get(META_KMS_KEY_ID);
+                let redacted = metadata.contains_key(META_REDACTED_AT);
+
+                // Bodies stored before checksums were recorded have none to compare. Compressed and encrypted objects
+                // have the checksum of the stored bytes, which can't be compared without storing the body again, and
+                // redacted objects no longer hold the body as it was fetched.
+                if let (Some(checksum), BodyCompression::None, None, false) =
+                    (head_object.checksum_sha256.as_deref(), compression, kms_key_id, redacted)
+                {
+                    verify_checksum(&key, info.sha256_b64, checksum)?;
+                }
+
+                return Ok(StoredBody {
+                    bucket,
+                    key,
+                    etag,
+                    existing: true,
+                    compression,
+                    kms_key_id: kms_key_id.cloned(),
+                });
+            }
+            Err(e) => {
+                let SdkError::ServiceError(ref service_error) = e else {
+                    return Err(Box::new(e));
+                };
+
+                let HeadObjectError::NotFound(_) = service_error.err() else {
+                    return Err(Box::new(e));
+                };
+            }
+        }
+
+        // No; write it out.
+        let compression = log_config.s3_compression;
+        let (etag, kms_key_id) = put_body(log_config, &bucket, &key, body, info).await?;
+        let stored = StoredBody {
+            bucket,
+            key,
+            etag,
+            existing: false,
+            compression,
+            kms_key_id,
+        };
+
+        // The object is the authority here, so a failure to index it only costs a later HeadObject.
+        if let Err(e) = log_config.body_index.insert(&address, &stored).await {
+            warn!("Failed to index body {address}: {e}");
+        }
+        return Ok(stored);
+    }
+
+    // The key depends on more than the body, so consult the hash index to see if we've stored this body before.
+    if let Some(stored) = pending.find(&address).await? {
+        debug!("Body {address} already stored at s3://{}/{}", stored.bucket, stored.key);
+        return Ok(stored);
+    }
+
+    let key = format!("{}{}", log_config.s3_prefix, render_s3_key(&log_config.s3_key_template, info));
+    let (etag, kms_key_id) = put_body(log_config, &bucket, &key, body, info).await?;
+    let stored = StoredBody {
+        bucket,
+        key,
+        etag,
+        existing: false,
+        compression: log_config.s3_compression,
+        kms_key_id,
+    };
+
+    log_config.body_index.insert(&address, &stored).await?;
+    Ok(stored)
+}
+
+/// Read a stored body back from S3, decrypting and decompressing it as it was stored.
+pub async fn load_body(log_config: &LogConfig, bucket: &str, key: &str) -> Result<Bytes, BoxError> {
+    let get_object = log_aws_err(
+        log_config.s3_client.get_object().bucket(bucket).key(key).send().await,
+        &format!("GetObject s3://{bucket}/{key}"),
+    )?;
+
+    let compression = BodyCompression::from_content_encoding(get_object.content_encoding.as_deref())?;
+    let metadata = get_object.metadata.unwrap_or_default();
+    let payload = get_object.body.collect().await?.into_bytes();
+    let payload = open_body(&log_config.kms_client, payload, &metadata).await?;
+    compression.decompress(&payload)
+}
+
+/// Replace a stored body with a redacted copy, keeping the object's compression, encryption key, and tags.
+///
+/// The object is marked with when it was redacted, so its checksum is no longer compared with the digest of identical
+/// bodies fetched later. Log items keep the digest of the body as it was fetched.
+pub async fn replace_body(log_config: &LogConfig, bucket: &str, key: &str, body: &Bytes) -> Result<(), BoxError> {
+    let head_object = log_aws_err(
+        log_config.s3_client.head_object().bucket(bucket).key(key).send().await,
+        &format!("HeadObject on s3://{bucket}/{key}"),
+    )?;
+    let tagging = log_aws_err(
+        log_config.s3_client.get_object_tagging().bucket(bucket).key(key).send().await,
+        &format!("GetObjectTagging s3://{bucket}/{key}"),
+    )?;
+
+    let compression = BodyCompression::from_content_encoding(head_object.content_encoding.as_deref())?;
+    let mut payload = compression.compress(body)?;
+    let mut metadata = HashMap::new();
+    if let Some(key_id) = head_object.metadata.as_ref().and_then(|metadata| metadata.get(META_KMS_KEY_ID)) {
+        let sha256_hex = hex::encode(Sha256::digest(body));
+        let sealed = seal_body(&log_config.kms_client, key_id, &payload, &sha256_hex).await?;
+        payload = sealed.payload;
+        metadata = sealed.metadata;
+    }
+    metadata.insert(META_REDACTED_AT.to_string(), Utc::now().to_rfc3339());
+
+    log_aws_err(
+        log_config
+            .s3_client
+            .put_object()
+            .bucket(bucket)
+            .key(key)
+            .set_content_encoding(compression.content_encoding().map(str::to_string))
+            .set_metadata(Some(metadata))
+            .checksum_algorithm(ChecksumAlgorithm::Sha256)
+            .body(ByteStream::from(payload))
+            .send()
+            .await,
+        &format!("PutObject s3://{bucket}/{key}"),
+    )?;
+
+    if !tagging.tag_set.is_empty() {
+        log_aws_err(
+            log_config
+                .s3_client
+                .put_object_tagging()
+                .bucket(bucket)
+                .key(key)
+                .tagging(Tagging::builder().set_tag_set(Some(tagging.tag_set)).build()?)
+                .send()
+                .await,
+            &format!("PutObjectTagging s3://{bucket}/{key}"),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Write a body to S3, returning its ETag and the KMS key it was encrypted under, if any.
+///
+/// The body is compressed as configured, encrypted if its portal has a key in
+/// [`s3_encryption_keys`][LogConfig::s3_encryption_keys], then sent as a stream so the SDK computes its SHA-256
+/// checksum as it is uploaded and sends it in a trailer, which S3 verifies, rather than hashing it again up front. For a
+/// body stored as it was received, the checksum S3 reports is also compared with the digest computed as the body was
+/// received; compressed and encrypted payloads are not hashed a second time. The object is tagged as raw crawl data
+/// from the body's portal and crawl; see [`classification`][crate::classification].
+async fn put_body(
+    log_config: &LogConfig,
+    bucket: &str,
+    key: &str,
+    body: &Bytes,
+    info: &BodyInfo<'_>,
+) -> Result<(String, Option<String>), BoxError> {
+    let compression = log_config.s3_compression;
+    let payload = compression.compress(body)?;
+    let encryption_key = encryption_key(log_config, info);
+    let (payload, metadata, kms_key_id) = match encryption_key {
+        Some(key_id) => {
+            let sealed = seal_body(&log_config.kms_client, key_id, &payload, info.sha256_hex).await?;
+            (sealed.payload, Some(sealed.metadata), Some(sealed.key_id))
+        }
+        None => (payload, None, None),
+    };
+    let expected_checksum = match (compression, kms_key_id.as_ref()) {
+        (BodyCompression::None, None) => Some(info.sha256_b64),
+        _ => None,
+    };
+
+    debug!("Logging to S3: s3://{bucket}/{key}");
+    debug!("SHA256: {} {}", info.sha256_hex, info.sha256_b64);
+    debug!("Compression: {compression}, {} of {} bytes", payload.len(), body.len());
+
+    let tags = ObjectTags {
+        portal: info.portal,
+        crawl_id: Some(info.crawl_id),
+        tenant: info.tenant,
+        ..ObjectTags::new(Classification::RawCrawlData)
+    };
+
+    let what = format!("PutObject s3://{bucket}/{key}");
+    let put_object = match log_aws_err(
+        retry_slow_down(&what, || {
+            let streamed = payload.clone();
+            let bytestream =
+                ByteStream::new(SdkBody::retryable(move || SdkBody::from_body_1_x(Full::new(streamed.clone()))));
+            log_config
+                .s3_client
+                .put_object()
+                .bucket(bucket)
+                .key(key)
+                .set_content_encoding(compression.content_encoding().map(str::to_string))
+                .checksum_algorithm(ChecksumAlgorithm::Sha256)
+                .tagging(tags.to_tagging())
+                .set_metadata(metadata.clone())
+                .body(bytestream)
+                .send()
+        })
+        .await,
+        &what,
+    ) {
+        Ok(put_object) => put_object,
+        Err(e) => {
+            if let SdkError::ServiceError(e2) = &e {
+                let metadata = e2.err().meta();
+                error!(
+                    "Error info: code={:?} message={:?} request_id={:?}",
+                    metadata.code(),
+                    metadata.message(),
+                    metadata.extra("request_id")
+                );
+            }
+
+            Err(e)?
+        }
+    };
+
+    let Some(checksum) = put_object.checksum_sha256.as_deref() else {
+        return Err(MissingFieldError::new("PutObject", "ChecksumSHA256").into());
+    };
+    if let Some(expected_checksum) = expected_checksum {
+        verify_checksum(key, expected_checksum, checksum)?;
+    }
+
+    match put_object.e_tag {
+        Some(etag) => Ok((etag, kms_key_id)),
+        None => Err(MissingFieldError::new("PutObject", "ETag").into()),
+    }
+}
+
+/// Indicates whether S3 refused a call because it is throttling requests: a `SlowDown` error or a 503 response.
+fn is_slow_down<E: ProvideErrorMetadata>(e: &SdkError<E, HttpResponse>) -> bool {
+    match e {
+        SdkError::ServiceError(e) => e.err().code() == Some(S3_SLOW_DOWN_CODE) || e.raw().status().as_u16() == 503,
+        _ => false,
+    }
+}
+
+/// Make an S3 call, retrying it with exponential backoff while S3 asks us to slow down, up to
+/// [`S3_SLOW_DOWN_ATTEMPTS`] attempts in all. `what` names the call in log messages.
+async fn retry_slow_down<T, E, F, Fut>(what: &str, mut call: F) -> Result<T, SdkError<E, HttpResponse>>
+where
+    E: ProvideErrorMetadata,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, SdkError<E, HttpResponse>>>,
+{
+    let mut delay = S3_SLOW_DOWN_BASE_DELAY;
+    let mut attempt = 1;
+
+    loop {
+        match call().await {
+            Err(e) if attempt < S3_SLOW_DOWN_ATTEMPTS && is_slow_down(&e) => {
+                metrics::registry().increment(METRIC_S3_SLOW_DOWNS, &[], 1);
+                warn!("{what}: S3 asked us to slow down; retrying in {delay:?} (attempt {attempt})");
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Compare the SHA-256 checksum S3 reported for a stored object with the digest computed locally.
+fn verify_checksum(key: &str, expected: &str, actual: &str) -> Result<(), ChecksumMismatchError> {
+    if expected == actual {
+        Ok(())
+    } else {
+        error!("SHA-256 checksum mismatch on {key}: S3 reported {actual}, expected {expected}");
+        Err(ChecksumMismatchError {
+            key: key.to_string(),
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            content_address, is_content_addressed, render_s3_key, retry_slow_down, verify_checksum, BodyCompression,
+            BodyInfo, DEFAULT_S3_KEY_TEMPLATE,
+        },
+        aws_sdk_s3::{error::ErrorMetadata, operation::head_object::HeadObjectError, primitives::SdkBody},
+        aws_smithy_runtime_api::{
+            client::result::SdkError,
+            http::{Response as HttpResponse, StatusCode},
+        },
+        bytes::Bytes,
+        chrono::{TimeZone, Utc},
+        std::sync::atomic::{AtomicU32, Ordering},
+    };
+
+    #[test]
+    fn key_templates() {
+        let info = BodyInfo {
+            portal: Some("Webs"),
+            crawl_id: "crawl-1",
+            tenant: None,
+            timestamp: Utc.with_ymd_and_hms(2024, 3, 7, 23, 59, 0).unwrap(),
+            sha256_hex: "abcd",
+            sha256_b64: "q80=",
+        };
+
+        assert_eq!(render_s3_key(DEFAULT_S3_KEY_TEMPLATE, &info).as_str(), "abcd");
+        assert_eq!(render_s3_key("{portal}/{yyyy}/{mm}/{dd}/{sha256}", &info).as_str(), "Webs/2024/03/07/abcd");
+        assert_eq!(render_s3_key("{crawl_id}/{sha256}.html", &info).as_str(), "crawl-1/abcd.html");
+
+        let info = BodyInfo {
+            portal: None,
+            ..info
+        };
+        assert_eq!(render_s3_key("{portal}/{sha256}", &info).as_str(), "unknown/abcd");
+        assert_eq!(render_s3_key("{tenant}/{sha256}", &info).as_str(), "shared/abcd");
+        let info = BodyInfo {
+            tenant: Some("Analytics"),
+            ..info
+        };
+        assert_eq!(render_s3_key("{tenant}/{sha256}", &info).as_str(), "Analytics/abcd");
+
+        assert!(is_content_addressed(DEFAULT_S3_KEY_TEMPLATE));
+        assert!(!is_content_addressed("{portal}/{sha256}"));
+
+        // A body encrypted under a key is only deduplicated against copies stored under the same key.
+        assert_eq!(content_address(&info, None), "abcd");
+        let encrypted = content_address(&info, Some("alias/webs"));
+        assert!(encrypted.starts_with("abcd-") && encrypted.len() == 21);
+        assert_eq!(content_address(&info, Some("alias/webs")), encrypted);
+        assert_ne!(content_address(&info, Some("alias/merx")), encrypted);
+    }
+
+    #[test]
+    fn checksums() {
+        assert!(verify_checksum("abcd", "q80=", "q80=").is_ok());
+
+        let error = verify_checksum("abcd", "q80=", "AAA=").unwrap_err();
+        assert_eq!(error.to_string(), "SHA-256 checksum of abcd is AAA=; expected q80=");
+    }
+
+    #[test]
+    fn compression() {
+        let body = Bytes::from("<html><body>".to_string() + &"<p>Invitation to bid</p>".repeat(200) + "</body></html>");
+
+        assert_eq!(BodyCompression::None.compress(&body).unwrap(), body);
+        let compressed = BodyCompression::Zstd.compress(&body).unwrap();
+        assert!(compressed.len() < body.len() / 10);
+        assert_eq!(zstd::decode_all(compressed.as_ref()).unwrap(), body);
+        assert_eq!(BodyCompression::Zstd.decompress(&compressed).unwrap(), body);
+
+        assert_eq!("zstd".parse::<BodyCompression>(), Ok(BodyCompression::Zstd));
+        assert!("lz4".parse::<BodyCompression>().is_err());
+        assert_eq!(BodyCompression::from_content_encoding(None), Ok(BodyCompression::None));
+        assert_eq!(BodyCompression::from_content_encoding(Some("zstd")), Ok(BodyCompression::Zstd));
+        assert_eq!(BodyCompression::Zstd.content_encoding(), Some("zstd"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_down_retries() {
+        let error = |code: &str, status: u16| {
+            SdkError::service_error(
+                HeadObjectError::generic(ErrorMetadata::builder().code(code).build()),
+                HttpResponse::new(StatusCode::try_from(status).unwrap(), SdkBody::empty()),
+            )
+        };
+
+        // Throttling is retried until the call succeeds.
+        let calls = AtomicU32::new(0);
+        let result = retry_slow_down("HeadObject", || async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(error("SlowDown", 503)),
+                1 => Err(error("ServiceUnavailable", 503)),
+                _ => Ok("found"),
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), "found");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // Other errors aren't, and throttling is given up on after the last attempt.
+        let calls = AtomicU32::new(0);
+        let result = retry_slow_down("HeadObject", || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(error("AccessDenied", 403))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let calls = AtomicU32::new(0);
+        let result = retry_slow_down("HeadObject", || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(error("SlowDown", 503))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+    }
+}
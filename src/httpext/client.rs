@@ -1,13 +1,18 @@
 use {
     crate::{
-        httpext::{CookieStoreRwLock, LogConfig, RequestBuilder, Response},
+        httpext::{
+            record_fetch_failure, AddressFamily, CachingResolver, CookieStoreRwLock, CrawlStats, HostAllowlist,
+            HostNotAllowedError, LogConfig, RequestBuilder, RequestPacer, Response, DEFAULT_REDIRECT_LIMIT,
+        },
         BoxError,
     },
+    chrono::Utc,
+    log::*,
     reqwest::{
         dns::Resolve,
         header::{HeaderMap, HeaderValue},
         redirect::Policy as RedirectPolicy,
-        Certificate, Error as ReqwestError, Identity, IntoUrl, Method, Proxy, Request,
+        Certificate, Error as ReqwestError, Identity, IntoUrl, Method, Proxy, Request, Url,
     },
     std::{
         clone::Clone,
@@ -31,6 +36,35 @@ pub struct ClientBuilder {
 
     /// The crawl id of the current crawl.
     pub crawl_id: String,
+
+    /// The portal (subsystem) being crawled, if known. This is used when laying out stored bodies.
+    pub portal: Option<String>,
+
+    /// Statistics about the requests made, shared by all clones of the client.
+    pub stats: Arc<CrawlStats>,
+
+    /// The hosts the client may contact. Requests to any other host are refused.
+    pub allowed_hosts: Arc<HostAllowlist>,
+
+    /// Paces requests, shared by all clones of the client.
+    pub pacer: Arc<RequestPacer>,
+
+    /// The portal account the client is logged in as, if any. This is recorded with each logged request.
+    pub account: Option<String>,
+
+    /// The team the crawl is run for, if any. This is recorded with each logged request and used when laying out
+    /// stored bodies.
+    pub tenant: Option<String>,
+
+    /// The number of times the operation making requests has been re-enqueued after a temporary condition. This is
+    /// recorded with each failed request.
+    pub attempt: u32,
+
+    /// The address families the client connects with. File transfers resolve hosts for these too.
+    pub address_family: AddressFamily,
+
+    /// How long connecting may take, if limited. File transfers connect within this too.
+    pub connect_timeout: Option<Duration>,
 }
 
 /// Track a Reqwest [Client][reqwest::Client] along with a cookie store.
@@ -47,6 +81,35 @@ pub struct Client {
 
     /// The crawl id of the current crawl.
     pub crawl_id: String,
+
+    /// The portal (subsystem) being crawled, if known. This is used when laying out stored bodies.
+    pub portal: Option<String>,
+
+    /// Statistics about the requests made, shared by all clones of the client.
+    pub stats: Arc<CrawlStats>,
+
+    /// The hosts the client may contact. Requests to any other host are refused.
+    pub allowed_hosts: Arc<HostAllowlist>,
+
+    /// Paces requests, shared by all clones of the client.
+    pub pacer: Arc<RequestPacer>,
+
+    /// The portal account the client is logged in as, if any. This is recorded with each logged request.
+    pub account: Option<String>,
+
+    /// The team the crawl is run for, if any. This is recorded with each logged request and used when laying out
+    /// stored bodies.
+    pub tenant: Option<String>,
+
+    /// The number of times the operation making requests has been re-enqueued after a temporary condition. This is
+    /// recorded with each failed request.
+    pub attempt: u32,
+
+    /// The address families the client connects with. File transfers resolve hosts for these too.
+    pub address_family: AddressFamily,
+
+    /// How long connecting may take, if limited. File transfers connect within this too.
+    pub connect_timeout: Option<Duration>,
 }
 
 impl ClientBuilder {
@@ -59,6 +122,15 @@ impl ClientBuilder {
             cookie_store,
             log_config: None,
             crawl_id: crawl_id.into(),
+            portal: None,
+            stats: Arc::new(CrawlStats::default()),
+            allowed_hosts: Arc::new(HostAllowlist::default()),
+            pacer: Arc::new(RequestPacer::default()),
+            account: None,
+            tenant: None,
+            attempt: 0,
+            address_family: AddressFamily::Any,
+            connect_timeout: None,
         }
     }
 
@@ -75,9 +147,85 @@ impl ClientBuilder {
             cookie_store: self.cookie_store,
             log_config: self.log_config,
             crawl_id: self.crawl_id,
+            portal: self.portal,
+            stats: self.stats,
+            allowed_hosts: self.allowed_hosts,
+            pacer: self.pacer,
+            account: self.account,
+            tenant: self.tenant,
+            attempt: self.attempt,
+            address_family: self.address_family,
+            connect_timeout: self.connect_timeout,
         })
     }
 
+    /// Sets the portal (subsystem) being crawled.
+    ///
+    /// This is recorded with each response and used when laying out stored bodies.
+    pub fn portal<S: Into<String>>(mut self, portal: S) -> ClientBuilder {
+        self.portal = Some(portal.into());
+        self
+    }
+
+    /// Restricts the client to the hosts in `allowlist`.
+    ///
+    /// Requests to other hosts fail with a [`HostNotAllowedError`], and so do redirects to them. This replaces the
+    /// redirect policy with one that also enforces the allowlist, following up to [`DEFAULT_REDIRECT_LIMIT`]
+    /// redirects.
+    pub fn allowed_hosts(mut self, allowlist: HostAllowlist) -> ClientBuilder {
+        let redirect_allowlist = allowlist.clone();
+        self.builder = self.builder.redirect(RedirectPolicy::custom(move |attempt| {
+            if !redirect_allowlist.allows(attempt.url()) {
+                let error = HostNotAllowedError::new(attempt.url().clone());
+                attempt.error(error)
+            } else if attempt.previous().len() >= DEFAULT_REDIRECT_LIMIT {
+                attempt.error("too many redirects")
+            } else {
+                attempt.follow()
+            }
+        }));
+        self.allowed_hosts = Arc::new(allowlist);
+        self
+    }
+
+    /// Sets the portal account the client is logged in as.
+    pub fn account<S: Into<String>>(mut self, account: S) -> ClientBuilder {
+        self.account = Some(account.into());
+        self
+    }
+
+    /// Sets the team the crawl is run for.
+    pub fn tenant<S: Into<String>>(mut self, tenant: S) -> ClientBuilder {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    /// Sets the number of times the operation making requests has been re-enqueued after a temporary condition.
+    pub fn attempt(mut self, attempt: u32) -> ClientBuilder {
+        self.attempt = attempt;
+        self
+    }
+
+    /// Sets the minimum time between the starts of consecutive requests made by this client.
+    pub fn request_interval(mut self, interval: Duration) -> ClientBuilder {
+        self.pacer = Arc::new(RequestPacer::new(interval));
+        self
+    }
+
+    /// Restricts or orders the address families the client connects with.
+    ///
+    /// The shared [`CachingResolver`] of the log configuration is used if there is one, otherwise lookups made by
+    /// this client aren't cached. Pinned hosts are connected to as pinned.
+    pub fn address_family(mut self, family: AddressFamily) -> ClientBuilder {
+        self.address_family = family;
+        let resolver = match self.log_config.as_ref().and_then(|log_config| log_config.dns_resolver.as_ref()) {
+            Some(resolver) => resolver.with_family(family),
+            None => CachingResolver::new(0).with_family(family),
+        };
+        self.builder = self.builder.dns_resolver(Arc::new(resolver));
+        self
+    }
+
     /// Sets the `User-Agent` header to be used by this client.
     ///
     /// # Example
@@ -183,6 +331,22 @@ impl ClientBuilder {
         self
     }
 
+    /// Enable auto zstd decompression by checking the `Content-Encoding` response header.
+    ///
+    /// If auto zstd decompression is turned on:
+    ///
+    /// - When sending a request and if the request's headers do not already contain
+    ///   an `Accept-Encoding` **and** `Range` values, the `Accept-Encoding` header is set to `zstd`.
+    ///   The request body is **not** automatically compressed.
+    /// - When receiving a response, if its headers contain a `Content-Encoding` value of
+    ///   `zstd`, both `Content-Encoding` and `Content-Length` are removed from the
+    ///   headers' set. The response body is automatically decompressed.
+    #[inline(always)]
+    pub fn zstd(mut self, enable: bool) -> ClientBuilder {
+        self.builder = self.builder.zstd(enable);
+        self
+    }
+
     /// Disable auto response body gzip decompression.
     #[inline(always)]
     pub fn no_gzip(mut self) -> ClientBuilder {
@@ -204,6 +368,13 @@ impl ClientBuilder {
         self
     }
 
+    /// Disable auto response body zstd decompression.
+    #[inline(always)]
+    pub fn no_zstd(mut self) -> ClientBuilder {
+        self.builder = self.builder.no_zstd();
+        self
+    }
+
     /// Set a `RedirectPolicy` for this client.
     ///
     /// Default will follow redirects up to a maximum of 10.
@@ -268,6 +439,7 @@ impl ClientBuilder {
     /// a tokio timer enabled.
     #[inline(always)]
     pub fn connect_timeout(mut self, timeout: Duration) -> ClientBuilder {
+        self.connect_timeout = Some(timeout);
         self.builder = self.builder.connect_timeout(timeout);
         self
     }
@@ -686,6 +858,15 @@ impl Client {
             cookie_store: self.cookie_store.clone(),
             log_config: self.log_config.clone(),
             crawl_id: self.crawl_id.clone(),
+            portal: self.portal.clone(),
+            stats: self.stats.clone(),
+            allowed_hosts: self.allowed_hosts.clone(),
+            pacer: self.pacer.clone(),
+            account: self.account.clone(),
+            tenant: self.tenant.clone(),
+            attempt: self.attempt,
+            address_family: self.address_family,
+            connect_timeout: self.connect_timeout,
         }
     }
 
@@ -701,6 +882,15 @@ impl Client {
             cookie_store: self.cookie_store.clone(),
             log_config: self.log_config.clone(),
             crawl_id: self.crawl_id.clone(),
+            portal: self.portal.clone(),
+            stats: self.stats.clone(),
+            allowed_hosts: self.allowed_hosts.clone(),
+            pacer: self.pacer.clone(),
+            account: self.account.clone(),
+            tenant: self.tenant.clone(),
+            attempt: self.attempt,
+            address_family: self.address_family,
+            connect_timeout: self.connect_timeout,
         }
     }
 
@@ -716,6 +906,15 @@ impl Client {
             cookie_store: self.cookie_store.clone(),
             log_config: self.log_config.clone(),
             crawl_id: self.crawl_id.clone(),
+            portal: self.portal.clone(),
+            stats: self.stats.clone(),
+            allowed_hosts: self.allowed_hosts.clone(),
+            pacer: self.pacer.clone(),
+            account: self.account.clone(),
+            tenant: self.tenant.clone(),
+            attempt: self.attempt,
+            address_family: self.address_family,
+            connect_timeout: self.connect_timeout,
         }
     }
 
@@ -731,6 +930,15 @@ impl Client {
             cookie_store: self.cookie_store.clone(),
             log_config: self.log_config.clone(),
             crawl_id: self.crawl_id.clone(),
+            portal: self.portal.clone(),
+            stats: self.stats.clone(),
+            allowed_hosts: self.allowed_hosts.clone(),
+            pacer: self.pacer.clone(),
+            account: self.account.clone(),
+            tenant: self.tenant.clone(),
+            attempt: self.attempt,
+            address_family: self.address_family,
+            connect_timeout: self.connect_timeout,
         }
     }
 
@@ -746,6 +954,15 @@ impl Client {
             cookie_store: self.cookie_store.clone(),
             log_config: self.log_config.clone(),
             crawl_id: self.crawl_id.clone(),
+            portal: self.portal.clone(),
+            stats: self.stats.clone(),
+            allowed_hosts: self.allowed_hosts.clone(),
+            pacer: self.pacer.clone(),
+            account: self.account.clone(),
+            tenant: self.tenant.clone(),
+            attempt: self.attempt,
+            address_family: self.address_family,
+            connect_timeout: self.connect_timeout,
         }
     }
 
@@ -761,6 +978,15 @@ impl Client {
             cookie_store: self.cookie_store.clone(),
             log_config: self.log_config.clone(),
             crawl_id: self.crawl_id.clone(),
+            portal: self.portal.clone(),
+            stats: self.stats.clone(),
+            allowed_hosts: self.allowed_hosts.clone(),
+            pacer: self.pacer.clone(),
+            account: self.account.clone(),
+            tenant: self.tenant.clone(),
+            attempt: self.attempt,
+            address_family: self.address_family,
+            connect_timeout: self.connect_timeout,
         }
     }
 
@@ -779,6 +1005,15 @@ impl Client {
             cookie_store: self.cookie_store.clone(),
             log_config: self.log_config.clone(),
             crawl_id: self.crawl_id.clone(),
+            portal: self.portal.clone(),
+            stats: self.stats.clone(),
+            allowed_hosts: self.allowed_hosts.clone(),
+            pacer: self.pacer.clone(),
+            account: self.account.clone(),
+            tenant: self.tenant.clone(),
+            attempt: self.attempt,
+            address_family: self.address_family,
+            connect_timeout: self.connect_timeout,
         }
     }
 
@@ -798,8 +1033,43 @@ impl Client {
     pub async fn execute(&self, request: Request) -> Result<Response, BoxError> {
         let method = request.method().clone();
         let url = request.url().clone();
-        Response::new(self.client.execute(request).await?, self.crawl_id.clone(), method, url, self.log_config.clone())
-            .await
+        self.admit(&url).await?;
+
+        let started_at = Utc::now();
+        let resp = match self.client.execute(request).await {
+            Ok(resp) => resp,
+            Err(e) => {
+                record_fetch_failure(self, &method, &url, started_at, &e).await;
+                return Err(e.into());
+            }
+        };
+
+        Response::new(resp, self, method, url, started_at).await
+    }
+
+    /// Wait until a request to `url` may be sent: refuse it if the host isn't allowed, then wait for room in the body
+    /// memory budget and for the pacer.
+    ///
+    /// Requests sent through the client do this themselves; fetches made other than with Reqwest, such as
+    /// [file transfers][crate::transfer], call it before connecting.
+    pub async fn admit(&self, url: &Url) -> Result<(), BoxError> {
+        if !self.allowed_hosts.allows(url) {
+            if let Some(log_config) = self.log_config.as_ref() {
+                log_config.audit.record_refused(&self.crawl_id, url);
+            }
+
+            let error = HostNotAllowedError::new(url.clone());
+            error!("{error}");
+            self.stats.record_failure();
+            return Err(error.into());
+        }
+
+        if let Some(log_config) = self.log_config.as_ref() {
+            log_config.body_memory.admit().await;
+        }
+
+        self.pacer.wait().await;
+        Ok(())
     }
 }
 
@@ -807,7 +1077,7 @@ impl Client {
 mod tests {
     use {
         super::ClientBuilder,
-        crate::httpext::{CookieStore, CookieStoreRwLock},
+        crate::httpext::{CookieStore, CookieStoreRwLock, HostAllowlist, HostNotAllowedError},
         httpmock::prelude::*,
         log::debug,
         serde::Serialize,
@@ -844,15 +1114,38 @@ mod tests {
 
         drop(client);
 
-        let cookie_store = cookie_store.read().unwrap();
-
         let cst = CookieStoreTest {
-            cookies: cookie_store.clone(),
+            cookies: cookie_store.snapshot(),
         };
         let ser_data = serde_json::to_string(&cst).unwrap();
         assert_eq!(
             ser_data.as_str(),
-            r#"{"cookies":[{"raw_cookie":"TestCookie=Value; Domain=127.0.0.1","path":["/",false],"domain":{"Suffix":"127.0.0.1"},"expires":"SessionEnd"}]}"#
+            r#"{"cookies":{"v":1,"c":[{"n":"TestCookie","v":"Value","d":".127.0.0.1","p":"/"}]}}"#
         );
     }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn host_allowlist() {
+        let server = MockServer::start();
+        let redirect_mock = server.mock(|when, then| {
+            when.method(GET).path("/redirect");
+            then.status(302).header("location", format!("http://localhost:{}/", server.port()));
+        });
+
+        let client = ClientBuilder::new(Arc::new(CookieStoreRwLock::default()), "test")
+            .allowed_hosts(HostAllowlist::new(["127.0.0.1"]))
+            .build()
+            .expect("Failed to build client");
+
+        // A redirect off the allowlist is refused.
+        let err = client.get(server.url("/redirect")).send().await.unwrap_err();
+        debug!("Redirect error: {err:?}");
+        assert!(format!("{err:?}").contains("HostNotAllowedError"));
+        redirect_mock.assert();
+
+        // So is a direct request.
+        let err = client.get(format!("http://localhost:{}/", server.port())).send().await.unwrap_err();
+        assert!(err.downcast_ref::<HostNotAllowedError>().is_some());
+    }
 }
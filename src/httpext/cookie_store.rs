@@ -1,21 +1,46 @@
 //! A fork of [`reqwest_cookie_store`](https://docs.rs/reqwest_cookie_store) that serializes
 //! non-persistent cookies by default.
+//!
+//! Cookie stores travel inside every SQS message, so they are serialized in a compact, versioned wire format that
+//! holds only the name, value, domain, path, and expiry of each cookie:
+//!
+//! ```json
+//! {"v":1,"c":[{"n":"ASP.NET_SessionId","v":"abc","d":"example.com","p":"/"},
+//!             {"n":"Auth","v":"def","d":".example.com","p":"/","x":1767225600}]}
+//! ```
+//!
+//! A domain with a leading `.` also matches subdomains; `x` is the expiry as seconds since the Unix epoch and is
+//! omitted for session cookies. Stores written by older versions as a bare sequence of `cookie_store` cookies are
+//! still accepted.
 use {
     bytes::Bytes,
-    cookie_store::{CookieStore as BaseCookieStore, RawCookie, RawCookieParseError},
+    chrono::Utc,
+    cookie_store::{
+        Cookie, CookieDomain, CookieError, CookieExpiration, CookieStore as BaseCookieStore, RawCookie,
+        RawCookieParseError,
+    },
+    log::*,
+    parking_lot::RwLock,
     reqwest::{header::HeaderValue, Url},
     serde::{
-        de::{SeqAccess, Visitor},
+        de::{Error as DeError, MapAccess, SeqAccess, Visitor},
+        ser::SerializeMap,
         Deserialize, Deserializer, Serialize, Serializer,
     },
     std::{
         fmt::{Formatter, Result as FmtResult},
         iter,
         ops::{Deref, DerefMut},
-        sync::RwLock,
+        time::Duration,
     },
+    time::OffsetDateTime,
 };
 
+/// The current version of the cookie store wire format.
+const COOKIE_WIRE_VERSION: u32 = 1;
+const WIRE_KEY_VERSION: &str = "v";
+const WIRE_KEY_COOKIES: &str = "c";
+
 /// A cookie store that can be serialized and deserialized across requests.
 ///
 /// This is a variant of `reqwest_cookie_store::CookieStore` that implements `Serialize` and
@@ -24,6 +49,100 @@ use {
 #[derive(Clone, Debug, Default)]
 pub struct CookieStore(BaseCookieStore);
 
+impl CookieStore {
+    /// Return the unexpired cookies that will expire within `window`.
+    ///
+    /// Session cookies have no expiration time and are never reported.
+    pub fn expiring_within(&self, window: Duration) -> Vec<&Cookie<'static>> {
+        let deadline = Utc::now().timestamp().saturating_add(window.as_secs().try_into().unwrap_or(i64::MAX));
+
+        self.iter_unexpired()
+            .filter(|cookie| match &cookie.expires {
+                CookieExpiration::AtUtc(expires) => expires.unix_timestamp() <= deadline,
+                CookieExpiration::SessionEnd => false,
+            })
+            .collect()
+    }
+
+    /// Remove every cookie that would not be sent to `url`'s host, returning the number removed.
+    ///
+    /// This keeps cookies picked up from other sites (redirects, single sign-on, analytics) out of the serialized
+    /// store, where they would bloat SQS messages and be sent along to a different portal.
+    pub fn retain_domain(&mut self, url: &Url) -> usize {
+        let foreign: Vec<(String, String, String)> = self
+            .iter_any()
+            .filter(|cookie| !cookie.domain.matches(url))
+            .map(|cookie| {
+                (
+                    cookie.domain.as_cow().unwrap_or_default().into_owned(),
+                    cookie.path.to_string(),
+                    cookie.name().to_string(),
+                )
+            })
+            .collect();
+
+        for (domain, path, name) in &foreign {
+            self.remove(domain, path, name);
+        }
+
+        foreign.len()
+    }
+
+    /// Add operator-supplied cookies that aren't already in the store, returning the number added.
+    ///
+    /// A cookie the portal has since set with the same name, domain, and path is left alone, so a portal updating a
+    /// consent or bucket cookie isn't overridden by its initial value on every request.
+    pub fn inject(&mut self, cookies: &[CrawlCookie]) -> usize {
+        let mut added = 0;
+
+        for cookie in cookies {
+            let domain = cookie.domain.strip_prefix('.').unwrap_or(&cookie.domain);
+            if self.get(domain, &cookie.path, &cookie.name).is_some() {
+                continue;
+            }
+
+            let wire = WireCookie {
+                name: cookie.name.clone(),
+                value: cookie.value.clone(),
+                domain: cookie.domain.clone(),
+                path: cookie.path.clone(),
+                expires: None,
+            };
+
+            match wire.insert_into(&mut self.0) {
+                Ok(()) => added += 1,
+                Err(e) => warn!("Ignoring crawl cookie {}: {e}", cookie.name),
+            }
+        }
+
+        added
+    }
+}
+
+/// A cookie an operator supplies in the crawl parameters, such as a consent banner's cookie or an A/B bucket pin, to
+/// be sent from the first request of a crawl.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CrawlCookie {
+    /// The name of the cookie.
+    pub name: String,
+
+    /// The value of the cookie.
+    pub value: String,
+
+    /// The host the cookie is sent to; a leading `.` also sends it to subdomains.
+    pub domain: String,
+
+    /// The path the cookie is sent under.
+    #[serde(default = "default_cookie_path")]
+    pub path: String,
+}
+
+/// Return the default path for a [`CrawlCookie`].
+fn default_cookie_path() -> String {
+    "/".to_string()
+}
+
 impl Deref for CookieStore {
     type Target = BaseCookieStore;
 
@@ -44,21 +163,128 @@ impl From<BaseCookieStore> for CookieStore {
     }
 }
 
+/// A cookie in the wire format.
+#[derive(Debug, Deserialize, Serialize)]
+struct WireCookie {
+    #[serde(rename = "n")]
+    name: String,
+
+    #[serde(rename = "v")]
+    value: String,
+
+    /// The domain; a leading `.` marks a domain cookie that also matches subdomains.
+    #[serde(rename = "d")]
+    domain: String,
+
+    #[serde(rename = "p")]
+    path: String,
+
+    /// The expiry in seconds since the Unix epoch, or `None` for a session cookie.
+    #[serde(rename = "x", default, skip_serializing_if = "Option::is_none")]
+    expires: Option<i64>,
+}
+
+impl WireCookie {
+    /// Convert a stored cookie into the wire format. Returns `None` for cookies without a domain, which the store
+    /// never holds.
+    fn from_cookie(cookie: &Cookie<'static>) -> Option<Self> {
+        let domain = match &cookie.domain {
+            CookieDomain::HostOnly(host) => host.clone(),
+            CookieDomain::Suffix(suffix) => format!(".{suffix}"),
+            CookieDomain::NotPresent | CookieDomain::Empty => return None,
+        };
+
+        let expires = match &cookie.expires {
+            CookieExpiration::AtUtc(expires) => Some(expires.unix_timestamp()),
+            CookieExpiration::SessionEnd => None,
+        };
+
+        Some(Self {
+            name: cookie.name().to_string(),
+            value: cookie.value().to_string(),
+            domain,
+            path: cookie.path.to_string(),
+            expires,
+        })
+    }
+
+    /// Insert this cookie into a store as though it had been received from its own domain and path.
+    fn insert_into(self, store: &mut BaseCookieStore) -> Result<(), String> {
+        let (host, suffix) = match self.domain.strip_prefix('.') {
+            Some(suffix) => (suffix.to_string(), true),
+            None => (self.domain.clone(), false),
+        };
+
+        let url = Url::parse(&format!("https://{host}{}", self.path))
+            .map_err(|e| format!("invalid cookie domain or path {}{}: {e}", self.domain, self.path))?;
+
+        let mut raw = RawCookie::new(self.name, self.value);
+        raw.set_path(self.path);
+        if suffix {
+            raw.set_domain(host);
+        }
+
+        if let Some(expires) = self.expires {
+            let expires =
+                OffsetDateTime::from_unix_timestamp(expires).map_err(|e| format!("invalid cookie expiry: {e}"))?;
+            raw.set_expires(expires);
+        }
+
+        match store.insert_raw(&raw, &url) {
+            // Cookies that expired in transit are simply dropped.
+            Ok(_) | Err(CookieError::Expired) => Ok(()),
+            Err(e) => Err(format!("invalid cookie {}: {e}", raw.name())),
+        }
+    }
+}
+
 struct CookieStoreVisitor;
 
 impl<'de> Visitor<'de> for CookieStoreVisitor {
     type Value = CookieStore;
 
     fn expecting(&self, f: &mut Formatter) -> FmtResult {
-        f.write_str("a sequence of cookies")
+        f.write_str("a versioned cookie store or a sequence of cookies")
     }
 
+    /// Read the original format: a sequence of serialized `cookie_store` cookies.
     fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
     where
         A: SeqAccess<'de>,
     {
         Ok(CookieStore(BaseCookieStore::from_cookies(iter::from_fn(|| seq.next_element().transpose()), false)?))
     }
+
+    /// Read the versioned wire format.
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut version: Option<u32> = None;
+        let mut cookies: Option<Vec<WireCookie>> = None;
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                WIRE_KEY_VERSION => version = Some(map.next_value()?),
+                WIRE_KEY_COOKIES => cookies = Some(map.next_value()?),
+                _ => {
+                    map.next_value::<serde::de::IgnoredAny>()?;
+                }
+            }
+        }
+
+        let version = version.ok_or_else(|| A::Error::missing_field(WIRE_KEY_VERSION))?;
+        if version != COOKIE_WIRE_VERSION {
+            return Err(A::Error::custom(format!("unsupported cookie store version {version}")));
+        }
+
+        let mut store = BaseCookieStore::default();
+        for cookie in cookies.unwrap_or_default() {
+            cookie.insert_into(&mut store).map_err(A::Error::custom)?;
+        }
+
+        Ok(CookieStore(store))
+    }
 }
 
 impl<'de> Deserialize<'de> for CookieStore {
@@ -66,7 +292,7 @@ impl<'de> Deserialize<'de> for CookieStore {
     where
         D: Deserializer<'de>,
     {
-        deserializer.deserialize_seq(CookieStoreVisitor)
+        deserializer.deserialize_any(CookieStoreVisitor)
     }
 }
 
@@ -75,17 +301,46 @@ impl Serialize for CookieStore {
     where
         S: Serializer,
     {
-        serializer.collect_seq(self.iter_unexpired())
+        // Sort so the same cookies always produce the same message body.
+        let mut cookies: Vec<WireCookie> = self.iter_unexpired().filter_map(WireCookie::from_cookie).collect();
+        cookies.sort_by(|a, b| (&a.domain, &a.path, &a.name).cmp(&(&b.domain, &b.path, &b.name)));
+
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry(WIRE_KEY_VERSION, &COOKIE_WIRE_VERSION)?;
+        map.serialize_entry(WIRE_KEY_COOKIES, &cookies)?;
+        map.end()
     }
 }
 
 /// A [`CookieStore`] wrapped in a read-write lock.
 ///
-/// This allows the read-write lockked cookie store to implement the
+/// This allows the read-write locked cookie store to implement the
 /// Reqwest [`CookieStore`][reqwest::cookie::CookieStore] trait.
+///
+/// The lock does not poison, so a panic while it is held cannot take down later requests in the same Lambda
+/// invocation. Access goes through closures or [`snapshot`][CookieStoreRwLock::snapshot] so that a guard is never
+/// held across an `.await`.
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct CookieStoreRwLock(RwLock<CookieStore>);
 
+impl CookieStoreRwLock {
+    /// Return a copy of the current cookies, suitable for serializing into a
+    /// [`NextRequest`][crate::shapes::NextRequest].
+    pub fn snapshot(&self) -> CookieStore {
+        self.0.read().clone()
+    }
+
+    /// Run `f` with shared access to the cookies.
+    pub fn with<R>(&self, f: impl FnOnce(&CookieStore) -> R) -> R {
+        f(&self.0.read())
+    }
+
+    /// Run `f` with exclusive access to the cookies.
+    pub fn with_mut<R>(&self, f: impl FnOnce(&mut CookieStore) -> R) -> R {
+        f(&mut self.0.write())
+    }
+}
+
 impl From<BaseCookieStore> for CookieStoreRwLock {
     fn from(store: BaseCookieStore) -> Self {
         CookieStoreRwLock(RwLock::new(store.into()))
@@ -98,28 +353,13 @@ impl From<CookieStore> for CookieStoreRwLock {
     }
 }
 
-impl Deref for CookieStoreRwLock {
-    type Target = RwLock<CookieStore>;
-    fn deref(&self) -> &RwLock<CookieStore> {
-        &self.0
-    }
-}
-
-impl DerefMut for CookieStoreRwLock {
-    fn deref_mut(&mut self) -> &mut RwLock<CookieStore> {
-        &mut self.0
-    }
-}
-
 impl reqwest::cookie::CookieStore for CookieStoreRwLock {
     fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
-        let mut write = self.0.write().unwrap();
-        set_cookies(&mut write, cookie_headers, url);
+        self.with_mut(|store| set_cookies(store, cookie_headers, url));
     }
 
     fn cookies(&self, url: &Url) -> Option<HeaderValue> {
-        let read = self.0.read().unwrap();
-        cookies(&read, url)
+        self.with(|store| cookies(store, url))
     }
 }
 
@@ -147,3 +387,98 @@ fn cookies(cookie_store: &CookieStore, url: &Url) -> Option<HeaderValue> {
 
     HeaderValue::from_maybe_shared(Bytes::from(s)).ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{CookieStore, CrawlCookie},
+        cookie_store::RawCookie,
+        reqwest::Url,
+        std::time::Duration,
+    };
+
+    fn store() -> CookieStore {
+        let mut store = CookieStore::default();
+        let portal = Url::parse("https://pr-webs-vendor.des.wa.gov/Home.aspx").unwrap();
+        let sso = Url::parse("https://login.example.com/sso").unwrap();
+
+        for (cookie, url) in [
+            ("ASP.NET_SessionId=abc; Path=/", &portal),
+            ("Auth=def; Path=/; Max-Age=300", &portal),
+            ("Prefs=ghi; Path=/; Max-Age=86400", &portal),
+            ("Tracker=jkl; Path=/; Max-Age=60", &sso),
+        ] {
+            let cookie = RawCookie::parse(cookie).unwrap().into_owned();
+            store.insert_raw(&cookie, url).unwrap();
+        }
+
+        store
+    }
+
+    #[test]
+    fn expiring_within() {
+        let store = store();
+        let mut names: Vec<&str> = store.expiring_within(Duration::from_secs(600)).iter().map(|c| c.name()).collect();
+        names.sort();
+        assert_eq!(names, vec!["Auth", "Tracker"]);
+        assert!(store.expiring_within(Duration::ZERO).is_empty());
+    }
+
+    #[test]
+    fn wire_format_round_trip() {
+        let mut store = store();
+        store.retain_domain(&Url::parse("https://pr-webs-vendor.des.wa.gov/").unwrap());
+
+        let json = serde_json::to_string(&store).unwrap();
+        assert!(json
+            .starts_with(r#"{"v":1,"c":[{"n":"ASP.NET_SessionId","v":"abc","d":"pr-webs-vendor.des.wa.gov","p":"/"}"#));
+
+        let restored: CookieStore = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+
+        let url = Url::parse("https://pr-webs-vendor.des.wa.gov/Home.aspx").unwrap();
+        assert_eq!(restored.get_request_values(&url).count(), 3);
+    }
+
+    #[test]
+    fn wire_format_migration() {
+        const LEGACY: &str = r#"[{"raw_cookie":"TestCookie=Value; Domain=127.0.0.1","path":["/",false],"domain":{"Suffix":"127.0.0.1"},"expires":"SessionEnd"}]"#;
+        let store: CookieStore = serde_json::from_str(LEGACY).unwrap();
+        assert_eq!(
+            serde_json::to_string(&store).unwrap(),
+            r#"{"v":1,"c":[{"n":"TestCookie","v":"Value","d":".127.0.0.1","p":"/"}]}"#
+        );
+
+        assert!(serde_json::from_str::<CookieStore>(r#"{"v":2,"c":[]}"#).is_err());
+    }
+
+    #[test]
+    fn retain_domain() {
+        let mut store = store();
+        let portal = Url::parse("https://pr-webs-vendor.des.wa.gov/Search_Bid.aspx").unwrap();
+        assert_eq!(store.retain_domain(&portal), 1);
+        assert_eq!(store.iter_any().count(), 3);
+        assert!(store.iter_any().all(|c| c.name() != "Tracker"));
+        assert_eq!(store.retain_domain(&portal), 0);
+    }
+
+    #[test]
+    fn inject() {
+        let mut store = store();
+        let cookies: Vec<CrawlCookie> = serde_json::from_str(
+            r#"[{"Name": "Consent", "Value": "yes", "Domain": ".des.wa.gov"},
+                {"Name": "Auth", "Value": "pinned", "Domain": "pr-webs-vendor.des.wa.gov", "Path": "/"},
+                {"Name": "Bad", "Value": "x", "Domain": "not a host"}]"#,
+        )
+        .unwrap();
+        assert_eq!(cookies[0].path, "/");
+
+        assert_eq!(store.inject(&cookies), 1);
+        assert_eq!(store.inject(&cookies), 0);
+
+        let url = Url::parse("https://pr-webs-vendor.des.wa.gov/Home.aspx").unwrap();
+        let mut values: Vec<(&str, &str)> = store.get_request_values(&url).collect();
+        values.sort();
+        assert_eq!(values, [("ASP.NET_SessionId", "abc"), ("Auth", "def"), ("Consent", "yes"), ("Prefs", "ghi")]);
+    }
+}
@@ -0,0 +1,276 @@
+use {
+    hickory_resolver::{system_conf, TokioAsyncResolver},
+    log::*,
+    reqwest::dns::{Addrs, Name, Resolve, Resolving},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::BTreeMap,
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        io,
+        net::{IpAddr, SocketAddr},
+        str::FromStr,
+        sync::Arc,
+        time::Duration,
+    },
+    tokio::sync::OnceCell,
+};
+
+/// The longest a failed lookup is cached. Portals with flaky DNS sometimes return `SERVFAIL`; caching that for the
+/// full negative TTL of the zone would fail every request for the rest of the invocation.
+const NEGATIVE_MAX_TTL_SECS: u64 = 5;
+
+/// The address families a client connects with.
+///
+/// Reqwest connects with "happy eyeballs": it tries the first address family returned by the resolver, then falls
+/// back to the other if the connection hasn't been established within a short delay. Some portals publish AAAA
+/// records for hosts that don't answer on IPv6, which costs that delay (or a full connect timeout, if every address
+/// is IPv6) on every connection from a dual-stack subnet. Those portals can be preferred or forced onto IPv4.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum AddressFamily {
+    /// Use addresses in the order the resolver returns them.
+    #[default]
+    Any,
+
+    /// Connect only over IPv4.
+    Ipv4,
+
+    /// Connect only over IPv6.
+    Ipv6,
+
+    /// Try IPv4 first, falling back to IPv6.
+    PreferIpv4,
+
+    /// Try IPv6 first, falling back to IPv4.
+    PreferIpv6,
+}
+
+impl AddressFamily {
+    /// Indicates whether addresses of every family are used in the resolver's order.
+    pub fn is_any(&self) -> bool {
+        *self == Self::Any
+    }
+
+    /// Filter and order resolved addresses for this preference.
+    pub fn apply(self, addrs: impl IntoIterator<Item = IpAddr>) -> Vec<IpAddr> {
+        let mut addrs: Vec<IpAddr> = addrs.into_iter().collect();
+        match self {
+            Self::Any => (),
+            Self::Ipv4 => addrs.retain(IpAddr::is_ipv4),
+            Self::Ipv6 => addrs.retain(IpAddr::is_ipv6),
+            // Sorting is stable, so the resolver's order is kept within each family.
+            Self::PreferIpv4 => addrs.sort_by_key(IpAddr::is_ipv6),
+            Self::PreferIpv6 => addrs.sort_by_key(IpAddr::is_ipv4),
+        }
+
+        addrs
+    }
+}
+
+impl Display for AddressFamily {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Any => f.write_str("Any"),
+            Self::Ipv4 => f.write_str("Ipv4"),
+            Self::Ipv6 => f.write_str("Ipv6"),
+            Self::PreferIpv4 => f.write_str("PreferIpv4"),
+            Self::PreferIpv6 => f.write_str("PreferIpv6"),
+        }
+    }
+}
+
+/// A DNS resolver that caches lookups, respecting their TTLs.
+///
+/// Each crawl operation builds a new client, so Reqwest's own resolver would start with an empty cache every time.
+/// This resolver is created once with the [LogConfig][crate::httpext::LogConfig] and shared by every client built
+/// during the invocation, so the requests made to a portal resolve its hosts once.
+#[derive(Clone, Debug)]
+pub struct CachingResolver {
+    /// The maximum number of lookups cached.
+    cache_size: usize,
+
+    /// The address families returned to the client.
+    family: AddressFamily,
+
+    /// The resolver. This is created on the first lookup since it must be created within a Tokio runtime.
+    state: Arc<OnceCell<TokioAsyncResolver>>,
+}
+
+impl CachingResolver {
+    /// Create a new resolver caching up to `cache_size` lookups.
+    pub fn new(cache_size: usize) -> Self {
+        Self {
+            cache_size,
+            family: AddressFamily::Any,
+            state: Arc::new(OnceCell::new()),
+        }
+    }
+
+    /// Returns a resolver sharing this one's cache that returns addresses for the given families.
+    pub fn with_family(&self, family: AddressFamily) -> Self {
+        Self {
+            family,
+            ..self.clone()
+        }
+    }
+
+    /// Create the underlying resolver from the system configuration (`/etc/resolv.conf`).
+    async fn resolver(&self) -> io::Result<&TokioAsyncResolver> {
+        self.state
+            .get_or_try_init(|| async {
+                let (config, mut opts) = system_conf::read_system_conf()
+                    .map_err(|e| io::Error::other(format!("error reading DNS system conf: {e}")))?;
+                opts.cache_size = self.cache_size;
+                opts.negative_max_ttl = Some(Duration::from_secs(NEGATIVE_MAX_TTL_SECS));
+                Ok(TokioAsyncResolver::tokio(config, opts))
+            })
+            .await
+    }
+}
+
+impl Resolve for CachingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let lookup = resolver.resolver().await?.lookup_ip(name.as_str()).await?;
+            let ips = resolver.family.apply(lookup.iter());
+            debug!("Resolved {} to {:?} ({})", name.as_str(), ips, resolver.family);
+            if ips.is_empty() {
+                return Err(NoAddressError {
+                    host: name.as_str().to_string(),
+                    family: resolver.family,
+                }
+                .into());
+            }
+
+            let addrs: Addrs = Box::new(ips.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
+
+/// An error indicating a host has no addresses in the address families a client is restricted to.
+#[derive(Debug)]
+pub struct NoAddressError {
+    /// The host that was looked up.
+    pub host: String,
+
+    /// The address families the client is restricted to.
+    pub family: AddressFamily,
+}
+
+impl Display for NoAddressError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "No {} addresses for {}", self.family, self.host)
+    }
+}
+
+impl Error for NoAddressError {}
+
+/// Hosts whose addresses are pinned rather than looked up.
+///
+/// This is written as `host=address,address;host=address`, e.g.
+/// `www.example.gov=192.0.2.10,2001:db8::10;bids.example.gov=192.0.2.20`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DnsPins(BTreeMap<String, Vec<IpAddr>>);
+
+impl DnsPins {
+    /// Returns the pinned addresses for `host`, if any.
+    pub fn get(&self, host: &str) -> Option<&[IpAddr]> {
+        self.0.get(&host.to_ascii_lowercase()).map(Vec::as_slice)
+    }
+
+    /// Returns an iterator over the pinned hosts and their addresses.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[IpAddr])> {
+        self.0.iter().map(|(host, addrs)| (host.as_str(), addrs.as_slice()))
+    }
+
+    /// Returns `true` if no hosts are pinned.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromStr for DnsPins {
+    type Err = InvalidDnsPinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pins = BTreeMap::new();
+
+        for pin in s.split(';').map(str::trim).filter(|pin| !pin.is_empty()) {
+            let invalid = || InvalidDnsPinError(pin.to_string());
+            let (host, addrs) = pin.split_once('=').ok_or_else(invalid)?;
+            let host = host.trim();
+            if host.is_empty() {
+                return Err(invalid());
+            }
+
+            let addrs = addrs.split(',').map(|addr| addr.trim().parse()).collect::<Result<Vec<IpAddr>, _>>();
+            match addrs {
+                Ok(addrs) if !addrs.is_empty() => {
+                    pins.insert(host.to_ascii_lowercase(), addrs);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(Self(pins))
+    }
+}
+
+/// An error indicating a DNS pin is not of the form `host=address,address`.
+#[derive(Debug)]
+pub struct InvalidDnsPinError(pub String);
+
+impl Display for InvalidDnsPinError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "Invalid DNS pin: {:?}", self.0)
+    }
+}
+
+impl Error for InvalidDnsPinError {}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{AddressFamily, DnsPins},
+        std::net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    };
+
+    #[test]
+    fn address_families() {
+        let v4: IpAddr = "192.0.2.10".parse().unwrap();
+        let v4b: IpAddr = "192.0.2.11".parse().unwrap();
+        let v6: IpAddr = "2001:db8::10".parse().unwrap();
+        let addrs = [v6, v4, v4b];
+
+        assert_eq!(AddressFamily::Any.apply(addrs), vec![v6, v4, v4b]);
+        assert_eq!(AddressFamily::Ipv4.apply(addrs), vec![v4, v4b]);
+        assert_eq!(AddressFamily::Ipv6.apply(addrs), vec![v6]);
+        assert_eq!(AddressFamily::PreferIpv4.apply(addrs), vec![v4, v4b, v6]);
+        assert_eq!(AddressFamily::PreferIpv6.apply([v4, v6, v4b]), vec![v6, v4, v4b]);
+        assert!(AddressFamily::Ipv6.apply([v4]).is_empty());
+
+        let family: AddressFamily = serde_json::from_str(r#""PreferIpv4""#).unwrap();
+        assert_eq!(family, AddressFamily::PreferIpv4);
+    }
+
+    #[test]
+    fn pins() {
+        let pins: DnsPins =
+            " WWW.Example.gov = 192.0.2.10, 2001:db8::10 ; bids.example.gov=192.0.2.20;".parse().unwrap();
+        assert_eq!(
+            pins.get("www.example.gov").unwrap(),
+            &[IpAddr::V4(Ipv4Addr::new(192, 0, 2, 10)), IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x10))]
+        );
+        assert_eq!(pins.get("BIDS.example.gov").unwrap(), &[IpAddr::V4(Ipv4Addr::new(192, 0, 2, 20))]);
+        assert!(pins.get("example.gov").is_none());
+        assert_eq!(pins.iter().count(), 2);
+
+        assert!("".parse::<DnsPins>().unwrap().is_empty());
+        assert!("www.example.gov".parse::<DnsPins>().is_err());
+        assert!("www.example.gov=".parse::<DnsPins>().is_err());
+        assert!("=192.0.2.10".parse::<DnsPins>().is_err());
+        assert!("www.example.gov=192.0.2.300".parse::<DnsPins>().is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use {
+    crate::httpext::NoAddressError,
+    hickory_resolver::error::ResolveError,
+    serde::{Deserialize, Serialize},
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        io::{Error as IoError, ErrorKind as IoErrorKind},
+    },
+};
+
+/// Fragments of error messages, compared in lowercase, that mark a failed name lookup.
+const DNS_MARKERS: &[&str] = &["dns error", "failed to lookup address", "no record found", "name or service not known"];
+
+/// Fragments of error messages, compared in lowercase, that mark a failed TLS handshake.
+const TLS_MARKERS: &[&str] = &["certificate", "tls", "ssl", "handshake", "received fatal alert"];
+
+/// Fragments of error messages, compared in lowercase, that mark a connection dropped by the server.
+const RESET_MARKERS: &[&str] = &["connection closed before message completed", "connection reset"];
+
+/// The kind of connection-level failure behind a request that didn't get a complete response.
+///
+/// This is recorded with the request log item for a failed fetch so portal outages can be told apart from the data: a
+/// DNS failure or refused connection usually means the portal is down or has moved, a TLS failure that its certificate
+/// has lapsed, and timeouts or resets that it is overloaded.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum FetchErrorKind {
+    /// The host name couldn't be resolved.
+    Dns,
+
+    /// The TLS handshake failed, for example because the certificate was invalid or expired.
+    Tls,
+
+    /// The connection wasn't established in time.
+    ConnectTimeout,
+
+    /// The connection was established, but the response wasn't received in time.
+    ReadTimeout,
+
+    /// The server closed or reset the connection before the response was complete.
+    Reset,
+
+    /// The server refused the connection.
+    Refused,
+
+    /// Any other failure.
+    Other,
+}
+
+impl FetchErrorKind {
+    /// Classify an error returned while sending a request or reading its response, examining its chain of sources.
+    pub fn classify(error: &(dyn Error + 'static)) -> Self {
+        let mut connecting = false;
+        let mut timed_out = false;
+        let mut source = Some(error);
+
+        while let Some(e) = source {
+            source = e.source();
+
+            if e.is::<NoAddressError>() || e.is::<ResolveError>() {
+                return Self::Dns;
+            }
+
+            // Reqwest's own messages include the URL, so only its flags are examined.
+            if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+                connecting |= e.is_connect();
+                timed_out |= e.is_timeout();
+                continue;
+            }
+
+            if let Some(e) = e.downcast_ref::<IoError>() {
+                match e.kind() {
+                    IoErrorKind::ConnectionReset
+                    | IoErrorKind::ConnectionAborted
+                    | IoErrorKind::BrokenPipe
+                    | IoErrorKind::UnexpectedEof => return Self::Reset,
+                    IoErrorKind::ConnectionRefused => return Self::Refused,
+                    IoErrorKind::TimedOut => timed_out = true,
+                    _ => (),
+                }
+            }
+
+            let message = e.to_string().to_lowercase();
+            let matches = |markers: &[&str]| markers.iter().any(|marker| message.contains(marker));
+            if matches(DNS_MARKERS) {
+                return Self::Dns;
+            }
+
+            if matches(TLS_MARKERS) {
+                return Self::Tls;
+            }
+
+            if matches(RESET_MARKERS) {
+                return Self::Reset;
+            }
+        }
+
+        match (timed_out, connecting) {
+            (true, true) => Self::ConnectTimeout,
+            (true, false) => Self::ReadTimeout,
+            (false, _) => Self::Other,
+        }
+    }
+
+    /// Return the name recorded in log items for this kind of failure.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Dns => "Dns",
+            Self::Tls => "Tls",
+            Self::ConnectTimeout => "ConnectTimeout",
+            Self::ReadTimeout => "ReadTimeout",
+            Self::Reset => "Reset",
+            Self::Refused => "Refused",
+            Self::Other => "Other",
+        }
+    }
+}
+
+impl Display for FetchErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Dns => f.write_str("DNS failure"),
+            Self::Tls => f.write_str("TLS failure"),
+            Self::ConnectTimeout => f.write_str("connect timeout"),
+            Self::ReadTimeout => f.write_str("read timeout"),
+            Self::Reset => f.write_str("connection reset"),
+            Self::Refused => f.write_str("connection refused"),
+            Self::Other => f.write_str("other failure"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::FetchErrorKind,
+        crate::httpext::{AddressFamily, NoAddressError},
+        std::{
+            error::Error,
+            fmt::{Display, Formatter, Result as FmtResult},
+            io::{Error as IoError, ErrorKind as IoErrorKind},
+            net::TcpListener,
+        },
+    };
+
+    /// An error wrapping another, as hyper and reqwest do.
+    #[derive(Debug)]
+    struct Wrapped(&'static str, Box<dyn Error + Send + Sync>);
+
+    impl Display for Wrapped {
+        fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+            f.write_str(self.0)
+        }
+    }
+
+    impl Error for Wrapped {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(self.1.as_ref())
+        }
+    }
+
+    fn wrapped<E: Into<Box<dyn Error + Send + Sync>>>(inner: E) -> Wrapped {
+        Wrapped("client error (Connect)", inner.into())
+    }
+
+    #[test]
+    fn classify_chains() {
+        let no_address = NoAddressError {
+            host: "bids.example.gov".to_string(),
+            family: AddressFamily::Ipv4,
+        };
+        assert_eq!(FetchErrorKind::classify(&wrapped(no_address)), FetchErrorKind::Dns);
+        assert_eq!(
+            FetchErrorKind::classify(&wrapped(IoError::other("dns error: failed to lookup address information"))),
+            FetchErrorKind::Dns
+        );
+        assert_eq!(
+            FetchErrorKind::classify(&wrapped(IoError::new(
+                IoErrorKind::InvalidData,
+                "invalid peer certificate: Expired"
+            ))),
+            FetchErrorKind::Tls
+        );
+        assert_eq!(
+            FetchErrorKind::classify(&wrapped(IoError::from(IoErrorKind::ConnectionReset))),
+            FetchErrorKind::Reset
+        );
+        assert_eq!(
+            FetchErrorKind::classify(&wrapped("connection closed before message completed")),
+            FetchErrorKind::Reset
+        );
+        assert_eq!(
+            FetchErrorKind::classify(&wrapped(IoError::from(IoErrorKind::TimedOut))),
+            FetchErrorKind::ReadTimeout
+        );
+        assert_eq!(FetchErrorKind::classify(&wrapped("something else")), FetchErrorKind::Other);
+    }
+
+    #[tokio::test]
+    async fn classify_refused() {
+        // Bind a port, then release it so nothing is listening there.
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let err = reqwest::get(format!("http://127.0.0.1:{port}/")).await.unwrap_err();
+        assert_eq!(FetchErrorKind::classify(&err), FetchErrorKind::Refused);
+    }
+}
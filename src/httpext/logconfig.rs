@@ -1,20 +1,97 @@
 use {
-    crate::{httpext::log_aws_err, BoxError},
-    aws_sdk_dynamodb::Client as DynamoDbClient,
-    aws_sdk_s3::Client as S3Client,
+    crate::{
+        attachment::AttachmentStore,
+        checkpoint::CheckpointStore,
+        config,
+        cost::{self, Prices, UsageMeter},
+        crawl::CrawlRegistry,
+        dead_letter::FailureStore,
+        extract,
+        flags::FeatureFlags,
+        frontier::FrontierStore,
+        health::HealthThresholds,
+        httpext::{
+            log_aws_err, BodyCompression, BodyIndex, BodyMemory, CachingResolver, DdbBatchWriter, RequestAudit,
+            StoredBody, TransportSettings, WriteSpill, DEFAULT_BODY_LOOKUP_BATCH_THRESHOLD, DEFAULT_S3_KEY_TEMPLATE,
+        },
+        journal::Journal,
+        link_graph::LinkStore,
+        reconcile::{OpportunityStore, DEFAULT_UNSEEN_DAYS},
+        retry::DEFAULT_MAX_ATTEMPTS,
+        schedule::ScheduleStore,
+        search::{SearchSettings, SearchSink, ENV_OPENSEARCH_ENDPOINT},
+        session::SessionStore,
+        vendors::VendorStore,
+        wa_local, BoxError,
+    },
+    aws_config::{sts::AssumeRoleProvider, Region, SdkConfig},
+    aws_sdk_dynamodb::{
+        config::{retry::RetryConfig, Builder as DynamoDbConfigBuilder},
+        Client as DynamoDbClient,
+    },
+    aws_sdk_kms::Client as KmsClient,
+    aws_sdk_s3::{config::SharedCredentialsProvider, Client as S3Client},
+    aws_sdk_sns::Client as SnsClient,
     aws_sdk_sqs::Client as SqsClient,
     aws_sdk_ssm::Client as SsmClient,
+    aws_sdk_textract::Client as TextractClient,
+    chrono::Duration,
     log::*,
-    std::env,
+    parking_lot::Mutex,
+    std::{
+        collections::BTreeMap,
+        env,
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        sync::Arc,
+    },
 };
 
 const ENV_LOG_S3_BUCKET: &str = "LOG_S3_BUCKET";
 const ENV_LOG_S3_PREFIX: &str = "LOG_S3_PREFIX";
-const ENV_LOG_DDB_TABLE: &str = "LOG_DYNAMODB_TABLE";
+const ENV_LOG_S3_KEY_TEMPLATE: &str = "LOG_S3_KEY_TEMPLATE";
+const ENV_LOG_S3_COMPRESSION: &str = "LOG_S3_COMPRESSION";
+const ENV_LOG_S3_ENCRYPTION_KEYS: &str = "LOG_S3_ENCRYPTION_KEYS";
+const ENV_LOG_DDB_TABLE: &str = "LOG_DDB_TABLE";
 const ENV_LOG_DYNAMODB_TABLE: &str = "LOG_DYNAMODB_TABLE";
+const ENV_JOURNAL_DYNAMODB_TABLE: &str = "JOURNAL_DYNAMODB_TABLE";
+const ENV_CHECKPOINT_DYNAMODB_TABLE: &str = "CHECKPOINT_DYNAMODB_TABLE";
+const ENV_CRAWL_DYNAMODB_TABLE: &str = "CRAWL_DYNAMODB_TABLE";
+const ENV_SESSION_DYNAMODB_TABLE: &str = "SESSION_DYNAMODB_TABLE";
+const ENV_ATTACHMENT_DYNAMODB_TABLE: &str = "ATTACHMENT_DYNAMODB_TABLE";
+const ENV_OPPORTUNITY_DYNAMODB_TABLE: &str = "OPPORTUNITY_DYNAMODB_TABLE";
+const ENV_FAILURE_DYNAMODB_TABLE: &str = "FAILURE_DYNAMODB_TABLE";
+const ENV_SCHEDULE_DYNAMODB_TABLE: &str = "SCHEDULE_DYNAMODB_TABLE";
+const ENV_LINK_DYNAMODB_TABLE: &str = "LINK_DYNAMODB_TABLE";
+const ENV_FRONTIER_DYNAMODB_TABLE: &str = "FRONTIER_DYNAMODB_TABLE";
+const ENV_VENDOR_DYNAMODB_TABLE: &str = "VENDOR_DYNAMODB_TABLE";
+const ENV_LOGIN_MIN_INTERVAL_SECS: &str = "LOGIN_MIN_INTERVAL_SECS";
+const ENV_LOG_DYNAMODB_WRITE_SHARDS: &str = "LOG_DYNAMODB_WRITE_SHARDS";
+const ENV_LOG_DYNAMODB_MAX_ATTEMPTS: &str = "LOG_DYNAMODB_MAX_ATTEMPTS";
+const ENV_BODY_LOOKUP_BATCH_THRESHOLD: &str = "BODY_LOOKUP_BATCH_THRESHOLD";
+const ENV_LOG_S3_REGION: &str = "LOG_S3_REGION";
+const ENV_LOG_S3_ROLE_ARN: &str = "LOG_S3_ROLE_ARN";
+const ENV_LOG_DYNAMODB_REGION: &str = "LOG_DYNAMODB_REGION";
+const ENV_LOG_DYNAMODB_ROLE_ARN: &str = "LOG_DYNAMODB_ROLE_ARN";
 const ENV_SQS_QUEUE_URL: &str = "SQS_QUEUE_URL";
+const ENV_SQS_REGION: &str = "SQS_REGION";
+const ENV_SQS_ROLE_ARN: &str = "SQS_ROLE_ARN";
 const ENV_SSM_PREFIX: &str = "SSM_PREFIX";
+const ENV_ALERT_SNS_TOPIC_ARN: &str = "ALERT_SNS_TOPIC_ARN";
+const ENV_CRAWL_AUDIT: &str = "CRAWL_AUDIT";
+const ENV_RECONCILE_UNSEEN_DAYS: &str = "RECONCILE_UNSEEN_DAYS";
+const ENV_RETRY_MAX_ATTEMPTS: &str = "RETRY_MAX_ATTEMPTS";
 const DEFAULT_SSM_PREFIX: &str = "/GovScout/";
+const DEFAULT_DDB_WRITE_SHARDS: u32 = 1;
+
+/// Attempts the DynamoDB client makes at each call, with the SDK's adaptive retry mode slowing calls to a throttling
+/// table.
+const DEFAULT_DDB_MAX_ATTEMPTS: u32 = 10;
+const DEFAULT_LOGIN_MIN_INTERVAL_SECS: u64 = 900;
+const ASSUME_ROLE_SESSION_NAME: &str = "GovScout";
+
+/// Environment variables that must be set for [`LogConfig::new`] to succeed.
+pub const REQUIRED_ENV_VARS: &[&str] = &[ENV_LOG_S3_BUCKET, ENV_SQS_QUEUE_URL, ENV_LOG_DYNAMODB_TABLE];
 
 /// Configuration for logging requests and responses.
 #[derive(Clone, Debug)]
@@ -31,12 +108,40 @@ pub struct LogConfig {
     /// The Systems Manager client to use.
     pub ssm_client: SsmClient,
 
+    /// The Simple Notification Service (SNS) client to use for alerts.
+    pub sns_client: SnsClient,
+
     /// The S3 bucket to log to.
     pub s3_bucket: String,
 
     /// The S3 key prefix to use.
     pub s3_prefix: String,
 
+    /// The template for S3 keys of stored bodies, appended to the prefix. See
+    /// [`render_s3_key`][crate::httpext::render_s3_key] for the supported placeholders.
+    pub s3_key_template: String,
+
+    /// How bodies are compressed when they are stored.
+    pub s3_compression: BodyCompression,
+
+    /// The KMS keys bodies are encrypted under before they are stored, by portal (subsystem); see
+    /// [`body_crypto`][crate::httpext::seal_body]. Bodies from other portals are stored unencrypted.
+    pub s3_encryption_keys: BTreeMap<String, String>,
+
+    /// The client for generating and decrypting the data keys of encrypted bodies.
+    pub kms_client: KmsClient,
+
+    /// The client for detecting the text of scanned attachments in the log bucket.
+    pub textract_client: TextractClient,
+
+    /// Whether attachments without a text layer are sent to Textract for [text detection][crate::extract], from
+    /// `ATTACHMENT_OCR`.
+    pub attachment_ocr: bool,
+
+    /// Where opportunities and the text of their attachments are indexed for [keyword search][crate::search], or
+    /// `None` if `OPENSEARCH_ENDPOINT` is unset.
+    pub search: Option<SearchSink>,
+
     /// The SQS queue URL to use.
     pub sqs_queue_url: String,
 
@@ -45,34 +150,509 @@ pub struct LogConfig {
 
     /// The DynamoDB table to use.
     pub ddb_table: String,
+
+    /// Buffered writer for log items in the DynamoDB table, which spills the items a throttling table keeps refusing to
+    /// the log bucket.
+    pub ddb_writer: DdbBatchWriter,
+
+    /// The index of stored bodies by SHA-256 digest in the DynamoDB table, which batches lookups while many bodies are
+    /// being stored at once.
+    pub body_index: BodyIndex,
+
+    /// The number of partition key shards each crawl's log items are spread across.
+    pub ddb_write_shards: u32,
+
+    /// The crawl event journal.
+    pub journal: Journal,
+
+    /// Checkpoints for resumable operations.
+    pub checkpoints: CheckpointStore,
+
+    /// The registry of crawls.
+    pub crawls: CrawlRegistry,
+
+    /// Login sessions for portal accounts.
+    pub sessions: SessionStore,
+
+    /// Links between stored attachments and the opportunities publishing them.
+    pub attachments: AttachmentStore,
+
+    /// The lifecycle of every opportunity seen, for closing those portals stop publishing.
+    pub opportunities: OpportunityStore,
+
+    /// Records of failed deliveries of requests, for triaging the dead-letter queue.
+    pub failures: FailureStore,
+
+    /// Crawl schedules and the change rates they are tuned from.
+    pub schedules: ScheduleStore,
+
+    /// The links crawls follow from page to page.
+    pub links: LinkStore,
+
+    /// The URLs each crawl has visited and has yet to visit.
+    pub frontier: FrontierStore,
+
+    /// The vendors listed in portals' vendor directories.
+    pub vendors: VendorStore,
+
+    /// The minimum time between fresh logins to the same portal account. Within it, the last session is reused.
+    pub login_min_interval: Duration,
+
+    /// How long an opportunity can go unseen, relative to the latest sighting on its portal, before
+    /// [reconciliation][crate::reconcile] closes it.
+    pub reconcile_unseen_after: Duration,
+
+    /// The number of times a request is re-enqueued by the [`Retry`][crate::middleware::Retry] middleware before its
+    /// failure is allowed through.
+    pub retry_max_attempts: u32,
+
+    /// The SNS topic to publish alerts to. If unset, alerts are only logged.
+    pub alert_topic_arn: Option<String>,
+
+    /// Thresholds for crawl health checks.
+    pub health_thresholds: HealthThresholds,
+
+    /// Connection and protocol settings for crawl clients.
+    pub transport: TransportSettings,
+
+    /// The caching DNS resolver shared by crawl clients, or `None` if DNS caching is disabled.
+    pub dns_resolver: Option<Arc<CachingResolver>>,
+
+    /// Tallies of the domains each crawl contacts, recorded if `CRAWL_AUDIT` is set to `true`.
+    pub audit: RequestAudit,
+
+    /// Response body bytes held by crawl clients, which new fetches wait on when over the transport's limit.
+    pub body_memory: BodyMemory,
+
+    /// Where the body of the last response logged by the current operation was stored. Each operation is given its
+    /// own by [`for_operation`][LogConfig::for_operation].
+    pub last_stored: Arc<Mutex<Option<StoredBody>>>,
+
+    /// The billable resources used by the current operation. Each operation is given its own by
+    /// [`for_operation`][LogConfig::for_operation].
+    pub usage: UsageMeter,
+
+    /// Unit prices for estimating the cost of crawls.
+    pub prices: Prices,
+
+    /// The monthly budgets of portals, in US dollars, from `CRAWL_MONTHLY_BUDGETS`.
+    pub monthly_budgets: BTreeMap<String, f64>,
+
+    /// The feature flags in effect for the current operation, resolved by the
+    /// [`FeatureFlagging`][crate::middleware::FeatureFlagging] middleware.
+    pub flags: FeatureFlags,
+
+    /// The ids of the Washington local sites to crawl, from `WA_LOCAL_SITES`.
+    pub wa_local_sites: Vec<&'static str>,
+}
+
+/// A single missing or invalid configuration setting.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConfigProblem {
+    /// The environment variable at fault.
+    pub var: String,
+
+    /// What is wrong with it.
+    pub message: String,
+}
+
+/// The deployment configuration has missing or invalid settings. Every problem found is reported, not just the first.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ConfigError {
+    /// The problems found.
+    pub problems: Vec<ConfigProblem>,
+}
+
+impl ConfigError {
+    /// Record a problem with a setting.
+    pub(crate) fn push(&mut self, var: &str, message: impl Into<String>) {
+        self.problems.push(ConfigProblem {
+            var: var.to_string(),
+            message: message.into(),
+        });
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "Invalid configuration ({} problem(s))", self.problems.len())?;
+        for problem in self.problems.iter() {
+            write!(f, "; {}: {}", problem.var, problem.message)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Settings read from environment variables, validated but not yet turned into clients.
+#[derive(Clone, Debug, PartialEq)]
+struct Settings {
+    s3_bucket: String,
+    s3_prefix: String,
+    s3_key_template: String,
+    s3_compression: BodyCompression,
+    s3_encryption_keys: BTreeMap<String, String>,
+    sqs_queue_url: String,
+    ssm_prefix: String,
+    ddb_table: String,
+    journal_table: String,
+    checkpoint_table: String,
+    crawl_table: String,
+    session_table: String,
+    attachment_table: String,
+    opportunity_table: String,
+    failure_table: String,
+    schedule_table: String,
+    link_table: String,
+    frontier_table: String,
+    vendor_table: String,
+    ddb_write_shards: u32,
+    ddb_max_attempts: u32,
+    body_lookup_batch_threshold: usize,
+    login_min_interval_secs: u64,
+    reconcile_unseen_days: i64,
+    retry_max_attempts: u32,
+    alert_topic_arn: Option<String>,
+    audit: bool,
+    transport: TransportSettings,
+    health_thresholds: HealthThresholds,
+    prices: Prices,
+    monthly_budgets: BTreeMap<String, f64>,
+    ddb_location: ResourceLocation,
+    s3_location: ResourceLocation,
+    sqs_location: ResourceLocation,
+    wa_local_sites: Vec<&'static str>,
+    attachment_ocr: bool,
+    search: Option<SearchSettings>,
+}
+
+/// Where a resource lives, if not in the Lambda's own region and account.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct ResourceLocation {
+    region: Option<String>,
+    role_arn: Option<String>,
+}
+
+impl ResourceLocation {
+    /// Read a resource's region from `region_var` and the role to assume to reach it from `role_arn_var`, looking
+    /// them up with `lookup`. Invalid values are recorded in `errors`.
+    fn from_lookup<F: Fn(&str) -> Option<String>>(
+        lookup: &F,
+        region_var: &str,
+        role_arn_var: &str,
+        errors: &mut ConfigError,
+    ) -> Self {
+        let region = lookup(region_var).filter(|region| {
+            let valid = region.contains('-')
+                && region.ends_with(|c: char| c.is_ascii_digit())
+                && region.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+            if !valid {
+                errors.push(region_var, format!("{region:?} is not an AWS region"));
+            }
+            valid
+        });
+        let role_arn = lookup(role_arn_var).filter(|role_arn| {
+            let valid = role_arn.starts_with("arn:") && role_arn.contains(":iam::") && role_arn.contains(":role/");
+            if !valid {
+                errors.push(role_arn_var, format!("{role_arn:?} is not an IAM role ARN"));
+            }
+            valid
+        });
+
+        Self {
+            region,
+            role_arn,
+        }
+    }
+}
+
+impl Settings {
+    /// Read and validate the settings, looking variables up with `lookup`.
+    fn from_lookup<F: Fn(&str) -> Option<String>>(lookup: F) -> Result<Self, ConfigError> {
+        let mut errors = ConfigError::default();
+        let mut required = |var: &str| match lookup(var) {
+            Some(value) if !value.is_empty() => value,
+            _ => {
+                errors.push(var, "must be set");
+                String::new()
+            }
+        };
+
+        let s3_bucket = required(ENV_LOG_S3_BUCKET);
+        let sqs_queue_url = required(ENV_SQS_QUEUE_URL);
+        let ddb_table = match lookup(ENV_LOG_DYNAMODB_TABLE).or_else(|| lookup(ENV_LOG_DDB_TABLE)) {
+            Some(table) if !table.is_empty() => table,
+            _ => {
+                errors.push(ENV_LOG_DYNAMODB_TABLE, format!("must be set (or the legacy {ENV_LOG_DDB_TABLE})"));
+                String::new()
+            }
+        };
+
+        let s3_key_template = match lookup(ENV_LOG_S3_KEY_TEMPLATE) {
+            Some(template) if template.contains("{sha256}") => template,
+            Some(template) => {
+                errors.push(ENV_LOG_S3_KEY_TEMPLATE, format!("{template:?} does not contain {{sha256}}"));
+                template
+            }
+            None => DEFAULT_S3_KEY_TEMPLATE.to_string(),
+        };
+
+        let s3_compression = match lookup(ENV_LOG_S3_COMPRESSION) {
+            Some(value) => value.parse().unwrap_or_else(|e| {
+                errors.push(ENV_LOG_S3_COMPRESSION, e);
+                BodyCompression::default()
+            }),
+            None => BodyCompression::default(),
+        };
+
+        let mut s3_encryption_keys = BTreeMap::new();
+        for entry in lookup(ENV_LOG_S3_ENCRYPTION_KEYS).unwrap_or_default().split(',').map(str::trim) {
+            match entry.split_once('=').map(|(portal, key_id)| (portal.trim(), key_id.trim())) {
+                _ if entry.is_empty() => (),
+                Some((portal, key_id)) if !portal.is_empty() && !key_id.is_empty() => {
+                    s3_encryption_keys.insert(portal.to_string(), key_id.to_string());
+                }
+                _ => errors.push(ENV_LOG_S3_ENCRYPTION_KEYS, format!("{entry:?} is not Portal=KeyId")),
+            }
+        }
+
+        let ddb_write_shards = match lookup(ENV_LOG_DYNAMODB_WRITE_SHARDS) {
+            Some(value) => match value.parse() {
+                Ok(shards) if shards > 0 => shards,
+                _ => {
+                    errors.push(ENV_LOG_DYNAMODB_WRITE_SHARDS, format!("{value:?} is not a positive integer"));
+                    DEFAULT_DDB_WRITE_SHARDS
+                }
+            },
+            None => DEFAULT_DDB_WRITE_SHARDS,
+        };
+
+        let ddb_max_attempts = match lookup(ENV_LOG_DYNAMODB_MAX_ATTEMPTS) {
+            Some(value) => match value.parse() {
+                Ok(attempts) if attempts > 0 => attempts,
+                _ => {
+                    errors.push(ENV_LOG_DYNAMODB_MAX_ATTEMPTS, format!("{value:?} is not a positive integer"));
+                    DEFAULT_DDB_MAX_ATTEMPTS
+                }
+            },
+            None => DEFAULT_DDB_MAX_ATTEMPTS,
+        };
+
+        let body_lookup_batch_threshold = match lookup(ENV_BODY_LOOKUP_BATCH_THRESHOLD) {
+            Some(value) => value.parse().unwrap_or_else(|_| {
+                errors.push(ENV_BODY_LOOKUP_BATCH_THRESHOLD, format!("{value:?} is not a number of bodies"));
+                DEFAULT_BODY_LOOKUP_BATCH_THRESHOLD
+            }),
+            None => DEFAULT_BODY_LOOKUP_BATCH_THRESHOLD,
+        };
+
+        let login_min_interval_secs = match lookup(ENV_LOGIN_MIN_INTERVAL_SECS) {
+            Some(value) => value.parse().unwrap_or_else(|_| {
+                errors.push(ENV_LOGIN_MIN_INTERVAL_SECS, format!("{value:?} is not a number of seconds"));
+                DEFAULT_LOGIN_MIN_INTERVAL_SECS
+            }),
+            None => DEFAULT_LOGIN_MIN_INTERVAL_SECS,
+        };
+
+        let reconcile_unseen_days = match lookup(ENV_RECONCILE_UNSEEN_DAYS) {
+            Some(value) => match value.parse() {
+                Ok(days) if days > 0 => days,
+                _ => {
+                    errors.push(ENV_RECONCILE_UNSEEN_DAYS, format!("{value:?} is not a positive number of days"));
+                    DEFAULT_UNSEEN_DAYS
+                }
+            },
+            None => DEFAULT_UNSEEN_DAYS,
+        };
+
+        let retry_max_attempts = match lookup(ENV_RETRY_MAX_ATTEMPTS) {
+            Some(value) => value.trim().parse().unwrap_or_else(|_| {
+                errors.push(ENV_RETRY_MAX_ATTEMPTS, format!("{value:?} is not a number of attempts"));
+                DEFAULT_MAX_ATTEMPTS
+            }),
+            None => DEFAULT_MAX_ATTEMPTS,
+        };
+
+        let audit = match lookup(ENV_CRAWL_AUDIT) {
+            Some(value) => value.parse().unwrap_or_else(|_| {
+                errors.push(ENV_CRAWL_AUDIT, format!("{value:?} is not true or false"));
+                false
+            }),
+            None => false,
+        };
+
+        let transport = TransportSettings::from_lookup(&lookup, &mut errors);
+        let health_thresholds = HealthThresholds::from_lookup(&lookup, &mut errors);
+        let prices = Prices::from_lookup(&lookup, &mut errors);
+        let monthly_budgets = cost::monthly_budgets(&lookup, &mut errors);
+        let ddb_location =
+            ResourceLocation::from_lookup(&lookup, ENV_LOG_DYNAMODB_REGION, ENV_LOG_DYNAMODB_ROLE_ARN, &mut errors);
+        let s3_location = ResourceLocation::from_lookup(&lookup, ENV_LOG_S3_REGION, ENV_LOG_S3_ROLE_ARN, &mut errors);
+        let sqs_location = ResourceLocation::from_lookup(&lookup, ENV_SQS_REGION, ENV_SQS_ROLE_ARN, &mut errors);
+        let wa_local_sites = wa_local::enabled_sites(&lookup, &mut errors);
+        let attachment_ocr = extract::ocr_enabled(&lookup, &mut errors);
+        let search = SearchSettings::from_lookup(&lookup, &mut errors);
+
+        // The interval is read again by the process-wide watcher; it is checked here so a bad value fails startup.
+        config::reload_interval(&lookup, &mut errors);
+
+        if !errors.problems.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(Self {
+            s3_bucket,
+            s3_prefix: lookup(ENV_LOG_S3_PREFIX).unwrap_or_default(),
+            s3_key_template,
+            s3_compression,
+            s3_encryption_keys,
+            sqs_queue_url,
+            ssm_prefix: lookup(ENV_SSM_PREFIX).unwrap_or_else(|| DEFAULT_SSM_PREFIX.to_string()),
+            journal_table: lookup(ENV_JOURNAL_DYNAMODB_TABLE).unwrap_or_else(|| ddb_table.clone()),
+            checkpoint_table: lookup(ENV_CHECKPOINT_DYNAMODB_TABLE).unwrap_or_else(|| ddb_table.clone()),
+            crawl_table: lookup(ENV_CRAWL_DYNAMODB_TABLE).unwrap_or_else(|| ddb_table.clone()),
+            session_table: lookup(ENV_SESSION_DYNAMODB_TABLE).unwrap_or_else(|| ddb_table.clone()),
+            attachment_table: lookup(ENV_ATTACHMENT_DYNAMODB_TABLE).unwrap_or_else(|| ddb_table.clone()),
+            opportunity_table: lookup(ENV_OPPORTUNITY_DYNAMODB_TABLE).unwrap_or_else(|| ddb_table.clone()),
+            failure_table: lookup(ENV_FAILURE_DYNAMODB_TABLE).unwrap_or_else(|| ddb_table.clone()),
+            schedule_table: lookup(ENV_SCHEDULE_DYNAMODB_TABLE).unwrap_or_else(|| ddb_table.clone()),
+            link_table: lookup(ENV_LINK_DYNAMODB_TABLE).unwrap_or_else(|| ddb_table.clone()),
+            frontier_table: lookup(ENV_FRONTIER_DYNAMODB_TABLE).unwrap_or_else(|| ddb_table.clone()),
+            vendor_table: lookup(ENV_VENDOR_DYNAMODB_TABLE).unwrap_or_else(|| ddb_table.clone()),
+            ddb_table,
+            ddb_write_shards,
+            ddb_max_attempts,
+            body_lookup_batch_threshold,
+            login_min_interval_secs,
+            reconcile_unseen_days,
+            retry_max_attempts,
+            alert_topic_arn: lookup(ENV_ALERT_SNS_TOPIC_ARN),
+            audit,
+            transport,
+            health_thresholds,
+            prices,
+            monthly_budgets,
+            ddb_location,
+            s3_location,
+            sqs_location,
+            wa_local_sites,
+            attachment_ocr,
+            search,
+        })
+    }
 }
 
 impl LogConfig {
     /// Create a new LogConfig from environment variables.
-    pub async fn new() -> Self {
+    ///
+    /// The settings are validated before any clients are created; every missing or invalid setting is reported in the
+    /// returned [`ConfigError`].
+    pub async fn new() -> Result<Self, ConfigError> {
+        let settings = Settings::from_lookup(|var| env::var(var).ok())?;
+
         let aws_config = aws_config::load_from_env().await;
-        let ddb_client = DynamoDbClient::new(&aws_config);
-        let s3_client = S3Client::new(&aws_config);
-        let sqs_client = SqsClient::new(&aws_config);
+        let ddb_config = resource_config(&aws_config, &settings.ddb_location).await;
+        let ddb_client = DynamoDbClient::from_conf(
+            DynamoDbConfigBuilder::from(&ddb_config)
+                .retry_config(RetryConfig::adaptive().with_max_attempts(settings.ddb_max_attempts))
+                .build(),
+        );
+        let s3_config = resource_config(&aws_config, &settings.s3_location).await;
+        let s3_client = S3Client::new(&s3_config);
+        let kms_client = KmsClient::new(&s3_config);
+        let textract_client = TextractClient::new(&s3_config);
+        let sqs_client = SqsClient::new(&resource_config(&aws_config, &settings.sqs_location).await);
         let ssm_client = SsmClient::new(&aws_config);
+        let sns_client = SnsClient::new(&aws_config);
 
-        let s3_bucket = env::var(ENV_LOG_S3_BUCKET).expect("LOG_S3_BUCKET must be set");
-        let s3_prefix = env::var(ENV_LOG_S3_PREFIX).unwrap_or_else(|_| "".to_string());
-        let sqs_queue_url = env::var(ENV_SQS_QUEUE_URL).expect("SQS_QUEUE_URL must be set");
-        let ssm_prefix = env::var(ENV_SSM_PREFIX).unwrap_or_else(|_| DEFAULT_SSM_PREFIX.to_string());
-        let ddb_table = env::var(ENV_LOG_DYNAMODB_TABLE)
-            .unwrap_or_else(|_| env::var(ENV_LOG_DDB_TABLE).expect("LOG_DYNAMODB_TABLE or LOG_DDB_TABLE must be set"));
+        let ddb_writer = DdbBatchWriter::new(ddb_client.clone(), settings.ddb_table.clone())
+            .with_spill(WriteSpill::new(s3_client.clone(), settings.s3_bucket.clone(), settings.s3_prefix.clone()));
+        let body_index = BodyIndex::new(ddb_client.clone(), settings.ddb_table.clone())
+            .with_batch_threshold(settings.body_lookup_batch_threshold);
+        let journal = Journal::new(ddb_client.clone(), settings.journal_table);
+        let checkpoints = CheckpointStore::new(ddb_client.clone(), settings.checkpoint_table);
+        let crawls = CrawlRegistry::new(ddb_client.clone(), settings.crawl_table);
+        let sessions = SessionStore::new(ddb_client.clone(), settings.session_table);
+        let attachments = AttachmentStore::new(ddb_client.clone(), settings.attachment_table);
+        let opportunities = OpportunityStore::new(ddb_client.clone(), settings.opportunity_table);
+        let failures = FailureStore::new(ddb_client.clone(), settings.failure_table);
+        let schedules = ScheduleStore::new(ddb_client.clone(), settings.schedule_table);
+        let links = LinkStore::new(ddb_client.clone(), settings.link_table);
+        let frontier = FrontierStore::new(ddb_client.clone(), settings.frontier_table);
+        let vendors = VendorStore::new(ddb_client.clone(), settings.vendor_table);
+        let health_thresholds = settings.health_thresholds;
+        let transport = settings.transport;
+        let dns_resolver = transport.dns_cache_size.map(|size| Arc::new(CachingResolver::new(size)));
+        let body_memory = BodyMemory::new(transport.body_memory_limit);
+        let search = match settings.search.as_ref().map(|search| SearchSink::connect(search, &aws_config)) {
+            Some(Ok(sink)) => Some(sink),
+            Some(Err(e)) => {
+                let mut errors = ConfigError::default();
+                errors.push(ENV_OPENSEARCH_ENDPOINT, format!("cannot connect: {e}"));
+                return Err(errors);
+            }
+            None => None,
+        };
 
-        Self {
+        Ok(Self {
             ddb_client,
             s3_client,
             sqs_client,
             ssm_client,
-            s3_bucket,
-            s3_prefix,
-            sqs_queue_url,
-            ssm_prefix,
-            ddb_table,
+            sns_client,
+            s3_bucket: settings.s3_bucket,
+            s3_prefix: settings.s3_prefix,
+            s3_key_template: settings.s3_key_template,
+            s3_compression: settings.s3_compression,
+            s3_encryption_keys: settings.s3_encryption_keys,
+            kms_client,
+            textract_client,
+            attachment_ocr: settings.attachment_ocr,
+            search,
+            sqs_queue_url: settings.sqs_queue_url,
+            ssm_prefix: settings.ssm_prefix,
+            ddb_table: settings.ddb_table,
+            ddb_writer,
+            body_index,
+            ddb_write_shards: settings.ddb_write_shards,
+            journal,
+            checkpoints,
+            crawls,
+            sessions,
+            attachments,
+            opportunities,
+            failures,
+            schedules,
+            links,
+            frontier,
+            vendors,
+            login_min_interval: Duration::seconds(settings.login_min_interval_secs.try_into().unwrap_or(i64::MAX)),
+            reconcile_unseen_after: Duration::days(settings.reconcile_unseen_days),
+            retry_max_attempts: settings.retry_max_attempts,
+            alert_topic_arn: settings.alert_topic_arn,
+            health_thresholds,
+            transport,
+            dns_resolver,
+            audit: RequestAudit::new(settings.audit),
+            body_memory,
+            last_stored: Arc::new(Mutex::new(None)),
+            usage: UsageMeter::default(),
+            prices: settings.prices,
+            monthly_budgets: settings.monthly_budgets,
+            flags: FeatureFlags::default(),
+            wa_local_sites: settings.wa_local_sites,
+        })
+    }
+
+    /// Return a copy of this configuration for running an operation, sharing everything but the record of the last
+    /// response stored and the tally of resources used.
+    pub fn for_operation(&self) -> Self {
+        Self {
+            last_stored: Arc::new(Mutex::new(None)),
+            usage: UsageMeter::default(),
+            ..self.clone()
         }
     }
 
@@ -95,3 +675,122 @@ impl LogConfig {
         Ok(value)
     }
 }
+
+/// Return the SDK configuration for a resource that may live in a different region or account than the Lambda.
+///
+/// If the location names a region, clients are pointed at that region. If it names a role, the role is assumed using
+/// the Lambda's own credentials. Otherwise, the Lambda's configuration is used unchanged.
+async fn resource_config(base: &SdkConfig, location: &ResourceLocation) -> SdkConfig {
+    if location.region.is_none() && location.role_arn.is_none() {
+        return base.clone();
+    }
+
+    let mut builder = base.to_builder();
+
+    if let Some(region) = location.region.as_ref() {
+        debug!("Using region {region}");
+        builder = builder.region(Region::new(region.clone()));
+    }
+
+    if let Some(role_arn) = location.role_arn.as_ref() {
+        debug!("Assuming role {role_arn}");
+        let provider = AssumeRoleProvider::builder(role_arn.clone())
+            .session_name(ASSUME_ROLE_SESSION_NAME)
+            .configure(base)
+            .build()
+            .await;
+        builder = builder.credentials_provider(SharedCredentialsProvider::new(provider));
+    }
+
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::Settings, crate::httpext::BodyCompression, std::collections::HashMap};
+
+    #[test]
+    fn settings_validation() {
+        let vars = HashMap::from([
+            ("LOG_S3_BUCKET", "bucket"),
+            ("SQS_QUEUE_URL", "https://sqs.example.com/queue"),
+            ("LOG_DDB_TABLE", "log"),
+            ("CRAWL_DYNAMODB_TABLE", "crawls"),
+            ("LOG_S3_ENCRYPTION_KEYS", "PublicPurchase=alias/pp, BidNet = arn:aws:kms:us-west-2:1:key/2,"),
+        ]);
+        let settings = Settings::from_lookup(|var| vars.get(var).map(|v| v.to_string())).unwrap();
+        assert_eq!(settings.ddb_table, "log");
+        assert_eq!(settings.journal_table, "log");
+        assert_eq!(settings.crawl_table, "crawls");
+        assert_eq!(settings.attachment_table, "log");
+        assert_eq!(settings.opportunity_table, "log");
+        assert_eq!(settings.failure_table, "log");
+        assert_eq!(settings.schedule_table, "log");
+        assert_eq!(settings.link_table, "log");
+        assert_eq!(settings.frontier_table, "log");
+        assert_eq!(settings.vendor_table, "log");
+        assert_eq!(settings.s3_compression, BodyCompression::None);
+        assert_eq!(settings.s3_encryption_keys.len(), 2);
+        assert_eq!(settings.s3_encryption_keys["PublicPurchase"], "alias/pp");
+        assert_eq!(settings.s3_encryption_keys["BidNet"], "arn:aws:kms:us-west-2:1:key/2");
+        assert_eq!(settings.ddb_write_shards, 1);
+        assert_eq!(settings.ddb_max_attempts, 10);
+        assert_eq!(settings.body_lookup_batch_threshold, 4);
+        assert_eq!(settings.login_min_interval_secs, 900);
+        assert_eq!(settings.reconcile_unseen_days, 14);
+        assert_eq!(settings.retry_max_attempts, 24);
+        assert!(!settings.audit);
+        assert!(!settings.attachment_ocr);
+        assert_eq!(settings.search, None);
+
+        // Every problem is reported at once.
+        let vars = HashMap::from([
+            ("SQS_QUEUE_URL", ""),
+            ("LOG_DYNAMODB_WRITE_SHARDS", "0"),
+            ("LOG_DYNAMODB_MAX_ATTEMPTS", "many"),
+            ("LOG_S3_COMPRESSION", "lz4"),
+            ("LOG_S3_ENCRYPTION_KEYS", "alias/pp"),
+            ("CRAWL_AUDIT", "sometimes"),
+            ("RECONCILE_UNSEEN_DAYS", "-3"),
+            ("RETRY_MAX_ATTEMPTS", "forever"),
+            ("HTTP_HTTP1_ONLY", "yes"),
+            ("CONFIG_RELOAD_SECS", "1m"),
+            ("HEALTH_MAX_ERROR_RATE_PERCENT", "ten"),
+            ("COST_S3_PUT", "-1"),
+            ("CRAWL_MONTHLY_BUDGETS", "Webs=25$"),
+            ("LOG_S3_REGION", "US West 2"),
+            ("SQS_ROLE_ARN", "crawler"),
+            ("WA_LOCAL_SITES", "Seattle,Spokane"),
+            ("ATTACHMENT_OCR", "scanned"),
+            ("OPENSEARCH_ENDPOINT", "search.example.com"),
+        ]);
+        let error = Settings::from_lookup(|var| vars.get(var).map(|v| v.to_string())).unwrap_err();
+        let vars: Vec<&str> = error.problems.iter().map(|p| p.var.as_str()).collect();
+        assert_eq!(
+            vars,
+            vec![
+                "LOG_S3_BUCKET",
+                "SQS_QUEUE_URL",
+                "LOG_DYNAMODB_TABLE",
+                "LOG_S3_COMPRESSION",
+                "LOG_S3_ENCRYPTION_KEYS",
+                "LOG_DYNAMODB_WRITE_SHARDS",
+                "LOG_DYNAMODB_MAX_ATTEMPTS",
+                "RECONCILE_UNSEEN_DAYS",
+                "RETRY_MAX_ATTEMPTS",
+                "CRAWL_AUDIT",
+                "HTTP_HTTP1_ONLY",
+                "HEALTH_MAX_ERROR_RATE_PERCENT",
+                "COST_S3_PUT",
+                "CRAWL_MONTHLY_BUDGETS",
+                "LOG_S3_REGION",
+                "SQS_ROLE_ARN",
+                "WA_LOCAL_SITES",
+                "ATTACHMENT_OCR",
+                "OPENSEARCH_ENDPOINT",
+                "CONFIG_RELOAD_SECS"
+            ]
+        );
+        assert!(error.to_string().starts_with("Invalid configuration (20 problem(s)); LOG_S3_BUCKET: must be set;"));
+    }
+}
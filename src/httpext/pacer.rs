@@ -0,0 +1,77 @@
+use {
+    std::time::Duration,
+    tokio::{
+        sync::Mutex,
+        time::{sleep_until, Instant},
+    },
+};
+
+/// Spaces out the requests made by a client (and all of its clones) by a minimum interval.
+#[derive(Debug, Default)]
+pub struct RequestPacer {
+    /// The minimum time between the starts of consecutive requests.
+    interval: Duration,
+
+    /// The earliest time the next request may start.
+    next_allowed: Mutex<Option<Instant>>,
+}
+
+impl RequestPacer {
+    /// Create a pacer that allows one request per `interval`. A zero interval disables pacing.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next_allowed: Mutex::new(None),
+        }
+    }
+
+    /// Return the minimum time between requests.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Wait until the next request is allowed to start, then reserve the following slot.
+    pub async fn wait(&self) {
+        if self.interval.is_zero() {
+            return;
+        }
+
+        let mut next_allowed = self.next_allowed.lock().await;
+        let now = Instant::now();
+        let start = match *next_allowed {
+            Some(next) if next > now => {
+                sleep_until(next).await;
+                next
+            }
+            _ => now,
+        };
+
+        *next_allowed = Some(start + self.interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::RequestPacer,
+        std::time::Duration,
+        tokio::time::{self, Instant},
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn paces_requests() {
+        let pacer = RequestPacer::new(Duration::from_millis(500));
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            pacer.wait().await;
+        }
+        assert_eq!(Instant::now() - start, Duration::from_millis(1000));
+
+        // Idle time counts towards the interval.
+        time::advance(Duration::from_secs(2)).await;
+        let resumed = Instant::now();
+        pacer.wait().await;
+        assert_eq!(Instant::now(), resumed);
+    }
+}
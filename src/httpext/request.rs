@@ -1,6 +1,8 @@
 use {
     crate::{
-        httpext::{Client, CookieStoreRwLock, LogConfig, Response},
+        httpext::{
+            AddressFamily, Client, CookieStoreRwLock, CrawlStats, HostAllowlist, LogConfig, RequestPacer, Response,
+        },
         BoxError,
     },
     reqwest::{
@@ -28,6 +30,33 @@ pub struct RequestBuilder {
 
     /// The crawl id of the current crawl.
     pub crawl_id: String,
+
+    /// The portal (subsystem) being crawled, if known.
+    pub portal: Option<String>,
+
+    /// Statistics about the requests made.
+    pub stats: Arc<CrawlStats>,
+
+    /// The hosts the client may contact.
+    pub allowed_hosts: Arc<HostAllowlist>,
+
+    /// Paces requests.
+    pub pacer: Arc<RequestPacer>,
+
+    /// The portal account the client is logged in as, if any.
+    pub account: Option<String>,
+
+    /// The team the crawl is run for, if any.
+    pub tenant: Option<String>,
+
+    /// The number of times the operation making requests has been re-enqueued.
+    pub attempt: u32,
+
+    /// The address families the client connects with.
+    pub address_family: AddressFamily,
+
+    /// How long connecting may take, if limited.
+    pub connect_timeout: Option<Duration>,
 }
 
 impl RequestBuilder {
@@ -260,6 +289,15 @@ impl RequestBuilder {
             cookie_store: self.cookie_store,
             log_config: self.log_config,
             crawl_id: self.crawl_id.clone(),
+            portal: self.portal,
+            stats: self.stats,
+            allowed_hosts: self.allowed_hosts,
+            pacer: self.pacer,
+            account: self.account,
+            tenant: self.tenant,
+            attempt: self.attempt,
+            address_family: self.address_family,
+            connect_timeout: self.connect_timeout,
         };
 
         client.execute(request).await
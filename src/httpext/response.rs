@@ -1,13 +1,18 @@
 use {
     crate::{
-        httpext::{log_aws_err, LogConfig},
-        BoxError,
+        classification::{Classification, DDB_KEY_CLASSIFICATION},
+        httpext::{
+            aws_err_str, is_throttle, record_throttle, sharded_partition_key, store_body, BodyHold, BodyInfo, Client,
+            ContentKind, ContentMismatch, FetchErrorKind, LogConfig, StoredBody,
+        },
+        journal::CrawlEvent,
+        metrics::{self, Unit},
+        redact, BoxError, CRATE_VERSION,
     },
     aws_sdk_dynamodb::types::AttributeValue,
-    aws_sdk_s3::{operation::head_object::HeadObjectError, primitives::ByteStream},
-    aws_smithy_runtime_api::client::result::SdkError,
     base64::prelude::*,
     bytes::{BufMut, Bytes, BytesMut},
+    chrono::{DateTime, Utc},
     futures_util::StreamExt,
     http::Extensions,
     log::*,
@@ -17,17 +22,19 @@ use {
     },
     sha2::{Digest, Sha256},
     std::{
+        collections::HashMap,
         error::Error,
         fmt::{Display, Formatter, Result as FmtResult},
         str::Utf8Error,
     },
-    uuid::{NoContext, Timestamp, Uuid},
+    uuid::{Builder as UuidBuilder, NoContext, Timestamp, Uuid},
 };
 
 const HEADER_CONTENT_LANGUAGE: &str = "Content-Language";
 const HEADER_CONTENT_TYPE: &str = "Content-Type";
 
 const DDB_KEY_CRAWL_ID: &str = "CrawlId";
+const DDB_KEY_BASE_CRAWL_ID: &str = "BaseCrawlId";
 const DDB_KEY_REQUEST_ID: &str = "RequestId";
 const DDB_KEY_ORIGINAL_URL: &str = "OriginalUrl";
 const DDB_KEY_FINAL_URL: &str = "FinalUrl";
@@ -38,13 +45,34 @@ const DDB_KEY_CONTENT_TYPE: &str = "ContentType";
 const DDB_KEY_CONTENT_LANGUAGE: &str = "ContentLanguage";
 const DDB_KEY_CONTENT_LENGTH: &str = "ContentLength";
 const DDB_KEY_ETAG: &str = "Etag";
-const DDB_KEY_MD5: &str = "Md5";
 const DDB_KEY_S3_BUCKET: &str = "S3Bucket";
 const DDB_KEY_S3_KEY: &str = "S3Key";
+const DDB_KEY_COMPRESSION: &str = "Compression";
+const DDB_KEY_KMS_KEY_ID: &str = "KmsKeyId";
 const DDB_KEY_SHA256: &str = "Sha256";
+const DDB_KEY_STARTED_AT: &str = "StartedAt";
+const DDB_KEY_COMPLETED_AT: &str = "CompletedAt";
+const DDB_KEY_ELAPSED_MS: &str = "ElapsedMs";
+const DDB_KEY_ATTEMPTS: &str = "Attempts";
+const DDB_KEY_LAST_ATTEMPT_AT: &str = "LastAttemptAt";
+const DDB_KEY_ACCOUNT: &str = "Account";
+const DDB_KEY_PORTAL: &str = "Portal";
+const DDB_KEY_TENANT: &str = "Tenant";
+const DDB_KEY_CRATE_VERSION: &str = "CrateVersion";
+const DDB_KEY_ERROR_KIND: &str = "ErrorKind";
+const DDB_KEY_ERROR: &str = "Error";
+const DDB_KEY_OPERATION_ATTEMPT: &str = "OperationAttempt";
 
 const INITIAL_BODY_CAPACITY: usize = 65536;
 
+const METRIC_HTTP_REQUESTS: &str = "HttpRequests";
+const METRIC_RESPONSE_SIZE: &str = "ResponseSize";
+const METRIC_RESPONSE_TIME: &str = "ResponseTime";
+const METRIC_HTTP_FAILURES: &str = "HttpFailures";
+const DIMENSION_PORTAL: &str = "Portal";
+const DIMENSION_ERROR_KIND: &str = "ErrorKind";
+const UNKNOWN_PORTAL: &str = "unknown";
+
 /// A Response to a submitted `Request`.
 ///
 /// This logs the response to an S3 bucket upon creation.
@@ -70,6 +98,21 @@ pub struct Response {
 
     /// The size of the body.
     content_length: usize,
+
+    /// Hex-encoded SHA-256 digest of the body.
+    sha256: String,
+
+    /// Where the body was stored, if the client logs responses.
+    stored: Option<StoredBody>,
+
+    /// When the request was sent.
+    started_at: DateTime<Utc>,
+
+    /// When the last byte of the body was received.
+    completed_at: DateTime<Utc>,
+
+    /// The body's bytes held against the client's body memory budget until the response is dropped.
+    _memory: BodyHold,
 }
 
 /// Error returned when an HTTP status code is not in the 200-399 range.
@@ -99,143 +142,236 @@ where
     fn error_for_status(self) -> Result<Response, BoxError>;
 }
 
+/// A response body as read, with its SHA-256 digest and its hold on the client's body memory budget.
+struct ReceivedBody {
+    bytes: Bytes,
+    sha256: [u8; 32],
+    memory: BodyHold,
+}
+
+/// The parts of a response other than its body.
+struct ResponseHead {
+    status: StatusCode,
+    version: Version,
+    headers: HeaderMap<HeaderValue>,
+    extensions: Extensions,
+    url: Url,
+}
+
 impl Response {
     /// Create a new [`Response`] that wraps a Reqwest [response][reqwest::Response]
-    /// and tracks other metadata about this crawl.
+    /// and tracks other metadata about this crawl using the settings of the client that made the request.
+    ///
+    /// `started_at` is when the request was sent; the response is timed from then until its body has been read.
     pub async fn new(
         resp: reqwest::Response,
-        crawl_id: String,
+        client: &Client,
         method: Method,
         orig_url: Url,
-        log_config: Option<LogConfig>,
+        started_at: DateTime<Utc>,
     ) -> Result<Self, BoxError> {
-        let status = resp.status();
-        let version = resp.version();
-        let headers = resp.headers().clone();
-        let extensions = resp.extensions().clone();
-        let final_url = resp.url().clone();
-        let timestamp = Timestamp::now(NoContext);
-        let (timestamp_secs, timestamp_nanos) = timestamp.to_unix();
-        let request_id = Uuid::new_v7(timestamp);
+        let head = ResponseHead {
+            status: resp.status(),
+            version: resp.version(),
+            headers: resp.headers().clone(),
+            extensions: resp.extensions().clone(),
+            url: resp.url().clone(),
+        };
         let mut body = BytesMut::with_capacity(INITIAL_BODY_CAPACITY);
+        let mut hasher = Sha256::new();
 
-        // let mut body = BytesMut::with_capacity(INITIAL_BODY_CAPACITY);
+        let mut memory = client.log_config.as_ref().map(|c| c.body_memory.hold()).unwrap_or_default();
         let mut stream = resp.bytes_stream();
-        let mut sha256 = Sha256::new();
-        let mut md5 = md5::Context::new();
 
         while let Some(chunk) = stream.next().await {
-            let chunk = chunk?;
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    record_fetch_failure(client, &method, &orig_url, started_at, &e).await;
+                    return Err(e.into());
+                }
+            };
             body.put_slice(&chunk);
-            sha256.update(&chunk);
-            md5.consume(&chunk);
+            hasher.update(&chunk);
+            memory.add(chunk.len());
         }
 
-        let body = body.freeze();
-        let content_length = body.len();
+        let body = ReceivedBody {
+            bytes: body.freeze(),
+            sha256: hasher.finalize().into(),
+            memory,
+        };
+        Self::record(client, method, orig_url, head, body, started_at).await
+    }
 
-        let sha256 = sha256.finalize();
-        let sha256_str = hex::encode(sha256.as_slice());
-        let sha256_b64 = BASE64_STANDARD.encode(sha256.as_slice());
+    /// Create a new [`Response`] for a file retrieved other than over HTTP, such as from an FTP server, logging it like
+    /// any other response. It is recorded with a `200 OK` status, no headers, and `method` naming the retrieval.
+    pub async fn from_transfer(
+        client: &Client,
+        method: Method,
+        url: Url,
+        body: Bytes,
+        started_at: DateTime<Utc>,
+    ) -> Result<Self, BoxError> {
+        let head = ResponseHead {
+            status: StatusCode::OK,
+            version: Version::default(),
+            headers: HeaderMap::new(),
+            extensions: Extensions::new(),
+            url: url.clone(),
+        };
+
+        let mut memory = client.log_config.as_ref().map(|c| c.body_memory.hold()).unwrap_or_default();
+        memory.add(body.len());
+        let body = ReceivedBody {
+            sha256: Sha256::digest(&body).into(),
+            bytes: body,
+            memory,
+        };
+        Self::record(client, method, url, head, body, started_at).await
+    }
 
-        let md5 = *md5.compute();
-        let md5_str = BASE64_STANDARD.encode(md5);
+    /// Log a complete response: time it, tally it, and, if the client logs responses, store its body and write its
+    /// request log item and journal event.
+    async fn record(
+        client: &Client,
+        method: Method,
+        orig_url: Url,
+        head: ResponseHead,
+        body: ReceivedBody,
+        started_at: DateTime<Utc>,
+    ) -> Result<Self, BoxError> {
+        let crawl_id = &client.crawl_id;
+        let portal = &client.portal;
+        let ResponseHead {
+            status,
+            version,
+            headers,
+            extensions,
+            url: final_url,
+        } = head;
+        let ReceivedBody {
+            bytes: body,
+            sha256,
+            memory,
+        } = body;
+        let timestamp = Timestamp::now(NoContext);
+        let (timestamp_secs, timestamp_nanos) = timestamp.to_unix();
 
-        debug!("HTTP: {orig_url} status {status}, content-length {content_length}, sha256 {sha256_str}");
+        let completed_at = Utc::now();
+        let elapsed = (completed_at - started_at).to_std().unwrap_or_default();
+        client.stats.record_response_time(orig_url.host_str().unwrap_or_default(), elapsed);
 
-        if let Some(log_config) = log_config {
-            let bucket = log_config.s3_bucket.clone();
-            let key = format!("{}{}", log_config.s3_prefix, sha256_str);
+        let content_length = body.len();
+        if let Some(log_config) = client.log_config.as_ref() {
+            log_config.audit.record_response(crawl_id, &orig_url, content_length as u64);
+        }
 
-            // Does a body with this SHA256 checksum already exist?
-            let etag = match log_aws_err(
-                log_config.s3_client.head_object().bucket(bucket.clone()).key(key.clone()).send().await,
-                &format!("HeadObject on s3://{bucket}/{key}"),
-            ) {
-                Ok(head_object) => head_object.e_tag.unwrap(),
-                Err(e) => {
-                    let SdkError::ServiceError(ref service_error) = e else {
-                        return Err(Box::new(e));
-                    };
-
-                    let HeadObjectError::NotFound(_) = service_error.err() else {
-                        return Err(Box::new(e));
-                    };
-
-                    // No; write it out.
-                    let bytestream = ByteStream::from(body.clone());
-
-                    debug!("Logging to S3: s3://{bucket}/{key}");
-                    debug!("MD5: {md5_str}");
-                    debug!("SHA256: {sha256_str} {sha256_b64}");
-
-                    let put_object = match log_aws_err(
-                        log_config
-                            .s3_client
-                            .put_object()
-                            .bucket(bucket.clone())
-                            .key(key.clone())
-                            .content_md5(md5_str.clone())
-                            .checksum_sha256(sha256_b64.clone())
-                            .body(bytestream)
-                            .send()
-                            .await,
-                        &format!("PutObject s3://{bucket}/{key}"),
-                    ) {
-                        Ok(put_object) => put_object,
-                        Err(e) => {
-                            if let aws_smithy_runtime_api::client::result::SdkError::ServiceError(e2) = &e {
-                                let metadata = e2.err().meta();
-                                error!(
-                                    "Error info: code={:?} message={:?} request_id={:?}",
-                                    metadata.code(),
-                                    metadata.message(),
-                                    metadata.extra("request_id")
-                                );
-                            }
-
-                            Err(e)?
-                        }
-                    };
-
-                    put_object.e_tag.unwrap()
-                }
+        let sha256_str = hex::encode(sha256);
+        let sha256_b64 = BASE64_STANDARD.encode(sha256);
+
+        let request_id = request_identity(crawl_id, &method, &orig_url, &sha256_str);
+
+        debug!(
+            "HTTP: {orig_url} status {status}, content-length {content_length}, sha256 {sha256_str}, {} ms",
+            elapsed.as_millis()
+        );
+
+        let portal_dimension = metrics::with_tenant(
+            &[(DIMENSION_PORTAL, portal.as_deref().unwrap_or(UNKNOWN_PORTAL))],
+            client.tenant.as_deref(),
+        );
+        metrics::registry().increment(METRIC_HTTP_REQUESTS, &portal_dimension, 1);
+        metrics::registry().observe(METRIC_RESPONSE_SIZE, &portal_dimension, Unit::Bytes, content_length as f64);
+        metrics::registry().observe(
+            METRIC_RESPONSE_TIME,
+            &portal_dimension,
+            Unit::Milliseconds,
+            elapsed.as_secs_f64() * 1000.0,
+        );
+
+        let mut unchanged = false;
+        let mut stored_body = None;
+
+        if let Some(log_config) = &client.log_config {
+            let info = BodyInfo {
+                portal: portal.as_deref(),
+                crawl_id,
+                tenant: client.tenant.as_deref(),
+                timestamp: DateTime::from_timestamp(timestamp_secs as i64, timestamp_nanos).unwrap_or_default(),
+                sha256_hex: &sha256_str,
+                sha256_b64: &sha256_b64,
             };
-
-            // Write this to DynamoDB.
-            let mut put_item = log_config
-                .ddb_client
-                .put_item()
-                .table_name(log_config.ddb_table)
-                .item(DDB_KEY_CRAWL_ID, AttributeValue::S(crawl_id.clone()))
-                .item(DDB_KEY_REQUEST_ID, AttributeValue::S(request_id.to_string()))
-                .item(DDB_KEY_FINAL_URL, AttributeValue::S(final_url.to_string()))
-                .item(DDB_KEY_ORIGINAL_URL, AttributeValue::S(orig_url.to_string()))
-                .item(DDB_KEY_METHOD, AttributeValue::S(method.to_string()))
-                .item(DDB_KEY_SHA256, AttributeValue::S(sha256_str.clone()))
-                .item(DDB_KEY_MD5, AttributeValue::S(md5_str.clone()))
-                .item(DDB_KEY_ETAG, AttributeValue::S(etag.clone()))
-                .item(DDB_KEY_CONTENT_LENGTH, AttributeValue::N(content_length.to_string()))
-                .item(DDB_KEY_S3_BUCKET, AttributeValue::S(log_config.s3_bucket.clone()))
-                .item(DDB_KEY_S3_KEY, AttributeValue::S(key))
-                .item(DDB_KEY_STATUS_CODE, AttributeValue::N(status.as_u16().to_string()))
-                .item(DDB_KEY_TIMESTAMP, AttributeValue::N(format!("{timestamp_secs}.{timestamp_nanos:09}")));
+            let stored = store_body(log_config, &body, &info).await?;
+            unchanged = stored.existing;
+            stored_body = Some(stored.clone());
+            *log_config.last_stored.lock() = Some(stored.clone());
+
+            // Write this to DynamoDB. The partition key may be sharded to avoid a hot partition on large crawls; the
+            // unsharded crawl id is kept in a separate attribute for querying via an index.
+            let partition_key = sharded_partition_key(crawl_id, request_id.as_u128(), log_config.ddb_write_shards);
+            let mut item = HashMap::from([
+                (DDB_KEY_CRAWL_ID.to_string(), AttributeValue::S(partition_key)),
+                (DDB_KEY_BASE_CRAWL_ID.to_string(), AttributeValue::S(crawl_id.clone())),
+                (DDB_KEY_REQUEST_ID.to_string(), AttributeValue::S(request_id.to_string())),
+                (DDB_KEY_FINAL_URL.to_string(), AttributeValue::S(final_url.to_string())),
+                (DDB_KEY_ORIGINAL_URL.to_string(), AttributeValue::S(orig_url.to_string())),
+                (DDB_KEY_METHOD.to_string(), AttributeValue::S(method.to_string())),
+                (DDB_KEY_SHA256.to_string(), AttributeValue::S(sha256_str.clone())),
+                (DDB_KEY_ETAG.to_string(), AttributeValue::S(stored.etag)),
+                (DDB_KEY_CONTENT_LENGTH.to_string(), AttributeValue::N(content_length.to_string())),
+                (DDB_KEY_S3_BUCKET.to_string(), AttributeValue::S(stored.bucket.clone())),
+                (DDB_KEY_S3_KEY.to_string(), AttributeValue::S(stored.key.clone())),
+                (DDB_KEY_COMPRESSION.to_string(), AttributeValue::S(stored.compression.as_str().to_string())),
+                (DDB_KEY_STATUS_CODE.to_string(), AttributeValue::N(status.as_u16().to_string())),
+                (DDB_KEY_TIMESTAMP.to_string(), AttributeValue::N(format!("{timestamp_secs}.{timestamp_nanos:09}"))),
+                (DDB_KEY_STARTED_AT.to_string(), AttributeValue::N(epoch_str(&started_at))),
+                (DDB_KEY_COMPLETED_AT.to_string(), AttributeValue::N(epoch_str(&completed_at))),
+                (DDB_KEY_ELAPSED_MS.to_string(), AttributeValue::N(elapsed.as_millis().to_string())),
+                (DDB_KEY_ATTEMPTS.to_string(), AttributeValue::N("1".to_string())),
+                (DDB_KEY_CRATE_VERSION.to_string(), AttributeValue::S(CRATE_VERSION.to_string())),
+            ]);
+
+            if let Some(kms_key_id) = stored.kms_key_id.as_ref() {
+                item.insert(DDB_KEY_KMS_KEY_ID.to_string(), AttributeValue::S(kms_key_id.clone()));
+            }
 
             if let Some(content_type) = headers.get(HEADER_CONTENT_TYPE) {
-                put_item =
-                    put_item.item(DDB_KEY_CONTENT_TYPE, AttributeValue::S(content_type.to_str().unwrap().to_string()));
+                item.insert(
+                    DDB_KEY_CONTENT_TYPE.to_string(),
+                    AttributeValue::S(String::from_utf8_lossy(content_type.as_bytes()).into_owned()),
+                );
             }
 
             if let Some(content_language) = headers.get(HEADER_CONTENT_LANGUAGE) {
-                put_item = put_item
-                    .item(DDB_KEY_CONTENT_LANGUAGE, AttributeValue::S(content_language.to_str().unwrap().to_string()));
+                item.insert(
+                    DDB_KEY_CONTENT_LANGUAGE.to_string(),
+                    AttributeValue::S(String::from_utf8_lossy(content_language.as_bytes()).into_owned()),
+                );
             }
 
-            log_aws_err(put_item.send().await, "PutItem")?;
+            insert_attribution(&mut item, client);
+
+            put_log_item(log_config, item, &completed_at).await?;
+            redact::index_body_urls(log_config, &[&orig_url, &final_url], &stored.bucket, &stored.key).await?;
+            log_config.usage.record_response(content_length as u64, !unchanged);
+            log_config
+                .journal
+                .record(
+                    crawl_id,
+                    CrawlEvent::PageFetched {
+                        url: final_url.to_string(),
+                        status: status.as_u16(),
+                        request_id: request_id.to_string(),
+                    },
+                )
+                .await;
 
             info!("Logged response to S3 and DynamoDB: crawl_id={crawl_id}, request_id={request_id}");
         }
 
+        client.stats.record_response(status.is_client_error() || status.is_server_error(), unchanged);
+
         Ok(Response {
             status,
             version,
@@ -244,9 +380,26 @@ impl Response {
             url: final_url,
             body,
             content_length,
+            sha256: sha256_str,
+            stored: stored_body,
+            started_at,
+            completed_at,
+            _memory: memory,
         })
     }
 
+    /// Get the time the request was sent.
+    #[inline(always)]
+    pub fn started_at(&self) -> DateTime<Utc> {
+        self.started_at
+    }
+
+    /// Get the time the last byte of the response body was received.
+    #[inline(always)]
+    pub fn completed_at(&self) -> DateTime<Utc> {
+        self.completed_at
+    }
+
     /// Get the `StatusCode` of this `Response`.
     #[inline(always)]
     pub fn status(&self) -> StatusCode {
@@ -277,6 +430,18 @@ impl Response {
         self.content_length
     }
 
+    /// Get the hex-encoded SHA-256 digest of the body.
+    #[inline(always)]
+    pub fn sha256(&self) -> &str {
+        &self.sha256
+    }
+
+    /// Get where the body was stored, if the client logs responses.
+    #[inline(always)]
+    pub fn stored(&self) -> Option<&StoredBody> {
+        self.stored.as_ref()
+    }
+
     /// Get the final `Url` of this `Response`.
     #[inline(always)]
     pub fn url(&self) -> &Url {
@@ -295,6 +460,17 @@ impl Response {
         &mut self.extensions
     }
 
+    /// Get the kind of content sniffed from the body, regardless of the declared `Content-Type`.
+    pub fn content_kind(&self) -> ContentKind {
+        ContentKind::sniff(&self.body)
+    }
+
+    /// Compare the declared `Content-Type` against the body, returning the mismatch if they disagree.
+    pub fn content_mismatch(&self) -> Option<ContentMismatch> {
+        let content_type = self.headers.get(HEADER_CONTENT_TYPE).and_then(|value| value.to_str().ok());
+        ContentMismatch::check(content_type, &self.body)
+    }
+
     /// Get the full response text.
     ///
     /// This method decodes the response body with BOM sniffing
@@ -344,3 +520,217 @@ impl ResponseExt for Result<Response, BoxError> {
         }
     }
 }
+
+/// Return the deterministic request id for a response.
+///
+/// The id is derived from the crawl, method, URL, and body hash, so a Lambda retry that fetches the same page and gets
+/// the same body produces the same id and is recorded as another attempt rather than a duplicate item.
+fn request_identity(crawl_id: &str, method: &Method, orig_url: &Url, sha256_hex: &str) -> Uuid {
+    let mut hasher = Sha256::new();
+    for part in [crawl_id, method.as_str(), orig_url.as_str(), sha256_hex] {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+
+    let digest = hasher.finalize();
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    UuidBuilder::from_custom_bytes(bytes).into_uuid()
+}
+
+/// Record a request that failed before a complete response was received.
+///
+/// The failure is classified by [FetchErrorKind] and, if the client logs responses, written to the request log table
+/// and the crawl journal. Logging is best-effort: errors writing the log item are logged and otherwise ignored, so the
+/// caller still sees the original error.
+pub async fn record_fetch_failure(
+    client: &Client,
+    method: &Method,
+    orig_url: &Url,
+    started_at: DateTime<Utc>,
+    error: &(dyn Error + 'static),
+) -> FetchErrorKind {
+    let crawl_id = &client.crawl_id;
+    let error_kind = FetchErrorKind::classify(error);
+    let completed_at = Utc::now();
+    let elapsed = (completed_at - started_at).to_std().unwrap_or_default();
+    client.stats.record_failure();
+    warn!("HTTP: {orig_url} failed after {} ms ({error_kind}): {error}", elapsed.as_millis());
+
+    let portal_dimension = (DIMENSION_PORTAL, client.portal.as_deref().unwrap_or(UNKNOWN_PORTAL));
+    metrics::registry().increment(
+        METRIC_HTTP_FAILURES,
+        &[portal_dimension, (DIMENSION_ERROR_KIND, error_kind.as_str())],
+        1,
+    );
+
+    let Some(log_config) = client.log_config.as_ref() else {
+        return error_kind;
+    };
+
+    log_config.audit.record_failure(crawl_id, orig_url);
+
+    // Failures have no body, so the kind of failure stands in for its hash: a Lambda retry that fails the same way is
+    // recorded as another attempt.
+    let request_id = request_identity(crawl_id, method, orig_url, error_kind.as_str());
+    let partition_key = sharded_partition_key(crawl_id, request_id.as_u128(), log_config.ddb_write_shards);
+    let mut item = HashMap::from([
+        (DDB_KEY_CRAWL_ID.to_string(), AttributeValue::S(partition_key)),
+        (DDB_KEY_BASE_CRAWL_ID.to_string(), AttributeValue::S(crawl_id.clone())),
+        (DDB_KEY_REQUEST_ID.to_string(), AttributeValue::S(request_id.to_string())),
+        (DDB_KEY_ORIGINAL_URL.to_string(), AttributeValue::S(orig_url.to_string())),
+        (DDB_KEY_METHOD.to_string(), AttributeValue::S(method.to_string())),
+        (DDB_KEY_ERROR_KIND.to_string(), AttributeValue::S(error_kind.as_str().to_string())),
+        (DDB_KEY_ERROR.to_string(), AttributeValue::S(error_chain_str(error))),
+        (DDB_KEY_TIMESTAMP.to_string(), AttributeValue::N(epoch_str(&completed_at))),
+        (DDB_KEY_STARTED_AT.to_string(), AttributeValue::N(epoch_str(&started_at))),
+        (DDB_KEY_COMPLETED_AT.to_string(), AttributeValue::N(epoch_str(&completed_at))),
+        (DDB_KEY_ELAPSED_MS.to_string(), AttributeValue::N(elapsed.as_millis().to_string())),
+        (DDB_KEY_ATTEMPTS.to_string(), AttributeValue::N("1".to_string())),
+        (DDB_KEY_OPERATION_ATTEMPT.to_string(), AttributeValue::N(client.attempt.to_string())),
+        (DDB_KEY_CRATE_VERSION.to_string(), AttributeValue::S(CRATE_VERSION.to_string())),
+    ]);
+
+    insert_attribution(&mut item, client);
+
+    if put_log_item(log_config, item, &completed_at).await.is_err() {
+        return error_kind;
+    }
+
+    log_config
+        .journal
+        .record(
+            crawl_id,
+            CrawlEvent::FetchFailed {
+                url: orig_url.to_string(),
+                error_kind,
+                error: error.to_string(),
+                attempt: client.attempt,
+                request_id: request_id.to_string(),
+            },
+        )
+        .await;
+
+    error_kind
+}
+
+/// Format an error and its chain of sources, which for connection failures hold the detail reqwest's message omits.
+fn error_chain_str(error: &(dyn Error + 'static)) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(e) = source {
+        message.push_str(": ");
+        message.push_str(&e.to_string());
+        source = e.source();
+    }
+
+    message
+}
+
+/// Write a request log item unless an item with the same key already exists. If it does, the existing item's attempt
+/// counter is incremented instead, so a Lambda retry producing the same [request identity][request_identity] is
+/// recorded as another attempt rather than overwriting the item.
+///
+/// If the table is still throttling after the client's retries, the item is queued on the
+/// [log writer][LogConfig::ddb_writer] instead, which paces, holds back, or spills it; the check for an existing item
+/// is skipped, so a retried request's item is overwritten rather than counted.
+async fn put_log_item(
+    log_config: &LogConfig,
+    item: HashMap<String, AttributeValue>,
+    completed_at: &DateTime<Utc>,
+) -> Result<(), BoxError> {
+    let key: HashMap<String, AttributeValue> = item
+        .iter()
+        .filter(|(name, _)| name.as_str() == DDB_KEY_CRAWL_ID || name.as_str() == DDB_KEY_REQUEST_ID)
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+
+    let result = log_config
+        .ddb_client
+        .put_item()
+        .table_name(&log_config.ddb_table)
+        .set_item(Some(item.clone()))
+        .condition_expression("attribute_not_exists(#request_id)")
+        .expression_attribute_names("#request_id", DDB_KEY_REQUEST_ID)
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => return Ok(()),
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => (),
+        Err(e) if is_throttle(&e) => {
+            record_throttle(&log_config.ddb_table);
+            warn!("PutItem request log item throttled; queueing it: {}", aws_err_str(&e));
+            return log_config.ddb_writer.put(item).await;
+        }
+        Err(e) => {
+            error!("PutItem request log item: {}", aws_err_str(&e));
+            return Err(e.into());
+        }
+    }
+
+    info!("Request log item {key:?} already exists; recording another attempt");
+    let result = log_config
+        .ddb_client
+        .update_item()
+        .table_name(&log_config.ddb_table)
+        .set_key(Some(key))
+        .update_expression("ADD #attempts :one SET #last_attempt_at = :now")
+        .expression_attribute_names("#attempts", DDB_KEY_ATTEMPTS)
+        .expression_attribute_names("#last_attempt_at", DDB_KEY_LAST_ATTEMPT_AT)
+        .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+        .expression_attribute_values(":now", AttributeValue::N(epoch_str(completed_at)))
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            error!("UpdateItem request log item attempts: {}", aws_err_str(&e));
+            Err(e.into())
+        }
+    }
+}
+
+/// Add the attributes saying who a request log item's request was made for, and what kind of data it holds, to the
+/// item.
+fn insert_attribution(item: &mut HashMap<String, AttributeValue>, client: &Client) {
+    item.insert(DDB_KEY_CLASSIFICATION.to_string(), AttributeValue::S(Classification::RawCrawlData.to_string()));
+
+    if let Some(portal) = client.portal.as_ref() {
+        item.insert(DDB_KEY_PORTAL.to_string(), AttributeValue::S(portal.clone()));
+    }
+
+    if let Some(account) = client.account.as_ref() {
+        item.insert(DDB_KEY_ACCOUNT.to_string(), AttributeValue::S(account.clone()));
+    }
+
+    if let Some(tenant) = client.tenant.as_ref() {
+        item.insert(DDB_KEY_TENANT.to_string(), AttributeValue::S(tenant.clone()));
+    }
+}
+
+/// Format a time as fractional seconds since the Unix epoch, matching the `Timestamp` attribute of log items.
+fn epoch_str(time: &DateTime<Utc>) -> String {
+    format!("{}.{:09}", time.timestamp(), time.timestamp_subsec_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::request_identity,
+        reqwest::{Method, Url},
+    };
+
+    #[test]
+    fn request_identity_is_deterministic() {
+        let url = Url::parse("https://pr-webs-vendor.des.wa.gov/Home.aspx").unwrap();
+        let id = request_identity("crawl", &Method::GET, &url, "abc");
+        assert_eq!(id, request_identity("crawl", &Method::GET, &url, "abc"));
+        assert_eq!(id.get_version_num(), 8);
+
+        assert_ne!(id, request_identity("crawl", &Method::GET, &url, "abd"));
+        assert_ne!(id, request_identity("crawl", &Method::POST, &url, "abc"));
+        assert_ne!(id, request_identity("other", &Method::GET, &url, "abc"));
+    }
+}
@@ -0,0 +1,175 @@
+use {
+    serde::{Deserialize, Serialize},
+    std::fmt::{Display, Formatter, Result as FmtResult},
+};
+
+/// How many leading bytes of a body are examined when sniffing its content.
+const SNIFF_LENGTH: usize = 1024;
+
+/// Leading bytes of a PDF document.
+const PDF_MAGIC: &[u8] = b"%PDF-";
+
+/// Leading bytes of a ZIP archive, including Office Open XML documents.
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// Leading bytes of an OLE compound file, such as a legacy Word or Excel document.
+const OLE_MAGIC: &[u8] = b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1";
+
+/// Markup that begins an HTML document, compared in lowercase.
+const HTML_MARKERS: &[&str] = &["<!doctype html", "<html", "<head", "<body"];
+
+/// UTF-8 byte order mark, skipped before looking for HTML markup.
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// The broad kind of content in a response body.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ContentKind {
+    /// An HTML (or XHTML) page.
+    Html,
+
+    /// A PDF document.
+    Pdf,
+
+    /// A ZIP archive or Office Open XML document.
+    Zip,
+
+    /// A legacy Office document.
+    Ole,
+
+    /// Anything else, or content that couldn't be recognized.
+    Unknown,
+}
+
+impl ContentKind {
+    /// Determine the kind of content from the leading bytes of a body.
+    pub fn sniff(body: &[u8]) -> Self {
+        let head = &body[..body.len().min(SNIFF_LENGTH)];
+        if head.starts_with(PDF_MAGIC) {
+            return Self::Pdf;
+        }
+
+        if head.starts_with(ZIP_MAGIC) {
+            return Self::Zip;
+        }
+
+        if head.starts_with(OLE_MAGIC) {
+            return Self::Ole;
+        }
+
+        let head = head.strip_prefix(UTF8_BOM).unwrap_or(head);
+        let text = String::from_utf8_lossy(head).trim_start().to_lowercase();
+        if HTML_MARKERS.iter().any(|marker| text.starts_with(marker))
+            || (text.starts_with("<!--") && HTML_MARKERS.iter().any(|marker| text.contains(marker)))
+        {
+            return Self::Html;
+        }
+
+        Self::Unknown
+    }
+
+    /// Determine the kind of content a `Content-Type` header value declares.
+    pub fn declared(content_type: &str) -> Self {
+        let media_type = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        match media_type.as_str() {
+            "text/html" | "application/xhtml+xml" => Self::Html,
+            "application/pdf" | "application/x-pdf" => Self::Pdf,
+            "application/zip"
+            | "application/x-zip-compressed"
+            | "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+            | "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+            | "application/vnd.openxmlformats-officedocument.presentationml.presentation" => Self::Zip,
+            "application/msword" | "application/vnd.ms-excel" | "application/vnd.ms-powerpoint" => Self::Ole,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Whether this is a document to be archived as an attachment rather than a page to be parsed.
+    pub fn is_document(&self) -> bool {
+        matches!(self, Self::Pdf | Self::Zip | Self::Ole)
+    }
+}
+
+impl Display for ContentKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Html => f.write_str("HTML"),
+            Self::Pdf => f.write_str("PDF"),
+            Self::Zip => f.write_str("ZIP"),
+            Self::Ole => f.write_str("OLE"),
+            Self::Unknown => f.write_str("unknown"),
+        }
+    }
+}
+
+/// A body whose content doesn't match its declared `Content-Type`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ContentMismatch {
+    /// The kind of content the `Content-Type` header declared.
+    pub declared: ContentKind,
+
+    /// The kind of content sniffed from the body.
+    pub actual: ContentKind,
+}
+
+impl ContentMismatch {
+    /// Compare the declared `Content-Type` (if any) against the body, returning the mismatch if both are recognized and
+    /// they disagree.
+    pub fn check(content_type: Option<&str>, body: &[u8]) -> Option<Self> {
+        let declared = ContentKind::declared(content_type?);
+        let actual = ContentKind::sniff(body);
+        if declared == ContentKind::Unknown || actual == ContentKind::Unknown || declared == actual {
+            None
+        } else {
+            Some(Self {
+                declared,
+                actual,
+            })
+        }
+    }
+}
+
+impl Display for ContentMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "declared {} but contains {}", self.declared, self.actual)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ContentKind, ContentMismatch};
+
+    #[test]
+    fn sniff_and_compare() {
+        assert_eq!(ContentKind::sniff(b"%PDF-1.7\n%\xE2\xE3\xCF\xD3"), ContentKind::Pdf);
+        assert_eq!(ContentKind::sniff(b"PK\x03\x04\x14\x00"), ContentKind::Zip);
+        assert_eq!(ContentKind::sniff(b"\xEF\xBB\xBF\r\n  <!DOCTYPE html><html>"), ContentKind::Html);
+        assert_eq!(ContentKind::sniff(b"<!-- generated --><HTML><BODY>"), ContentKind::Html);
+        assert_eq!(ContentKind::sniff(b"{\"json\": true}"), ContentKind::Unknown);
+
+        assert_eq!(ContentKind::declared("text/html; charset=utf-8"), ContentKind::Html);
+        assert_eq!(ContentKind::declared("Application/PDF"), ContentKind::Pdf);
+        assert_eq!(ContentKind::declared("application/octet-stream"), ContentKind::Unknown);
+
+        // A PDF served as HTML, and an HTML error page served as a PDF.
+        assert_eq!(
+            ContentMismatch::check(Some("text/html"), b"%PDF-1.4"),
+            Some(ContentMismatch {
+                declared: ContentKind::Html,
+                actual: ContentKind::Pdf,
+            })
+        );
+        assert_eq!(
+            ContentMismatch::check(Some("application/pdf"), b"<html><body>Not found</body></html>"),
+            Some(ContentMismatch {
+                declared: ContentKind::Pdf,
+                actual: ContentKind::Html,
+            })
+        );
+
+        // Matching, undeclared, or unrecognized content isn't a mismatch.
+        assert_eq!(ContentMismatch::check(Some("application/pdf"), b"%PDF-1.4"), None);
+        assert_eq!(ContentMismatch::check(None, b"%PDF-1.4"), None);
+        assert_eq!(ContentMismatch::check(Some("application/octet-stream"), b"%PDF-1.4"), None);
+        assert_eq!(ContentMismatch::check(Some("text/html"), b"plain text"), None);
+    }
+}
@@ -0,0 +1,160 @@
+use {
+    parking_lot::Mutex,
+    std::{
+        collections::BTreeMap,
+        sync::atomic::{AtomicU64, Ordering},
+        time::Duration,
+    },
+};
+
+/// Running statistics about the requests made by a client during a crawl operation.
+///
+/// These are shared by all clones of a [`Client`][crate::httpext::Client] and are used to evaluate crawl health when
+/// an operation completes.
+#[derive(Debug, Default)]
+pub struct CrawlStats {
+    /// The number of responses received.
+    requests: AtomicU64,
+
+    /// The number of requests that failed, either at the transport level or with an HTTP error status.
+    errors: AtomicU64,
+
+    /// The number of responses whose body was identical to one already stored.
+    unchanged: AtomicU64,
+
+    /// Response times in milliseconds, by host.
+    response_times: Mutex<BTreeMap<String, Vec<u64>>>,
+}
+
+/// Response time percentiles for a single host.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HostLatency {
+    /// The host the requests were made to.
+    pub host: String,
+
+    /// The number of responses timed.
+    pub requests: usize,
+
+    /// The median response time, in milliseconds.
+    pub p50_ms: u64,
+
+    /// The 95th percentile response time, in milliseconds.
+    pub p95_ms: u64,
+
+    /// The slowest response time, in milliseconds.
+    pub max_ms: u64,
+}
+
+/// A point-in-time copy of [`CrawlStats`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CrawlStatsSnapshot {
+    /// The number of requests made.
+    pub requests: u64,
+
+    /// The number of requests that failed.
+    pub errors: u64,
+
+    /// The number of responses whose body was identical to one already stored.
+    pub unchanged: u64,
+}
+
+impl CrawlStats {
+    /// Record a response.
+    pub fn record_response(&self, is_error: bool, unchanged: bool) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if is_error {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        if unchanged {
+            self.unchanged.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record the wall time taken to fetch a response from `host`, from sending the request to reading the last byte
+    /// of the body.
+    pub fn record_response_time(&self, host: &str, elapsed: Duration) {
+        let millis = elapsed.as_millis().try_into().unwrap_or(u64::MAX);
+        self.response_times.lock().entry(host.to_string()).or_default().push(millis);
+    }
+
+    /// Return response time percentiles for each host, slowest (by 95th percentile) first.
+    pub fn host_latencies(&self) -> Vec<HostLatency> {
+        let response_times = self.response_times.lock();
+        let mut latencies: Vec<HostLatency> = response_times
+            .iter()
+            .filter(|(_, times)| !times.is_empty())
+            .map(|(host, times)| {
+                let mut times = times.clone();
+                times.sort_unstable();
+                HostLatency {
+                    host: host.clone(),
+                    requests: times.len(),
+                    p50_ms: percentile(&times, 50),
+                    p95_ms: percentile(&times, 95),
+                    max_ms: times[times.len() - 1],
+                }
+            })
+            .collect();
+
+        latencies.sort_by(|a, b| b.p95_ms.cmp(&a.p95_ms).then_with(|| a.host.cmp(&b.host)));
+        latencies
+    }
+
+    /// Record a request that failed before a response was received.
+    pub fn record_failure(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Return a snapshot of the current statistics.
+    pub fn snapshot(&self) -> CrawlStatsSnapshot {
+        CrawlStatsSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            unchanged: self.unchanged.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Return the nearest-rank percentile of a sorted, non-empty slice.
+fn percentile(sorted: &[u64], pct: usize) -> u64 {
+    let rank = (pct * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{CrawlStats, HostLatency},
+        std::time::Duration,
+    };
+
+    #[test]
+    fn host_latencies() {
+        let stats = CrawlStats::default();
+        for ms in 1..=100 {
+            stats.record_response_time("fast.example.com", Duration::from_millis(ms));
+        }
+        stats.record_response_time("slow.example.com", Duration::from_secs(5));
+
+        assert_eq!(
+            stats.host_latencies(),
+            vec![
+                HostLatency {
+                    host: "slow.example.com".to_string(),
+                    requests: 1,
+                    p50_ms: 5000,
+                    p95_ms: 5000,
+                    max_ms: 5000,
+                },
+                HostLatency {
+                    host: "fast.example.com".to_string(),
+                    requests: 100,
+                    p50_ms: 50,
+                    p95_ms: 95,
+                    max_ms: 100,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use {
+    crate::httpext::{ConfigError, DnsPins},
+    std::{net::SocketAddr, str::FromStr, time::Duration},
+};
+
+const ENV_HTTP_POOL_MAX_IDLE_PER_HOST: &str = "HTTP_POOL_MAX_IDLE_PER_HOST";
+const ENV_HTTP_POOL_IDLE_TIMEOUT_SECS: &str = "HTTP_POOL_IDLE_TIMEOUT_SECS";
+const ENV_HTTP_CONNECT_TIMEOUT_MS: &str = "HTTP_CONNECT_TIMEOUT_MS";
+const ENV_HTTP_TCP_KEEPALIVE_SECS: &str = "HTTP_TCP_KEEPALIVE_SECS";
+const ENV_HTTP_TCP_NODELAY: &str = "HTTP_TCP_NODELAY";
+const ENV_HTTP_HTTP1_ONLY: &str = "HTTP_HTTP1_ONLY";
+const ENV_HTTP_HTTP2_ADAPTIVE_WINDOW: &str = "HTTP_HTTP2_ADAPTIVE_WINDOW";
+const ENV_HTTP_HTTP2_KEEPALIVE_INTERVAL_SECS: &str = "HTTP_HTTP2_KEEPALIVE_INTERVAL_SECS";
+const ENV_HTTP_DNS_CACHE_SIZE: &str = "HTTP_DNS_CACHE_SIZE";
+const ENV_HTTP_DNS_PINS: &str = "HTTP_DNS_PINS";
+const ENV_HTTP_BODY_MEMORY_LIMIT_MB: &str = "HTTP_BODY_MEMORY_LIMIT_MB";
+
+/// Set by Lambda to the function's memory size in megabytes.
+const ENV_AWS_LAMBDA_FUNCTION_MEMORY_SIZE: &str = "AWS_LAMBDA_FUNCTION_MEMORY_SIZE";
+
+/// Idle connections kept per host. Crawl operations fetch a burst of pages from one portal, then the Lambda is frozen,
+/// so a handful is plenty.
+const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 8;
+
+/// How long idle connections are kept. This is shorter than the keep-alive timeout of the portals' servers, so a
+/// connection left over from before the Lambda was frozen isn't reused after the server has closed it.
+const DEFAULT_POOL_IDLE_TIMEOUT_SECS: u64 = 30;
+
+/// How long to wait for a connection to be established.
+const DEFAULT_CONNECT_TIMEOUT_MS: u64 = 10_000;
+
+/// The interval between TCP keep-alive probes.
+const DEFAULT_TCP_KEEPALIVE_SECS: u64 = 30;
+
+/// The interval between HTTP/2 pings on open connections.
+const DEFAULT_HTTP2_KEEPALIVE_INTERVAL_SECS: u64 = 20;
+
+/// DNS lookups cached per invocation. A crawl touches only a few hosts, so this is generous.
+const DEFAULT_DNS_CACHE_SIZE: usize = 256;
+
+/// The share of the Lambda's memory, in percent, that response bodies may hold before new fetches wait. The rest is
+/// left for parsing, which can take several times a page's size.
+const DEFAULT_BODY_MEMORY_PERCENT: usize = 40;
+
+const BYTES_PER_MB: usize = 1 << 20;
+
+/// Connection and protocol settings for crawl clients.
+///
+/// The defaults are tuned for Lambda: invocations are short but make bursts of requests to the same portal, so
+/// connections are kept for reuse within a burst but not long enough to go stale while the Lambda is frozen. Each
+/// setting can be overridden with an environment variable; setting a duration or the DNS cache size to `off`
+/// disables it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TransportSettings {
+    /// The maximum number of idle connections kept per host.
+    pub pool_max_idle_per_host: usize,
+
+    /// How long idle connections are kept, or `None` to keep them indefinitely.
+    pub pool_idle_timeout: Option<Duration>,
+
+    /// How long to wait for a connection to be established, or `None` to wait indefinitely.
+    pub connect_timeout: Option<Duration>,
+
+    /// The interval between TCP keep-alive probes, or `None` to disable them.
+    pub tcp_keepalive: Option<Duration>,
+
+    /// Whether to disable Nagle's algorithm, so small requests such as form posts are sent immediately.
+    pub tcp_nodelay: bool,
+
+    /// Whether to use only HTTP/1.1, even with servers that offer HTTP/2.
+    pub http1_only: bool,
+
+    /// Whether to size HTTP/2 flow-control windows adaptively, which speeds up large downloads.
+    pub http2_adaptive_window: bool,
+
+    /// The interval between HTTP/2 pings on open connections, or `None` to disable them.
+    pub http2_keep_alive_interval: Option<Duration>,
+
+    /// The number of DNS lookups cached by the shared [CachingResolver][crate::httpext::CachingResolver], or `None`
+    /// to use Reqwest's resolver without a shared cache.
+    pub dns_cache_size: Option<usize>,
+
+    /// Hosts whose addresses are pinned rather than looked up.
+    pub dns_pins: DnsPins,
+
+    /// The response body bytes held in memory across an invocation's operations above which new fetches wait, or
+    /// `None` for no limit. Defaults to a share of the Lambda's memory size; outside Lambda, there is no limit.
+    pub body_memory_limit: Option<usize>,
+}
+
+impl Default for TransportSettings {
+    fn default() -> Self {
+        Self {
+            pool_max_idle_per_host: DEFAULT_POOL_MAX_IDLE_PER_HOST,
+            pool_idle_timeout: Some(Duration::from_secs(DEFAULT_POOL_IDLE_TIMEOUT_SECS)),
+            connect_timeout: Some(Duration::from_millis(DEFAULT_CONNECT_TIMEOUT_MS)),
+            tcp_keepalive: Some(Duration::from_secs(DEFAULT_TCP_KEEPALIVE_SECS)),
+            tcp_nodelay: true,
+            http1_only: false,
+            http2_adaptive_window: true,
+            http2_keep_alive_interval: Some(Duration::from_secs(DEFAULT_HTTP2_KEEPALIVE_INTERVAL_SECS)),
+            dns_cache_size: Some(DEFAULT_DNS_CACHE_SIZE),
+            dns_pins: DnsPins::default(),
+            body_memory_limit: None,
+        }
+    }
+}
+
+impl TransportSettings {
+    /// Read settings, looking variables up with `lookup`, falling back to the defaults. Invalid values are recorded in
+    /// `errors`, so they are reported with the rest of the [`LogConfig`][crate::httpext::LogConfig] settings.
+    pub(crate) fn from_lookup<F: Fn(&str) -> Option<String>>(lookup: &F, errors: &mut ConfigError) -> Self {
+        let defaults = Self::default();
+        let body_memory_limit_mb = lookup(ENV_AWS_LAMBDA_FUNCTION_MEMORY_SIZE)
+            .and_then(|mb| mb.parse::<usize>().ok())
+            .map(|mb| mb * DEFAULT_BODY_MEMORY_PERCENT / 100);
+        let mut secs = |var, default: Option<Duration>| {
+            setting(lookup, var, default.map(|d| d.as_secs()), errors).map(Duration::from_secs)
+        };
+        let pool_idle_timeout = secs(ENV_HTTP_POOL_IDLE_TIMEOUT_SECS, defaults.pool_idle_timeout);
+        let tcp_keepalive = secs(ENV_HTTP_TCP_KEEPALIVE_SECS, defaults.tcp_keepalive);
+        let http2_keep_alive_interval =
+            secs(ENV_HTTP_HTTP2_KEEPALIVE_INTERVAL_SECS, defaults.http2_keep_alive_interval);
+
+        Self {
+            pool_max_idle_per_host: setting(
+                lookup,
+                ENV_HTTP_POOL_MAX_IDLE_PER_HOST,
+                Some(defaults.pool_max_idle_per_host),
+                errors,
+            )
+            .unwrap_or_default(),
+            pool_idle_timeout,
+            connect_timeout: setting(
+                lookup,
+                ENV_HTTP_CONNECT_TIMEOUT_MS,
+                defaults.connect_timeout.map(|d| d.as_millis() as u64),
+                errors,
+            )
+            .map(Duration::from_millis),
+            tcp_keepalive,
+            tcp_nodelay: setting(lookup, ENV_HTTP_TCP_NODELAY, Some(defaults.tcp_nodelay), errors).unwrap_or_default(),
+            http1_only: setting(lookup, ENV_HTTP_HTTP1_ONLY, Some(defaults.http1_only), errors).unwrap_or_default(),
+            http2_adaptive_window: setting(
+                lookup,
+                ENV_HTTP_HTTP2_ADAPTIVE_WINDOW,
+                Some(defaults.http2_adaptive_window),
+                errors,
+            )
+            .unwrap_or_default(),
+            http2_keep_alive_interval,
+            dns_cache_size: setting(lookup, ENV_HTTP_DNS_CACHE_SIZE, defaults.dns_cache_size, errors),
+            dns_pins: setting(lookup, ENV_HTTP_DNS_PINS, Some(defaults.dns_pins), errors).unwrap_or_default(),
+            body_memory_limit: setting(lookup, ENV_HTTP_BODY_MEMORY_LIMIT_MB, body_memory_limit_mb, errors)
+                .map(|mb| mb * BYTES_PER_MB),
+        }
+    }
+
+    /// Apply the settings to a Reqwest client builder.
+    ///
+    /// This applies the DNS pins but not the shared resolver, which is held by the
+    /// [LogConfig][crate::httpext::LogConfig].
+    pub fn apply(&self, builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        let mut builder = builder
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .tcp_keepalive(self.tcp_keepalive)
+            .tcp_nodelay(self.tcp_nodelay);
+
+        for (host, addrs) in self.dns_pins.iter() {
+            // Port 0 means the conventional port for the scheme is used.
+            let addrs = addrs.iter().map(|ip| SocketAddr::new(*ip, 0)).collect::<Vec<_>>();
+            builder = builder.resolve_to_addrs(host, &addrs);
+        }
+
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+
+        if self.http1_only {
+            builder.http1_only()
+        } else {
+            builder
+                .http2_adaptive_window(self.http2_adaptive_window)
+                .http2_keep_alive_interval(self.http2_keep_alive_interval)
+        }
+    }
+}
+
+/// Read a setting, returning `None` if it is `off` and the default if it is unset. An invalid value is recorded in
+/// `errors`, and the default returned.
+fn setting<F: Fn(&str) -> Option<String>, T: FromStr>(
+    lookup: &F,
+    var: &str,
+    default: Option<T>,
+    errors: &mut ConfigError,
+) -> Option<T> {
+    let Some(value) = lookup(var) else {
+        return default;
+    };
+
+    if value.eq_ignore_ascii_case("off") {
+        return None;
+    }
+
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            errors.push(var, format!("{value:?} is not a valid value or off"));
+            default
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::TransportSettings,
+        crate::httpext::ConfigError,
+        httpmock::prelude::*,
+        std::{
+            collections::HashMap,
+            net::IpAddr,
+            time::{Duration, Instant},
+        },
+    };
+
+    #[test]
+    fn settings() {
+        let mut errors = ConfigError::default();
+        assert_eq!(TransportSettings::from_lookup(&|_| None, &mut errors), TransportSettings::default());
+        assert!(errors.problems.is_empty());
+
+        let vars = HashMap::from([
+            ("HTTP_POOL_MAX_IDLE_PER_HOST", "2"),
+            ("HTTP_POOL_IDLE_TIMEOUT_SECS", "off"),
+            ("HTTP_CONNECT_TIMEOUT_MS", "2500"),
+            ("HTTP_TCP_NODELAY", "false"),
+            ("HTTP_DNS_CACHE_SIZE", "off"),
+            ("HTTP_DNS_PINS", "www.example.gov=192.0.2.10"),
+            ("AWS_LAMBDA_FUNCTION_MEMORY_SIZE", "1024"),
+        ]);
+        let settings = TransportSettings::from_lookup(&|var| vars.get(var).map(|v| v.to_string()), &mut errors);
+        assert!(errors.problems.is_empty());
+        assert_eq!(settings.pool_max_idle_per_host, 2);
+        assert_eq!(settings.pool_idle_timeout, None);
+        assert_eq!(settings.connect_timeout, Some(Duration::from_millis(2500)));
+        assert!(!settings.tcp_nodelay);
+        assert_eq!(settings.tcp_keepalive, TransportSettings::default().tcp_keepalive);
+        assert_eq!(settings.dns_cache_size, None);
+        assert_eq!(settings.dns_pins.get("www.example.gov").unwrap(), &["192.0.2.10".parse::<IpAddr>().unwrap()]);
+
+        // Bodies may hold a share of the Lambda's memory unless overridden.
+        assert_eq!(settings.body_memory_limit, Some(409 << 20));
+        let settings = TransportSettings::from_lookup(
+            &|var| match var {
+                "AWS_LAMBDA_FUNCTION_MEMORY_SIZE" => Some("1024".to_string()),
+                "HTTP_BODY_MEMORY_LIMIT_MB" => Some("off".to_string()),
+                _ => None,
+            },
+            &mut errors,
+        );
+        assert_eq!(settings.body_memory_limit, None);
+
+        // Invalid values are reported, every one of them.
+        let vars = HashMap::from([("HTTP_HTTP1_ONLY", "yes"), ("HTTP_TCP_KEEPALIVE_SECS", "-1")]);
+        TransportSettings::from_lookup(&|var| vars.get(var).map(|v| v.to_string()), &mut errors);
+        let vars: Vec<&str> = errors.problems.iter().map(|p| p.var.as_str()).collect();
+        assert_eq!(vars, vec!["HTTP_TCP_KEEPALIVE_SECS", "HTTP_HTTP1_ONLY"]);
+    }
+
+    /// Compare fetching a run of listing pages without connection reuse, as a client that opens a connection per
+    /// request would, against the tuned settings.
+    ///
+    /// Run with `cargo test --release transport_benchmark -- --ignored --nocapture`.
+    #[tokio::test]
+    #[ignore]
+    async fn transport_benchmark() {
+        const PAGES: usize = 200;
+
+        let server = MockServer::start_async().await;
+        let page = include_str!("../webs/webs-search-bids-page1.html");
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/Search_Bid.aspx");
+                then.status(200).header("Content-Type", "text/html").body(page);
+            })
+            .await;
+
+        let untuned = TransportSettings {
+            pool_max_idle_per_host: 0,
+            tcp_nodelay: false,
+            ..TransportSettings::default()
+        };
+
+        for (name, settings) in [("no reuse", untuned), ("tuned", TransportSettings::default())] {
+            let client = settings.apply(reqwest::ClientBuilder::new()).build().unwrap();
+            let started = Instant::now();
+            for _ in 0..PAGES {
+                let response = client.get(server.url("/Search_Bid.aspx")).send().await.unwrap();
+                assert_eq!(response.bytes().await.unwrap().len(), page.len());
+            }
+
+            let elapsed = started.elapsed();
+            println!("{name}: {PAGES} pages in {elapsed:?} ({:?} per page)", elapsed / PAGES as u32);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Request/response types for ingesting opportunity lists published as spreadsheets.
+//!
+//! Some agencies publish their open solicitations only as a CSV or Excel (`.xlsx`) download. The `Ingest:Table`
+//! operation downloads such a table over HTTP, FTP, or SFTP (see [transfer][crate::transfer]), archived and checked
+//! like any other [attachment][crate::attachment], reads its rows, and normalizes each into an opportunity with the
+//! column mappings listed under `Tables` in the [rules][crate::rules] configuration document. Requests are matched to
+//! their mappings by URL; without a URL, the operation schedules itself for every configured table.
+mod csv;
+mod xlsx;
+
+use {
+    crate::{
+        attachment, closure,
+        httpext::{ContentKind, HostAllowlist, LogConfig, ResponseExt},
+        journal::CrawlEvent,
+        quality,
+        rules::{extract_table, load_table_rules},
+        shapes::{NextRequest, Operation, Request, Response, SUBSYS_INGEST},
+        transfer, BoxError,
+    },
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    reqwest::Url,
+    serde::{Deserialize, Serialize},
+    std::{
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+const OP_TABLE: &str = "Table";
+
+/// Possible operations for table ingestion.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum IngestOperation {
+    /// Download the table given as the URL and record its opportunities, or schedule every configured table.
+    Table,
+}
+
+impl FromStr for IngestOperation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            OP_TABLE => Ok(Self::Table),
+            _ => Err(format!("Unknown operation: {value}")),
+        }
+    }
+}
+
+impl Display for IngestOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.operation())
+    }
+}
+
+impl IngestOperation {
+    /// Handle a request.
+    pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+        match self {
+            Self::Table => table(log_config, req, context).await,
+        }
+    }
+
+    /// Return the operation name within the subsystem.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::Table => OP_TABLE,
+        }
+    }
+}
+
+/// The format of a published table.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum TableFormat {
+    /// Comma-separated values.
+    Csv,
+
+    /// An Excel workbook in the Office Open XML format.
+    Xlsx,
+}
+
+impl TableFormat {
+    /// Recognize the format of a download from the kind of its body: workbooks are ZIP packages, and anything else is
+    /// read as CSV.
+    pub fn sniff(kind: ContentKind) -> Self {
+        match kind {
+            ContentKind::Zip => Self::Xlsx,
+            _ => Self::Csv,
+        }
+    }
+}
+
+/// Read the rows of a table: its cells as text, in order. `sheet` names the worksheet to read from a workbook, by
+/// default the first.
+pub fn read_table(format: TableFormat, body: &[u8], sheet: Option<&str>) -> Result<Vec<Vec<String>>, BoxError> {
+    match format {
+        TableFormat::Csv => csv::parse_csv(body),
+        TableFormat::Xlsx => xlsx::parse_xlsx(body, sheet),
+    }
+}
+
+/// Download a table and record the opportunities in it, or, without a URL, schedule every configured table.
+async fn table(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let tables = load_table_rules(&log_config).await?;
+
+    let Some(url) = req.url.as_deref() else {
+        let next_requests = tables
+            .into_iter()
+            .map(|rules| NextRequest {
+                operation: Operation::Ingest(IngestOperation::Table),
+                url: Some(rules.url),
+                link_text: None,
+                crawl: req.crawl.child(),
+                delay_seconds: 0,
+            })
+            .collect();
+
+        return Ok(Response {
+            next_requests,
+        });
+    };
+
+    let url = Url::parse(url)?;
+    let Some(rules) = tables.into_iter().find(|rules| rules.table_url().is_ok_and(|table_url| table_url == url)) else {
+        return Err(format!("No table rules are configured for {url}").into());
+    };
+
+    let client = req
+        .crawl
+        .build_client(log_config.clone(), &context)
+        .portal(SUBSYS_INGEST)
+        .allowed_hosts(HostAllowlist::from_urls([&url]))
+        .build()?;
+
+    let response = match transfer::fetch(&client, &url).await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch {} table: {e}", rules.id);
+            return Err(e);
+        }
+    };
+
+    attachment::record_attachment(&log_config, &req.crawl, SUBSYS_INGEST, url.as_str(), &response).await;
+    let kind = response.content_kind();
+    if kind == ContentKind::Html {
+        return Err(format!("Expected a table at {url} but received an HTML page").into());
+    }
+
+    let format = rules.format.unwrap_or_else(|| TableFormat::sniff(kind));
+    let parser = rules.parser_name();
+    let result = read_table(format, &response.bytes(), rules.sheet.as_deref())
+        .and_then(|rows| extract_table(&rules, &rows, &url, req.crawl.locale.dates));
+    let event = match &result {
+        Ok(opportunities) => CrawlEvent::ParseSucceeded {
+            url: response.url().to_string(),
+            parser: parser.clone(),
+            items: opportunities.len(),
+        },
+        Err(e) => CrawlEvent::ParseFailed {
+            url: response.url().to_string(),
+            parser: parser.clone(),
+            error: e.to_string(),
+        },
+    };
+    log_config.journal.record(&client.crawl_id, event).await;
+
+    for opportunity in result? {
+        let mut opportunity = opportunity.with_parser(&parser, rules.version).with_tenant(client.tenant.as_deref());
+        quality::record_quality(&log_config, &client, &mut opportunity).await;
+        closure::record_seen(&log_config, &client, &opportunity).await;
+        info!("{} opportunity: {}", rules.id, serde_json::to_string(&opportunity)?);
+    }
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{read_table, TableFormat},
+        crate::httpext::ContentKind,
+    };
+
+    #[test]
+    fn formats() {
+        assert_eq!(TableFormat::sniff(ContentKind::Zip), TableFormat::Xlsx);
+        assert_eq!(TableFormat::sniff(ContentKind::Unknown), TableFormat::Csv);
+        assert_eq!(serde_json::from_str::<TableFormat>(r#""Xlsx""#).unwrap(), TableFormat::Xlsx);
+        assert_eq!(read_table(TableFormat::Csv, b"Project\nPaving\n", None).unwrap(), [["Project"], ["Paving"]]);
+    }
+}
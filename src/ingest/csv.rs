@@ -0,0 +1,46 @@
+//! Reading CSV downloads.
+use crate::BoxError;
+
+/// Decode a CSV download as UTF-8, after any byte order mark, or as Latin-1 if it isn't valid UTF-8, as spreadsheets
+/// exported on Windows often aren't.
+fn decode(body: &[u8]) -> String {
+    let body = body.strip_prefix(b"\xef\xbb\xbf").unwrap_or(body);
+    match std::str::from_utf8(body) {
+        Ok(text) => text.to_string(),
+        Err(_) => body.iter().map(|&b| char::from(b)).collect(),
+    }
+}
+
+/// Read the rows of a CSV download. Every row must have as many fields as the first.
+pub(super) fn parse_csv(body: &[u8]) -> Result<Vec<Vec<String>>, BoxError> {
+    let text = decode(body);
+    let mut reader = ::csv::ReaderBuilder::new().has_headers(false).from_reader(text.as_bytes());
+    let mut rows = vec![];
+    for record in reader.records() {
+        rows.push(record?.iter().map(str::to_string).collect());
+    }
+
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_csv;
+
+    #[test]
+    fn csv_rows() {
+        let rows = parse_csv(b"\xef\xbb\xbfBid No.,Project,Estimate\r\n22-014,\"Paving, Phase \"\"2\"\"\",\"$1,250,000\"\r\n22-015,\"Striping\nand signs\",\n,,\n22-016,Caf\xe9,");
+        assert_eq!(
+            rows.unwrap(),
+            [
+                vec!["Bid No.", "Project", "Estimate"],
+                vec!["22-014", "Paving, Phase \"2\"", "$1,250,000"],
+                vec!["22-015", "Striping\nand signs", ""],
+                vec!["", "", ""],
+                vec!["22-016", "Café", ""],
+            ]
+        );
+        assert!(parse_csv(b"").unwrap().is_empty());
+        assert!(parse_csv(b"Bid No.,Project\n22-014\n").is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! Reading Excel workbooks (Office Open XML `.xlsx`).
+//!
+//! Workbooks are read with calamine. Formulas are read as their cached values, and dates as the day numbers
+//! spreadsheets store them as.
+use {
+    crate::BoxError,
+    calamine::{Data, Reader, Xlsx},
+    std::io::Cursor,
+    zip::ZipArchive,
+};
+
+/// The largest part that is decompressed, so a crafted package can't exhaust memory.
+const MAX_PART_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Refuse a workbook with a part larger than [MAX_PART_SIZE] once decompressed.
+fn check_part_sizes(body: &[u8]) -> Result<(), BoxError> {
+    let mut package = ZipArchive::new(Cursor::new(body))?;
+    for index in 0..package.len() {
+        let part = package.by_index_raw(index)?;
+        if part.size() > MAX_PART_SIZE {
+            return Err(format!("Workbook part {} is larger than {MAX_PART_SIZE} bytes", part.name()).into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Return the text of a cell as the spreadsheet shows it, except that dates are day numbers.
+fn cell_text(cell: &Data) -> String {
+    match cell {
+        Data::Empty => String::new(),
+        Data::String(text) | Data::DateTimeIso(text) | Data::DurationIso(text) => text.clone(),
+        Data::Float(number) => number.to_string(),
+        Data::Int(number) => number.to_string(),
+        Data::Bool(true) => "TRUE".to_string(),
+        Data::Bool(false) => "FALSE".to_string(),
+        Data::DateTime(date) => date.as_f64().to_string(),
+        Data::Error(error) => error.to_string(),
+    }
+}
+
+/// Read the rows of a worksheet of a workbook: the one named `sheet`, or the first.
+pub(super) fn parse_xlsx(body: &[u8], sheet: Option<&str>) -> Result<Vec<Vec<String>>, BoxError> {
+    check_part_sizes(body)?;
+    let mut workbook = Xlsx::new(Cursor::new(body))?;
+    let names = workbook.sheet_names();
+    let name = match sheet {
+        Some(sheet) => names
+            .iter()
+            .find(|name| name.eq_ignore_ascii_case(sheet))
+            .ok_or_else(|| format!("Workbook has no sheet named {sheet}"))?,
+        None => names.first().ok_or("Workbook has no worksheets")?,
+    }
+    .clone();
+
+    // The range starts at the first cell that has a value; pad the rows so columns line up with the sheet's.
+    let range = workbook.worksheet_range(&name)?;
+    let (first_row, first_column) = range.start().unwrap_or_default();
+    let mut rows = vec![vec![]; first_row as usize];
+    rows.extend(range.rows().map(|cells| {
+        let mut row = vec![String::new(); first_column as usize];
+        row.extend(cells.iter().map(cell_text));
+        row
+    }));
+
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::parse_xlsx,
+        std::io::{Cursor, Write},
+        zip::{write::SimpleFileOptions, ZipWriter},
+    };
+
+    /// Build a ZIP package of deflated parts.
+    fn package(parts: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for (name, contents) in parts {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn workbooks() {
+        let workbook = package(&[
+            (
+                "[Content_Types].xml",
+                r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+                    <Default Extension="xml" ContentType="application/xml"/></Types>"#,
+            ),
+            (
+                "_rels/.rels",
+                r#"<Relationships><Relationship Id="rId1" Target="xl/workbook.xml"
+                    Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument"/>
+                   </Relationships>"#,
+            ),
+            (
+                "xl/workbook.xml",
+                r#"<workbook xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets>
+                    <sheet name="Notes" sheetId="1" r:id="rId1"/><sheet name="Open Bids" sheetId="2" r:id="rId2"/>
+                   </sheets></workbook>"#,
+            ),
+            (
+                "xl/_rels/workbook.xml.rels",
+                r#"<Relationships>
+                    <Relationship Id="rId1" Target="worksheets/sheet1.xml"
+                     Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet"/>
+                    <Relationship Id="rId2" Target="/xl/worksheets/bids.xml"
+                     Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet"/>
+                   </Relationships>"#,
+            ),
+            (
+                "xl/sharedStrings.xml",
+                r#"<sst><si><t>Project</t></si><si><r><t xml:space="preserve">Paving </t></r><r><t>&amp; Striping</t></r><rPh><t>x</t></rPh></si>
+                   <si><t>Bid No.</t></si></sst>"#,
+            ),
+            (
+                "xl/worksheets/sheet1.xml",
+                r#"<worksheet><sheetData><row r="1"><c r="A1" t="inlineStr"><is><t>Read me</t></is></c></row></sheetData></worksheet>"#,
+            ),
+            (
+                "xl/worksheets/bids.xml",
+                r#"<worksheet><sheetData>
+                    <row r="1"><c r="A1" t="s"><v>0</v></c><c r="C1" t="s"><v>2</v></c></row>
+                    <row r="2"><c r="A2" t="s"><v>1</v></c><c r="B2"><f>TODAY()</f><v>44895.5</v></c><c r="C2" t="str"><v>22-014</v></c><c r="D2" t="b"><v>1</v></c></row>
+                   </sheetData></worksheet>"#,
+            ),
+        ]);
+
+        assert_eq!(parse_xlsx(&workbook, None).unwrap(), [vec!["Read me"]]);
+        assert_eq!(
+            parse_xlsx(&workbook, Some("open bids")).unwrap(),
+            [vec!["Project", "", "Bid No.", ""], vec!["Paving & Striping", "44895.5", "22-014", "TRUE"]]
+        );
+        assert!(parse_xlsx(&workbook, Some("Closed")).is_err());
+        assert!(parse_xlsx(b"Bid No.,Project\n", None).is_err());
+    }
+}
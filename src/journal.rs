@@ -0,0 +1,509 @@
+//! Append-only journal of crawl lifecycle events.
+//!
+//! Each event is written as its own DynamoDB item, keyed by crawl and ordered by a UUIDv7 event id, so the
+//! sequence of operations, fetches, parses, and emitted messages for a crawl can be replayed exactly.
+//!
+//! Every item records the crate version that wrote it, and events produced by a parser also record the parser and
+//! its version as top-level attributes, so the events of a faulty parser version can be queried for re-parsing.
+use {
+    crate::{
+        coverage::parser_version,
+        httpext::{aws_err_str, DdbBatchWriter, FetchErrorKind},
+        opportunity::{OpportunityStatus, SolicitationStage},
+        quality::QualityIssue,
+        BoxError, CRATE_VERSION,
+    },
+    aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoDbClient},
+    log::*,
+    serde::{Deserialize, Serialize},
+    std::collections::HashMap,
+    uuid::{NoContext, Timestamp, Uuid},
+};
+
+const DDB_KEY_CRAWL_ID: &str = "CrawlId";
+const DDB_KEY_REQUEST_ID: &str = "RequestId";
+const DDB_KEY_TIMESTAMP: &str = "Timestamp";
+const DDB_KEY_EVENT_TYPE: &str = "EventType";
+const DDB_KEY_DETAIL: &str = "Detail";
+const DDB_KEY_CRATE_VERSION: &str = "CrateVersion";
+const DDB_KEY_PARSER: &str = "Parser";
+const DDB_KEY_PARSER_VERSION: &str = "ParserVersion";
+
+/// Partition key prefix for journal items, keeping them apart from request log items in a shared table.
+const JOURNAL_PARTITION_PREFIX: &str = "Journal#";
+
+/// Sort key prefix for journal items.
+const EVENT_SORT_PREFIX: &str = "Event#";
+
+/// A lifecycle event in a crawl.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "Event", rename_all = "PascalCase")]
+pub enum CrawlEvent {
+    /// An operation began processing a request.
+    #[serde(rename_all = "PascalCase")]
+    OperationStarted {
+        /// The operation being performed.
+        operation: String,
+
+        /// The URL the operation started from, if any.
+        url: Option<String>,
+    },
+
+    /// An operation completed successfully.
+    #[serde(rename_all = "PascalCase")]
+    OperationCompleted {
+        /// The operation that was performed.
+        operation: String,
+
+        /// The number of next requests the operation produced.
+        next_requests: usize,
+    },
+
+    /// An operation failed.
+    #[serde(rename_all = "PascalCase")]
+    OperationFailed {
+        /// The operation that was performed.
+        operation: String,
+
+        /// The error that was returned.
+        error: String,
+    },
+
+    /// An operation hit a temporary condition and was re-enqueued to run later.
+    #[serde(rename_all = "PascalCase")]
+    OperationDeferred {
+        /// The operation that was performed.
+        operation: String,
+
+        /// The condition encountered.
+        condition: String,
+
+        /// The number of times the request has now been re-enqueued.
+        attempt: u32,
+
+        /// How long the request is held before it runs again, in seconds.
+        delay_seconds: u32,
+    },
+
+    /// A page was fetched and logged.
+    #[serde(rename_all = "PascalCase")]
+    PageFetched {
+        /// The final URL of the page.
+        url: String,
+
+        /// The HTTP status code returned.
+        status: u16,
+
+        /// The request id of the log item for this fetch.
+        request_id: String,
+    },
+
+    /// A fetch failed before a complete response was received.
+    #[serde(rename_all = "PascalCase")]
+    FetchFailed {
+        /// The URL requested.
+        url: String,
+
+        /// The kind of connection-level failure.
+        error_kind: FetchErrorKind,
+
+        /// The error that was returned.
+        error: String,
+
+        /// The number of times the operation had been re-enqueued when the fetch failed.
+        attempt: u32,
+
+        /// The request id of the log item for this fetch.
+        request_id: String,
+    },
+
+    /// The body of a response didn't match its declared `Content-Type`.
+    #[serde(rename_all = "PascalCase")]
+    ContentTypeMismatch {
+        /// The final URL of the response.
+        url: String,
+
+        /// The kind of content the `Content-Type` header declared.
+        declared: String,
+
+        /// The kind of content sniffed from the body.
+        actual: String,
+    },
+
+    /// An opportunity was found to be no longer published, by its detail page or by reconciliation.
+    #[serde(rename_all = "PascalCase")]
+    OpportunityClosed {
+        /// The URL of the opportunity.
+        url: String,
+
+        /// Whether the opportunity was closed or expired.
+        status: OpportunityStatus,
+
+        /// Why the opportunity was taken to be closed.
+        reason: String,
+    },
+
+    /// A solicitation moved to a later stage of its [lifecycle][crate::lifecycle].
+    #[serde(rename_all = "PascalCase")]
+    StageChanged {
+        /// The URL of the opportunity.
+        url: String,
+
+        /// The stage the solicitation was in, or `None` if it was first seen.
+        from: Option<SolicitationStage>,
+
+        /// The stage the solicitation moved to.
+        to: SolicitationStage,
+
+        /// What the move was inferred from.
+        reason: String,
+    },
+
+    /// A page was parsed successfully.
+    #[serde(rename_all = "PascalCase")]
+    ParseSucceeded {
+        /// The URL of the page.
+        url: String,
+
+        /// The parser that was used.
+        parser: String,
+
+        /// The number of items extracted.
+        items: usize,
+    },
+
+    /// A page could not be parsed.
+    #[serde(rename_all = "PascalCase")]
+    ParseFailed {
+        /// The URL of the page.
+        url: String,
+
+        /// The parser that was used.
+        parser: String,
+
+        /// The parse error.
+        error: String,
+    },
+
+    /// The number of items extracted from a listing did not match the total the portal reported.
+    #[serde(rename_all = "PascalCase")]
+    ListingCountMismatch {
+        /// The parser that was used.
+        parser: String,
+
+        /// The total number of items the portal reported.
+        expected: usize,
+
+        /// The number of items actually extracted.
+        extracted: usize,
+    },
+
+    /// Response time percentiles for a host, reported when a crawl completes.
+    #[serde(rename_all = "PascalCase")]
+    HostResponseTimes {
+        /// The host the requests were made to.
+        host: String,
+
+        /// The number of responses timed.
+        requests: usize,
+
+        /// The median response time, in milliseconds.
+        p50_ms: u64,
+
+        /// The 95th percentile response time, in milliseconds.
+        p95_ms: u64,
+
+        /// The slowest response time, in milliseconds.
+        max_ms: u64,
+    },
+
+    /// A portal refused to log an account in because it is locked out or rate limited.
+    #[serde(rename_all = "PascalCase")]
+    AccountLockedOut {
+        /// The portal (subsystem).
+        portal: String,
+
+        /// The account that was refused.
+        account: String,
+    },
+
+    /// Next-request messages were sent to the queue.
+    #[serde(rename_all = "PascalCase")]
+    MessagesEmitted {
+        /// The number of messages sent.
+        count: usize,
+    },
+
+    /// A parsed opportunity's data quality was assessed.
+    #[serde(rename_all = "PascalCase")]
+    QualityAssessed {
+        /// The URL of the opportunity.
+        url: String,
+
+        /// The parser that produced the opportunity.
+        parser: String,
+
+        /// The quality score.
+        score: u32,
+
+        /// The checks the opportunity failed.
+        issues: Vec<QualityIssue>,
+    },
+
+    /// A page was also parsed by a parser running in [shadow][crate::shadow], and its output compared with the stable
+    /// parser's.
+    #[serde(rename_all = "PascalCase")]
+    ShadowParseCompared {
+        /// The URL of the page.
+        url: String,
+
+        /// The stable parser, whose output was kept.
+        parser: String,
+
+        /// The parser running in shadow.
+        shadow_parser: String,
+
+        /// The fields whose values differ between the two outputs.
+        differences: Vec<String>,
+
+        /// The error the shadow parser returned, if it failed.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+/// Writer for the crawl event journal.
+///
+/// Events are buffered and written in batches; call [`flush`][Journal::flush] when an operation completes.
+#[derive(Clone, Debug)]
+pub struct Journal {
+    ddb_client: DynamoDbClient,
+    writer: DdbBatchWriter,
+}
+
+impl CrawlEvent {
+    /// Return the parser that produced the event, if it was produced by one.
+    pub fn parser(&self) -> Option<&str> {
+        match self {
+            Self::ParseSucceeded {
+                parser,
+                ..
+            }
+            | Self::ParseFailed {
+                parser,
+                ..
+            }
+            | Self::ListingCountMismatch {
+                parser,
+                ..
+            }
+            | Self::QualityAssessed {
+                parser,
+                ..
+            }
+            | Self::ShadowParseCompared {
+                parser,
+                ..
+            } => Some(parser),
+            _ => None,
+        }
+    }
+
+    /// Return the name of the event type.
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            Self::OperationStarted {
+                ..
+            } => "OperationStarted",
+            Self::OperationCompleted {
+                ..
+            } => "OperationCompleted",
+            Self::OperationFailed {
+                ..
+            } => "OperationFailed",
+            Self::OperationDeferred {
+                ..
+            } => "OperationDeferred",
+            Self::PageFetched {
+                ..
+            } => "PageFetched",
+            Self::FetchFailed {
+                ..
+            } => "FetchFailed",
+            Self::ContentTypeMismatch {
+                ..
+            } => "ContentTypeMismatch",
+            Self::OpportunityClosed {
+                ..
+            } => "OpportunityClosed",
+            Self::StageChanged {
+                ..
+            } => "StageChanged",
+            Self::ParseSucceeded {
+                ..
+            } => "ParseSucceeded",
+            Self::ParseFailed {
+                ..
+            } => "ParseFailed",
+            Self::ListingCountMismatch {
+                ..
+            } => "ListingCountMismatch",
+            Self::HostResponseTimes {
+                ..
+            } => "HostResponseTimes",
+            Self::AccountLockedOut {
+                ..
+            } => "AccountLockedOut",
+            Self::MessagesEmitted {
+                ..
+            } => "MessagesEmitted",
+            Self::QualityAssessed {
+                ..
+            } => "QualityAssessed",
+            Self::ShadowParseCompared {
+                ..
+            } => "ShadowParseCompared",
+        }
+    }
+}
+
+impl Journal {
+    /// Create a journal writing to the given table.
+    pub fn new<S: Into<String>>(ddb_client: DynamoDbClient, table_name: S) -> Self {
+        Self {
+            writer: DdbBatchWriter::new(ddb_client.clone(), table_name),
+            ddb_client,
+        }
+    }
+
+    /// Return the name of the table events are written to.
+    pub fn table_name(&self) -> &str {
+        self.writer.table_name()
+    }
+
+    /// Append an event to the journal for the given crawl.
+    ///
+    /// Journal writes are best-effort: failures are logged rather than failing the operation being journaled.
+    pub async fn record(&self, crawl_id: &str, event: CrawlEvent) {
+        let result = match event_item(crawl_id, &event, Timestamp::now(NoContext)) {
+            Ok(item) => self.writer.put(item).await,
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = result {
+            warn!("Failed to journal {} event for crawl {crawl_id}: {e}", event.event_type());
+        }
+    }
+
+    /// Write all buffered events.
+    pub async fn flush(&self) -> Result<(), BoxError> {
+        self.writer.flush().await
+    }
+
+    /// Return the events written for the given crawl, in the order they were recorded. Events that can't be parsed,
+    /// such as those of a newer crate version, are skipped.
+    pub async fn events(&self, crawl_id: &str) -> Result<Vec<CrawlEvent>, BoxError> {
+        let table_name = self.writer.table_name();
+        let mut events = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .ddb_client
+                .query()
+                .table_name(table_name)
+                .key_condition_expression("#pk = :pk AND begins_with(#sk, :prefix)")
+                .expression_attribute_names("#pk", DDB_KEY_CRAWL_ID)
+                .expression_attribute_names("#sk", DDB_KEY_REQUEST_ID)
+                .expression_attribute_values(":pk", AttributeValue::S(format!("{JOURNAL_PARTITION_PREFIX}{crawl_id}")))
+                .expression_attribute_values(":prefix", AttributeValue::S(EVENT_SORT_PREFIX.to_string()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await;
+            let output = match result {
+                Ok(output) => output,
+                Err(e) => {
+                    error!("Query journal of crawl {crawl_id}: {}", aws_err_str(&e));
+                    return Err(e.into());
+                }
+            };
+
+            for item in output.items() {
+                let Some(detail) = item.get(DDB_KEY_DETAIL).and_then(|v| v.as_s().ok()) else {
+                    continue;
+                };
+
+                match serde_json::from_str(detail) {
+                    Ok(event) => events.push(event),
+                    Err(e) => warn!("Skipping unparseable journal event of crawl {crawl_id}: {e}"),
+                }
+            }
+
+            start_key = output.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(events);
+            }
+        }
+    }
+}
+
+/// Convert an event into a DynamoDB item.
+fn event_item(
+    crawl_id: &str,
+    event: &CrawlEvent,
+    timestamp: Timestamp,
+) -> Result<HashMap<String, AttributeValue>, BoxError> {
+    let (secs, nanos) = timestamp.to_unix();
+    let event_id = Uuid::new_v7(timestamp);
+
+    let mut item = HashMap::from([
+        (DDB_KEY_CRAWL_ID.to_string(), AttributeValue::S(format!("{JOURNAL_PARTITION_PREFIX}{crawl_id}"))),
+        (DDB_KEY_REQUEST_ID.to_string(), AttributeValue::S(format!("{EVENT_SORT_PREFIX}{event_id}"))),
+        (DDB_KEY_TIMESTAMP.to_string(), AttributeValue::N(format!("{secs}.{nanos:09}"))),
+        (DDB_KEY_EVENT_TYPE.to_string(), AttributeValue::S(event.event_type().to_string())),
+        (DDB_KEY_DETAIL.to_string(), AttributeValue::S(serde_json::to_string(event)?)),
+        (DDB_KEY_CRATE_VERSION.to_string(), AttributeValue::S(CRATE_VERSION.to_string())),
+    ]);
+
+    if let Some(parser) = event.parser() {
+        item.insert(DDB_KEY_PARSER.to_string(), AttributeValue::S(parser.to_string()));
+        if let Some(version) = parser_version(parser) {
+            item.insert(DDB_KEY_PARSER_VERSION.to_string(), AttributeValue::N(version.to_string()));
+        }
+    }
+
+    Ok(item)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{event_item, CrawlEvent},
+        uuid::{NoContext, Timestamp},
+    };
+
+    #[test]
+    fn event_items() {
+        let event = CrawlEvent::ParseSucceeded {
+            url: "https://example.com/".to_string(),
+            parser: "Webs:Listing".to_string(),
+            items: 3,
+        };
+        let item = event_item("crawl-1", &event, Timestamp::from_unix(NoContext, 1700000000, 5)).unwrap();
+
+        assert_eq!(item["CrawlId"].as_s().unwrap().as_str(), "Journal#crawl-1");
+        assert!(item["RequestId"].as_s().unwrap().starts_with("Event#"));
+        assert_eq!(item["Timestamp"].as_n().unwrap().as_str(), "1700000000.000000005");
+        assert_eq!(item["EventType"].as_s().unwrap().as_str(), "ParseSucceeded");
+        assert_eq!(item["CrateVersion"].as_s().unwrap().as_str(), crate::CRATE_VERSION);
+        assert_eq!(item["Parser"].as_s().unwrap().as_str(), "Webs:Listing");
+        assert_eq!(item["ParserVersion"].as_n().unwrap().as_str(), crate::webs::PARSER_VERSION.to_string());
+
+        let detail: CrawlEvent = serde_json::from_str(item["Detail"].as_s().unwrap()).unwrap();
+        assert_eq!(detail, event);
+        assert_eq!(
+            item["Detail"].as_s().unwrap().as_str(),
+            r#"{"Event":"ParseSucceeded","Url":"https://example.com/","Parser":"Webs:Listing","Items":3}"#
+        );
+    }
+}
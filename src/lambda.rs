@@ -0,0 +1,294 @@
+//! The Lambda entry point.
+//!
+//! The function is invoked with batches of SQS messages, each holding a [`Request`]. Every request is run through the
+//! [middleware pipeline][crate::middleware], and the next requests the operations return are sent back to the queue.
+use {
+    crate::{
+        dead_letter::{error_class, FailureRecord},
+        httpext::{log_aws_err, LogConfig},
+        journal::CrawlEvent,
+        metrics,
+        middleware::{OperationCall, Pipeline},
+        shapes::{NextRequest, Operation, Request, Response},
+        BoxError,
+    },
+    aws_lambda_events::sqs::{BatchItemFailure, SqsBatchResponse, SqsEventObj},
+    aws_sdk_sqs::types::{
+        MessageAttributeValue, MessageSystemAttributeNameForSends, MessageSystemAttributeValue,
+        SendMessageBatchRequestEntry,
+    },
+    chrono::{DateTime, Utc},
+    futures::stream::FuturesUnordered,
+    lambda_runtime::{Context, Error as LambdaError, LambdaEvent},
+    log::*,
+    std::{collections::HashMap, str::FromStr},
+    uuid::{NoContext, Timestamp, Uuid},
+};
+
+const MSG_ATTR_SUBSYSTEM: &str = "Subsystem";
+const MSG_ATTR_OPERATION: &str = "Operation";
+const MSG_DATA_TYPE_STRING: &str = "String";
+const SQS_ATTR_APPROXIMATE_RECEIVE_COUNT: &str = "ApproximateReceiveCount";
+const MAX_SQS_BATCH_SIZE: usize = 10;
+const METRIC_MESSAGES_EMITTED: &str = "MessagesEmitted";
+
+/// Handle a batch of SQS messages.
+///
+/// Records that fail are reported individually as batch item failures so only they are retried; this requires the
+/// event source mapping to have `ReportBatchItemFailures` enabled.
+pub async fn handler(event: LambdaEvent<SqsEventObj<Request>>) -> Result<SqsBatchResponse, LambdaError> {
+    let result = handle_batch(event).await;
+    metrics::publish();
+    result
+}
+
+async fn handle_batch(event: LambdaEvent<SqsEventObj<Request>>) -> Result<SqsBatchResponse, LambdaError> {
+    let (request, context) = event.into_parts();
+    let log_config = match LogConfig::new().await {
+        Ok(log_config) => log_config,
+        Err(e) => {
+            error!("{e}");
+            return Err(e.into());
+        }
+    };
+    let pipeline = Pipeline::standard(&log_config);
+    let futures = FuturesUnordered::new();
+
+    for record in request.records.into_iter() {
+        info!("Received record {record:?}");
+        let message_id = record.message_id;
+        let receive_count =
+            record.attributes.get(SQS_ATTR_APPROXIMATE_RECEIVE_COUNT).and_then(|count| count.parse().ok()).unwrap_or(1);
+        let request = record.body;
+        let (pipeline, log_config, context) = (&pipeline, log_config.clone(), context.clone());
+        futures.push(Box::pin(async move {
+            let result = dispatch(pipeline, log_config, request, context, message_id.as_deref(), receive_count).await;
+            (message_id, result)
+        }));
+    }
+
+    let mut next_requests = Vec::with_capacity(futures.len() * 5);
+    let mut batch_item_failures = vec![];
+    let mut unidentified_errors = vec![];
+
+    for future in futures.into_iter() {
+        match future.await {
+            (_, Ok(response)) => next_requests.extend(response.next_requests),
+            (Some(message_id), Err(e)) => {
+                error!("Error processing message {message_id}: {e}");
+                batch_item_failures.push(BatchItemFailure {
+                    item_identifier: message_id,
+                });
+            }
+            (None, Err(e)) => {
+                error!("Error processing message without a message id: {e}");
+                unidentified_errors.push(e);
+            }
+        }
+    }
+
+    // A failed record we can't identify can only be retried by failing the whole batch.
+    match unidentified_errors.len() {
+        0 => (),
+        1 => return Err(unidentified_errors.pop().unwrap()),
+        _ => return Err("Multiple errors".into()),
+    }
+
+    if batch_item_failures.is_empty() {
+        info!("All futures completed successfully");
+    }
+
+    send_next_requests(&log_config, &context, next_requests).await?;
+
+    if let Err(e) = log_config.journal.flush().await {
+        warn!("Failed to flush crawl journal: {e}");
+    }
+
+    Ok(SqsBatchResponse {
+        batch_item_failures,
+    })
+}
+
+/// Send next requests to the SQS queue, journaling the number sent for each crawl.
+///
+/// Request URLs are canonicalized first so that downstream deduplication and caching see a consistent key.
+///
+/// A next request that can't be converted into a message is logged and dropped rather than failing the batch.
+async fn send_next_requests(
+    log_config: &LogConfig,
+    context: &Context,
+    mut next_requests: Vec<NextRequest>,
+) -> Result<(), LambdaError> {
+    let timestamp = Timestamp::now(NoContext);
+
+    let mut batch_size = 0;
+    let mut emitted: HashMap<String, usize> = HashMap::new();
+    let send_message_batch_base = log_config.sqs_client.send_message_batch().queue_url(&log_config.sqs_queue_url);
+    let mut send_message_batch = send_message_batch_base.clone();
+
+    metrics::registry().increment(METRIC_MESSAGES_EMITTED, &[], next_requests.len() as u64);
+
+    next_requests.iter_mut().for_each(NextRequest::normalize);
+
+    // Record the requests in their crawls' frontiers before any of them can be delivered and marked visited.
+    if let Err(e) = log_config.frontier.enqueue(&next_requests, Utc::now()).await {
+        warn!("Failed to record next requests in crawl frontiers: {e}");
+    }
+
+    for next_request in next_requests {
+        let id = Uuid::new_v7(timestamp);
+        let message = match message_entry(&next_request, id, context.xray_trace_id.as_deref()) {
+            Ok(message) => message,
+            Err(e) => {
+                error!("Dropping next request {next_request:?}: {e}");
+                continue;
+            }
+        };
+
+        if let Some(crawl_id) = next_request.crawl.crawl_id.as_ref() {
+            *emitted.entry(crawl_id.clone()).or_default() += 1;
+        }
+
+        send_message_batch = send_message_batch.entries(message);
+        batch_size += 1;
+
+        if batch_size == MAX_SQS_BATCH_SIZE {
+            log_aws_err(send_message_batch.send().await, "SendMessageBatch")?;
+            send_message_batch = send_message_batch_base.clone();
+            batch_size = 0;
+        }
+    }
+
+    if batch_size > 0 {
+        log_aws_err(send_message_batch.send().await, "SendMessageBatch")?;
+    }
+
+    for (crawl_id, count) in emitted {
+        log_config
+            .journal
+            .record(
+                &crawl_id,
+                CrawlEvent::MessagesEmitted {
+                    count,
+                },
+            )
+            .await;
+    }
+
+    Ok(())
+}
+
+/// Convert a next request into an SQS message.
+fn message_entry(
+    next_request: &NextRequest,
+    id: Uuid,
+    xray_trace_id: Option<&str>,
+) -> Result<SendMessageBatchRequestEntry, BoxError> {
+    let message_body = serde_json::to_string(next_request)?;
+    let subsystem = MessageAttributeValue::builder()
+        .string_value(next_request.operation.subsystem())
+        .data_type(MSG_DATA_TYPE_STRING)
+        .build()?;
+    let operation = MessageAttributeValue::builder()
+        .string_value(next_request.operation.operation())
+        .data_type(MSG_DATA_TYPE_STRING)
+        .build()?;
+
+    let mut message = SendMessageBatchRequestEntry::builder()
+        .id(id)
+        .message_body(message_body)
+        .delay_seconds(next_request.delay_seconds as i32)
+        .message_attributes(MSG_ATTR_SUBSYSTEM, subsystem)
+        .message_attributes(MSG_ATTR_OPERATION, operation);
+    if let Some(xray_trace_id) = xray_trace_id {
+        let xray_trace_id = MessageSystemAttributeValue::builder()
+            .string_value(xray_trace_id)
+            .data_type(MSG_DATA_TYPE_STRING)
+            .build()?;
+        message = message.message_system_attributes(MessageSystemAttributeNameForSends::AwsTraceHeader, xray_trace_id);
+    }
+
+    Ok(message.build()?)
+}
+
+/// Run a request through the middleware pipeline.
+///
+/// If the request arrived as an SQS message, a failure is recorded for triage should the message be dead-lettered,
+/// and the record of an earlier failed delivery is removed once it succeeds.
+async fn dispatch(
+    pipeline: &Pipeline,
+    log_config: LogConfig,
+    request: Request,
+    context: Context,
+    message_id: Option<&str>,
+    receive_count: u32,
+) -> Result<Response, LambdaError> {
+    let Ok(operation) = Operation::from_str(&request.operation) else {
+        return Err(format!("Invalid operation: {}", request.operation).into());
+    };
+
+    let log_config = log_config.for_operation();
+    let started_at = Utc::now();
+    let call = OperationCall::new(operation, request.clone(), log_config.clone()).with_deadline(context.deadline);
+    let mut result = pipeline.run(call, context).await;
+
+    // Write any log items this operation left buffered.
+    if let Err(e) = log_config.ddb_writer.flush().await {
+        error!("Failed to flush DynamoDB log items: {e}");
+        if result.is_ok() {
+            result = Err(e);
+        }
+    }
+
+    if let Err(e) = log_config.journal.flush().await {
+        warn!("Failed to flush crawl journal: {e}");
+    }
+
+    if let Err(e) = log_config.links.flush().await {
+        warn!("Failed to flush link graph: {e}");
+    }
+
+    if let Err(e) = log_config.audit.flush(&log_config.crawls).await {
+        warn!("Failed to flush request audit: {e}");
+    }
+
+    if let Some(message_id) = message_id {
+        record_delivery(&log_config, message_id, receive_count, request, started_at, &result).await;
+    }
+
+    result
+}
+
+/// Record a failed delivery of a message, or clear the record of an earlier failed delivery once one succeeds.
+///
+/// Failure records are best-effort: errors writing them are logged rather than changing the outcome of the delivery.
+async fn record_delivery(
+    log_config: &LogConfig,
+    message_id: &str,
+    receive_count: u32,
+    request: Request,
+    started_at: DateTime<Utc>,
+    result: &Result<Response, LambdaError>,
+) {
+    let outcome = match result {
+        Ok(_) if receive_count > 1 => log_config.failures.clear(request.crawl.crawl_id.as_deref(), message_id).await,
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let record = FailureRecord {
+                message_id: message_id.to_string(),
+                receive_count,
+                error: e.to_string(),
+                error_class: error_class(e.as_ref()),
+                started_at,
+                elapsed_ms: (Utc::now() - started_at).num_milliseconds().max(0) as u64,
+                last_response: log_config.last_stored.lock().as_ref().map(|s| (s.bucket.clone(), s.key.clone())),
+                request,
+            };
+            log_config.failures.record(&record).await
+        }
+    };
+
+    if let Err(e) = outcome {
+        warn!("Failed to update failure record for message {message_id}: {e}");
+    }
+}
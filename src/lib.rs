@@ -0,0 +1,191 @@
+//! The GovScout crawler: portal crawlers and parsers, the HTML and HTTP utilities they share, and the request shapes
+//! that drive them.
+//!
+//! The AWS Lambda function is a thin binary over this library; [`lambda::handler`] is its entry point. Companion
+//! tools such as `loadgen` use the library to build requests and parse pages the same way the crawler does.
+#![warn(clippy::all)]
+#![deny(rustdoc::missing_crate_level_docs)]
+#![deny(rustdoc::broken_intra_doc_links)]
+#![deny(missing_docs)]
+
+/// Administrative operations.
+pub mod admin;
+
+/// Backfilling historical opportunities from the Internet Archive's snapshots of portals.
+pub mod archive;
+
+/// Deduplicated storage of opportunity attachments.
+pub mod attachment;
+
+/// Awards read from the pages of awarded solicitations.
+pub mod award;
+
+/// BidNet Direct service functionality.
+pub mod bidnet;
+
+/// Parser regression canary.
+pub mod canary;
+
+/// Data classification of stored objects and items.
+pub mod classification;
+
+/// Bulk export of stored opportunities to S3.
+pub mod export;
+
+/// Extraction of the text of opportunity attachments.
+pub mod extract;
+
+/// The opportunity representation the GovScout frontend consumes.
+#[cfg(any(test, feature = "frontend"))]
+pub mod frontend;
+
+/// Florida Vendor Bid System (VBS) service functionality.
+pub mod fl_vbs;
+
+/// Feature flags evaluated at dispatch time.
+pub mod flags;
+
+/// Per-crawl index of visited and outstanding URLs.
+pub mod frontier;
+
+/// Fuzzing entry points for the HTML parsers.
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzz;
+
+/// Golden-file snapshot testing for parsers.
+#[cfg(any(test, feature = "test-utils"))]
+pub mod golden;
+
+/// Detection of opportunities that portals no longer publish.
+pub mod closure;
+
+/// Checkpoints for resumable crawl operations.
+pub mod checkpoint;
+
+/// Per-subsystem configuration documents.
+pub mod config;
+
+/// Extraction of contact details from the text of detail pages and attachments.
+pub mod contacts;
+
+/// Estimated cost of crawls, and monthly budgets per portal.
+pub mod cost;
+
+/// Registry of the portals GovScout crawls.
+pub mod coverage;
+
+/// Registry of crawls.
+pub mod crawl;
+
+/// Records of failed requests, for triaging the dead-letter queue.
+pub mod dead_letter;
+
+/// Clustering of near-duplicate opportunities published on several portals.
+pub mod duplicates;
+
+/// Crawl health checks and alerting.
+pub mod health;
+
+/// HTTP extension utilities.
+pub mod httpext;
+
+/// Crawl event journal.
+pub mod journal;
+
+/// Ingestion of opportunity lists published as spreadsheets.
+pub mod ingest;
+
+/// The Lambda entry point: handling batches of SQS messages.
+pub mod lambda;
+
+/// Modeling of where solicitations are in their lifecycle.
+pub mod lifecycle;
+
+/// Graph of the links crawls follow.
+pub mod link_graph;
+
+/// Ingestion of the email portals send.
+pub mod mail;
+
+/// MERX (merx.com) Canadian tendering service functionality.
+pub mod merx;
+
+/// Solicitation page markup shared by the portals mdf commerce runs.
+pub mod mets;
+
+/// Metrics collection and publishing.
+pub mod metrics;
+
+/// Middleware applied around every operation.
+pub mod middleware;
+
+/// The normalized opportunity model.
+pub mod opportunity;
+
+/// Pagination of portal listings.
+pub mod pagination;
+
+/// PublicPurchase (publicpurchase.com) service functionality.
+pub mod publicpurchase;
+
+/// Data quality scoring for parsed opportunities.
+pub mod quality;
+
+/// Reconciliation of stored opportunities with what portals still publish.
+pub mod reconcile;
+
+/// Redaction of personal data from stored pages.
+pub mod redact;
+
+/// Retrying operations after temporary conditions.
+pub mod retry;
+
+/// Declarative extraction rules for simple portals.
+pub mod rules;
+
+/// Adaptive scheduling of portal crawls.
+pub mod schedule;
+
+/// Portal login sessions.
+pub mod session;
+
+/// Keyword search over opportunities in OpenSearch.
+pub mod search;
+
+/// Shadow runs of new parsers alongside the stable ones.
+pub mod shadow;
+
+/// Shapes used in the request.
+pub mod shapes;
+
+/// HTML parsing library.
+pub mod soup;
+
+/// Fake portal for exercising the crawler end to end.
+pub mod testportal;
+
+/// Fetching files from FTP and SFTP servers.
+pub mod transfer;
+
+/// Crawling of the vendor directories some portals publish.
+pub mod vendors;
+
+/// Washington city and county portals outside of WEBS.
+pub mod wa_local;
+
+/// Washington State Electronic Business Solution (WEBS) service functionality.
+pub mod webs;
+
+pub use {
+    httpext::LogConfig,
+    opportunity::Opportunity,
+    shapes::{NextRequest, Operation, Request, Response},
+};
+
+use std::error::Error;
+
+/// Dynamic error type that is safe to send across threads.
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+/// The version of this crate, stamped on stored records so they can be traced to the code that produced them.
+pub const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -0,0 +1,248 @@
+//! Modeling of where solicitations are in their lifecycle.
+//!
+//! A solicitation is [posted][SolicitationStage::Posted], may be amended, stops taking questions, closes, and is then
+//! awarded or cancelled. Stages only advance, though a solicitation can skip any of them. After a detail page is
+//! parsed, [`record_stage`] infers the latest stage the page shows from:
+//!
+//! * a status label or notice saying the solicitation was cancelled or awarded, e.g. `Bid Status: Cancelled` or
+//!   `Notice of Award`, or an [award][crate::award] its parser read;
+//! * a status label saying it is closed, or a due date more than [`DUE_GRACE`] ago;
+//! * a deadline for questions that has passed, e.g. `Questions Due: 04/20/2023 5:00 PM`; or
+//! * a status label or document name mentioning an amendment or addendum.
+//!
+//! Otherwise the solicitation is taken to be posted. When [soft 404 detection][crate::closure] or a
+//! [reconciliation][crate::reconcile] sweep closes an opportunity, it moves to [closed][SolicitationStage::Closed].
+//!
+//! Each move to a later stage is stored in the opportunity store with what it was inferred from, and journaled as a
+//! [`StageChanged`][CrawlEvent::StageChanged] event. A page showing an earlier stage than the stored one, such as one
+//! that no longer lists an addendum, leaves the stored stage as it is, and the opportunity's record carries the stored
+//! stage.
+use {
+    crate::{
+        contacts::page_lines,
+        httpext::{Client, LogConfig},
+        journal::CrawlEvent,
+        opportunity::{parse_date_time, DateLocale, Opportunity, SolicitationStage, StageTransition},
+        reconcile::DUE_GRACE,
+    },
+    chrono::{NaiveDateTime, Utc},
+    log::*,
+};
+
+/// Words in the value of a status label and the stage they mean, latest stage first.
+const STATUS_WORDS: &[(&str, SolicitationStage)] = &[
+    ("cancel", SolicitationStage::Cancelled),
+    ("award", SolicitationStage::Awarded),
+    ("closed", SolicitationStage::Closed),
+    ("amend", SolicitationStage::Amended),
+    ("addend", SolicitationStage::Amended),
+];
+
+/// Phrases announcing a stage anywhere on a page.
+const NOTICES: &[(&str, SolicitationStage)] = &[
+    ("has been cancelled", SolicitationStage::Cancelled),
+    ("has been canceled", SolicitationStage::Cancelled),
+    ("notice of cancellation", SolicitationStage::Cancelled),
+    ("has been awarded", SolicitationStage::Awarded),
+    ("notice of award", SolicitationStage::Awarded),
+    ("award notice", SolicitationStage::Awarded),
+];
+
+/// Words in a document name marking it as an amendment.
+const AMENDMENT_WORDS: &[&str] = &["addend", "amendment"];
+
+/// Words that, with "question", make a label the deadline for questions.
+const QUESTION_DEADLINE_WORDS: &[&str] = &["due", "deadline", "cutoff", "cut-off", "last day", "close"];
+
+/// Labels longer than this many words aren't taken as labels.
+const MAX_LABEL_WORDS: usize = 4;
+
+/// Dates are read from at most this many words following a label, e.g. `04/20/2023 5:00 PM`.
+const MAX_DATE_WORDS: usize = 3;
+
+/// Split a line into the label at its start and the value following it, lowercased.
+fn labelled(line: &str) -> Option<(String, String)> {
+    let (label, value) = line.split_once(['\t', ':'])?;
+    let label = label.trim().to_lowercase();
+    let words = label.split_whitespace().count();
+    (words > 0 && words <= MAX_LABEL_WORDS).then(|| (label, value.trim().to_lowercase()))
+}
+
+/// Return the date and time at the start of a value, if it starts with one.
+fn leading_date_time(value: &str) -> Option<NaiveDateTime> {
+    let words: Vec<&str> = value.split_whitespace().collect();
+    (1..=words.len().min(MAX_DATE_WORDS))
+        .rev()
+        .find_map(|n| parse_date_time(&words[..n].join(" "), DateLocale::English))
+}
+
+/// Infer the latest stage an opportunity's detail page shows, given the lines of text on the page and the current time
+/// in UTC, and return it with what it was inferred from.
+pub fn page_stage(opportunity: &Opportunity, lines: &[String], now: NaiveDateTime) -> (SolicitationStage, String) {
+    let mut found = vec![(SolicitationStage::Posted, format!("published on {}", opportunity.portal))];
+
+    for line in std::iter::once(&opportunity.title).chain(lines) {
+        let lower = line.to_lowercase();
+        if let Some((notice, stage)) = NOTICES.iter().find(|(notice, _)| lower.contains(notice)) {
+            found.push((*stage, format!("page says \"{notice}\"")));
+        }
+
+        let Some((label, value)) = labelled(line) else {
+            continue;
+        };
+
+        if label.ends_with("status") {
+            if let Some((_, stage)) = STATUS_WORDS.iter().find(|(word, _)| value.contains(word)) {
+                found.push((*stage, format!("{label} is \"{value}\"")));
+            }
+        } else if label.contains("question") && QUESTION_DEADLINE_WORDS.iter().any(|word| label.contains(word)) {
+            if let Some(deadline) = leading_date_time(&value).filter(|deadline| *deadline < now) {
+                found.push((SolicitationStage::QuestionsClosed, format!("questions due at {deadline}")));
+            }
+        }
+    }
+
+    if let Some(award) = opportunity.awards.first() {
+        found.push((SolicitationStage::Awarded, format!("awarded to {}", award.awardee)));
+    }
+
+    if let Some(due_at) = opportunity.due_at.filter(|due_at| *due_at + DUE_GRACE < now) {
+        found.push((SolicitationStage::Closed, format!("due at {due_at}")));
+    }
+
+    if let Some(document) = opportunity
+        .documents
+        .iter()
+        .find(|document| AMENDMENT_WORDS.iter().any(|word| document.name.to_lowercase().contains(word)))
+    {
+        found.push((SolicitationStage::Amended, format!("document \"{}\" published", document.name)));
+    }
+
+    found.into_iter().max_by_key(|(stage, _)| *stage).expect("posted is always found")
+}
+
+/// Move the opportunity at `url` on `portal` to `stage` if it is at an earlier one, journaling the move, and return the
+/// stage it is now at.
+///
+/// Moves are best-effort: failures are logged, and `stage` returned.
+pub async fn advance(
+    log_config: &LogConfig,
+    crawl_id: &str,
+    portal: &str,
+    url: &str,
+    stage: SolicitationStage,
+    reason: &str,
+) -> SolicitationStage {
+    let transition = StageTransition {
+        stage,
+        at: Utc::now(),
+        reason: reason.to_string(),
+    };
+
+    match log_config.opportunities.advance_stage(portal, url, &transition).await {
+        Ok((from, true)) => {
+            info!("{portal} opportunity {url} moved from {from:?} to {stage:?}: {reason}");
+            let event = CrawlEvent::StageChanged {
+                url: url.to_string(),
+                from,
+                to: stage,
+                reason: transition.reason,
+            };
+            log_config.journal.record(crawl_id, event).await;
+            stage
+        }
+        Ok((current, false)) => current.unwrap_or(stage),
+        Err(e) => {
+            warn!("Failed to move opportunity {url} to {stage:?}: {e}");
+            stage
+        }
+    }
+}
+
+/// Infer the stage an opportunity's detail page, given as HTML, shows, record any move to it, and give the opportunity
+/// the stage it is now at.
+pub async fn record_stage(log_config: &LogConfig, client: &Client, opportunity: &mut Opportunity, html: &str) {
+    let (stage, reason) = page_stage(opportunity, &page_lines(html), Utc::now().naive_utc());
+    let stage = advance(log_config, &client.crawl_id, &opportunity.portal, &opportunity.url, stage, &reason).await;
+    opportunity.stage = Some(stage);
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::page_stage,
+        crate::opportunity::{Award, Document, Opportunity, SolicitationStage},
+        chrono::NaiveDate,
+    };
+
+    #[test]
+    fn stages() {
+        let now = NaiveDate::from_ymd_opt(2023, 4, 25).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        let lines = |lines: &[&str]| lines.iter().map(|line| line.to_string()).collect::<Vec<_>>();
+        let opportunity = Opportunity {
+            portal: "Webs".to_string(),
+            title: "Road resurfacing".to_string(),
+            due_at: NaiveDate::from_ymd_opt(2023, 5, 1).unwrap().and_hms_opt(14, 0, 0),
+            ..Opportunity::default()
+        };
+
+        let posted = lines(&["Bid Status\tOpen", "Questions Due: 04/28/2023 5:00 PM", "Scope of work"]);
+        assert_eq!(
+            page_stage(&opportunity, &posted, now),
+            (SolicitationStage::Posted, "published on Webs".to_string())
+        );
+
+        let amended = Opportunity {
+            documents: vec![Document {
+                name: "Addendum No. 1".to_string(),
+                url: "https://example.com/addendum-1.pdf".to_string(),
+            }],
+            ..opportunity.clone()
+        };
+        assert_eq!(page_stage(&amended, &posted, now).0, SolicitationStage::Amended);
+
+        // A passed questions deadline outranks the amendment.
+        let questions_closed = lines(&["Questions Due: 04/20/2023 5:00 PM"]);
+        assert_eq!(
+            page_stage(&amended, &questions_closed, now),
+            (SolicitationStage::QuestionsClosed, "questions due at 2023-04-20 17:00:00".to_string())
+        );
+
+        // A due date is given a day's grace.
+        let due = NaiveDate::from_ymd_opt(2023, 5, 2).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        assert_eq!(page_stage(&opportunity, &[], due).0, SolicitationStage::Posted);
+        let later = NaiveDate::from_ymd_opt(2023, 5, 3).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        assert_eq!(page_stage(&opportunity, &[], later).0, SolicitationStage::Closed);
+
+        // Status labels and notices, including in the title.
+        let closed = lines(&["Status: Closed - Under Evaluation"]);
+        assert_eq!(page_stage(&opportunity, &closed, now).0, SolicitationStage::Closed);
+        let awarded = lines(&["Notice of Award", "Bid Status\tClosed"]);
+        assert_eq!(
+            page_stage(&opportunity, &awarded, now),
+            (SolicitationStage::Awarded, "page says \"notice of award\"".to_string())
+        );
+        let cancelled = Opportunity {
+            title: "CANCELLED - This solicitation has been cancelled".to_string(),
+            ..opportunity.clone()
+        };
+        assert_eq!(page_stage(&cancelled, &awarded, now).0, SolicitationStage::Cancelled);
+
+        let awarded = Opportunity {
+            awards: vec![Award {
+                awardee: "Acme Paving LLC".to_string(),
+                ..Award::default()
+            }],
+            ..opportunity.clone()
+        };
+        assert_eq!(
+            page_stage(&awarded, &posted, now),
+            (SolicitationStage::Awarded, "awarded to Acme Paving LLC".to_string())
+        );
+
+        // Descriptions mentioning an award or status aren't labels.
+        let described =
+            lines(&["The contract will be awarded to the lowest responsive bidder, whose status: pending."]);
+        assert_eq!(page_stage(&opportunity, &described, now).0, SolicitationStage::Posted);
+    }
+}
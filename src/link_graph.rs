@@ -0,0 +1,333 @@
+//! Graph of the links crawls follow.
+//!
+//! Whenever an operation emits next requests, each is recorded as a link from the page the operation worked on (the
+//! parent) to the requested URL (the child), with its position among the requests emitted and, where the operation
+//! knows it, the text of the link. Operations that don't work on a page, such as starting a crawl from configured
+//! listing pages, record links without a parent URL, naming only the operation. Links are stored per crawl, keyed by
+//! child URL, so `Admin:TraceLinks` can answer which listing page referenced a given opportunity and which pages a
+//! crawl processed without any link to them. Each link is also stored per child URL, keyed by crawl, so the links to a
+//! URL from every crawl can be read without scanning the table.
+//!
+//! Link writes are best-effort and buffered like the [journal][crate::journal]; call [`flush`][LinkStore::flush] when
+//! an operation completes.
+use {
+    crate::{
+        classification::{Classification, DDB_KEY_CLASSIFICATION},
+        httpext::{aws_err_str, DdbBatchWriter},
+        BoxError,
+    },
+    aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoDbClient},
+    log::*,
+    serde::{Deserialize, Serialize},
+    std::collections::{BTreeSet, HashMap},
+};
+
+const DDB_KEY_CRAWL_ID: &str = "CrawlId";
+const DDB_KEY_REQUEST_ID: &str = "RequestId";
+const DDB_KEY_LINK_CRAWL_ID: &str = "LinkCrawlId";
+const DDB_KEY_PARENT_URL: &str = "ParentUrl";
+const DDB_KEY_PARENT_OPERATION: &str = "ParentOperation";
+const DDB_KEY_CHILD_URL: &str = "ChildUrl";
+const DDB_KEY_LINK_TEXT: &str = "LinkText";
+const DDB_KEY_POSITION: &str = "Position";
+const DDB_KEY_OPERATION: &str = "Operation";
+
+/// Partition key prefix for link items, followed by the crawl id, keeping them apart from request log items in a
+/// shared table.
+const LINK_PARTITION_PREFIX: &str = "Links#";
+
+/// Partition key prefix for the links leading to a URL.
+const REFERRER_PARTITION_PREFIX: &str = "LinksTo#";
+
+/// A link followed from one page to another.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Link {
+    /// The crawl that followed the link.
+    pub crawl_id: String,
+
+    /// The URL of the page the link was found on, if the operation that emitted it worked on one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_url: Option<String>,
+
+    /// The operation that emitted the link.
+    pub parent_operation: String,
+
+    /// The URL the link leads to.
+    pub child_url: String,
+
+    /// The text of the link, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_text: Option<String>,
+
+    /// The position of the link among the requests emitted from the parent page, starting at zero.
+    pub position: usize,
+
+    /// The operation requested for the child URL.
+    pub operation: String,
+}
+
+impl Link {
+    /// Return the DynamoDB item for this link.
+    fn item(&self) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::from([
+            (DDB_KEY_CRAWL_ID.to_string(), AttributeValue::S(format!("{LINK_PARTITION_PREFIX}{}", self.crawl_id))),
+            (
+                DDB_KEY_REQUEST_ID.to_string(),
+                AttributeValue::S(format!(
+                    "{}#{}#{}#{}",
+                    self.child_url,
+                    self.parent_operation,
+                    self.parent_url.as_deref().unwrap_or_default(),
+                    self.position
+                )),
+            ),
+            (DDB_KEY_LINK_CRAWL_ID.to_string(), AttributeValue::S(self.crawl_id.clone())),
+            (DDB_KEY_PARENT_OPERATION.to_string(), AttributeValue::S(self.parent_operation.clone())),
+            (DDB_KEY_CHILD_URL.to_string(), AttributeValue::S(self.child_url.clone())),
+            (DDB_KEY_POSITION.to_string(), AttributeValue::N(self.position.to_string())),
+            (DDB_KEY_OPERATION.to_string(), AttributeValue::S(self.operation.clone())),
+            (DDB_KEY_CLASSIFICATION.to_string(), AttributeValue::S(Classification::CrawlMetadata.to_string())),
+        ]);
+
+        if let Some(parent_url) = self.parent_url.as_ref() {
+            item.insert(DDB_KEY_PARENT_URL.to_string(), AttributeValue::S(parent_url.clone()));
+        }
+
+        if let Some(link_text) = self.link_text.as_ref() {
+            item.insert(DDB_KEY_LINK_TEXT.to_string(), AttributeValue::S(link_text.clone()));
+        }
+
+        item
+    }
+
+    /// Return the DynamoDB item for this link in the partition of the links leading to its child URL.
+    fn referrer_item(&self) -> HashMap<String, AttributeValue> {
+        let mut item = self.item();
+        item.insert(
+            DDB_KEY_CRAWL_ID.to_string(),
+            AttributeValue::S(format!("{REFERRER_PARTITION_PREFIX}{}", self.child_url)),
+        );
+        item.insert(
+            DDB_KEY_REQUEST_ID.to_string(),
+            AttributeValue::S(format!(
+                "{}#{}#{}#{}",
+                self.crawl_id,
+                self.parent_operation,
+                self.parent_url.as_deref().unwrap_or_default(),
+                self.position
+            )),
+        );
+        item
+    }
+
+    /// Read a link from its DynamoDB item, returning `None` if the item is incomplete.
+    fn from_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        let string = |name: &str| item.get(name).and_then(|value| value.as_s().ok()).cloned();
+
+        Some(Self {
+            crawl_id: string(DDB_KEY_LINK_CRAWL_ID)?,
+            parent_url: string(DDB_KEY_PARENT_URL),
+            parent_operation: string(DDB_KEY_PARENT_OPERATION)?,
+            child_url: string(DDB_KEY_CHILD_URL)?,
+            link_text: string(DDB_KEY_LINK_TEXT),
+            position: item.get(DDB_KEY_POSITION).and_then(|value| value.as_n().ok()).and_then(|n| n.parse().ok())?,
+            operation: string(DDB_KEY_OPERATION).unwrap_or_default(),
+        })
+    }
+}
+
+/// Storage for the link graph in DynamoDB.
+#[derive(Clone, Debug)]
+pub struct LinkStore {
+    ddb_client: DynamoDbClient,
+    writer: DdbBatchWriter,
+}
+
+impl LinkStore {
+    /// Create a link store using the given table.
+    pub fn new<S: Into<String>>(ddb_client: DynamoDbClient, table_name: S) -> Self {
+        Self {
+            writer: DdbBatchWriter::new(ddb_client.clone(), table_name),
+            ddb_client,
+        }
+    }
+
+    /// Return the name of the table links are stored in.
+    pub fn table_name(&self) -> &str {
+        self.writer.table_name()
+    }
+
+    /// Record a link. Failures are logged rather than failing the operation that emitted it.
+    pub async fn record(&self, link: &Link) {
+        for item in [link.item(), link.referrer_item()] {
+            if let Err(e) = self.writer.put(item).await {
+                warn!("Failed to record link to {} for crawl {}: {e}", link.child_url, link.crawl_id);
+            }
+        }
+    }
+
+    /// Write all buffered links.
+    pub async fn flush(&self) -> Result<(), BoxError> {
+        self.writer.flush().await
+    }
+
+    /// Return the links recorded for the given crawl, optionally only those leading to `child_url`.
+    pub async fn links(&self, crawl_id: &str, child_url: Option<&str>) -> Result<Vec<Link>, BoxError> {
+        let table_name = self.writer.table_name();
+        let mut links = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let mut query = self
+                .ddb_client
+                .query()
+                .table_name(table_name)
+                .key_condition_expression("#pk = :pk")
+                .expression_attribute_names("#pk", DDB_KEY_CRAWL_ID)
+                .expression_attribute_values(":pk", AttributeValue::S(format!("{LINK_PARTITION_PREFIX}{crawl_id}")))
+                .set_exclusive_start_key(start_key);
+            if let Some(child_url) = child_url {
+                query = query
+                    .key_condition_expression("#pk = :pk AND begins_with(#sk, :prefix)")
+                    .expression_attribute_names("#sk", DDB_KEY_REQUEST_ID)
+                    .expression_attribute_values(":prefix", AttributeValue::S(format!("{child_url}#")));
+            }
+
+            let result = query.send().await;
+            let output = match result {
+                Ok(output) => output,
+                Err(e) => {
+                    error!("Query links of crawl {crawl_id}: {}", aws_err_str(&e));
+                    return Err(e.into());
+                }
+            };
+
+            links.extend(output.items().iter().filter_map(Link::from_item));
+
+            start_key = output.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(links);
+            }
+        }
+    }
+
+    /// Return the links leading to `child_url` in any crawl.
+    pub async fn referrers(&self, child_url: &str) -> Result<Vec<Link>, BoxError> {
+        let table_name = self.writer.table_name();
+        let mut links = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .ddb_client
+                .query()
+                .table_name(table_name)
+                .key_condition_expression("#pk = :pk")
+                .expression_attribute_names("#pk", DDB_KEY_CRAWL_ID)
+                .expression_attribute_values(
+                    ":pk",
+                    AttributeValue::S(format!("{REFERRER_PARTITION_PREFIX}{child_url}")),
+                )
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await;
+            let output = match result {
+                Ok(output) => output,
+                Err(e) => {
+                    error!("Query links to {child_url}: {}", aws_err_str(&e));
+                    return Err(e.into());
+                }
+            };
+
+            links.extend(output.items().iter().filter_map(Link::from_item));
+
+            start_key = output.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(links);
+            }
+        }
+    }
+}
+
+/// Return the pages no link leads to, other than the given entry points.
+///
+/// For a crawl, `pages` are the URLs its operations worked on and `entry_points` the URLs it was started from; any
+/// other page without a link to it was reached some other way, such as a hand-sent request.
+pub fn unlinked_pages<'a>(
+    links: &[Link],
+    pages: impl IntoIterator<Item = &'a str>,
+    entry_points: &[&str],
+) -> BTreeSet<String> {
+    let linked: BTreeSet<&str> = links.iter().map(|link| link.child_url.as_str()).collect();
+    pages
+        .into_iter()
+        .filter(|page| !linked.contains(page) && !entry_points.contains(page))
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unlinked_pages, Link};
+
+    fn link(parent_url: &str, child_url: &str, position: usize) -> Link {
+        Link {
+            crawl_id: "crawl".to_string(),
+            parent_url: Some(parent_url.to_string()),
+            parent_operation: "Webs:FetchOpportunityListingPage".to_string(),
+            child_url: child_url.to_string(),
+            link_text: Some("Bid 1".to_string()),
+            position,
+            operation: "Webs:FetchOpportunityDetailPage".to_string(),
+        }
+    }
+
+    #[test]
+    fn link_items() {
+        let link = link("https://example.gov/bids?page=1", "https://example.gov/bid/1", 3);
+        let item = link.item();
+        assert_eq!(item["CrawlId"].as_s().unwrap(), "Links#crawl");
+        assert_eq!(
+            item["RequestId"].as_s().unwrap(),
+            "https://example.gov/bid/1#Webs:FetchOpportunityListingPage#https://example.gov/bids?page=1#3"
+        );
+        assert_eq!(item["Position"].as_n().unwrap(), "3");
+        assert_eq!(item["Classification"].as_s().unwrap(), "CrawlMetadata");
+        assert_eq!(Link::from_item(&item), Some(link.clone()));
+
+        let item = link.referrer_item();
+        assert_eq!(item["CrawlId"].as_s().unwrap(), "LinksTo#https://example.gov/bid/1");
+        assert_eq!(
+            item["RequestId"].as_s().unwrap(),
+            "crawl#Webs:FetchOpportunityListingPage#https://example.gov/bids?page=1#3"
+        );
+        assert_eq!(Link::from_item(&item), Some(link.clone()));
+
+        let entry = Link {
+            parent_url: None,
+            link_text: None,
+            ..link
+        };
+        let item = entry.item();
+        assert!(!item.contains_key("ParentUrl") && !item.contains_key("LinkText"));
+        assert_eq!(Link::from_item(&item), Some(entry));
+    }
+
+    #[test]
+    fn unlinked() {
+        let links = vec![
+            link("https://example.gov/bids", "https://example.gov/bid/1", 0),
+            link("https://example.gov/bids", "https://example.gov/bids?page=2", 1),
+        ];
+        let pages = [
+            "https://example.gov/bids",
+            "https://example.gov/bid/1",
+            "https://example.gov/bids?page=2",
+            "https://example.gov/bid/7",
+        ];
+
+        let unlinked = unlinked_pages(&links, pages, &["https://example.gov/bids"]);
+        assert_eq!(unlinked.into_iter().collect::<Vec<_>>(), vec!["https://example.gov/bid/7"]);
+    }
+}
@@ -0,0 +1,395 @@
+//! Request/response types for ingesting the email portals send.
+//!
+//! Some portals only push updates by email. Messages sent to GovScout are received by SES and stored, as raw MIME, in
+//! an S3 bucket; the `Mail:Ingest` operation reads the messages stored under the bucket and prefix given as its URL
+//! (`s3://bucket/prefix/`), [parses][MailMessage] them, and hands each to the first extractor configured for its
+//! sender. An extractor turns a message into crawl requests for the pages it links to and, when the message carries
+//! schema.org structured data, an opportunity update. Messages are deleted once they have been handled.
+//!
+//! Extractors are read from the `Mail` subsystem's configuration document. `From` lists sender addresses, or domains
+//! prefixed with `@`; `Links` lists the URL prefixes of the pages to fetch, each with `Operation`:
+//!
+//! ```json
+//! {
+//!     "Extractors": [{
+//!         "Id": "Olympia",
+//!         "Portal": "Rules",
+//!         "From": ["bids@olympiawa.gov", "@notices.olympiawa.gov"],
+//!         "Links": ["https://bids.example.gov/bid/"],
+//!         "Operation": "Rules:Extract"
+//!     }]
+//! }
+//! ```
+//!
+//! Portals whose notices need more than this, such as a login before their pages can be fetched, use the parsing and
+//! storage functions here from an operation of their own.
+mod mime;
+
+pub use mime::{MailAttachment, MailMessage};
+
+use {
+    crate::{
+        closure,
+        config::load_subsystem_config,
+        httpext::{log_aws_err, LogConfig},
+        metrics,
+        opportunity::{DateLocale, Opportunity},
+        shapes::{NextRequest, Operation, Request, Response, SUBSYS_MAIL},
+        soup::{parse_html_str, structured_data::structured_data},
+        BoxError,
+    },
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    reqwest::Url,
+    serde::{Deserialize, Serialize},
+    std::{
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+const OP_INGEST: &str = "Ingest";
+
+/// Version of the extractors. Bump it whenever a change alters what is extracted.
+const PARSER_VERSION: u32 = 1;
+
+const METRIC_MAIL_MESSAGES: &str = "MailMessages";
+const DIMENSION_OUTCOME: &str = "Outcome";
+const OUTCOME_EXTRACTED: &str = "Extracted";
+const OUTCOME_UNMATCHED: &str = "Unmatched";
+const OUTCOME_FAILED: &str = "Failed";
+
+/// The most messages read by one operation, so a backlog is worked through over several runs rather than overrunning
+/// the Lambda deadline.
+const MAX_MESSAGES: i32 = 100;
+
+/// Possible operations for email ingestion.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum MailOperation {
+    /// Handle the messages stored under the `s3://bucket/prefix` URL.
+    Ingest,
+}
+
+impl FromStr for MailOperation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            OP_INGEST => Ok(Self::Ingest),
+            _ => Err(format!("Unknown operation: {value}")),
+        }
+    }
+}
+
+impl Display for MailOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.operation())
+    }
+}
+
+impl MailOperation {
+    /// Handle a request.
+    pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+        match self {
+            Self::Ingest => ingest(log_config, req, context).await,
+        }
+    }
+
+    /// Return the operation name within the subsystem.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::Ingest => OP_INGEST,
+        }
+    }
+}
+
+/// The `Mail` configuration document.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+struct MailConfig {
+    /// The extractors, tried in order.
+    extractors: Vec<MailExtractor>,
+}
+
+/// How to handle the messages a portal sends.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct MailExtractor {
+    /// Short identifier for the extractor, used in parser names, e.g. `Olympia`.
+    pub id: String,
+
+    /// The portal opportunity updates are attributed to.
+    pub portal: String,
+
+    /// The sender addresses handled, or domains prefixed with `@`, matched ignoring case.
+    pub from: Vec<String>,
+
+    /// The URL prefixes of the links to fetch, matched ignoring case.
+    #[serde(default)]
+    pub links: Vec<String>,
+
+    /// The operation to fetch each link with.
+    pub operation: Operation,
+}
+
+/// What a message asks of the crawler.
+#[derive(Clone, Debug)]
+pub enum MailAction {
+    /// Fetch a page the message links to.
+    Fetch {
+        /// The operation to fetch it with.
+        operation: Operation,
+
+        /// The URL of the page.
+        url: String,
+    },
+
+    /// Record an opportunity the message describes.
+    Update(Box<Opportunity>),
+}
+
+/// A message read from S3.
+#[derive(Clone, Debug)]
+pub struct StoredMessage {
+    /// The key of the stored message.
+    pub key: String,
+
+    /// The parsed message.
+    pub message: MailMessage,
+}
+
+impl MailExtractor {
+    /// Indicates whether this extractor handles messages from `address`.
+    pub fn matches(&self, address: &str) -> bool {
+        let address = address.to_ascii_lowercase();
+        self.from.iter().any(|from| {
+            let from = from.to_ascii_lowercase();
+            if from.starts_with('@') {
+                address.ends_with(&from)
+            } else {
+                address == from
+            }
+        })
+    }
+
+    /// Return what a message asks of the crawler: a fetch of each distinct link it has to one of
+    /// [`links`][Self::links], then an update if its HTML describes an opportunity in structured data. The update's URL
+    /// is the first link.
+    pub fn extract(&self, message: &MailMessage) -> Vec<MailAction> {
+        let prefixes: Vec<String> = self.links.iter().map(|link| link.to_ascii_lowercase()).collect();
+        let mut urls: Vec<String> = vec![];
+        for url in message.bodies().flat_map(find_urls) {
+            if prefixes.iter().any(|prefix| url.to_ascii_lowercase().starts_with(prefix)) && !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+
+        let mut actions: Vec<MailAction> = urls
+            .iter()
+            .map(|url| MailAction::Fetch {
+                operation: self.operation,
+                url: url.clone(),
+            })
+            .collect();
+
+        let page_url = urls.first().and_then(|url| Url::parse(url).ok());
+        if let (Some(html), Some(page_url)) = (message.html.as_deref(), page_url) {
+            let items = structured_data(&parse_html_str(html));
+            if let Some(opportunity) = Opportunity::from_structured_data(&items, &page_url, DateLocale::English) {
+                let opportunity = Opportunity {
+                    portal: self.portal.clone(),
+                    ..opportunity
+                };
+                actions.push(MailAction::Update(Box::new(opportunity.with_parser(self.parser_name(), PARSER_VERSION))));
+            }
+        }
+
+        actions
+    }
+
+    /// Return the name of the extractor as a parser, e.g. `Mail:Olympia`.
+    fn parser_name(&self) -> String {
+        format!("{SUBSYS_MAIL}:{}", self.id)
+    }
+}
+
+/// Return the `http` and `https` URLs in a message body, in order. Entities in HTML attributes are decoded.
+fn find_urls(body: &str) -> Vec<String> {
+    let mut urls = vec![];
+    let mut rest = body;
+
+    while let Some(start) = rest.find("http") {
+        let candidate = &rest[start..];
+        let end = candidate.find(|c: char| c.is_whitespace() || "\"'<>".contains(c)).unwrap_or(candidate.len());
+        let url = &candidate[..end];
+        if url.starts_with("https://") || url.starts_with("http://") {
+            urls.push(url.trim_end_matches(['.', ',', ')', ';']).replace("&amp;", "&"));
+        }
+        rest = &candidate[end.max(4)..];
+    }
+
+    urls
+}
+
+/// Split an `s3://bucket/prefix` URL into its bucket and key prefix.
+pub fn parse_location(url: &Url) -> Result<(String, String), BoxError> {
+    match (url.scheme(), url.host_str()) {
+        ("s3", Some(bucket)) if !bucket.is_empty() => {
+            Ok((bucket.to_string(), url.path().trim_start_matches('/').to_string()))
+        }
+        _ => Err(format!("Email must be read from an s3://bucket/prefix URL, not {url}").into()),
+    }
+}
+
+/// Read and parse up to 100 of the messages stored under `prefix` in `bucket`.
+pub async fn read_messages(log_config: &LogConfig, bucket: &str, prefix: &str) -> Result<Vec<StoredMessage>, BoxError> {
+    let output = log_aws_err(
+        log_config.s3_client.list_objects_v2().bucket(bucket).prefix(prefix).max_keys(MAX_MESSAGES).send().await,
+        &format!("ListObjectsV2 s3://{bucket}/{prefix}"),
+    )?;
+
+    let mut messages = vec![];
+    for key in output.contents().iter().filter_map(|object| object.key()) {
+        let object = log_aws_err(
+            log_config.s3_client.get_object().bucket(bucket).key(key).send().await,
+            &format!("GetObject s3://{bucket}/{key}"),
+        )?;
+        let body = object.body.collect().await?.into_bytes();
+        messages.push(StoredMessage {
+            key: key.to_string(),
+            message: MailMessage::parse(&body),
+        });
+    }
+
+    Ok(messages)
+}
+
+/// Delete a message once it has been handled.
+pub async fn delete_message(log_config: &LogConfig, bucket: &str, key: &str) -> Result<(), BoxError> {
+    log_aws_err(
+        log_config.s3_client.delete_object().bucket(bucket).key(key).send().await,
+        &format!("DeleteObject s3://{bucket}/{key}"),
+    )?;
+    Ok(())
+}
+
+/// Handle the messages stored under the request's `s3://bucket/prefix` URL with the configured extractors.
+///
+/// Messages without an extractor for their sender are logged and deleted, as no later run would handle them either.
+async fn ingest(log_config: LogConfig, req: Request, _context: Context) -> Result<Response, LambdaError> {
+    let Some(url_str) = req.url.as_deref() else {
+        return Err("Ingest requires an s3://bucket/prefix URL".into());
+    };
+    let (bucket, prefix) = parse_location(&Url::parse(url_str)?)?;
+
+    let config: MailConfig = load_subsystem_config(&log_config, SUBSYS_MAIL).await?;
+    let crawl_id = req.crawl.crawl_id.clone().unwrap_or_default();
+    let mut next_requests = vec![];
+
+    for stored in read_messages(&log_config, &bucket, &prefix).await? {
+        let from = stored.message.from_address().unwrap_or_default();
+        let outcome = match config.extractors.iter().find(|extractor| extractor.matches(&from)) {
+            Some(extractor) => {
+                let actions = extractor.extract(&stored.message);
+                info!(
+                    "Extractor {} found {} action(s) in message {} from {from}",
+                    extractor.id,
+                    actions.len(),
+                    stored.key
+                );
+                for action in actions {
+                    match action {
+                        MailAction::Fetch {
+                            operation,
+                            url,
+                        } => next_requests.push(NextRequest {
+                            operation,
+                            url: Some(url),
+                            link_text: stored.message.subject().map(str::to_string),
+                            crawl: req.crawl.child(),
+                            delay_seconds: 0,
+                        }),
+                        MailAction::Update(opportunity) => {
+                            let opportunity = opportunity.with_tenant(req.crawl.tenant.as_deref());
+                            closure::record_sighting(&log_config, &crawl_id, &opportunity).await;
+                            info!("{} opportunity: {}", opportunity.portal, serde_json::to_string(&opportunity)?);
+                        }
+                    }
+                }
+                OUTCOME_EXTRACTED
+            }
+            None => {
+                warn!("No extractor for message {} from {from:?}; dropping it", stored.key);
+                OUTCOME_UNMATCHED
+            }
+        };
+
+        let outcome = match delete_message(&log_config, &bucket, &stored.key).await {
+            Ok(()) => outcome,
+            Err(e) => {
+                warn!("Failed to delete message {}: {e}", stored.key);
+                OUTCOME_FAILED
+            }
+        };
+        metrics::registry().increment(METRIC_MAIL_MESSAGES, &[(DIMENSION_OUTCOME, outcome)], 1);
+    }
+
+    Ok(Response {
+        next_requests,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{parse_location, MailAction, MailConfig, MailMessage},
+        crate::config::parse_config,
+        reqwest::Url,
+    };
+
+    #[test]
+    fn extraction() {
+        let config: MailConfig = parse_config(
+            "Mail",
+            r#"{"Extractors": [{"Id": "Olympia", "Portal": "Rules", "From": ["@OlympiaWA.gov"],
+                "Links": ["https://bids.example.gov/bid/"], "Operation": "Rules:Extract"}]}"#,
+        )
+        .unwrap();
+        let extractor = &config.extractors[0];
+        assert!(extractor.matches("bids@olympiawa.gov"));
+        assert!(!extractor.matches("bids@example.gov"));
+
+        let message = MailMessage::parse(
+            b"From: Bids <bids@olympiawa.gov>\r\n\
+            Content-Type: text/html\r\n\
+            \r\n\
+            <div itemscope itemtype=\"https://schema.org/Demand\"><h1 itemprop=\"name\">Street Paving</h1></div>\r\n\
+            <a href=\"https://bids.example.gov/bid/22-015?a=1&amp;b=2\">Street Paving</a>.\r\n\
+            <a href=\"https://bids.example.gov/bid/22-015?a=1&amp;b=2\">Again</a>\r\n\
+            <a href=\"https://olympiawa.gov/unsubscribe\">Unsubscribe</a>\r\n",
+        );
+        let actions = extractor.extract(&message);
+        assert_eq!(actions.len(), 2);
+        let MailAction::Fetch {
+            operation,
+            url,
+        } = &actions[0]
+        else {
+            panic!("Expected a fetch, got {:?}", actions[0]);
+        };
+        assert_eq!(operation.to_string(), "Rules:Extract");
+        assert_eq!(url, "https://bids.example.gov/bid/22-015?a=1&b=2");
+        let MailAction::Update(opportunity) = &actions[1] else {
+            panic!("Expected an update, got {:?}", actions[1]);
+        };
+        assert_eq!((opportunity.portal.as_str(), opportunity.title.as_str()), ("Rules", "Street Paving"));
+        assert_eq!(opportunity.url, "https://bids.example.gov/bid/22-015?a=1&b=2");
+        assert_eq!(opportunity.parsed_by.as_ref().map(|parsed_by| parsed_by.parser.as_str()), Some("Mail:Olympia"));
+
+        assert!(extractor.extract(&MailMessage::parse(b"From: bids@olympiawa.gov\n\nNo links.\n")).is_empty());
+
+        let (bucket, prefix) = parse_location(&Url::parse("s3://govscout-inbound/mail/").unwrap()).unwrap();
+        assert_eq!((bucket.as_str(), prefix.as_str()), ("govscout-inbound", "mail/"));
+        assert!(parse_location(&Url::parse("https://govscout-inbound/mail/").unwrap()).is_err());
+    }
+}
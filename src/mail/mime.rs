@@ -0,0 +1,410 @@
+//! A small MIME parser for the messages portals send.
+//!
+//! Only what bid notices use is supported: folded headers with RFC 2047 encoded words, nested multiparts, the base64
+//! and quoted-printable transfer encodings, and UTF-8 or Latin-1 text. Anything unrecognized is kept as an attachment
+//! rather than rejected.
+use base64::prelude::*;
+
+const HEADER_CONTENT_TYPE: &str = "Content-Type";
+const HEADER_CONTENT_TRANSFER_ENCODING: &str = "Content-Transfer-Encoding";
+const HEADER_CONTENT_DISPOSITION: &str = "Content-Disposition";
+const HEADER_FROM: &str = "From";
+const HEADER_SUBJECT: &str = "Subject";
+
+/// The content type assumed for parts that don't state one.
+const DEFAULT_CONTENT_TYPE: &str = "text/plain";
+
+/// How deeply multiparts may nest before the rest is kept as an attachment.
+const MAX_NESTING: usize = 8;
+
+/// A parsed email message.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MailMessage {
+    /// The message's headers in order, with folded lines joined and encoded words decoded.
+    pub headers: Vec<(String, String)>,
+
+    /// The first plain text part.
+    pub text: Option<String>,
+
+    /// The first HTML part.
+    pub html: Option<String>,
+
+    /// The remaining parts: files attached to the message, and any parts of types that aren't text.
+    pub attachments: Vec<MailAttachment>,
+}
+
+/// A file attached to an email message.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MailAttachment {
+    /// The attachment's file name, if the message gives one.
+    pub filename: Option<String>,
+
+    /// The attachment's content type, e.g. `application/pdf`.
+    pub content_type: String,
+
+    /// The decoded content.
+    pub body: Vec<u8>,
+}
+
+/// A part's `Content-Type` or `Content-Disposition`: its value, lowercased, and its parameters.
+struct HeaderValue {
+    value: String,
+    params: Vec<(String, String)>,
+}
+
+impl HeaderValue {
+    fn parse(header: &str) -> Self {
+        let mut pieces = split_params(header).into_iter();
+        let value = pieces.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let params = pieces
+            .filter_map(|piece| {
+                let (name, value) = piece.split_once('=')?;
+                Some((name.trim().to_ascii_lowercase(), value.trim().trim_matches('"').to_string()))
+            })
+            .collect();
+
+        Self {
+            value,
+            params,
+        }
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(param, _)| param == name).map(|(_, value)| value.as_str())
+    }
+}
+
+impl MailMessage {
+    /// Parse a raw message, as SES stores it.
+    pub fn parse(raw: &[u8]) -> Self {
+        let mut message = Self::default();
+        let (headers, body) = split_headers(raw);
+        message.headers = headers.clone();
+        message.add_part(&headers, body, 0);
+        message
+    }
+
+    /// Return the value of the first header named `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.headers, name)
+    }
+
+    /// Return the subject.
+    pub fn subject(&self) -> Option<&str> {
+        self.header(HEADER_SUBJECT)
+    }
+
+    /// Return the sender's address, lowercased, e.g. `bids@example.gov`.
+    pub fn from_address(&self) -> Option<String> {
+        let from = self.header(HEADER_FROM)?;
+        let address = match (from.rfind('<'), from.rfind('>')) {
+            (Some(start), Some(end)) if start < end => &from[start + 1..end],
+            _ => from,
+        };
+
+        let address = address.trim().to_ascii_lowercase();
+        address.contains('@').then_some(address)
+    }
+
+    /// Return the text of the message's plain text and HTML parts.
+    pub fn bodies(&self) -> impl Iterator<Item = &str> {
+        self.text.iter().chain(self.html.iter()).map(String::as_str)
+    }
+
+    fn add_part(&mut self, headers: &[(String, String)], body: &[u8], depth: usize) {
+        let content_type = HeaderValue::parse(header(headers, HEADER_CONTENT_TYPE).unwrap_or(DEFAULT_CONTENT_TYPE));
+        let disposition = header(headers, HEADER_CONTENT_DISPOSITION).map(HeaderValue::parse);
+
+        if content_type.value.starts_with("multipart/") && depth < MAX_NESTING {
+            if let Some(boundary) = content_type.param("boundary") {
+                for part in split_multipart(body, boundary) {
+                    let (headers, body) = split_headers(part);
+                    self.add_part(&headers, body, depth + 1);
+                }
+                return;
+            }
+        }
+
+        let encoding =
+            header(headers, HEADER_CONTENT_TRANSFER_ENCODING).unwrap_or_default().trim().to_ascii_lowercase();
+        let body = match encoding.as_str() {
+            "base64" => decode_base64(body),
+            "quoted-printable" => decode_quoted_printable(body),
+            _ => body.to_vec(),
+        };
+
+        let filename = disposition
+            .as_ref()
+            .and_then(|disposition| disposition.param("filename"))
+            .or_else(|| content_type.param("name"))
+            .map(decode_encoded_words);
+        let attached = filename.is_some() || disposition.is_some_and(|disposition| disposition.value == "attachment");
+
+        if !attached {
+            let text = || decode_charset(&body, content_type.param("charset"));
+            match content_type.value.as_str() {
+                "text/plain" if self.text.is_none() => {
+                    self.text = Some(text());
+                    return;
+                }
+                "text/html" if self.html.is_none() => {
+                    self.html = Some(text());
+                    return;
+                }
+                _ => (),
+            }
+        }
+
+        self.attachments.push(MailAttachment {
+            filename,
+            content_type: content_type.value,
+            body,
+        });
+    }
+}
+
+/// Return the value of the first header named `name`, ignoring case.
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers.iter().find(|(header, _)| header.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+}
+
+/// Split a message or part into its headers and body. Folded header lines are joined and encoded words decoded.
+fn split_headers(raw: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+    // A part may start with the blank line, having no headers at all.
+    if let Some(body) = raw.strip_prefix(b"\r\n").or_else(|| raw.strip_prefix(b"\n")) {
+        return (vec![], body);
+    }
+
+    let (head, body) =
+        match find(raw, b"\r\n\r\n").map(|end| (end, 4)).or_else(|| find(raw, b"\n\n").map(|end| (end, 2))) {
+            Some((end, separator)) => (&raw[..end], &raw[end + separator..]),
+            None => (raw, &raw[raw.len()..]),
+        };
+
+    let mut headers: Vec<(String, String)> = vec![];
+    for line in String::from_utf8_lossy(head).lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    for (_, value) in headers.iter_mut() {
+        *value = decode_encoded_words(value);
+    }
+
+    (headers, body)
+}
+
+/// Split a multipart body into its parts, dropping the preamble and epilogue.
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{boundary}");
+    let mut parts = vec![];
+    let mut start = None;
+    let mut offset = 0;
+
+    for line in body.split_inclusive(|&b| b == b'\n') {
+        let trimmed = line.trim_ascii_end();
+        if trimmed.starts_with(delimiter.as_bytes()) {
+            if let Some(start) = start {
+                // The line break before the delimiter belongs to it.
+                let mut end = offset;
+                if body[..end].ends_with(b"\r\n") {
+                    end -= 2;
+                } else if body[..end].ends_with(b"\n") {
+                    end -= 1;
+                }
+                parts.push(&body[start..end.max(start)]);
+            }
+
+            if trimmed[delimiter.len()..].starts_with(b"--") {
+                return parts;
+            }
+            start = Some(offset + line.len());
+        }
+        offset += line.len();
+    }
+
+    if let Some(start) = start {
+        parts.push(&body[start..]);
+    }
+
+    parts
+}
+
+/// Return the position of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Split a header value on semicolons outside of quotes.
+fn split_params(header: &str) -> Vec<&str> {
+    let mut pieces = vec![];
+    let mut quoted = false;
+    let mut start = 0;
+
+    for (i, c) in header.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                pieces.push(&header[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+
+    pieces.push(&header[start..]);
+    pieces
+}
+
+/// Decode base64 content, ignoring the line breaks it is wrapped with.
+fn decode_base64(body: &[u8]) -> Vec<u8> {
+    let compact: Vec<u8> = body.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect();
+    BASE64_STANDARD.decode(&compact).unwrap_or(compact)
+}
+
+/// Undo quoted-printable encoding: soft line breaks (`=` at the end of a line) are removed and `=XX` escapes decoded.
+fn decode_quoted_printable(body: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(body.len());
+    let mut i = 0;
+
+    while i < body.len() {
+        if body[i] == b'=' {
+            if body[i + 1..].starts_with(b"\r\n") {
+                i += 3;
+                continue;
+            }
+            if body[i + 1..].starts_with(b"\n") {
+                i += 2;
+                continue;
+            }
+
+            let hex = body.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+            if let Some(byte) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+
+        decoded.push(body[i]);
+        i += 1;
+    }
+
+    decoded
+}
+
+/// Decode text in its charset: UTF-8 (the default), or Latin-1 and its Windows superset, which are read byte for
+/// byte.
+fn decode_charset(body: &[u8], charset: Option<&str>) -> String {
+    match charset.map(str::to_ascii_lowercase).as_deref() {
+        Some("iso-8859-1" | "latin1" | "windows-1252" | "us-ascii") => body.iter().map(|&b| b as char).collect(),
+        _ => String::from_utf8_lossy(body).into_owned(),
+    }
+}
+
+/// Decode the RFC 2047 encoded words (`=?charset?B?...?=` or `=?charset?Q?...?=`) in a header value.
+fn decode_encoded_words(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    let mut after_word = false;
+
+    while let Some(start) = rest.find("=?") {
+        let Some((word, len)) = encoded_word(&rest[start..]) else {
+            decoded.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            after_word = false;
+            continue;
+        };
+
+        // Whitespace between adjacent encoded words is dropped.
+        let between = &rest[..start];
+        if !(after_word && between.trim().is_empty()) {
+            decoded.push_str(between);
+        }
+        decoded.push_str(&word);
+        rest = &rest[start + len..];
+        after_word = true;
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Decode the encoded word at the start of `text`, returning it and its length in `text`.
+fn encoded_word(text: &str) -> Option<(String, usize)> {
+    let mut fields = text[2..].splitn(3, '?');
+    let charset = fields.next()?;
+    let encoding = fields.next()?;
+    let rest = fields.next()?;
+    let end = rest.find("?=")?;
+    let payload = &rest[..end];
+    let len = 2 + charset.len() + 1 + encoding.len() + 1 + end + 2;
+
+    let bytes = match encoding {
+        "B" | "b" => BASE64_STANDARD.decode(payload).ok()?,
+        "Q" | "q" => decode_quoted_printable(payload.replace('_', " ").as_bytes()),
+        _ => return None,
+    };
+
+    Some((decode_charset(&bytes, Some(charset)), len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MailMessage;
+
+    #[test]
+    fn mime_messages() {
+        const MESSAGE: &str = "From: \"City of Olympia\" <Bids@OlympiaWA.gov>\r\n\
+            Subject: =?UTF-8?Q?New_bid:_Street_Paving_?= =?UTF-8?B?4oCUIDIyLTAxNQ==?=\r\n\
+            Content-Type: multipart/mixed;\r\n\
+            \tboundary=\"outer\"\r\n\
+            \r\n\
+            This is a multi-part message in MIME format.\r\n\
+            --outer\r\n\
+            Content-Type: multipart/alternative; boundary=inner\r\n\
+            \r\n\
+            --inner\r\n\
+            Content-Type: text/plain; charset=utf-8\r\n\
+            Content-Transfer-Encoding: quoted-printable\r\n\
+            \r\n\
+            Bids are due Nov=C3=A9mber 30 at https://olympiawa.gov/bids/22-=\r\n\
+            015\r\n\
+            --inner\r\n\
+            Content-Type: text/html; charset=iso-8859-1\r\n\
+            Content-Transfer-Encoding: base64\r\n\
+            \r\n\
+            PHA+Tm92\r\n\
+            6W1icmU8L3A+\r\n\
+            --inner--\r\n\
+            --outer\r\n\
+            Content-Type: application/pdf; name=\"plans.pdf\"\r\n\
+            Content-Disposition: attachment\r\n\
+            Content-Transfer-Encoding: base64\r\n\
+            \r\n\
+            JVBERi0=\r\n\
+            --outer--\r\n\
+            Epilogue\r\n";
+
+        let message = MailMessage::parse(MESSAGE.as_bytes());
+        assert_eq!(message.from_address().as_deref(), Some("bids@olympiawa.gov"));
+        assert_eq!(message.subject(), Some("New bid: Street Paving — 22-015"));
+        assert_eq!(message.text.as_deref(), Some("Bids are due Novémber 30 at https://olympiawa.gov/bids/22-015"));
+        assert_eq!(message.html.as_deref(), Some("<p>Novémbre</p>"));
+        assert_eq!(message.attachments.len(), 1);
+        assert_eq!(message.attachments[0].filename.as_deref(), Some("plans.pdf"));
+        assert_eq!(message.attachments[0].content_type, "application/pdf");
+        assert_eq!(message.attachments[0].body, b"%PDF-");
+
+        let plain = MailMessage::parse(b"From: bids@example.gov\nSubject: Hello\n\nJust text.\n");
+        assert_eq!(plain.text.as_deref(), Some("Just text.\n"));
+        assert_eq!(plain.bodies().collect::<Vec<_>>(), ["Just text.\n"]);
+        assert!(MailMessage::parse(b"From: nobody\n\n").from_address().is_none());
+    }
+}
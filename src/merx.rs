@@ -0,0 +1,342 @@
+//! Request/response types for MERX (merx.com), the Canadian tendering service carrying federal, provincial, and
+//! municipal opportunities.
+//!
+//! MERX needs no login to browse: the open solicitation listing is paged with plain GET requests, and each solicitation
+//! has its own page. Pages are in English or French depending on the `language` query parameter; either is parsed, and
+//! the language is recorded on the opportunity. Solicitation documents are sold to subscribers and are not fetched.
+mod listing;
+mod solicitation;
+
+use {
+    crate::{
+        archive::{ArchivedContent, ArchivedPage},
+        attachment, closure, contacts, health,
+        httpext::{Client, LogConfig, ResponseExt},
+        journal::CrawlEvent,
+        lifecycle,
+        opportunity::Opportunity,
+        quality,
+        shapes::{CrawlParameters, NextRequest, Operation, Request, Response, SUBSYS_MERX},
+        soup::parse_html_str,
+        BoxError,
+    },
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    reqwest::Url,
+    serde::{Deserialize, Serialize},
+    std::{
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+pub(crate) const DEFAULT_MERX_BASE_URL: &str = "https://www.merx.com/";
+const OPEN_SOLICITATIONS_PATH: &str = "public/solicitations/open";
+
+const OP_START_CRAWL: &str = "StartCrawl";
+const OP_FETCH_LISTING_PAGE: &str = "FetchListingPage";
+const OP_FETCH_SOLICITATION_PAGE: &str = "FetchSolicitationPage";
+
+/// Version of this subsystem's parsers. Bump it whenever a parser change alters what is extracted.
+pub(crate) const PARSER_VERSION: u32 = 1;
+
+const PARSER_LISTING: &str = "Merx:Listing";
+const PARSER_SOLICITATION: &str = "Merx:Solicitation";
+
+/// Phrases shown in place of an opportunity the portal no longer publishes; see [`closure`][crate::closure].
+const NOT_FOUND_MARKERS: &[&str] =
+    &["this opportunity is no longer available", "cette opportunité n'est plus disponible"];
+
+/// Possible operations for MERX.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum MerxOperation {
+    /// Start a crawl by fetching the first page of open solicitations, or the listing given as the URL.
+    StartCrawl,
+
+    /// Fetch a subsequent page of open solicitations.
+    FetchListingPage,
+
+    /// Fetch a solicitation page.
+    FetchSolicitationPage,
+}
+
+impl FromStr for MerxOperation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            OP_START_CRAWL => Ok(Self::StartCrawl),
+            OP_FETCH_LISTING_PAGE => Ok(Self::FetchListingPage),
+            OP_FETCH_SOLICITATION_PAGE => Ok(Self::FetchSolicitationPage),
+            _ => Err(format!("Unknown operation: {value}")),
+        }
+    }
+}
+
+impl Display for MerxOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.operation())
+    }
+}
+
+impl MerxOperation {
+    /// Handle a request.
+    pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+        match self {
+            Self::StartCrawl => start_crawl(log_config, req, context).await,
+            Self::FetchListingPage => {
+                let url = req.required_url()?;
+                fetch_listing_page(&log_config, &req.crawl, &context, &url).await.map(|(response, _)| response)
+            }
+            Self::FetchSolicitationPage => fetch_solicitation_page(log_config, req, context).await,
+        }
+    }
+
+    /// Return the operation name within the subsystem.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::StartCrawl => OP_START_CRAWL,
+            Self::FetchListingPage => OP_FETCH_LISTING_PAGE,
+            Self::FetchSolicitationPage => OP_FETCH_SOLICITATION_PAGE,
+        }
+    }
+}
+
+/// Return the URL of the first page of open solicitations.
+fn default_listing_url() -> Url {
+    Url::parse(DEFAULT_MERX_BASE_URL)
+        .and_then(|base| base.join(OPEN_SOLICITATIONS_PATH))
+        .expect("DEFAULT_MERX_BASE_URL and OPEN_SOLICITATIONS_PATH form a valid URL")
+}
+
+/// Start a MERX crawl from the first page of open solicitations, checking the crawl's health as VBS does.
+async fn start_crawl(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let url = match req.url.as_deref() {
+        Some(url) => Url::parse(url)?,
+        None => default_listing_url(),
+    };
+
+    let (response, client) = fetch_listing_page(&log_config, &req.crawl, &context, &url).await?;
+    let solicitations = response
+        .next_requests
+        .iter()
+        .filter(|r| matches!(r.operation, Operation::Merx(MerxOperation::FetchSolicitationPage)))
+        .count();
+
+    health::check_crawl_health(
+        &log_config,
+        SUBSYS_MERX,
+        &client.crawl_id,
+        &client.stats.snapshot(),
+        &client.stats.host_latencies(),
+        solicitations,
+    )
+    .await;
+
+    Ok(response)
+}
+
+/// Fetch a page of open solicitations, returning requests for its solicitations and the next page.
+///
+/// The listing's language carries over to the solicitation pages, since their links keep the session's language.
+async fn fetch_listing_page(
+    log_config: &LogConfig,
+    crawl: &CrawlParameters,
+    context: &Context,
+    url: &Url,
+) -> Result<(Response, Client), BoxError> {
+    let client = crawl.portal_client(log_config, context, SUBSYS_MERX, DEFAULT_MERX_BASE_URL, url)?.build()?;
+
+    let response = match client.get(url.clone()).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch MERX listing: {e}");
+            return Err(e);
+        }
+    };
+
+    let document = parse_html_str(response.text()?);
+    let result = listing::parse_listing_page(&document, response.url());
+    let event = match &result {
+        Ok(urls) => CrawlEvent::ParseSucceeded {
+            url: response.url().to_string(),
+            parser: PARSER_LISTING.to_string(),
+            items: urls.len(),
+        },
+        Err(e) => CrawlEvent::ParseFailed {
+            url: response.url().to_string(),
+            parser: PARSER_LISTING.to_string(),
+            error: e.to_string(),
+        },
+    };
+    log_config.journal.record(&client.crawl_id, event).await;
+
+    let mut next_requests: Vec<NextRequest> = result?
+        .into_iter()
+        .map(|url| NextRequest {
+            operation: Operation::Merx(MerxOperation::FetchSolicitationPage),
+            url: Some(url.to_string()),
+            link_text: None,
+            crawl: crawl.child(),
+            delay_seconds: 0,
+        })
+        .collect();
+
+    next_requests.extend(listing::PAGINATION.continuation(
+        Operation::Merx(MerxOperation::FetchListingPage),
+        &document,
+        response.url(),
+        crawl,
+    ));
+
+    Ok((
+        Response {
+            next_requests,
+        },
+        client,
+    ))
+}
+
+/// Fetch a solicitation page and parse it into the normalized opportunity model.
+async fn fetch_solicitation_page(
+    log_config: LogConfig,
+    req: Request,
+    context: Context,
+) -> Result<Response, LambdaError> {
+    let url = req.required_url()?;
+    let client = req.crawl.portal_client(&log_config, &context, SUBSYS_MERX, DEFAULT_MERX_BASE_URL, &url)?.build()?;
+
+    let response = match client.get(url.clone()).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch MERX solicitation: {e}");
+            return Err(e);
+        }
+    };
+
+    let Some(response) = attachment::html_page(&log_config, &req.crawl, response, Some(url.as_str())).await else {
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    };
+
+    if let Some(reason) =
+        closure::soft_not_found(response.text()?, NOT_FOUND_MARKERS, &req.crawl.locale.not_found_markers)
+    {
+        closure::record_closed(&log_config, &client, SUBSYS_MERX, url.as_str(), &reason).await?;
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    }
+
+    let document = parse_html_str(response.text()?);
+    let result = solicitation::parse_solicitation_page(&document, response.url());
+    let event = match &result {
+        Ok(_) => CrawlEvent::ParseSucceeded {
+            url: response.url().to_string(),
+            parser: PARSER_SOLICITATION.to_string(),
+            items: 1,
+        },
+        Err(e) => CrawlEvent::ParseFailed {
+            url: response.url().to_string(),
+            parser: PARSER_SOLICITATION.to_string(),
+            error: e.to_string(),
+        },
+    };
+    log_config.journal.record(&client.crawl_id, event).await;
+
+    let mut opportunity: Opportunity =
+        result?.with_parser(PARSER_SOLICITATION, PARSER_VERSION).with_tenant(client.tenant.as_deref());
+    contacts::record_contacts(&log_config, &client, &mut opportunity, response.text()?).await;
+    lifecycle::record_stage(&log_config, &client, &mut opportunity, response.text()?).await;
+    quality::record_quality(&log_config, &client, &mut opportunity).await;
+    closure::record_seen(&log_config, &client, &opportunity).await;
+    info!("MERX opportunity: {}", serde_json::to_string(&opportunity)?);
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Parse a capture of a MERX page for the [Internet Archive backfill][crate::archive]: an open solicitation listing,
+/// whose solicitations and next page are returned as links, or a solicitation. Returns `None` for any other page.
+pub(crate) fn parse_archived_page(text: &str, page_url: &Url) -> Option<ArchivedPage> {
+    let document = parse_html_str(text);
+    let path = page_url.path();
+
+    if path.starts_with(&format!("/{OPEN_SOLICITATIONS_PATH}")) {
+        let content = listing::parse_listing_page(&document, page_url).map(|mut urls| {
+            urls.extend(listing::PAGINATION.next_url(&document, page_url));
+            ArchivedContent::Links(urls)
+        });
+
+        return Some(ArchivedPage {
+            parser: PARSER_LISTING.to_string(),
+            parser_version: PARSER_VERSION,
+            content,
+        });
+    }
+
+    if path.contains("/solicitations/") {
+        let content = solicitation::parse_solicitation_page(&document, page_url)
+            .map(|opportunity| ArchivedContent::Opportunity(Box::new(opportunity)));
+
+        return Some(ArchivedPage {
+            parser: PARSER_SOLICITATION.to_string(),
+            parser_version: PARSER_VERSION,
+            content,
+        });
+    }
+
+    None
+}
+
+/// Parse a MERX snapshot for golden-file testing. `parser` is the name of the snapshot directory.
+#[cfg(any(test, feature = "test-utils"))]
+pub fn parse_snapshot(parser: &str, text: &str) -> Result<serde_json::Value, BoxError> {
+    use serde_json::json;
+
+    let base_url = Url::parse(DEFAULT_MERX_BASE_URL)?;
+    let document = parse_html_str(text);
+
+    match parser {
+        "listing" => {
+            let page_url = default_listing_url();
+            let urls = listing::parse_listing_page(&document, &page_url)?;
+            let next_page = listing::PAGINATION.next_url(&document, &page_url);
+
+            Ok(json!({
+                "Solicitations": urls.iter().map(Url::as_str).collect::<Vec<_>>(),
+                "NextPage": next_page.as_ref().map(Url::as_str),
+            }))
+        }
+        "solicitation" => {
+            let page_url = base_url.join("public/solicitations/Solicitation/0")?;
+            Ok(serde_json::to_value(solicitation::parse_solicitation_page(&document, &page_url)?)?)
+        }
+        _ => Err(format!("Unknown MERX snapshot parser {parser}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{default_listing_url, parse_snapshot},
+        crate::{
+            golden::{check_snapshots, snapshot_dir},
+            shapes::SUBSYS_MERX,
+        },
+    };
+
+    #[test_log::test]
+    fn golden_snapshots() {
+        let checked = check_snapshots(&snapshot_dir(&SUBSYS_MERX.to_lowercase()), parse_snapshot)
+            .unwrap_or_else(|e| panic!("Golden snapshot mismatch:\n{e}"));
+        assert!(checked >= 3);
+    }
+
+    #[test]
+    fn listing_url() {
+        assert_eq!(default_listing_url().as_str(), "https://www.merx.com/public/solicitations/open");
+    }
+}
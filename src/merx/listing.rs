@@ -0,0 +1,99 @@
+//! MERX open solicitation listing handling.
+//!
+//! Open solicitations are listed with one title link per solicitation, paged with a `pageNumber` query parameter.
+//! Solicitation pages live under `/<organization>/solicitations/<title>/<id>`.
+use {
+    crate::{
+        pagination::{NextLink, Pagination},
+        soup::{NodeExt, QueryBuilderExt},
+        BoxError,
+    },
+    log::*,
+    markup5ever_rcdom::RcDom,
+    reqwest::Url,
+};
+
+/// Class of the links to solicitation pages.
+const TITLE_LINK_CLASS: &str = "solicitationsTitleLink";
+
+/// Path segment preceding the title and id of a solicitation page.
+const SOLICITATIONS_SEGMENT: &str = "solicitations";
+
+/// Each page of results links to the next with a `rel="next"` link.
+pub(crate) const PAGINATION: Pagination = Pagination::NextLink(&[NextLink::Rel("next")]);
+
+/// Return the URLs of the solicitations on a page of open solicitations, in order.
+///
+/// A page without a result count is not a listing (usually an error page) and is an error; a listing with no
+/// solicitations on it is not.
+pub(crate) fn parse_listing_page(document: &RcDom, page_url: &Url) -> Result<Vec<Url>, BoxError> {
+    if document.tag("div").attr("class", "mets-result-count").find().is_none() {
+        return Err(format!("MERX result count not found on {page_url}").into());
+    }
+
+    let mut urls: Vec<Url> = Vec::new();
+    for a in document.tag("a").attr("class", TITLE_LINK_CLASS).find_all() {
+        let Some(href) = a.get("href") else {
+            continue;
+        };
+
+        let url = match page_url.join(&href) {
+            Ok(url) => url,
+            Err(e) => {
+                warn!("Ignoring unparseable MERX solicitation link {href}: {e}");
+                continue;
+            }
+        };
+
+        if !is_solicitation_url(&url) {
+            debug!("Ignoring non-solicitation link {href} in MERX listing");
+            continue;
+        }
+
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+
+    Ok(urls)
+}
+
+/// Indicates whether a URL is a solicitation page: `.../solicitations/<title>/<numeric id>`.
+fn is_solicitation_url(url: &Url) -> bool {
+    let Some(segments) = url.path_segments() else {
+        return false;
+    };
+
+    let segments: Vec<&str> = segments.collect();
+    match segments.as_slice() {
+        [.., solicitations, _, id] => {
+            *solicitations == SOLICITATIONS_SEGMENT && !id.is_empty() && id.chars().all(|c| c.is_ascii_digit())
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{parse_listing_page, PAGINATION},
+        crate::soup::parse_html_str,
+        reqwest::Url,
+    };
+
+    #[test]
+    fn listing_page() {
+        let page_url = Url::parse("https://www.merx.com/public/solicitations/open").unwrap();
+        let document = parse_html_str(include_str!("merx-open-solicitations-page1.html"));
+
+        let urls = parse_listing_page(&document, &page_url).unwrap();
+        assert_eq!(urls.len(), 3);
+        assert_eq!(
+            urls[2].as_str(),
+            "https://www.merx.com/villedemontreal/solicitations/Deneigement-des-voies-cyclables/0000246113"
+        );
+        assert_eq!(PAGINATION.next_url(&document, &page_url).unwrap().query(), Some("pageNumber=2"));
+
+        assert!(parse_listing_page(&parse_html_str("<p>Error</p>"), &page_url).is_err());
+    }
+}
@@ -0,0 +1,306 @@
+//! MERX solicitation page handling.
+//!
+//! A solicitation page is a series of labelled fields (`<div class="mets-field-label">` followed by
+//! `<div class="mets-field-body">`). Pages are served in English or French, as declared by the `lang` attribute of
+//! `<html>`, and the labels, date format, and number format follow that language:
+//!
+//! | Field            | English                    | French                          |
+//! |------------------|----------------------------|---------------------------------|
+//! | Closing date     | `2023/05/04 02:00 PM EDT`  | `2023/05/04 14 h 00 HAE`        |
+//! | Estimated value  | `$1,250,000.00 CAD`        | `1 250 000,00 $`                |
+//!
+//! Federal solicitations carry their title and description in the other official language as well; these become
+//! [`Translation`]s of the opportunity.
+use {
+    crate::{
+        award::page_awards,
+        mets::{field_description, labelled_fields},
+        opportunity::{clean_text, parse_amount, text_lines, Contact, DateLocale, Opportunity, Translation},
+        shapes::SUBSYS_MERX,
+        soup::{NodeExt, QueryBuilderExt},
+        BoxError,
+    },
+    chrono::{NaiveDate, NaiveDateTime, NaiveTime},
+    log::*,
+    markup5ever_rcdom::RcDom,
+    reqwest::Url,
+};
+
+/// A page language MERX serves.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Language {
+    English,
+    French,
+}
+
+impl Language {
+    /// Return the ISO 639-1 code of the language.
+    fn code(self) -> &'static str {
+        match self {
+            Self::English => "en",
+            Self::French => "fr",
+        }
+    }
+
+    /// Return the other official language.
+    fn other(self) -> Self {
+        match self {
+            Self::English => Self::French,
+            Self::French => Self::English,
+        }
+    }
+}
+
+/// The label of a field in each language.
+struct Label {
+    english: &'static str,
+    french: &'static str,
+}
+
+impl Label {
+    fn get(&self, language: Language) -> &'static str {
+        match language {
+            Language::English => self.english,
+            Language::French => self.french,
+        }
+    }
+}
+
+const LABEL_REFERENCE: Label = Label {
+    english: "Solicitation Number",
+    french: "Numéro de sollicitation",
+};
+const LABEL_ORGANIZATION: Label = Label {
+    english: "Issuing Organization",
+    french: "Organisation émettrice",
+};
+const LABEL_PUBLISHED: Label = Label {
+    english: "Publication Date",
+    french: "Date de publication",
+};
+const LABEL_CLOSING: Label = Label {
+    english: "Closing Date",
+    french: "Date de clôture",
+};
+const LABEL_VALUE: Label = Label {
+    english: "Estimated Value",
+    french: "Valeur estimée",
+};
+const LABEL_CONTACT: Label = Label {
+    english: "Contact Information",
+    french: "Coordonnées",
+};
+const LABEL_CATEGORIES: Label = Label {
+    english: "Categories",
+    french: "Catégories",
+};
+const LABEL_DESCRIPTION: Label = Label {
+    english: "Description",
+    french: "Description",
+};
+
+/// Labels of the title and description in the other language, keyed by the page's language.
+const LABEL_TRANSLATED_TITLE: Label = Label {
+    english: "Title (French)",
+    french: "Titre (anglais)",
+};
+const LABEL_TRANSLATED_DESCRIPTION: Label = Label {
+    english: "Description (French)",
+    french: "Description (anglais)",
+};
+
+/// Currency of amounts that don't name one.
+const DEFAULT_CURRENCY: &str = "CAD";
+
+/// Separator between a commodity code and its description.
+const COMMODITY_CODE_SEPARATOR: &str = " - ";
+
+/// Date formats used by MERX in either language.
+const DATE_FORMATS: &[&str] = &["%Y/%m/%d", "%Y-%m-%d"];
+
+/// Date and time formats used by MERX, after [`normalize_time`].
+const DATE_TIME_FORMATS: &[&str] = &["%Y/%m/%d %I:%M %p", "%Y/%m/%d %H:%M", "%Y-%m-%d %H:%M"];
+
+/// Parse a solicitation page into the normalized model.
+///
+/// A page without a title or solicitation number is not a solicitation (usually an error page) and is an error.
+pub(crate) fn parse_solicitation_page(document: &RcDom, page_url: &Url) -> Result<Opportunity, BoxError> {
+    let Some(title) = document.tag("h1").attr("class", "mets-page-title").find().and_then(|h1| clean_text(&h1.text()))
+    else {
+        return Err(format!("MERX solicitation title not found on {page_url}").into());
+    };
+
+    let language = page_language(document);
+    let date_locale = match language {
+        Language::English => DateLocale::English,
+        Language::French => DateLocale::French,
+    };
+    let fields = labelled_fields(document);
+    let body = |label: &Label| fields.get(label.get(language));
+    let text = |label: &Label| body(label).and_then(|body| clean_text(&body.text()));
+    let lines = |label: &Label| body(label).map(text_lines).unwrap_or_default();
+    let date_time = |label: &Label| {
+        let value = text(label)?;
+        let date_time = parse_date_time(&value);
+        if date_time.is_none() {
+            warn!("Unparseable MERX {} {value:?} on {page_url}", label.english);
+        }
+        date_time
+    };
+
+    let Some(reference) = text(&LABEL_REFERENCE) else {
+        return Err(format!("MERX solicitation number not found on {page_url}").into());
+    };
+
+    let commodity_codes = lines(&LABEL_CATEGORIES)
+        .into_iter()
+        .map(|line| match line.split_once(COMMODITY_CODE_SEPARATOR) {
+            Some((code, _)) => code.to_string(),
+            None => line,
+        })
+        .collect();
+
+    let estimated_value = text(&LABEL_VALUE).and_then(|value| {
+        let amount = parse_amount(&value, DEFAULT_CURRENCY);
+        if amount.is_none() {
+            debug!("Unparseable MERX estimated value {value:?} on {page_url}");
+        }
+        amount
+    });
+
+    let translated_title = text(&LABEL_TRANSLATED_TITLE);
+    let translated_description = body(&LABEL_TRANSLATED_DESCRIPTION).and_then(field_description);
+    let translations = if translated_title.is_some() || translated_description.is_some() {
+        vec![Translation {
+            language: language.other().code().to_string(),
+            title: translated_title,
+            description: translated_description,
+        }]
+    } else {
+        vec![]
+    };
+
+    Ok(Opportunity {
+        portal: SUBSYS_MERX.to_string(),
+        url: page_url.to_string(),
+        title,
+        reference: Some(reference),
+        organization: text(&LABEL_ORGANIZATION),
+        contact: Contact::from_lines(&lines(&LABEL_CONTACT)),
+        published_on: date_time(&LABEL_PUBLISHED).map(|published| published.date()),
+        due_at: date_time(&LABEL_CLOSING),
+        description: body(&LABEL_DESCRIPTION).and_then(field_description),
+        commodity_codes,
+        // Documents are only offered to paying subscribers, and Canadian portals have no counties.
+        counties: vec![],
+        documents: vec![],
+        language: Some(language.code().to_string()),
+        translations,
+        estimated_value,
+        awards: page_awards(document, date_locale, DEFAULT_CURRENCY),
+        ..Opportunity::default()
+    })
+}
+
+/// Return the language a page is written in. Pages that don't declare French are taken to be English.
+fn page_language(document: &RcDom) -> Language {
+    let lang = document.tag("html").find().and_then(|html| html.get("lang")).unwrap_or_default();
+    if lang.to_lowercase().starts_with("fr") {
+        Language::French
+    } else {
+        Language::English
+    }
+}
+
+/// Drop a trailing time zone abbreviation (`EDT`, `HAE`) and rewrite a French time (`14 h 00`) as `14:00`.
+fn normalize_time(text: &str) -> String {
+    let text = match text.rsplit_once(' ') {
+        Some((date_time, zone)) if (3..=4).contains(&zone.len()) && zone.chars().all(|c| c.is_ascii_uppercase()) => {
+            date_time
+        }
+        _ => text,
+    };
+
+    text.replace(" h ", ":")
+}
+
+/// Parse a date and time as written by MERX in either language. A bare date is taken to mean the end of that day.
+fn parse_date_time(text: &str) -> Option<NaiveDateTime> {
+    let text = normalize_time(&clean_text(text)?);
+    DATE_TIME_FORMATS.iter().find_map(|format| NaiveDateTime::parse_from_str(&text, format).ok()).or_else(|| {
+        let date = DATE_FORMATS.iter().find_map(|format| NaiveDate::parse_from_str(&text, format).ok())?;
+        Some(date.and_time(NaiveTime::from_hms_opt(23, 59, 59)?))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{parse_date_time, parse_solicitation_page},
+        crate::{opportunity::Amount, soup::parse_html_str},
+        chrono::NaiveDate,
+        reqwest::Url,
+    };
+
+    #[test]
+    fn english_page() {
+        let url =
+            Url::parse("https://www.merx.com/pwgsc/solicitations/Janitorial-Services-Halifax/0000245978").unwrap();
+        let opportunity =
+            parse_solicitation_page(&parse_html_str(include_str!("merx-solicitation-en.html")), &url).unwrap();
+
+        assert_eq!(opportunity.title, "Janitorial Services - Halifax");
+        assert_eq!(opportunity.reference.as_deref(), Some("W0105-23-J41"));
+        assert_eq!(opportunity.language.as_deref(), Some("en"));
+        assert_eq!(opportunity.published_on, NaiveDate::from_ymd_opt(2023, 4, 3));
+        assert_eq!(opportunity.due_at, NaiveDate::from_ymd_opt(2023, 5, 4).unwrap().and_hms_opt(14, 0, 0));
+        assert_eq!(
+            opportunity.estimated_value,
+            Some(Amount {
+                minor_units: 125_000_000,
+                currency: "CAD".to_string(),
+            })
+        );
+        assert_eq!(opportunity.contact.phone.as_deref(), Some("(902) 496-5102"));
+        assert_eq!(opportunity.commodity_codes, vec!["76111500"]);
+
+        assert_eq!(opportunity.translations.len(), 1);
+        let french = &opportunity.translations[0];
+        assert_eq!(french.language, "fr");
+        assert_eq!(french.title.as_deref(), Some("Services de conciergerie - Halifax"));
+        assert_eq!(french.description.as_ref().unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn french_page() {
+        let url =
+            Url::parse("https://www.merx.com/villedemontreal/solicitations/Deneigement-des-voies-cyclables/0000246113")
+                .unwrap();
+        let opportunity =
+            parse_solicitation_page(&parse_html_str(include_str!("merx-solicitation-fr.html")), &url).unwrap();
+
+        assert_eq!(opportunity.title, "Déneigement des voies cyclables");
+        assert_eq!(opportunity.reference.as_deref(), Some("23-19874"));
+        assert_eq!(opportunity.organization.as_deref(), Some("Ville de Montréal"));
+        assert_eq!(opportunity.language.as_deref(), Some("fr"));
+        assert_eq!(opportunity.due_at, NaiveDate::from_ymd_opt(2023, 5, 16).unwrap().and_hms_opt(14, 0, 0));
+        assert_eq!(opportunity.estimated_value.unwrap().minor_units, 85_000_000);
+        assert_eq!(opportunity.contact.email.as_deref(), Some("julien.gagnon@montreal.ca"));
+        assert_eq!(opportunity.commodity_codes, vec!["72102900", "78181700"]);
+
+        assert_eq!(opportunity.translations.len(), 1);
+        assert_eq!(opportunity.translations[0].language, "en");
+        assert_eq!(opportunity.translations[0].title.as_deref(), Some("Snow Removal for Bike Paths"));
+        assert_eq!(opportunity.translations[0].description, None);
+    }
+
+    #[test]
+    fn date_times() {
+        let closing = NaiveDate::from_ymd_opt(2023, 5, 4).unwrap();
+        assert_eq!(parse_date_time("2023/05/04 02:00 PM EDT"), closing.and_hms_opt(14, 0, 0));
+        assert_eq!(parse_date_time("2023/05/04 14 h 00 HAE"), closing.and_hms_opt(14, 0, 0));
+        assert_eq!(parse_date_time("2023/05/04 02:00 PM"), closing.and_hms_opt(14, 0, 0));
+        assert_eq!(parse_date_time("2023/05/04"), closing.and_hms_opt(23, 59, 59));
+        assert_eq!(parse_date_time("À déterminer"), None);
+    }
+}
@@ -0,0 +1,359 @@
+//! In-process metrics registry.
+//!
+//! Metrics are collected in a single process-wide registry. At the end of each Lambda invocation they are emitted
+//! to CloudWatch using the [Embedded Metric Format][emf] (EMF), which CloudWatch Logs extracts from stdout. For local
+//! runs, the same registry can also be rendered in the Prometheus text exposition format and written to a textfile
+//! for scraping by a node_exporter textfile collector.
+//!
+//! [emf]: https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format_Specification.html
+use {
+    lazy_static::lazy_static,
+    log::*,
+    parking_lot::Mutex,
+    serde_json::{json, Map, Value},
+    std::{
+        collections::BTreeMap,
+        env,
+        fmt::Write,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+const ENV_METRICS_NAMESPACE: &str = "METRICS_NAMESPACE";
+const ENV_METRICS_PROMETHEUS_TEXTFILE: &str = "METRICS_PROMETHEUS_TEXTFILE";
+const DEFAULT_METRICS_NAMESPACE: &str = "GovScout";
+
+/// The dimension attributing a metric to the team a crawl is run for.
+pub const DIMENSION_TENANT: &str = "Tenant";
+
+/// Maximum number of values EMF accepts for a single metric in one log line.
+const MAX_EMF_VALUES: usize = 100;
+
+lazy_static! {
+    static ref REGISTRY: MetricsRegistry = MetricsRegistry::default();
+}
+
+/// The unit of a metric, using the CloudWatch unit names.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Unit {
+    /// A dimensionless count.
+    Count,
+
+    /// A size in bytes.
+    Bytes,
+
+    /// A duration in milliseconds.
+    Milliseconds,
+
+    /// A percentage.
+    Percent,
+}
+
+/// A metric name along with its dimensions.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct MetricKey {
+    /// The metric name.
+    pub name: String,
+
+    /// Dimension names and values, sorted by name.
+    pub dimensions: Vec<(String, String)>,
+}
+
+/// Accumulated values for a single metric.
+#[derive(Clone, Debug)]
+struct MetricData {
+    /// The unit of the metric.
+    unit: Unit,
+
+    /// Number of observations since the process started.
+    count: u64,
+
+    /// Sum of all observations since the process started.
+    sum: f64,
+
+    /// Observations not yet emitted to CloudWatch.
+    pending: Vec<f64>,
+}
+
+/// A registry of metrics.
+#[derive(Debug, Default)]
+pub struct MetricsRegistry {
+    metrics: Mutex<BTreeMap<MetricKey, MetricData>>,
+}
+
+impl Unit {
+    /// Return the CloudWatch name of this unit.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Count => "Count",
+            Self::Bytes => "Bytes",
+            Self::Milliseconds => "Milliseconds",
+            Self::Percent => "Percent",
+        }
+    }
+}
+
+impl MetricKey {
+    /// Create a new metric key. Dimensions are sorted by name.
+    pub fn new(name: &str, dimensions: &[(&str, &str)]) -> Self {
+        let mut dimensions: Vec<(String, String)> =
+            dimensions.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        dimensions.sort();
+
+        Self {
+            name: name.to_string(),
+            dimensions,
+        }
+    }
+}
+
+impl MetricsRegistry {
+    /// Add `value` to a counter.
+    pub fn increment(&self, name: &str, dimensions: &[(&str, &str)], value: u64) {
+        self.record(MetricKey::new(name, dimensions), Unit::Count, value as f64);
+    }
+
+    /// Record an observation of a distribution (e.g., a size or duration).
+    pub fn observe(&self, name: &str, dimensions: &[(&str, &str)], unit: Unit, value: f64) {
+        self.record(MetricKey::new(name, dimensions), unit, value);
+    }
+
+    fn record(&self, key: MetricKey, unit: Unit, value: f64) {
+        let mut metrics = self.metrics.lock();
+        let data = metrics.entry(key).or_insert_with(|| MetricData {
+            unit,
+            count: 0,
+            sum: 0.0,
+            pending: Vec::new(),
+        });
+        data.count += 1;
+        data.sum += value;
+        data.pending.push(value);
+    }
+
+    /// Return the total of all observations recorded for a metric since the process started.
+    pub fn total(&self, name: &str, dimensions: &[(&str, &str)]) -> f64 {
+        let metrics = self.metrics.lock();
+        metrics.get(&MetricKey::new(name, dimensions)).map(|data| data.sum).unwrap_or_default()
+    }
+
+    /// Render the observations recorded since the last call as EMF log lines, one per metric, and reset them.
+    pub fn drain_emf(&self, namespace: &str, timestamp_millis: u128) -> Vec<String> {
+        let mut metrics = self.metrics.lock();
+        let mut lines = Vec::new();
+
+        for (key, data) in metrics.iter_mut() {
+            if data.pending.is_empty() {
+                continue;
+            }
+
+            let pending = std::mem::take(&mut data.pending);
+            let values: Vec<f64> = match data.unit {
+                // Counters are summed into a single value.
+                Unit::Count => vec![pending.iter().sum()],
+                _ => pending,
+            };
+
+            for chunk in values.chunks(MAX_EMF_VALUES) {
+                let dimension_names: Vec<&str> = key.dimensions.iter().map(|(k, _)| k.as_str()).collect();
+                let mut line = Map::new();
+                line.insert(
+                    "_aws".to_string(),
+                    json!({
+                        "Timestamp": timestamp_millis as u64,
+                        "CloudWatchMetrics": [{
+                            "Namespace": namespace,
+                            "Dimensions": [dimension_names],
+                            "Metrics": [{"Name": key.name, "Unit": data.unit.as_str()}],
+                        }],
+                    }),
+                );
+
+                for (name, value) in key.dimensions.iter() {
+                    line.insert(name.clone(), Value::String(value.clone()));
+                }
+
+                let value = if chunk.len() == 1 {
+                    json!(chunk[0])
+                } else {
+                    json!(chunk)
+                };
+                line.insert(key.name.clone(), value);
+                lines.push(Value::Object(line).to_string());
+            }
+        }
+
+        lines
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    ///
+    /// Counters are exposed as `<name>_total`; other metrics are exposed as summaries with `_sum` and `_count`.
+    pub fn render_prometheus(&self) -> String {
+        let metrics = self.metrics.lock();
+        let mut output = String::new();
+        let mut last_name: Option<&str> = None;
+
+        for (key, data) in metrics.iter() {
+            let name = prometheus_name(&key.name, data.unit);
+            let labels = prometheus_labels(&key.dimensions);
+
+            if last_name != Some(key.name.as_str()) {
+                let kind = match data.unit {
+                    Unit::Count => "counter",
+                    _ => "summary",
+                };
+                let _ = writeln!(output, "# TYPE {name} {kind}");
+                last_name = Some(key.name.as_str());
+            }
+
+            match data.unit {
+                Unit::Count => {
+                    let _ = writeln!(output, "{name}_total{labels} {}", data.sum);
+                }
+                _ => {
+                    let _ = writeln!(output, "{name}_sum{labels} {}", data.sum);
+                    let _ = writeln!(output, "{name}_count{labels} {}", data.count);
+                }
+            }
+        }
+
+        output
+    }
+}
+
+/// Return the process-wide metrics registry.
+pub fn registry() -> &'static MetricsRegistry {
+    &REGISTRY
+}
+
+/// Return `dimensions` with the crawl's tenant added, if it has one. Metrics of crawls without a tenant keep the
+/// dimensions they have always had.
+pub fn with_tenant<'a>(dimensions: &[(&'a str, &'a str)], tenant: Option<&'a str>) -> Vec<(&'a str, &'a str)> {
+    let mut dimensions = dimensions.to_vec();
+    if let Some(tenant) = tenant {
+        dimensions.push((DIMENSION_TENANT, tenant));
+    }
+    dimensions
+}
+
+/// Emit the metrics recorded since the last call.
+///
+/// Metrics are printed to stdout in EMF for CloudWatch. If `METRICS_PROMETHEUS_TEXTFILE` is set (typically for local
+/// runs), the full registry is also written to that path in the Prometheus text format.
+pub fn publish() {
+    let namespace = env::var(ENV_METRICS_NAMESPACE).unwrap_or_else(|_| DEFAULT_METRICS_NAMESPACE.to_string());
+    let timestamp_millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+
+    for line in registry().drain_emf(&namespace, timestamp_millis) {
+        println!("{line}");
+    }
+
+    if let Ok(path) = env::var(ENV_METRICS_PROMETHEUS_TEXTFILE) {
+        // Write to a temporary file and rename so scrapers never see a partial file.
+        let tmp_path = format!("{path}.tmp");
+        let result =
+            std::fs::write(&tmp_path, registry().render_prometheus()).and_then(|_| std::fs::rename(&tmp_path, &path));
+        if let Err(e) = result {
+            warn!("Failed to write Prometheus metrics to {path}: {e}");
+        }
+    }
+}
+
+/// Convert a CamelCase metric name to a snake_case Prometheus name with a unit suffix.
+fn prometheus_name(name: &str, unit: Unit) -> String {
+    let mut result = String::with_capacity(name.len() + 8);
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                result.push('_');
+            }
+            result.push(c.to_ascii_lowercase());
+        } else if c.is_ascii_alphanumeric() {
+            result.push(c);
+        } else {
+            result.push('_');
+        }
+    }
+
+    match unit {
+        Unit::Bytes => result.push_str("_bytes"),
+        Unit::Milliseconds => result.push_str("_milliseconds"),
+        Unit::Percent => result.push_str("_percent"),
+        Unit::Count => (),
+    }
+
+    result
+}
+
+/// Format dimensions as Prometheus labels.
+fn prometheus_labels(dimensions: &[(String, String)]) -> String {
+    if dimensions.is_empty() {
+        return String::new();
+    }
+
+    let labels: Vec<String> = dimensions
+        .iter()
+        .map(|(k, v)| {
+            let value = v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{}=\"{value}\"", prometheus_name(k, Unit::Count))
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{with_tenant, MetricsRegistry, Unit};
+
+    #[test]
+    fn prometheus_rendering() {
+        let registry = MetricsRegistry::default();
+        registry.increment("HttpRequests", &[("Portal", "Webs")], 1);
+        registry.increment("HttpRequests", &[("Portal", "Webs")], 2);
+        registry.observe("ResponseSize", &[("Portal", "Webs")], Unit::Bytes, 100.0);
+        registry.observe("ResponseSize", &[("Portal", "Webs")], Unit::Bytes, 50.0);
+
+        assert_eq!(
+            registry.render_prometheus().as_str(),
+            "# TYPE http_requests counter\n\
+             http_requests_total{portal=\"Webs\"} 3\n\
+             # TYPE response_size_bytes summary\n\
+             response_size_bytes_sum{portal=\"Webs\"} 150\n\
+             response_size_bytes_count{portal=\"Webs\"} 2\n"
+        );
+    }
+
+    #[test]
+    fn emf_rendering() {
+        let registry = MetricsRegistry::default();
+        registry.increment("HttpRequests", &[("Portal", "Webs")], 1);
+        registry.increment("HttpRequests", &[("Portal", "Webs")], 1);
+        registry.observe("ResponseSize", &[], Unit::Bytes, 10.0);
+        registry.observe("ResponseSize", &[], Unit::Bytes, 20.0);
+
+        let lines = registry.drain_emf("Test", 1000);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0].as_str(),
+            r#"{"HttpRequests":2.0,"Portal":"Webs","_aws":{"CloudWatchMetrics":[{"Dimensions":[["Portal"]],"Metrics":[{"Name":"HttpRequests","Unit":"Count"}],"Namespace":"Test"}],"Timestamp":1000}}"#
+        );
+        assert_eq!(
+            lines[1].as_str(),
+            r#"{"ResponseSize":[10.0,20.0],"_aws":{"CloudWatchMetrics":[{"Dimensions":[[]],"Metrics":[{"Name":"ResponseSize","Unit":"Bytes"}],"Namespace":"Test"}],"Timestamp":1000}}"#
+        );
+
+        // Pending values are drained; totals remain for Prometheus.
+        assert!(registry.drain_emf("Test", 2000).is_empty());
+        assert_eq!(registry.total("HttpRequests", &[("Portal", "Webs")]), 2.0);
+    }
+
+    #[test]
+    fn tenant_dimension() {
+        assert_eq!(with_tenant(&[("Portal", "Webs")], None), [("Portal", "Webs")]);
+        assert_eq!(
+            with_tenant(&[("Portal", "Webs")], Some("Analytics")),
+            [("Portal", "Webs"), ("Tenant", "Analytics")]
+        );
+    }
+}
@@ -0,0 +1,69 @@
+//! Solicitation page markup shared by the portals mdf commerce runs, BidNet Direct and MERX.
+//!
+//! Their solicitation pages are a series of labelled fields, each a `<div class="mets-field">` holding a
+//! `<div class="mets-field-label">` and a `<div class="mets-field-body">`. The portals differ in their labels and in
+//! how they format dates and amounts, which their own parsers handle.
+use {
+    crate::{
+        opportunity::{clean_text, text_lines},
+        soup::{NodeExt, QueryBuilderExt},
+    },
+    markup5ever_rcdom::{Handle, RcDom},
+    std::collections::HashMap,
+};
+
+/// Return the bodies of the labelled fields, keyed by their label.
+pub(crate) fn labelled_fields(document: &RcDom) -> HashMap<String, Handle> {
+    let mut fields = HashMap::new();
+
+    for field in document.tag("div").attr("class", "mets-field").find_all() {
+        let label =
+            field.tag("div").attr("class", "mets-field-label").find().and_then(|label| clean_text(&label.text()));
+        let body = field.tag("div").attr("class", "mets-field-body").find();
+
+        if let (Some(label), Some(body)) = (label, body) {
+            fields.insert(label, body);
+        }
+    }
+
+    fields
+}
+
+/// Return the description in a field body: one line per paragraph, or per `<br>`-separated line if it has no
+/// paragraphs.
+pub(crate) fn field_description(body: &Handle) -> Option<String> {
+    let mut lines: Vec<String> = body.tag("p").find_all().filter_map(|p| clean_text(&p.text())).collect();
+    if lines.is_empty() {
+        lines = text_lines(body);
+    }
+
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{field_description, labelled_fields},
+        crate::soup::parse_html_str,
+    };
+
+    #[test]
+    fn fields() {
+        let document = parse_html_str(
+            r#"<div class="mets-field"><div class="mets-field-label">Description</div>
+                 <div class="mets-field-body"><p>Repair the dock.</p><p>Bring a boat.</p></div></div>
+               <div class="mets-field"><div class="mets-field-label">Notes</div>
+                 <div class="mets-field-body">First line<br>Second line</div></div>
+               <div class="mets-field"><div class="mets-field-label">Empty</div></div>"#,
+        );
+
+        let fields = labelled_fields(&document);
+        assert_eq!(fields.len(), 2);
+        assert_eq!(field_description(&fields["Description"]).unwrap(), "Repair the dock.\nBring a boat.");
+        assert_eq!(field_description(&fields["Notes"]).unwrap(), "First line\nSecond line");
+    }
+}
@@ -0,0 +1,782 @@
+//! Middleware applied around every operation.
+//!
+//! Cross-cutting concerns such as crawl registration, journaling, metrics, and budget enforcement are implemented
+//! once as [`Middleware`] and applied to every operation by a [`Pipeline`], rather than inside each subsystem's
+//! handlers.
+//!
+//! Before an operation runs, each middleware's [`before`][Middleware::before] hook is called in order; any of them
+//! may reject the operation with an error or skip it by supplying a response of its own. After the operation (or the
+//! hook that stopped it), the [`after`][Middleware::after] hooks of the middleware that were entered are called in
+//! reverse order and may inspect or amend the result.
+use {
+    crate::{
+        config::load_crawl_settings,
+        cost,
+        flags::{load_flags, FlagsDocument},
+        httpext::LogConfig,
+        journal::CrawlEvent,
+        link_graph::Link,
+        metrics::{self, Unit},
+        retry::{FailedAttempt, RetriesExhaustedError, RetryableError, MAX_DELAY},
+        shapes::{normalize_url, NextRequest, Operation, Request, Response, SUBSYS_ADMIN},
+    },
+    futures::future::BoxFuture,
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    std::{
+        env,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+    tokio::sync::OnceCell,
+};
+
+const ENV_DISABLED_OPERATIONS: &str = "DISABLED_OPERATIONS";
+const METRIC_OPERATIONS: &str = "Operations";
+const DIMENSION_OPERATION: &str = "Operation";
+const DIMENSION_OUTCOME: &str = "Outcome";
+const OUTCOME_SUCCESS: &str = "Success";
+const OUTCOME_FAILURE: &str = "Failure";
+const METRIC_OPERATION_DURATION: &str = "OperationDuration";
+const METRIC_OPERATION_BUDGET_USED: &str = "OperationBudgetUsed";
+const METRIC_OPERATIONS_DEFERRED: &str = "OperationsDeferred";
+const DIMENSION_CONDITION: &str = "Condition";
+const CONDITION_CRAWL_PAUSED: &str = "CrawlPaused";
+
+/// An operation being dispatched through a [`Pipeline`].
+#[derive(Clone, Debug)]
+pub struct OperationCall {
+    /// The operation to perform.
+    pub operation: Operation,
+
+    /// The request for the operation. Hooks may modify it before the operation runs.
+    pub request: Request,
+
+    /// The logging configuration.
+    pub log_config: LogConfig,
+
+    /// When the call entered the pipeline.
+    pub started_at: Instant,
+
+    /// The time left before the Lambda deadline when the call entered the pipeline, if the deadline is known.
+    pub budget: Option<Duration>,
+}
+
+impl OperationCall {
+    /// Create a call for an operation.
+    pub fn new(operation: Operation, request: Request, log_config: LogConfig) -> Self {
+        Self {
+            operation,
+            request,
+            log_config,
+            started_at: Instant::now(),
+            budget: None,
+        }
+    }
+
+    /// Set the Lambda deadline, in milliseconds since the Unix epoch, from which the call's budget is measured. A
+    /// deadline of zero means it is unknown.
+    pub fn with_deadline(mut self, deadline_ms: u64) -> Self {
+        if deadline_ms > 0 {
+            let deadline = UNIX_EPOCH + Duration::from_millis(deadline_ms);
+            self.budget = Some(deadline.duration_since(SystemTime::now()).unwrap_or_default());
+        }
+        self
+    }
+
+    /// Return the crawl id of the request, if one has been assigned.
+    pub fn crawl_id(&self) -> Option<&str> {
+        self.request.crawl.crawl_id.as_deref()
+    }
+}
+
+/// Hooks run before and after an operation.
+pub trait Middleware: Send + Sync {
+    /// Called before the operation runs.
+    ///
+    /// Returning `Ok(Some(response))` skips the operation (and the remaining `before` hooks), using `response` as its
+    /// result. Returning an error fails the operation without running it.
+    fn before<'a>(&'a self, call: &'a mut OperationCall) -> BoxFuture<'a, Result<Option<Response>, LambdaError>> {
+        let _ = call;
+        Box::pin(async { Ok(None) })
+    }
+
+    /// Called after the operation completes, or after a `before` hook stopped it.
+    fn after<'a>(
+        &'a self,
+        call: &'a OperationCall,
+        result: &'a mut Result<Response, LambdaError>,
+    ) -> BoxFuture<'a, ()> {
+        let _ = (call, result);
+        Box::pin(async {})
+    }
+}
+
+/// An ordered chain of [`Middleware`] wrapped around [`Operation::handle`].
+#[derive(Default)]
+pub struct Pipeline {
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl Pipeline {
+    /// Create a pipeline without any middleware.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create the pipeline used to dispatch operations from the queue, configured from `log_config`.
+    pub fn standard(log_config: &LogConfig) -> Self {
+        Self::new()
+            .with(Tracing)
+            .with(KillSwitch::from_env())
+            .with(Localization)
+            .with(CrawlRegistration)
+            .with(FeatureFlagging::default())
+            .with(CrawlPause)
+            .with(FrontierTracking)
+            .with(Journaling)
+            .with(OperationMetrics)
+            .with(CostAccounting)
+            .with(Retry::new(log_config.retry_max_attempts))
+            .with(BudgetEnforcement)
+            .with(LinkCapture)
+            .with(RefererChaining)
+    }
+
+    /// Append a middleware to the chain. Middleware added later runs closer to the operation.
+    pub fn with<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Run an operation through the pipeline.
+    pub async fn run(&self, mut call: OperationCall, context: Context) -> Result<Response, LambdaError> {
+        let mut entered = 0;
+        let mut result = None;
+
+        for middleware in self.middleware.iter() {
+            entered += 1;
+            match middleware.before(&mut call).await {
+                Ok(None) => (),
+                Ok(Some(response)) => {
+                    result = Some(Ok(response));
+                    break;
+                }
+                Err(e) => {
+                    result = Some(Err(e));
+                    break;
+                }
+            }
+        }
+
+        let mut result = match result {
+            Some(result) => result,
+            None => call.operation.handle(call.log_config.clone(), call.request.clone(), context).await,
+        };
+
+        for middleware in self.middleware[..entered].iter().rev() {
+            middleware.after(&call, &mut result).await;
+        }
+
+        result
+    }
+}
+
+/// Log the start and end of each operation, along with how long it took.
+pub struct Tracing;
+
+impl Middleware for Tracing {
+    fn before<'a>(&'a self, call: &'a mut OperationCall) -> BoxFuture<'a, Result<Option<Response>, LambdaError>> {
+        Box::pin(async move {
+            info!("Starting {} for {}", call.operation, call.request.url.as_deref().unwrap_or("<no url>"));
+            Ok(None)
+        })
+    }
+
+    fn after<'a>(
+        &'a self,
+        call: &'a OperationCall,
+        result: &'a mut Result<Response, LambdaError>,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let elapsed = call.started_at.elapsed();
+            match result {
+                Ok(response) => info!(
+                    "Finished {} in {elapsed:?} with {} next request(s)",
+                    call.operation,
+                    response.next_requests.len()
+                ),
+                Err(e) => warn!("Failed {} after {elapsed:?}: {e}", call.operation),
+            }
+        })
+    }
+}
+
+/// Skip operations that have been disabled through the `DISABLED_OPERATIONS` environment variable.
+///
+/// The variable holds a comma-separated list of subsystems (`Webs`) or operations (`Webs:FetchOpportunityDetailPage`).
+/// Disabled operations complete successfully without scheduling anything, so their messages are consumed rather than
+/// retried.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KillSwitch {
+    disabled: Vec<String>,
+}
+
+impl KillSwitch {
+    /// Create a kill switch from a comma-separated list of subsystems and operations.
+    pub fn new(disabled: &str) -> Self {
+        Self {
+            disabled: disabled.split(',').map(str::trim).filter(|s| !s.is_empty()).map(str::to_string).collect(),
+        }
+    }
+
+    /// Create a kill switch from the `DISABLED_OPERATIONS` environment variable.
+    pub fn from_env() -> Self {
+        Self::new(&env::var(ENV_DISABLED_OPERATIONS).unwrap_or_default())
+    }
+
+    /// Indicates whether the operation is disabled.
+    pub fn is_disabled(&self, operation: &Operation) -> bool {
+        let name = operation.to_string();
+        self.disabled.iter().any(|entry| entry == operation.subsystem() || *entry == name)
+    }
+}
+
+impl Middleware for KillSwitch {
+    fn before<'a>(&'a self, call: &'a mut OperationCall) -> BoxFuture<'a, Result<Option<Response>, LambdaError>> {
+        Box::pin(async move {
+            if self.is_disabled(&call.operation) {
+                warn!("{} is disabled; dropping request for {:?}", call.operation, call.request.url);
+                return Ok(Some(Response {
+                    next_requests: vec![],
+                }));
+            }
+
+            Ok(None)
+        })
+    }
+}
+
+/// Read the [settings][crate::config::CrawlSettings] of a new crawl, such as its locale, from its subsystem's
+/// configuration document.
+///
+/// This runs before [`CrawlRegistration`], so a request without a crawl id is the start of a crawl; the operations it
+/// schedules inherit the settings in their crawl parameters. A request that already names a locale or turns on referer
+/// chaining keeps it. Administrative operations aren't part of a crawl and are passed through untouched.
+pub struct Localization;
+
+impl Middleware for Localization {
+    fn before<'a>(&'a self, call: &'a mut OperationCall) -> BoxFuture<'a, Result<Option<Response>, LambdaError>> {
+        Box::pin(async move {
+            let subsystem = call.operation.subsystem();
+            let crawl = &mut call.request.crawl;
+            if subsystem == SUBSYS_ADMIN || crawl.crawl_id.is_some() {
+                return Ok(None);
+            }
+
+            let settings = load_crawl_settings(&call.log_config, subsystem).await?;
+            if crawl.locale.is_default() {
+                crawl.locale = settings.locale;
+                if !crawl.locale.is_default() {
+                    info!("Crawling {subsystem} with locale {:?}", crawl.locale);
+                }
+            }
+
+            if settings.referer_chain && !crawl.referer_chain {
+                info!("Crawling {subsystem} with referer chaining");
+                crawl.referer_chain = true;
+            }
+
+            Ok(None)
+        })
+    }
+}
+
+/// Register a new crawl for requests without a crawl id, and reject requests for crawls that were never registered.
+///
+/// Administrative operations aren't part of a crawl and are passed through untouched.
+pub struct CrawlRegistration;
+
+impl Middleware for CrawlRegistration {
+    fn before<'a>(&'a self, call: &'a mut OperationCall) -> BoxFuture<'a, Result<Option<Response>, LambdaError>> {
+        Box::pin(async move {
+            if call.operation.subsystem() == SUBSYS_ADMIN {
+                return Ok(None);
+            }
+
+            let crawls = &call.log_config.crawls;
+            match call.request.crawl.crawl_id.as_ref() {
+                Some(crawl_id) => crawls.require(crawl_id).await?,
+                None => {
+                    let record = crawls
+                        .register(
+                            call.operation.subsystem(),
+                            &call.operation.to_string(),
+                            call.request.url.as_deref(),
+                            &call.request.crawl.user_agent,
+                            call.request.crawl.tenant.as_deref(),
+                        )
+                        .await?;
+                    call.request.crawl.crawl_id = Some(record.crawl_id);
+                }
+            }
+
+            Ok(None)
+        })
+    }
+}
+
+/// Resolve the [feature flags][crate::flags] in effect for each operation into its logging configuration.
+///
+/// The flags document is read the first time an operation is dispatched through the pipeline and reused for the rest
+/// of the batch, so a change to it takes effect with the next batch. If it can't be read, operations run with the
+/// flags' built-in defaults rather than failing. This runs after [`CrawlRegistration`], so a new crawl's operations see
+/// its crawl overrides too.
+#[derive(Default)]
+pub struct FeatureFlagging {
+    document: OnceCell<FlagsDocument>,
+}
+
+impl Middleware for FeatureFlagging {
+    fn before<'a>(&'a self, call: &'a mut OperationCall) -> BoxFuture<'a, Result<Option<Response>, LambdaError>> {
+        Box::pin(async move {
+            let document = self
+                .document
+                .get_or_init(|| async {
+                    load_flags(&call.log_config).await.unwrap_or_else(|e| {
+                        error!("Failed to load feature flags; using their defaults: {e}");
+                        FlagsDocument::default()
+                    })
+                })
+                .await;
+
+            call.log_config.flags = document.resolve(call.operation.subsystem(), call.crawl_id());
+            Ok(None)
+        })
+    }
+}
+
+/// Hold back the operations of paused crawls by re-enqueueing their requests, unchanged, with the longest delay the
+/// queue allows. Requests keep circulating this way until `Admin:ResumeCrawl` clears the pause, so a maintenance window
+/// doesn't lose in-flight work.
+///
+/// This looks the crawl record up for every operation, since a crawl can be paused at any time. Administrative
+/// operations aren't part of a crawl and are passed through untouched.
+pub struct CrawlPause;
+
+impl Middleware for CrawlPause {
+    fn before<'a>(&'a self, call: &'a mut OperationCall) -> BoxFuture<'a, Result<Option<Response>, LambdaError>> {
+        Box::pin(async move {
+            let Some(crawl_id) = call.crawl_id() else {
+                return Ok(None);
+            };
+
+            if call.operation.subsystem() == SUBSYS_ADMIN || !call.log_config.crawls.is_paused(crawl_id).await? {
+                return Ok(None);
+            }
+
+            let delay_seconds = MAX_DELAY.as_secs() as u32;
+            info!(
+                "Crawl {crawl_id} is paused; deferring {} for {:?} by {delay_seconds}s",
+                call.operation, call.request.url
+            );
+
+            metrics::registry().increment(
+                METRIC_OPERATIONS_DEFERRED,
+                &[(DIMENSION_CONDITION, CONDITION_CRAWL_PAUSED)],
+                1,
+            );
+            let event = CrawlEvent::OperationDeferred {
+                operation: call.operation.to_string(),
+                condition: CONDITION_CRAWL_PAUSED.to_string(),
+                attempt: call.request.crawl.attempt,
+                delay_seconds,
+            };
+            call.log_config.journal.record(crawl_id, event).await;
+
+            Ok(Some(Response {
+                next_requests: vec![NextRequest {
+                    operation: call.operation,
+                    url: call.request.url.clone(),
+                    link_text: None,
+                    crawl: call.request.crawl.clone(),
+                    delay_seconds,
+                }],
+            }))
+        })
+    }
+}
+
+/// Mark the URL of each operation that completes successfully as visited in its crawl's [frontier][crate::frontier].
+///
+/// Administrative operations aren't part of a crawl and are passed through untouched.
+pub struct FrontierTracking;
+
+impl Middleware for FrontierTracking {
+    fn after<'a>(
+        &'a self,
+        call: &'a OperationCall,
+        result: &'a mut Result<Response, LambdaError>,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let (Ok(_), Some(crawl_id), Some(url)) = (result, call.crawl_id(), call.request.url.as_deref()) else {
+                return;
+            };
+
+            if call.operation.subsystem() == SUBSYS_ADMIN {
+                return;
+            }
+
+            let operation = call.operation.to_string();
+            if let Err(e) = call.log_config.frontier.mark_visited(crawl_id, &operation, url).await {
+                warn!("Failed to mark {url} visited in the frontier of crawl {crawl_id}: {e}");
+            }
+        })
+    }
+}
+
+/// Journal the start and outcome of each operation for its crawl.
+pub struct Journaling;
+
+impl Middleware for Journaling {
+    fn before<'a>(&'a self, call: &'a mut OperationCall) -> BoxFuture<'a, Result<Option<Response>, LambdaError>> {
+        Box::pin(async move {
+            if let Some(crawl_id) = call.crawl_id() {
+                let event = CrawlEvent::OperationStarted {
+                    operation: call.operation.to_string(),
+                    url: call.request.url.clone(),
+                };
+                call.log_config.journal.record(crawl_id, event).await;
+            }
+
+            Ok(None)
+        })
+    }
+
+    fn after<'a>(
+        &'a self,
+        call: &'a OperationCall,
+        result: &'a mut Result<Response, LambdaError>,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let Some(crawl_id) = call.crawl_id() else {
+                return;
+            };
+
+            let event = match result {
+                Ok(response) => CrawlEvent::OperationCompleted {
+                    operation: call.operation.to_string(),
+                    next_requests: response.next_requests.len(),
+                },
+                Err(e) => CrawlEvent::OperationFailed {
+                    operation: call.operation.to_string(),
+                    error: e.to_string(),
+                },
+            };
+            call.log_config.journal.record(crawl_id, event).await;
+        })
+    }
+}
+
+/// Count operations by outcome, and record how long each took and how much of the Lambda time budget it used.
+///
+/// The budget is the time that was left before the Lambda deadline when the operation entered the pipeline, so an
+/// operation that used 100% of it would have been cut off by the timeout.
+pub struct OperationMetrics;
+
+impl Middleware for OperationMetrics {
+    fn after<'a>(
+        &'a self,
+        call: &'a OperationCall,
+        result: &'a mut Result<Response, LambdaError>,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let outcome = if result.is_ok() {
+                OUTCOME_SUCCESS
+            } else {
+                OUTCOME_FAILURE
+            };
+            let operation = call.operation.to_string();
+            let tenant = call.request.crawl.tenant.as_deref();
+            metrics::registry().increment(
+                METRIC_OPERATIONS,
+                &metrics::with_tenant(&[(DIMENSION_OPERATION, &operation), (DIMENSION_OUTCOME, outcome)], tenant),
+                1,
+            );
+
+            let elapsed = call.started_at.elapsed();
+            let dimensions = metrics::with_tenant(&[(DIMENSION_OPERATION, operation.as_str())], tenant);
+            metrics::registry().observe(
+                METRIC_OPERATION_DURATION,
+                &dimensions,
+                Unit::Milliseconds,
+                elapsed.as_secs_f64() * 1000.0,
+            );
+
+            if let Some(percent) = call.budget.and_then(|budget| budget_used_percent(elapsed, budget)) {
+                metrics::registry().observe(METRIC_OPERATION_BUDGET_USED, &dimensions, Unit::Percent, percent);
+            }
+        })
+    }
+}
+
+/// Return the percentage of `budget` that `elapsed` used, or `None` if there was no budget to use.
+fn budget_used_percent(elapsed: Duration, budget: Duration) -> Option<f64> {
+    if budget.is_zero() {
+        return None;
+    }
+
+    Some(elapsed.as_secs_f64() / budget.as_secs_f64() * 100.0)
+}
+
+/// Estimate what each operation cost, adding it to its crawl's record and its portal's spend for the month; see
+/// [`cost`][crate::cost].
+pub struct CostAccounting;
+
+impl Middleware for CostAccounting {
+    fn after<'a>(
+        &'a self,
+        call: &'a OperationCall,
+        _result: &'a mut Result<Response, LambdaError>,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let portal = call.operation.subsystem();
+            let tenant = call.request.crawl.tenant.as_deref();
+            cost::record_operation(&call.log_config, portal, tenant, call.crawl_id(), call.started_at.elapsed()).await;
+        })
+    }
+}
+
+/// Re-enqueue requests whose operation failed with a [`RetryableError`], delayed as the error asks.
+///
+/// The operation then counts as a success, so the message is consumed rather than redelivered. The re-enqueued
+/// request records the failed attempt in its crawl parameters. Once a request has been re-enqueued `max_attempts`
+/// times, the error is replaced with a [`RetriesExhaustedError`] carrying that history and passed through, so the
+/// message is dead-lettered.
+pub struct Retry {
+    max_attempts: u32,
+}
+
+impl Retry {
+    /// Create a middleware that re-enqueues a request at most `max_attempts` times.
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+        }
+    }
+}
+
+impl Middleware for Retry {
+    fn after<'a>(
+        &'a self,
+        call: &'a OperationCall,
+        result: &'a mut Result<Response, LambdaError>,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let Err(e) = result else {
+                return;
+            };
+
+            let Some(retryable) = e.downcast_ref::<RetryableError>() else {
+                return;
+            };
+
+            if call.request.crawl.attempt >= self.max_attempts {
+                let exhausted = RetriesExhaustedError {
+                    history: call.request.crawl.failed_attempts.clone(),
+                    error: retryable.clone(),
+                };
+                warn!("{} gave up: {exhausted}", call.operation);
+                *result = Err(exhausted.into());
+                return;
+            }
+
+            let crawl = call.request.crawl.retry(FailedAttempt::new(retryable));
+            let delay_seconds = retryable.delay.seconds(crawl.attempt);
+            let condition = retryable.condition;
+            info!("Deferring {} for {delay_seconds}s (attempt {}): {retryable}", call.operation, crawl.attempt);
+
+            metrics::registry().increment(METRIC_OPERATIONS_DEFERRED, &[(DIMENSION_CONDITION, condition)], 1);
+            if let Some(crawl_id) = call.crawl_id() {
+                let event = CrawlEvent::OperationDeferred {
+                    operation: call.operation.to_string(),
+                    condition: condition.to_string(),
+                    attempt: crawl.attempt,
+                    delay_seconds,
+                };
+                call.log_config.journal.record(crawl_id, event).await;
+            }
+
+            *result = Ok(Response {
+                next_requests: vec![NextRequest {
+                    operation: call.operation,
+                    url: call.request.url.clone(),
+                    link_text: None,
+                    crawl,
+                    delay_seconds,
+                }],
+            });
+        })
+    }
+}
+
+/// Drop the next requests of an operation whose crawl budget is used up. The operation itself still runs.
+pub struct BudgetEnforcement;
+
+impl Middleware for BudgetEnforcement {
+    fn after<'a>(
+        &'a self,
+        call: &'a OperationCall,
+        result: &'a mut Result<Response, LambdaError>,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            if let Ok(response) = result {
+                if call.request.crawl.budget_exhausted() && !response.next_requests.is_empty() {
+                    info!(
+                        "Crawl {} budget exhausted; dropping {} next request(s)",
+                        call.crawl_id().unwrap_or_default(),
+                        response.next_requests.len()
+                    );
+                    response.next_requests.clear();
+                }
+            }
+        })
+    }
+}
+
+/// Record the next requests an operation emits as links in the [link graph][crate::link_graph].
+///
+/// This runs closest to the operation, so it sees the requests the operation emitted itself: before a deferral
+/// replaces them, and before an exhausted budget drops them.
+pub struct LinkCapture;
+
+impl Middleware for LinkCapture {
+    fn after<'a>(
+        &'a self,
+        call: &'a OperationCall,
+        result: &'a mut Result<Response, LambdaError>,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let (Ok(response), Some(crawl_id)) = (result, call.crawl_id()) else {
+                return;
+            };
+
+            for (position, next_request) in response.next_requests.iter().enumerate() {
+                let Some(url) = next_request.url.as_deref() else {
+                    continue;
+                };
+
+                // Match the URL the child request will be sent with.
+                let link = Link {
+                    crawl_id: crawl_id.to_string(),
+                    parent_url: call.request.url.clone(),
+                    parent_operation: call.operation.to_string(),
+                    child_url: normalize_url(url).unwrap_or_else(|_| url.to_string()),
+                    link_text: next_request.link_text.clone(),
+                    position,
+                    operation: next_request.operation.to_string(),
+                };
+                call.log_config.links.record(&link).await;
+            }
+        })
+    }
+}
+
+/// Record the page each next request was found on as its [referer][crate::shapes::CrawlParameters::referer], for
+/// crawls with referer chaining turned on.
+///
+/// Reqwest's own referer handling only covers redirects within a single client, and every operation runs with a new
+/// one, so the navigation path is carried in the requests instead. This runs closest to the operation, so requests
+/// re-enqueued by [`Retry`] keep the referer they were first sent with.
+pub struct RefererChaining;
+
+impl Middleware for RefererChaining {
+    fn after<'a>(
+        &'a self,
+        call: &'a OperationCall,
+        result: &'a mut Result<Response, LambdaError>,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            if let Ok(response) = result {
+                chain_referers(call.request.url.as_deref(), &mut response.next_requests);
+            }
+        })
+    }
+}
+
+/// Set `referer` as the referer of the next requests with referer chaining turned on that don't already have one.
+fn chain_referers(referer: Option<&str>, next_requests: &mut [NextRequest]) {
+    let Some(referer) = referer else {
+        return;
+    };
+
+    for next_request in next_requests.iter_mut() {
+        let crawl = &mut next_request.crawl;
+        if crawl.referer_chain && crawl.referer.is_none() {
+            crawl.referer = Some(referer.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{budget_used_percent, chain_referers, KillSwitch},
+        crate::{
+            shapes::{CrawlParameters, NextRequest, Operation},
+            webs::WebsOperation,
+        },
+        std::{str::FromStr, time::Duration},
+    };
+
+    #[test]
+    fn budget_used() {
+        assert_eq!(budget_used_percent(Duration::from_secs(3), Duration::from_secs(12)), Some(25.0));
+        assert_eq!(budget_used_percent(Duration::from_secs(15), Duration::from_secs(12)), Some(125.0));
+        assert_eq!(budget_used_percent(Duration::from_secs(1), Duration::ZERO), None);
+    }
+
+    #[test]
+    fn kill_switch() {
+        let listing = Operation::Webs(WebsOperation::FetchOpportunityListingPage);
+        let detail = Operation::Webs(WebsOperation::FetchOpportunityDetailPage);
+        let canary = Operation::from_str("Canary:Check").unwrap();
+
+        let switch = KillSwitch::new(" Webs:FetchOpportunityDetailPage , ,");
+        assert!(switch.is_disabled(&detail));
+        assert!(!switch.is_disabled(&listing));
+        assert!(!switch.is_disabled(&canary));
+
+        let switch = KillSwitch::new("Webs");
+        assert!(switch.is_disabled(&detail) && switch.is_disabled(&listing));
+        assert!(!switch.is_disabled(&canary));
+
+        assert_eq!(KillSwitch::new(""), KillSwitch::default());
+    }
+
+    #[test]
+    fn referer_chaining() {
+        let next_request = |referer_chain: bool, referer: Option<&str>| NextRequest {
+            operation: Operation::Webs(WebsOperation::FetchOpportunityDetailPage),
+            url: Some("https://example.gov/bids/22-015".to_string()),
+            link_text: None,
+            crawl: CrawlParameters {
+                referer_chain,
+                referer: referer.map(str::to_string),
+                ..CrawlParameters::default()
+            },
+            delay_seconds: 0,
+        };
+
+        let mut next_requests =
+            vec![next_request(true, None), next_request(false, None), next_request(true, Some("https://example.gov/"))];
+        chain_referers(Some("https://example.gov/bids?page=2"), &mut next_requests);
+        assert_eq!(
+            next_requests.iter().map(|next| next.crawl.referer.as_deref()).collect::<Vec<_>>(),
+            [Some("https://example.gov/bids?page=2"), None, Some("https://example.gov/")]
+        );
+
+        let mut next_requests = vec![next_request(true, None)];
+        chain_referers(None, &mut next_requests);
+        assert_eq!(next_requests[0].crawl.referer, None);
+    }
+}
@@ -0,0 +1,790 @@
+//! The normalized opportunity model.
+//!
+//! Each portal's parsers convert what they find into an [`Opportunity`], so everything downstream of the crawler sees
+//! the same shape regardless of where an opportunity was published.
+//!
+//! Most portals publish in English with amounts in US dollars and leave [`language`][Opportunity::language],
+//! [`translations`][Opportunity::translations], and [`estimated_value`][Opportunity::estimated_value] unset; those
+//! fields are omitted from serialized records when empty, so records from such portals keep their original shape.
+use {
+    crate::{
+        quality::QualityReport,
+        soup::{
+            structured_data::{item_types, structured_data},
+            NodeExt, QueryBuilderExt,
+        },
+        BoxError, CRATE_VERSION,
+    },
+    chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc},
+    log::*,
+    markup5ever_rcdom::{Handle, RcDom},
+    reqwest::Url,
+    serde::{Deserialize, Serialize},
+    serde_json::Value,
+};
+
+/// Date formats used by US portals, tried in order. Two-digit years come first, since `%Y` would read `22` as the year
+/// 22 rather than 2022. Month names are matched in English, after [`DateLocale::to_english`].
+const DATE_FORMATS: &[&str] = &["%m/%d/%y", "%m/%d/%Y", "%Y-%m-%d", "%B %d, %Y", "%B %d %Y", "%d %B %Y"];
+
+/// Date and time formats of structured data without a UTC offset, tried in order after RFC 3339.
+const ISO_DATE_TIME_FORMATS: &[&str] = &["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"];
+
+/// schema.org types of structured data items describing a solicitation, in order of preference.
+const POSTING_TYPES: &[&str] = &["Demand", "Offer"];
+
+/// The currency of structured data prices that don't name one.
+const DEFAULT_CURRENCY: &str = "USD";
+
+/// Minimum number of digits in a line for it to be taken as a phone number.
+const MIN_PHONE_DIGITS: usize = 7;
+
+/// Date and time formats used by US portals, tried in order.
+const DATE_TIME_FORMATS: &[&str] = &[
+    "%m/%d/%y %I:%M %p",
+    "%m/%d/%Y %I:%M %p",
+    "%m/%d/%Y %I:%M:%S %p",
+    "%m/%d/%Y %H:%M",
+    "%Y-%m-%d %H:%M",
+    "%B %d, %Y %I:%M %p",
+    "%d %B %Y %H:%M",
+];
+
+/// French month names and their abbreviations, with and without accents, and their English equivalents.
+const FRENCH_MONTHS: &[(&str, &str)] = &[
+    ("janvier", "January"),
+    ("janv.", "Jan"),
+    ("février", "February"),
+    ("fevrier", "February"),
+    ("févr.", "Feb"),
+    ("fevr.", "Feb"),
+    ("mars", "March"),
+    ("avril", "April"),
+    ("avr.", "Apr"),
+    ("mai", "May"),
+    ("juin", "June"),
+    ("juillet", "July"),
+    ("juil.", "Jul"),
+    ("août", "August"),
+    ("aout", "August"),
+    ("septembre", "September"),
+    ("sept.", "Sep"),
+    ("octobre", "October"),
+    ("oct.", "Oct"),
+    ("novembre", "November"),
+    ("nov.", "Nov"),
+    ("décembre", "December"),
+    ("decembre", "December"),
+    ("déc.", "Dec"),
+    ("dec.", "Dec"),
+];
+
+/// The language a portal writes month names in.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum DateLocale {
+    /// English month names, e.g. `November 2, 2022`.
+    #[default]
+    English,
+
+    /// French month names, e.g. `2 novembre 2022` or `1er mai 2023`.
+    French,
+}
+
+impl DateLocale {
+    /// Indicates whether month names are in English.
+    pub fn is_english(&self) -> bool {
+        *self == Self::English
+    }
+
+    /// Rewrite month names in `text` in English, so they can be parsed with Chrono's `%B`.
+    pub fn to_english(self, text: &str) -> String {
+        match self {
+            Self::English => text.to_string(),
+            Self::French => text
+                .split(' ')
+                .map(|word| {
+                    let lower = word.to_lowercase();
+                    if lower == "1er" {
+                        return "1";
+                    }
+                    FRENCH_MONTHS.iter().find(|(french, _)| *french == lower).map_or(word, |(_, english)| *english)
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+}
+
+/// A contracting opportunity.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Opportunity {
+    /// The portal (subsystem) the opportunity was found on.
+    pub portal: String,
+
+    /// The URL of the opportunity's page on the portal.
+    pub url: String,
+
+    /// The title of the opportunity.
+    pub title: String,
+
+    /// The reference or bid number assigned by the issuer.
+    pub reference: Option<String>,
+
+    /// The organization issuing the opportunity.
+    pub organization: Option<String>,
+
+    /// The person to contact about the opportunity.
+    pub contact: Contact,
+
+    /// Further contacts found in the text of the page and its attachments by [contact extraction][crate::contacts].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub contacts: Vec<Contact>,
+
+    /// The date the opportunity was published.
+    pub published_on: Option<NaiveDate>,
+
+    /// When responses are due, in the portal's local time.
+    pub due_at: Option<NaiveDateTime>,
+
+    /// The description of the opportunity.
+    pub description: Option<String>,
+
+    /// The commodity codes the opportunity is classified under.
+    pub commodity_codes: Vec<String>,
+
+    /// The counties the opportunity covers.
+    pub counties: Vec<String>,
+
+    /// Documents published with the opportunity.
+    pub documents: Vec<Document>,
+
+    /// The language of the title and description as an ISO 639-1 code, e.g. `fr`, if the portal states it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+
+    /// The title and description in other languages, for portals that publish in more than one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub translations: Vec<Translation>,
+
+    /// The issuer's estimate of the contract's value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub estimated_value: Option<Amount>,
+
+    /// The contracts the solicitation was [awarded][crate::award] as, read from its detail page once the portal shows
+    /// them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub awards: Vec<Award>,
+
+    /// The parser that produced the record, so records from a faulty parser version can be found and re-parsed.
+    /// Parsers leave this unset; the operation running the parser stamps it with [`with_parser`][Self::with_parser].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parsed_by: Option<ParsedBy>,
+
+    /// The data quality assessment, stored by [`record_quality`][crate::quality::record_quality] after parsing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<QualityReport>,
+
+    /// The team whose crawl found the opportunity, when several share the deployment. Stamped from the crawl with
+    /// [`with_tenant`][Self::with_tenant].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+
+    /// When the Internet Archive captured the page the record was parsed from, for records [backfilled][crate::archive]
+    /// from its snapshots rather than crawled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived_at: Option<DateTime<Utc>>,
+
+    /// The URL of the record this one [duplicates][crate::duplicates] on another portal, which consumers should alert
+    /// on instead. Set from the opportunity store, never by parsers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<String>,
+
+    /// Whether the opportunity is still published. Closed records carry only the portal and URL.
+    #[serde(default, skip_serializing_if = "OpportunityStatus::is_open")]
+    pub status: OpportunityStatus,
+
+    /// How far the solicitation has progressed, as [inferred][crate::lifecycle] from its detail page and by
+    /// reconciliation. Never set by parsers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage: Option<SolicitationStage>,
+
+    /// The stages the solicitation has passed through, oldest first. Only records read back from the opportunity store
+    /// carry it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stage_history: Vec<StageTransition>,
+}
+
+/// Whether an opportunity is still published.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum OpportunityStatus {
+    /// The opportunity is published on its portal.
+    #[default]
+    Open,
+
+    /// The portal no longer publishes the opportunity; see [`closure`][crate::closure].
+    Closed,
+
+    /// The opportunity's due date has passed; see [`reconcile`][crate::reconcile].
+    Expired,
+}
+
+impl OpportunityStatus {
+    /// Indicates whether the opportunity is still published.
+    pub fn is_open(&self) -> bool {
+        *self == Self::Open
+    }
+}
+
+/// A stage in the lifecycle of a solicitation, in the order a solicitation passes through them.
+///
+/// Stages only advance: a solicitation can skip stages, but never returns to an earlier one.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum SolicitationStage {
+    /// The solicitation is published and accepting questions and responses.
+    Posted,
+
+    /// The issuer has published an amendment or addendum.
+    Amended,
+
+    /// The deadline for questions has passed, but responses are still accepted.
+    QuestionsClosed,
+
+    /// Responses are no longer accepted.
+    Closed,
+
+    /// A contract has been awarded.
+    Awarded,
+
+    /// The issuer has cancelled the solicitation.
+    Cancelled,
+}
+
+impl SolicitationStage {
+    /// Every stage, in lifecycle order.
+    pub const ALL: [Self; 6] =
+        [Self::Posted, Self::Amended, Self::QuestionsClosed, Self::Closed, Self::Awarded, Self::Cancelled];
+
+    /// Return the name of the stage, as serialized.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Posted => "Posted",
+            Self::Amended => "Amended",
+            Self::QuestionsClosed => "QuestionsClosed",
+            Self::Closed => "Closed",
+            Self::Awarded => "Awarded",
+            Self::Cancelled => "Cancelled",
+        }
+    }
+}
+
+/// A solicitation's move to a later stage.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct StageTransition {
+    /// The stage the solicitation moved to.
+    pub stage: SolicitationStage,
+
+    /// When the move was recorded.
+    pub at: DateTime<Utc>,
+
+    /// What the move was inferred from, e.g. `due at 2023-05-01 14:00:00`.
+    pub reason: String,
+}
+
+impl Opportunity {
+    /// Stamp the opportunity with the parser that produced it and the running crate version.
+    pub fn with_parser(mut self, parser: impl Into<String>, parser_version: u32) -> Self {
+        self.parsed_by = Some(ParsedBy {
+            parser: parser.into(),
+            parser_version,
+            crate_version: CRATE_VERSION.to_string(),
+        });
+        self
+    }
+
+    /// Attribute the opportunity to the team the crawl that found it was run for, if any.
+    pub fn with_tenant(mut self, tenant: Option<&str>) -> Self {
+        self.tenant = tenant.map(str::to_string);
+        self
+    }
+
+    /// Build an opportunity from the first schema.org `Demand` or `Offer` among a page's
+    /// [structured data][crate::soup::structured_data], or `None` if there is no such item with a name.
+    pub fn from_structured_data(items: &[Value], page_url: &Url, date_locale: DateLocale) -> Option<Self> {
+        let item = POSTING_TYPES
+            .iter()
+            .find_map(|posting_type| items.iter().find(|item| item_types(item).contains(posting_type)))?;
+        let text = |names: &[&str]| names.iter().find_map(|name| value_text(item.get(name)?));
+        let date_time = |names: &[&str]| text(names).and_then(|text| structured_date_time(&text, date_locale));
+
+        let mut opportunity = Self {
+            url: page_url.to_string(),
+            title: text(&["name"])?,
+            reference: text(&["identifier", "sku"]),
+            organization: text(&["buyer", "offeredBy", "provider", "publisher", "sourceOrganization"]),
+            published_on: date_time(&["datePosted", "datePublished", "validFrom", "availabilityStarts"])
+                .map(|date_time| date_time.date()),
+            due_at: date_time(&["validThrough", "availabilityEnds", "expires"]),
+            description: text(&["description"]),
+            ..Default::default()
+        };
+
+        if let Some(contact) = nested_item(item, "contactPoint") {
+            let text = |name: &str| contact.get(name).and_then(value_text);
+            opportunity.contact = Contact {
+                name: text("name"),
+                email: text("email").map(|email| email.trim_start_matches("mailto:").to_string()),
+                phone: text("telephone"),
+            };
+        }
+
+        let price = nested_item(item, "priceSpecification").unwrap_or(item);
+        if let Some(amount) = price.get("price").and_then(value_text) {
+            let currency = price.get("priceCurrency").and_then(value_text);
+            opportunity.estimated_value = parse_amount(&amount, currency.as_deref().unwrap_or(DEFAULT_CURRENCY));
+        }
+
+        for name in ["subjectOf", "associatedMedia"] {
+            for document in item.get(name).map(values).unwrap_or_default() {
+                let href = document.get("contentUrl").or_else(|| document.get("url")).unwrap_or(document);
+                let Some(url) = href.as_str().and_then(|href| page_url.join(href).ok()) else {
+                    continue;
+                };
+
+                if !opportunity.documents.iter().any(|existing| existing.url == url.as_str()) {
+                    opportunity.documents.push(Document {
+                        name: document.get("name").and_then(value_text).unwrap_or_else(|| url.to_string()),
+                        url: url.to_string(),
+                    });
+                }
+            }
+        }
+
+        Some(opportunity)
+    }
+
+    /// Fill in the fields this opportunity leaves empty from another record of it, e.g. one scraped from the same
+    /// page. The parser, quality, tenant, and status are left alone.
+    pub fn fill_from(&mut self, other: Opportunity) {
+        fn fill<T>(field: &mut Option<T>, other: Option<T>) {
+            if field.is_none() {
+                *field = other;
+            }
+        }
+
+        fn fill_list<T>(field: &mut Vec<T>, other: Vec<T>) {
+            if field.is_empty() {
+                *field = other;
+            }
+        }
+
+        if self.portal.is_empty() {
+            self.portal = other.portal;
+        }
+        if self.url.is_empty() {
+            self.url = other.url;
+        }
+        if self.title.is_empty() {
+            self.title = other.title;
+        }
+        fill(&mut self.reference, other.reference);
+        fill(&mut self.organization, other.organization);
+        fill(&mut self.contact.name, other.contact.name);
+        fill(&mut self.contact.email, other.contact.email);
+        fill(&mut self.contact.phone, other.contact.phone);
+        fill(&mut self.published_on, other.published_on);
+        fill(&mut self.due_at, other.due_at);
+        fill(&mut self.description, other.description);
+        fill_list(&mut self.commodity_codes, other.commodity_codes);
+        fill_list(&mut self.counties, other.counties);
+        fill_list(&mut self.documents, other.documents);
+        fill(&mut self.language, other.language);
+        fill_list(&mut self.translations, other.translations);
+        fill(&mut self.estimated_value, other.estimated_value);
+        fill_list(&mut self.awards, other.awards);
+    }
+}
+
+/// The code that produced a record.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ParsedBy {
+    /// The parser's name, e.g. `FlVbs:Advertisement`.
+    pub parser: String,
+
+    /// The version of the parser's subsystem.
+    pub parser_version: u32,
+
+    /// The version of the crate.
+    pub crate_version: String,
+}
+
+/// The title and description of an opportunity in another language.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Translation {
+    /// The language as an ISO 639-1 code, e.g. `en`.
+    pub language: String,
+
+    /// The title in this language.
+    pub title: Option<String>,
+
+    /// The description in this language.
+    pub description: Option<String>,
+}
+
+/// An amount of money.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Amount {
+    /// The amount in hundredths of the currency unit (e.g. cents), so it is exact.
+    pub minor_units: i64,
+
+    /// The ISO 4217 currency code, e.g. `CAD`.
+    pub currency: String,
+}
+
+/// A contract awarded from a solicitation.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Award {
+    /// The supplier the contract was awarded to.
+    pub awardee: String,
+
+    /// The value of the contract.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<Amount>,
+
+    /// The date the contract was awarded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub awarded_on: Option<NaiveDate>,
+
+    /// The number the issuer assigned to the contract.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contract_number: Option<String>,
+}
+
+/// A contact for an opportunity.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Contact {
+    /// The contact's name.
+    pub name: Option<String>,
+
+    /// The contact's email address.
+    pub email: Option<String>,
+
+    /// The contact's phone number.
+    pub phone: Option<String>,
+}
+
+impl Contact {
+    /// Split lines of contact details into a name, email address, and phone number. The first line of each kind wins.
+    pub fn from_lines(lines: &[String]) -> Self {
+        let mut contact = Self::default();
+
+        for line in lines {
+            if line.contains('@') {
+                contact.email.get_or_insert_with(|| line.clone());
+            } else if is_phone_number(line) {
+                contact.phone.get_or_insert_with(|| line.clone());
+            } else {
+                contact.name.get_or_insert_with(|| line.clone());
+            }
+        }
+
+        contact
+    }
+}
+
+/// A document published with an opportunity, such as the solicitation itself or an addendum.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Document {
+    /// The name of the document as shown on the portal.
+    pub name: String,
+
+    /// The URL the document can be downloaded from.
+    pub url: String,
+}
+
+/// Collapse runs of whitespace into single spaces and trim the ends, returning `None` if nothing is left.
+pub fn clean_text(text: &str) -> Option<String> {
+    let cleaned = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if cleaned.is_empty() {
+        None
+    } else {
+        Some(cleaned)
+    }
+}
+
+/// Return the lines of text in an element, split at `<br>` tags, cleaned with [`clean_text`] and omitting blank lines.
+pub fn text_lines(element: &Handle) -> Vec<String> {
+    fn collect(node: &Handle, text: &mut String) {
+        for child in node.children() {
+            if child.is_text() {
+                // Line breaks in the markup are just whitespace; only `<br>` ends a line.
+                text.push_str(&child.text().replace(['\r', '\n'], " "));
+            } else if child.is_element() && child.name() == "br" {
+                text.push('\n');
+            } else {
+                collect(&child, text);
+            }
+        }
+    }
+
+    let mut text = String::new();
+    collect(element, &mut text);
+    text.lines().filter_map(clean_text).collect()
+}
+
+/// Indicates whether a line looks like a phone number, e.g. `(954) 777-4000` or `850.488.8440 x2`.
+pub fn is_phone_number(line: &str) -> bool {
+    let digits = line.chars().filter(char::is_ascii_digit).count();
+    digits >= MIN_PHONE_DIGITS && line.chars().all(|c| c.is_ascii_digit() || " ()-.+xX".contains(c))
+}
+
+/// Parse an amount of money, e.g. `$1,250,000.00`, `1 250 000,00 $`, or `CAD 25,000`.
+///
+/// Both English and French conventions are accepted: a comma or period followed by one or two trailing digits is the
+/// decimal separator, and any other commas, periods, or spaces group thousands. A three-letter currency code in the
+/// text takes precedence over `default_currency`.
+pub fn parse_amount(text: &str, default_currency: &str) -> Option<Amount> {
+    let currency = text
+        .split(|c: char| c.is_whitespace() || c == '$')
+        .find(|word| word.len() == 3 && word.chars().all(|c| c.is_ascii_uppercase()))
+        .unwrap_or(default_currency);
+
+    let number: String = text.chars().filter(|c| c.is_ascii_digit() || *c == ',' || *c == '.').collect();
+    let (whole, fraction) = match number.rfind([',', '.']) {
+        Some(i) if (2..=3).contains(&(number.len() - i)) => (&number[..i], &number[i + 1..]),
+        _ => (number.as_str(), ""),
+    };
+
+    let whole: String = whole.chars().filter(char::is_ascii_digit).collect();
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+
+    let whole: i64 = if whole.is_empty() {
+        0
+    } else {
+        whole.parse().ok()?
+    };
+    let fraction: i64 = format!("{fraction:0<2}").parse().ok()?;
+
+    Some(Amount {
+        minor_units: whole.checked_mul(100)?.checked_add(fraction)?,
+        currency: currency.to_string(),
+    })
+}
+
+/// Parse a date as written by a US portal, e.g. `11/02/2022`, `11/02/22`, or `November 2, 2022`, with month names in
+/// the given language.
+pub fn parse_date(text: &str, locale: DateLocale) -> Option<NaiveDate> {
+    let text = locale.to_english(&clean_text(text)?);
+    DATE_FORMATS.iter().find_map(|format| NaiveDate::parse_from_str(&text, format).ok())
+}
+
+/// Parse a date and time as written by a US portal, e.g. `11/30/2022 2:00 PM`.
+///
+/// Month names are read in the given language. A bare date is taken to mean the end of that day, since that is how
+/// portals treat a due date without a time.
+pub fn parse_date_time(text: &str, locale: DateLocale) -> Option<NaiveDateTime> {
+    let text = locale.to_english(&clean_text(text)?);
+    DATE_TIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&text, format).ok())
+        .or_else(|| Some(parse_date(&text, DateLocale::English)?.and_time(NaiveTime::from_hms_opt(23, 59, 59)?)))
+}
+
+/// Parse a solicitation's page, preferring what its [structured data][crate::soup::structured_data] says and scraping
+/// it with `scrape` for the rest.
+///
+/// When the page describes the solicitation as a schema.org `Demand` or `Offer`, the scraped record only fills in the
+/// fields the structured data leaves empty, and a scraping failure is logged and ignored. Otherwise the page is
+/// scraped as if it had no structured data.
+pub fn parse_with_structured_data<F>(
+    document: &RcDom,
+    page_url: &Url,
+    date_locale: DateLocale,
+    scrape: F,
+) -> Result<Opportunity, BoxError>
+where
+    F: FnOnce() -> Result<Opportunity, BoxError>,
+{
+    let Some(mut opportunity) = Opportunity::from_structured_data(&structured_data(document), page_url, date_locale)
+    else {
+        return scrape();
+    };
+
+    match scrape() {
+        Ok(scraped) => opportunity.fill_from(scraped),
+        Err(e) => debug!("Using structured data alone for {page_url}: {e}"),
+    }
+
+    Ok(opportunity)
+}
+
+/// Return the text of a structured data value: a string cleaned with [`clean_text`], a number, the first such value
+/// of an array, or the name or value of a nested item.
+fn value_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => clean_text(text),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Array(values) => values.iter().find_map(value_text),
+        Value::Object(_) => ["name", "value", "@value"].iter().find_map(|key| value_text(value.get(key)?)),
+        _ => None,
+    }
+}
+
+/// Return the values of a structured data property, which may be a single value or an array.
+fn values(value: &Value) -> Vec<&Value> {
+    match value {
+        Value::Array(values) => values.iter().collect(),
+        value => vec![value],
+    }
+}
+
+/// Return the first nested item given for a structured data property.
+fn nested_item<'a>(item: &'a Value, name: &str) -> Option<&'a Value> {
+    values(item.get(name)?).into_iter().find(|value| value.is_object())
+}
+
+/// Parse a structured data date and time, e.g. `2022-11-30T14:00:00-08:00`, keeping the time as written. Other forms
+/// are read as [`parse_date_time`] reads them, so a bare date is the end of that day.
+fn structured_date_time(text: &str, locale: DateLocale) -> Option<NaiveDateTime> {
+    DateTime::parse_from_rfc3339(text)
+        .ok()
+        .map(|date_time| date_time.naive_local())
+        .or_else(|| ISO_DATE_TIME_FORMATS.iter().find_map(|format| NaiveDateTime::parse_from_str(text, format).ok()))
+        .or_else(|| parse_date_time(text, locale))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            clean_text, is_phone_number, parse_amount, parse_date, parse_date_time, parse_with_structured_data, Amount,
+            Contact, DateLocale, Opportunity,
+        },
+        crate::soup::parse_html_str,
+        chrono::NaiveDate,
+        reqwest::Url,
+    };
+
+    #[test]
+    fn dates() {
+        let day = NaiveDate::from_ymd_opt(2022, 11, 2).unwrap();
+        let english = DateLocale::English;
+        assert_eq!(parse_date(" 11/02/2022 ", english), Some(day));
+        assert_eq!(parse_date("11/02/22", english), Some(day));
+        assert_eq!(parse_date("November 2, 2022", english), Some(day));
+        assert_eq!(parse_date("Nov 2, 2022", english), Some(day));
+        assert_eq!(parse_date("TBD", english), None);
+
+        assert_eq!(parse_date_time("11/02/2022  2:00 PM", english), Some(day.and_hms_opt(14, 0, 0).unwrap()));
+        assert_eq!(parse_date_time("11/02/2022", english), Some(day.and_hms_opt(23, 59, 59).unwrap()));
+        assert_eq!(parse_date_time("November 2, 2022 2:00 PM", english), Some(day.and_hms_opt(14, 0, 0).unwrap()));
+        assert_eq!(parse_date_time("", english), None);
+
+        // French month names are only understood when the portal is configured for them.
+        let french = DateLocale::French;
+        assert_eq!(parse_date("2 novembre 2022", french), Some(day));
+        assert_eq!(parse_date("2 Nov. 2022", french), Some(day));
+        assert_eq!(parse_date("1er mai 2023", french), NaiveDate::from_ymd_opt(2023, 5, 1));
+        assert_eq!(parse_date("11/02/2022", french), Some(day));
+        assert_eq!(parse_date("2 novembre 2022", english), None);
+        assert_eq!(parse_date_time("2 novembre 2022 14:00", french), Some(day.and_hms_opt(14, 0, 0).unwrap()));
+
+        assert_eq!(clean_text("  a\n\t b  ").as_deref(), Some("a b"));
+        assert_eq!(clean_text(" \n "), None);
+    }
+
+    #[test]
+    fn contacts() {
+        assert!(is_phone_number("(954) 777-4000"));
+        assert!(is_phone_number("850.488.8440 x2"));
+        assert!(!is_phone_number("Maria Lopez"));
+        assert!(!is_phone_number("Room 101"));
+
+        let lines = ["Maria Lopez", "(954) 777-4000", "maria.lopez@dot.state.fl.us", "Room 101"].map(String::from);
+        let contact = Contact::from_lines(&lines);
+        assert_eq!(contact.name.as_deref(), Some("Maria Lopez"));
+        assert_eq!(contact.email.as_deref(), Some("maria.lopez@dot.state.fl.us"));
+        assert_eq!(contact.phone.as_deref(), Some("(954) 777-4000"));
+    }
+
+    #[test]
+    fn amounts() {
+        let amount = |minor_units, currency: &str| {
+            Some(Amount {
+                minor_units,
+                currency: currency.to_string(),
+            })
+        };
+
+        assert_eq!(parse_amount("$1,250,000.00", "USD"), amount(125_000_000, "USD"));
+        assert_eq!(parse_amount("1 250 000,00 $", "CAD"), amount(125_000_000, "CAD"));
+        assert_eq!(parse_amount("1\u{a0}250\u{a0}000,5 $", "CAD"), amount(125_000_050, "CAD"));
+        assert_eq!(parse_amount("CAD 25,000", "USD"), amount(2_500_000, "CAD"));
+        assert_eq!(parse_amount("25.000 EUR", "CAD"), amount(2_500_000, "EUR"));
+        assert_eq!(parse_amount("$.99", "USD"), amount(99, "USD"));
+        assert_eq!(parse_amount("To be determined", "CAD"), None);
+    }
+
+    #[test]
+    fn structured_data() {
+        let page_url = Url::parse("https://bids.example.gov/bid/1").unwrap();
+        let document = parse_html_str(
+            r#"<script type="application/ld+json">{"@type": "Demand", "name": "Street Paving", "identifier": 22014,
+                   "validThrough": "2022-11-30T14:00:00-08:00", "datePosted": "2022-11-02",
+                   "buyer": {"@type": "GovernmentOrganization", "name": "City of Olympia"},
+                   "contactPoint": [{"email": "mailto:bids@example.gov"}],
+                   "priceSpecification": {"price": 1250000, "priceCurrency": "USD"},
+                   "subjectOf": [{"name": "Plans", "contentUrl": "plans.pdf"}, "plans.pdf"]}</script>
+               <h1>Paving</h1><p class="description">Mill and overlay.</p>"#,
+        );
+        let scrape = |title: &str| {
+            let title = title.to_string();
+            move || {
+                Ok(Opportunity {
+                    portal: "WaLocal".to_string(),
+                    title,
+                    reference: Some("RFB 22-014".to_string()),
+                    description: Some("Mill and overlay.".to_string()),
+                    ..Default::default()
+                })
+            }
+        };
+
+        let opportunity =
+            parse_with_structured_data(&document, &page_url, DateLocale::English, scrape("Paving")).unwrap();
+        assert_eq!(opportunity.portal, "WaLocal");
+        assert_eq!(opportunity.url, page_url.as_str());
+        assert_eq!(opportunity.title, "Street Paving");
+        assert_eq!(opportunity.reference.as_deref(), Some("22014"));
+        assert_eq!(opportunity.organization.as_deref(), Some("City of Olympia"));
+        assert_eq!(opportunity.description.as_deref(), Some("Mill and overlay."));
+        assert_eq!(opportunity.published_on, NaiveDate::from_ymd_opt(2022, 11, 2));
+        assert_eq!(opportunity.due_at, NaiveDate::from_ymd_opt(2022, 11, 30).unwrap().and_hms_opt(14, 0, 0));
+        assert_eq!(opportunity.contact.email.as_deref(), Some("bids@example.gov"));
+        assert_eq!(opportunity.estimated_value.map(|amount| amount.minor_units), Some(125_000_000));
+        assert_eq!(opportunity.documents.len(), 1);
+        assert_eq!(opportunity.documents[0].url, "https://bids.example.gov/bid/plans.pdf");
+
+        let failing = || Err("No title".into());
+        assert_eq!(
+            parse_with_structured_data(&document, &page_url, DateLocale::English, failing).unwrap().title,
+            "Street Paving"
+        );
+
+        let plain = parse_html_str("<h1>Paving</h1>");
+        assert_eq!(
+            parse_with_structured_data(&plain, &page_url, DateLocale::English, scrape("Paving")).unwrap().title,
+            "Paving"
+        );
+        assert!(parse_with_structured_data(&plain, &page_url, DateLocale::English, failing).is_err());
+    }
+}
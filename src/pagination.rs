@@ -0,0 +1,491 @@
+//! Pagination of portal listings.
+//!
+//! Each subsystem describes how its listings are paged with a [`Pagination`] strategy, and asks the strategy for the
+//! page after the one it just parsed:
+//!
+//! * [`NextLink`][Pagination::NextLink]: follow a link to the next page, found by `rel`, class, or text.
+//! * [`Offset`][Pagination::Offset]: advance an offset query parameter by the page size while pages offer more.
+//! * [`Cursor`][Pagination::Cursor]: set a query parameter to the continuation token the page hands out.
+//! * [`Postback`][Pagination::Postback]: submit the ASP.NET postback of a numbered pager (`__doPostBack`).
+//!
+//! Pages reached by URL are fetched by a continuation message built with [`next_page_request`], so every subsystem
+//! emits them the same way. Postbacks have to be submitted with the form of the page before them, so they are followed
+//! within the operation that fetched the first page.
+//!
+//! ASP.NET WebForms keep the state of a listing in the form's view state and the portal session, so two postbacks
+//! submitted in parallel from the same view state fail validation. An operation following postbacks holds a
+//! [`PostbackLease`] for the crawl while it does; another delivery of the same message (or any other operation needing
+//! the same view state) is deferred until the lease is released, and then starts over from a freshly fetched form.
+//! Under load, ASP.NET also rejects postbacks whose view state or event validation has gone stale; see
+//! [`is_postback_rejection`].
+use {
+    crate::{
+        checkpoint::CheckpointStore,
+        httpext::Form,
+        opportunity::clean_text,
+        retry::{RetryDelay, RetryableError},
+        shapes::{CrawlParameters, ListingQuery, NextRequest, Operation},
+        soup::{NodeExt, QueryBuilderExt},
+        BoxError,
+    },
+    chrono::Duration,
+    log::*,
+    markup5ever_rcdom::{Handle, RcDom},
+    reqwest::{StatusCode, Url},
+    serde::{Deserialize, Serialize},
+    std::time::Duration as StdDuration,
+    uuid::{NoContext, Timestamp, Uuid},
+};
+
+const FORM_FIELD_EVENTTARGET: &str = "__EVENTTARGET";
+const FORM_FIELD_EVENTARGUMENT: &str = "__EVENTARGUMENT";
+
+/// The condition reported when a postback lease is held by another operation.
+const CONDITION_VIEW_STATE_IN_USE: &str = "ViewStateInUse";
+
+/// How long a postback lease lasts if its holder never releases it: the longest a Lambda invocation can run.
+const POSTBACK_LEASE_MINUTES: i64 = 15;
+
+/// How long to wait before retrying an operation whose postback lease was held by another.
+const POSTBACK_LEASE_RETRY_DELAY: StdDuration = StdDuration::from_secs(60);
+
+/// Phrases on the error page ASP.NET returns for a postback whose `__VIEWSTATE` or `__EVENTVALIDATION` it rejects.
+const POSTBACK_REJECTION_PHRASES: &[&str] = &[
+    "Invalid postback or callback argument",
+    "Validation of viewstate MAC failed",
+    "The state information is invalid",
+];
+
+/// How a portal's listing is paged.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Pagination {
+    /// Follow the first link matching any of these, in order.
+    NextLink(&'static [NextLink]),
+
+    /// Advance `offset_param` by the page size, taken from `limit_param` or defaulting to `limit`, while the page has
+    /// a link matching `more`. The link's own target is ignored, as such links are often scripts or buttons.
+    Offset {
+        offset_param: &'static str,
+        limit_param: &'static str,
+        limit: usize,
+        more: NextLink,
+    },
+
+    /// Set `param` to the value of the `<input name="{input_name}">` on the page, until a page has none.
+    Cursor {
+        param: &'static str,
+        input_name: &'static str,
+    },
+
+    /// Submit the postback of the link to the following page in the pager rows with class `pager_class`.
+    Postback {
+        pager_class: &'static str,
+    },
+}
+
+/// How to find the link to the next page.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum NextLink {
+    /// A link with this `rel` attribute.
+    Rel(&'static str),
+
+    /// A link with this class.
+    Class(&'static str),
+
+    /// A link whose text, ignoring a trailing " >", is one of these.
+    Text(&'static [&'static str]),
+}
+
+/// The way to the page after the current one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum NextPage {
+    /// Fetch this URL.
+    Url(Url),
+
+    /// Submit this postback with the current page's form.
+    Postback(FormEvent),
+}
+
+/// Encapuslates an event target and event value for a form submission.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct FormEvent {
+    /// The target of the event.
+    pub target: String,
+
+    /// The value of the event.
+    pub argument: String,
+}
+
+impl FormEvent {
+    /// Set the corresponding fields on the form.
+    pub fn set_form_fields(&self, form: &mut Form) {
+        form.set(FORM_FIELD_EVENTTARGET, &self.target);
+        form.set(FORM_FIELD_EVENTARGUMENT, &self.argument);
+    }
+}
+
+/// Exclusive use of a crawl's form view state by one operation, held while it submits postbacks.
+pub(crate) struct PostbackLease {
+    checkpoints: CheckpointStore,
+    crawl_id: String,
+    scope: String,
+    holder: String,
+}
+
+impl PostbackLease {
+    /// Take the lease on the view state `scope` of a crawl, or return a [`RetryableError`] deferring the operation
+    /// that started at `url` if another operation holds it.
+    pub(crate) async fn acquire(
+        checkpoints: &CheckpointStore,
+        crawl_id: &str,
+        scope: &str,
+        url: &str,
+    ) -> Result<Self, BoxError> {
+        let holder = Uuid::new_v7(Timestamp::now(NoContext)).to_string();
+        let duration = Duration::minutes(POSTBACK_LEASE_MINUTES);
+        if !checkpoints.acquire_lease(crawl_id, scope, &holder, duration).await? {
+            info!("View state {scope} of crawl {crawl_id} is in use by another operation; deferring");
+            return Err(RetryableError {
+                condition: CONDITION_VIEW_STATE_IN_USE,
+                url: url.to_string(),
+                delay: RetryDelay::Fixed(POSTBACK_LEASE_RETRY_DELAY),
+            }
+            .into());
+        }
+
+        Ok(Self {
+            checkpoints: checkpoints.clone(),
+            crawl_id: crawl_id.to_string(),
+            scope: scope.to_string(),
+            holder,
+        })
+    }
+
+    /// Give up the lease. Failures are logged; the lease then lapses on its own.
+    pub(crate) async fn release(self) {
+        if let Err(e) = self.checkpoints.release_lease(&self.crawl_id, &self.scope, &self.holder).await {
+            warn!("Failed to release view state {} of crawl {}: {e}", self.scope, self.crawl_id);
+        }
+    }
+}
+
+/// Indicates whether a response to a postback is ASP.NET rejecting the form's view state or event validation, which a
+/// fresh copy of the form usually cures.
+pub(crate) fn is_postback_rejection(status: StatusCode, text: &str) -> bool {
+    status.is_server_error() && POSTBACK_REJECTION_PHRASES.iter().any(|phrase| text.contains(phrase))
+}
+
+/// A link in a numbered pager.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct PagerLink {
+    /// The page number the link leads to, or `None` for a "..." link to the next or previous window of pages.
+    pub page: Option<usize>,
+
+    /// The postback event that follows the link.
+    pub event: FormEvent,
+}
+
+impl Pagination {
+    /// Return the way to the page after `page_url`, if there is one.
+    pub(crate) fn next_page(&self, document: &RcDom, page_url: &Url) -> Option<NextPage> {
+        match self {
+            Self::NextLink(links) => {
+                let href = links.iter().find_map(|link| find_link(document, link))?.get("href")?;
+                match page_url.join(&href) {
+                    Ok(url) => Some(NextPage::Url(url)),
+                    Err(e) => {
+                        warn!("Ignoring unparseable next page link {href} on {page_url}: {e}");
+                        None
+                    }
+                }
+            }
+            Self::Offset {
+                offset_param,
+                limit_param,
+                limit,
+                more,
+            } => {
+                find_link(document, more)?;
+                let param =
+                    |name: &str| page_url.query_pairs().find(|(n, _)| n == name).and_then(|(_, v)| v.parse().ok());
+                let limit = param(limit_param).unwrap_or(*limit);
+                let offset = param(offset_param).unwrap_or(0) + limit;
+                Some(NextPage::Url(
+                    ListingQuery::new().offset(offset_param, offset).page_size(limit_param, limit).url(page_url),
+                ))
+            }
+            Self::Cursor {
+                param,
+                input_name,
+            } => {
+                let token = document.tag("input").attr("name", *input_name).find()?.get("value")?;
+                let token = token.trim();
+                if token.is_empty() {
+                    return None;
+                }
+
+                Some(NextPage::Url(ListingQuery::new().cursor(param, token).url(page_url)))
+            }
+            Self::Postback {
+                pager_class,
+            } => {
+                let page = current_page(document, pager_class)?;
+                next_page_event(&pager_links(document, pager_class), page).map(NextPage::Postback)
+            }
+        }
+    }
+
+    /// Return the URL of the page after `page_url`, if there is one and it is reached by URL.
+    pub(crate) fn next_url(&self, document: &RcDom, page_url: &Url) -> Option<Url> {
+        match self.next_page(document, page_url)? {
+            NextPage::Url(url) => Some(url),
+            NextPage::Postback(_) => None,
+        }
+    }
+
+    /// Return the postback to the page after the current one, if there is one and it is reached by postback.
+    pub(crate) fn next_postback(&self, document: &RcDom, page_url: &Url) -> Option<FormEvent> {
+        match self.next_page(document, page_url)? {
+            NextPage::Postback(event) => Some(event),
+            NextPage::Url(_) => None,
+        }
+    }
+
+    /// Return the continuation message fetching the page after `page_url` with `operation`, if there is one.
+    pub(crate) fn continuation(
+        &self,
+        operation: Operation,
+        document: &RcDom,
+        page_url: &Url,
+        crawl: &CrawlParameters,
+    ) -> Option<NextRequest> {
+        let next_page = self.next_url(document, page_url)?;
+        Some(next_page_request(operation, &next_page, crawl))
+    }
+}
+
+/// Return the continuation message fetching a listing's next page with `operation`.
+pub(crate) fn next_page_request(operation: Operation, url: &Url, crawl: &CrawlParameters) -> NextRequest {
+    NextRequest {
+        operation,
+        url: Some(url.to_string()),
+        link_text: None,
+        crawl: crawl.child(),
+        delay_seconds: 0,
+    }
+}
+
+/// Return the first link matching `link`.
+fn find_link(document: &RcDom, link: &NextLink) -> Option<Handle> {
+    match link {
+        NextLink::Rel(rel) => document.tag("a").attr("rel", *rel).find(),
+        NextLink::Class(class) => document.tag("a").class(*class).find(),
+        NextLink::Text(texts) => document
+            .tag("a")
+            .find_all()
+            .find(|a| clean_text(&a.text()).is_some_and(|text| texts.contains(&text.trim_end_matches(" >")))),
+    }
+}
+
+/// Return the current page number, shown as unlinked text in the pager.
+pub(crate) fn current_page(document: &RcDom, pager_class: &str) -> Option<usize> {
+    for tr in document.tag("tr").class(pager_class).find_all() {
+        if let Some(page) = tr.tag("span").find_all().find_map(|span| span.text().trim().parse().ok()) {
+            return Some(page);
+        }
+    }
+
+    None
+}
+
+/// Return the event in `links` that moves from `current_page` to the following page, if there is one.
+///
+/// Numbered pagers often only show a window of page numbers; when the next page is outside the window, the trailing
+/// "..." link is followed instead.
+pub(crate) fn next_page_event(links: &[PagerLink], current_page: usize) -> Option<FormEvent> {
+    let target = current_page + 1;
+
+    if let Some(link) = links.iter().find(|link| link.page == Some(target)) {
+        return Some(link.event.clone());
+    }
+
+    // The next page is past the end of the window. A "..." link after the last numbered link continues forward; one
+    // before the numbered links goes back to the previous window.
+    let last_numbered = links.iter().rposition(|link| link.page.is_some());
+    let highest = links.iter().filter_map(|link| link.page).max().unwrap_or(0);
+    if highest > target {
+        return None;
+    }
+
+    let forward = links.iter().enumerate().rev().find(|(i, link)| {
+        link.page.is_none()
+            && match last_numbered {
+                Some(last) => *i > last,
+                None => true,
+            }
+    });
+
+    forward.map(|(_, link)| link.event.clone())
+}
+
+/// Parse the links in the pager rows with class `pager_class`.
+pub(crate) fn pager_links(document: &RcDom, pager_class: &str) -> Vec<PagerLink> {
+    let mut links = vec![];
+
+    // The pager links are <a> elements with an href similar to
+    // "javascript:__doPostBack(&#39;DataGrid1$_ctl104$_ctl2&#39;,&#39;&#39;)"
+    for tr in document.tag("tr").class(pager_class).find_all() {
+        for a in tr.tag("a").find_all() {
+            let Some(href) = a.get("href") else {
+                warn!("No href attribute found for pager link: {a:?}");
+                continue;
+            };
+
+            let post_back_args = match href.strip_prefix("javascript:__doPostBack(").and_then(|s| s.strip_suffix(')')) {
+                Some(s) => s,
+                None => {
+                    warn!("Unexpected pager link href: {href}");
+                    continue;
+                }
+            };
+
+            // If this is still URL encoded, decode it.
+            let post_back_args = post_back_args.replace("&#39;", "'");
+
+            let parts: Vec<&str> = post_back_args.split(',').collect();
+            if parts.len() != 2 {
+                warn!("Unexpected pager link format: {post_back_args}");
+                continue;
+            }
+
+            let event_target = parts[0].strip_prefix('\'').and_then(|s| s.strip_suffix('\''));
+            let event_argument = parts[1].strip_prefix('\'').and_then(|s| s.strip_suffix('\''));
+
+            let Some(event_target) = event_target else {
+                warn!("Unexpected pager link format: {post_back_args}");
+                continue;
+            };
+
+            let Some(event_argument) = event_argument else {
+                warn!("Unexpected pager link format: {post_back_args}");
+                continue;
+            };
+
+            links.push(PagerLink {
+                page: a.text().trim().parse().ok(),
+                event: FormEvent {
+                    target: event_target.to_string(),
+                    argument: event_argument.to_string(),
+                },
+            });
+        }
+    }
+
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{current_page, is_postback_rejection, NextLink, NextPage, Pagination},
+        crate::soup::parse_html_str,
+        reqwest::{StatusCode, Url},
+    };
+
+    const PAGER: Pagination = Pagination::Postback {
+        pager_class: "Grid3Pager",
+    };
+
+    #[test]
+    fn next_links() {
+        let page_url = Url::parse("https://example.gov/bids/").unwrap();
+        let pagination = Pagination::NextLink(&[NextLink::Rel("next"), NextLink::Text(&["Next", "Next Page"])]);
+
+        let document = parse_html_str(r#"<a href="?page=0">Previous</a><a class="more" href="?page=2">Next &gt;</a>"#);
+        assert_eq!(pagination.next_url(&document, &page_url).unwrap().as_str(), "https://example.gov/bids/?page=2");
+        assert!(Pagination::NextLink(&[NextLink::Rel("next")]).next_url(&document, &page_url).is_none());
+        assert_eq!(
+            Pagination::NextLink(&[NextLink::Class("more")]).next_url(&document, &page_url).unwrap().query(),
+            Some("page=2")
+        );
+
+        // Earlier matchers win.
+        let document = parse_html_str(r#"<a href="?page=9">Next</a><a rel="next" href="?page=3">3</a>"#);
+        assert_eq!(pagination.next_url(&document, &page_url).unwrap().query(), Some("page=3"));
+        assert!(pagination.next_postback(&document, &page_url).is_none());
+    }
+
+    #[test]
+    fn offsets() {
+        let pagination = Pagination::Offset {
+            offset_param: "offset",
+            limit_param: "max",
+            limit: 25,
+            more: NextLink::Class("nextLink"),
+        };
+        let document = parse_html_str(r#"<a class="nextLink" href="javascript:void(0)">Next</a>"#);
+
+        let page_url = Url::parse("https://example.gov/agencies").unwrap();
+        assert_eq!(pagination.next_url(&document, &page_url).unwrap().query(), Some("max=25&offset=25"));
+        assert!(pagination.next_url(&parse_html_str("<p>Last page</p>"), &page_url).is_none());
+
+        let page_url = Url::parse("https://example.gov/agencies?max=10&sort=name&offset=30").unwrap();
+        assert_eq!(pagination.next_url(&document, &page_url).unwrap().query(), Some("max=10&offset=40&sort=name"));
+    }
+
+    #[test]
+    fn cursors() {
+        let pagination = Pagination::Cursor {
+            param: "cursor",
+            input_name: "cursor",
+        };
+        let page_url = Url::parse("https://example.gov/bids?status=open&cursor=a1").unwrap();
+
+        let document = parse_html_str(r#"<form><input type="hidden" name="cursor" value="b2 c3"></form>"#);
+        assert_eq!(
+            pagination.next_page(&document, &page_url),
+            Some(NextPage::Url(Url::parse("https://example.gov/bids?cursor=b2+c3&status=open").unwrap()))
+        );
+
+        let document = parse_html_str(r#"<form><input type="hidden" name="cursor" value=""></form>"#);
+        assert!(pagination.next_page(&document, &page_url).is_none());
+    }
+
+    #[test_log::test]
+    fn postback_ellipsis() {
+        fn pager(links: &str) -> String {
+            format!(r#"<html><body><table><tr class="Grid3Pager"><td>{links}</td></tr></table></body></html>"#)
+        }
+        fn link(n: usize, text: &str) -> String {
+            format!(r#"<a href="javascript:__doPostBack(&#39;DataGrid1$_ctl104$_ctl{n}&#39;,&#39;&#39;)">{text}</a>"#)
+        }
+        let page_url = Url::parse("https://pr-webs-vendor.des.wa.gov/Search_Bid.aspx").unwrap();
+
+        // Last page of the first window: the trailing "..." leads to page 11.
+        let links: Vec<String> = (1..=9).map(|i| link(i, &i.to_string())).collect();
+        let document = parse_html_str(&pager(&format!("{}<span>10</span>{}", links.join(""), link(10, "..."))));
+        assert_eq!(current_page(&document, "Grid3Pager"), Some(10));
+        assert_eq!(PAGER.next_postback(&document, &page_url).unwrap().target.as_str(), "DataGrid1$_ctl104$_ctl10");
+        assert!(PAGER.next_url(&document, &page_url).is_none());
+
+        // First page of the second window: the leading "..." goes backward and must not be followed.
+        let links: Vec<String> = (12..=20).map(|i| link(i - 10, &i.to_string())).collect();
+        let document = parse_html_str(&pager(&format!("{}<span>11</span>{}", link(0, "..."), links.join(""))));
+        assert_eq!(PAGER.next_postback(&document, &page_url).unwrap().target.as_str(), "DataGrid1$_ctl104$_ctl2");
+
+        // Last page: nothing follows.
+        let links: Vec<String> = (11..=19).map(|i| link(i - 10, &i.to_string())).collect();
+        let document = parse_html_str(&pager(&format!("{}{}<span>20</span>", link(0, "..."), links.join(""))));
+        assert!(PAGER.next_page(&document, &page_url).is_none());
+    }
+
+    #[test]
+    fn postback_rejections() {
+        let error_page = "<title>Invalid postback or callback argument.  Event validation is enabled using ...</title>";
+        assert!(is_postback_rejection(StatusCode::INTERNAL_SERVER_ERROR, error_page));
+        assert!(is_postback_rejection(StatusCode::INTERNAL_SERVER_ERROR, "Validation of viewstate MAC failed."));
+        assert!(!is_postback_rejection(StatusCode::INTERNAL_SERVER_ERROR, "Server Error in '/' Application."));
+        assert!(!is_postback_rejection(StatusCode::OK, error_page));
+    }
+}
@@ -0,0 +1,503 @@
+//! Request/response types for PublicPurchase (publicpurchase.com), a bid portal hosting many local agencies.
+//!
+//! A crawl logs in, walks the directory of participating agencies, follows each agency to its open bids, and parses
+//! each bid page. The public documents listed with a bid are then downloaded, so they are archived alongside the
+//! pages.
+mod bid;
+mod listing;
+mod login;
+
+pub(crate) use login::check_credentials;
+
+use {
+    crate::{
+        archive::{ArchivedContent, ArchivedPage},
+        attachment, closure, contacts,
+        flags::Flag,
+        httpext::{Client, LogConfig, Response as HttpResponse, ResponseExt},
+        journal::CrawlEvent,
+        lifecycle,
+        opportunity::{DateLocale, Opportunity},
+        quality,
+        retry::{RetryDelay, RetryableError, MAX_DELAY},
+        shapes::{NextRequest, Operation, Request, Response, SUBSYS_PUBLIC_PURCHASE},
+        soup::parse_html_str,
+        BoxError,
+    },
+    chrono::Utc,
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    reqwest::Url,
+    serde::{Deserialize, Serialize},
+    std::{
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+pub(crate) const DEFAULT_PUBLIC_PURCHASE_BASE_URL: &str = "https://www.publicpurchase.com/gems/";
+const LOGIN_PATH: &str = "login/login";
+const AGENCIES_PATH: &str = "browse/agencies";
+
+const OP_START_CRAWL: &str = "StartCrawl";
+const OP_FETCH_AGENCY_LISTING_PAGE: &str = "FetchAgencyListingPage";
+const OP_FETCH_BID_LISTING_PAGE: &str = "FetchBidListingPage";
+const OP_FETCH_BID_PAGE: &str = "FetchBidPage";
+const OP_FETCH_DOCUMENT: &str = "FetchDocument";
+
+/// Version of this subsystem's parsers. Bump it whenever a parser change alters what is extracted.
+pub(crate) const PARSER_VERSION: u32 = 1;
+
+const PARSER_AGENCY_LISTING: &str = "PublicPurchase:AgencyListing";
+const PARSER_BID_LISTING: &str = "PublicPurchase:BidListing";
+const PARSER_BID: &str = "PublicPurchase:Bid";
+
+/// Phrases shown in place of an opportunity the portal no longer publishes; see [`closure`][crate::closure].
+const NOT_FOUND_MARKERS: &[&str] = &["this bid is no longer available", "bid not found"];
+
+/// Condition name for a login refused because the account logged in too recently and has no session to reuse.
+const CONDITION_LOGIN_THROTTLED: &str = "PublicPurchaseLoginThrottled";
+
+/// Possible operations for PublicPurchase.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum PublicPurchaseOperation {
+    /// Log in and start a crawl from the agency directory, or from the directory page given as the URL.
+    StartCrawl,
+
+    /// Fetch a page of the agency directory.
+    FetchAgencyListingPage,
+
+    /// Fetch a page of an agency's open bids.
+    FetchBidListingPage,
+
+    /// Fetch a bid page.
+    FetchBidPage,
+
+    /// Download a document published with a bid.
+    FetchDocument,
+}
+
+impl FromStr for PublicPurchaseOperation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            OP_START_CRAWL => Ok(Self::StartCrawl),
+            OP_FETCH_AGENCY_LISTING_PAGE => Ok(Self::FetchAgencyListingPage),
+            OP_FETCH_BID_LISTING_PAGE => Ok(Self::FetchBidListingPage),
+            OP_FETCH_BID_PAGE => Ok(Self::FetchBidPage),
+            OP_FETCH_DOCUMENT => Ok(Self::FetchDocument),
+            _ => Err(format!("Unknown operation: {value}")),
+        }
+    }
+}
+
+impl Display for PublicPurchaseOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.operation())
+    }
+}
+
+impl PublicPurchaseOperation {
+    /// Handle a request.
+    pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+        match self {
+            Self::StartCrawl => start_crawl(log_config, req, context).await,
+            Self::FetchAgencyListingPage => fetch_agency_listing_page(log_config, req, context).await,
+            Self::FetchBidListingPage => fetch_bid_listing_page(log_config, req, context).await,
+            Self::FetchBidPage => fetch_bid_page(log_config, req, context).await,
+            Self::FetchDocument => fetch_document(log_config, req, context).await,
+        }
+    }
+
+    /// Return the operation name within the subsystem.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::StartCrawl => OP_START_CRAWL,
+            Self::FetchAgencyListingPage => OP_FETCH_AGENCY_LISTING_PAGE,
+            Self::FetchBidListingPage => OP_FETCH_BID_LISTING_PAGE,
+            Self::FetchBidPage => OP_FETCH_BID_PAGE,
+            Self::FetchDocument => OP_FETCH_DOCUMENT,
+        }
+    }
+}
+
+/// Return the URL of a page on the default PublicPurchase site.
+fn default_url(path: &str) -> Url {
+    Url::parse(DEFAULT_PUBLIC_PURCHASE_BASE_URL)
+        .and_then(|base| base.join(path))
+        .expect("DEFAULT_PUBLIC_PURCHASE_BASE_URL and its paths form valid URLs")
+}
+
+/// Log in and schedule the first page of the agency directory, or the directory page given as the URL (e.g. one
+/// filtered by state).
+async fn start_crawl(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let url = match req.url.as_deref() {
+        Some(url) => Url::parse(url)?,
+        None => default_url(AGENCIES_PATH),
+    };
+
+    let client = log_in(&log_config, &req, &context, &url).await?;
+    let mut cookies = client.cookie_store.snapshot();
+    cookies.retain_domain(&url);
+
+    Ok(Response {
+        next_requests: vec![NextRequest {
+            operation: Operation::PublicPurchase(PublicPurchaseOperation::FetchAgencyListingPage),
+            url: Some(url.to_string()),
+            link_text: None,
+            crawl: req.crawl.child().with_cookies(cookies).with_account(client.account.clone()),
+            delay_seconds: 0,
+        }],
+    })
+}
+
+/// Log in to PublicPurchase, returning the logged-in client.
+///
+/// If the account logged in within the last [`login_min_interval`][LogConfig::login_min_interval], its session is
+/// reused instead. If that session can't be reused, the crawl is deferred until a fresh login is allowed.
+async fn log_in(log_config: &LogConfig, req: &Request, context: &Context, url: &Url) -> Result<Client, BoxError> {
+    let sessions = &log_config.sessions;
+    let interval = log_config.login_min_interval;
+
+    if let Some(session) = sessions.load(SUBSYS_PUBLIC_PURCHASE, login::ACCOUNT_ID).await? {
+        if let Some(cookies) = session.reusable_cookies(Utc::now(), interval) {
+            info!("Reusing PublicPurchase session from {}", session.last_login_at);
+            return Ok(req
+                .crawl
+                .clone()
+                .with_cookies(cookies.clone())
+                .portal_client(log_config, context, SUBSYS_PUBLIC_PURCHASE, DEFAULT_PUBLIC_PURCHASE_BASE_URL, url)?
+                .account(login::ACCOUNT_ID)
+                .build()?);
+        }
+    }
+
+    if !sessions.claim_login(SUBSYS_PUBLIC_PURCHASE, login::ACCOUNT_ID, interval).await? {
+        warn!("PublicPurchase account logged in less than {interval} ago; deferring the crawl");
+        return Err(RetryableError {
+            condition: CONDITION_LOGIN_THROTTLED,
+            url: url.to_string(),
+            delay: RetryDelay::Fixed(interval.to_std().unwrap_or(MAX_DELAY)),
+        }
+        .into());
+    }
+
+    let client = req
+        .crawl
+        .portal_client(log_config, context, SUBSYS_PUBLIC_PURCHASE, DEFAULT_PUBLIC_PURCHASE_BASE_URL, url)?
+        .account(login::ACCOUNT_ID)
+        .build()?;
+    let login_url = url.join(&format!("/gems/{LOGIN_PATH}"))?;
+    let response = match client.get(login_url).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch PublicPurchase login page: {e}");
+            return Err(e);
+        }
+    };
+
+    info!("Submitting PublicPurchase login");
+    let _ = login::submit_login(&client, log_config, response).await?;
+
+    if let Err(e) =
+        sessions.save_cookies(SUBSYS_PUBLIC_PURCHASE, login::ACCOUNT_ID, &client.cookie_store.snapshot()).await
+    {
+        warn!("Failed to save PublicPurchase session: {e}");
+    }
+
+    Ok(client)
+}
+
+/// Fetch a page with the crawl's session, failing if PublicPurchase asks to log in again.
+async fn fetch_page(client: &Client, url: &Url, what: &str) -> Result<HttpResponse, BoxError> {
+    let response = match client.get(url.clone()).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch PublicPurchase {what}: {e}");
+            return Err(e);
+        }
+    };
+
+    if login::login_required(response.text().unwrap_or_default()) {
+        return Err(format!("PublicPurchase session expired fetching {what} {url}").into());
+    }
+
+    Ok(response)
+}
+
+/// Record the outcome of parsing a page in the journal.
+async fn record_parse<T>(
+    log_config: &LogConfig,
+    client: &Client,
+    url: &Url,
+    parser: &str,
+    result: &Result<T, BoxError>,
+    items: impl FnOnce(&T) -> usize,
+) {
+    let event = match result {
+        Ok(value) => CrawlEvent::ParseSucceeded {
+            url: url.to_string(),
+            parser: parser.to_string(),
+            items: items(value),
+        },
+        Err(e) => CrawlEvent::ParseFailed {
+            url: url.to_string(),
+            parser: parser.to_string(),
+            error: e.to_string(),
+        },
+    };
+    log_config.journal.record(&client.crawl_id, event).await;
+}
+
+/// Fetch a page of the agency directory, returning requests for the agencies' bid listings and the next page.
+async fn fetch_agency_listing_page(
+    log_config: LogConfig,
+    req: Request,
+    context: Context,
+) -> Result<Response, LambdaError> {
+    let url = req.required_url()?;
+    let client = req
+        .crawl
+        .portal_client(&log_config, &context, SUBSYS_PUBLIC_PURCHASE, DEFAULT_PUBLIC_PURCHASE_BASE_URL, &url)?
+        .account(login::ACCOUNT_ID)
+        .build()?;
+    let response = fetch_page(&client, &url, "agency directory").await?;
+
+    let document = parse_html_str(response.text()?);
+    let result = listing::parse_agency_listing_page(&document, response.url());
+    record_parse(&log_config, &client, response.url(), PARSER_AGENCY_LISTING, &result, Vec::len).await;
+
+    let mut next_requests: Vec<NextRequest> = result?
+        .into_iter()
+        .map(|url| NextRequest {
+            operation: Operation::PublicPurchase(PublicPurchaseOperation::FetchBidListingPage),
+            url: Some(url.to_string()),
+            link_text: None,
+            crawl: req.crawl.child(),
+            delay_seconds: 0,
+        })
+        .collect();
+
+    next_requests.extend(listing::PAGINATION.continuation(
+        Operation::PublicPurchase(PublicPurchaseOperation::FetchAgencyListingPage),
+        &document,
+        response.url(),
+        &req.crawl,
+    ));
+
+    Ok(Response {
+        next_requests,
+    })
+}
+
+/// Fetch a page of an agency's open bids, returning requests for the bids and the next page.
+async fn fetch_bid_listing_page(
+    log_config: LogConfig,
+    req: Request,
+    context: Context,
+) -> Result<Response, LambdaError> {
+    let url = req.required_url()?;
+    let client = req
+        .crawl
+        .portal_client(&log_config, &context, SUBSYS_PUBLIC_PURCHASE, DEFAULT_PUBLIC_PURCHASE_BASE_URL, &url)?
+        .account(login::ACCOUNT_ID)
+        .build()?;
+    let response = fetch_page(&client, &url, "bid listing").await?;
+
+    let document = parse_html_str(response.text()?);
+    let result = listing::parse_bid_listing_page(&document, response.url());
+    record_parse(&log_config, &client, response.url(), PARSER_BID_LISTING, &result, Vec::len).await;
+
+    let mut next_requests: Vec<NextRequest> = result?
+        .into_iter()
+        .map(|url| NextRequest {
+            operation: Operation::PublicPurchase(PublicPurchaseOperation::FetchBidPage),
+            url: Some(url.to_string()),
+            link_text: None,
+            crawl: req.crawl.child(),
+            delay_seconds: 0,
+        })
+        .collect();
+
+    next_requests.extend(listing::PAGINATION.continuation(
+        Operation::PublicPurchase(PublicPurchaseOperation::FetchBidListingPage),
+        &document,
+        response.url(),
+        &req.crawl,
+    ));
+
+    Ok(Response {
+        next_requests,
+    })
+}
+
+/// Fetch a bid page, parse it into the normalized opportunity model, and return requests for its public documents.
+async fn fetch_bid_page(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let url = req.required_url()?;
+    let client = req
+        .crawl
+        .portal_client(&log_config, &context, SUBSYS_PUBLIC_PURCHASE, DEFAULT_PUBLIC_PURCHASE_BASE_URL, &url)?
+        .account(login::ACCOUNT_ID)
+        .build()?;
+    let response = fetch_page(&client, &url, "bid").await?;
+
+    let Some(response) = attachment::html_page(&log_config, &req.crawl, response, Some(url.as_str())).await else {
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    };
+
+    if let Some(reason) =
+        closure::soft_not_found(response.text()?, NOT_FOUND_MARKERS, &req.crawl.locale.not_found_markers)
+    {
+        closure::record_closed(&log_config, &client, SUBSYS_PUBLIC_PURCHASE, url.as_str(), &reason).await?;
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    }
+
+    let document = parse_html_str(response.text()?);
+    let result = bid::parse_bid_page(&document, response.url(), req.crawl.locale.dates);
+    record_parse(&log_config, &client, response.url(), PARSER_BID, &result, |_| 1).await;
+
+    let mut opportunity: Opportunity =
+        result?.with_parser(PARSER_BID, PARSER_VERSION).with_tenant(client.tenant.as_deref());
+    contacts::record_contacts(&log_config, &client, &mut opportunity, response.text()?).await;
+    lifecycle::record_stage(&log_config, &client, &mut opportunity, response.text()?).await;
+    quality::record_quality(&log_config, &client, &mut opportunity).await;
+    closure::record_seen(&log_config, &client, &opportunity).await;
+    info!("PublicPurchase opportunity: {}", serde_json::to_string(&opportunity)?);
+
+    let document_urls: Vec<&str> = opportunity.documents.iter().map(|document| document.url.as_str()).collect();
+    attachment::prune_attachments(&log_config, &opportunity.url, &document_urls).await;
+
+    let next_requests = opportunity
+        .documents
+        .iter()
+        .map(|document| NextRequest {
+            operation: Operation::PublicPurchase(PublicPurchaseOperation::FetchDocument),
+            url: Some(document.url.clone()),
+            link_text: Some(document.name.clone()),
+            crawl: req.crawl.child().with_opportunity_url(&opportunity.url),
+            delay_seconds: 0,
+        })
+        .collect();
+
+    Ok(Response {
+        next_requests,
+    })
+}
+
+/// Download a document published with a bid. The client archives the body like any other response, and the archived
+/// body is linked to the bid as an attachment.
+async fn fetch_document(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let url = req.required_url()?;
+    if !log_config.flags.is_enabled(Flag::AttachmentDownloads) {
+        info!("Attachment downloads are disabled; not fetching PublicPurchase document {url}");
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    }
+
+    let client = req
+        .crawl
+        .portal_client(&log_config, &context, SUBSYS_PUBLIC_PURCHASE, DEFAULT_PUBLIC_PURCHASE_BASE_URL, &url)?
+        .account(login::ACCOUNT_ID)
+        .build()?;
+    let response = fetch_page(&client, &url, "document").await?;
+    attachment::record_attachment(&log_config, &req.crawl, SUBSYS_PUBLIC_PURCHASE, url.as_str(), &response).await;
+    info!("Downloaded PublicPurchase document {} ({} bytes)", response.url(), response.content_length());
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Parse a capture of a PublicPurchase page for the [Internet Archive backfill][crate::archive]: an agency or bid
+/// listing, whose links and next page are returned, or a bid. Returns `None` for any other page.
+pub(crate) fn parse_archived_page(text: &str, page_url: &Url, date_locale: DateLocale) -> Option<ArchivedPage> {
+    let document = parse_html_str(text);
+    let path = page_url.path();
+    let links = |parser: &str, urls: Result<Vec<Url>, BoxError>| ArchivedPage {
+        parser: parser.to_string(),
+        parser_version: PARSER_VERSION,
+        content: urls.map(|mut urls| {
+            urls.extend(listing::PAGINATION.next_url(&document, page_url));
+            ArchivedContent::Links(urls)
+        }),
+    };
+
+    if path.ends_with(AGENCIES_PATH) {
+        Some(links(PARSER_AGENCY_LISTING, listing::parse_agency_listing_page(&document, page_url)))
+    } else if path.ends_with("bid/agencyBids") {
+        Some(links(PARSER_BID_LISTING, listing::parse_bid_listing_page(&document, page_url)))
+    } else if path.ends_with("bid/bidView") {
+        Some(ArchivedPage {
+            parser: PARSER_BID.to_string(),
+            parser_version: PARSER_VERSION,
+            content: bid::parse_bid_page(&document, page_url, date_locale)
+                .map(|opportunity| ArchivedContent::Opportunity(Box::new(opportunity))),
+        })
+    } else {
+        None
+    }
+}
+
+/// Parse a PublicPurchase snapshot for golden-file testing. `parser` is the name of the snapshot directory.
+#[cfg(any(test, feature = "test-utils"))]
+pub fn parse_snapshot(parser: &str, text: &str) -> Result<serde_json::Value, BoxError> {
+    use serde_json::json;
+
+    let document = parse_html_str(text);
+
+    match parser {
+        "agency-listing" | "bid-listing" => {
+            let page_url = default_url(AGENCIES_PATH);
+            let urls = if parser == "agency-listing" {
+                listing::parse_agency_listing_page(&document, &page_url)?
+            } else {
+                listing::parse_bid_listing_page(&document, &page_url)?
+            };
+            let next_page = listing::PAGINATION.next_url(&document, &page_url);
+
+            Ok(json!({
+                "Links": urls.iter().map(Url::as_str).collect::<Vec<_>>(),
+                "NextPage": next_page.as_ref().map(Url::as_str),
+            }))
+        }
+        "bid" => Ok(serde_json::to_value(bid::parse_bid_page(
+            &document,
+            &default_url("bid/bidView"),
+            DateLocale::default(),
+        )?)?),
+        _ => Err(format!("Unknown PublicPurchase snapshot parser {parser}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{default_url, parse_snapshot, AGENCIES_PATH, LOGIN_PATH},
+        crate::{
+            golden::{check_snapshots, snapshot_dir},
+            shapes::SUBSYS_PUBLIC_PURCHASE,
+        },
+    };
+
+    #[test_log::test]
+    fn golden_snapshots() {
+        let checked = check_snapshots(&snapshot_dir(&SUBSYS_PUBLIC_PURCHASE.to_lowercase()), parse_snapshot)
+            .unwrap_or_else(|e| panic!("Golden snapshot mismatch:\n{e}"));
+        assert!(checked >= 3);
+    }
+
+    #[test]
+    fn default_urls() {
+        assert_eq!(default_url(AGENCIES_PATH).as_str(), "https://www.publicpurchase.com/gems/browse/agencies");
+
+        // The login page is found on the host of whatever listing the crawl starts from.
+        let start = default_url("bid/agencyBids?agencyId=4421");
+        assert_eq!(start.join(&format!("/gems/{LOGIN_PATH}")).unwrap().as_str(), default_url(LOGIN_PATH).as_str());
+    }
+}
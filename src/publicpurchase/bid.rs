@@ -0,0 +1,178 @@
+//! PublicPurchase bid page handling.
+//!
+//! A bid page is a table of labelled rows (`<th>Due Date:</th><td>...</td>`), followed by a table of documents. Some
+//! documents are released only to registered plan holders; those are listed without a link and are skipped.
+use {
+    crate::{
+        award::{page_awards, US_DOLLARS},
+        opportunity::{
+            clean_text, parse_date, parse_date_time, text_lines, Contact, DateLocale, Document, Opportunity,
+        },
+        shapes::SUBSYS_PUBLIC_PURCHASE,
+        soup::{NodeExt, QueryBuilderExt},
+        BoxError,
+    },
+    log::*,
+    markup5ever_rcdom::{Handle, RcDom},
+    reqwest::Url,
+    std::collections::HashMap,
+};
+
+const LABEL_AGENCY: &str = "Agency";
+const LABEL_NUMBER: &str = "Bid Number";
+const LABEL_ISSUED: &str = "Issue Date";
+const LABEL_DUE: &str = "Due Date";
+const LABEL_CONTACT: &str = "Contact";
+const LABEL_NIGP_CODES: &str = "NIGP Codes";
+const LABEL_DESCRIPTION: &str = "Description";
+
+/// Separator between a commodity code and its description.
+const COMMODITY_CODE_SEPARATOR: &str = " - ";
+
+/// Path of the document download endpoint.
+const DOWNLOAD_PATH: &str = "/gems/bid/download";
+
+/// Parse a bid page into the normalized model.
+///
+/// A page without a title or bid details is not a bid page (usually an error or login page) and is an error.
+pub(crate) fn parse_bid_page(
+    document: &RcDom,
+    page_url: &Url,
+    date_locale: DateLocale,
+) -> Result<Opportunity, BoxError> {
+    let Some(table) = document.tag("table").attr("id", "bidInfo").find() else {
+        return Err(format!("PublicPurchase bid details not found on {page_url}").into());
+    };
+
+    let Some(title) = document.tag("h1").find().and_then(|h1| clean_text(&h1.text())) else {
+        return Err(format!("PublicPurchase bid title not found on {page_url}").into());
+    };
+
+    let fields = labelled_rows(&table);
+    let text = |label: &str| fields.get(label).and_then(|td| clean_text(&td.text()));
+    let lines = |label: &str| fields.get(label).map(text_lines).unwrap_or_default();
+
+    let due_at = text(LABEL_DUE).and_then(|due| {
+        let due_at = parse_date_time(&due, date_locale);
+        if due_at.is_none() {
+            warn!("Unparseable PublicPurchase due date {due:?} on {page_url}");
+        }
+        due_at
+    });
+
+    let description = lines(LABEL_DESCRIPTION);
+    let commodity_codes = lines(LABEL_NIGP_CODES)
+        .into_iter()
+        .map(|line| match line.split_once(COMMODITY_CODE_SEPARATOR) {
+            Some((code, _)) => code.to_string(),
+            None => line,
+        })
+        .collect();
+
+    Ok(Opportunity {
+        portal: SUBSYS_PUBLIC_PURCHASE.to_string(),
+        url: page_url.to_string(),
+        title,
+        reference: text(LABEL_NUMBER),
+        organization: text(LABEL_AGENCY),
+        contact: Contact::from_lines(&lines(LABEL_CONTACT)),
+        published_on: text(LABEL_ISSUED).and_then(|issued| parse_date(&issued, date_locale)),
+        due_at,
+        description: if description.is_empty() {
+            None
+        } else {
+            Some(description.join("\n"))
+        },
+        commodity_codes,
+        counties: vec![],
+        documents: parse_documents(document, page_url),
+        awards: page_awards(document, date_locale, US_DOLLARS),
+        ..Opportunity::default()
+    })
+}
+
+/// Return the value cells of the bid details table, keyed by their label without the trailing colon.
+fn labelled_rows(table: &Handle) -> HashMap<String, Handle> {
+    let mut fields = HashMap::new();
+
+    for tr in table.tag("tr").find_all() {
+        let (Some(th), Some(td)) = (tr.tag("th").find(), tr.tag("td").find()) else {
+            continue;
+        };
+
+        if let Some(label) = clean_text(&th.text()) {
+            fields.insert(label.trim_end_matches(':').to_string(), td);
+        }
+    }
+
+    fields
+}
+
+/// Return the publicly downloadable documents listed with the bid.
+fn parse_documents(document: &RcDom, page_url: &Url) -> Vec<Document> {
+    let Some(table) = document.tag("table").attr("id", "bidDocuments").find() else {
+        return vec![];
+    };
+
+    table
+        .tag("a")
+        .find_all()
+        .filter_map(|a| {
+            let href = a.get("href")?;
+            let url = match page_url.join(&href) {
+                Ok(url) => url,
+                Err(e) => {
+                    warn!("Ignoring unparseable PublicPurchase document link {href}: {e}");
+                    return None;
+                }
+            };
+
+            if url.path() != DOWNLOAD_PATH {
+                return None;
+            }
+
+            Some(Document {
+                name: clean_text(&a.text()).unwrap_or_else(|| url.to_string()),
+                url: url.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::parse_bid_page,
+        crate::{opportunity::DateLocale, soup::parse_html_str},
+        chrono::NaiveDate,
+        reqwest::Url,
+    };
+
+    #[test]
+    fn bid_page() {
+        let url = Url::parse("https://www.publicpurchase.com/gems/bid/bidView?bidId=98765").unwrap();
+        let opportunity =
+            parse_bid_page(&parse_html_str(include_str!("publicpurchase-bid.html")), &url, DateLocale::English)
+                .unwrap();
+
+        assert_eq!(opportunity.title, "Lake Whatcom Boulevard Water Main Replacement");
+        assert_eq!(opportunity.reference.as_deref(), Some("2023-0118"));
+        assert_eq!(opportunity.organization.as_deref(), Some("City of Bellingham"));
+        assert_eq!(opportunity.contact.name.as_deref(), Some("Pat Morrow"));
+        assert_eq!(opportunity.contact.email.as_deref(), Some("pmorrow@cob.org"));
+        assert_eq!(opportunity.contact.phone.as_deref(), Some("(360) 778-7700"));
+        assert_eq!(opportunity.published_on, NaiveDate::from_ymd_opt(2023, 3, 30));
+        assert_eq!(opportunity.due_at, NaiveDate::from_ymd_opt(2023, 4, 20).unwrap().and_hms_opt(14, 0, 0));
+        assert_eq!(opportunity.commodity_codes, vec!["913-45", "670-76"]);
+        assert_eq!(opportunity.description.unwrap().lines().count(), 2);
+
+        // The plan holders' documents have no link.
+        assert_eq!(opportunity.documents.len(), 2);
+        assert_eq!(opportunity.documents[1].name, "Addendum 1.pdf");
+        assert!(opportunity.documents[1].url.ends_with("/gems/bid/download?bidId=98765&docId=331202"));
+
+        // A login page served in place of the bid is not a bid.
+        assert!(parse_bid_page(&parse_html_str(include_str!("publicpurchase-login.html")), &url, DateLocale::English)
+            .is_err());
+    }
+}
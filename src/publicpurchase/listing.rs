@@ -0,0 +1,114 @@
+//! PublicPurchase agency and bid listing handling.
+//!
+//! The agency directory is a paged table of participating agencies, each linking to its list of open bids. An
+//! agency's bids are listed in a single table, each linking to the bid's page.
+use {
+    crate::{
+        pagination::{NextLink, Pagination},
+        soup::{NodeExt, QueryBuilderExt},
+        BoxError,
+    },
+    log::*,
+    markup5ever_rcdom::{Handle, RcDom},
+    reqwest::Url,
+};
+
+/// Query parameter identifying an agency on its bid listing.
+const AGENCY_ID_PARAM: &str = "agencyId";
+
+/// Query parameter identifying a bid on its page.
+const BID_ID_PARAM: &str = "bidId";
+
+/// Paged listings are paged by `offset` and `max` query parameters, 25 rows at a time unless `max` says otherwise,
+/// while the page has a link of class `nextLink`.
+pub(crate) const PAGINATION: Pagination = Pagination::Offset {
+    offset_param: "offset",
+    limit_param: "max",
+    limit: 25,
+    more: NextLink::Class("nextLink"),
+};
+
+/// Return the URLs of the agencies' bid listings on a page of the agency directory, in order.
+pub(crate) fn parse_agency_listing_page(document: &RcDom, page_url: &Url) -> Result<Vec<Url>, BoxError> {
+    let Some(table) = document.tag("table").attr("id", "agencyList").find() else {
+        return Err(format!("PublicPurchase agency table not found on {page_url}").into());
+    };
+
+    Ok(links_with_param(&table, page_url, AGENCY_ID_PARAM))
+}
+
+/// Return the URLs of the bids on an agency's bid listing, in order.
+pub(crate) fn parse_bid_listing_page(document: &RcDom, page_url: &Url) -> Result<Vec<Url>, BoxError> {
+    let Some(table) = document.tag("table").attr("id", "bidList").find() else {
+        return Err(format!("PublicPurchase bid table not found on {page_url}").into());
+    };
+
+    Ok(links_with_param(&table, page_url, BID_ID_PARAM))
+}
+
+/// Return the targets of the links within `table` carrying the query parameter `param`, without duplicates.
+fn links_with_param(table: &Handle, page_url: &Url, param: &str) -> Vec<Url> {
+    let mut urls: Vec<Url> = Vec::new();
+
+    for a in table.tag("a").find_all() {
+        let Some(href) = a.get("href") else {
+            continue;
+        };
+
+        let url = match page_url.join(&href) {
+            Ok(url) => url,
+            Err(e) => {
+                warn!("Ignoring unparseable PublicPurchase link {href}: {e}");
+                continue;
+            }
+        };
+
+        if !url.query_pairs().any(|(name, _)| name == param) {
+            debug!("Ignoring PublicPurchase link {href} without {param}");
+            continue;
+        }
+
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{parse_agency_listing_page, parse_bid_listing_page, PAGINATION},
+        crate::soup::parse_html_str,
+        reqwest::Url,
+    };
+
+    #[test]
+    fn agency_listing_page() {
+        let page_url = Url::parse("https://www.publicpurchase.com/gems/browse/agencies").unwrap();
+        let document = parse_html_str(include_str!("publicpurchase-agencies-page1.html"));
+
+        let urls = parse_agency_listing_page(&document, &page_url).unwrap();
+        assert_eq!(urls.len(), 3);
+        assert_eq!(urls[0].as_str(), "https://www.publicpurchase.com/gems/bid/agencyBids?agencyId=4421&status=open");
+        assert_eq!(PAGINATION.next_url(&document, &page_url).unwrap().query(), Some("max=25&offset=25"));
+
+        assert!(parse_agency_listing_page(&parse_html_str("<p>Error</p>"), &page_url).is_err());
+    }
+
+    #[test]
+    fn bid_listing_page() {
+        let page_url = Url::parse("https://www.publicpurchase.com/gems/bid/agencyBids?agencyId=4421").unwrap();
+        let document = parse_html_str(include_str!("publicpurchase-agency-bids.html"));
+
+        let urls = parse_bid_listing_page(&document, &page_url).unwrap();
+        assert_eq!(urls.len(), 3);
+        assert_eq!(urls[2].as_str(), "https://www.publicpurchase.com/gems/bid/bidView?bidId=98840");
+        assert!(PAGINATION.next_url(&document, &page_url).is_none());
+
+        // The agency directory isn't a bid listing.
+        let agencies = parse_html_str(include_str!("publicpurchase-agencies-page1.html"));
+        assert!(parse_bid_listing_page(&agencies, &page_url).is_err());
+    }
+}
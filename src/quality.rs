@@ -0,0 +1,360 @@
+//! Data quality scoring for parsed opportunities.
+//!
+//! Each opportunity is checked for the fields downstream consumers rely on, plausible dates, a meaningful description,
+//! and usable document links. Every failed check costs a fixed number of points from a perfect score of 100. The score
+//! and the failed checks are stored on the opportunity and journaled, and the crawl's running average feeds the
+//! [crawl health checks][crate::health::check_quality].
+use {
+    crate::{
+        health,
+        httpext::{Client, LogConfig},
+        journal::CrawlEvent,
+        opportunity::Opportunity,
+    },
+    chrono::{Months, NaiveDate, NaiveDateTime, Utc},
+    log::*,
+    reqwest::Url,
+    serde::{Deserialize, Serialize},
+    std::fmt::{Display, Formatter, Result as FmtResult},
+};
+
+/// The score of an opportunity that passes every check.
+pub const MAX_SCORE: u32 = 100;
+
+/// Descriptions shorter than this many characters are unlikely to describe the opportunity.
+const MIN_DESCRIPTION_LEN: usize = 40;
+
+/// Due dates further in the future than this are taken to be typos.
+const MAX_DUE_MONTHS_AHEAD: u32 = 36;
+
+/// Due dates further in the past than this are taken to be typos. Crawls pick up recently closed opportunities, so
+/// some past due dates are expected.
+const MAX_DUE_MONTHS_BEHIND: u32 = 12;
+
+/// A check an opportunity failed.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "Issue", rename_all = "PascalCase")]
+pub enum QualityIssue {
+    /// The title is empty.
+    MissingTitle,
+
+    /// There is no reference or bid number.
+    MissingReference,
+
+    /// The issuing organization is unknown.
+    MissingOrganization,
+
+    /// There is no contact name, email address, or phone number.
+    MissingContact,
+
+    /// The due date is unknown.
+    MissingDueDate,
+
+    /// The due date is too far in the past or future to be plausible.
+    #[serde(rename_all = "PascalCase")]
+    ImplausibleDueDate {
+        /// The due date.
+        due_at: NaiveDateTime,
+    },
+
+    /// The publication date is in the future.
+    #[serde(rename_all = "PascalCase")]
+    PublishedInFuture {
+        /// The publication date.
+        published_on: NaiveDate,
+    },
+
+    /// The due date is before the publication date.
+    DueBeforePublished,
+
+    /// There is no description.
+    MissingDescription,
+
+    /// The description is too short to be useful.
+    #[serde(rename_all = "PascalCase")]
+    ShortDescription {
+        /// The length of the description, in characters.
+        length: usize,
+    },
+
+    /// A document link can't be fetched.
+    #[serde(rename_all = "PascalCase")]
+    BadDocumentLink {
+        /// The link.
+        url: String,
+    },
+}
+
+impl QualityIssue {
+    /// Return the number of points the issue costs.
+    pub fn penalty(&self) -> u32 {
+        match self {
+            Self::MissingTitle => 40,
+            Self::MissingDueDate => 20,
+            Self::ImplausibleDueDate {
+                ..
+            } => 15,
+            Self::DueBeforePublished => 15,
+            Self::MissingDescription => 15,
+            Self::MissingReference => 10,
+            Self::MissingOrganization => 10,
+            Self::MissingContact => 10,
+            Self::PublishedInFuture {
+                ..
+            } => 10,
+            Self::ShortDescription {
+                ..
+            } => 5,
+            Self::BadDocumentLink {
+                ..
+            } => 5,
+        }
+    }
+}
+
+impl Display for QualityIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::MissingTitle => f.write_str("missing title"),
+            Self::MissingReference => f.write_str("missing reference number"),
+            Self::MissingOrganization => f.write_str("missing organization"),
+            Self::MissingContact => f.write_str("missing contact"),
+            Self::MissingDueDate => f.write_str("missing due date"),
+            Self::ImplausibleDueDate {
+                due_at,
+            } => write!(f, "implausible due date {due_at}"),
+            Self::PublishedInFuture {
+                published_on,
+            } => write!(f, "published in the future on {published_on}"),
+            Self::DueBeforePublished => f.write_str("due before it was published"),
+            Self::MissingDescription => f.write_str("missing description"),
+            Self::ShortDescription {
+                length,
+            } => write!(f, "description is only {length} characters"),
+            Self::BadDocumentLink {
+                url,
+            } => write!(f, "unusable document link {url}"),
+        }
+    }
+}
+
+/// The quality assessment of an opportunity.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct QualityReport {
+    /// The score, from 0 to [`MAX_SCORE`].
+    pub score: u32,
+
+    /// The checks the opportunity failed.
+    pub issues: Vec<QualityIssue>,
+}
+
+/// The running total of a crawl's quality scores.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct QualityTally {
+    /// The number of opportunities scored.
+    pub opportunities: u64,
+
+    /// The sum of their scores.
+    pub total_score: u64,
+}
+
+impl QualityTally {
+    /// Return the average score, or `None` if nothing has been scored.
+    pub fn average(&self) -> Option<f64> {
+        if self.opportunities == 0 {
+            None
+        } else {
+            Some(self.total_score as f64 / self.opportunities as f64)
+        }
+    }
+}
+
+/// Assess an opportunity crawled at `now`.
+pub fn assess(opportunity: &Opportunity, now: NaiveDateTime) -> QualityReport {
+    let mut issues = Vec::new();
+
+    if opportunity.title.trim().is_empty() {
+        issues.push(QualityIssue::MissingTitle);
+    }
+
+    if opportunity.reference.is_none() {
+        issues.push(QualityIssue::MissingReference);
+    }
+
+    if opportunity.organization.is_none() {
+        issues.push(QualityIssue::MissingOrganization);
+    }
+
+    let contact = &opportunity.contact;
+    if contact.name.is_none() && contact.email.is_none() && contact.phone.is_none() {
+        issues.push(QualityIssue::MissingContact);
+    }
+
+    if let Some(published_on) = opportunity.published_on {
+        if published_on > now.date() {
+            issues.push(QualityIssue::PublishedInFuture {
+                published_on,
+            });
+        }
+    }
+
+    match opportunity.due_at {
+        None => issues.push(QualityIssue::MissingDueDate),
+        Some(due_at) => {
+            let earliest = now.checked_sub_months(Months::new(MAX_DUE_MONTHS_BEHIND));
+            let latest = now.checked_add_months(Months::new(MAX_DUE_MONTHS_AHEAD));
+            if earliest.is_some_and(|earliest| due_at < earliest) || latest.is_some_and(|latest| due_at > latest) {
+                issues.push(QualityIssue::ImplausibleDueDate {
+                    due_at,
+                });
+            }
+
+            if opportunity.published_on.is_some_and(|published_on| due_at.date() < published_on) {
+                issues.push(QualityIssue::DueBeforePublished);
+            }
+        }
+    }
+
+    match opportunity.description.as_deref().map(|description| description.trim().chars().count()) {
+        None | Some(0) => issues.push(QualityIssue::MissingDescription),
+        Some(length) if length < MIN_DESCRIPTION_LEN => issues.push(QualityIssue::ShortDescription {
+            length,
+        }),
+        Some(_) => (),
+    }
+
+    for document in opportunity.documents.iter() {
+        if !is_fetchable(&document.url) {
+            issues.push(QualityIssue::BadDocumentLink {
+                url: document.url.clone(),
+            });
+        }
+    }
+
+    let penalty: u32 = issues.iter().map(QualityIssue::penalty).sum();
+    QualityReport {
+        score: MAX_SCORE.saturating_sub(penalty),
+        issues,
+    }
+}
+
+/// Indicates whether a link is an absolute HTTP or HTTPS URL with a host, so it can be fetched.
+fn is_fetchable(url: &str) -> bool {
+    match Url::parse(url) {
+        Ok(url) => matches!(url.scheme(), "http" | "https") && url.host_str().is_some_and(|host| !host.is_empty()),
+        Err(_) => false,
+    }
+}
+
+/// Assess a parsed opportunity and store the assessment on it, then journal it and add it to the crawl's running
+/// average, alerting if the average has dropped too low.
+///
+/// Journaling and tallying are best-effort: failures are logged rather than failing the operation.
+pub async fn record_quality(log_config: &LogConfig, client: &Client, opportunity: &mut Opportunity) {
+    let report = assess(opportunity, Utc::now().naive_utc());
+    if !report.issues.is_empty() {
+        let issues: Vec<String> = report.issues.iter().map(ToString::to_string).collect();
+        info!("Quality of {} is {}: {}", opportunity.url, report.score, issues.join("; "));
+    }
+
+    let event = CrawlEvent::QualityAssessed {
+        url: opportunity.url.clone(),
+        parser: opportunity.parsed_by.as_ref().map(|parsed_by| parsed_by.parser.clone()).unwrap_or_default(),
+        score: report.score,
+        issues: report.issues.clone(),
+    };
+    log_config.journal.record(&client.crawl_id, event).await;
+
+    match log_config.crawls.add_quality_score(&client.crawl_id, report.score).await {
+        Ok(tally) => {
+            let portal = client.portal.as_deref().unwrap_or(&opportunity.portal);
+            health::check_quality(log_config, portal, &client.crawl_id, &tally).await;
+        }
+        Err(e) => warn!("Failed to tally quality score for crawl {}: {e}", client.crawl_id),
+    }
+
+    opportunity.quality = Some(report);
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{assess, QualityIssue, QualityTally, MAX_SCORE},
+        crate::opportunity::{Contact, Document, Opportunity},
+        chrono::NaiveDate,
+    };
+
+    #[test]
+    fn scores() {
+        let now = NaiveDate::from_ymd_opt(2023, 4, 10).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        let opportunity = Opportunity {
+            portal: "FlVbs".to_string(),
+            url: "https://www.myflorida.com/apps/vbs/vbs_www.ad_r2.view_ad?advertisement_key_num=1".to_string(),
+            title: "Roadside Mowing".to_string(),
+            reference: Some("E1A42".to_string()),
+            organization: Some("Department of Transportation".to_string()),
+            contact: Contact {
+                email: Some("maria.lopez@dot.state.fl.us".to_string()),
+                ..Contact::default()
+            },
+            published_on: NaiveDate::from_ymd_opt(2023, 4, 3),
+            due_at: NaiveDate::from_ymd_opt(2023, 5, 4).unwrap().and_hms_opt(14, 0, 0),
+            description: Some("Mowing and litter removal along state roads in District Four.".to_string()),
+            documents: vec![Document {
+                name: "Bid Package.pdf".to_string(),
+                url: "https://www.myflorida.com/apps/vbs/adoc/F1_ITB.pdf".to_string(),
+            }],
+            ..Opportunity::default()
+        };
+
+        let report = assess(&opportunity, now);
+        assert_eq!(report.score, MAX_SCORE);
+        assert!(report.issues.is_empty());
+
+        let poor = Opportunity {
+            reference: None,
+            published_on: NaiveDate::from_ymd_opt(2023, 6, 1),
+            due_at: NaiveDate::from_ymd_opt(2032, 5, 4).unwrap().and_hms_opt(14, 0, 0),
+            description: Some("See attached.".to_string()),
+            documents: vec![Document {
+                name: "Bid Package.pdf".to_string(),
+                url: "javascript:openDoc(7)".to_string(),
+            }],
+            ..opportunity
+        };
+
+        let report = assess(&poor, now);
+        assert_eq!(
+            report.issues,
+            vec![
+                QualityIssue::MissingReference,
+                QualityIssue::PublishedInFuture {
+                    published_on: NaiveDate::from_ymd_opt(2023, 6, 1).unwrap(),
+                },
+                QualityIssue::ImplausibleDueDate {
+                    due_at: NaiveDate::from_ymd_opt(2032, 5, 4).unwrap().and_hms_opt(14, 0, 0).unwrap(),
+                },
+                QualityIssue::ShortDescription {
+                    length: 13,
+                },
+                QualityIssue::BadDocumentLink {
+                    url: "javascript:openDoc(7)".to_string(),
+                },
+            ]
+        );
+        assert_eq!(report.score, 100 - 10 - 10 - 15 - 5 - 5);
+        assert_eq!(report.issues[3].to_string(), "description is only 13 characters");
+
+        let empty = assess(&Opportunity::default(), now);
+        assert_eq!(empty.score, 0);
+        assert!(empty.issues.contains(&QualityIssue::MissingTitle));
+
+        assert_eq!(QualityTally::default().average(), None);
+        let tally = QualityTally {
+            opportunities: 4,
+            total_score: 310,
+        };
+        assert_eq!(tally.average(), Some(77.5));
+    }
+}
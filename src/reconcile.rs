@@ -0,0 +1,827 @@
+//! Reconciliation of stored opportunities with what portals still publish.
+//!
+//! Every opportunity parsed from a detail page is recorded in the opportunity store (`Opportunity#{portal}` /
+//! `{url}`) with its due date and when it was last seen. Detail pages are only fetched for opportunities found in a
+//! listing, so an opportunity that hasn't been seen for a while has stopped appearing in its portal's listings.
+//!
+//! The scheduled `Reconcile:CloseStale` operation sweeps the open opportunities and closes those that are stale:
+//!
+//! * an opportunity whose due date passed more than [`DUE_GRACE`] ago is marked
+//!   [expired][OpportunityStatus::Expired];
+//! * an opportunity last seen more than the unseen period (`RECONCILE_UNSEEN_DAYS`, default
+//!   [`DEFAULT_UNSEEN_DAYS`]) before the most recent sighting of any opportunity on the same portal is marked
+//!   [closed][OpportunityStatus::Closed]. Measuring from the portal's latest sighting rather than the current time
+//!   keeps a paused or failing crawl from closing everything it publishes.
+//!
+//! Each closure is recorded like one found by [soft 404 detection][crate::closure].
+//!
+//! Each sighting also stores the full record of the opportunity, read back in bulk by [exports][crate::export], and a
+//! [fingerprint] of it, so a sighting can tell whether the opportunity is new, changed since it was last seen, or
+//! unchanged. The [scheduler][crate::schedule] tunes how often a portal is crawled from these. Opportunities found to
+//! [duplicate][crate::duplicates] one on another portal are linked to it with `DuplicateOf`, which records carry as
+//! [`Opportunity::duplicate_of`]. The [lifecycle stage][crate::lifecycle] of each opportunity is kept in `Stage`, with
+//! the transitions that led to it in `StageHistory`.
+use {
+    crate::{
+        classification::{Classification, DDB_KEY_CLASSIFICATION},
+        closure, coverage,
+        httpext::{aws_err_str, LogConfig},
+        opportunity::{Opportunity, OpportunityStatus, SolicitationStage, StageTransition},
+        shapes::{Request, Response},
+        BoxError,
+    },
+    aws_sdk_dynamodb::{
+        operation::update_item::UpdateItemError,
+        types::{AttributeValue, ReturnValue, ReturnValuesOnConditionCheckFailure},
+        Client as DynamoDbClient,
+    },
+    aws_smithy_runtime_api::client::result::SdkError,
+    chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc},
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    serde::{Deserialize, Serialize},
+    sha2::{Digest, Sha256},
+    std::{
+        collections::{BTreeMap, HashMap},
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+const OP_CLOSE_STALE: &str = "CloseStale";
+
+/// How long an opportunity can go unseen, relative to the latest sighting on its portal, before it is closed.
+pub const DEFAULT_UNSEEN_DAYS: i64 = 14;
+
+/// How long after its due date an opportunity is expired. Due dates are in the portal's local time, so this also
+/// covers the difference from UTC.
+pub const DUE_GRACE: Duration = Duration::days(1);
+
+const DDB_KEY_CRAWL_ID: &str = "CrawlId";
+const DDB_KEY_REQUEST_ID: &str = "RequestId";
+const DDB_KEY_PORTAL: &str = "Portal";
+const DDB_KEY_STATUS: &str = "Status";
+const DDB_KEY_DUE_AT: &str = "DueAt";
+const DDB_KEY_LAST_SEEN_AT: &str = "LastSeenAt";
+const DDB_KEY_LAST_SEEN_CRAWL_ID: &str = "LastSeenCrawlId";
+const DDB_KEY_CLOSED_AT: &str = "ClosedAt";
+const DDB_KEY_CLOSED_REASON: &str = "ClosedReason";
+const DDB_KEY_FINGERPRINT: &str = "Fingerprint";
+const DDB_KEY_LAST_SEEN_TENANT: &str = "LastSeenTenant";
+const DDB_KEY_RECORD: &str = "Record";
+const DDB_KEY_DUPLICATE_OF: &str = "DuplicateOf";
+const DDB_KEY_STAGE: &str = "Stage";
+const DDB_KEY_STAGE_HISTORY: &str = "StageHistory";
+
+/// Partition key prefix for opportunity items; the portal follows it.
+const OPPORTUNITY_PARTITION_PREFIX: &str = "Opportunity#";
+
+/// Format of stored due dates.
+const DUE_AT_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// Possible reconciliation operations.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum ReconcileOperation {
+    /// Close opportunities that are past due or no longer listed.
+    CloseStale,
+}
+
+impl FromStr for ReconcileOperation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            OP_CLOSE_STALE => Ok(Self::CloseStale),
+            _ => Err(format!("Unknown operation: {value}")),
+        }
+    }
+}
+
+impl Display for ReconcileOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.operation())
+    }
+}
+
+impl ReconcileOperation {
+    /// Handle a request.
+    pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+        match self {
+            Self::CloseStale => close_stale(log_config, req, context).await,
+        }
+    }
+
+    /// Return the operation name within the subsystem.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::CloseStale => OP_CLOSE_STALE,
+        }
+    }
+}
+
+/// What a sighting of an opportunity found compared to the last one.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Sighting {
+    /// The opportunity had not been seen before, or had been closed.
+    New,
+
+    /// The opportunity's details changed since it was last seen.
+    Changed,
+
+    /// The opportunity is as it was last seen.
+    Unchanged,
+}
+
+impl Sighting {
+    /// Indicates whether the portal published something new or changed.
+    pub fn is_change(&self) -> bool {
+        *self != Self::Unchanged
+    }
+}
+
+/// An open opportunity in the opportunity store.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StoredOpportunity {
+    /// The portal (subsystem) the opportunity was found on.
+    pub portal: String,
+
+    /// The URL of the opportunity's page.
+    pub url: String,
+
+    /// When responses are due, in the portal's local time.
+    pub due_at: Option<NaiveDateTime>,
+
+    /// When the opportunity was last parsed from its detail page.
+    pub last_seen_at: DateTime<Utc>,
+}
+
+/// Which stored opportunities [`OpportunityStore::records`] reads.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RecordFilter {
+    /// Only read opportunities found on this portal (subsystem).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub portal: Option<String>,
+
+    /// Only read opportunities last seen on or after this day (UTC).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seen_from: Option<NaiveDate>,
+
+    /// Only read opportunities last seen on or before this day (UTC).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seen_until: Option<NaiveDate>,
+}
+
+/// A page of full opportunity records read by [`OpportunityStore::records`].
+#[derive(Clone, Debug, Default)]
+pub struct RecordPage {
+    /// The opportunities on the page, with their current status.
+    pub records: Vec<Opportunity>,
+
+    /// The number of items on the page without a readable record, such as those last seen before records were stored.
+    pub skipped: usize,
+
+    /// Where the next page starts, or `None` if this is the last page.
+    pub next_key: Option<HashMap<String, AttributeValue>>,
+}
+
+/// Storage for the lifecycle of opportunities in DynamoDB.
+#[derive(Clone, Debug)]
+pub struct OpportunityStore {
+    ddb_client: DynamoDbClient,
+    table_name: String,
+}
+
+impl OpportunityStore {
+    /// Create an opportunity store using the given table.
+    pub fn new<S: Into<String>>(ddb_client: DynamoDbClient, table_name: S) -> Self {
+        Self {
+            ddb_client,
+            table_name: table_name.into(),
+        }
+    }
+
+    /// Return the name of the table opportunities are recorded in.
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    /// Record that an open opportunity was seen by a crawl, reopening it if it had been closed, and return how it
+    /// compares to the previous sighting.
+    pub async fn record_seen(&self, opportunity: &Opportunity, crawl_id: &str) -> Result<Sighting, BoxError> {
+        let (set_due_at, remove_due_at) = match opportunity.due_at {
+            Some(_) => (", #due_at = :due_at", ""),
+            None => ("", ", #due_at"),
+        };
+        let (set_tenant, remove_tenant) = match opportunity.tenant {
+            Some(_) => (", #tenant = :tenant", ""),
+            None => ("", ", #tenant"),
+        };
+        let update = format!(
+            "SET #status = :open, #last_seen_at = :now, #last_seen_crawl_id = :crawl_id, #portal = :portal, \
+             #classification = :classification, #fingerprint = :fingerprint, #record = :record{set_due_at}{set_tenant} REMOVE #closed_at, #closed_reason{remove_due_at}\
+             {remove_tenant}"
+        );
+        let fingerprint = fingerprint(opportunity);
+        let record = serde_json::to_string(opportunity)?;
+        let request = self
+            .ddb_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(opportunity_partition_key(&opportunity.portal)))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(opportunity.url.clone()))
+            .expression_attribute_names("#status", DDB_KEY_STATUS)
+            .expression_attribute_names("#last_seen_at", DDB_KEY_LAST_SEEN_AT)
+            .expression_attribute_names("#last_seen_crawl_id", DDB_KEY_LAST_SEEN_CRAWL_ID)
+            .expression_attribute_names("#portal", DDB_KEY_PORTAL)
+            .expression_attribute_names("#closed_at", DDB_KEY_CLOSED_AT)
+            .expression_attribute_names("#closed_reason", DDB_KEY_CLOSED_REASON)
+            .expression_attribute_names("#due_at", DDB_KEY_DUE_AT)
+            .expression_attribute_names("#fingerprint", DDB_KEY_FINGERPRINT)
+            .expression_attribute_names("#tenant", DDB_KEY_LAST_SEEN_TENANT)
+            .expression_attribute_names("#classification", DDB_KEY_CLASSIFICATION)
+            .expression_attribute_names("#record", DDB_KEY_RECORD)
+            .expression_attribute_values(":open", AttributeValue::S(status_str(OpportunityStatus::Open).to_string()))
+            .expression_attribute_values(":now", AttributeValue::S(Utc::now().to_rfc3339()))
+            .expression_attribute_values(":crawl_id", AttributeValue::S(crawl_id.to_string()))
+            .expression_attribute_values(":portal", AttributeValue::S(opportunity.portal.clone()))
+            .expression_attribute_values(":fingerprint", AttributeValue::S(fingerprint.clone()))
+            .expression_attribute_values(":record", AttributeValue::S(record))
+            .expression_attribute_values(
+                ":classification",
+                AttributeValue::S(Classification::ExtractedOpportunity.to_string()),
+            )
+            .return_values(ReturnValue::UpdatedOld);
+        let request = match opportunity.due_at {
+            Some(due_at) => request
+                .expression_attribute_values(":due_at", AttributeValue::S(due_at.format(DUE_AT_FORMAT).to_string())),
+            None => request,
+        };
+        let request = match opportunity.tenant.as_ref() {
+            Some(tenant) => request.expression_attribute_values(":tenant", AttributeValue::S(tenant.clone())),
+            None => request,
+        };
+
+        match request.update_expression(update).send().await {
+            Ok(output) => Ok(sighting(&output.attributes.unwrap_or_default(), &fingerprint)),
+            Err(e) => {
+                error!("UpdateItem opportunity {}: {}", opportunity.url, aws_err_str(&e));
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Mark an opportunity closed or expired, returning `false` if it was already closed.
+    ///
+    /// An opportunity that was never recorded as seen is recorded closed, so a detail page found closed on its first
+    /// visit is kept out of later sweeps.
+    pub async fn close(
+        &self,
+        portal: &str,
+        url: &str,
+        status: OpportunityStatus,
+        reason: &str,
+    ) -> Result<bool, BoxError> {
+        let result = self
+            .ddb_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(opportunity_partition_key(portal)))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(url.to_string()))
+            .update_expression("SET #status = :status, #closed_at = :now, #closed_reason = :reason, #portal = :portal")
+            .condition_expression("attribute_not_exists(#status) OR #status = :open")
+            .expression_attribute_names("#status", DDB_KEY_STATUS)
+            .expression_attribute_names("#closed_at", DDB_KEY_CLOSED_AT)
+            .expression_attribute_names("#closed_reason", DDB_KEY_CLOSED_REASON)
+            .expression_attribute_names("#portal", DDB_KEY_PORTAL)
+            .expression_attribute_values(":status", AttributeValue::S(status_str(status).to_string()))
+            .expression_attribute_values(":open", AttributeValue::S(status_str(OpportunityStatus::Open).to_string()))
+            .expression_attribute_values(":now", AttributeValue::S(Utc::now().to_rfc3339()))
+            .expression_attribute_values(":reason", AttributeValue::S(reason.to_string()))
+            .expression_attribute_values(":portal", AttributeValue::S(portal.to_string()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError(ref e))
+                if matches!(e.err(), UpdateItemError::ConditionalCheckFailedException(_)) =>
+            {
+                debug!("Opportunity {url} is already closed");
+                Ok(false)
+            }
+            Err(e) => {
+                error!("UpdateItem close opportunity {url}: {}", aws_err_str(&e));
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Link an opportunity to the one it duplicates on another portal, or unlink it if `duplicate_of` is `None`.
+    ///
+    /// Only opportunities already in the store are updated; returns `false` for any other.
+    pub async fn set_duplicate_of(
+        &self,
+        portal: &str,
+        url: &str,
+        duplicate_of: Option<&str>,
+    ) -> Result<bool, BoxError> {
+        let request = self
+            .ddb_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(opportunity_partition_key(portal)))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(url.to_string()))
+            .condition_expression("attribute_exists(#status)")
+            .expression_attribute_names("#status", DDB_KEY_STATUS)
+            .expression_attribute_names("#duplicate_of", DDB_KEY_DUPLICATE_OF);
+        let request = match duplicate_of {
+            Some(duplicate_of) => request
+                .update_expression("SET #duplicate_of = :duplicate_of")
+                .expression_attribute_values(":duplicate_of", AttributeValue::S(duplicate_of.to_string())),
+            None => request.update_expression("REMOVE #duplicate_of"),
+        };
+
+        match request.send().await {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError(ref e))
+                if matches!(e.err(), UpdateItemError::ConditionalCheckFailedException(_)) =>
+            {
+                warn!("Opportunity {url} is not in the opportunity store");
+                Ok(false)
+            }
+            Err(e) => {
+                error!("UpdateItem duplicate link of opportunity {url}: {}", aws_err_str(&e));
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Move an opportunity to a later lifecycle stage, appending the transition to its history.
+    ///
+    /// Returns the stage the opportunity was in before, and whether it moved; an opportunity already at `stage` or a
+    /// later one is left as it is. An opportunity not yet in the store is recorded at `stage`.
+    pub async fn advance_stage(
+        &self,
+        portal: &str,
+        url: &str,
+        transition: &StageTransition,
+    ) -> Result<(Option<SolicitationStage>, bool), BoxError> {
+        let earlier: Vec<String> = SolicitationStage::ALL
+            .iter()
+            .filter(|stage| **stage < transition.stage)
+            .map(|stage| stage.as_str().to_string())
+            .collect();
+        let condition = match earlier.len() {
+            0 => "attribute_not_exists(#stage)".to_string(),
+            n => format!(
+                "attribute_not_exists(#stage) OR #stage IN ({})",
+                (0..n).map(|i| format!(":earlier{i}")).collect::<Vec<_>>().join(", ")
+            ),
+        };
+        let mut request = self
+            .ddb_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(opportunity_partition_key(portal)))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(url.to_string()))
+            .update_expression(
+                "SET #stage = :stage, #history = list_append(if_not_exists(#history, :empty), :transition), \
+                 #portal = :portal",
+            )
+            .condition_expression(condition)
+            .expression_attribute_names("#stage", DDB_KEY_STAGE)
+            .expression_attribute_names("#history", DDB_KEY_STAGE_HISTORY)
+            .expression_attribute_names("#portal", DDB_KEY_PORTAL)
+            .expression_attribute_values(":stage", AttributeValue::S(transition.stage.as_str().to_string()))
+            .expression_attribute_values(":empty", AttributeValue::L(vec![]))
+            .expression_attribute_values(
+                ":transition",
+                AttributeValue::L(vec![AttributeValue::S(serde_json::to_string(transition)?)]),
+            )
+            .expression_attribute_values(":portal", AttributeValue::S(portal.to_string()))
+            .return_values(ReturnValue::UpdatedOld)
+            .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld);
+        for (i, stage) in earlier.into_iter().enumerate() {
+            request = request.expression_attribute_values(format!(":earlier{i}"), AttributeValue::S(stage));
+        }
+
+        match request.send().await {
+            Ok(output) => Ok((stage_attr(&output.attributes.unwrap_or_default()), true)),
+            Err(e) => match e.as_service_error() {
+                Some(UpdateItemError::ConditionalCheckFailedException(failed)) => {
+                    let current = failed.item().and_then(stage_attr);
+                    debug!("Opportunity {url} is already {current:?}");
+                    Ok((current, false))
+                }
+                _ => {
+                    error!("UpdateItem stage of opportunity {url}: {}", aws_err_str(&e));
+                    Err(e.into())
+                }
+            },
+        }
+    }
+
+    /// Return every open opportunity, querying the partition of each crawled portal.
+    pub async fn open(&self) -> Result<Vec<StoredOpportunity>, BoxError> {
+        let mut opportunities = Vec::new();
+
+        for portal in coverage::subsystems() {
+            let mut start_key = None;
+
+            loop {
+                let result = self
+                    .ddb_client
+                    .query()
+                    .table_name(&self.table_name)
+                    .key_condition_expression("#pk = :pk")
+                    .filter_expression("#status = :open")
+                    .expression_attribute_names("#pk", DDB_KEY_CRAWL_ID)
+                    .expression_attribute_names("#status", DDB_KEY_STATUS)
+                    .expression_attribute_values(":pk", AttributeValue::S(opportunity_partition_key(&portal)))
+                    .expression_attribute_values(
+                        ":open",
+                        AttributeValue::S(status_str(OpportunityStatus::Open).to_string()),
+                    )
+                    .set_exclusive_start_key(start_key)
+                    .send()
+                    .await;
+                let output = match result {
+                    Ok(output) => output,
+                    Err(e) => {
+                        error!("Query {portal} open opportunities: {}", aws_err_str(&e));
+                        return Err(e.into());
+                    }
+                };
+
+                opportunities.extend(output.items.unwrap_or_default().iter().filter_map(parse_opportunity));
+
+                start_key = output.last_evaluated_key;
+                if start_key.is_none() {
+                    break;
+                }
+            }
+        }
+
+        Ok(opportunities)
+    }
+
+    /// Read a page of at most `limit` items of the full records of the opportunities matching `filter`, starting at
+    /// `start_key`.
+    ///
+    /// Each portal's opportunities are queried from its partition; without a portal in the filter, the partitions of
+    /// the crawled portals are read in turn. A page never spans two partitions: the last page of one ends with a key
+    /// holding only the partition key of the next, which starts it from the beginning.
+    pub async fn records(
+        &self,
+        filter: &RecordFilter,
+        start_key: Option<HashMap<String, AttributeValue>>,
+        limit: i32,
+    ) -> Result<RecordPage, BoxError> {
+        let portals = match filter.portal.as_ref() {
+            Some(portal) => vec![portal.clone()],
+            None => coverage::subsystems(),
+        };
+        let start_portal = start_key
+            .as_ref()
+            .and_then(|key| string_attr(key, DDB_KEY_CRAWL_ID))
+            .and_then(|pk| pk.strip_prefix(OPPORTUNITY_PARTITION_PREFIX).map(str::to_string));
+        let index = match start_portal {
+            Some(start_portal) => match portals.iter().position(|portal| *portal == start_portal) {
+                Some(index) => index,
+                None => return Err(format!("Invalid start key: unknown portal {start_portal}").into()),
+            },
+            None => 0,
+        };
+        let Some(portal) = portals.get(index) else {
+            return Ok(RecordPage::default());
+        };
+        let start_key = start_key.filter(|key| key.contains_key(DDB_KEY_REQUEST_ID));
+
+        let mut conditions: Vec<&str> = Vec::new();
+        let mut names = HashMap::from([("#pk".to_string(), DDB_KEY_CRAWL_ID.to_string())]);
+        let mut values = HashMap::from([(":pk".to_string(), AttributeValue::S(opportunity_partition_key(portal)))]);
+
+        if filter.seen_from.is_some() || filter.seen_until.is_some() {
+            names.insert("#last_seen_at".to_string(), DDB_KEY_LAST_SEEN_AT.to_string());
+        }
+        // Sightings are stored in RFC 3339 form in UTC, so they compare with days as strings.
+        if let Some(from) = filter.seen_from {
+            conditions.push("#last_seen_at >= :seen_from");
+            values.insert(":seen_from".to_string(), AttributeValue::S(from.to_string()));
+        }
+        if let Some(until) = filter.seen_until.and_then(|until| until.succ_opt()) {
+            conditions.push("#last_seen_at < :seen_until");
+            values.insert(":seen_until".to_string(), AttributeValue::S(until.to_string()));
+        }
+        let filter_expression = (!conditions.is_empty()).then(|| conditions.join(" AND "));
+
+        let result = self
+            .ddb_client
+            .query()
+            .table_name(&self.table_name)
+            .key_condition_expression("#pk = :pk")
+            .set_filter_expression(filter_expression)
+            .set_expression_attribute_names(Some(names))
+            .set_expression_attribute_values(Some(values))
+            .set_exclusive_start_key(start_key)
+            .limit(limit)
+            .send()
+            .await;
+        let (items, next_key) = match result {
+            Ok(output) => (output.items.unwrap_or_default(), output.last_evaluated_key),
+            Err(e) => {
+                error!("Query {portal} opportunity records: {}", aws_err_str(&e));
+                return Err(e.into());
+            }
+        };
+        let next_key = next_key.or_else(|| {
+            portals.get(index + 1).map(|next| {
+                HashMap::from([(DDB_KEY_CRAWL_ID.to_string(), AttributeValue::S(opportunity_partition_key(next)))])
+            })
+        });
+
+        let records: Vec<Opportunity> = items.iter().filter_map(parse_record).collect();
+        Ok(RecordPage {
+            skipped: items.len() - records.len(),
+            records,
+            next_key,
+        })
+    }
+}
+
+/// Decide whether an open opportunity is stale, returning the status to give it and why.
+///
+/// `portal_last_seen` is the most recent sighting of any opportunity on the same portal.
+pub fn staleness(
+    opportunity: &StoredOpportunity,
+    now: DateTime<Utc>,
+    portal_last_seen: DateTime<Utc>,
+    unseen_after: Duration,
+) -> Option<(OpportunityStatus, String)> {
+    if let Some(due_at) = opportunity.due_at {
+        if due_at + DUE_GRACE < now.naive_utc() {
+            return Some((OpportunityStatus::Expired, format!("due at {due_at}")));
+        }
+    }
+
+    if opportunity.last_seen_at + unseen_after < portal_last_seen {
+        return Some((
+            OpportunityStatus::Closed,
+            format!("not listed since {}", opportunity.last_seen_at.format("%Y-%m-%d %H:%M:%S UTC")),
+        ));
+    }
+
+    None
+}
+
+/// Return a digest of what a parser extracted for an opportunity, ignoring the parser, quality, and tenant stamps,
+/// which change with the crawler rather than the portal.
+pub fn fingerprint(opportunity: &Opportunity) -> String {
+    let published = Opportunity {
+        parsed_by: None,
+        quality: None,
+        tenant: None,
+        ..opportunity.clone()
+    };
+    let json = serde_json::to_vec(&published).expect("opportunities are serializable");
+    hex::encode(Sha256::digest(json))
+}
+
+/// Compare a sighting with the attributes the opportunity had before it.
+///
+/// Opportunities recorded before fingerprints were stored are taken to be unchanged.
+fn sighting(old: &HashMap<String, AttributeValue>, fingerprint: &str) -> Sighting {
+    if string_attr(old, DDB_KEY_STATUS).as_deref() != Some(status_str(OpportunityStatus::Open)) {
+        return Sighting::New;
+    }
+
+    match string_attr(old, DDB_KEY_FINGERPRINT) {
+        Some(old_fingerprint) if old_fingerprint != fingerprint => Sighting::Changed,
+        _ => Sighting::Unchanged,
+    }
+}
+
+/// Close every open opportunity that is past due or no longer listed.
+async fn close_stale(log_config: LogConfig, req: Request, _context: Context) -> Result<Response, LambdaError> {
+    let unseen_after = log_config.reconcile_unseen_after;
+    let crawl_id = req.crawl.crawl_id.clone().unwrap_or_default();
+
+    let opportunities = log_config.opportunities.open().await?;
+    let mut portal_last_seen: BTreeMap<&str, DateTime<Utc>> = BTreeMap::new();
+    for opportunity in opportunities.iter() {
+        let latest = portal_last_seen.entry(&opportunity.portal).or_insert(opportunity.last_seen_at);
+        *latest = (*latest).max(opportunity.last_seen_at);
+    }
+
+    let now = Utc::now();
+    let mut closed = 0;
+    for opportunity in opportunities.iter() {
+        let Some((status, reason)) =
+            staleness(opportunity, now, portal_last_seen[opportunity.portal.as_str()], unseen_after)
+        else {
+            continue;
+        };
+
+        if closure::close(&log_config, &crawl_id, &opportunity.portal, &opportunity.url, status, &reason).await? {
+            closed += 1;
+        }
+    }
+
+    info!("Closed {closed} of {} open opportunities", opportunities.len());
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Return the partition key of a portal's opportunities.
+fn opportunity_partition_key(portal: &str) -> String {
+    format!("{OPPORTUNITY_PARTITION_PREFIX}{portal}")
+}
+
+/// Return the stored form of a status.
+fn status_str(status: OpportunityStatus) -> &'static str {
+    match status {
+        OpportunityStatus::Open => "Open",
+        OpportunityStatus::Closed => "Closed",
+        OpportunityStatus::Expired => "Expired",
+    }
+}
+
+fn string_attr(item: &HashMap<String, AttributeValue>, name: &str) -> Option<String> {
+    item.get(name).and_then(|v| v.as_s().ok()).cloned()
+}
+
+/// Return the lifecycle stage of an opportunity item, if it has one.
+fn stage_attr(item: &HashMap<String, AttributeValue>) -> Option<SolicitationStage> {
+    let stage = string_attr(item, DDB_KEY_STAGE)?;
+    SolicitationStage::ALL.into_iter().find(|known| known.as_str() == stage)
+}
+
+/// Parse an opportunity item, skipping incomplete ones.
+fn parse_opportunity(item: &HashMap<String, AttributeValue>) -> Option<StoredOpportunity> {
+    let portal = string_attr(item, DDB_KEY_CRAWL_ID)?.strip_prefix(OPPORTUNITY_PARTITION_PREFIX)?.to_string();
+    let url = string_attr(item, DDB_KEY_REQUEST_ID)?;
+    let Some(last_seen_at) =
+        string_attr(item, DDB_KEY_LAST_SEEN_AT).and_then(|value| DateTime::parse_from_rfc3339(&value).ok())
+    else {
+        warn!("Opportunity {url} has no valid {DDB_KEY_LAST_SEEN_AT}");
+        return None;
+    };
+    let due_at =
+        string_attr(item, DDB_KEY_DUE_AT).and_then(|value| NaiveDateTime::parse_from_str(&value, DUE_AT_FORMAT).ok());
+
+    Some(StoredOpportunity {
+        portal,
+        url,
+        due_at,
+        last_seen_at: last_seen_at.with_timezone(&Utc),
+    })
+}
+
+/// Parse the full record of an opportunity item, giving it the item's current status, duplicate link, and lifecycle
+/// stage. Items without a readable record are skipped.
+fn parse_record(item: &HashMap<String, AttributeValue>) -> Option<Opportunity> {
+    let mut opportunity: Opportunity = serde_json::from_str(&string_attr(item, DDB_KEY_RECORD)?).ok()?;
+    let status = string_attr(item, DDB_KEY_STATUS);
+    opportunity.status = [OpportunityStatus::Closed, OpportunityStatus::Expired]
+        .into_iter()
+        .find(|closed| status.as_deref() == Some(status_str(*closed)))
+        .unwrap_or(OpportunityStatus::Open);
+    opportunity.duplicate_of = string_attr(item, DDB_KEY_DUPLICATE_OF);
+    opportunity.stage = stage_attr(item).or(opportunity.stage);
+    opportunity.stage_history = item
+        .get(DDB_KEY_STAGE_HISTORY)
+        .and_then(|v| v.as_l().ok())
+        .map(|history| history.iter().filter_map(|v| serde_json::from_str(v.as_s().ok()?).ok()).collect())
+        .unwrap_or_default();
+    Some(opportunity)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{fingerprint, parse_opportunity, parse_record, sighting, staleness, Sighting, StoredOpportunity},
+        crate::opportunity::{Opportunity, OpportunityStatus, SolicitationStage, StageTransition},
+        aws_sdk_dynamodb::types::AttributeValue,
+        chrono::{Duration, NaiveDate, TimeZone, Utc},
+        std::collections::HashMap,
+    };
+
+    #[test]
+    fn stale_opportunities() {
+        let item = HashMap::from([
+            ("CrawlId".to_string(), AttributeValue::S("Opportunity#Webs".to_string())),
+            ("RequestId".to_string(), AttributeValue::S("https://example.com/bid/1".to_string())),
+            ("Status".to_string(), AttributeValue::S("Open".to_string())),
+            ("DueAt".to_string(), AttributeValue::S("2023-04-10T14:00:00".to_string())),
+            ("LastSeenAt".to_string(), AttributeValue::S("2023-04-01T08:00:00+00:00".to_string())),
+        ]);
+        let opportunity = parse_opportunity(&item).unwrap();
+        assert_eq!(
+            opportunity,
+            StoredOpportunity {
+                portal: "Webs".to_string(),
+                url: "https://example.com/bid/1".to_string(),
+                due_at: NaiveDate::from_ymd_opt(2023, 4, 10).unwrap().and_hms_opt(14, 0, 0),
+                last_seen_at: Utc.with_ymd_and_hms(2023, 4, 1, 8, 0, 0).unwrap(),
+            }
+        );
+
+        let unseen_after = Duration::days(14);
+        let seen = opportunity.last_seen_at;
+
+        // Still open: due tomorrow and seen in the latest crawl.
+        let now = Utc.with_ymd_and_hms(2023, 4, 9, 0, 0, 0).unwrap();
+        assert_eq!(staleness(&opportunity, now, seen, unseen_after), None);
+
+        // Due dates get a day's grace before the opportunity expires.
+        let now = Utc.with_ymd_and_hms(2023, 4, 11, 0, 0, 0).unwrap();
+        assert_eq!(staleness(&opportunity, now, seen, unseen_after), None);
+        let now = Utc.with_ymd_and_hms(2023, 4, 12, 0, 0, 0).unwrap();
+        assert_eq!(staleness(&opportunity, now, seen, unseen_after).unwrap().0, OpportunityStatus::Expired);
+
+        // Without a due date, an opportunity is closed once the portal's crawls stop listing it.
+        let undated = StoredOpportunity {
+            due_at: None,
+            ..opportunity
+        };
+        let later = Utc.with_ymd_and_hms(2023, 6, 1, 0, 0, 0).unwrap();
+        assert_eq!(staleness(&undated, later, seen + Duration::days(3), unseen_after), None);
+        assert_eq!(
+            staleness(&undated, later, seen + Duration::days(15), unseen_after),
+            Some((OpportunityStatus::Closed, "not listed since 2023-04-01 08:00:00 UTC".to_string()))
+        );
+
+        // Items without a last sighting are skipped.
+        let mut incomplete = item.clone();
+        incomplete.remove("LastSeenAt");
+        assert_eq!(parse_opportunity(&incomplete), None);
+
+        // Full records take the item's current status; items without one are skipped.
+        assert_eq!(parse_record(&item), None);
+        let mut recorded = item.clone();
+        let record = Opportunity {
+            portal: "Webs".to_string(),
+            title: "Paving".to_string(),
+            ..Opportunity::default()
+        };
+        recorded.insert("Record".to_string(), AttributeValue::S(serde_json::to_string(&record).unwrap()));
+        recorded.insert("Status".to_string(), AttributeValue::S("Expired".to_string()));
+        let parsed = parse_record(&recorded).unwrap();
+        assert_eq!((parsed.title.as_str(), parsed.status), ("Paving", OpportunityStatus::Expired));
+        assert_eq!(parsed.duplicate_of, None);
+        recorded.insert("DuplicateOf".to_string(), AttributeValue::S("https://example.org/bids/7".to_string()));
+        assert_eq!(parse_record(&recorded).unwrap().duplicate_of.as_deref(), Some("https://example.org/bids/7"));
+
+        let transition = StageTransition {
+            stage: SolicitationStage::Awarded,
+            at: Utc.with_ymd_and_hms(2023, 5, 2, 9, 0, 0).unwrap(),
+            reason: "page says \"notice of award\"".to_string(),
+        };
+        recorded.insert("Stage".to_string(), AttributeValue::S("Awarded".to_string()));
+        recorded.insert(
+            "StageHistory".to_string(),
+            AttributeValue::L(vec![
+                AttributeValue::S(serde_json::to_string(&transition).unwrap()),
+                AttributeValue::S("{}".to_string()),
+            ]),
+        );
+        let parsed = parse_record(&recorded).unwrap();
+        assert_eq!((parsed.stage, parsed.stage_history), (Some(SolicitationStage::Awarded), vec![transition]));
+    }
+
+    #[test]
+    fn sightings() {
+        let opportunity = Opportunity {
+            portal: "Webs".to_string(),
+            url: "https://example.com/bid/1".to_string(),
+            title: "Road resurfacing".to_string(),
+            ..Opportunity::default()
+        };
+        let current = fingerprint(&opportunity);
+        assert_eq!(fingerprint(&opportunity.clone().with_parser("Webs:OpportunityDetail", 2)), current);
+        assert_eq!(fingerprint(&opportunity.clone().with_tenant(Some("Analytics"))), current);
+
+        let amended = Opportunity {
+            due_at: NaiveDate::from_ymd_opt(2023, 4, 10).unwrap().and_hms_opt(14, 0, 0),
+            ..opportunity.clone()
+        };
+        assert_ne!(fingerprint(&amended), current);
+
+        let item = |status: &str, fingerprint: Option<&str>| {
+            let mut item = HashMap::from([("Status".to_string(), AttributeValue::S(status.to_string()))]);
+            if let Some(fingerprint) = fingerprint {
+                item.insert("Fingerprint".to_string(), AttributeValue::S(fingerprint.to_string()));
+            }
+            item
+        };
+        assert_eq!(sighting(&HashMap::new(), &current), Sighting::New);
+        assert_eq!(sighting(&item("Closed", Some(&current)), &current), Sighting::New);
+        assert_eq!(sighting(&item("Open", Some(&current)), &current), Sighting::Unchanged);
+        assert_eq!(sighting(&item("Open", Some(&fingerprint(&amended))), &current), Sighting::Changed);
+        assert_eq!(sighting(&item("Open", None), &current), Sighting::Unchanged);
+        assert!(Sighting::Changed.is_change() && !Sighting::Unchanged.is_change());
+    }
+}
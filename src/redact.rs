@@ -0,0 +1,303 @@
+//! Redaction of personal data from stored pages.
+//!
+//! Government pages occasionally carry personal contact details that have to be purged on request. `Admin:Redact`
+//! rewrites the stored bodies named by `S3Keys`, or fetched from URLs matching `UrlPattern` (where `*` matches any run
+//! of characters), with every occurrence of each of the `RedactPatterns` masked by [`MASK`], matched ignoring ASCII
+//! case. Bodies keep their compression, encryption, and tags; see [`replace_body`].
+//!
+//! Each rewritten body gets a redaction audit item (`Redaction#{s3 key}` / `{redacted at}`) recording when it was
+//! redacted, by which Lambda request, and how many matches were masked. The patterns themselves are personal data, so
+//! the audit keeps only their SHA-256 digests, which are enough to show whether a given value was purged.
+//!
+//! Each stored body is also indexed by the host of the URLs it was fetched from (`BodyUrls#{host}` /
+//! `{url}#{s3 key}`), so finding bodies by URL queries a single host's partition. A `UrlPattern` therefore has to name
+//! its host before any wildcard.
+use {
+    crate::{
+        classification::{Classification, DDB_KEY_CLASSIFICATION},
+        httpext::{aws_err_str, load_body, replace_body, LogConfig},
+        BoxError,
+    },
+    aws_sdk_dynamodb::types::AttributeValue,
+    bytes::Bytes,
+    chrono::{DateTime, Utc},
+    log::*,
+    reqwest::Url,
+    sha2::{Digest, Sha256},
+    std::collections::{BTreeSet, HashMap},
+};
+
+const DDB_KEY_CRAWL_ID: &str = "CrawlId";
+const DDB_KEY_REQUEST_ID: &str = "RequestId";
+const DDB_KEY_S3_BUCKET: &str = "S3Bucket";
+const DDB_KEY_S3_KEY: &str = "S3Key";
+const DDB_KEY_MATCHES: &str = "Matches";
+const DDB_KEY_PATTERN_DIGESTS: &str = "PatternDigests";
+const DDB_KEY_LAMBDA_REQUEST_ID: &str = "LambdaRequestId";
+const DDB_KEY_URL: &str = "Url";
+
+/// Partition key prefix for redaction audit items; the S3 key of the redacted body follows it.
+const REDACTION_PARTITION_PREFIX: &str = "Redaction#";
+
+/// Partition key prefix for the URL index of stored bodies; the host of the URL follows it.
+const BODY_URL_PARTITION_PREFIX: &str = "BodyUrls#";
+
+/// The text that replaces each match.
+pub const MASK: &str = "[REDACTED]";
+
+/// A stored body to redact.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct RedactionTarget {
+    /// The S3 bucket holding the body.
+    pub bucket: String,
+
+    /// The S3 key of the body.
+    pub key: String,
+}
+
+/// Return `body` with every occurrence of each pattern masked, matched ignoring ASCII case, and the number of matches.
+///
+/// Empty patterns are ignored. Bodies are matched as bytes, so bodies that aren't valid UTF-8 are redacted too.
+pub fn redact(body: &[u8], patterns: &[String]) -> (Vec<u8>, usize) {
+    let patterns: Vec<&[u8]> = patterns.iter().map(|pattern| pattern.as_bytes()).filter(|p| !p.is_empty()).collect();
+    let mut redacted = Vec::with_capacity(body.len());
+    let mut matches = 0;
+    let mut i = 0;
+
+    while i < body.len() {
+        let rest = &body[i..];
+        match patterns
+            .iter()
+            .find(|pattern| rest.len() >= pattern.len() && rest[..pattern.len()].eq_ignore_ascii_case(pattern))
+        {
+            Some(pattern) => {
+                redacted.extend_from_slice(MASK.as_bytes());
+                matches += 1;
+                i += pattern.len();
+            }
+            None => {
+                redacted.push(body[i]);
+                i += 1;
+            }
+        }
+    }
+
+    (redacted, matches)
+}
+
+/// Indicates whether `url` matches `pattern`, where `*` matches any run of characters.
+pub fn url_matches(pattern: &str, url: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = url.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard: the whole URL must match.
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+/// Index a stored body under each distinct URL it was fetched from, so [`find_by_url`] can find it.
+pub(crate) async fn index_body_urls(
+    log_config: &LogConfig,
+    urls: &[&Url],
+    bucket: &str,
+    key: &str,
+) -> Result<(), BoxError> {
+    let mut indexed: Vec<&Url> = Vec::with_capacity(urls.len());
+
+    for url in urls.iter().copied() {
+        let Some(host) = url.host_str() else {
+            continue;
+        };
+        if indexed.contains(&url) {
+            continue;
+        }
+        indexed.push(url);
+
+        let item = HashMap::from([
+            (DDB_KEY_CRAWL_ID.to_string(), AttributeValue::S(format!("{BODY_URL_PARTITION_PREFIX}{host}"))),
+            (DDB_KEY_REQUEST_ID.to_string(), AttributeValue::S(format!("{url}#{key}"))),
+            (DDB_KEY_URL.to_string(), AttributeValue::S(url.to_string())),
+            (DDB_KEY_S3_BUCKET.to_string(), AttributeValue::S(bucket.to_string())),
+            (DDB_KEY_S3_KEY.to_string(), AttributeValue::S(key.to_string())),
+            (DDB_KEY_CLASSIFICATION.to_string(), AttributeValue::S(Classification::CrawlMetadata.to_string())),
+        ]);
+        log_config.ddb_writer.put(item).await?;
+    }
+
+    Ok(())
+}
+
+/// Return the partition of the URL index holding the URLs `pattern` can match, or `None` if the pattern has a
+/// wildcard before the end of its host.
+fn body_url_partition_key(pattern: &str) -> Option<String> {
+    let prefix = pattern.split('*').next().unwrap_or_default();
+    let (_, authority_and_path) = prefix.split_once("://")?;
+    if prefix.len() < pattern.len() && !authority_and_path.contains('/') {
+        return None;
+    }
+
+    let host = Url::parse(prefix).ok()?.host_str()?.to_string();
+    Some(format!("{BODY_URL_PARTITION_PREFIX}{host}"))
+}
+
+/// Return the stored bodies fetched from URLs matching `pattern`.
+///
+/// This queries the URL index of the pattern's host, narrowed to URLs starting with the part of the pattern before its
+/// first wildcard.
+pub async fn find_by_url(log_config: &LogConfig, pattern: &str) -> Result<BTreeSet<RedactionTarget>, BoxError> {
+    let Some(partition_key) = body_url_partition_key(pattern) else {
+        return Err(format!("UrlPattern {pattern} must name a host before any wildcard").into());
+    };
+    let prefix = pattern.split('*').next().unwrap_or_default();
+    let mut targets = BTreeSet::new();
+    let mut start_key = None;
+
+    loop {
+        let result = log_config
+            .ddb_client
+            .query()
+            .table_name(&log_config.ddb_table)
+            .key_condition_expression("#pk = :pk AND begins_with(#sk, :prefix)")
+            .expression_attribute_names("#pk", DDB_KEY_CRAWL_ID)
+            .expression_attribute_names("#sk", DDB_KEY_REQUEST_ID)
+            .expression_attribute_values(":pk", AttributeValue::S(partition_key.clone()))
+            .expression_attribute_values(":prefix", AttributeValue::S(prefix.to_string()))
+            .set_exclusive_start_key(start_key)
+            .send()
+            .await;
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => {
+                error!("Query body URL index for {pattern}: {}", aws_err_str(&e));
+                return Err(e.into());
+            }
+        };
+
+        for item in output.items.unwrap_or_default() {
+            let get_str = |name: &str| item.get(name).and_then(|value| value.as_s().ok()).cloned();
+            let matched = get_str(DDB_KEY_URL).is_some_and(|url| url_matches(pattern, &url));
+            if let (true, Some(bucket), Some(key)) = (matched, get_str(DDB_KEY_S3_BUCKET), get_str(DDB_KEY_S3_KEY)) {
+                targets.insert(RedactionTarget {
+                    bucket,
+                    key,
+                });
+            }
+        }
+
+        start_key = output.last_evaluated_key;
+        if start_key.is_none() {
+            return Ok(targets);
+        }
+    }
+}
+
+/// Redact a stored body, returning the number of matches masked. A body without matches is left untouched.
+pub async fn redact_body(
+    log_config: &LogConfig,
+    target: &RedactionTarget,
+    patterns: &[String],
+    lambda_request_id: &str,
+) -> Result<usize, BoxError> {
+    let body = load_body(log_config, &target.bucket, &target.key).await?;
+    let (redacted, matches) = redact(&body, patterns);
+    if matches == 0 {
+        return Ok(0);
+    }
+
+    replace_body(log_config, &target.bucket, &target.key, &Bytes::from(redacted)).await?;
+    record_redaction(log_config, target, patterns, matches, lambda_request_id, Utc::now()).await?;
+    Ok(matches)
+}
+
+/// Record a redaction audit item.
+async fn record_redaction(
+    log_config: &LogConfig,
+    target: &RedactionTarget,
+    patterns: &[String],
+    matches: usize,
+    lambda_request_id: &str,
+    redacted_at: DateTime<Utc>,
+) -> Result<(), BoxError> {
+    let result = log_config
+        .ddb_client
+        .put_item()
+        .table_name(&log_config.ddb_table)
+        .item(DDB_KEY_CRAWL_ID, AttributeValue::S(format!("{REDACTION_PARTITION_PREFIX}{}", target.key)))
+        .item(DDB_KEY_REQUEST_ID, AttributeValue::S(redacted_at.to_rfc3339()))
+        .item(DDB_KEY_S3_BUCKET, AttributeValue::S(target.bucket.clone()))
+        .item(DDB_KEY_S3_KEY, AttributeValue::S(target.key.clone()))
+        .item(DDB_KEY_MATCHES, AttributeValue::N(matches.to_string()))
+        .item(
+            DDB_KEY_PATTERN_DIGESTS,
+            AttributeValue::L(pattern_digests(patterns).into_iter().map(AttributeValue::S).collect()),
+        )
+        .item(DDB_KEY_LAMBDA_REQUEST_ID, AttributeValue::S(lambda_request_id.to_string()))
+        .item(DDB_KEY_CLASSIFICATION, AttributeValue::S(Classification::CrawlMetadata.to_string()))
+        .send()
+        .await;
+
+    if let Err(e) = result {
+        error!("PutItem redaction of {}: {}", target.key, aws_err_str(&e));
+        return Err(e.into());
+    }
+
+    Ok(())
+}
+
+/// Return the hex-encoded SHA-256 digests of the patterns, for the audit.
+fn pattern_digests(patterns: &[String]) -> Vec<String> {
+    patterns.iter().filter(|pattern| !pattern.is_empty()).map(|pattern| hex::encode(Sha256::digest(pattern))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{body_url_partition_key, pattern_digests, redact, url_matches};
+
+    #[test]
+    fn redaction() {
+        let patterns = vec!["jane.doe@example.gov".to_string(), "555-0100".to_string(), String::new()];
+        let body = b"<p>Contact Jane.Doe@Example.gov or 555-0100 (555-0100).</p>";
+
+        let (redacted, matches) = redact(body, &patterns);
+        assert_eq!(matches, 3);
+        assert_eq!(String::from_utf8(redacted).unwrap(), "<p>Contact [REDACTED] or [REDACTED] ([REDACTED]).</p>");
+
+        let (unchanged, matches) = redact(b"\xff\xfe<p>Bid</p>", &patterns);
+        assert_eq!((unchanged.as_slice(), matches), (b"\xff\xfe<p>Bid</p>".as_slice(), 0));
+
+        let digests = pattern_digests(&patterns);
+        assert_eq!(digests.len(), 2);
+        assert!(digests.iter().all(|digest| digest.len() == 64 && !digest.contains("555")));
+    }
+
+    #[test]
+    fn url_patterns() {
+        assert!(url_matches("https://example.gov/bid/1", "https://example.gov/bid/1"));
+        assert!(!url_matches("https://example.gov/bid/1", "https://example.gov/bid/12"));
+        assert!(url_matches("https://example.gov/bid/*", "https://example.gov/bid/12"));
+        assert!(url_matches("https://example.gov/*/contact*", "https://example.gov/bid/12/contact?id=3"));
+        assert!(!url_matches("https://example.gov/*/contact", "https://example.gov/bid/12/contact?id=3"));
+        assert!(!url_matches("https://example.gov/*", "https://other.gov/bid/12"));
+        assert!(url_matches("*", "https://other.gov/"));
+
+        assert_eq!(body_url_partition_key("https://example.gov/bid/*").as_deref(), Some("BodyUrls#example.gov"));
+        assert_eq!(body_url_partition_key("https://Example.gov:8443/*").as_deref(), Some("BodyUrls#example.gov"));
+        assert_eq!(body_url_partition_key("https://example.gov").as_deref(), Some("BodyUrls#example.gov"));
+        assert_eq!(body_url_partition_key("https://example.*/bid"), None);
+        assert_eq!(body_url_partition_key("*"), None);
+    }
+}
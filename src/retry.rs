@@ -0,0 +1,175 @@
+//! Temporary conditions that call for retrying an operation later.
+//!
+//! When a portal is down for maintenance or overloaded, failing the message only gets it redelivered after the queue's
+//! visibility timeout and, soon after, sent to the dead-letter queue. Instead, a handler returns a [`RetryableError`]
+//! and the [`Retry`][crate::middleware::Retry] middleware re-enqueues the request with a delay.
+//!
+//! Each re-enqueued request carries the history of its failed attempts, so once it gives up and is dead-lettered the
+//! message itself shows which conditions it hit and when.
+use {
+    serde::{Deserialize, Serialize},
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// The longest delay SQS allows on a message.
+pub const MAX_DELAY: Duration = Duration::from_secs(900);
+
+/// The default number of times a request is re-enqueued before its failure is allowed through.
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 24;
+
+/// How long to wait before retrying.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RetryDelay {
+    /// The same delay on every attempt.
+    Fixed(Duration),
+
+    /// A delay that starts at the given duration and doubles with each attempt.
+    Exponential(Duration),
+}
+
+/// An operation hit a temporary condition and should be retried after a delay.
+#[derive(Clone, Debug)]
+pub struct RetryableError {
+    /// The condition encountered, e.g. `WebsMaintenance`. Used as a metric dimension.
+    pub condition: &'static str,
+
+    /// The URL of the page that showed the condition.
+    pub url: String,
+
+    /// How long to wait before retrying.
+    pub delay: RetryDelay,
+}
+
+impl Display for RetryableError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{} at {}", self.condition, self.url)
+    }
+}
+
+impl Error for RetryableError {}
+
+/// A failed attempt at a request, recorded in the crawl parameters of the request re-enqueued after it.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct FailedAttempt {
+    /// The condition encountered, e.g. `WebsMaintenance`.
+    pub condition: String,
+
+    /// The error that was returned.
+    pub error: String,
+
+    /// When the attempt failed, in seconds since the Unix epoch.
+    pub failed_at: u64,
+}
+
+impl FailedAttempt {
+    /// Record a failed attempt that hit a retryable condition just now.
+    pub fn new(error: &RetryableError) -> Self {
+        Self {
+            condition: error.condition.to_string(),
+            error: error.to_string(),
+            failed_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        }
+    }
+}
+
+impl Display for FailedAttempt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{} at {}", self.error, self.failed_at)
+    }
+}
+
+/// A request kept hitting temporary conditions and was re-enqueued as many times as it may be.
+///
+/// This replaces the final [`RetryableError`] so the failure that dead-letters the request reports its whole history.
+#[derive(Debug)]
+pub struct RetriesExhaustedError {
+    /// The attempts that failed before the final one.
+    pub history: Vec<FailedAttempt>,
+
+    /// The error of the final attempt.
+    pub error: RetryableError,
+}
+
+impl Display for RetriesExhaustedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{} (gave up after {} attempts", self.error, self.history.len() + 1)?;
+        for attempt in self.history.iter() {
+            write!(f, "; {attempt}")?;
+        }
+        f.write_str(")")
+    }
+}
+
+impl Error for RetriesExhaustedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl RetryDelay {
+    /// Return the delay in whole seconds before the given attempt (the first retry is attempt 1), capped at
+    /// [`MAX_DELAY`].
+    pub fn seconds(&self, attempt: u32) -> u32 {
+        let delay = match self {
+            Self::Fixed(delay) => *delay,
+            Self::Exponential(base) => {
+                let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+                base.saturating_mul(factor)
+            }
+        };
+
+        delay.min(MAX_DELAY).as_secs() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{FailedAttempt, RetriesExhaustedError, RetryDelay, RetryableError},
+        std::time::Duration,
+    };
+
+    #[test]
+    fn retry_delays() {
+        let fixed = RetryDelay::Fixed(Duration::from_secs(900));
+        assert_eq!(fixed.seconds(1), 900);
+        assert_eq!(fixed.seconds(5), 900);
+        assert_eq!(RetryDelay::Fixed(Duration::from_secs(3600)).seconds(1), 900);
+
+        let exponential = RetryDelay::Exponential(Duration::from_secs(30));
+        assert_eq!(
+            (1..=6).map(|attempt| exponential.seconds(attempt)).collect::<Vec<_>>(),
+            [30, 60, 120, 240, 480, 900]
+        );
+        assert_eq!(exponential.seconds(40), 900);
+    }
+
+    #[test]
+    fn retries_exhausted() {
+        let error = |url: &str| RetryableError {
+            condition: "WebsMaintenance",
+            url: url.to_string(),
+            delay: RetryDelay::Fixed(Duration::from_secs(900)),
+        };
+        let history = vec![FailedAttempt {
+            failed_at: 1_700_000_000,
+            ..FailedAttempt::new(&error("https://example.com/1"))
+        }];
+        assert_eq!(history[0].condition, "WebsMaintenance");
+
+        let exhausted = RetriesExhaustedError {
+            history,
+            error: error("https://example.com/2"),
+        };
+        assert_eq!(
+            exhausted.to_string(),
+            "WebsMaintenance at https://example.com/2 (gave up after 2 attempts; WebsMaintenance at \
+             https://example.com/1 at 1700000000)"
+        );
+    }
+}
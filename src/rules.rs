@@ -0,0 +1,436 @@
+//! Request/response types for portals crawled with declarative extraction rules.
+//!
+//! Simple portals — a listing of links to one page per solicitation — can be onboarded without writing a parser. Each
+//! portal's rules name its listing URL, [selectors][selector] for the listing's links and next page, and how to read
+//! each field of a solicitation's page, optionally [transformed][extract::Transform]. Complex portals, such as the
+//! ASP.NET WebForms ones driven by postbacks, keep their hand-written parsers.
+//!
+//! Rules are read from the `Rules` subsystem's configuration document. Rules too large to hold there are stored as
+//! JSON objects in the log bucket and listed, relative to the log prefix, under `RuleKeys`:
+//!
+//! ```json
+//! {
+//!     "Portals": [{
+//!         "Id": "Olympia",
+//!         "Organization": "City of Olympia",
+//!         "ListingUrl": "https://bids.example.gov/open",
+//!         "Version": 1,
+//!         "Listing": {"Links": "table.bids td a", "NextPage": "a[rel=next]"},
+//!         "Detail": {
+//!             "Fields": {
+//!                 "Title": {"Selector": "h1"},
+//!                 "Reference": {"Selector": "th", "Label": "Bid Number", "Transforms": [{"StripPrefix": "No. "}]},
+//!                 "DueAt": {"Selector": "th", "Label": "Due Date"}
+//!             },
+//!             "Documents": "ul.documents a"
+//!         }
+//!     }],
+//!     "Tables": [{
+//!         "Id": "Thurston",
+//!         "Organization": "Thurston County",
+//!         "Url": "https://example.gov/open-bids.xlsx",
+//!         "Version": 1,
+//!         "Columns": {
+//!             "Title": {"Header": "Project"},
+//!             "Reference": {"Header": "Bid No."},
+//!             "DueAt": {"Header": "Closing Date"}
+//!         }
+//!     }],
+//!     "RuleKeys": ["rules/tumwater.json"]
+//! }
+//! ```
+//!
+//! Requests are matched to their portal's rules by host. `Tables` maps the columns of opportunity lists published as
+//! spreadsheets, which the [`ingest`][crate::ingest] subsystem downloads, to the same fields.
+mod extract;
+mod selector;
+mod table;
+
+pub(crate) use {
+    selector::Selector,
+    table::{extract_table, TableRules},
+};
+
+use {
+    crate::{
+        archive::{ArchivedContent, ArchivedPage},
+        attachment, closure,
+        config::load_subsystem_config,
+        contacts,
+        httpext::{log_aws_err, Client, HostAllowlist, LogConfig, ResponseExt},
+        journal::CrawlEvent,
+        lifecycle,
+        opportunity::DateLocale,
+        pagination, quality,
+        shapes::{CrawlParameters, NextRequest, Operation, Request, Response, SUBSYS_RULES},
+        soup::parse_html_str,
+        BoxError,
+    },
+    extract::{extract_listing, extract_opportunity, PortalRules},
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    reqwest::Url,
+    serde::{Deserialize, Serialize},
+    std::{
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+const OP_START_CRAWL: &str = "StartCrawl";
+const OP_FETCH_LISTING_PAGE: &str = "FetchListingPage";
+const OP_EXTRACT: &str = "Extract";
+
+const PARSER_LISTING: &str = "Listing";
+const PARSER_DETAIL: &str = "Detail";
+
+/// The rules documents: the `Rules` configuration document and the objects it names.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+struct RulesConfig {
+    /// Rules for each portal.
+    portals: Vec<PortalRules>,
+
+    /// Column mappings for each published table.
+    tables: Vec<TableRules>,
+
+    /// Keys, relative to the log prefix, of objects in the log bucket each holding one portal's rules.
+    rule_keys: Vec<String>,
+}
+
+/// Possible operations for rule-driven portals.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum RulesOperation {
+    /// Start a crawl of every portal with rules, or of the portal whose listing is given as the URL.
+    StartCrawl,
+
+    /// Fetch a page of a portal's listing.
+    FetchListingPage,
+
+    /// Fetch a solicitation's page and extract it with its portal's rules.
+    Extract,
+}
+
+impl FromStr for RulesOperation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            OP_START_CRAWL => Ok(Self::StartCrawl),
+            OP_FETCH_LISTING_PAGE => Ok(Self::FetchListingPage),
+            OP_EXTRACT => Ok(Self::Extract),
+            _ => Err(format!("Unknown operation: {value}")),
+        }
+    }
+}
+
+impl Display for RulesOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.operation())
+    }
+}
+
+impl RulesOperation {
+    /// Handle a request.
+    pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+        match self {
+            Self::StartCrawl => start_crawl(log_config, req, context).await,
+            Self::FetchListingPage => fetch_listing_page(log_config, req, context).await,
+            Self::Extract => extract(log_config, req, context).await,
+        }
+    }
+
+    /// Return the operation name within the subsystem.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::StartCrawl => OP_START_CRAWL,
+            Self::FetchListingPage => OP_FETCH_LISTING_PAGE,
+            Self::Extract => OP_EXTRACT,
+        }
+    }
+}
+
+impl PortalRules {
+    /// Return the URL of the first page of the listing.
+    fn listing_url(&self) -> Result<Url, BoxError> {
+        Url::parse(&self.listing_url).map_err(|e| format!("Invalid listing URL in rules for {}: {e}", self.id).into())
+    }
+
+    /// Return the name of one of this portal's parsers, e.g. `Rules:Olympia:Detail`.
+    fn parser_name(&self, parser: &str) -> String {
+        format!("{SUBSYS_RULES}:{}:{parser}", self.id)
+    }
+}
+
+/// Load the rules for every portal.
+async fn load_rules(log_config: &LogConfig) -> Result<Vec<PortalRules>, BoxError> {
+    let config: RulesConfig = load_subsystem_config(log_config, SUBSYS_RULES).await?;
+    let mut portals = config.portals;
+
+    for key in config.rule_keys.iter() {
+        let key = format!("{}{key}", log_config.s3_prefix);
+        let output = log_aws_err(
+            log_config.s3_client.get_object().bucket(&log_config.s3_bucket).key(&key).send().await,
+            &format!("GetObject s3://{}/{key}", log_config.s3_bucket),
+        )?;
+        let body = output.body.collect().await?.into_bytes();
+        let rules: PortalRules = serde_json::from_slice(&body)
+            .map_err(|e| format!("Invalid rules in s3://{}/{key}: {e}", log_config.s3_bucket))?;
+        portals.push(rules);
+    }
+
+    Ok(portals)
+}
+
+/// Load the column mappings for every published table.
+pub(crate) async fn load_table_rules(log_config: &LogConfig) -> Result<Vec<TableRules>, BoxError> {
+    let config: RulesConfig = load_subsystem_config(log_config, SUBSYS_RULES).await?;
+    Ok(config.tables)
+}
+
+/// Return the rules of the portal whose listing is on the same host as `url`.
+fn rules_for_url(portals: Vec<PortalRules>, url: &Url) -> Option<PortalRules> {
+    let host = url.host_str()?;
+    portals.into_iter().find(|rules| {
+        Url::parse(&rules.listing_url).is_ok_and(|listing_url| {
+            listing_url.host_str().is_some_and(|rules_host| rules_host.eq_ignore_ascii_case(host))
+        })
+    })
+}
+
+/// Return the URL of a request and its portal's rules, which operations other than `StartCrawl` require.
+async fn required_rules_url(log_config: &LogConfig, req: &Request) -> Result<(PortalRules, Url), BoxError> {
+    let Some(url) = req.url.as_deref() else {
+        return Err(format!("{} requires a URL", req.operation).into());
+    };
+
+    let url = Url::parse(url)?;
+    match rules_for_url(load_rules(log_config).await?, &url) {
+        Some(rules) => Ok((rules, url)),
+        None => Err(format!("No extraction rules are configured for {url}").into()),
+    }
+}
+
+/// Build a client for crawling the portal `rules` describe from `url`.
+fn build_client(
+    log_config: &LogConfig,
+    crawl: &CrawlParameters,
+    context: &Context,
+    rules: &PortalRules,
+    url: &Url,
+) -> Result<Client, BoxError> {
+    Ok(crawl
+        .build_client(log_config.clone(), context)
+        .portal(SUBSYS_RULES)
+        .allowed_hosts(HostAllowlist::from_urls([url, &rules.listing_url()?]))
+        .build()?)
+}
+
+/// Start a crawl by scheduling the first listing page of each portal with rules, or, given a URL, by fetching it as
+/// the first listing page of its portal.
+async fn start_crawl(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    if req.url.is_some() {
+        return fetch_listing_page(log_config, req, context).await;
+    }
+
+    let next_requests = load_rules(&log_config)
+        .await?
+        .into_iter()
+        .map(|rules| NextRequest {
+            operation: Operation::Rules(RulesOperation::FetchListingPage),
+            url: Some(rules.listing_url),
+            link_text: None,
+            crawl: req.crawl.child(),
+            delay_seconds: 0,
+        })
+        .collect();
+
+    Ok(Response {
+        next_requests,
+    })
+}
+
+/// Fetch a page of a portal's listing, returning requests for its solicitations and the next page.
+async fn fetch_listing_page(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let (rules, url) = required_rules_url(&log_config, &req).await?;
+    let client = build_client(&log_config, &req.crawl, &context, &rules, &url)?;
+
+    let response = match client.get(url.clone()).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch {} listing: {e}", rules.id);
+            return Err(e);
+        }
+    };
+
+    let document = parse_html_str(response.text()?);
+    let listing = extract_listing(&rules.listing, &document, response.url());
+    log_config
+        .journal
+        .record(
+            &client.crawl_id,
+            CrawlEvent::ParseSucceeded {
+                url: response.url().to_string(),
+                parser: rules.parser_name(PARSER_LISTING),
+                items: listing.detail_urls.len(),
+            },
+        )
+        .await;
+
+    let mut next_requests: Vec<NextRequest> = listing
+        .detail_urls
+        .into_iter()
+        .map(|url| NextRequest {
+            operation: Operation::Rules(RulesOperation::Extract),
+            url: Some(url.to_string()),
+            link_text: None,
+            crawl: req.crawl.child(),
+            delay_seconds: 0,
+        })
+        .collect();
+
+    if let Some(next_page) = listing.next_page {
+        next_requests.push(pagination::next_page_request(
+            Operation::Rules(RulesOperation::FetchListingPage),
+            &next_page,
+            &req.crawl,
+        ));
+    }
+
+    Ok(Response {
+        next_requests,
+    })
+}
+
+/// Fetch a solicitation's page and extract it into the normalized opportunity model with its portal's rules.
+async fn extract(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let (rules, url) = required_rules_url(&log_config, &req).await?;
+    let client = build_client(&log_config, &req.crawl, &context, &rules, &url)?;
+
+    let response = match client.get(url.clone()).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch {} solicitation: {e}", rules.id);
+            return Err(e);
+        }
+    };
+
+    let Some(response) = attachment::html_page(&log_config, &req.crawl, response, Some(url.as_str())).await else {
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    };
+
+    let markers: Vec<&str> = rules.not_found_markers.iter().map(String::as_str).collect();
+    if let Some(reason) = closure::soft_not_found(response.text()?, &markers, &req.crawl.locale.not_found_markers) {
+        closure::record_closed(&log_config, &client, SUBSYS_RULES, url.as_str(), &reason).await?;
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    }
+
+    let document = parse_html_str(response.text()?);
+    let parser = rules.parser_name(PARSER_DETAIL);
+    let result = extract_opportunity(&rules, &document, response.url(), req.crawl.locale.dates);
+    let event = match &result {
+        Ok(_) => CrawlEvent::ParseSucceeded {
+            url: response.url().to_string(),
+            parser: parser.clone(),
+            items: 1,
+        },
+        Err(e) => CrawlEvent::ParseFailed {
+            url: response.url().to_string(),
+            parser: parser.clone(),
+            error: e.to_string(),
+        },
+    };
+    log_config.journal.record(&client.crawl_id, event).await;
+
+    let mut opportunity = result?.with_parser(parser, rules.version).with_tenant(client.tenant.as_deref());
+    contacts::record_contacts(&log_config, &client, &mut opportunity, response.text()?).await;
+    lifecycle::record_stage(&log_config, &client, &mut opportunity, response.text()?).await;
+    quality::record_quality(&log_config, &client, &mut opportunity).await;
+    closure::record_seen(&log_config, &client, &opportunity).await;
+    info!("{} opportunity: {}", rules.id, serde_json::to_string(&opportunity)?);
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Parse a capture of a page from a portal with rules for the [Internet Archive backfill][crate::archive]: a page of
+/// the listing, whose solicitations and next page are returned as links, or a solicitation's page. Returns `None` if no
+/// portal's rules cover the page's host.
+pub(crate) async fn parse_archived_page(
+    log_config: &LogConfig,
+    text: &str,
+    page_url: &Url,
+    date_locale: DateLocale,
+) -> Result<Option<ArchivedPage>, BoxError> {
+    let Some(rules) = rules_for_url(load_rules(log_config).await?, page_url) else {
+        return Ok(None);
+    };
+
+    let document = parse_html_str(text);
+    if rules.listing_url()?.path() == page_url.path() {
+        let listing = extract_listing(&rules.listing, &document, page_url);
+        let mut links = listing.detail_urls;
+        links.extend(listing.next_page);
+        return Ok(Some(ArchivedPage {
+            parser: rules.parser_name(PARSER_LISTING),
+            parser_version: rules.version,
+            content: Ok(ArchivedContent::Links(links)),
+        }));
+    }
+
+    let content = extract_opportunity(&rules, &document, page_url, date_locale)
+        .map(|opportunity| ArchivedContent::Opportunity(Box::new(opportunity)));
+    Ok(Some(ArchivedPage {
+        parser: rules.parser_name(PARSER_DETAIL),
+        parser_version: rules.version,
+        content,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{rules_for_url, RulesConfig},
+        reqwest::Url,
+    };
+
+    #[test]
+    fn rules_documents() {
+        let config: RulesConfig = serde_json::from_str(
+            r#"{
+                "Locale": {"Dates": "French"},
+                "Portals": [{
+                    "Id": "Olympia",
+                    "ListingUrl": "https://bids.example.gov/open",
+                    "Version": 1,
+                    "Listing": {"Links": "td a"},
+                    "Detail": {"Fields": {"Title": {"Selector": "h1"}}}
+                }],
+                "Tables": [{
+                    "Id": "Thurston",
+                    "Url": "https://example.gov/open-bids.xlsx",
+                    "Format": "Xlsx",
+                    "Version": 1,
+                    "Columns": {"Title": {"Header": "Project"}}
+                }],
+                "RuleKeys": ["rules/tumwater.json"]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.rule_keys, ["rules/tumwater.json"]);
+        assert_eq!(config.tables[0].parser_name(), "Ingest:Thurston:Table");
+
+        let url = Url::parse("https://BIDS.example.gov/bid/1").unwrap();
+        assert_eq!(rules_for_url(config.portals.clone(), &url).map(|rules| rules.id).as_deref(), Some("Olympia"));
+        assert!(rules_for_url(config.portals, &Url::parse("https://example.gov/").unwrap()).is_none());
+
+        let invalid = r#"{"Portals": [{"Id": "X", "ListingUrl": "https://x.gov/", "Version": 1,
+            "Listing": {"Links": "td a["}, "Detail": {"Fields": {}}}]}"#;
+        assert!(serde_json::from_str::<RulesConfig>(invalid).is_err());
+    }
+}
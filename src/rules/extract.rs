@@ -0,0 +1,470 @@
+//! Portal rule sets and the extraction they drive.
+use {
+    super::selector::Selector,
+    crate::{
+        award::page_awards,
+        opportunity::{
+            clean_text, parse_amount, parse_date, parse_date_time, parse_with_structured_data, text_lines, DateLocale,
+            Document, Opportunity,
+        },
+        shapes::SUBSYS_RULES,
+        soup::NodeExt,
+        wa_local::Listing,
+        BoxError,
+    },
+    log::*,
+    markup5ever_rcdom::{Handle, RcDom},
+    reqwest::Url,
+    serde::{Deserialize, Serialize},
+    std::{collections::BTreeMap, rc::Rc},
+};
+
+/// The currency of amounts that don't name one.
+pub(super) const DEFAULT_CURRENCY: &str = "USD";
+
+/// The rules for crawling one portal.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct PortalRules {
+    /// Short identifier for the portal, used in parser names, e.g. `Olympia`.
+    pub id: String,
+
+    /// The organization publishing the solicitations, used when a page doesn't name one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub organization: Option<String>,
+
+    /// The URL of the first page of open solicitations. Requests are matched to their rules by this URL's host.
+    pub listing_url: String,
+
+    /// Version of these rules, recorded as the parser version. Bump it whenever a rule change alters what is extracted.
+    pub version: u32,
+
+    /// How to read the listing.
+    pub listing: ListingRules,
+
+    /// How to read a solicitation's page.
+    pub detail: DetailRules,
+
+    /// Phrases shown in place of a solicitation the portal no longer publishes; see [`closure`][crate::closure].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub not_found_markers: Vec<String>,
+}
+
+/// How to read a portal's listing.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct ListingRules {
+    /// Selects the links to solicitations' pages.
+    pub links: Selector,
+
+    /// Selects the link to the next page of the listing. The first match is followed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_page: Option<Selector>,
+}
+
+/// How to read a solicitation's page.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct DetailRules {
+    /// How to read each field. `Title` is required.
+    pub fields: BTreeMap<Field, FieldRule>,
+
+    /// Selects the links to documents published with the solicitation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub documents: Option<Selector>,
+}
+
+/// A field of the normalized opportunity model that rules can fill in.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) enum Field {
+    /// [`Opportunity::title`]. Required.
+    Title,
+
+    /// [`Opportunity::reference`].
+    Reference,
+
+    /// [`Opportunity::organization`], defaulting to the portal's organization.
+    Organization,
+
+    /// [`Opportunity::description`].
+    Description,
+
+    /// [`Opportunity::published_on`], parsed with [`parse_date`].
+    PublishedOn,
+
+    /// [`Opportunity::due_at`], parsed with [`parse_date_time`].
+    DueAt,
+
+    /// The contact's name.
+    ContactName,
+
+    /// The contact's email address.
+    ContactEmail,
+
+    /// The contact's phone number.
+    ContactPhone,
+
+    /// [`Opportunity::commodity_codes`]. Every value is kept, so this is usually split with [`Transform::Split`].
+    CommodityCodes,
+
+    /// [`Opportunity::estimated_value`], parsed with [`parse_amount`] in US dollars unless the text names a currency.
+    EstimatedValue,
+}
+
+/// How to read a field.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct FieldRule {
+    /// Selects the elements holding the value or, with a label, the elements holding labels.
+    pub selector: Selector,
+
+    /// The label, matched ignoring case and a trailing colon, of a selected element whose next sibling element holds
+    /// the value, e.g. `Due Date` for `<th>Due Date:</th><td>11/30/2022</td>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+
+    /// The attribute holding the value, e.g. `href`, instead of the element's text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attr: Option<String>,
+
+    /// Transforms applied to the value, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transforms: Vec<Transform>,
+}
+
+/// A transformation of a field's value.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all_fields = "PascalCase")]
+pub(crate) enum Transform {
+    /// Replace every occurrence of some text.
+    Replace {
+        /// The text to replace.
+        from: String,
+
+        /// The text to replace it with.
+        to: String,
+    },
+
+    /// Remove a prefix, matched ignoring case, if present.
+    StripPrefix(String),
+
+    /// Remove a suffix, matched ignoring case, if present.
+    StripSuffix(String),
+
+    /// Keep the text after the first occurrence of a separator, or drop the value if there is none.
+    After(String),
+
+    /// Keep the text before the first occurrence of a separator.
+    Before(String),
+
+    /// Keep the first non-blank line.
+    FirstLine,
+
+    /// Convert to lowercase.
+    Lowercase,
+
+    /// Convert to uppercase.
+    Uppercase,
+
+    /// Split into several values at a separator, for list fields such as `CommodityCodes`.
+    Split(String),
+}
+
+impl Transform {
+    /// Apply the transform to a value, returning the values it becomes.
+    pub(super) fn apply(&self, value: String) -> Vec<String> {
+        let transformed = match self {
+            Self::Replace {
+                from,
+                to,
+            } => value.replace(from.as_str(), to),
+            Self::StripPrefix(prefix) => match value.trim_start().get(..prefix.len()) {
+                Some(start) if start.eq_ignore_ascii_case(prefix) => value.trim_start()[prefix.len()..].to_string(),
+                _ => value,
+            },
+            Self::StripSuffix(suffix) => {
+                let trimmed = value.trim_end();
+                match trimmed
+                    .len()
+                    .checked_sub(suffix.len())
+                    .and_then(|start| trimmed.get(start..).map(|end| (start, end)))
+                {
+                    Some((start, end)) if end.eq_ignore_ascii_case(suffix) => trimmed[..start].to_string(),
+                    _ => value,
+                }
+            }
+            Self::After(separator) => match value.split_once(separator.as_str()) {
+                Some((_, after)) => after.to_string(),
+                None => return vec![],
+            },
+            Self::Before(separator) => match value.split_once(separator.as_str()) {
+                Some((before, _)) => before.to_string(),
+                None => value,
+            },
+            Self::FirstLine => value.lines().find(|line| !line.trim().is_empty()).unwrap_or_default().to_string(),
+            Self::Lowercase => value.to_lowercase(),
+            Self::Uppercase => value.to_uppercase(),
+            Self::Split(separator) => return value.split(separator.as_str()).map(str::to_string).collect(),
+        };
+
+        vec![transformed]
+    }
+}
+
+impl FieldRule {
+    /// Return the field's values on a page: the text or attribute of each element holding a value, transformed and
+    /// cleaned with [`clean_text`], omitting blank values.
+    fn values(&self, document: &RcDom) -> Vec<String> {
+        let elements: Vec<Handle> = match &self.label {
+            None => self.selector.select(&document.document),
+            Some(label) => self
+                .selector
+                .select(&document.document)
+                .into_iter()
+                .filter(|element| label_matches(element, label))
+                .filter_map(|element| next_element_sibling(&element))
+                .collect(),
+        };
+
+        let mut values: Vec<String> = elements
+            .iter()
+            .filter_map(|element| match &self.attr {
+                Some(attr) => element.get(attr),
+                None => Some(text_lines(element).join("\n")),
+            })
+            .collect();
+
+        for transform in self.transforms.iter() {
+            values = values.into_iter().flat_map(|value| transform.apply(value)).collect();
+        }
+
+        values.iter().filter_map(|value| clean_text(value)).collect()
+    }
+}
+
+/// Indicates whether an element's text is the given label, ignoring case and a trailing colon.
+fn label_matches(element: &Handle, label: &str) -> bool {
+    clean_text(&element.text()).is_some_and(|text| text.trim_end_matches(':').trim_end().eq_ignore_ascii_case(label))
+}
+
+/// Return the element following an element among its parent's children.
+fn next_element_sibling(element: &Handle) -> Option<Handle> {
+    let parent = element.parent()?;
+    let children = parent.children.borrow();
+    let position = children.iter().position(|child| Rc::ptr_eq(child, element))?;
+    children[position + 1..].iter().find(|child| child.is_element()).cloned()
+}
+
+/// Resolve the link in an element's `href` attribute, logging and ignoring it if it can't be parsed.
+fn element_link(element: &Handle, page_url: &Url) -> Option<Url> {
+    let href = element.get("href")?;
+    match page_url.join(&href) {
+        Ok(url) => Some(url),
+        Err(e) => {
+            warn!("Ignoring unparseable link {href} on {page_url}: {e}");
+            None
+        }
+    }
+}
+
+/// Read a page of a portal's listing.
+pub(crate) fn extract_listing(rules: &ListingRules, document: &RcDom, page_url: &Url) -> Listing {
+    let mut detail_urls: Vec<Url> = Vec::new();
+    for url in rules.links.select(&document.document).iter().filter_map(|a| element_link(a, page_url)) {
+        if !detail_urls.contains(&url) {
+            detail_urls.push(url);
+        }
+    }
+
+    let next_page = rules
+        .next_page
+        .as_ref()
+        .and_then(|selector| selector.first(&document.document))
+        .and_then(|a| element_link(&a, page_url))
+        .filter(|url| url != page_url);
+
+    Listing {
+        detail_urls,
+        next_page,
+    }
+}
+
+/// Read a solicitation's page into the normalized opportunity model from its structured data or, for what that
+/// leaves out, with the portal's rules.
+pub(crate) fn extract_opportunity(
+    rules: &PortalRules,
+    document: &RcDom,
+    page_url: &Url,
+    date_locale: DateLocale,
+) -> Result<Opportunity, BoxError> {
+    let mut opportunity = parse_with_structured_data(document, page_url, date_locale, || {
+        scrape_opportunity(rules, document, page_url, date_locale)
+    })?;
+    opportunity.portal = SUBSYS_RULES.to_string();
+    if opportunity.organization.is_none() {
+        opportunity.organization.clone_from(&rules.organization);
+    }
+
+    Ok(opportunity)
+}
+
+/// Read a solicitation's page with the portal's rules.
+fn scrape_opportunity(
+    rules: &PortalRules,
+    document: &RcDom,
+    page_url: &Url,
+    date_locale: DateLocale,
+) -> Result<Opportunity, BoxError> {
+    let fields = &rules.detail.fields;
+    let values = |field: Field| fields.get(&field).map(|rule| rule.values(document)).unwrap_or_default();
+    let first = |field: Field| values(field).into_iter().next();
+
+    let Some(title_rule) = fields.get(&Field::Title) else {
+        return Err(format!("Rules for {} have no Title field", rules.id).into());
+    };
+    let Some(title) = first(Field::Title) else {
+        return Err(format!("No title matched {} on {page_url}", title_rule.selector).into());
+    };
+
+    let mut opportunity = Opportunity {
+        url: page_url.to_string(),
+        title,
+        reference: first(Field::Reference),
+        organization: first(Field::Organization),
+        description: first(Field::Description),
+        published_on: first(Field::PublishedOn).and_then(|text| parse_date(&text, date_locale)),
+        due_at: first(Field::DueAt).and_then(|text| parse_date_time(&text, date_locale)),
+        estimated_value: first(Field::EstimatedValue).and_then(|text| parse_amount(&text, DEFAULT_CURRENCY)),
+        awards: page_awards(document, date_locale, DEFAULT_CURRENCY),
+        ..Default::default()
+    };
+
+    opportunity.contact.name = first(Field::ContactName);
+    opportunity.contact.email = first(Field::ContactEmail);
+    opportunity.contact.phone = first(Field::ContactPhone);
+
+    for code in values(Field::CommodityCodes) {
+        if !opportunity.commodity_codes.contains(&code) {
+            opportunity.commodity_codes.push(code);
+        }
+    }
+
+    if let Some(selector) = &rules.detail.documents {
+        for a in selector.select(&document.document) {
+            let Some(url) = element_link(&a, page_url) else {
+                continue;
+            };
+
+            if opportunity.documents.iter().any(|document| document.url == url.as_str()) {
+                continue;
+            }
+
+            opportunity.documents.push(Document {
+                name: clean_text(&a.text()).unwrap_or_else(|| url.to_string()),
+                url: url.to_string(),
+            });
+        }
+    }
+
+    Ok(opportunity)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{extract_listing, extract_opportunity, PortalRules, Transform},
+        crate::{
+            opportunity::{Amount, DateLocale},
+            soup::parse_html_str,
+        },
+        chrono::NaiveDate,
+        reqwest::Url,
+    };
+
+    const RULES: &str = r##"{
+        "Id": "Olympia",
+        "Organization": "City of Olympia",
+        "ListingUrl": "https://bids.example.gov/open",
+        "Version": 3,
+        "Listing": {"Links": "table.bids td a", "NextPage": "a[rel=next]"},
+        "Detail": {
+            "Fields": {
+                "Title": {"Selector": "h1"},
+                "Reference": {"Selector": "th", "Label": "Bid Number", "Transforms": [{"StripPrefix": "No. "}]},
+                "DueAt": {"Selector": "th", "Label": "Due"},
+                "ContactEmail": {"Selector": "a.contact", "Attr": "href", "Transforms": [{"StripPrefix": "mailto:"}]},
+                "CommodityCodes": {"Selector": "#codes", "Transforms": [{"Split": ";"}]},
+                "EstimatedValue": {"Selector": "th", "Label": "Estimate"}
+            },
+            "Documents": "ul.docs a"
+        }
+    }"##;
+
+    #[test]
+    fn extraction() {
+        let rules: PortalRules = serde_json::from_str(RULES).unwrap();
+        let page_url = Url::parse("https://bids.example.gov/open").unwrap();
+
+        let listing = extract_listing(
+            &rules.listing,
+            &parse_html_str(
+                r#"<table class="bids"><tr><td><a href="bid/1">Paving</a></td><td><a href="bid/1">More</a></td></tr>
+                   <tr><td><a href="bid/2">Striping</a></td></tr></table><a href="?page=2" rel="next">Next</a>"#,
+            ),
+            &page_url,
+        );
+        assert_eq!(
+            listing.detail_urls.iter().map(Url::as_str).collect::<Vec<_>>(),
+            ["https://bids.example.gov/bid/1", "https://bids.example.gov/bid/2"]
+        );
+        assert_eq!(listing.next_page.unwrap().as_str(), "https://bids.example.gov/open?page=2");
+
+        let page_url = page_url.join("bid/1").unwrap();
+        let document = parse_html_str(
+            r#"<h1> 2022 Street Paving </h1>
+               <table><tr><th>Bid Number:</th><td>No. 22-014</td></tr>
+               <tr><th>Due</th><td>11/30/2022 2:00 PM</td></tr>
+               <tr><th>Estimate</th><td>$1,250,000</td></tr></table>
+               <p id="codes">745-52; 913-43 ;745-52</p>
+               <a class="contact" href="mailto:bids@example.gov">Email</a>
+               <ul class="docs"><li><a href="plans.pdf">Plans</a></li><li><a href="plans.pdf">Plans</a></li></ul>"#,
+        );
+
+        let opportunity = extract_opportunity(&rules, &document, &page_url, DateLocale::default()).unwrap();
+        assert_eq!(opportunity.portal, "Rules");
+        assert_eq!(opportunity.title, "2022 Street Paving");
+        assert_eq!(opportunity.reference.as_deref(), Some("22-014"));
+        assert_eq!(opportunity.organization.as_deref(), Some("City of Olympia"));
+        assert_eq!(opportunity.due_at, NaiveDate::from_ymd_opt(2022, 11, 30).unwrap().and_hms_opt(14, 0, 0));
+        assert_eq!(opportunity.contact.email.as_deref(), Some("bids@example.gov"));
+        assert_eq!(opportunity.commodity_codes, ["745-52", "913-43"]);
+        assert_eq!(
+            opportunity.estimated_value,
+            Some(Amount {
+                minor_units: 125_000_000,
+                currency: "USD".to_string(),
+            })
+        );
+        assert_eq!(opportunity.documents.len(), 1);
+        assert_eq!(opportunity.documents[0].url, "https://bids.example.gov/bid/plans.pdf");
+
+        let error = extract_opportunity(&rules, &parse_html_str("<p>Not here</p>"), &page_url, DateLocale::default());
+        assert!(error.unwrap_err().to_string().contains("h1"));
+    }
+
+    #[test]
+    fn transforms() {
+        let apply = |transform: &str, value: &str| {
+            let transform: Transform = serde_json::from_str(transform).unwrap();
+            transform.apply(value.to_string())
+        };
+
+        assert_eq!(apply(r#"{"Replace": {"From": "-", "To": "/"}}"#, "11-30-2022"), ["11/30/2022"]);
+        assert_eq!(apply(r#"{"StripSuffix": " (PDT)"}"#, "2:00 PM (pdt)"), ["2:00 PM"]);
+        assert_eq!(apply(r##"{"After": "#"}"##, "Bid #22-014"), ["22-014"]);
+        assert!(apply(r##"{"After": "#"}"##, "Bid 22-014").is_empty());
+        assert_eq!(apply(r#"{"Before": " - "}"#, "Paving - Phase 2"), ["Paving"]);
+        assert_eq!(apply(r#""FirstLine""#, "\n Paving\nPhase 2"), [" Paving"]);
+        assert_eq!(apply(r#""Uppercase""#, "rfp"), ["RFP"]);
+    }
+}
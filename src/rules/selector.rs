@@ -0,0 +1,213 @@
+//! A small subset of CSS selectors for extraction rules.
+//!
+//! A selector is a list of steps separated by whitespace, each matching descendants of the elements the step before it
+//! matched. A step is a tag name (or `*`) followed by any number of `.class`, `#id`, `[attr]`, and `[attr=value]`
+//! conditions, e.g. `table.bids a[rel=next]`. Other combinators and pseudo-classes aren't supported. Classes match any
+//! of an element's space-separated classes; tag and attribute names match ignoring case.
+use {
+    crate::{
+        soup::{NodeExt, QueryBuilderExt},
+        BoxError,
+    },
+    markup5ever_rcdom::Handle,
+    serde::{Deserialize, Serialize},
+    std::{
+        fmt::{Display, Formatter, Result as FmtResult},
+        rc::Rc,
+        str::FromStr,
+    },
+};
+
+/// A parsed selector.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct Selector {
+    source: String,
+    steps: Vec<Step>,
+}
+
+/// One step of a selector.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Step {
+    /// The tag name, lowercased, or `None` to match any element.
+    tag: Option<String>,
+
+    /// Classes the element must have.
+    classes: Vec<String>,
+
+    /// Attributes the element must have, lowercased, and the values they must have, if any.
+    attrs: Vec<(String, Option<String>)>,
+}
+
+impl Selector {
+    /// Return the elements within `scope` matching the selector, in document order and without duplicates.
+    pub(crate) fn select(&self, scope: &Handle) -> Vec<Handle> {
+        let mut matched = vec![scope.clone()];
+
+        for step in self.steps.iter() {
+            let mut next: Vec<Handle> = Vec::new();
+            for node in matched.iter() {
+                for element in node.tag(true).find_all().filter(|element| step.matches(element)) {
+                    if !next.iter().any(|seen| Rc::ptr_eq(seen, &element)) {
+                        next.push(element);
+                    }
+                }
+            }
+            matched = next;
+        }
+
+        matched
+    }
+
+    /// Return the first element within `scope` matching the selector.
+    pub(crate) fn first(&self, scope: &Handle) -> Option<Handle> {
+        self.select(scope).into_iter().next()
+    }
+}
+
+impl Step {
+    /// Indicates whether an element matches this step.
+    fn matches(&self, element: &Handle) -> bool {
+        if !element.is_element() {
+            return false;
+        }
+
+        if self.tag.as_ref().is_some_and(|tag| !element.name().eq_ignore_ascii_case(tag)) {
+            return false;
+        }
+
+        let element_classes = element.get("class").unwrap_or_default();
+        if !self.classes.iter().all(|class| element_classes.split_ascii_whitespace().any(|c| c == class)) {
+            return false;
+        }
+
+        self.attrs.iter().all(|(name, value)| match (element.get(name), value) {
+            (Some(actual), Some(expected)) => actual == *expected,
+            (Some(_), None) => true,
+            (None, _) => false,
+        })
+    }
+
+    /// Parse a step.
+    fn parse(source: &str) -> Result<Self, BoxError> {
+        let mut step = Step::default();
+        let mut rest = source;
+
+        let tag_end = rest.find(['.', '#', '[']).unwrap_or(rest.len());
+        match &rest[..tag_end] {
+            "" | "*" => (),
+            tag if tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') => step.tag = Some(tag.to_lowercase()),
+            tag => return Err(format!("Invalid tag name {tag:?} in selector step {source:?}").into()),
+        }
+        rest = &rest[tag_end..];
+
+        while let Some(first) = rest.chars().next() {
+            match first {
+                '.' | '#' => {
+                    let end = rest[1..].find(['.', '#', '[']).map_or(rest.len(), |end| end + 1);
+                    let name = &rest[1..end];
+                    if name.is_empty() {
+                        return Err(format!("Empty class or id in selector step {source:?}").into());
+                    }
+                    if first == '.' {
+                        step.classes.push(name.to_string());
+                    } else {
+                        step.attrs.push(("id".to_string(), Some(name.to_string())));
+                    }
+                    rest = &rest[end..];
+                }
+                '[' => {
+                    let Some(end) = rest.find(']') else {
+                        return Err(format!("Unclosed attribute condition in selector step {source:?}").into());
+                    };
+                    let condition = &rest[1..end];
+                    let (name, value) = match condition.split_once('=') {
+                        Some((name, value)) => (name, Some(value.trim_matches(['"', '\'']).to_string())),
+                        None => (condition, None),
+                    };
+                    if name.is_empty() {
+                        return Err(format!("Empty attribute name in selector step {source:?}").into());
+                    }
+                    step.attrs.push((name.to_lowercase(), value));
+                    rest = &rest[end + 1..];
+                }
+                _ => return Err(format!("Unexpected {first:?} in selector step {source:?}").into()),
+            }
+        }
+
+        Ok(step)
+    }
+}
+
+impl FromStr for Selector {
+    type Err = BoxError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let steps = source.split_whitespace().map(Step::parse).collect::<Result<Vec<_>, _>>()?;
+        if steps.is_empty() {
+            return Err("Empty selector".into());
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            steps,
+        })
+    }
+}
+
+impl TryFrom<String> for Selector {
+    type Error = BoxError;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        source.parse()
+    }
+}
+
+impl From<Selector> for String {
+    fn from(selector: Selector) -> Self {
+        selector.source
+    }
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::Selector,
+        crate::soup::{parse_html_str, NodeExt},
+    };
+
+    #[test]
+    fn selectors() {
+        let document = parse_html_str(
+            r#"<table class="bids wide"><tr><td><a href="/bid/1" rel="bookmark">Bid 1</a></td></tr>
+               <tr><td><a id="b2" href="/bid/2">Bid 2</a></td></tr></table>
+               <p class="bids"><a href="/help">Help</a><A REL="next" href="?page=2">Next</A></p>"#,
+        );
+        let hrefs = |selector: &str| {
+            let selector: Selector = selector.parse().unwrap();
+            selector.select(&document.document).iter().filter_map(|a| a.get("href")).collect::<Vec<_>>()
+        };
+
+        assert_eq!(hrefs("table.bids a"), ["/bid/1", "/bid/2"]);
+        assert_eq!(hrefs(".bids a"), ["/bid/1", "/bid/2", "/help", "?page=2"]);
+        assert_eq!(hrefs("table.wide.bids tr a#b2"), ["/bid/2"]);
+        assert_eq!(hrefs("a[rel]"), ["/bid/1", "?page=2"]);
+        assert_eq!(hrefs("a[rel=\"next\"]"), ["?page=2"]);
+        assert_eq!(hrefs("* [href='/help']"), ["/help"]);
+        assert!(hrefs("table.narrow a").is_empty());
+
+        for invalid in ["", "a[rel", "a..b", "ta>ble", "a[=x]"] {
+            assert!(invalid.parse::<Selector>().is_err(), "{invalid:?} should not parse");
+        }
+
+        let selector: Selector = serde_json::from_str(r#""td a""#).unwrap();
+        assert_eq!(serde_json::to_string(&selector).unwrap(), r#""td a""#);
+        assert!(serde_json::from_str::<Selector>(r#""a[""#).is_err());
+    }
+}
@@ -0,0 +1,282 @@
+//! Column mappings for opportunity lists published as tables, and the normalization they drive.
+use {
+    super::extract::{Field, Transform, DEFAULT_CURRENCY},
+    crate::{
+        ingest::TableFormat,
+        opportunity::{clean_text, parse_amount, parse_date, parse_date_time, DateLocale, Opportunity},
+        shapes::SUBSYS_INGEST,
+        BoxError,
+    },
+    chrono::{NaiveDate, NaiveDateTime, TimeDelta},
+    log::*,
+    reqwest::Url,
+    serde::{Deserialize, Serialize},
+    std::collections::BTreeMap,
+};
+
+/// The largest day number a spreadsheet date can have: December 31, 9999.
+const MAX_SPREADSHEET_SERIAL: f64 = 2_958_466.0;
+
+/// The rules for reading one published table of opportunities.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct TableRules {
+    /// Short identifier for the source, used in parser names, e.g. `Thurston`.
+    pub id: String,
+
+    /// The organization publishing the opportunities, used when a row doesn't name one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub organization: Option<String>,
+
+    /// The URL the table is downloaded from. Requests are matched to their rules by this URL.
+    pub url: String,
+
+    /// The format of the download. When omitted, it is recognized from the body.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<TableFormat>,
+
+    /// The name of the worksheet to read from a workbook. Defaults to the first one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sheet: Option<String>,
+
+    /// Version of these rules, recorded as the parser version. Bump it whenever a rule change alters what is extracted.
+    pub version: u32,
+
+    /// How to read each field. `Title` is required.
+    pub columns: BTreeMap<Field, ColumnRule>,
+
+    /// The column holding the link to each opportunity's page. Without one, opportunities are identified by the table's
+    /// URL with their reference as the fragment, so `Reference` must be mapped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<ColumnRule>,
+}
+
+/// How to read a field from a column.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct ColumnRule {
+    /// The column's header, matched ignoring case and surrounding whitespace.
+    pub header: String,
+
+    /// Transforms applied to the value, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transforms: Vec<Transform>,
+}
+
+impl TableRules {
+    /// Return the URL the table is downloaded from.
+    pub fn table_url(&self) -> Result<Url, BoxError> {
+        Url::parse(&self.url).map_err(|e| format!("Invalid table URL in rules for {}: {e}", self.id).into())
+    }
+
+    /// Return the name of the table's parser, e.g. `Ingest:Thurston:Table`.
+    pub fn parser_name(&self) -> String {
+        format!("{SUBSYS_INGEST}:{}:Table", self.id)
+    }
+
+    /// Return the mapped columns: every field's, then the link's.
+    fn column_rules(&self) -> impl Iterator<Item = &ColumnRule> {
+        self.columns.values().chain(self.link.as_ref())
+    }
+}
+
+impl ColumnRule {
+    /// Return the column's values in a row: the cell transformed and cleaned with [`clean_text`], omitting blank
+    /// values.
+    fn values(&self, row: &[String], column: Option<usize>) -> Vec<String> {
+        let Some(cell) = column.and_then(|column| row.get(column)) else {
+            return vec![];
+        };
+
+        let mut values = vec![cell.clone()];
+        for transform in self.transforms.iter() {
+            values = values.into_iter().flat_map(|value| transform.apply(value)).collect();
+        }
+
+        values.iter().filter_map(|value| clean_text(value)).collect()
+    }
+}
+
+/// Indicates whether a cell holds the given header, ignoring case and surrounding whitespace.
+fn header_matches(cell: &str, header: &str) -> bool {
+    clean_text(cell).is_some_and(|cell| cell.eq_ignore_ascii_case(header.trim()))
+}
+
+/// Parse a day number, counted from December 30, 1899 with the time of day as its fraction, which spreadsheets store
+/// dates as.
+fn parse_spreadsheet_serial(text: &str) -> Option<NaiveDateTime> {
+    let serial: f64 = text.trim().parse().ok()?;
+    if !(1.0..MAX_SPREADSHEET_SERIAL).contains(&serial) {
+        return None;
+    }
+
+    let epoch = NaiveDate::from_ymd_opt(1899, 12, 30)?.and_hms_opt(0, 0, 0)?;
+    epoch.checked_add_signed(TimeDelta::seconds((serial * 86_400.0).round() as i64))
+}
+
+/// Read the rows of a table into the normalized opportunity model with its rules.
+///
+/// The header row is the first row holding every mapped column's header; rows above it, such as a title, are skipped.
+/// Rows without a title are skipped silently, and rows that can't be identified are skipped with a warning.
+pub(crate) fn extract_table(
+    rules: &TableRules,
+    rows: &[Vec<String>],
+    table_url: &Url,
+    date_locale: DateLocale,
+) -> Result<Vec<Opportunity>, BoxError> {
+    if !rules.columns.contains_key(&Field::Title) {
+        return Err(format!("Rules for {} have no Title column", rules.id).into());
+    }
+
+    if rules.link.is_none() && !rules.columns.contains_key(&Field::Reference) {
+        return Err(format!("Rules for {} have neither a Link nor a Reference column", rules.id).into());
+    }
+
+    let Some(header_index) = rows
+        .iter()
+        .position(|row| rules.column_rules().all(|rule| row.iter().any(|cell| header_matches(cell, &rule.header))))
+    else {
+        return Err(format!("No row of the {} table holds every mapped header", rules.id).into());
+    };
+
+    let header = &rows[header_index];
+    let column = |rule: &ColumnRule| header.iter().position(|cell| header_matches(cell, &rule.header));
+    let mut opportunities = vec![];
+
+    for (index, row) in rows.iter().enumerate().skip(header_index + 1) {
+        let values =
+            |field: Field| rules.columns.get(&field).map(|rule| rule.values(row, column(rule))).unwrap_or_default();
+        let first = |field: Field| values(field).into_iter().next();
+
+        let Some(title) = first(Field::Title) else {
+            continue;
+        };
+
+        let reference = first(Field::Reference);
+        let link = rules.link.as_ref().and_then(|rule| rule.values(row, column(rule)).into_iter().next());
+        let url = match (link, &reference) {
+            (Some(link), _) => match table_url.join(&link) {
+                Ok(url) => url,
+                Err(e) => {
+                    warn!("Skipping row {} of the {} table with unparseable link {link}: {e}", index + 1, rules.id);
+                    continue;
+                }
+            },
+            (None, Some(reference)) => {
+                let mut url = table_url.clone();
+                url.set_fragment(Some(reference));
+                url
+            }
+            (None, None) => {
+                warn!("Skipping row {} of the {} table, which has neither a link nor a reference", index + 1, rules.id);
+                continue;
+            }
+        };
+
+        let mut opportunity = Opportunity {
+            portal: SUBSYS_INGEST.to_string(),
+            url: url.to_string(),
+            title,
+            reference,
+            organization: first(Field::Organization).or_else(|| rules.organization.clone()),
+            description: first(Field::Description),
+            published_on: first(Field::PublishedOn).and_then(|text| {
+                parse_date(&text, date_locale).or_else(|| parse_spreadsheet_serial(&text).map(|at| at.date()))
+            }),
+            due_at: first(Field::DueAt)
+                .and_then(|text| parse_date_time(&text, date_locale).or_else(|| parse_spreadsheet_serial(&text))),
+            estimated_value: first(Field::EstimatedValue).and_then(|text| parse_amount(&text, DEFAULT_CURRENCY)),
+            ..Default::default()
+        };
+
+        opportunity.contact.name = first(Field::ContactName);
+        opportunity.contact.email = first(Field::ContactEmail);
+        opportunity.contact.phone = first(Field::ContactPhone);
+
+        for code in values(Field::CommodityCodes) {
+            if !opportunity.commodity_codes.contains(&code) {
+                opportunity.commodity_codes.push(code);
+            }
+        }
+
+        opportunities.push(opportunity);
+    }
+
+    Ok(opportunities)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{extract_table, TableRules},
+        crate::opportunity::{Amount, DateLocale},
+        chrono::NaiveDate,
+        reqwest::Url,
+    };
+
+    const RULES: &str = r#"{
+        "Id": "Thurston",
+        "Organization": "Thurston County",
+        "Url": "https://example.gov/bids.csv",
+        "Version": 1,
+        "Columns": {
+            "Title": {"Header": "Project"},
+            "Reference": {"Header": "Bid No.", "Transforms": [{"StripPrefix": "No. "}]},
+            "DueAt": {"Header": "Closes"},
+            "PublishedOn": {"Header": "Posted"},
+            "CommodityCodes": {"Header": "Codes", "Transforms": [{"Split": ";"}]},
+            "EstimatedValue": {"Header": "Estimate"}
+        }
+    }"#;
+
+    fn rows(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter().map(|row| row.iter().map(|cell| cell.to_string()).collect()).collect()
+    }
+
+    #[test]
+    fn normalization() {
+        let rules: TableRules = serde_json::from_str(RULES).unwrap();
+        let table_url = Url::parse(&rules.url).unwrap();
+        let table = rows(&[
+            &["Open Bids as of 11/1/2022"],
+            &["Bid No.", " PROJECT ", "Posted", "Closes", "Codes", "Estimate"],
+            &["No. 22-014", "Street  Paving", "10/31/2022", "11/30/2022 2:00 PM", "745-52;913-43;745-52", "$1,250,000"],
+            &["", "", "", "", "", ""],
+            &["22-015", "Striping", "44865", "44895.5833333333", "", ""],
+            &["", "Untracked", "", "", "", ""],
+        ]);
+
+        let opportunities = extract_table(&rules, &table, &table_url, DateLocale::default()).unwrap();
+        assert_eq!(opportunities.len(), 2);
+
+        let paving = &opportunities[0];
+        assert_eq!(paving.portal, "Ingest");
+        assert_eq!(paving.url, "https://example.gov/bids.csv#22-014");
+        assert_eq!(paving.title, "Street Paving");
+        assert_eq!(paving.organization.as_deref(), Some("Thurston County"));
+        assert_eq!(paving.published_on, NaiveDate::from_ymd_opt(2022, 10, 31));
+        assert_eq!(paving.due_at, NaiveDate::from_ymd_opt(2022, 11, 30).unwrap().and_hms_opt(14, 0, 0));
+        assert_eq!(paving.commodity_codes, ["745-52", "913-43"]);
+        assert_eq!(
+            paving.estimated_value,
+            Some(Amount {
+                minor_units: 125_000_000,
+                currency: "USD".to_string(),
+            })
+        );
+
+        let striping = &opportunities[1];
+        assert_eq!(striping.published_on, NaiveDate::from_ymd_opt(2022, 10, 31));
+        assert_eq!(striping.due_at, NaiveDate::from_ymd_opt(2022, 11, 30).unwrap().and_hms_opt(14, 0, 0));
+
+        let linked: TableRules = serde_json::from_str(
+            r#"{"Id": "Lacey", "Url": "https://example.gov/bids.csv", "Version": 1,
+                "Columns": {"Title": {"Header": "Project"}}, "Link": {"Header": "Link"}}"#,
+        )
+        .unwrap();
+        let table = rows(&[&["Project", "Link"], &["Paving", "bid/14"]]);
+        let opportunities = extract_table(&linked, &table, &table_url, DateLocale::default()).unwrap();
+        assert_eq!(opportunities[0].url, "https://example.gov/bid/14");
+        assert!(extract_table(&linked, &rows(&[&["Project", "Bid No."]]), &table_url, DateLocale::default()).is_err());
+    }
+}
@@ -0,0 +1,485 @@
+//! Adaptive crawl scheduling.
+//!
+//! Each portal (subsystem) has a schedule item in the schedule table (`Schedule#{portal}` / `Policy`) recording how
+//! often it is crawled, when its next crawl is due, and a tally of the crawls started and of the new or changed
+//! opportunities they found since the schedule was last tuned.
+//!
+//! * The frequently scheduled `Schedule:Dispatch` operation starts a crawl of each portal whose next crawl is due.
+//!   Portals without a schedule item start out at their [coverage cadence][crate::coverage::Cadence].
+//!   Portals that have spent their [monthly budget][crate::cost] are skipped until the next month.
+//! * The daily `Schedule:Tune` operation adjusts each portal's interval to its change rate. Portals averaging at least
+//!   [`BUSY_CHANGES_PER_CRAWL`] new or changed opportunities a crawl are crawled twice as often, down to
+//!   [`MIN_INTERVAL`]; portals averaging fewer than [`QUIET_CHANGES_PER_CRAWL`] are crawled half as often, up to
+//!   [`MAX_INTERVAL`].
+use {
+    crate::{
+        cost, coverage,
+        httpext::{aws_err_str, LogConfig},
+        shapes::{CrawlParameters, NextRequest, Operation, Request, Response},
+        BoxError,
+    },
+    aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoDbClient},
+    chrono::{DateTime, Duration, Utc},
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+const OP_DISPATCH: &str = "Dispatch";
+const OP_TUNE: &str = "Tune";
+
+/// The operation each subsystem starts its crawls with.
+const OP_START_CRAWL: &str = "StartCrawl";
+
+/// The shortest interval between crawls of a portal.
+pub const MIN_INTERVAL: Duration = Duration::hours(1);
+
+/// The longest interval between crawls of a portal.
+pub const MAX_INTERVAL: Duration = Duration::days(7);
+
+/// Portals averaging at least this many new or changed opportunities a crawl are crawled more often.
+pub const BUSY_CHANGES_PER_CRAWL: f64 = 10.0;
+
+/// Portals averaging fewer than this many new or changed opportunities a crawl are crawled less often.
+pub const QUIET_CHANGES_PER_CRAWL: f64 = 1.0;
+
+const DDB_KEY_CRAWL_ID: &str = "CrawlId";
+const DDB_KEY_REQUEST_ID: &str = "RequestId";
+const DDB_KEY_INTERVAL_SECS: &str = "IntervalSecs";
+const DDB_KEY_NEXT_CRAWL_AT: &str = "NextCrawlAt";
+const DDB_KEY_CRAWLS: &str = "Crawls";
+const DDB_KEY_CHANGES: &str = "Changes";
+const DDB_KEY_TUNED_AT: &str = "TunedAt";
+const DDB_KEY_COST_MICROS: &str = "CostMicros";
+
+/// Partition key prefix for schedule items; the portal follows it.
+const SCHEDULE_PARTITION_PREFIX: &str = "Schedule#";
+
+/// Sort key of a portal's schedule within its partition.
+const SCHEDULE_SORT_KEY: &str = "Policy";
+
+/// Sort key prefix of a portal's monthly spend within its partition; the month (e.g. `2024-05`) follows it.
+const SPEND_SORT_PREFIX: &str = "Spend#";
+
+/// Possible scheduling operations.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum ScheduleOperation {
+    /// Start crawls of the portals that are due.
+    Dispatch,
+
+    /// Adjust how often each portal is crawled to how much it changes.
+    Tune,
+}
+
+impl FromStr for ScheduleOperation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            OP_DISPATCH => Ok(Self::Dispatch),
+            OP_TUNE => Ok(Self::Tune),
+            _ => Err(format!("Unknown operation: {value}")),
+        }
+    }
+}
+
+impl Display for ScheduleOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.operation())
+    }
+}
+
+impl ScheduleOperation {
+    /// Handle a request.
+    pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+        match self {
+            Self::Dispatch => dispatch(log_config, req, context).await,
+            Self::Tune => tune(log_config, req, context).await,
+        }
+    }
+
+    /// Return the operation name within the subsystem.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::Dispatch => OP_DISPATCH,
+            Self::Tune => OP_TUNE,
+        }
+    }
+}
+
+/// A portal's crawl schedule.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PortalSchedule {
+    /// The portal (subsystem) crawled.
+    pub portal: String,
+
+    /// How long to wait between the starts of crawls.
+    pub interval: Duration,
+
+    /// When the next crawl is due.
+    pub next_crawl_at: DateTime<Utc>,
+
+    /// The crawls started since the schedule was last tuned.
+    pub crawls: u64,
+
+    /// The new or changed opportunities found since the schedule was last tuned.
+    pub changes: u64,
+}
+
+/// Storage for crawl schedules in DynamoDB.
+#[derive(Clone, Debug)]
+pub struct ScheduleStore {
+    ddb_client: DynamoDbClient,
+    table_name: String,
+}
+
+impl ScheduleStore {
+    /// Create a schedule store using the given table.
+    pub fn new<S: Into<String>>(ddb_client: DynamoDbClient, table_name: S) -> Self {
+        Self {
+            ddb_client,
+            table_name: table_name.into(),
+        }
+    }
+
+    /// Return the name of the table schedules are stored in.
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    /// Return a portal's schedule, or `None` if it has never been dispatched.
+    pub async fn load(&self, portal: &str) -> Result<Option<PortalSchedule>, BoxError> {
+        let result = self
+            .ddb_client
+            .get_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(schedule_partition_key(portal)))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(SCHEDULE_SORT_KEY.to_string()))
+            .consistent_read(true)
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => Ok(output.item.as_ref().and_then(|item| parse_schedule(portal, item))),
+            Err(e) => {
+                error!("GetItem {portal} schedule: {}", aws_err_str(&e));
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Add new or changed opportunities found on a portal to its tally.
+    pub async fn record_changes(&self, portal: &str, changes: u64) -> Result<(), BoxError> {
+        let result = self
+            .ddb_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(schedule_partition_key(portal)))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(SCHEDULE_SORT_KEY.to_string()))
+            .update_expression("ADD #changes :changes")
+            .expression_attribute_names("#changes", DDB_KEY_CHANGES)
+            .expression_attribute_values(":changes", AttributeValue::N(changes.to_string()))
+            .send()
+            .await;
+
+        if let Err(e) = result {
+            error!("UpdateItem {portal} schedule changes: {}", aws_err_str(&e));
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    /// Add to a portal's spend for the month, in millionths of a US dollar.
+    pub async fn add_spend(&self, portal: &str, month: &str, cost_micros: u64) -> Result<(), BoxError> {
+        let result = self
+            .ddb_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(schedule_partition_key(portal)))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(format!("{SPEND_SORT_PREFIX}{month}")))
+            .update_expression("ADD #cost :cost")
+            .expression_attribute_names("#cost", DDB_KEY_COST_MICROS)
+            .expression_attribute_values(":cost", AttributeValue::N(cost_micros.to_string()))
+            .send()
+            .await;
+
+        if let Err(e) = result {
+            error!("UpdateItem {portal} spend for {month}: {}", aws_err_str(&e));
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    /// Return a portal's spend for the month, in millionths of a US dollar.
+    pub async fn spend(&self, portal: &str, month: &str) -> Result<u64, BoxError> {
+        let result = self
+            .ddb_client
+            .get_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(schedule_partition_key(portal)))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(format!("{SPEND_SORT_PREFIX}{month}")))
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => Ok(output
+                .item
+                .as_ref()
+                .and_then(|item| item.get(DDB_KEY_COST_MICROS)?.as_n().ok()?.parse().ok())
+                .unwrap_or(0)),
+            Err(e) => {
+                error!("GetItem {portal} spend for {month}: {}", aws_err_str(&e));
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Record that a crawl of the portal was started at `now`, scheduling the next one after `interval`.
+    pub async fn record_dispatch(&self, portal: &str, interval: Duration, now: DateTime<Utc>) -> Result<(), BoxError> {
+        let result = self
+            .ddb_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(schedule_partition_key(portal)))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(SCHEDULE_SORT_KEY.to_string()))
+            .update_expression("SET #interval = :interval, #next_crawl_at = :next_crawl_at ADD #crawls :one")
+            .expression_attribute_names("#interval", DDB_KEY_INTERVAL_SECS)
+            .expression_attribute_names("#next_crawl_at", DDB_KEY_NEXT_CRAWL_AT)
+            .expression_attribute_names("#crawls", DDB_KEY_CRAWLS)
+            .expression_attribute_values(":interval", AttributeValue::N(interval.num_seconds().to_string()))
+            .expression_attribute_values(":next_crawl_at", AttributeValue::N((now + interval).timestamp().to_string()))
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .send()
+            .await;
+
+        if let Err(e) = result {
+            error!("UpdateItem {portal} schedule dispatch: {}", aws_err_str(&e));
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    /// Set a portal's interval, taking the crawls and changes it was tuned from off its tally.
+    ///
+    /// The next crawl is brought forward if it is due later than a crawl started now would be.
+    pub async fn record_tuning(
+        &self,
+        schedule: &PortalSchedule,
+        interval: Duration,
+        now: DateTime<Utc>,
+    ) -> Result<(), BoxError> {
+        let next_crawl_at = schedule.next_crawl_at.min(now + interval);
+        let result = self
+            .ddb_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, AttributeValue::S(schedule_partition_key(&schedule.portal)))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(SCHEDULE_SORT_KEY.to_string()))
+            .update_expression(
+                "SET #interval = :interval, #next_crawl_at = :next_crawl_at, #tuned_at = :now \
+                 ADD #crawls :crawls, #changes :changes",
+            )
+            .expression_attribute_names("#interval", DDB_KEY_INTERVAL_SECS)
+            .expression_attribute_names("#next_crawl_at", DDB_KEY_NEXT_CRAWL_AT)
+            .expression_attribute_names("#tuned_at", DDB_KEY_TUNED_AT)
+            .expression_attribute_names("#crawls", DDB_KEY_CRAWLS)
+            .expression_attribute_names("#changes", DDB_KEY_CHANGES)
+            .expression_attribute_values(":interval", AttributeValue::N(interval.num_seconds().to_string()))
+            .expression_attribute_values(":next_crawl_at", AttributeValue::N(next_crawl_at.timestamp().to_string()))
+            .expression_attribute_values(":now", AttributeValue::S(now.to_rfc3339()))
+            // Counters are decremented rather than reset, so sightings recorded while tuning aren't lost.
+            .expression_attribute_values(":crawls", AttributeValue::N(format!("-{}", schedule.crawls)))
+            .expression_attribute_values(":changes", AttributeValue::N(format!("-{}", schedule.changes)))
+            .send()
+            .await;
+
+        if let Err(e) = result {
+            error!("UpdateItem {} schedule tuning: {}", schedule.portal, aws_err_str(&e));
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Return the interval a portal should be crawled at, given its current interval and the crawls and changes since it
+/// was last tuned, or `None` to leave it alone.
+pub fn tuned_interval(interval: Duration, crawls: u64, changes: u64) -> Option<Duration> {
+    if crawls == 0 {
+        return None;
+    }
+
+    let rate = changes as f64 / crawls as f64;
+    let tuned = if rate >= BUSY_CHANGES_PER_CRAWL {
+        (interval / 2).max(MIN_INTERVAL)
+    } else if rate < QUIET_CHANGES_PER_CRAWL {
+        (interval * 2).min(MAX_INTERVAL)
+    } else {
+        interval
+    };
+
+    (tuned != interval).then_some(tuned)
+}
+
+/// Return each crawled subsystem with the interval it starts out at, in the order of the coverage registry.
+fn portals() -> Vec<(String, Duration)> {
+    let mut portals: Vec<(String, Duration)> = Vec::new();
+    for portal in coverage::registry() {
+        if !portals.iter().any(|(subsystem, _)| *subsystem == portal.subsystem) {
+            portals.push((portal.subsystem, portal.cadence.interval()));
+        }
+    }
+
+    portals
+}
+
+/// Start a crawl of each portal whose next crawl is due.
+async fn dispatch(log_config: LogConfig, req: Request, _context: Context) -> Result<Response, LambdaError> {
+    let now = Utc::now();
+    let month = cost::spend_month(now);
+    let mut next_requests = Vec::new();
+
+    for (portal, default_interval) in portals() {
+        let schedule = log_config.schedules.load(&portal).await?;
+        if let Some(schedule) = schedule.as_ref() {
+            if schedule.next_crawl_at > now {
+                debug!("{portal} crawl is not due until {}", schedule.next_crawl_at);
+                continue;
+            }
+        }
+
+        if let Some(budget) = log_config.monthly_budgets.get(&portal) {
+            let spend = log_config.schedules.spend(&portal, &month).await? as f64 / 1_000_000.0;
+            if spend >= *budget {
+                warn!("Not crawling {portal}: spent ${spend:.2} of its ${budget:.2} budget for {month}");
+                continue;
+            }
+        }
+
+        let interval = schedule.map_or(default_interval, |schedule| schedule.interval);
+        log_config.schedules.record_dispatch(&portal, interval, now).await?;
+        info!("Starting {portal} crawl; next crawl in {} hour(s)", interval.num_hours());
+
+        // Each crawl registers its own crawl id when it starts.
+        next_requests.push(NextRequest {
+            operation: Operation::from_str(&format!("{portal}:{OP_START_CRAWL}"))?,
+            url: None,
+            link_text: None,
+            crawl: CrawlParameters {
+                user_agent: req.crawl.user_agent.clone(),
+                ..CrawlParameters::default()
+            },
+            delay_seconds: 0,
+        });
+    }
+
+    Ok(Response {
+        next_requests,
+    })
+}
+
+/// Adjust each portal's interval to how much it changed since it was last tuned.
+async fn tune(log_config: LogConfig, _req: Request, _context: Context) -> Result<Response, LambdaError> {
+    let now = Utc::now();
+
+    for (portal, _) in portals() {
+        let Some(schedule) = log_config.schedules.load(&portal).await? else {
+            continue;
+        };
+
+        let interval =
+            tuned_interval(schedule.interval, schedule.crawls, schedule.changes).unwrap_or(schedule.interval);
+        info!(
+            "{portal}: {} change(s) in {} crawl(s); crawling every {} hour(s), was {}",
+            schedule.changes,
+            schedule.crawls,
+            interval.num_hours(),
+            schedule.interval.num_hours()
+        );
+        log_config.schedules.record_tuning(&schedule, interval, now).await?;
+    }
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Return the partition key of a portal's schedule.
+fn schedule_partition_key(portal: &str) -> String {
+    format!("{SCHEDULE_PARTITION_PREFIX}{portal}")
+}
+
+/// Parse a schedule item. Items holding only changes recorded before the portal's first dispatch are skipped.
+fn parse_schedule(portal: &str, item: &HashMap<String, AttributeValue>) -> Option<PortalSchedule> {
+    let number = |name: &str| item.get(name).and_then(|value| value.as_n().ok());
+    let count = |name: &str| number(name).and_then(|value| value.parse().ok()).unwrap_or(0);
+
+    Some(PortalSchedule {
+        portal: portal.to_string(),
+        interval: Duration::seconds(number(DDB_KEY_INTERVAL_SECS)?.parse().ok()?),
+        next_crawl_at: DateTime::from_timestamp(number(DDB_KEY_NEXT_CRAWL_AT)?.parse().ok()?, 0)?,
+        crawls: count(DDB_KEY_CRAWLS),
+        changes: count(DDB_KEY_CHANGES),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{parse_schedule, portals, tuned_interval, MAX_INTERVAL, MIN_INTERVAL},
+        crate::shapes::{SUBSYS_WA_LOCAL, SUBSYS_WEBS},
+        aws_sdk_dynamodb::types::AttributeValue,
+        chrono::{Duration, TimeZone, Utc},
+        std::collections::HashMap,
+    };
+
+    #[test]
+    fn tuning() {
+        let day = Duration::days(1);
+
+        // Busy portals are crawled more often, quiet ones less, and in-between ones are left alone.
+        assert_eq!(tuned_interval(day, 4, 80), Some(Duration::hours(12)));
+        assert_eq!(tuned_interval(day, 4, 2), Some(Duration::days(2)));
+        assert_eq!(tuned_interval(day, 4, 12), None);
+
+        // Intervals stay within bounds.
+        assert_eq!(tuned_interval(MIN_INTERVAL, 24, 1000), None);
+        assert_eq!(tuned_interval(Duration::minutes(90), 24, 1000), Some(MIN_INTERVAL));
+        assert_eq!(tuned_interval(MAX_INTERVAL, 1, 0), None);
+        assert_eq!(tuned_interval(Duration::days(5), 1, 0), Some(MAX_INTERVAL));
+
+        // Without a crawl there is nothing to go on.
+        assert_eq!(tuned_interval(day, 0, 50), None);
+    }
+
+    #[test]
+    fn schedules() {
+        let portals = portals();
+        assert_eq!(portals.iter().filter(|(portal, _)| portal == SUBSYS_WA_LOCAL).count(), 1);
+        assert!(portals.contains(&(SUBSYS_WEBS.to_string(), Duration::hours(1))));
+
+        let mut item = HashMap::from([
+            ("CrawlId".to_string(), AttributeValue::S("Schedule#Webs".to_string())),
+            ("RequestId".to_string(), AttributeValue::S("Policy".to_string())),
+            ("IntervalSecs".to_string(), AttributeValue::N("7200".to_string())),
+            ("NextCrawlAt".to_string(), AttributeValue::N("1700000000".to_string())),
+            ("Changes".to_string(), AttributeValue::N("17".to_string())),
+        ]);
+        let schedule = parse_schedule("Webs", &item).unwrap();
+        assert_eq!(schedule.interval, Duration::hours(2));
+        assert_eq!(schedule.next_crawl_at, Utc.timestamp_opt(1_700_000_000, 0).unwrap());
+        assert_eq!((schedule.crawls, schedule.changes), (0, 17));
+
+        // Changes recorded before the first dispatch don't make a schedule.
+        item.remove("IntervalSecs");
+        assert_eq!(parse_schedule("Webs", &item), None);
+    }
+}
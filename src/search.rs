@@ -0,0 +1,420 @@
+//! Keyword search over opportunities in OpenSearch.
+//!
+//! When `OPENSEARCH_ENDPOINT` is set, every opportunity a crawl [records as seen][crate::closure::record_seen] is
+//! written to a search document in the `OPENSEARCH_INDEX` index (`opportunities` by default), keyed by the SHA-256
+//! digest of its URL. As each attachment of the opportunity is [linked][crate::attachment::record_attachment], the text
+//! [extracted][crate::extract] from it is appended to the document's `Attachments`, replacing the text of an earlier
+//! version of the same document, so keyword matching covers the solicitation documents and not just the listing.
+//!
+//! A match in the listing counts for more than one in a long attachment: the `opportunity-keywords` search template
+//! matches keywords across the [boosted fields][crate::search::BOOSTED_FIELDS], which weigh the title and reference
+//! highest. `Admin:SetUpSearch` creates the index with its mappings and stores the template; consumers search with
+//!
+//! ```json
+//! GET /opportunities/_search/template
+//! { "id": "opportunity-keywords", "params": { "keywords": "asphalt paving" } }
+//! ```
+//!
+//! Requests are signed with the Lambda's own credentials for Amazon OpenSearch Service. Indexing is best-effort:
+//! failures are logged rather than failing the crawl.
+use {
+    crate::{
+        httpext::{ConfigError, LogConfig},
+        opportunity::Opportunity,
+        BoxError,
+    },
+    aws_config::SdkConfig,
+    log::*,
+    opensearch::{
+        auth::Credentials,
+        http::{
+            transport::{SingleNodeConnectionPool, TransportBuilder},
+            StatusCode, Url,
+        },
+        indices::{IndicesCreateParts, IndicesExistsParts},
+        OpenSearch, PutScriptParts, UpdateParts,
+    },
+    serde::Serialize,
+    serde_json::{json, Value},
+    sha2::{Digest, Sha256},
+    std::time::Duration,
+};
+
+pub(crate) const ENV_OPENSEARCH_ENDPOINT: &str = "OPENSEARCH_ENDPOINT";
+const ENV_OPENSEARCH_INDEX: &str = "OPENSEARCH_INDEX";
+const DEFAULT_INDEX: &str = "opportunities";
+
+/// The id of the stored search template for keyword matching.
+pub const KEYWORD_TEMPLATE: &str = "opportunity-keywords";
+
+/// The fields keywords are matched against, with their boosts. Attachment text is long and repetitive, so a match
+/// there counts for the least.
+pub const BOOSTED_FIELDS: &[&str] =
+    &["Title^4", "Reference^4", "Organization^2", "Description^2", "CommodityCodes^2", "Attachments.Text"];
+
+/// The most text kept from a single attachment, in bytes. Past this, the rest of a long document adds little to
+/// matching and only grows the index.
+const MAX_ATTACHMENT_TEXT: usize = 1 << 20;
+
+/// Times an update is retried when another writer changed the document first, as happens when several attachments of
+/// an opportunity are linked at once.
+const RETRY_ON_CONFLICT: i64 = 3;
+
+/// How long to wait for OpenSearch to answer a request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where search documents are written, from `OPENSEARCH_ENDPOINT` and `OPENSEARCH_INDEX`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SearchSettings {
+    /// The URL of the OpenSearch domain.
+    pub endpoint: Url,
+
+    /// The index search documents are written to.
+    pub index: String,
+}
+
+impl SearchSettings {
+    /// Read the settings, looking variables up with `lookup`. Returns `None` if no endpoint is set, disabling search.
+    /// Invalid values are recorded in `errors`.
+    pub(crate) fn from_lookup<F: Fn(&str) -> Option<String>>(lookup: &F, errors: &mut ConfigError) -> Option<Self> {
+        let endpoint = lookup(ENV_OPENSEARCH_ENDPOINT).filter(|endpoint| !endpoint.is_empty());
+        let endpoint = endpoint.and_then(|endpoint| match Url::parse(&endpoint) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") && url.host().is_some() => Some(url),
+            _ => {
+                errors.push(ENV_OPENSEARCH_ENDPOINT, format!("{endpoint:?} is not an HTTP(S) URL"));
+                None
+            }
+        });
+
+        let index = lookup(ENV_OPENSEARCH_INDEX).unwrap_or_else(|| DEFAULT_INDEX.to_string());
+        let valid = !index.is_empty()
+            && !index.starts_with(['-', '_', '+'])
+            && index.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "-_.".contains(c));
+        if !valid {
+            errors.push(ENV_OPENSEARCH_INDEX, format!("{index:?} is not an index name"));
+            return None;
+        }
+
+        Some(Self {
+            endpoint: endpoint?,
+            index,
+        })
+    }
+}
+
+/// The searchable fields of an opportunity. Attachment text is kept under `Attachments`, which only
+/// [`SearchSink::add_attachment_text`] writes.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SearchDocument<'a> {
+    portal: &'a str,
+    url: &'a str,
+    title: &'a str,
+    reference: Option<&'a str>,
+    organization: Option<&'a str>,
+    description: Option<&'a str>,
+    commodity_codes: &'a [String],
+    counties: &'a [String],
+}
+
+impl<'a> From<&'a Opportunity> for SearchDocument<'a> {
+    fn from(opportunity: &'a Opportunity) -> Self {
+        Self {
+            portal: &opportunity.portal,
+            url: &opportunity.url,
+            title: &opportunity.title,
+            reference: opportunity.reference.as_deref(),
+            organization: opportunity.organization.as_deref(),
+            description: opportunity.description.as_deref(),
+            commodity_codes: &opportunity.commodity_codes,
+            counties: &opportunity.counties,
+        }
+    }
+}
+
+/// Writes opportunities and the text of their attachments to an OpenSearch index.
+#[derive(Clone, Debug)]
+pub struct SearchSink {
+    client: OpenSearch,
+    index: String,
+}
+
+impl SearchSink {
+    /// Create a sink writing to `index` with the given client.
+    pub fn new<S: Into<String>>(client: OpenSearch, index: S) -> Self {
+        Self {
+            client,
+            index: index.into(),
+        }
+    }
+
+    /// Create a sink for the OpenSearch Service domain in `settings`, signing requests with the credentials of
+    /// `sdk_config`.
+    pub fn connect(settings: &SearchSettings, sdk_config: &SdkConfig) -> Result<Self, BoxError> {
+        let transport = TransportBuilder::new(SingleNodeConnectionPool::new(settings.endpoint.clone()))
+            .auth(Credentials::try_from(sdk_config)?)
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+
+        Ok(Self::new(OpenSearch::new(transport), settings.index.clone()))
+    }
+
+    /// Return the name of the index documents are written to.
+    pub fn index(&self) -> &str {
+        &self.index
+    }
+
+    /// Write the searchable fields of an opportunity to its document, keeping the text of its attachments.
+    pub async fn index_opportunity(&self, opportunity: &Opportunity) -> Result<(), BoxError> {
+        let body = json!({
+            "doc": SearchDocument::from(opportunity),
+            "doc_as_upsert": true,
+        });
+        self.update(&opportunity.url, body).await
+    }
+
+    /// Add the text of a document of an opportunity to the opportunity's search document, replacing any text kept for
+    /// the same document. The text is cut to 1 MiB.
+    pub async fn add_attachment_text(
+        &self,
+        opportunity_url: &str,
+        document_url: &str,
+        text: &str,
+    ) -> Result<(), BoxError> {
+        let attachment = json!({
+            "DocumentUrl": document_url,
+            "Text": truncate(text, MAX_ATTACHMENT_TEXT),
+        });
+        let body = json!({
+            "script": {
+                "lang": "painless",
+                "source": "if (ctx._source.Attachments == null) { ctx._source.Attachments = []; } \
+                           if (ctx._source.Attachments.contains(params.attachment)) { ctx.op = 'none'; } else { \
+                           ctx._source.Attachments.removeIf(a -> a.DocumentUrl == params.attachment.DocumentUrl); \
+                           ctx._source.Attachments.add(params.attachment); }",
+                "params": { "attachment": attachment },
+            },
+            "upsert": {
+                "Url": opportunity_url,
+                "Attachments": [attachment],
+            },
+        });
+        self.update(opportunity_url, body).await
+    }
+
+    /// Create the index with its mappings if it doesn't exist, and store the [`KEYWORD_TEMPLATE`] search template.
+    pub async fn set_up(&self) -> Result<(), BoxError> {
+        let response = self.client.indices().exists(IndicesExistsParts::Index(&[&self.index])).send().await?;
+        if response.status_code() == StatusCode::NOT_FOUND {
+            info!("Creating search index {}", self.index);
+            self.client
+                .indices()
+                .create(IndicesCreateParts::Index(&self.index))
+                .body(json!({ "mappings": mappings() }))
+                .send()
+                .await?
+                .error_for_status_code()?;
+        } else {
+            response.error_for_status_code()?;
+        }
+
+        self.client
+            .put_script(PutScriptParts::Id(KEYWORD_TEMPLATE))
+            .body(json!({
+                "script": {
+                    "lang": "mustache",
+                    "source": keyword_query("{{keywords}}"),
+                },
+            }))
+            .send()
+            .await?
+            .error_for_status_code()?;
+
+        Ok(())
+    }
+
+    /// Send an update of the search document of the opportunity at `opportunity_url`.
+    async fn update(&self, opportunity_url: &str, body: Value) -> Result<(), BoxError> {
+        let id = document_id(opportunity_url);
+        let response = self
+            .client
+            .update(UpdateParts::IndexId(&self.index, &id))
+            .retry_on_conflict(RETRY_ON_CONFLICT)
+            .body(body)
+            .send()
+            .await?;
+        let status = response.status_code();
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            return Err(format!("Update of search document {id} failed with {status}: {text}").into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Return the id of the search document of the opportunity at `url`: its hex-encoded SHA-256 digest, since URLs can be
+/// longer than OpenSearch allows ids to be.
+fn document_id(url: &str) -> String {
+    hex::encode(Sha256::digest(url.as_bytes()))
+}
+
+/// Return `text` cut to at most `limit` bytes, at a character boundary.
+fn truncate(text: &str, limit: usize) -> &str {
+    if text.len() <= limit {
+        return text;
+    }
+
+    let mut end = limit;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Return the mappings of the search index.
+fn mappings() -> Value {
+    json!({
+        "properties": {
+            "Portal": { "type": "keyword" },
+            "Url": { "type": "keyword" },
+            "Title": { "type": "text" },
+            "Reference": { "type": "text", "fields": { "Exact": { "type": "keyword" } } },
+            "Organization": { "type": "text" },
+            "Description": { "type": "text" },
+            "CommodityCodes": { "type": "text", "fields": { "Exact": { "type": "keyword" } } },
+            "Counties": { "type": "keyword" },
+            "Attachments": {
+                "properties": {
+                    "DocumentUrl": { "type": "keyword" },
+                    "Text": { "type": "text" },
+                },
+            },
+        },
+    })
+}
+
+/// Return the query matching `keywords` across the [`BOOSTED_FIELDS`]. Matches in several fields add to the score of
+/// the best one, so an opportunity naming the keywords in its title and its attachments ranks above one naming them in
+/// either alone.
+pub fn keyword_query(keywords: &str) -> Value {
+    json!({
+        "query": {
+            "multi_match": {
+                "query": keywords,
+                "fields": BOOSTED_FIELDS,
+                "type": "best_fields",
+                "tie_breaker": 0.3,
+            },
+        },
+    })
+}
+
+/// Write the searchable fields of an opportunity to the search index, if one is configured. Failures are logged.
+pub(crate) async fn record_opportunity(log_config: &LogConfig, opportunity: &Opportunity) {
+    let Some(search) = log_config.search.as_ref() else {
+        return;
+    };
+
+    if let Err(e) = search.index_opportunity(opportunity).await {
+        warn!("Failed to index opportunity {}: {e}", opportunity.url);
+    }
+}
+
+/// Add the text of an attachment of an opportunity to the search index, if one is configured. Failures are logged.
+pub(crate) async fn record_attachment_text(
+    log_config: &LogConfig,
+    opportunity_url: &str,
+    document_url: &str,
+    text: &str,
+) {
+    let Some(search) = log_config.search.as_ref() else {
+        return;
+    };
+
+    if let Err(e) = search.add_attachment_text(opportunity_url, document_url, text).await {
+        warn!("Failed to index the text of attachment {document_url} of {opportunity_url}: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{document_id, keyword_query, truncate, SearchSettings, SearchSink},
+        crate::{httpext::ConfigError, opportunity::Opportunity},
+        httpmock::{Method::POST, MockServer},
+        opensearch::{http::transport::Transport, OpenSearch},
+        serde_json::json,
+        std::collections::HashMap,
+    };
+
+    #[test]
+    fn settings() {
+        let mut errors = ConfigError::default();
+        assert_eq!(SearchSettings::from_lookup(&|_| None, &mut errors), None);
+
+        let vars = HashMap::from([("OPENSEARCH_ENDPOINT", "https://search-govscout.us-west-2.es.amazonaws.com")]);
+        let settings = SearchSettings::from_lookup(&|var| vars.get(var).map(|v| v.to_string()), &mut errors).unwrap();
+        assert_eq!(settings.endpoint.host_str(), Some("search-govscout.us-west-2.es.amazonaws.com"));
+        assert_eq!(settings.index, "opportunities");
+        assert!(errors.problems.is_empty());
+
+        let vars = HashMap::from([("OPENSEARCH_ENDPOINT", "search.example.com"), ("OPENSEARCH_INDEX", "Bids")]);
+        assert_eq!(SearchSettings::from_lookup(&|var| vars.get(var).map(|v| v.to_string()), &mut errors), None);
+        let vars: Vec<&str> = errors.problems.iter().map(|p| p.var.as_str()).collect();
+        assert_eq!(vars, ["OPENSEARCH_ENDPOINT", "OPENSEARCH_INDEX"]);
+    }
+
+    #[test]
+    fn keyword_queries() {
+        let query = keyword_query("asphalt paving");
+        assert_eq!(query["query"]["multi_match"]["query"], "asphalt paving");
+        assert_eq!(query["query"]["multi_match"]["fields"][0], "Title^4");
+        assert_eq!(query["query"]["multi_match"]["fields"][5], "Attachments.Text");
+    }
+
+    #[test]
+    fn truncation() {
+        assert_eq!(truncate("Café", 10), "Café");
+        assert_eq!(truncate("Café", 4), "Caf");
+        assert_eq!(truncate("Café", 5), "Café");
+        assert_eq!(document_id("https://example.com/bid/1").len(), 64);
+    }
+
+    #[tokio::test]
+    async fn updates() {
+        let server = MockServer::start_async().await;
+        let url = "https://example.com/bid/1";
+        let path = format!("/opportunities/_update/{}", document_id(url));
+        let opportunity_update = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path(&path)
+                    .query_param("retry_on_conflict", "3")
+                    .json_body_partial(r#"{"doc": {"Url": "https://example.com/bid/1", "Title": "Paving"}}"#);
+                then.status(200).json_body(json!({ "result": "updated" }));
+            })
+            .await;
+        let attachment_update = server
+            .mock_async(|when, then| {
+                when.method(POST).path(&path).json_body_partial(
+                    r#"{"upsert": {"Url": "https://example.com/bid/1",
+                        "Attachments": [{"DocumentUrl": "https://example.com/bid/1/spec.pdf", "Text": "Asphalt"}]}}"#,
+                );
+                then.status(429).json_body(json!({ "error": "too many requests" }));
+            })
+            .await;
+
+        let transport = Transport::single_node(&server.base_url()).unwrap();
+        let sink = SearchSink::new(OpenSearch::new(transport), "opportunities");
+        let opportunity = Opportunity {
+            url: url.to_string(),
+            title: "Paving".to_string(),
+            ..Opportunity::default()
+        };
+        sink.index_opportunity(&opportunity).await.unwrap();
+        assert!(sink.add_attachment_text(url, "https://example.com/bid/1/spec.pdf", "Asphalt").await.is_err());
+        assert_eq!(opportunity_update.hits_async().await, 1);
+        assert_eq!(attachment_update.hits_async().await, 1);
+    }
+}
@@ -0,0 +1,271 @@
+//! Portal login sessions, persisted per account.
+//!
+//! Portals lock accounts that log in too often, and a crawl that is retried (or several crawls started together) would
+//! otherwise log in afresh every time. Each account's last login is recorded in DynamoDB along with the session
+//! cookies it produced; a fresh login is only allowed once the minimum interval has passed, and until then the stored
+//! session is reused.
+use {
+    crate::{
+        httpext::{aws_err_str, CookieStore},
+        BoxError,
+    },
+    aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoDbClient},
+    chrono::{DateTime, Duration, Utc},
+    log::*,
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+const DDB_KEY_CRAWL_ID: &str = "CrawlId";
+const DDB_KEY_REQUEST_ID: &str = "RequestId";
+const DDB_KEY_LAST_LOGIN_AT: &str = "LastLoginAt";
+const DDB_KEY_COOKIES: &str = "Cookies";
+const DDB_KEY_EXPIRES_AT: &str = "ExpiresAt";
+
+/// Partition key prefix for session items, keeping them apart from request log items in a shared table.
+const SESSION_PARTITION_PREFIX: &str = "Session#";
+
+/// Sort key of the session item within its partition.
+const SESSION_SORT_KEY: &str = "Session";
+
+/// How long an unused session item is kept before the table's TTL removes it.
+const SESSION_TTL_DAYS: i64 = 7;
+
+/// The last login of an account.
+#[derive(Clone, Debug)]
+pub struct LoginSession {
+    /// When the account last attempted to log in.
+    pub last_login_at: DateTime<Utc>,
+
+    /// The cookies from the last successful login, if it succeeded.
+    pub cookies: Option<CookieStore>,
+}
+
+/// A fresh login was refused because the account logged in too recently and has no session to reuse.
+#[derive(Debug)]
+pub struct LoginThrottledError {
+    /// The portal (subsystem).
+    pub portal: String,
+
+    /// The account that was throttled.
+    pub account: String,
+}
+
+impl Display for LoginThrottledError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{} account {} logged in too recently to log in again", self.portal, self.account)
+    }
+}
+
+impl Error for LoginThrottledError {}
+
+impl LoginSession {
+    /// Return the session cookies if they can be reused at `now`: the login succeeded less than `min_interval` ago and
+    /// left unexpired cookies behind.
+    pub fn reusable_cookies(&self, now: DateTime<Utc>, min_interval: Duration) -> Option<&CookieStore> {
+        if now - self.last_login_at >= min_interval {
+            return None;
+        }
+
+        self.cookies.as_ref().filter(|cookies| cookies.iter_unexpired().next().is_some())
+    }
+}
+
+/// Storage for login sessions in DynamoDB.
+#[derive(Clone, Debug)]
+pub struct SessionStore {
+    ddb_client: DynamoDbClient,
+    table_name: String,
+}
+
+impl SessionStore {
+    /// Create a session store using the given table.
+    pub fn new<S: Into<String>>(ddb_client: DynamoDbClient, table_name: S) -> Self {
+        Self {
+            ddb_client,
+            table_name: table_name.into(),
+        }
+    }
+
+    /// Return the name of the table sessions are stored in.
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    /// Load the last login of an account, if one has been recorded.
+    pub async fn load(&self, portal: &str, account: &str) -> Result<Option<LoginSession>, BoxError> {
+        let result = self
+            .ddb_client
+            .get_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, partition_key(portal, account))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(SESSION_SORT_KEY.to_string()))
+            .consistent_read(true)
+            .send()
+            .await;
+
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => {
+                error!("GetItem session for {portal} account {account}: {}", aws_err_str(&e));
+                return Err(e.into());
+            }
+        };
+
+        let Some(item) = output.item else {
+            return Ok(None);
+        };
+
+        let last_login_at = item
+            .get(DDB_KEY_LAST_LOGIN_AT)
+            .and_then(|v| v.as_n().ok())
+            .and_then(|n| n.parse().ok())
+            .and_then(|secs| DateTime::from_timestamp(secs, 0));
+        let Some(last_login_at) = last_login_at else {
+            warn!("Ignoring session for {portal} account {account} without a valid {DDB_KEY_LAST_LOGIN_AT}");
+            return Ok(None);
+        };
+
+        let cookies = match item.get(DDB_KEY_COOKIES).and_then(|v| v.as_s().ok()) {
+            Some(cookies) => match serde_json::from_str(cookies) {
+                Ok(cookies) => Some(cookies),
+                Err(e) => {
+                    warn!("Ignoring unreadable cookies for {portal} account {account}: {e}");
+                    None
+                }
+            },
+            None => None,
+        };
+
+        Ok(Some(LoginSession {
+            last_login_at,
+            cookies,
+        }))
+    }
+
+    /// Record a login attempt for an account, unless it last attempted one less than `min_interval` ago.
+    ///
+    /// Returns `false` if the attempt is not allowed. The check and the write are a single conditional write, so two
+    /// crawls racing to log in as the same account can't both succeed. Any stored cookies are discarded.
+    pub async fn claim_login(&self, portal: &str, account: &str, min_interval: Duration) -> Result<bool, BoxError> {
+        let now = Utc::now();
+        let threshold = now - min_interval;
+
+        let result = self
+            .ddb_client
+            .put_item()
+            .table_name(&self.table_name)
+            .item(DDB_KEY_CRAWL_ID, partition_key(portal, account))
+            .item(DDB_KEY_REQUEST_ID, AttributeValue::S(SESSION_SORT_KEY.to_string()))
+            .item(DDB_KEY_LAST_LOGIN_AT, AttributeValue::N(now.timestamp().to_string()))
+            .item(DDB_KEY_EXPIRES_AT, AttributeValue::N(expires_at(now).to_string()))
+            .condition_expression("attribute_not_exists(#last_login_at) OR #last_login_at <= :threshold")
+            .expression_attribute_names("#last_login_at", DDB_KEY_LAST_LOGIN_AT)
+            .expression_attribute_values(":threshold", AttributeValue::N(threshold.timestamp().to_string()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => Ok(false),
+            Err(e) => {
+                error!("PutItem session for {portal} account {account}: {}", aws_err_str(&e));
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Give up a login attempt claimed with [`claim_login`][Self::claim_login] that failed without a lockout, so the
+    /// account can be logged in as again without waiting out the interval. A claim whose login already saved cookies is
+    /// kept.
+    pub async fn release_login(&self, portal: &str, account: &str) -> Result<(), BoxError> {
+        let result = self
+            .ddb_client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, partition_key(portal, account))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(SESSION_SORT_KEY.to_string()))
+            .condition_expression("attribute_not_exists(#cookies)")
+            .expression_attribute_names("#cookies", DDB_KEY_COOKIES)
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => Ok(()),
+            Err(e) => {
+                error!("DeleteItem session for {portal} account {account}: {}", aws_err_str(&e));
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Store the cookies of a successful login so they can be reused.
+    pub async fn save_cookies(&self, portal: &str, account: &str, cookies: &CookieStore) -> Result<(), BoxError> {
+        let result = self
+            .ddb_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key(DDB_KEY_CRAWL_ID, partition_key(portal, account))
+            .key(DDB_KEY_REQUEST_ID, AttributeValue::S(SESSION_SORT_KEY.to_string()))
+            .update_expression("SET #cookies = :cookies")
+            .expression_attribute_names("#cookies", DDB_KEY_COOKIES)
+            .expression_attribute_values(":cookies", AttributeValue::S(serde_json::to_string(cookies)?))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("UpdateItem session cookies for {portal} account {account}: {}", aws_err_str(&e));
+                Err(e.into())
+            }
+        }
+    }
+}
+
+/// Return the partition key of an account's session item.
+fn partition_key(portal: &str, account: &str) -> AttributeValue {
+    AttributeValue::S(format!("{SESSION_PARTITION_PREFIX}{portal}#{account}"))
+}
+
+/// Return the TTL timestamp for a session item written at `now`.
+fn expires_at(now: DateTime<Utc>) -> i64 {
+    (now + Duration::days(SESSION_TTL_DAYS)).timestamp()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::LoginSession,
+        crate::httpext::CookieStore,
+        chrono::{Duration, TimeZone, Utc},
+        cookie_store::RawCookie,
+        reqwest::Url,
+    };
+
+    #[test]
+    fn session_reuse() {
+        let url = Url::parse("https://pr-webs-vendor.des.wa.gov/Home.aspx").unwrap();
+        let mut cookies = CookieStore::default();
+        cookies.insert_raw(&RawCookie::parse("ASP.NET_SessionId=abc; Path=/").unwrap(), &url).unwrap();
+
+        let last_login_at = Utc.with_ymd_and_hms(2024, 3, 7, 12, 0, 0).unwrap();
+        let interval = Duration::minutes(15);
+        let session = LoginSession {
+            last_login_at,
+            cookies: Some(cookies),
+        };
+        assert!(session.reusable_cookies(last_login_at + Duration::minutes(5), interval).is_some());
+        assert!(session.reusable_cookies(last_login_at + Duration::minutes(15), interval).is_none());
+
+        // An attempt that never stored cookies (e.g. a lockout) can't be reused.
+        let session = LoginSession {
+            last_login_at,
+            cookies: None,
+        };
+        assert!(session.reusable_cookies(last_login_at + Duration::minutes(5), interval).is_none());
+    }
+}
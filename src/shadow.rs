@@ -0,0 +1,162 @@
+//! Shadow runs of new parsers alongside the stable ones.
+//!
+//! A parser rewrite is de-risked on live crawls by running it in shadow before it is switched on: the operation parses
+//! the page with its stable parser as usual, then hands the same document to the new parser through [`run_shadow`].
+//! Only the stable parser's output is kept; the two outputs are compared field by field and the fields that differ are
+//! logged, recorded in the crawl journal as a [`ShadowParseCompared`][CrawlEvent::ShadowParseCompared] event, and
+//! counted in the `ShadowParses` metric by outcome. Shadow runs are switched on per parser with a
+//! [feature flag][crate::flags].
+use {
+    crate::{httpext::LogConfig, journal::CrawlEvent, metrics, opportunity::Opportunity, BoxError},
+    log::*,
+    serde_json::Value,
+    std::fmt::{Display, Formatter, Result as FmtResult},
+};
+
+const METRIC_SHADOW_PARSES: &str = "ShadowParses";
+const DIMENSION_PARSER: &str = "Parser";
+const DIMENSION_OUTCOME: &str = "Outcome";
+const OUTCOME_MATCHED: &str = "Matched";
+const OUTCOME_DIFFERED: &str = "Differed";
+const OUTCOME_FAILED: &str = "Failed";
+
+/// Fields of a serialized opportunity stamped after parsing, which aren't compared.
+const UNCOMPARED_FIELDS: &[&str] =
+    &["ParsedBy", "Quality", "Tenant", "Status", "ArchivedAt", "DuplicateOf", "Stage", "StageHistory"];
+
+/// A field whose value differs between the stable and shadow parsers' outputs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FieldDifference {
+    /// The name of the field in serialized records, e.g. `DueAt`.
+    pub field: String,
+
+    /// The stable parser's value, or `null` if it left the field empty.
+    pub stable: Value,
+
+    /// The shadow parser's value, or `null` if it left the field empty.
+    pub shadow: Value,
+}
+
+impl Display for FieldDifference {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}: stable {}, shadow {}", self.field, self.stable, self.shadow)
+    }
+}
+
+/// Compare the outputs of two parsers, returning the fields that differ, ordered by name.
+pub fn compare(stable: &Opportunity, shadow: &Opportunity) -> Vec<FieldDifference> {
+    let (Ok(Value::Object(stable)), Ok(Value::Object(shadow))) =
+        (serde_json::to_value(stable), serde_json::to_value(shadow))
+    else {
+        return vec![];
+    };
+
+    let mut fields: Vec<&String> = stable.keys().collect();
+    fields.extend(shadow.keys().filter(|field| !stable.contains_key(*field)));
+    fields.sort();
+
+    fields
+        .into_iter()
+        .filter(|field| !UNCOMPARED_FIELDS.contains(&field.as_str()))
+        .filter_map(|field| {
+            let stable = stable.get(field).cloned().unwrap_or_default();
+            let shadow = shadow.get(field).cloned().unwrap_or_default();
+            (stable != shadow).then(|| FieldDifference {
+                field: field.clone(),
+                stable,
+                shadow,
+            })
+        })
+        .collect()
+}
+
+/// Run `shadow_parse` over a page the stable parser `parser` produced `stable` from, and record how its output
+/// differs. The shadow parser's output is discarded, and its failure doesn't fail the operation.
+pub async fn run_shadow<F>(
+    log_config: &LogConfig,
+    crawl_id: &str,
+    url: &str,
+    parser: &str,
+    shadow_parser: &str,
+    stable: &Opportunity,
+    shadow_parse: F,
+) where
+    F: FnOnce() -> Result<Opportunity, BoxError>,
+{
+    let (outcome, differences, error) = match shadow_parse() {
+        Ok(shadow) => {
+            let differences = compare(stable, &shadow);
+            if differences.is_empty() {
+                debug!("Shadow parser {shadow_parser} agrees with {parser} on {url}");
+                (OUTCOME_MATCHED, differences, None)
+            } else {
+                let summary = differences.iter().map(FieldDifference::to_string).collect::<Vec<_>>().join("; ");
+                warn!("Shadow parser {shadow_parser} differs from {parser} on {url}: {summary}");
+                (OUTCOME_DIFFERED, differences, None)
+            }
+        }
+        Err(e) => {
+            warn!("Shadow parser {shadow_parser} failed on {url}: {e}");
+            (OUTCOME_FAILED, vec![], Some(e.to_string()))
+        }
+    };
+
+    metrics::registry().increment(
+        METRIC_SHADOW_PARSES,
+        &[(DIMENSION_PARSER, shadow_parser), (DIMENSION_OUTCOME, outcome)],
+        1,
+    );
+
+    let event = CrawlEvent::ShadowParseCompared {
+        url: url.to_string(),
+        parser: parser.to_string(),
+        shadow_parser: shadow_parser.to_string(),
+        differences: differences.into_iter().map(|difference| difference.field).collect(),
+        error,
+    };
+    log_config.journal.record(crawl_id, event).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{compare, FieldDifference},
+        crate::opportunity::{Document, Opportunity, OpportunityStatus},
+        serde_json::{json, Value},
+    };
+
+    #[test]
+    fn differences() {
+        let stable = Opportunity {
+            portal: "Webs".to_string(),
+            title: "Street Paving".to_string(),
+            reference: Some("22-015".to_string()),
+            ..Opportunity::default()
+        };
+
+        let mut shadow = stable.clone().with_parser("Webs:OpportunityDetailStructuredData", 1);
+        shadow.status = OpportunityStatus::Closed;
+        assert!(compare(&stable, &shadow).is_empty());
+
+        shadow.title = "Street Paving Services".to_string();
+        shadow.reference = None;
+        shadow.documents.push(Document {
+            name: "Plans".to_string(),
+            url: "https://example.gov/plans.pdf".to_string(),
+        });
+        let differences = compare(&stable, &shadow);
+        assert_eq!(
+            differences.iter().map(|difference| difference.field.as_str()).collect::<Vec<_>>(),
+            ["Documents", "Reference", "Title"]
+        );
+        assert_eq!(
+            differences[1],
+            FieldDifference {
+                field: "Reference".to_string(),
+                stable: json!("22-015"),
+                shadow: Value::Null,
+            }
+        );
+        assert_eq!(differences[1].to_string(), r#"Reference: stable "22-015", shadow null"#);
+    }
+}
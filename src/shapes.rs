@@ -2,21 +2,52 @@
 
 use {
     crate::{
-        httpext::{default_headers, ClientBuilder, CookieStore, CookieStoreRwLock, LogConfig, DEFAULT_REDIRECT_LIMIT},
+        admin::AdminOperation,
+        archive::ArchiveOperation,
+        bidnet::BidNetOperation,
+        canary::CanaryOperation,
+        config::Locale,
+        duplicates::DuplicatesOperation,
+        export::ExportOperation,
+        fl_vbs::FlVbsOperation,
+        httpext::{
+            default_headers, AddressFamily, ClientBuilder, CookieStore, CookieStoreRwLock, CrawlCookie, CrawlStats,
+            HostAllowlist, LogConfig, RequestPacer, DEFAULT_REDIRECT_LIMIT,
+        },
+        ingest::IngestOperation,
+        mail::MailOperation,
+        merx::MerxOperation,
+        pagination,
+        publicpurchase::PublicPurchaseOperation,
+        reconcile::ReconcileOperation,
+        retry::FailedAttempt,
+        rules::RulesOperation,
+        schedule::ScheduleOperation,
+        testportal::TestPortalOperation,
+        vendors::VendorsOperation,
+        wa_local::WaLocalOperation,
         webs::WebsOperation,
+        BoxError,
     },
+    chrono::NaiveDate,
     lambda_runtime::{Context, Error as LambdaError},
     log::*,
-    reqwest::redirect::Policy as RedirectPolicy,
+    reqwest::{
+        header::{HeaderName, HeaderValue, ACCEPT_LANGUAGE, REFERER},
+        redirect::Policy as RedirectPolicy,
+        Url,
+    },
     serde::{
         de::{Deserializer, Error as SerdeError, Visitor},
         ser::Serializer,
         Deserialize, Serialize,
     },
     std::{
+        collections::BTreeMap,
         fmt::{Display, Formatter, Result as FmtResult},
         str::FromStr,
         sync::Arc,
+        time::Duration,
     },
 };
 
@@ -24,11 +55,84 @@ use {
 pub const DEFAULT_USER_AGENT: &str =
     "Mozilla/5.0 (compatible; GovScout/0.1; +https://github.com/dacut/govscout-backend)";
 
-const SUBSYS_WEBS: &str = "Webs";
+/// Query parameters and path parameters that carry session state rather than identify a resource. These are dropped
+/// when canonicalizing URLs; the session travels in the cookie store instead. The generic `sid` isn't among them, since
+/// some portals use it for a solicitation id.
+pub(crate) const SESSION_PARAMS: &[&str] = &["cfid", "cftoken", "jsessionid", "phpsessid", "sessionid"];
+
+pub(crate) const SUBSYS_ADMIN: &str = "Admin";
+pub(crate) const SUBSYS_ARCHIVE: &str = "Archive";
+pub(crate) const SUBSYS_BIDNET: &str = "BidNet";
+pub(crate) const SUBSYS_CANARY: &str = "Canary";
+pub(crate) const SUBSYS_DUPLICATES: &str = "Duplicates";
+pub(crate) const SUBSYS_EXPORT: &str = "Export";
+pub(crate) const SUBSYS_FL_VBS: &str = "FlVbs";
+pub(crate) const SUBSYS_INGEST: &str = "Ingest";
+pub(crate) const SUBSYS_MAIL: &str = "Mail";
+pub(crate) const SUBSYS_MERX: &str = "Merx";
+pub(crate) const SUBSYS_PUBLIC_PURCHASE: &str = "PublicPurchase";
+pub(crate) const SUBSYS_RECONCILE: &str = "Reconcile";
+pub(crate) const SUBSYS_RULES: &str = "Rules";
+pub(crate) const SUBSYS_SCHEDULE: &str = "Schedule";
+pub(crate) const SUBSYS_TEST_PORTAL: &str = "TestPortal";
+pub(crate) const SUBSYS_VENDORS: &str = "Vendors";
+pub(crate) const SUBSYS_WA_LOCAL: &str = "WaLocal";
+pub(crate) const SUBSYS_WEBS: &str = "Webs";
 
 /// Operations that can be performed.
 #[derive(Clone, Copy, Debug)]
 pub enum Operation {
+    /// Administrative operation.
+    Admin(AdminOperation),
+
+    /// Internet Archive backfill operation.
+    Archive(ArchiveOperation),
+
+    /// BidNet Direct operation.
+    BidNet(BidNetOperation),
+
+    /// Canary operation.
+    Canary(CanaryOperation),
+
+    /// Duplicate clustering operation.
+    Duplicates(DuplicatesOperation),
+
+    /// Bulk export operation.
+    Export(ExportOperation),
+
+    /// Florida Vendor Bid System operation.
+    FlVbs(FlVbsOperation),
+
+    /// Table ingestion operation.
+    Ingest(IngestOperation),
+
+    /// Email ingestion operation.
+    Mail(MailOperation),
+
+    /// MERX operation.
+    Merx(MerxOperation),
+
+    /// PublicPurchase operation.
+    PublicPurchase(PublicPurchaseOperation),
+
+    /// Opportunity reconciliation operation.
+    Reconcile(ReconcileOperation),
+
+    /// Rule-driven portal operation.
+    Rules(RulesOperation),
+
+    /// Crawl scheduling operation.
+    Schedule(ScheduleOperation),
+
+    /// Test portal operation.
+    TestPortal(TestPortalOperation),
+
+    /// Vendor directory operation.
+    Vendors(VendorsOperation),
+
+    /// Washington city and county portal operation.
+    WaLocal(WaLocalOperation),
+
     /// WEBS operation.
     Webs(WebsOperation),
 }
@@ -43,11 +147,68 @@ pub struct Request {
     /// The URL to start crawling from.
     pub url: Option<String>,
 
+    /// The crawl to compare the request's crawl against, for `Admin:CompareCrawls`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compare_to: Option<String>,
+
+    /// The subsystem whose failed requests are re-enqueued, for `Admin:Requeue`, or whose opportunities are exported,
+    /// for `Export:Opportunities`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subsystem: Option<String>,
+
+    /// The class of error whose failed requests are re-enqueued, for `Admin:Requeue`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_class: Option<String>,
+
+    /// The S3 keys of the stored bodies to redact, for `Admin:Redact`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub s3_keys: Vec<String>,
+
+    /// A pattern, where `*` matches any run of characters, selecting the stored bodies to redact by the URL they were
+    /// fetched from, for `Admin:Redact`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url_pattern: Option<String>,
+
+    /// The text to mask in the stored bodies, matched ignoring ASCII case, for `Admin:Redact`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redact_patterns: Vec<String>,
+
+    /// The S3 key of a frontier export to resume a crawl from, for `Admin:ResumeCrawl`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frontier_key: Option<String>,
+
+    /// Only export opportunities whose organization contains this text, ignoring case, for `Export:Opportunities`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jurisdiction: Option<String>,
+
+    /// Only export opportunities last seen on or after this day (UTC), for `Export:Opportunities`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seen_from: Option<NaiveDate>,
+
+    /// Only export opportunities last seen on or before this day (UTC), for `Export:Opportunities`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seen_until: Option<NaiveDate>,
+
+    /// Whether a `StartCrawl` only crawls what the portal posted or amended since its last successful crawl, for
+    /// portals whose search supports it.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub incremental: bool,
+
     /// Common crawl parameters
     #[serde(flatten)]
     pub crawl: CrawlParameters,
 }
 
+impl Request {
+    /// Return the URL of the request, which operations other than `StartCrawl` require.
+    pub fn required_url(&self) -> Result<Url, BoxError> {
+        match self.url.as_deref() {
+            Some(url) => Ok(Url::parse(url)?),
+            None => Err(format!("{} requires a URL", self.operation).into()),
+        }
+    }
+}
+
 /// Next request to schedule. This is similar to Request but is more strict about types.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
@@ -58,9 +219,148 @@ pub struct NextRequest {
     /// The URL to start crawling from.
     pub url: Option<String>,
 
+    /// The text of the link the URL was found in, if the operation that emitted the request knows it. This is recorded
+    /// in the [link graph][crate::link_graph] rather than sent with the request.
+    #[serde(skip)]
+    pub link_text: Option<String>,
+
     /// Common crawl parameters
     #[serde(flatten)]
     pub crawl: CrawlParameters,
+
+    /// How long the queue should hold the request before delivering it, in seconds. This is a property of the message
+    /// rather than part of its body.
+    #[serde(skip)]
+    pub delay_seconds: u32,
+}
+
+/// The query parameters of a page of a GET-paginated listing: its page number or offset, page size, and filters.
+///
+/// Parameters set here replace those of the same name in the URL they're applied to. The query is then written with
+/// its parameters sorted by name and form-urlencoded, so the same page always has the same URL and subsystems needn't
+/// concatenate query strings by hand.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct ListingQuery {
+    params: Vec<(String, String)>,
+}
+
+impl ListingQuery {
+    /// Create an empty set of query parameters.
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the page number.
+    pub(crate) fn page(self, name: &str, page: usize) -> Self {
+        self.param(name, page.to_string())
+    }
+
+    /// Set the number of results per page.
+    pub(crate) fn page_size(self, name: &str, size: usize) -> Self {
+        self.param(name, size.to_string())
+    }
+
+    /// Set the number of results to skip.
+    pub(crate) fn offset(self, name: &str, offset: usize) -> Self {
+        self.param(name, offset.to_string())
+    }
+
+    /// Set the opaque token identifying where the page starts.
+    pub(crate) fn cursor(self, name: &str, token: &str) -> Self {
+        self.param(name, token.to_string())
+    }
+
+    /// Set a filter on the results, e.g. a category or status.
+    pub(crate) fn filter(self, name: &str, value: &str) -> Self {
+        self.param(name, value.to_string())
+    }
+
+    /// Set a parameter, replacing any earlier value.
+    fn param(mut self, name: &str, value: String) -> Self {
+        self.params.retain(|(n, _)| n != name);
+        self.params.push((name.to_string(), value));
+        self
+    }
+
+    /// Return `base` with these parameters applied.
+    pub(crate) fn url(&self, base: &Url) -> Url {
+        let mut pairs: Vec<(String, String)> =
+            base.query_pairs().into_owned().filter(|(name, _)| !self.params.iter().any(|(n, _)| n == name)).collect();
+        pairs.extend(self.params.iter().cloned());
+        // A stable sort keeps the order of repeated parameters, which some portals read as a list.
+        pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut url = base.clone();
+        if pairs.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+        url
+    }
+
+    /// Return the continuation message fetching `base` with these parameters applied with `operation`.
+    pub(crate) fn next_request(&self, operation: Operation, base: &Url, crawl: &CrawlParameters) -> NextRequest {
+        pagination::next_page_request(operation, &self.url(base), crawl)
+    }
+}
+
+impl NextRequest {
+    /// Canonicalize the URL of this request with [`normalize_url`], leaving it untouched if it can't be parsed.
+    pub fn normalize(&mut self) {
+        if let Some(url) = self.url.as_mut() {
+            match normalize_url(url) {
+                Ok(normalized) => *url = normalized,
+                Err(e) => warn!("Not normalizing unparseable URL {url}: {e}"),
+            }
+        }
+    }
+}
+
+/// Canonicalize a URL so that equivalent URLs compare equal.
+///
+/// The fragment is removed, dot segments are resolved, session parameters are stripped from the path and query, and
+/// the remaining query parameters are sorted by name, keeping repeated parameters in the order they were given.
+pub fn normalize_url(url: &str) -> Result<String, BoxError> {
+    let mut url = Url::parse(url)?;
+    url.set_fragment(None);
+
+    // Remove ";jsessionid=..."-style path parameters.
+    let path = url.path();
+    if let Some(pos) = path.find(';') {
+        let (base, params) = path.split_at(pos);
+        let kept: Vec<&str> = params[1..].split(';').filter(|param| !is_session_param(param)).collect();
+        let path = if kept.is_empty() {
+            base.to_string()
+        } else {
+            format!("{base};{}", kept.join(";"))
+        };
+        url.set_path(&path);
+    }
+
+    if url.query().is_some() {
+        let mut pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(k, _)| !is_session_param(k))
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+        // As in `ListingQuery::url`, a stable sort on names keeps repeated parameters in order.
+        pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        if pairs.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+    }
+
+    Ok(url.into())
+}
+
+/// Indicates whether a query or path parameter (`name` or `name=value`) carries session state.
+fn is_session_param(param: &str) -> bool {
+    let name = param.split('=').next().unwrap_or_default().to_ascii_lowercase();
+    SESSION_PARAMS.contains(&name.as_str())
 }
 
 /// Response type for all operations.
@@ -71,6 +371,9 @@ pub struct Response {
 }
 
 /// Common parameters for crawling.
+///
+/// Requests scheduled by an operation carry parameters derived from the operation's own with
+/// [`child`][CrawlParameters::child], which defines what is inherited and what changes from one request to the next.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct CrawlParameters {
@@ -84,6 +387,112 @@ pub struct CrawlParameters {
     /// Cookies to use for the crawl.
     #[serde(default)]
     pub cookies: CookieStore,
+
+    /// Cookies to add to the cookie store before the first request, such as a consent banner's cookie, unless the
+    /// portal has set a cookie of the same name, domain, and path since.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_cookies: Vec<CrawlCookie>,
+
+    /// Additional headers to send with every request.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+
+    /// The minimum time between requests, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_interval_ms: Option<u64>,
+
+    /// The number of requests between the start of the crawl and this one.
+    #[serde(default)]
+    pub depth: u32,
+
+    /// The number of further generations of requests this crawl may schedule, or `None` for no limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget: Option<u32>,
+
+    /// The portal account the crawl is logged in as, for portals with more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+
+    /// The team that owns the crawl, when several share the deployment. Stored responses, opportunities, metrics, and
+    /// cost estimates are attributed to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+
+    /// The address families to connect with, for portals whose hosts publish addresses they don't answer on.
+    #[serde(default, skip_serializing_if = "AddressFamily::is_any")]
+    pub address_family: AddressFamily,
+
+    /// How long to wait for each connection, in milliseconds, overriding the deployment's connect timeout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_ms: Option<u64>,
+
+    /// The language the portal is crawled in, read from the subsystem's configuration document when the crawl starts.
+    #[serde(default, skip_serializing_if = "Locale::is_default")]
+    pub locale: Locale,
+
+    /// Only opportunities posted or amended on or after this date are crawled, for incremental crawls.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub posted_since: Option<NaiveDate>,
+
+    /// The number of times this request has been re-enqueued after a temporary condition.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub attempt: u32,
+
+    /// The attempts at this request that failed and caused it to be re-enqueued, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed_attempts: Vec<FailedAttempt>,
+
+    /// Send the request with a `Referer` naming the page it was found on, for portals that check it. This is read from
+    /// the subsystem's [crawl settings][crate::config::CrawlSettings] when the crawl starts.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub referer_chain: bool,
+
+    /// The URL of the page the request was found on, sent as its `Referer` when
+    /// [`referer_chain`][Self::referer_chain] is set. The [`RefererChaining`][crate::middleware::RefererChaining]
+    /// middleware fills this in from the request that emitted it; it is not inherited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub referer: Option<String>,
+
+    /// The URL of the opportunity a document is being fetched for. This is set only on document requests and is not
+    /// inherited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opportunity_url: Option<String>,
+}
+
+impl Default for CrawlParameters {
+    fn default() -> Self {
+        Self {
+            crawl_id: None,
+            user_agent: default_user_agent(),
+            cookies: CookieStore::default(),
+            extra_cookies: vec![],
+            headers: BTreeMap::new(),
+            request_interval_ms: None,
+            depth: 0,
+            budget: None,
+            account: None,
+            tenant: None,
+            address_family: AddressFamily::Any,
+            connect_timeout_ms: None,
+            locale: Locale::default(),
+            posted_since: None,
+            attempt: 0,
+            failed_attempts: vec![],
+            referer_chain: false,
+            referer: None,
+            opportunity_url: None,
+        }
+    }
+}
+
+/// Indicates whether a count is zero, for omitting it from messages.
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+/// Indicates whether a flag is unset, for omitting it from messages.
+fn is_false(b: &bool) -> bool {
+    !*b
 }
 
 /// Return the default user agent for [`CrawlParameters`].
@@ -111,6 +520,125 @@ impl<'de> Visitor<'de> for OperationVisitor {
         }
 
         match parts[0] {
+            SUBSYS_ADMIN => {
+                let admin_op = match AdminOperation::from_str(parts[1]) {
+                    Ok(op) => op,
+                    Err(_) => return Err(E::custom(format!("Unknown Admin operation {}", parts[1]))),
+                };
+                Ok(Operation::Admin(admin_op))
+            }
+            SUBSYS_ARCHIVE => {
+                let archive_op = match ArchiveOperation::from_str(parts[1]) {
+                    Ok(op) => op,
+                    Err(_) => return Err(E::custom(format!("Unknown Archive operation {}", parts[1]))),
+                };
+                Ok(Operation::Archive(archive_op))
+            }
+            SUBSYS_BIDNET => {
+                let bidnet_op = match BidNetOperation::from_str(parts[1]) {
+                    Ok(op) => op,
+                    Err(_) => return Err(E::custom(format!("Unknown BidNet operation {}", parts[1]))),
+                };
+                Ok(Operation::BidNet(bidnet_op))
+            }
+            SUBSYS_CANARY => {
+                let canary_op = match CanaryOperation::from_str(parts[1]) {
+                    Ok(op) => op,
+                    Err(_) => return Err(E::custom(format!("Unknown Canary operation {}", parts[1]))),
+                };
+                Ok(Operation::Canary(canary_op))
+            }
+            SUBSYS_DUPLICATES => {
+                let duplicates_op = match DuplicatesOperation::from_str(parts[1]) {
+                    Ok(op) => op,
+                    Err(_) => return Err(E::custom(format!("Unknown Duplicates operation {}", parts[1]))),
+                };
+                Ok(Operation::Duplicates(duplicates_op))
+            }
+            SUBSYS_EXPORT => {
+                let export_op = match ExportOperation::from_str(parts[1]) {
+                    Ok(op) => op,
+                    Err(_) => return Err(E::custom(format!("Unknown Export operation {}", parts[1]))),
+                };
+                Ok(Operation::Export(export_op))
+            }
+            SUBSYS_FL_VBS => {
+                let fl_vbs_op = match FlVbsOperation::from_str(parts[1]) {
+                    Ok(op) => op,
+                    Err(_) => return Err(E::custom(format!("Unknown FlVbs operation {}", parts[1]))),
+                };
+                Ok(Operation::FlVbs(fl_vbs_op))
+            }
+            SUBSYS_INGEST => {
+                let ingest_op = match IngestOperation::from_str(parts[1]) {
+                    Ok(op) => op,
+                    Err(_) => return Err(E::custom(format!("Unknown Ingest operation {}", parts[1]))),
+                };
+                Ok(Operation::Ingest(ingest_op))
+            }
+            SUBSYS_MAIL => {
+                let mail_op = match MailOperation::from_str(parts[1]) {
+                    Ok(op) => op,
+                    Err(_) => return Err(E::custom(format!("Unknown Mail operation {}", parts[1]))),
+                };
+                Ok(Operation::Mail(mail_op))
+            }
+            SUBSYS_MERX => {
+                let merx_op = match MerxOperation::from_str(parts[1]) {
+                    Ok(op) => op,
+                    Err(_) => return Err(E::custom(format!("Unknown Merx operation {}", parts[1]))),
+                };
+                Ok(Operation::Merx(merx_op))
+            }
+            SUBSYS_PUBLIC_PURCHASE => {
+                let public_purchase_op = match PublicPurchaseOperation::from_str(parts[1]) {
+                    Ok(op) => op,
+                    Err(_) => return Err(E::custom(format!("Unknown PublicPurchase operation {}", parts[1]))),
+                };
+                Ok(Operation::PublicPurchase(public_purchase_op))
+            }
+            SUBSYS_RECONCILE => {
+                let reconcile_op = match ReconcileOperation::from_str(parts[1]) {
+                    Ok(op) => op,
+                    Err(_) => return Err(E::custom(format!("Unknown Reconcile operation {}", parts[1]))),
+                };
+                Ok(Operation::Reconcile(reconcile_op))
+            }
+            SUBSYS_RULES => {
+                let rules_op = match RulesOperation::from_str(parts[1]) {
+                    Ok(op) => op,
+                    Err(_) => return Err(E::custom(format!("Unknown Rules operation {}", parts[1]))),
+                };
+                Ok(Operation::Rules(rules_op))
+            }
+            SUBSYS_SCHEDULE => {
+                let schedule_op = match ScheduleOperation::from_str(parts[1]) {
+                    Ok(op) => op,
+                    Err(_) => return Err(E::custom(format!("Unknown Schedule operation {}", parts[1]))),
+                };
+                Ok(Operation::Schedule(schedule_op))
+            }
+            SUBSYS_TEST_PORTAL => {
+                let test_portal_op = match TestPortalOperation::from_str(parts[1]) {
+                    Ok(op) => op,
+                    Err(_) => return Err(E::custom(format!("Unknown TestPortal operation {}", parts[1]))),
+                };
+                Ok(Operation::TestPortal(test_portal_op))
+            }
+            SUBSYS_VENDORS => {
+                let vendors_op = match VendorsOperation::from_str(parts[1]) {
+                    Ok(op) => op,
+                    Err(_) => return Err(E::custom(format!("Unknown Vendors operation {}", parts[1]))),
+                };
+                Ok(Operation::Vendors(vendors_op))
+            }
+            SUBSYS_WA_LOCAL => {
+                let wa_local_op = match WaLocalOperation::from_str(parts[1]) {
+                    Ok(op) => op,
+                    Err(_) => return Err(E::custom(format!("Unknown WaLocal operation {}", parts[1]))),
+                };
+                Ok(Operation::WaLocal(wa_local_op))
+            }
             SUBSYS_WEBS => {
                 let webs_op = match WebsOperation::from_str(parts[1]) {
                     Ok(op) => op,
@@ -135,6 +663,23 @@ impl<'de> Deserialize<'de> for Operation {
 impl Display for Operation {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         match self {
+            Operation::Admin(op) => write!(f, "{SUBSYS_ADMIN}:{op}"),
+            Operation::Archive(op) => write!(f, "{SUBSYS_ARCHIVE}:{op}"),
+            Operation::BidNet(op) => write!(f, "{SUBSYS_BIDNET}:{op}"),
+            Operation::Canary(op) => write!(f, "{SUBSYS_CANARY}:{op}"),
+            Operation::Duplicates(op) => write!(f, "{SUBSYS_DUPLICATES}:{op}"),
+            Operation::Export(op) => write!(f, "{SUBSYS_EXPORT}:{op}"),
+            Operation::FlVbs(op) => write!(f, "{SUBSYS_FL_VBS}:{op}"),
+            Operation::Ingest(op) => write!(f, "{SUBSYS_INGEST}:{op}"),
+            Operation::Mail(op) => write!(f, "{SUBSYS_MAIL}:{op}"),
+            Operation::Merx(op) => write!(f, "{SUBSYS_MERX}:{op}"),
+            Operation::PublicPurchase(op) => write!(f, "{SUBSYS_PUBLIC_PURCHASE}:{op}"),
+            Operation::Reconcile(op) => write!(f, "{SUBSYS_RECONCILE}:{op}"),
+            Operation::Rules(op) => write!(f, "{SUBSYS_RULES}:{op}"),
+            Operation::Schedule(op) => write!(f, "{SUBSYS_SCHEDULE}:{op}"),
+            Operation::TestPortal(op) => write!(f, "{SUBSYS_TEST_PORTAL}:{op}"),
+            Operation::Vendors(op) => write!(f, "{SUBSYS_VENDORS}:{op}"),
+            Operation::WaLocal(op) => write!(f, "{SUBSYS_WA_LOCAL}:{op}"),
             Operation::Webs(op) => write!(f, "{SUBSYS_WEBS}:{op}"),
         }
     }
@@ -150,6 +695,23 @@ impl FromStr for Operation {
         }
 
         match parts[0] {
+            SUBSYS_ADMIN => Ok(Self::Admin(AdminOperation::from_str(parts[1])?)),
+            SUBSYS_ARCHIVE => Ok(Self::Archive(ArchiveOperation::from_str(parts[1])?)),
+            SUBSYS_BIDNET => Ok(Self::BidNet(BidNetOperation::from_str(parts[1])?)),
+            SUBSYS_CANARY => Ok(Self::Canary(CanaryOperation::from_str(parts[1])?)),
+            SUBSYS_DUPLICATES => Ok(Self::Duplicates(DuplicatesOperation::from_str(parts[1])?)),
+            SUBSYS_EXPORT => Ok(Self::Export(ExportOperation::from_str(parts[1])?)),
+            SUBSYS_FL_VBS => Ok(Self::FlVbs(FlVbsOperation::from_str(parts[1])?)),
+            SUBSYS_INGEST => Ok(Self::Ingest(IngestOperation::from_str(parts[1])?)),
+            SUBSYS_MAIL => Ok(Self::Mail(MailOperation::from_str(parts[1])?)),
+            SUBSYS_MERX => Ok(Self::Merx(MerxOperation::from_str(parts[1])?)),
+            SUBSYS_PUBLIC_PURCHASE => Ok(Self::PublicPurchase(PublicPurchaseOperation::from_str(parts[1])?)),
+            SUBSYS_RECONCILE => Ok(Self::Reconcile(ReconcileOperation::from_str(parts[1])?)),
+            SUBSYS_RULES => Ok(Self::Rules(RulesOperation::from_str(parts[1])?)),
+            SUBSYS_SCHEDULE => Ok(Self::Schedule(ScheduleOperation::from_str(parts[1])?)),
+            SUBSYS_TEST_PORTAL => Ok(Self::TestPortal(TestPortalOperation::from_str(parts[1])?)),
+            SUBSYS_VENDORS => Ok(Self::Vendors(VendorsOperation::from_str(parts[1])?)),
+            SUBSYS_WA_LOCAL => Ok(Self::WaLocal(WaLocalOperation::from_str(parts[1])?)),
             SUBSYS_WEBS => Ok(Self::Webs(WebsOperation::from_str(parts[1])?)),
             _ => Err("unknown subsystem".to_string()),
         }
@@ -160,6 +722,23 @@ impl Operation {
     /// Handle a request.
     pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
         match self {
+            Operation::Admin(op) => op.handle(log_config, req, context).await,
+            Operation::Archive(op) => op.handle(log_config, req, context).await,
+            Operation::BidNet(op) => op.handle(log_config, req, context).await,
+            Operation::Canary(op) => op.handle(log_config, req, context).await,
+            Operation::Duplicates(op) => op.handle(log_config, req, context).await,
+            Operation::Export(op) => op.handle(log_config, req, context).await,
+            Operation::FlVbs(op) => op.handle(log_config, req, context).await,
+            Operation::Ingest(op) => op.handle(log_config, req, context).await,
+            Operation::Mail(op) => op.handle(log_config, req, context).await,
+            Operation::Merx(op) => op.handle(log_config, req, context).await,
+            Operation::PublicPurchase(op) => op.handle(log_config, req, context).await,
+            Operation::Reconcile(op) => op.handle(log_config, req, context).await,
+            Operation::Rules(op) => op.handle(log_config, req, context).await,
+            Operation::Schedule(op) => op.handle(log_config, req, context).await,
+            Operation::TestPortal(op) => op.handle(log_config, req, context).await,
+            Operation::Vendors(op) => op.handle(log_config, req, context).await,
+            Operation::WaLocal(op) => op.handle(log_config, req, context).await,
             Operation::Webs(op) => op.handle(log_config, req, context).await,
         }
     }
@@ -167,6 +746,23 @@ impl Operation {
     /// Return the subsystem of the operation.
     pub fn subsystem(&self) -> &'static str {
         match self {
+            Operation::Admin(_) => SUBSYS_ADMIN,
+            Operation::Archive(_) => SUBSYS_ARCHIVE,
+            Operation::BidNet(_) => SUBSYS_BIDNET,
+            Operation::Canary(_) => SUBSYS_CANARY,
+            Operation::Duplicates(_) => SUBSYS_DUPLICATES,
+            Operation::Export(_) => SUBSYS_EXPORT,
+            Operation::FlVbs(_) => SUBSYS_FL_VBS,
+            Operation::Ingest(_) => SUBSYS_INGEST,
+            Operation::Mail(_) => SUBSYS_MAIL,
+            Operation::Merx(_) => SUBSYS_MERX,
+            Operation::PublicPurchase(_) => SUBSYS_PUBLIC_PURCHASE,
+            Operation::Reconcile(_) => SUBSYS_RECONCILE,
+            Operation::Rules(_) => SUBSYS_RULES,
+            Operation::Schedule(_) => SUBSYS_SCHEDULE,
+            Operation::TestPortal(_) => SUBSYS_TEST_PORTAL,
+            Operation::Vendors(_) => SUBSYS_VENDORS,
+            Operation::WaLocal(_) => SUBSYS_WA_LOCAL,
             Operation::Webs(_) => SUBSYS_WEBS,
         }
     }
@@ -174,6 +770,23 @@ impl Operation {
     /// Return the operation name within the subsystem.
     pub fn operation(&self) -> &'static str {
         match self {
+            Operation::Admin(op) => op.operation(),
+            Operation::Archive(op) => op.operation(),
+            Operation::BidNet(op) => op.operation(),
+            Operation::Canary(op) => op.operation(),
+            Operation::Duplicates(op) => op.operation(),
+            Operation::Export(op) => op.operation(),
+            Operation::FlVbs(op) => op.operation(),
+            Operation::Ingest(op) => op.operation(),
+            Operation::Mail(op) => op.operation(),
+            Operation::Merx(op) => op.operation(),
+            Operation::PublicPurchase(op) => op.operation(),
+            Operation::Reconcile(op) => op.operation(),
+            Operation::Rules(op) => op.operation(),
+            Operation::Schedule(op) => op.operation(),
+            Operation::TestPortal(op) => op.operation(),
+            Operation::Vendors(op) => op.operation(),
+            Operation::WaLocal(op) => op.operation(),
             Operation::Webs(op) => op.operation(),
         }
     }
@@ -189,39 +802,196 @@ impl Serialize for Operation {
 }
 
 impl CrawlParameters {
-    /// Create a new Reqwest [ClientBuilder] with the appropriate settings from the crawl parameters.
+    /// Return the parameters for a request scheduled by the operation running with these parameters.
+    ///
+    /// The crawl id, user agent, cookies, extra cookies, headers, request interval, account, connection settings,
+    /// locale, and referer chaining are inherited unchanged. The depth is incremented, the budget, if any, is
+    /// decremented, the attempt count and history start over, and the referer and opportunity URL are dropped. Use
+    /// [`with_cookies`][CrawlParameters::with_cookies] to replace the inherited cookies with the session the operation
+    /// ended up with.
+    pub fn child(&self) -> Self {
+        Self {
+            crawl_id: self.crawl_id.clone(),
+            user_agent: self.user_agent.clone(),
+            cookies: self.cookies.clone(),
+            extra_cookies: self.extra_cookies.clone(),
+            headers: self.headers.clone(),
+            request_interval_ms: self.request_interval_ms,
+            depth: self.depth.saturating_add(1),
+            budget: self.budget.map(|budget| budget.saturating_sub(1)),
+            account: self.account.clone(),
+            tenant: self.tenant.clone(),
+            address_family: self.address_family,
+            connect_timeout_ms: self.connect_timeout_ms,
+            locale: self.locale.clone(),
+            posted_since: self.posted_since,
+            attempt: 0,
+            failed_attempts: vec![],
+            referer_chain: self.referer_chain,
+            referer: None,
+            opportunity_url: None,
+        }
+    }
+
+    /// Return the parameters for re-enqueueing this request after `failure`: unchanged apart from the attempt count,
+    /// which is incremented, and the history of failed attempts, to which `failure` is appended.
+    pub fn retry(&self, failure: FailedAttempt) -> Self {
+        let mut failed_attempts = self.failed_attempts.clone();
+        failed_attempts.push(failure);
+        Self {
+            attempt: self.attempt.saturating_add(1),
+            failed_attempts,
+            ..self.clone()
+        }
+    }
+
+    /// Replace the cookies.
+    pub fn with_cookies(mut self, cookies: CookieStore) -> Self {
+        self.cookies = cookies;
+        self
+    }
+
+    /// Set the URL of the opportunity a document is being fetched for.
+    pub fn with_opportunity_url(mut self, opportunity_url: impl Into<String>) -> Self {
+        self.opportunity_url = Some(opportunity_url.into());
+        self
+    }
+
+    /// Replace the account.
+    pub fn with_account(mut self, account: Option<String>) -> Self {
+        self.account = account;
+        self
+    }
+
+    /// Indicates whether the budget is used up, so no further requests should be scheduled.
+    pub fn budget_exhausted(&self) -> bool {
+        self.budget == Some(0)
+    }
+
+    /// Create a new Reqwest [ClientBuilder] with the appropriate settings from the crawl parameters, and the connection
+    /// and protocol settings of the deployment.
     pub fn build_client(&self, log_config: LogConfig, context: &Context) -> ClientBuilder {
-        let cookie_store = Arc::new(CookieStoreRwLock::from(self.cookies.clone()));
+        let mut cookies = self.cookies.clone();
+        cookies.inject(&self.extra_cookies);
+        let cookie_store = Arc::new(CookieStoreRwLock::from(cookies));
 
         let crawl_id = match self.crawl_id.as_ref() {
             Some(crawl_id) => crawl_id.clone(),
             None => {
-                info!("Assigning new crawl ID from Lambda context: {}", context.request_id);
+                // Dispatch registers a crawl for every request that lacks one, so this only happens when a client is
+                // built outside of dispatch.
+                warn!("No crawl ID in crawl parameters; using Lambda request ID {}", context.request_id);
                 context.request_id.clone()
             }
         };
 
-        let builder = reqwest::ClientBuilder::new()
+        let mut headers = default_headers();
+        if let Some(accept_language) = self.locale.accept_language.as_deref() {
+            match HeaderValue::from_str(accept_language) {
+                Ok(value) => {
+                    headers.insert(ACCEPT_LANGUAGE, value);
+                }
+                Err(_) => warn!("Ignoring invalid Accept-Language {accept_language:?}"),
+            }
+        }
+
+        if let Some(referer) = self.referer.as_deref().filter(|_| self.referer_chain) {
+            match HeaderValue::from_str(referer) {
+                Ok(value) => {
+                    headers.insert(REFERER, value);
+                }
+                Err(_) => warn!("Ignoring invalid referer {referer:?}"),
+            }
+        }
+
+        // Explicit headers take precedence over the locale and referer.
+        for (name, value) in self.headers.iter() {
+            match (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+                (Ok(name), Ok(value)) => {
+                    headers.insert(name, value);
+                }
+                _ => warn!("Ignoring invalid crawl header {name}: {value:?}"),
+            }
+        }
+
+        let builder = log_config
+            .transport
+            .apply(reqwest::ClientBuilder::new())
             .user_agent(self.user_agent.as_str())
-            .default_headers(default_headers())
+            .default_headers(headers)
             .cookie_provider(cookie_store.clone())
             .deflate(true)
             .gzip(true)
             .brotli(true)
+            .zstd(true)
             .redirect(RedirectPolicy::limited(DEFAULT_REDIRECT_LIMIT));
+        let builder = match log_config.dns_resolver.as_ref() {
+            Some(resolver) => builder.dns_resolver(resolver.clone()),
+            None => builder,
+        };
+        let connect_timeout =
+            self.connect_timeout_ms.map(Duration::from_millis).or(log_config.transport.connect_timeout);
+        let builder = match connect_timeout {
+            Some(connect_timeout) => builder.connect_timeout(connect_timeout),
+            None => builder,
+        };
 
-        ClientBuilder {
+        let client_builder = ClientBuilder {
             builder,
             log_config: Some(log_config),
             crawl_id,
             cookie_store,
+            portal: None,
+            stats: Arc::new(CrawlStats::default()),
+            allowed_hosts: Arc::new(HostAllowlist::default()),
+            pacer: Arc::new(RequestPacer::new(Duration::from_millis(self.request_interval_ms.unwrap_or_default()))),
+            account: self.account.clone(),
+            tenant: self.tenant.clone(),
+            attempt: self.attempt,
+            address_family: AddressFamily::Any,
+            connect_timeout,
+        };
+
+        if self.address_family.is_any() {
+            client_builder
+        } else {
+            client_builder.address_family(self.address_family)
         }
     }
+
+    /// Create a [ClientBuilder] for crawling `portal` from `url`, allowed to reach only the hosts of `url` and of the
+    /// portal's `default_base_url`.
+    pub fn portal_client(
+        &self,
+        log_config: &LogConfig,
+        context: &Context,
+        portal: &str,
+        default_base_url: &str,
+        url: &Url,
+    ) -> Result<ClientBuilder, BoxError> {
+        let default_url = Url::parse(default_base_url)?;
+        Ok(self
+            .build_client(log_config.clone(), context)
+            .portal(portal)
+            .allowed_hosts(HostAllowlist::from_urls([url, &default_url])))
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{shapes::Operation, webs::WebsOperation};
+    use {
+        crate::{
+            config::Locale,
+            httpext::{AddressFamily, CrawlCookie},
+            opportunity::DateLocale,
+            retry::FailedAttempt,
+            shapes::{normalize_url, CrawlParameters, ListingQuery, Operation},
+            testportal::TestPortalOperation,
+            webs::WebsOperation,
+        },
+        chrono::NaiveDate,
+        reqwest::Url,
+    };
 
     /// Check the serialization of operations.
     #[test]
@@ -229,5 +999,141 @@ mod test {
         let op = Operation::Webs(WebsOperation::StartCrawl);
         let op = serde_json::to_string(&op).unwrap();
         assert_eq!(op.as_str(), r#""Webs:StartCrawl""#);
+
+        let op: Operation = serde_json::from_str(r#""Canary:Check""#).unwrap();
+        assert_eq!(op.to_string().as_str(), "Canary:Check");
+
+        let op: Operation = serde_json::from_str(r#""Reconcile:CloseStale""#).unwrap();
+        assert_eq!(op.to_string().as_str(), "Reconcile:CloseStale");
+
+        let op: Operation = serde_json::from_str(r#""Schedule:Tune""#).unwrap();
+        assert_eq!(op.to_string().as_str(), "Schedule:Tune");
+
+        let op: Operation = serde_json::from_str(r#""Admin:HealthCheck""#).unwrap();
+        assert_eq!(op.to_string().as_str(), "Admin:HealthCheck");
+    }
+
+    /// Check URL canonicalization.
+    #[test]
+    fn normalize() {
+        assert_eq!(
+            normalize_url("HTTPS://Example.COM/a/./b/../c?z=1&a=2&PHPSESSID=x#frag").unwrap(),
+            "https://example.com/a/c?a=2&z=1"
+        );
+        assert_eq!(
+            normalize_url("https://example.com/Search.aspx;jsessionid=ABC?sid=1").unwrap(),
+            "https://example.com/Search.aspx?sid=1"
+        );
+        assert_eq!(
+            normalize_url("https://example.com/x?b=2&a=1").unwrap(),
+            normalize_url("https://example.com/x?a=1&b=2").unwrap()
+        );
+        assert_eq!(normalize_url("https://example.com/x?b=1&a=2&a=1").unwrap(), "https://example.com/x?a=2&a=1&b=1");
+        assert!(normalize_url("not a url").is_err());
+    }
+
+    /// Check the encoding of listing queries.
+    #[test]
+    fn listing_queries() {
+        let base = Url::parse("https://example.gov/bids?status=open&page=3&type=a&type=b").unwrap();
+        let query =
+            ListingQuery::new().page("page", 4).page_size("size", 50).filter("q", "road & bridge").page("page", 5);
+        assert_eq!(
+            query.url(&base).as_str(),
+            "https://example.gov/bids?page=5&q=road+%26+bridge&size=50&status=open&type=a&type=b"
+        );
+
+        let unfiltered = Url::parse("https://example.gov/bids").unwrap();
+        assert_eq!(ListingQuery::new().url(&unfiltered).as_str(), "https://example.gov/bids");
+        assert_eq!(ListingQuery::new().offset("offset", 25).url(&unfiltered).query(), Some("offset=25"));
+
+        let crawl = CrawlParameters {
+            depth: 1,
+            ..CrawlParameters::default()
+        };
+        let next = ListingQuery::new().cursor("cursor", "a/b").next_request(
+            Operation::TestPortal(TestPortalOperation::FetchListingPage),
+            &unfiltered,
+            &crawl,
+        );
+        assert_eq!(next.url.as_deref(), Some("https://example.gov/bids?cursor=a%2Fb"));
+        assert_eq!(next.crawl.depth, 2);
+    }
+
+    /// Check what child parameters inherit.
+    #[test]
+    fn child_parameters() {
+        let parent = CrawlParameters {
+            crawl_id: Some("crawl".to_string()),
+            user_agent: "agent".to_string(),
+            extra_cookies: vec![CrawlCookie {
+                name: "Consent".to_string(),
+                value: "yes".to_string(),
+                domain: "example.gov".to_string(),
+                path: "/".to_string(),
+            }],
+            headers: [("X-Test".to_string(), "1".to_string())].into(),
+            request_interval_ms: Some(250),
+            depth: 2,
+            budget: Some(1),
+            account: Some("1".to_string()),
+            tenant: Some("Analytics".to_string()),
+            address_family: AddressFamily::Ipv4,
+            connect_timeout_ms: Some(2000),
+            locale: Locale {
+                accept_language: Some("fr-CA".to_string()),
+                dates: DateLocale::French,
+                ..Locale::default()
+            },
+            posted_since: NaiveDate::from_ymd_opt(2024, 5, 1),
+            referer_chain: true,
+            referer: Some("https://example.gov/bids".to_string()),
+            ..CrawlParameters::default()
+        };
+
+        let child = parent.child();
+        assert_eq!(child.crawl_id.as_deref(), Some("crawl"));
+        assert_eq!(child.user_agent, "agent");
+        assert_eq!(child.extra_cookies, parent.extra_cookies);
+        assert_eq!(child.headers, parent.headers);
+        assert_eq!(child.request_interval_ms, Some(250));
+        assert_eq!(child.depth, 3);
+        assert_eq!(child.account.as_deref(), Some("1"));
+        assert_eq!(child.tenant.as_deref(), Some("Analytics"));
+        assert_eq!(child.address_family, AddressFamily::Ipv4);
+        assert_eq!(child.connect_timeout_ms, Some(2000));
+        assert_eq!(child.locale, parent.locale);
+        assert_eq!(child.posted_since, parent.posted_since);
+        assert!(child.referer_chain);
+        assert_eq!(child.referer, None);
+        assert_eq!(child.budget, Some(0));
+        assert!(child.budget_exhausted());
+        assert_eq!(child.child().budget, Some(0));
+
+        // Retries keep everything but the attempt count; children start over.
+        let failure = |condition: &str| FailedAttempt {
+            condition: condition.to_string(),
+            error: format!("{condition} at https://example.com/"),
+            failed_at: 1_700_000_000,
+        };
+        let retry = child.retry(failure("WebsMaintenance")).retry(failure("WebsServerTooBusy"));
+        assert_eq!((retry.depth, retry.budget, retry.attempt), (3, Some(0), 2));
+        assert_eq!(
+            retry.failed_attempts.iter().map(|a| a.condition.as_str()).collect::<Vec<_>>(),
+            ["WebsMaintenance", "WebsServerTooBusy"]
+        );
+        assert_eq!((retry.child().attempt, retry.child().failed_attempts.len()), (0, 0));
+
+        // Document requests name their opportunity; their children don't.
+        let document = child.child().with_opportunity_url("https://example.com/bid/1");
+        assert_eq!(document.opportunity_url.as_deref(), Some("https://example.com/bid/1"));
+        assert_eq!(document.child().opportunity_url, None);
+
+        // Unlimited budgets stay unlimited, and defaulted fields are omitted from messages.
+        let json = serde_json::to_string(&CrawlParameters::default().child()).unwrap();
+        assert!(!json.contains("Budget") && !json.contains("Headers") && !json.contains("Attempt"));
+        assert!(!json.contains("AddressFamily") && !json.contains("ConnectTimeoutMs") && !json.contains("Locale"));
+        assert!(!json.contains("Tenant"));
+        assert!(json.contains(r#""Depth":1"#));
     }
 }
@@ -307,6 +307,7 @@ mod find;
 mod node_ext;
 pub mod pattern;
 mod qb_ext;
+pub mod structured_data;
 
 pub use self::{find::QueryBuilder, node_ext::NodeExt, qb_ext::QueryBuilderExt};
 
@@ -11,7 +11,7 @@ pub trait NodeExt: Sized {
     /// Returns `true` if node is of type Document
     fn is_document(&self) -> bool {
         let node = self.get_node();
-        matches!(node.data, NodeData::Document { .. })
+        matches!(node.data, NodeData::Document)
     }
 
     /// Returns `true` if node is of type Doctype
@@ -57,9 +57,7 @@ pub trait NodeExt: Sized {
     fn name(&self) -> &str {
         let node = self.get_node();
         match node.data {
-            NodeData::Document {
-                ..
-            } => "[document]",
+            NodeData::Document => "[document]",
             NodeData::Doctype {
                 ..
             } => "[doctype]",
@@ -220,7 +218,7 @@ impl NodeExt for Handle {
     }
 }
 
-impl<'node> NodeExt for &'node Node {
+impl NodeExt for &Node {
     #[inline(always)]
     fn get_node(&self) -> &Node {
         self
@@ -37,7 +37,7 @@ impl Pattern for bool {
     }
 }
 
-impl<'a> Pattern for &'a str {
+impl Pattern for &str {
     fn matches(&self, haystack: &str) -> bool {
         *self == haystack
     }
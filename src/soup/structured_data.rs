@@ -0,0 +1,217 @@
+//! Structured data embedded in pages: JSON-LD blocks and microdata.
+//!
+//! Both forms are returned as JSON-LD style objects, so callers read them the same way: each item is a JSON object
+//! whose `@type` is the item's type name (e.g. `Demand` for `https://schema.org/Demand`) and whose other keys are its
+//! properties. A microdata property given more than once becomes an array, and a nested item becomes a nested object.
+
+use {
+    super::{NodeExt, QueryBuilderExt},
+    markup5ever_rcdom::{Handle, RcDom},
+    serde_json::{Map, Value},
+};
+
+/// The media type of JSON-LD script blocks.
+const JSON_LD_TYPE: &str = "application/ld+json";
+
+/// The key JSON-LD lists several top-level items under.
+const JSON_LD_GRAPH: &str = "@graph";
+
+/// The key holding an item's type.
+const JSON_LD_TYPE_KEY: &str = "@type";
+
+/// Return the items described by a document's JSON-LD blocks, then those described by its microdata, in document
+/// order.
+///
+/// JSON-LD blocks that aren't valid JSON are ignored, and top-level arrays and `@graph` lists are flattened into their
+/// items.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate soup;
+/// use soup::{prelude::*, structured_data::structured_data};
+///
+/// let soup = Soup::new(r#"<div itemscope itemtype="https://schema.org/Demand"><h1 itemprop="name">Paving</h1></div>"#);
+/// let items = structured_data(&soup);
+/// assert_eq!(items[0]["@type"], "Demand");
+/// assert_eq!(items[0]["name"], "Paving");
+/// ```
+pub fn structured_data(document: &RcDom) -> Vec<Value> {
+    let mut items = Vec::new();
+
+    for script in document.document.tag("script").find_all() {
+        if !script.get("type").is_some_and(|kind| kind.trim().eq_ignore_ascii_case(JSON_LD_TYPE)) {
+            continue;
+        }
+
+        if let Ok(value) = serde_json::from_str(&script.text()) {
+            flatten_json_ld(value, &mut items);
+        }
+    }
+
+    collect_microdata(&document.document, &mut items);
+    items
+}
+
+/// Return the type names of an item, e.g. `["Demand"]`.
+pub fn item_types(item: &Value) -> Vec<&str> {
+    match item.get(JSON_LD_TYPE_KEY) {
+        Some(Value::String(kind)) => vec![type_name(kind)],
+        Some(Value::Array(kinds)) => kinds.iter().filter_map(Value::as_str).map(type_name).collect(),
+        _ => vec![],
+    }
+}
+
+/// Return the name of a type given as a URL, e.g. `Demand` for `https://schema.org/Demand`.
+fn type_name(kind: &str) -> &str {
+    kind.trim().rsplit(['/', '#']).next().unwrap_or(kind)
+}
+
+/// Add the items of a JSON-LD block to `items`.
+fn flatten_json_ld(value: Value, items: &mut Vec<Value>) {
+    match value {
+        Value::Array(values) => values.into_iter().for_each(|value| flatten_json_ld(value, items)),
+        Value::Object(mut object) => match object.remove(JSON_LD_GRAPH) {
+            Some(graph) => flatten_json_ld(graph, items),
+            None => items.push(Value::Object(object)),
+        },
+        _ => (),
+    }
+}
+
+/// Add the top-level microdata items within `node` to `items`.
+fn collect_microdata(node: &Handle, items: &mut Vec<Value>) {
+    for child in node.children.borrow().iter() {
+        if child.is_element() && child.get("itemscope").is_some() && child.get("itemprop").is_none() {
+            items.push(microdata_item(child));
+        } else {
+            collect_microdata(child, items);
+        }
+    }
+}
+
+/// Return the item an `itemscope` element describes.
+fn microdata_item(element: &Handle) -> Value {
+    let mut item = Map::new();
+
+    if let Some(itemtype) = element.get("itemtype") {
+        let mut kinds: Vec<Value> = itemtype.split_ascii_whitespace().map(|kind| type_name(kind).into()).collect();
+        match kinds.len() {
+            0 => (),
+            1 => {
+                item.insert(JSON_LD_TYPE_KEY.to_string(), kinds.remove(0));
+            }
+            _ => {
+                item.insert(JSON_LD_TYPE_KEY.to_string(), Value::Array(kinds));
+            }
+        }
+    }
+
+    collect_properties(element, &mut item);
+    Value::Object(item)
+}
+
+/// Add the properties given by the descendants of `node`, stopping at nested items, to `item`.
+fn collect_properties(node: &Handle, item: &mut Map<String, Value>) {
+    for child in node.children.borrow().iter() {
+        if !child.is_element() {
+            continue;
+        }
+
+        let nested = child.get("itemscope").is_some();
+        if let Some(names) = child.get("itemprop") {
+            let value = if nested {
+                microdata_item(child)
+            } else {
+                Value::String(property_value(child))
+            };
+
+            for name in names.split_ascii_whitespace() {
+                add_property(item, name, value.clone());
+            }
+        }
+
+        if !nested {
+            collect_properties(child, item);
+        }
+    }
+}
+
+/// Add a value of a property to an item, making the property an array if it already has a value.
+fn add_property(item: &mut Map<String, Value>, name: &str, value: Value) {
+    match item.get_mut(name) {
+        None => {
+            item.insert(name.to_string(), value);
+        }
+        Some(Value::Array(values)) => values.push(value),
+        Some(existing) => {
+            let first = existing.take();
+            *existing = Value::Array(vec![first, value]);
+        }
+    }
+}
+
+/// Return the value of a microdata property element: the attribute the HTML standard assigns its element type, or its
+/// text with whitespace collapsed.
+fn property_value(element: &Handle) -> String {
+    let attr = match element.name() {
+        "meta" => Some("content"),
+        "a" | "area" | "link" => Some("href"),
+        "audio" | "embed" | "iframe" | "img" | "source" | "track" | "video" => Some("src"),
+        "object" => Some("data"),
+        "data" | "meter" => Some("value"),
+        "time" => Some("datetime"),
+        _ => None,
+    };
+
+    match attr.and_then(|attr| element.get(attr)) {
+        Some(value) => value.trim().to_string(),
+        None => element.text().split_whitespace().collect::<Vec<_>>().join(" "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{item_types, structured_data},
+        crate::soup::parse_html_str,
+        serde_json::json,
+    };
+
+    #[test]
+    fn json_ld_and_microdata() {
+        let document = parse_html_str(
+            r#"<script type="application/ld+json">{"@context": "https://schema.org", "@graph": [
+                   {"@type": "Organization", "name": "City of Olympia"},
+                   {"@type": ["Demand", "Thing"], "name": "Street Paving"}]}</script>
+               <script type="application/ld+json">not json</script>
+               <script>{"@type": "Ignored"}</script>
+               <div itemscope itemtype="https://schema.org/Demand">
+                 <h1 itemprop="name"> Striping
+                   Services </h1>
+                 <meta itemprop="identifier" content="22-015">
+                 <time itemprop="validThrough" datetime="2022-11-30T14:00:00-08:00">November 30</time>
+                 <a itemprop="subjectOf" href="/plans.pdf">Plans</a><a itemprop="subjectOf" href="/specs.pdf">Specs</a>
+                 <div itemprop="contactPoint" itemscope itemtype="https://schema.org/ContactPoint">
+                   <span itemprop="name">Pat Doe</span><span itemprop="email">bids@example.gov</span>
+                 </div>
+               </div>"#,
+        );
+
+        let items = structured_data(&document);
+        assert_eq!(items.len(), 3);
+        assert_eq!(item_types(&items[0]), ["Organization"]);
+        assert_eq!(item_types(&items[1]), ["Demand", "Thing"]);
+        assert_eq!(
+            items[2],
+            json!({
+                "@type": "Demand",
+                "name": "Striping Services",
+                "identifier": "22-015",
+                "validThrough": "2022-11-30T14:00:00-08:00",
+                "subjectOf": ["/plans.pdf", "/specs.pdf"],
+                "contactPoint": {"@type": "ContactPoint", "name": "Pat Doe", "email": "bids@example.gov"},
+            })
+        );
+    }
+}
@@ -0,0 +1,428 @@
+//! Request/response types for the test portal, a fake portal for exercising the crawler end to end.
+//!
+//! The test portal behaves like the real portals GovScout crawls: a crawl logs in, walks a paged listing, parses each
+//! opportunity's page, and downloads the documents published with it. Crawling it goes through the full pipeline
+//! (SQS fan-out, request logging, parsing, and opportunity writes) without touching a government site, so CI and load
+//! tests can run real crawls against it.
+//!
+//! The portal itself is `FakePortal`, an in-process mock server in the `portal` module, built with the `test-utils`
+//! feature. A crawl starts with `TestPortal:StartCrawl` and the portal's base URL. The portal accepts fixed
+//! credentials, so none are stored in SSM.
+mod detail;
+mod listing;
+
+/// The fake portal crawled by this subsystem.
+#[cfg(any(test, feature = "test-utils"))]
+pub mod portal;
+
+use {
+    crate::{
+        attachment, closure, contacts,
+        flags::Flag,
+        httpext::{Client, Form, HostAllowlist, LogConfig, Response as HttpResponse, ResponseExt},
+        journal::CrawlEvent,
+        lifecycle,
+        opportunity::Opportunity,
+        quality,
+        shapes::{CrawlParameters, ListingQuery, NextRequest, Operation, Request, Response, SUBSYS_TEST_PORTAL},
+        soup::parse_html_str,
+        BoxError,
+    },
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    reqwest::Url,
+    serde::{Deserialize, Serialize},
+    std::{
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+const LOGIN_PATH: &str = "login";
+const LISTING_PATH: &str = "opportunities";
+const PAGE_PARAM: &str = "page";
+
+const LISTING_TABLE_SUMMARY: &str = "Open opportunities";
+const DETAIL_TABLE_SUMMARY: &str = "Opportunity details";
+const DOCUMENTS_TABLE_SUMMARY: &str = "Documents";
+
+const FORM_NAME_LOGIN: &str = "loginForm";
+const FORM_FIELD_USERNAME: &str = "username";
+const FORM_FIELD_PASSWORD: &str = "password";
+
+/// The credentials the test portal accepts.
+const USERNAME: &str = "govscout";
+const PASSWORD: &str = "test-portal";
+
+/// Phrases on a page that show the login form or a refused login rather than what was requested.
+const LOGIN_REQUIRED_PHRASES: &[&str] = &["invalid username or password", r#"name="loginform""#];
+
+const OP_START_CRAWL: &str = "StartCrawl";
+const OP_FETCH_LISTING_PAGE: &str = "FetchListingPage";
+const OP_FETCH_OPPORTUNITY_PAGE: &str = "FetchOpportunityPage";
+const OP_FETCH_DOCUMENT: &str = "FetchDocument";
+
+/// Version of this subsystem's parsers. Bump it whenever a parser change alters what is extracted.
+pub(crate) const PARSER_VERSION: u32 = 1;
+
+const PARSER_LISTING: &str = "TestPortal:Listing";
+const PARSER_OPPORTUNITY: &str = "TestPortal:Opportunity";
+
+/// Phrases shown in place of an opportunity the portal no longer publishes; see [`closure`][crate::closure].
+const NOT_FOUND_MARKERS: &[&str] = &["opportunity not found"];
+
+/// Possible operations for the test portal.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum TestPortalOperation {
+    /// Log in to the portal at the given base URL and start a crawl from the first page of listings.
+    StartCrawl,
+
+    /// Fetch a page of listings.
+    FetchListingPage,
+
+    /// Fetch an opportunity page.
+    FetchOpportunityPage,
+
+    /// Download a document published with an opportunity.
+    FetchDocument,
+}
+
+impl FromStr for TestPortalOperation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            OP_START_CRAWL => Ok(Self::StartCrawl),
+            OP_FETCH_LISTING_PAGE => Ok(Self::FetchListingPage),
+            OP_FETCH_OPPORTUNITY_PAGE => Ok(Self::FetchOpportunityPage),
+            OP_FETCH_DOCUMENT => Ok(Self::FetchDocument),
+            _ => Err(format!("Unknown operation: {value}")),
+        }
+    }
+}
+
+impl Display for TestPortalOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.operation())
+    }
+}
+
+impl TestPortalOperation {
+    /// Handle a request.
+    pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+        match self {
+            Self::StartCrawl => start_crawl(log_config, req, context).await,
+            Self::FetchListingPage => fetch_listing_page(log_config, req, context).await,
+            Self::FetchOpportunityPage => fetch_opportunity_page(log_config, req, context).await,
+            Self::FetchDocument => fetch_document(log_config, req, context).await,
+        }
+    }
+
+    /// Return the operation name within the subsystem.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::StartCrawl => OP_START_CRAWL,
+            Self::FetchListingPage => OP_FETCH_LISTING_PAGE,
+            Self::FetchOpportunityPage => OP_FETCH_OPPORTUNITY_PAGE,
+            Self::FetchDocument => OP_FETCH_DOCUMENT,
+        }
+    }
+}
+
+/// Return the URL of the listings of the portal at `base_url`, without a query.
+fn listing_url(base_url: &Url) -> Result<Url, BoxError> {
+    Ok(base_url.join(&format!("/{LISTING_PATH}"))?)
+}
+
+/// Return the query of the first page of listings.
+fn first_listing_query() -> ListingQuery {
+    ListingQuery::new().page(PAGE_PARAM, 1)
+}
+
+/// Build a client for crawling the test portal at `url`.
+fn build_client(
+    log_config: &LogConfig,
+    crawl: &CrawlParameters,
+    context: &Context,
+    url: &Url,
+) -> Result<Client, BoxError> {
+    Ok(crawl
+        .build_client(log_config.clone(), context)
+        .portal(SUBSYS_TEST_PORTAL)
+        .allowed_hosts(HostAllowlist::from_urls([url]))
+        .build()?)
+}
+
+/// Log in and schedule the first page of listings.
+async fn start_crawl(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let base_url = req.required_url()?;
+    let client = build_client(&log_config, &req.crawl, &context, &base_url)?;
+    log_in(&client, &base_url).await?;
+
+    let mut cookies = client.cookie_store.snapshot();
+    cookies.retain_domain(&base_url);
+
+    let mut first_page = first_listing_query().next_request(
+        Operation::TestPortal(TestPortalOperation::FetchListingPage),
+        &listing_url(&base_url)?,
+        &req.crawl,
+    );
+    first_page.crawl = first_page.crawl.with_cookies(cookies);
+
+    Ok(Response {
+        next_requests: vec![first_page],
+    })
+}
+
+/// Log in to the test portal at `base_url`, leaving the session in the client's cookie store.
+async fn log_in(client: &Client, base_url: &Url) -> Result<(), BoxError> {
+    let login_url = base_url.join(&format!("/{LOGIN_PATH}"))?;
+    let response = match client.get(login_url.clone()).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch test portal login page: {e}");
+            return Err(e);
+        }
+    };
+
+    let mut form = Form::from_unparsed_form_name(&login_url, response.text()?, FORM_NAME_LOGIN)?;
+    form.set(FORM_FIELD_USERNAME, USERNAME);
+    form.set(FORM_FIELD_PASSWORD, PASSWORD);
+
+    info!("Submitting test portal login");
+    let response = match client.request(form.method, form.url).form(&form.fields).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to submit test portal login form: {e}");
+            return Err(e);
+        }
+    };
+
+    if login_required(response.text().unwrap_or_default()) {
+        return Err("Test portal refused the login".into());
+    }
+
+    Ok(())
+}
+
+/// Indicates whether a page shows the login form or a refused login rather than what was requested.
+fn login_required(text: &str) -> bool {
+    let lower = text.to_lowercase();
+    LOGIN_REQUIRED_PHRASES.iter().any(|phrase| lower.contains(phrase))
+}
+
+/// Fetch a page with the crawl's session, failing if the portal asks to log in again.
+async fn fetch_page(client: &Client, url: &Url, what: &str) -> Result<HttpResponse, BoxError> {
+    let response = match client.get(url.clone()).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch test portal {what}: {e}");
+            return Err(e);
+        }
+    };
+
+    if login_required(response.text().unwrap_or_default()) {
+        return Err(format!("Test portal session expired fetching {what} {url}").into());
+    }
+
+    Ok(response)
+}
+
+/// Record the outcome of parsing a page in the journal.
+async fn record_parse<T>(
+    log_config: &LogConfig,
+    client: &Client,
+    url: &Url,
+    parser: &str,
+    result: &Result<T, BoxError>,
+    items: impl FnOnce(&T) -> usize,
+) {
+    let event = match result {
+        Ok(value) => CrawlEvent::ParseSucceeded {
+            url: url.to_string(),
+            parser: parser.to_string(),
+            items: items(value),
+        },
+        Err(e) => CrawlEvent::ParseFailed {
+            url: url.to_string(),
+            parser: parser.to_string(),
+            error: e.to_string(),
+        },
+    };
+    log_config.journal.record(&client.crawl_id, event).await;
+}
+
+/// Fetch a page of listings, returning requests for its opportunities and the next page.
+async fn fetch_listing_page(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let url = req.required_url()?;
+    let client = build_client(&log_config, &req.crawl, &context, &url)?;
+    let response = fetch_page(&client, &url, "listing").await?;
+
+    let document = parse_html_str(response.text()?);
+    let result = listing::parse_listing_page(&document, response.url());
+    record_parse(&log_config, &client, response.url(), PARSER_LISTING, &result, Vec::len).await;
+
+    let mut next_requests: Vec<NextRequest> = result?
+        .into_iter()
+        .map(|url| NextRequest {
+            operation: Operation::TestPortal(TestPortalOperation::FetchOpportunityPage),
+            url: Some(url.to_string()),
+            link_text: None,
+            crawl: req.crawl.child(),
+            delay_seconds: 0,
+        })
+        .collect();
+
+    next_requests.extend(listing::PAGINATION.continuation(
+        Operation::TestPortal(TestPortalOperation::FetchListingPage),
+        &document,
+        response.url(),
+        &req.crawl,
+    ));
+
+    Ok(Response {
+        next_requests,
+    })
+}
+
+/// Fetch an opportunity page, parse it into the normalized opportunity model, and return requests for its documents.
+async fn fetch_opportunity_page(
+    log_config: LogConfig,
+    req: Request,
+    context: Context,
+) -> Result<Response, LambdaError> {
+    let url = req.required_url()?;
+    let client = build_client(&log_config, &req.crawl, &context, &url)?;
+    let response = fetch_page(&client, &url, "opportunity").await?;
+
+    let Some(response) = attachment::html_page(&log_config, &req.crawl, response, Some(url.as_str())).await else {
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    };
+
+    if let Some(reason) =
+        closure::soft_not_found(response.text()?, NOT_FOUND_MARKERS, &req.crawl.locale.not_found_markers)
+    {
+        closure::record_closed(&log_config, &client, SUBSYS_TEST_PORTAL, url.as_str(), &reason).await?;
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    }
+
+    let document = parse_html_str(response.text()?);
+    let result = detail::parse_opportunity_page(&document, response.url(), req.crawl.locale.dates);
+    record_parse(&log_config, &client, response.url(), PARSER_OPPORTUNITY, &result, |_| 1).await;
+
+    let mut opportunity: Opportunity =
+        result?.with_parser(PARSER_OPPORTUNITY, PARSER_VERSION).with_tenant(client.tenant.as_deref());
+    contacts::record_contacts(&log_config, &client, &mut opportunity, response.text()?).await;
+    lifecycle::record_stage(&log_config, &client, &mut opportunity, response.text()?).await;
+    quality::record_quality(&log_config, &client, &mut opportunity).await;
+    closure::record_seen(&log_config, &client, &opportunity).await;
+    info!("Test portal opportunity: {}", serde_json::to_string(&opportunity)?);
+
+    let document_urls: Vec<&str> = opportunity.documents.iter().map(|document| document.url.as_str()).collect();
+    attachment::prune_attachments(&log_config, &opportunity.url, &document_urls).await;
+
+    let next_requests = opportunity
+        .documents
+        .iter()
+        .map(|document| NextRequest {
+            operation: Operation::TestPortal(TestPortalOperation::FetchDocument),
+            url: Some(document.url.clone()),
+            link_text: Some(document.name.clone()),
+            crawl: req.crawl.child().with_opportunity_url(&opportunity.url),
+            delay_seconds: 0,
+        })
+        .collect();
+
+    Ok(Response {
+        next_requests,
+    })
+}
+
+/// Download a document published with an opportunity and link its archived body to the opportunity.
+async fn fetch_document(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let url = req.required_url()?;
+    if !log_config.flags.is_enabled(Flag::AttachmentDownloads) {
+        info!("Attachment downloads are disabled; not fetching test portal document {url}");
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    }
+
+    let client = build_client(&log_config, &req.crawl, &context, &url)?;
+    let response = fetch_page(&client, &url, "document").await?;
+    attachment::record_attachment(&log_config, &req.crawl, SUBSYS_TEST_PORTAL, url.as_str(), &response).await;
+    info!("Downloaded test portal document {} ({} bytes)", response.url(), response.content_length());
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            detail::parse_opportunity_page,
+            fetch_page, first_listing_query, listing, listing_url, log_in,
+            portal::{FakePortal, PortalShape},
+        },
+        crate::{
+            httpext::{ClientBuilder, ContentKind, CookieStoreRwLock, HostAllowlist},
+            opportunity::DateLocale,
+            soup::parse_html_str,
+        },
+        reqwest::Url,
+        std::sync::Arc,
+    };
+
+    /// Crawl the fake portal with the subsystem's own login, fetching, and parsing, as the operations chained through
+    /// SQS would.
+    #[tokio::test]
+    #[test_log::test]
+    async fn crawl_fake_portal() {
+        let shape = PortalShape {
+            listing_pages: 2,
+            per_page: 3,
+            documents_per_opportunity: 2,
+        };
+        let portal = FakePortal::start(shape).await;
+        let base_url = Url::parse(&portal.base_url()).unwrap();
+        let client = ClientBuilder::new(Arc::new(CookieStoreRwLock::default()), "test")
+            .allowed_hosts(HostAllowlist::from_urls([&base_url]))
+            .build()
+            .unwrap();
+
+        // Pages other than the login page need a session.
+        let first_page = first_listing_query().url(&listing_url(&base_url).unwrap());
+        assert!(fetch_page(&client, &first_page, "listing").await.unwrap_err().to_string().contains("session expired"));
+        log_in(&client, &base_url).await.unwrap();
+
+        let mut opportunities = Vec::new();
+        let mut next_page = Some(first_page);
+        while let Some(url) = next_page.take() {
+            let response = fetch_page(&client, &url, "listing").await.unwrap();
+            let document = parse_html_str(response.text().unwrap());
+            opportunities.extend(listing::parse_listing_page(&document, response.url()).unwrap());
+            next_page = listing::PAGINATION.next_url(&document, response.url());
+        }
+        assert_eq!(opportunities.len(), shape.opportunities());
+
+        let mut documents = 0;
+        for url in opportunities.iter() {
+            let response = fetch_page(&client, url, "opportunity").await.unwrap();
+            let opportunity =
+                parse_opportunity_page(&parse_html_str(response.text().unwrap()), url, DateLocale::English).unwrap();
+
+            for document in opportunity.documents.iter() {
+                let response = fetch_page(&client, &Url::parse(&document.url).unwrap(), "document").await.unwrap();
+                assert_eq!(response.content_kind(), ContentKind::Pdf);
+                documents += 1;
+            }
+        }
+        assert_eq!(documents, shape.documents());
+    }
+}
@@ -0,0 +1,160 @@
+//! Test portal opportunity page handling.
+//!
+//! The opportunity is a table of labelled rows (`<th>Title:</th><td>...</td>`), followed by a table of documents, in
+//! the style of the Florida Vendor Bid System. Multi-line values are separated by `<br>` tags.
+use {
+    super::{DETAIL_TABLE_SUMMARY, DOCUMENTS_TABLE_SUMMARY},
+    crate::{
+        award::{page_awards, US_DOLLARS},
+        opportunity::{
+            clean_text, parse_date, parse_date_time, text_lines, Contact, DateLocale, Document, Opportunity,
+        },
+        shapes::SUBSYS_TEST_PORTAL,
+        soup::{NodeExt, QueryBuilderExt},
+        BoxError,
+    },
+    log::*,
+    markup5ever_rcdom::{Handle, RcDom},
+    reqwest::Url,
+    std::collections::HashMap,
+};
+
+const LABEL_TITLE: &str = "Title";
+const LABEL_REFERENCE: &str = "Reference";
+const LABEL_ORGANIZATION: &str = "Organization";
+const LABEL_CONTACT: &str = "Contact";
+const LABEL_PUBLISHED: &str = "Published";
+const LABEL_DUE: &str = "Due";
+const LABEL_DESCRIPTION: &str = "Description";
+const LABEL_COMMODITY_CODES: &str = "Commodity Codes";
+
+/// Parse an opportunity page into the normalized model.
+///
+/// A page without a title is not an opportunity (usually the login page) and is an error.
+pub(crate) fn parse_opportunity_page(
+    document: &RcDom,
+    page_url: &Url,
+    date_locale: DateLocale,
+) -> Result<Opportunity, BoxError> {
+    let fields = labelled_fields(document);
+    let text = |label: &str| fields.get(label).and_then(|td| clean_text(&td.text()));
+    let lines = |label: &str| fields.get(label).map(text_lines).unwrap_or_default();
+
+    let Some(title) = text(LABEL_TITLE) else {
+        return Err(format!("Test portal opportunity title not found on {page_url}").into());
+    };
+
+    let due_at = text(LABEL_DUE).and_then(|due| {
+        let due_at = parse_date_time(&due, date_locale);
+        if due_at.is_none() {
+            warn!("Unparseable test portal due date {due:?} on {page_url}");
+        }
+        due_at
+    });
+
+    let description = lines(LABEL_DESCRIPTION);
+
+    Ok(Opportunity {
+        portal: SUBSYS_TEST_PORTAL.to_string(),
+        url: page_url.to_string(),
+        title,
+        reference: text(LABEL_REFERENCE),
+        organization: text(LABEL_ORGANIZATION),
+        contact: Contact::from_lines(&lines(LABEL_CONTACT)),
+        published_on: text(LABEL_PUBLISHED).and_then(|published| parse_date(&published, date_locale)),
+        due_at,
+        description: if description.is_empty() {
+            None
+        } else {
+            Some(description.join("\n"))
+        },
+        commodity_codes: lines(LABEL_COMMODITY_CODES),
+        documents: parse_documents(document, page_url),
+        awards: page_awards(document, date_locale, US_DOLLARS),
+        ..Opportunity::default()
+    })
+}
+
+/// Return the value cells of the opportunity table, keyed by their label without the trailing colon.
+fn labelled_fields(document: &RcDom) -> HashMap<String, Handle> {
+    let mut fields = HashMap::new();
+    let Some(table) = document.tag("table").attr("summary", DETAIL_TABLE_SUMMARY).find() else {
+        return fields;
+    };
+
+    for tr in table.tag("tr").find_all() {
+        let (Some(th), Some(td)) = (tr.tag("th").find(), tr.tag("td").find()) else {
+            continue;
+        };
+
+        if let Some(label) = clean_text(&th.text()) {
+            fields.insert(label.trim_end_matches(':').to_string(), td);
+        }
+    }
+
+    fields
+}
+
+/// Return the documents published with the opportunity.
+fn parse_documents(document: &RcDom, page_url: &Url) -> Vec<Document> {
+    let Some(table) = document.tag("table").attr("summary", DOCUMENTS_TABLE_SUMMARY).find() else {
+        return vec![];
+    };
+
+    table
+        .tag("a")
+        .find_all()
+        .filter_map(|a| {
+            let href = a.get("href")?;
+            let url = match page_url.join(&href) {
+                Ok(url) => url,
+                Err(e) => {
+                    warn!("Ignoring unparseable test portal document link {href}: {e}");
+                    return None;
+                }
+            };
+
+            Some(Document {
+                name: clean_text(&a.text()).unwrap_or_else(|| url.to_string()),
+                url: url.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::parse_opportunity_page,
+        crate::{
+            opportunity::DateLocale,
+            soup::parse_html_str,
+            testportal::portal::{detail_html, PortalShape},
+        },
+        reqwest::Url,
+    };
+
+    #[test]
+    fn opportunity_page() {
+        let url = Url::parse("http://127.0.0.1:8080/opportunities/7").unwrap();
+        let opportunity = parse_opportunity_page(
+            &parse_html_str(&detail_html(&PortalShape::default(), 7)),
+            &url,
+            DateLocale::English,
+        )
+        .unwrap();
+
+        assert_eq!(opportunity.title, "Test opportunity 7");
+        assert_eq!(opportunity.reference.as_deref(), Some("TP-00007"));
+        assert_eq!(opportunity.organization.as_deref(), Some("Department of Testing"));
+        assert_eq!(opportunity.contact.email.as_deref(), Some("pat.example@example.gov"));
+        assert!(opportunity.published_on.is_some());
+        assert!(opportunity.due_at.is_some_and(|due_at| due_at.date() > opportunity.published_on.unwrap()));
+        assert_eq!(opportunity.description.unwrap().lines().count(), 2);
+        assert_eq!(opportunity.commodity_codes, vec!["43000007"]);
+        assert_eq!(opportunity.documents.len(), 2);
+        assert_eq!(opportunity.documents[1].url, "http://127.0.0.1:8080/opportunities/7/documents/2.pdf");
+
+        assert!(parse_opportunity_page(&parse_html_str("<p>Log in</p>"), &url, DateLocale::English).is_err());
+    }
+}
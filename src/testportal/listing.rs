@@ -0,0 +1,74 @@
+//! Test portal listing handling.
+//!
+//! Listings are a table with one opportunity per row, the title linking to the opportunity's page. Each page but the
+//! last hands out an opaque cursor token for the next, as paged APIs do.
+use {
+    super::{LISTING_PATH, LISTING_TABLE_SUMMARY},
+    crate::{
+        pagination::Pagination,
+        soup::{NodeExt, QueryBuilderExt},
+        BoxError,
+    },
+    log::*,
+    markup5ever_rcdom::RcDom,
+    reqwest::Url,
+};
+
+/// Each page hands out a cursor token for the next in a hidden `cursor` input.
+pub(crate) const PAGINATION: Pagination = Pagination::Cursor {
+    param: "cursor",
+    input_name: "cursor",
+};
+
+/// Return the URLs of the opportunities listed on a page, in order.
+pub(crate) fn parse_listing_page(document: &RcDom, page_url: &Url) -> Result<Vec<Url>, BoxError> {
+    let Some(table) = document.tag("table").attr("summary", LISTING_TABLE_SUMMARY).find() else {
+        return Err(format!("Test portal listing table not found on {page_url}").into());
+    };
+
+    let prefix = format!("/{LISTING_PATH}/");
+    let mut urls = Vec::new();
+    for a in table.tag("a").find_all() {
+        let Some(href) = a.get("href") else {
+            continue;
+        };
+
+        match page_url.join(&href) {
+            Ok(url) if url.path().starts_with(&prefix) => urls.push(url),
+            Ok(_) => debug!("Ignoring non-opportunity link {href} in test portal listing"),
+            Err(e) => warn!("Ignoring unparseable test portal opportunity link {href}: {e}"),
+        }
+    }
+
+    Ok(urls)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{parse_listing_page, PAGINATION},
+        crate::{
+            soup::parse_html_str,
+            testportal::portal::{cursor_token, listing_html, PortalShape},
+        },
+        reqwest::Url,
+    };
+
+    #[test]
+    fn listing_page() {
+        let shape = PortalShape::default();
+        let page_url =
+            Url::parse(&format!("http://127.0.0.1:8080/opportunities?page=1&cursor={}", cursor_token(2))).unwrap();
+
+        let document = parse_html_str(&listing_html(&shape, 2));
+        let urls = parse_listing_page(&document, &page_url).unwrap();
+        assert_eq!(urls.len(), shape.per_page);
+        assert_eq!(urls[0].as_str(), "http://127.0.0.1:8080/opportunities/6");
+        let next_page = PAGINATION.next_url(&document, &page_url).unwrap();
+        assert_eq!(next_page.query(), Some(format!("cursor={}&page=1", cursor_token(3)).as_str()));
+
+        let last_page = parse_html_str(&listing_html(&shape, shape.listing_pages));
+        assert!(PAGINATION.next_url(&last_page, &page_url).is_none());
+        assert!(parse_listing_page(&parse_html_str("<p>Error</p>"), &page_url).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! The fake portal the `TestPortal` subsystem crawls.
+//!
+//! The portal is served by an in-process [`httpmock`] server with a catalog of generated opportunities, sized by a
+//! [`PortalShape`]. Every page but the login page requires the session cookie set by a successful login; without it,
+//! the login page is served in its place, as real portals do when a session expires.
+use {
+    super::{
+        DETAIL_TABLE_SUMMARY, DOCUMENTS_TABLE_SUMMARY, FORM_FIELD_PASSWORD, FORM_FIELD_USERNAME, FORM_NAME_LOGIN,
+        LISTING_PATH, LISTING_TABLE_SUMMARY, LOGIN_PATH, PASSWORD, USERNAME,
+    },
+    chrono::{Duration, Utc},
+    httpmock::{Method::GET, Method::POST, MockServer},
+};
+
+/// Name of the cookie carrying the portal session.
+const SESSION_COOKIE: &str = "TestPortalSession";
+
+/// The session token handed out on login. The fake portal has a single session shared by every crawl.
+const SESSION_TOKEN: &str = "d3b07384d113edec49eaa6238ad5ff00";
+
+/// The start of every PDF file, enough for content sniffing to recognize the fake documents.
+const PDF_HEADER: &[u8] = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n";
+
+/// The size of the fake portal's catalog.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PortalShape {
+    /// The number of pages of listings.
+    pub listing_pages: usize,
+
+    /// The number of opportunities listed on each page.
+    pub per_page: usize,
+
+    /// The number of documents published with each opportunity.
+    pub documents_per_opportunity: usize,
+}
+
+impl Default for PortalShape {
+    fn default() -> Self {
+        Self {
+            listing_pages: 3,
+            per_page: 5,
+            documents_per_opportunity: 2,
+        }
+    }
+}
+
+impl PortalShape {
+    /// Return the total number of opportunities in the catalog.
+    pub fn opportunities(&self) -> usize {
+        self.listing_pages * self.per_page
+    }
+
+    /// Return the total number of documents in the catalog.
+    pub fn documents(&self) -> usize {
+        self.opportunities() * self.documents_per_opportunity
+    }
+
+    /// Return the ids of the opportunities listed on a page, numbered from 1.
+    fn page_ids(&self, page: usize) -> impl Iterator<Item = usize> {
+        (page - 1) * self.per_page + 1..=page * self.per_page
+    }
+}
+
+/// A running fake portal. The server stops when this is dropped.
+pub struct FakePortal {
+    server: MockServer,
+    shape: PortalShape,
+}
+
+impl FakePortal {
+    /// Start a fake portal serving a catalog of the given shape.
+    pub async fn start(shape: PortalShape) -> Self {
+        let server = MockServer::start_async().await;
+
+        // Mocks are matched in the order they are defined, so the pages requiring a session come before the login
+        // page served in their place. Later listing pages are requested with the first page's query plus a cursor, so
+        // they come before the first page.
+        for page in (1..=shape.listing_pages).rev() {
+            let body = listing_html(&shape, page);
+            let (param, value) = match page {
+                1 => ("page", "1".to_string()),
+                _ => ("cursor", cursor_token(page)),
+            };
+            server
+                .mock_async(|when, then| {
+                    when.method(GET)
+                        .path(format!("/{LISTING_PATH}"))
+                        .query_param(param, value)
+                        .cookie(SESSION_COOKIE, SESSION_TOKEN);
+                    then.status(200).header("Content-Type", "text/html; charset=utf-8").body(body);
+                })
+                .await;
+        }
+
+        for id in 1..=shape.opportunities() {
+            let body = detail_html(&shape, id);
+            server
+                .mock_async(|when, then| {
+                    when.method(GET).path(format!("/{LISTING_PATH}/{id}")).cookie(SESSION_COOKIE, SESSION_TOKEN);
+                    then.status(200).header("Content-Type", "text/html; charset=utf-8").body(body);
+                })
+                .await;
+
+            for n in 1..=shape.documents_per_opportunity {
+                let body = document_body(id, n);
+                server
+                    .mock_async(|when, then| {
+                        when.method(GET)
+                            .path(format!("/{LISTING_PATH}/{id}/documents/{n}.pdf"))
+                            .cookie(SESSION_COOKIE, SESSION_TOKEN);
+                        then.status(200).header("Content-Type", "application/pdf").body(body);
+                    })
+                    .await;
+            }
+        }
+
+        server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path(format!("/{LOGIN_PATH}"))
+                    .x_www_form_urlencoded_tuple(FORM_FIELD_USERNAME, USERNAME)
+                    .x_www_form_urlencoded_tuple(FORM_FIELD_PASSWORD, PASSWORD);
+                then.status(200)
+                    .header("Content-Type", "text/html; charset=utf-8")
+                    .header("Set-Cookie", format!("{SESSION_COOKIE}={SESSION_TOKEN}; Path=/; HttpOnly"))
+                    .body(format!(r#"<html><body><p>Welcome, {USERNAME}.</p></body></html>"#));
+            })
+            .await;
+
+        server
+            .mock_async(|when, then| {
+                when.method(POST).path(format!("/{LOGIN_PATH}"));
+                then.status(200)
+                    .header("Content-Type", "text/html; charset=utf-8")
+                    .body(format!("<html><body><p>Invalid username or password.</p>{}</body></html>", login_form()));
+            })
+            .await;
+
+        server
+            .mock_async(|when, then| {
+                when.method(GET);
+                then.status(200)
+                    .header("Content-Type", "text/html; charset=utf-8")
+                    .body(format!("<html><body>{}</body></html>", login_form()));
+            })
+            .await;
+
+        Self {
+            server,
+            shape,
+        }
+    }
+
+    /// Return the portal's base URL, to be used as the URL of a `TestPortal:StartCrawl` request.
+    pub fn base_url(&self) -> String {
+        self.server.url("/")
+    }
+
+    /// Return the shape of the portal's catalog.
+    pub fn shape(&self) -> PortalShape {
+        self.shape
+    }
+}
+
+/// Return the login form.
+fn login_form() -> String {
+    format!(
+        r#"<form name="{FORM_NAME_LOGIN}" method="post" action="/{LOGIN_PATH}">
+<input type="text" name="{FORM_FIELD_USERNAME}" value="">
+<input type="password" name="{FORM_FIELD_PASSWORD}" value="">
+<input type="submit" value="Log in">
+</form>"#
+    )
+}
+
+/// Return a page of listings.
+pub(crate) fn listing_html(shape: &PortalShape, page: usize) -> String {
+    let rows: String = shape
+        .page_ids(page)
+        .map(|id| {
+            format!(r#"<tr><td><a href="/{LISTING_PATH}/{id}">Test opportunity {id}</a></td><td>TP-{id:05}</td></tr>"#)
+        })
+        .collect();
+    let next = if page < shape.listing_pages {
+        format!(
+            r#"<form method="get" action="/{LISTING_PATH}"><input type="hidden" name="cursor" value="{}"><input type="submit" value="Next"></form>"#,
+            cursor_token(page + 1)
+        )
+    } else {
+        String::new()
+    };
+
+    format!(
+        r#"<html><body><h1>Open opportunities</h1>
+<table summary="{LISTING_TABLE_SUMMARY}"><tr><th>Title</th><th>Reference</th></tr>{rows}</table>
+<p>Page {page} of {}</p>{next}</body></html>"#,
+        shape.listing_pages
+    )
+}
+
+/// Return the opaque cursor token leading to a page of listings.
+pub(crate) fn cursor_token(page: usize) -> String {
+    format!("{:016x}", (page as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+}
+
+/// Return an opportunity's page.
+pub(crate) fn detail_html(shape: &PortalShape, id: usize) -> String {
+    let today = Utc::now().date_naive();
+    let published_on = (today - Duration::days((id % 7) as i64)).format("%m/%d/%Y");
+    let due_on = (today + Duration::days(30 + (id % 14) as i64)).format("%m/%d/%Y");
+    let documents: String = (1..=shape.documents_per_opportunity)
+        .map(|n| format!(r#"<tr><td><a href="/{LISTING_PATH}/{id}/documents/{n}.pdf">Document {n}.pdf</a></td></tr>"#))
+        .collect();
+
+    format!(
+        r#"<html><body>
+<table summary="{DETAIL_TABLE_SUMMARY}">
+<tr><th>Title:</th><td>Test opportunity {id}</td></tr>
+<tr><th>Reference:</th><td>TP-{id:05}</td></tr>
+<tr><th>Organization:</th><td>Department of Testing</td></tr>
+<tr><th>Contact:</th><td>Pat Example<br>pat.example@example.gov<br>(555) 010-{:04}</td></tr>
+<tr><th>Published:</th><td>{published_on}</td></tr>
+<tr><th>Due:</th><td>{due_on} 2:00 PM</td></tr>
+<tr><th>Description:</th><td>Generated opportunity {id} for end-to-end tests.<br>Not a real solicitation.</td></tr>
+<tr><th>Commodity Codes:</th><td>{:08}</td></tr>
+</table>
+<table summary="{DOCUMENTS_TABLE_SUMMARY}">{documents}</table>
+</body></html>"#,
+        id % 10_000,
+        43_000_000 + id,
+    )
+}
+
+/// Return the body of an opportunity's document.
+fn document_body(id: usize, n: usize) -> Vec<u8> {
+    let mut body = PDF_HEADER.to_vec();
+    body.extend_from_slice(format!("% Test opportunity {id}, document {n}\n%%EOF\n").as_bytes());
+    body
+}
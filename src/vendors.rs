@@ -0,0 +1,520 @@
+//! Request/response types for crawling the vendor directories some portals publish.
+//!
+//! Besides their solicitations, some portals publish a directory of the vendors registered with them, with each
+//! vendor's Washington UBI or DUNS number and the commodity codes it is registered for. Knowing who is registered for
+//! which commodities on which portals is useful for market research, so these directories can be crawled into the
+//! [vendor store][VendorStore], which by default shares the log table.
+//!
+//! Directories are read from the `Vendors` subsystem's configuration document. Each names the portal it belongs to, its
+//! first page, [selectors][crate::rules] for its table of vendors and next page, and the headers of the table's
+//! columns:
+//!
+//! ```json
+//! {
+//!     "Directories": [{
+//!         "Portal": "Webs",
+//!         "Url": "https://pr-webs-vendor.des.wa.gov/VendorSearch.aspx",
+//!         "Table": "table.vendors",
+//!         "NextPage": "a[rel=next]",
+//!         "Columns": {
+//!             "Name": "Vendor Name",
+//!             "Ubi": "UBI",
+//!             "Duns": "DUNS",
+//!             "CommodityCodes": "Commodity Codes"
+//!         },
+//!         "CommoditySeparator": ","
+//!     }]
+//! }
+//! ```
+//!
+//! Crawling a directory is optional: a directory is only crawled while the
+//! [`VendorDirectories`][crate::flags::Flag::VendorDirectories] flag is enabled for its portal. Requests are matched to
+//! their directory by host.
+use {
+    crate::{
+        classification::{Classification, DDB_KEY_CLASSIFICATION},
+        config::load_subsystem_config,
+        flags::{load_flags, Flag},
+        httpext::{Client, DdbBatchWriter, HostAllowlist, LogConfig, ResponseExt},
+        journal::CrawlEvent,
+        opportunity::clean_text,
+        pagination,
+        rules::Selector,
+        shapes::{CrawlParameters, NextRequest, Operation, Request, Response, SUBSYS_VENDORS},
+        soup::{parse_html_str, NodeExt, QueryBuilderExt},
+        BoxError,
+    },
+    aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoDbClient},
+    chrono::{DateTime, SecondsFormat, Utc},
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    markup5ever_rcdom::{Handle, RcDom},
+    reqwest::Url,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{BTreeMap, HashMap},
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+const OP_START_CRAWL: &str = "StartCrawl";
+const OP_FETCH_DIRECTORY_PAGE: &str = "FetchDirectoryPage";
+
+const PARSER_DIRECTORY: &str = "Directory";
+
+const DDB_KEY_CRAWL_ID: &str = "CrawlId";
+const DDB_KEY_REQUEST_ID: &str = "RequestId";
+const DDB_KEY_RECORD: &str = "Record";
+const DDB_KEY_LAST_SEEN_AT: &str = "LastSeenAt";
+const DDB_KEY_LAST_SEEN_CRAWL_ID: &str = "LastSeenCrawlId";
+
+/// Partition key prefix for vendor items, followed by the portal, keeping them apart from request log items in a
+/// shared table.
+const VENDOR_PARTITION_PREFIX: &str = "Vendor#";
+
+/// Number of digits in a UBI or DUNS number.
+const REGISTRATION_NUMBER_DIGITS: usize = 9;
+
+/// The `Vendors` configuration document.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+struct VendorsConfig {
+    /// The directories that can be crawled.
+    directories: Vec<DirectoryRules>,
+}
+
+/// How to crawl a portal's vendor directory.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DirectoryRules {
+    /// The portal the directory belongs to, whose flags decide whether it is crawled.
+    portal: String,
+
+    /// The URL of the directory's first page.
+    url: String,
+
+    /// The table of vendors on each page.
+    table: Selector,
+
+    /// The link to the directory's next page, if it is paged.
+    #[serde(default)]
+    next_page: Option<Selector>,
+
+    /// The headers of the table's columns.
+    columns: DirectoryColumns,
+
+    /// The separator between the commodity codes in a cell.
+    #[serde(default = "default_commodity_separator")]
+    commodity_separator: String,
+}
+
+/// The headers of a vendor directory's columns; only the vendor's name is required.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DirectoryColumns {
+    name: String,
+    #[serde(default)]
+    ubi: Option<String>,
+    #[serde(default)]
+    duns: Option<String>,
+    #[serde(default)]
+    commodity_codes: Option<String>,
+}
+
+fn default_commodity_separator() -> String {
+    ",".to_string()
+}
+
+/// A vendor listed in a portal's directory.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Vendor {
+    /// The portal whose directory lists the vendor.
+    pub portal: String,
+
+    /// The vendor's name.
+    pub name: String,
+
+    /// The vendor's Washington Unified Business Identifier, as 9 digits.
+    pub ubi: Option<String>,
+
+    /// The vendor's DUNS number, as 9 digits.
+    pub duns: Option<String>,
+
+    /// The commodity codes the vendor is registered for.
+    pub commodity_codes: Vec<String>,
+
+    /// The URL of the directory page listing the vendor.
+    pub directory_url: String,
+}
+
+impl Vendor {
+    /// Return the vendor's key within its portal: its UBI, its DUNS number, or, lacking both, its name ignoring case.
+    pub fn key(&self) -> String {
+        match (&self.ubi, &self.duns) {
+            (Some(ubi), _) => format!("Ubi#{ubi}"),
+            (None, Some(duns)) => format!("Duns#{duns}"),
+            (None, None) => format!("Name#{}", self.name.to_lowercase()),
+        }
+    }
+}
+
+/// Storage for vendors in DynamoDB.
+#[derive(Clone, Debug)]
+pub struct VendorStore {
+    writer: DdbBatchWriter,
+}
+
+impl VendorStore {
+    /// Create a vendor store using the given table.
+    pub fn new<S: Into<String>>(ddb_client: DynamoDbClient, table_name: S) -> Self {
+        Self {
+            writer: DdbBatchWriter::new(ddb_client, table_name),
+        }
+    }
+
+    /// Return the name of the table vendors are stored in.
+    pub fn table_name(&self) -> &str {
+        self.writer.table_name()
+    }
+
+    /// Record vendors seen by a crawl, replacing what was stored for them. A vendor repeated in the batch is recorded
+    /// once.
+    pub async fn record(&self, vendors: &[Vendor], crawl_id: &str, now: DateTime<Utc>) -> Result<(), BoxError> {
+        // BatchWriteItem rejects a batch that writes the same key twice.
+        let mut items = BTreeMap::new();
+        for vendor in vendors {
+            let partition = format!("{VENDOR_PARTITION_PREFIX}{}", vendor.portal);
+            let item = HashMap::from([
+                (DDB_KEY_CRAWL_ID.to_string(), AttributeValue::S(partition.clone())),
+                (DDB_KEY_REQUEST_ID.to_string(), AttributeValue::S(vendor.key())),
+                (DDB_KEY_RECORD.to_string(), AttributeValue::S(serde_json::to_string(vendor)?)),
+                (DDB_KEY_LAST_SEEN_AT.to_string(), AttributeValue::S(now.to_rfc3339_opts(SecondsFormat::Secs, true))),
+                (DDB_KEY_LAST_SEEN_CRAWL_ID.to_string(), AttributeValue::S(crawl_id.to_string())),
+                (
+                    DDB_KEY_CLASSIFICATION.to_string(),
+                    AttributeValue::S(Classification::ExtractedOpportunity.to_string()),
+                ),
+            ]);
+            items.insert((partition, vendor.key()), item);
+        }
+
+        for item in items.into_values() {
+            self.writer.put(item).await?;
+        }
+
+        self.writer.flush().await
+    }
+}
+
+/// Possible operations for vendor directories.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum VendorsOperation {
+    /// Start a crawl of every directory whose portal has the flag enabled, or of the directory whose page is given as
+    /// the URL.
+    StartCrawl,
+
+    /// Fetch a page of a directory.
+    FetchDirectoryPage,
+}
+
+impl FromStr for VendorsOperation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            OP_START_CRAWL => Ok(Self::StartCrawl),
+            OP_FETCH_DIRECTORY_PAGE => Ok(Self::FetchDirectoryPage),
+            _ => Err(format!("Unknown operation: {value}")),
+        }
+    }
+}
+
+impl Display for VendorsOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.operation())
+    }
+}
+
+impl VendorsOperation {
+    /// Handle a request.
+    pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+        match self {
+            Self::StartCrawl => start_crawl(log_config, req, context).await,
+            Self::FetchDirectoryPage => fetch_directory_page(log_config, req, context).await,
+        }
+    }
+
+    /// Return the operation name within the subsystem.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::StartCrawl => OP_START_CRAWL,
+            Self::FetchDirectoryPage => OP_FETCH_DIRECTORY_PAGE,
+        }
+    }
+}
+
+impl DirectoryRules {
+    /// Return the URL of the directory's first page.
+    fn url(&self) -> Result<Url, BoxError> {
+        Url::parse(&self.url).map_err(|e| format!("Invalid directory URL for {}: {e}", self.portal).into())
+    }
+
+    /// Return the name of the directory's parser, e.g. `Vendors:Webs:Directory`.
+    fn parser_name(&self) -> String {
+        format!("{SUBSYS_VENDORS}:{}:{PARSER_DIRECTORY}", self.portal)
+    }
+}
+
+/// Load the directories that can be crawled.
+async fn load_directories(log_config: &LogConfig) -> Result<Vec<DirectoryRules>, BoxError> {
+    let config: VendorsConfig = load_subsystem_config(log_config, SUBSYS_VENDORS).await?;
+    Ok(config.directories)
+}
+
+/// Return the directories whose portals have vendor directory crawling enabled for the crawl `crawl_id`, if any.
+async fn enabled_directories(log_config: &LogConfig, crawl_id: Option<&str>) -> Result<Vec<DirectoryRules>, BoxError> {
+    let flags = load_flags(log_config).await?;
+    let directories = load_directories(log_config).await?;
+    Ok(directories
+        .into_iter()
+        .filter(|directory| flags.resolve(&directory.portal, crawl_id).is_enabled(Flag::VendorDirectories))
+        .collect())
+}
+
+/// Return the directory on the same host as `url`.
+fn directory_for_url(directories: Vec<DirectoryRules>, url: &Url) -> Option<DirectoryRules> {
+    let host = url.host_str()?;
+    directories.into_iter().find(|directory| {
+        Url::parse(&directory.url).is_ok_and(|directory_url| {
+            directory_url.host_str().is_some_and(|directory_host| directory_host.eq_ignore_ascii_case(host))
+        })
+    })
+}
+
+/// Build a client for crawling `directory` from `url`.
+fn build_client(
+    log_config: &LogConfig,
+    crawl: &CrawlParameters,
+    context: &Context,
+    directory: &DirectoryRules,
+    url: &Url,
+) -> Result<Client, BoxError> {
+    Ok(crawl
+        .build_client(log_config.clone(), context)
+        .portal(SUBSYS_VENDORS)
+        .allowed_hosts(HostAllowlist::from_urls([url, &directory.url()?]))
+        .build()?)
+}
+
+/// Start a crawl by scheduling the first page of each enabled directory, or, given a URL, by fetching it as the first
+/// page of its directory.
+async fn start_crawl(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    if req.url.is_some() {
+        return fetch_directory_page(log_config, req, context).await;
+    }
+
+    let next_requests = enabled_directories(&log_config, req.crawl.crawl_id.as_deref())
+        .await?
+        .into_iter()
+        .map(|directory| NextRequest {
+            operation: Operation::Vendors(VendorsOperation::FetchDirectoryPage),
+            url: Some(directory.url),
+            link_text: None,
+            crawl: req.crawl.child(),
+            delay_seconds: 0,
+        })
+        .collect();
+
+    Ok(Response {
+        next_requests,
+    })
+}
+
+/// Fetch a page of a directory, recording its vendors and returning a request for the next page.
+async fn fetch_directory_page(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let Some(url) = req.url.as_deref() else {
+        return Err(format!("{} requires a URL", req.operation).into());
+    };
+
+    let url = Url::parse(url)?;
+    let Some(directory) =
+        directory_for_url(enabled_directories(&log_config, req.crawl.crawl_id.as_deref()).await?, &url)
+    else {
+        info!("Vendor directory crawling isn't enabled for {url}; skipping");
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    };
+
+    let client = build_client(&log_config, &req.crawl, &context, &directory, &url)?;
+    let response = match client.get(url.clone()).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch {} vendor directory: {e}", directory.portal);
+            return Err(e);
+        }
+    };
+
+    let document = parse_html_str(response.text()?);
+    let (vendors, next_page) = parse_directory_page(&directory, &document, response.url());
+    log_config.vendors.record(&vendors, &client.crawl_id, Utc::now()).await?;
+    log_config
+        .journal
+        .record(
+            &client.crawl_id,
+            CrawlEvent::ParseSucceeded {
+                url: response.url().to_string(),
+                parser: directory.parser_name(),
+                items: vendors.len(),
+            },
+        )
+        .await;
+    info!("Recorded {} {} vendors from {}", vendors.len(), directory.portal, response.url());
+
+    let next_requests = next_page
+        .map(|next_page| {
+            pagination::next_page_request(
+                Operation::Vendors(VendorsOperation::FetchDirectoryPage),
+                &next_page,
+                &req.crawl,
+            )
+        })
+        .into_iter()
+        .collect();
+
+    Ok(Response {
+        next_requests,
+    })
+}
+
+/// Return the text of the header or data cells of a table row.
+fn row_cells(tr: &Handle) -> Vec<String> {
+    tr.children()
+        .filter(|cell| cell.is_element() && matches!(cell.name(), "td" | "th"))
+        .map(|cell| clean_text(&cell.text()).unwrap_or_default())
+        .collect()
+}
+
+/// Return a registration number reduced to its digits, if it has the right number of them.
+fn registration_number(value: &str) -> Option<String> {
+    let digits: String = value.chars().filter(char::is_ascii_digit).collect();
+    (digits.len() == REGISTRATION_NUMBER_DIGITS).then_some(digits)
+}
+
+/// Read a page of a directory, returning its vendors and the URL of the next page, if any.
+fn parse_directory_page(directory: &DirectoryRules, document: &RcDom, page_url: &Url) -> (Vec<Vendor>, Option<Url>) {
+    let mut vendors = vec![];
+
+    for table in directory.table.select(&document.document) {
+        let mut rows = table.tag("tr").find_all();
+        let Some(header) = rows.next() else {
+            continue;
+        };
+
+        let headers = row_cells(&header);
+        let column = |label: Option<&String>| {
+            let label = label?;
+            headers.iter().position(|header| header.trim_end_matches(':').trim_end().eq_ignore_ascii_case(label))
+        };
+        let Some(name_column) = column(Some(&directory.columns.name)) else {
+            warn!("No {:?} column in {} vendor directory {page_url}", directory.columns.name, directory.portal);
+            continue;
+        };
+        let ubi_column = column(directory.columns.ubi.as_ref());
+        let duns_column = column(directory.columns.duns.as_ref());
+        let commodity_column = column(directory.columns.commodity_codes.as_ref());
+
+        for tr in rows {
+            let cells = row_cells(&tr);
+            let cell = |column: Option<usize>| column.and_then(|column| cells.get(column)).map(String::as_str);
+            let Some(name) = cell(Some(name_column)).filter(|name| !name.is_empty()) else {
+                continue;
+            };
+
+            vendors.push(Vendor {
+                portal: directory.portal.clone(),
+                name: name.to_string(),
+                ubi: cell(ubi_column).and_then(registration_number),
+                duns: cell(duns_column).and_then(registration_number),
+                commodity_codes: cell(commodity_column)
+                    .map(|codes| codes.split(directory.commodity_separator.as_str()).filter_map(clean_text).collect())
+                    .unwrap_or_default(),
+                directory_url: page_url.to_string(),
+            });
+        }
+    }
+
+    let next_page = directory
+        .next_page
+        .as_ref()
+        .and_then(|selector| selector.first(&document.document))
+        .and_then(|a| page_url.join(&a.get("href")?).ok())
+        .filter(|url| url != page_url);
+
+    (vendors, next_page)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{directory_for_url, parse_directory_page, Vendor, VendorsConfig},
+        crate::soup::parse_html_str,
+        reqwest::Url,
+    };
+
+    #[test]
+    fn directory_pages() {
+        let config: VendorsConfig = serde_json::from_str(
+            r#"{
+                "Directories": [{
+                    "Portal": "Webs",
+                    "Url": "https://vendors.example.gov/search",
+                    "Table": "table.vendors",
+                    "NextPage": "a[rel=next]",
+                    "Columns": {"Name": "Vendor Name", "Ubi": "UBI", "CommodityCodes": "Commodity Codes"},
+                    "CommoditySeparator": ";"
+                }]
+            }"#,
+        )
+        .unwrap();
+        let page_url = Url::parse("https://VENDORS.example.gov/search?page=1").unwrap();
+        let directory = directory_for_url(config.directories, &page_url).unwrap();
+
+        let page = r#"<html><body>
+            <table class="vendors">
+              <tr><th>Vendor Name:</th><th>UBI</th><th>Commodity Codes</th></tr>
+              <tr><td>Acme Paving LLC</td><td>602 123 456</td><td>745-32; 913-00</td></tr>
+              <tr><td>Cascade Concrete</td><td>pending</td><td></td></tr>
+              <tr><td></td><td>603000000</td><td>745-32</td></tr>
+            </table>
+            <a rel="next" href="?page=2">Next</a>
+        </body></html>"#;
+        let (vendors, next_page) = parse_directory_page(&directory, &parse_html_str(page), &page_url);
+        assert_eq!(
+            vendors,
+            vec![
+                Vendor {
+                    portal: "Webs".to_string(),
+                    name: "Acme Paving LLC".to_string(),
+                    ubi: Some("602123456".to_string()),
+                    duns: None,
+                    commodity_codes: vec!["745-32".to_string(), "913-00".to_string()],
+                    directory_url: page_url.to_string(),
+                },
+                Vendor {
+                    portal: "Webs".to_string(),
+                    name: "Cascade Concrete".to_string(),
+                    directory_url: page_url.to_string(),
+                    ..Vendor::default()
+                },
+            ]
+        );
+        assert_eq!(vendors[0].key(), "Ubi#602123456");
+        assert_eq!(vendors[1].key(), "Name#cascade concrete");
+        assert_eq!(next_page.map(String::from).as_deref(), Some("https://vendors.example.gov/search?page=2"));
+
+        assert!(directory_for_url(vec![directory], &Url::parse("https://example.gov/").unwrap()).is_none());
+    }
+}
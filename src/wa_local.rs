@@ -0,0 +1,507 @@
+//! Request/response types for Washington city and county portals that post solicitations outside of WEBS.
+//!
+//! Each of these sites is small and has its own markup, so rather than a subsystem per site, this module holds a table
+//! of [`Site`]s. A site supplies only its listing URL and two parser functions; fetching, pagination, journaling, and
+//! attachment extraction are shared. Requests are matched to their site by host.
+//!
+//! The sites to crawl are configured with the `WA_LOCAL_SITES` environment variable, a comma-separated list of site
+//! ids. When it is unset or empty, every site is crawled.
+mod seattle;
+mod tacoma;
+
+use {
+    crate::{
+        attachment, closure, contacts,
+        httpext::{Client, ConfigError, HostAllowlist, LogConfig, ResponseExt},
+        journal::CrawlEvent,
+        lifecycle,
+        opportunity::{clean_text, parse_with_structured_data, DateLocale, Document, Opportunity},
+        pagination::{self, NextLink, Pagination},
+        quality,
+        shapes::{CrawlParameters, NextRequest, Operation, Request, Response, SUBSYS_WA_LOCAL},
+        soup::{parse_html_str, NodeExt, QueryBuilderExt},
+        BoxError,
+    },
+    lambda_runtime::{Context, Error as LambdaError},
+    log::*,
+    markup5ever_rcdom::{Handle, RcDom},
+    reqwest::Url,
+    serde::{Deserialize, Serialize},
+    std::{
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+const ENV_WA_LOCAL_SITES: &str = "WA_LOCAL_SITES";
+
+const OP_START_CRAWL: &str = "StartCrawl";
+const OP_FETCH_LISTING_PAGE: &str = "FetchListingPage";
+const OP_FETCH_DETAIL_PAGE: &str = "FetchDetailPage";
+
+/// Version of this subsystem's parsers. Bump it whenever a parser change alters what is extracted.
+pub(crate) const PARSER_VERSION: u32 = 1;
+
+const PARSER_LISTING: &str = "Listing";
+const PARSER_DETAIL: &str = "Detail";
+
+/// File extensions of links taken to be documents published with an opportunity.
+const DOCUMENT_EXTENSIONS: &[&str] = &["pdf", "doc", "docx", "xls", "xlsx", "zip", "dwg"];
+
+/// Listings link to their next page with a `rel="next"` link or one whose text is "Next".
+pub(crate) const PAGINATION: Pagination =
+    Pagination::NextLink(&[NextLink::Rel("next"), NextLink::Text(&["Next", "Next ›", "Next »"])]);
+
+/// Every supported site.
+pub(crate) const SITES: &[Site] = &[seattle::SITE, tacoma::SITE];
+
+/// A site publishing solicitations, and the parsers for its pages.
+pub(crate) struct Site {
+    /// Short identifier for the site, used in configuration and parser names, e.g. `Seattle`.
+    pub id: &'static str,
+
+    /// The jurisdiction publishing the solicitations, used when a page doesn't name the issuing department.
+    pub jurisdiction: &'static str,
+
+    /// The URL of the first page of open solicitations.
+    pub listing_url: &'static str,
+
+    /// Parse a page of the listing.
+    pub parse_listing: fn(&RcDom, &Url) -> Result<Listing, BoxError>,
+
+    /// Parse a solicitation's page into the normalized model. The portal and, if the page doesn't name one, the
+    /// organization are filled in afterwards.
+    pub parse_detail: fn(&RcDom, &Url, DateLocale) -> Result<Opportunity, BoxError>,
+
+    /// Phrases shown in place of a solicitation the site no longer publishes; see [`closure`][crate::closure].
+    pub not_found_markers: &'static [&'static str],
+}
+
+/// What a site's listing page links to.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Listing {
+    /// The URLs of the solicitations' pages, in order.
+    pub detail_urls: Vec<Url>,
+
+    /// The URL of the next page of the listing, if there is one.
+    pub next_page: Option<Url>,
+}
+
+/// Possible operations for the Washington local portals.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum WaLocalOperation {
+    /// Start a crawl of every configured site, or of the site whose listing is given as the URL.
+    StartCrawl,
+
+    /// Fetch a page of a site's listing.
+    FetchListingPage,
+
+    /// Fetch a solicitation's page.
+    FetchDetailPage,
+}
+
+impl FromStr for WaLocalOperation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            OP_START_CRAWL => Ok(Self::StartCrawl),
+            OP_FETCH_LISTING_PAGE => Ok(Self::FetchListingPage),
+            OP_FETCH_DETAIL_PAGE => Ok(Self::FetchDetailPage),
+            _ => Err(format!("Unknown operation: {value}")),
+        }
+    }
+}
+
+impl Display for WaLocalOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.operation())
+    }
+}
+
+impl WaLocalOperation {
+    /// Handle a request.
+    pub async fn handle(self, log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+        match self {
+            Self::StartCrawl => start_crawl(log_config, req, context).await,
+            Self::FetchListingPage => fetch_listing_page(log_config, req, context).await,
+            Self::FetchDetailPage => fetch_detail_page(log_config, req, context).await,
+        }
+    }
+
+    /// Return the operation name within the subsystem.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::StartCrawl => OP_START_CRAWL,
+            Self::FetchListingPage => OP_FETCH_LISTING_PAGE,
+            Self::FetchDetailPage => OP_FETCH_DETAIL_PAGE,
+        }
+    }
+}
+
+impl Site {
+    /// Return the URL of the first page of the listing.
+    fn listing_url(&self) -> Url {
+        Url::parse(self.listing_url).expect("Site listing URLs are valid")
+    }
+
+    /// Return the name of one of this site's parsers, e.g. `WaLocal:Seattle:Listing`.
+    fn parser_name(&self, parser: &str) -> String {
+        format!("{SUBSYS_WA_LOCAL}:{}:{parser}", self.id)
+    }
+}
+
+/// Return the site whose listing is on the same host as `url`.
+pub(crate) fn site_for_url(url: &Url) -> Option<&'static Site> {
+    let host = url.host_str()?;
+    SITES
+        .iter()
+        .find(|site| site.listing_url().host_str().is_some_and(|site_host| site_host.eq_ignore_ascii_case(host)))
+}
+
+/// Read the ids of the sites to crawl, looking `WA_LOCAL_SITES` up with `lookup`. It is a comma-separated list of site
+/// ids, ignoring case; every site is crawled if it is unset or empty. Unknown ids are recorded in `errors`.
+pub(crate) fn enabled_sites<F: Fn(&str) -> Option<String>>(lookup: &F, errors: &mut ConfigError) -> Vec<&'static str> {
+    let config = lookup(ENV_WA_LOCAL_SITES).unwrap_or_default();
+    let ids: Vec<&str> = config.split(',').map(str::trim).filter(|id| !id.is_empty()).collect();
+    if ids.is_empty() {
+        return SITES.iter().map(|site| site.id).collect();
+    }
+
+    for id in ids.iter() {
+        if !SITES.iter().any(|site| site.id.eq_ignore_ascii_case(id)) {
+            errors.push(ENV_WA_LOCAL_SITES, format!("{id:?} is not a Washington local site"));
+        }
+    }
+
+    SITES.iter().filter(|site| ids.iter().any(|id| site.id.eq_ignore_ascii_case(id))).map(|site| site.id).collect()
+}
+
+/// Return the URL of a request and the site it belongs to, which operations other than `StartCrawl` require.
+fn required_site_url(req: &Request) -> Result<(&'static Site, Url), BoxError> {
+    let Some(url) = req.url.as_deref() else {
+        return Err(format!("{} requires a URL", req.operation).into());
+    };
+
+    let url = Url::parse(url)?;
+    match site_for_url(&url) {
+        Some(site) => Ok((site, url)),
+        None => Err(format!("No Washington local site is configured for {url}").into()),
+    }
+}
+
+/// Build a client for crawling `site` from `url`.
+fn build_client(
+    log_config: &LogConfig,
+    crawl: &CrawlParameters,
+    context: &Context,
+    site: &Site,
+    url: &Url,
+) -> Result<Client, BoxError> {
+    Ok(crawl
+        .build_client(log_config.clone(), context)
+        .portal(SUBSYS_WA_LOCAL)
+        .allowed_hosts(HostAllowlist::from_urls([url, &site.listing_url()]))
+        .build()?)
+}
+
+/// Start a crawl by scheduling the first listing page of each configured site, or, given a URL, by fetching it as the
+/// first listing page of its site.
+async fn start_crawl(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    if req.url.is_some() {
+        return fetch_listing_page(log_config, req, context).await;
+    }
+
+    let next_requests = SITES
+        .iter()
+        .filter(|site| log_config.wa_local_sites.contains(&site.id))
+        .map(|site| NextRequest {
+            operation: Operation::WaLocal(WaLocalOperation::FetchListingPage),
+            url: Some(site.listing_url.to_string()),
+            link_text: None,
+            crawl: req.crawl.child(),
+            delay_seconds: 0,
+        })
+        .collect();
+
+    Ok(Response {
+        next_requests,
+    })
+}
+
+/// Fetch a page of a site's listing, returning requests for its solicitations and the next page.
+async fn fetch_listing_page(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let (site, url) = required_site_url(&req)?;
+    let client = build_client(&log_config, &req.crawl, &context, site, &url)?;
+
+    let response = match client.get(url.clone()).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch {} listing: {e}", site.id);
+            return Err(e);
+        }
+    };
+
+    let document = parse_html_str(response.text()?);
+    let parser = site.parser_name(PARSER_LISTING);
+    let result = (site.parse_listing)(&document, response.url());
+    let event = match &result {
+        Ok(listing) => CrawlEvent::ParseSucceeded {
+            url: response.url().to_string(),
+            parser,
+            items: listing.detail_urls.len(),
+        },
+        Err(e) => CrawlEvent::ParseFailed {
+            url: response.url().to_string(),
+            parser,
+            error: e.to_string(),
+        },
+    };
+    log_config.journal.record(&client.crawl_id, event).await;
+
+    let listing = result?;
+    let mut next_requests: Vec<NextRequest> = listing
+        .detail_urls
+        .into_iter()
+        .map(|url| NextRequest {
+            operation: Operation::WaLocal(WaLocalOperation::FetchDetailPage),
+            url: Some(url.to_string()),
+            link_text: None,
+            crawl: req.crawl.child(),
+            delay_seconds: 0,
+        })
+        .collect();
+
+    if let Some(next_page) = listing.next_page {
+        next_requests.push(pagination::next_page_request(
+            Operation::WaLocal(WaLocalOperation::FetchListingPage),
+            &next_page,
+            &req.crawl,
+        ));
+    }
+
+    Ok(Response {
+        next_requests,
+    })
+}
+
+/// Fetch a solicitation's page and parse it into the normalized opportunity model.
+async fn fetch_detail_page(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let (site, url) = required_site_url(&req)?;
+    let client = build_client(&log_config, &req.crawl, &context, site, &url)?;
+
+    let response = match client.get(url.clone()).send().await.error_for_status() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch {} solicitation: {e}", site.id);
+            return Err(e);
+        }
+    };
+
+    let Some(response) = attachment::html_page(&log_config, &req.crawl, response, Some(url.as_str())).await else {
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    };
+
+    if let Some(reason) =
+        closure::soft_not_found(response.text()?, site.not_found_markers, &req.crawl.locale.not_found_markers)
+    {
+        closure::record_closed(&log_config, &client, SUBSYS_WA_LOCAL, url.as_str(), &reason).await?;
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    }
+
+    let document = parse_html_str(response.text()?);
+    let parser = site.parser_name(PARSER_DETAIL);
+    let result = parse_detail(site, &document, response.url(), req.crawl.locale.dates);
+    let event = match &result {
+        Ok(_) => CrawlEvent::ParseSucceeded {
+            url: response.url().to_string(),
+            parser: parser.clone(),
+            items: 1,
+        },
+        Err(e) => CrawlEvent::ParseFailed {
+            url: response.url().to_string(),
+            parser: parser.clone(),
+            error: e.to_string(),
+        },
+    };
+    log_config.journal.record(&client.crawl_id, event).await;
+
+    let mut opportunity = result?.with_parser(parser, PARSER_VERSION).with_tenant(client.tenant.as_deref());
+    contacts::record_contacts(&log_config, &client, &mut opportunity, response.text()?).await;
+    lifecycle::record_stage(&log_config, &client, &mut opportunity, response.text()?).await;
+    quality::record_quality(&log_config, &client, &mut opportunity).await;
+    closure::record_seen(&log_config, &client, &opportunity).await;
+    info!("{} opportunity: {}", site.id, serde_json::to_string(&opportunity)?);
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Parse a solicitation's page from its structured data or with its site's parser, filling in what the site leaves to
+/// the shared plumbing.
+fn parse_detail(
+    site: &Site,
+    document: &RcDom,
+    page_url: &Url,
+    date_locale: DateLocale,
+) -> Result<Opportunity, BoxError> {
+    let mut opportunity = parse_with_structured_data(document, page_url, date_locale, || {
+        (site.parse_detail)(document, page_url, date_locale)
+    })?;
+    opportunity.portal = SUBSYS_WA_LOCAL.to_string();
+    opportunity.organization.get_or_insert_with(|| site.jurisdiction.to_string());
+    Ok(opportunity)
+}
+
+/// Resolve a link on a page, logging and ignoring it if it can't be parsed.
+fn resolve_link(page_url: &Url, href: &str) -> Option<Url> {
+    match page_url.join(href) {
+        Ok(url) => Some(url),
+        Err(e) => {
+            warn!("Ignoring unparseable link {href} on {page_url}: {e}");
+            None
+        }
+    }
+}
+
+/// Return the targets of the links within `scope` accepted by `keep`, in order and without duplicates.
+pub(crate) fn links<F>(scope: &Handle, page_url: &Url, keep: F) -> Vec<Url>
+where
+    F: Fn(&Url) -> bool,
+{
+    let mut urls: Vec<Url> = Vec::new();
+    for a in scope.tag("a").find_all() {
+        let Some(url) = a.get("href").and_then(|href| resolve_link(page_url, &href)) else {
+            continue;
+        };
+
+        if keep(&url) && !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+
+    urls
+}
+
+/// Return the documents linked from `scope`: links whose path ends in a document file extension.
+pub(crate) fn document_links(scope: &Handle, page_url: &Url) -> Vec<Document> {
+    let mut documents: Vec<Document> = Vec::new();
+    for a in scope.tag("a").find_all() {
+        let Some(url) = a.get("href").and_then(|href| resolve_link(page_url, &href)) else {
+            continue;
+        };
+
+        let extension = url.path().rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+        if !extension.is_some_and(|extension| DOCUMENT_EXTENSIONS.contains(&extension.as_str())) {
+            continue;
+        }
+
+        if documents.iter().any(|document| document.url == url.as_str()) {
+            continue;
+        }
+
+        documents.push(Document {
+            name: clean_text(&a.text()).unwrap_or_else(|| url.to_string()),
+            url: url.to_string(),
+        });
+    }
+
+    documents
+}
+
+/// Parse a Washington local snapshot for golden-file testing. `parser` is the name of the snapshot directory, the
+/// lowercase site id and the page kind, e.g. `seattle-listing`.
+#[cfg(any(test, feature = "test-utils"))]
+pub fn parse_snapshot(parser: &str, text: &str) -> Result<serde_json::Value, BoxError> {
+    use serde_json::json;
+
+    let Some((site_id, kind)) = parser.rsplit_once('-') else {
+        return Err(format!("Unknown Washington local snapshot parser {parser}").into());
+    };
+
+    let Some(site) = SITES.iter().find(|site| site.id.eq_ignore_ascii_case(site_id)) else {
+        return Err(format!("Unknown Washington local site {site_id}").into());
+    };
+
+    let page_url = site.listing_url();
+    let document = parse_html_str(text);
+
+    match kind {
+        "listing" => {
+            let listing = (site.parse_listing)(&document, &page_url)?;
+            Ok(json!({
+                "DetailUrls": listing.detail_urls.iter().map(Url::as_str).collect::<Vec<_>>(),
+                "NextPage": listing.next_page.as_ref().map(Url::as_str),
+            }))
+        }
+        "detail" => Ok(serde_json::to_value(parse_detail(site, &document, &page_url, DateLocale::default())?)?),
+        _ => Err(format!("Unknown Washington local snapshot parser {parser}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{document_links, enabled_sites, parse_snapshot, site_for_url, PAGINATION, SITES},
+        crate::{
+            golden::{check_snapshots, snapshot_dir},
+            httpext::ConfigError,
+            shapes::SUBSYS_WA_LOCAL,
+            soup::parse_html_str,
+        },
+        reqwest::Url,
+    };
+
+    #[test_log::test]
+    fn golden_snapshots() {
+        let checked = check_snapshots(&snapshot_dir(&SUBSYS_WA_LOCAL.to_lowercase()), parse_snapshot)
+            .unwrap_or_else(|e| panic!("Golden snapshot mismatch:\n{e}"));
+        assert!(checked >= 4);
+    }
+
+    #[test]
+    fn site_table() {
+        for site in SITES {
+            assert_eq!(site_for_url(&site.listing_url()).map(|found| found.id), Some(site.id));
+        }
+
+        assert!(site_for_url(&Url::parse("https://pr-webs-vendor.des.wa.gov/").unwrap()).is_none());
+    }
+
+    #[test]
+    fn site_selection() {
+        let ids = |config: &str| {
+            let mut errors = ConfigError::default();
+            let ids = enabled_sites(&|_| Some(config.to_string()), &mut errors);
+            (ids, errors.problems.len())
+        };
+        assert_eq!(ids(""), (SITES.iter().map(|site| site.id).collect::<Vec<_>>(), 0));
+        assert_eq!(ids(" tacoma , Seattle"), (vec!["Seattle", "Tacoma"], 0));
+        assert_eq!(ids(" tacoma , Spokane"), (vec!["Tacoma"], 1));
+    }
+
+    #[test]
+    fn shared_plumbing() {
+        let page_url = Url::parse("https://example.gov/bids/").unwrap();
+        let document = parse_html_str(
+            r#"<a href="rfp.PDF">RFP</a> <a href="rfp.PDF">RFP again</a> <a href="plans.zip"></a>
+               <a href="details">Details</a> <a href="?page=2">Next ›</a>"#,
+        );
+
+        let documents = document_links(&document.document, &page_url);
+        assert_eq!(documents.len(), 2);
+        assert_eq!(
+            (documents[0].name.as_str(), documents[0].url.as_str()),
+            ("RFP", "https://example.gov/bids/rfp.PDF")
+        );
+        assert_eq!(documents[1].name, "https://example.gov/bids/plans.zip");
+
+        assert_eq!(PAGINATION.next_url(&document, &page_url).unwrap().as_str(), "https://example.gov/bids/?page=2");
+        assert!(PAGINATION.next_url(&parse_html_str("<a href='x'>Previous</a>"), &page_url).is_none());
+    }
+}
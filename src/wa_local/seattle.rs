@@ -0,0 +1,158 @@
+//! City of Seattle purchasing bid opportunities.
+//!
+//! The listing is a single table of open solicitations linking to a page per solicitation. Each solicitation page
+//! describes the solicitation with a definition list (`<dt>Due Date</dt><dd>...</dd>`), followed by the description and
+//! a list of documents.
+use {
+    crate::{
+        award::{page_awards, US_DOLLARS},
+        opportunity::{clean_text, parse_date, parse_date_time, text_lines, Contact, DateLocale, Opportunity},
+        soup::{NodeExt, QueryBuilderExt},
+        wa_local::{document_links, links, Listing, Site, PAGINATION},
+        BoxError,
+    },
+    log::*,
+    markup5ever_rcdom::{Handle, RcDom},
+    reqwest::Url,
+    std::collections::HashMap,
+};
+
+pub(super) const SITE: Site = Site {
+    id: "Seattle",
+    jurisdiction: "City of Seattle",
+    listing_url: "https://www.seattle.gov/purchasing-and-contracting/bid-opportunities",
+    parse_listing,
+    parse_detail,
+    not_found_markers: &["this bid opportunity has closed", "the page you requested could not be found"],
+};
+
+/// Path prefix of solicitation pages.
+const DETAIL_PATH: &str = "/purchasing-and-contracting/bid-opportunities/";
+
+const LABEL_NUMBER: &str = "Solicitation Number";
+const LABEL_DEPARTMENT: &str = "Department";
+const LABEL_POSTED: &str = "Posted";
+const LABEL_DUE: &str = "Due Date";
+const LABEL_BUYER: &str = "Buyer";
+
+/// Parse the listing of open solicitations.
+fn parse_listing(document: &RcDom, page_url: &Url) -> Result<Listing, BoxError> {
+    let Some(table) = document.tag("table").attr("id", "bidOpportunities").find() else {
+        return Err(format!("Seattle bid opportunities table not found on {page_url}").into());
+    };
+
+    Ok(Listing {
+        detail_urls: links(&table, page_url, |url| url.path().starts_with(DETAIL_PATH)),
+        next_page: PAGINATION.next_url(document, page_url),
+    })
+}
+
+/// Parse a solicitation page.
+fn parse_detail(document: &RcDom, page_url: &Url, date_locale: DateLocale) -> Result<Opportunity, BoxError> {
+    let Some(title) = document.tag("h1").find().and_then(|h1| clean_text(&h1.text())) else {
+        return Err(format!("Seattle solicitation title not found on {page_url}").into());
+    };
+
+    let fields = document.tag("dl").attr("class", "bid-details").find().map(definitions).unwrap_or_default();
+    let text = |label: &str| fields.get(label).and_then(|dd| clean_text(&dd.text()));
+
+    let due_at = text(LABEL_DUE).and_then(|due| {
+        let due_at = parse_date_time(&due, date_locale);
+        if due_at.is_none() {
+            warn!("Unparseable Seattle due date {due:?} on {page_url}");
+        }
+        due_at
+    });
+
+    let description = document
+        .tag("div")
+        .attr("class", "bid-description")
+        .find()
+        .map(|div| div.tag("p").find_all().filter_map(|p| clean_text(&p.text())).collect::<Vec<_>>().join("\n"));
+
+    Ok(Opportunity {
+        url: page_url.to_string(),
+        title,
+        reference: text(LABEL_NUMBER),
+        organization: text(LABEL_DEPARTMENT),
+        contact: Contact::from_lines(&fields.get(LABEL_BUYER).map(text_lines).unwrap_or_default()),
+        published_on: text(LABEL_POSTED).and_then(|posted| parse_date(&posted, date_locale)),
+        due_at,
+        description: description.filter(|description| !description.is_empty()),
+        documents: document
+            .tag("div")
+            .attr("class", "bid-documents")
+            .find()
+            .map(|div| document_links(&div, page_url))
+            .unwrap_or_default(),
+        awards: page_awards(document, date_locale, US_DOLLARS),
+        ..Opportunity::default()
+    })
+}
+
+/// Return the `<dd>` elements of a definition list, keyed by the text of the `<dt>` preceding each.
+fn definitions(dl: Handle) -> HashMap<String, Handle> {
+    let mut fields = HashMap::new();
+    let mut label = None;
+
+    for child in dl.children().filter(|child| child.is_element()) {
+        match child.name() {
+            "dt" => label = clean_text(&child.text()),
+            "dd" => {
+                if let Some(label) = label.take() {
+                    fields.insert(label, child);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{parse_detail, parse_listing, SITE},
+        crate::{opportunity::DateLocale, soup::parse_html_str},
+        chrono::NaiveDate,
+        reqwest::Url,
+    };
+
+    #[test]
+    fn listing_page() {
+        let page_url = Url::parse(SITE.listing_url).unwrap();
+        let listing =
+            parse_listing(&parse_html_str(include_str!("seattle-bid-opportunities.html")), &page_url).unwrap();
+
+        assert_eq!(listing.detail_urls.len(), 3);
+        assert_eq!(
+            listing.detail_urls[0].as_str(),
+            "https://www.seattle.gov/purchasing-and-contracting/bid-opportunities/scl-pw-2023-017"
+        );
+        assert!(listing.next_page.is_none());
+
+        assert!(parse_listing(&parse_html_str("<p>Page not found</p>"), &page_url).is_err());
+    }
+
+    #[test]
+    fn detail_page() {
+        let page_url =
+            Url::parse("https://www.seattle.gov/purchasing-and-contracting/bid-opportunities/scl-pw-2023-017").unwrap();
+        let opportunity =
+            parse_detail(&parse_html_str(include_str!("seattle-bid-detail.html")), &page_url, DateLocale::English)
+                .unwrap();
+
+        assert_eq!(opportunity.title, "Substation Roof Replacement");
+        assert_eq!(opportunity.reference.as_deref(), Some("SCL-PW-2023-017"));
+        assert_eq!(opportunity.organization.as_deref(), Some("Seattle City Light"));
+        assert_eq!(opportunity.contact.name.as_deref(), Some("Dana Whitfield"));
+        assert_eq!(opportunity.contact.email.as_deref(), Some("dana.whitfield@seattle.gov"));
+        assert_eq!(opportunity.contact.phone.as_deref(), Some("(206) 684-0445"));
+        assert_eq!(opportunity.published_on, NaiveDate::from_ymd_opt(2023, 3, 1));
+        assert_eq!(opportunity.due_at, NaiveDate::from_ymd_opt(2023, 3, 29).unwrap().and_hms_opt(14, 0, 0));
+        assert_eq!(opportunity.description.unwrap().lines().count(), 2);
+        assert_eq!(opportunity.documents.len(), 3);
+        assert_eq!(opportunity.documents[1].name, "Addendum 1");
+    }
+}
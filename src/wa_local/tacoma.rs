@@ -0,0 +1,156 @@
+//! City of Tacoma contracting opportunities.
+//!
+//! The listing is a paged table of open specifications, each linking to a content page. A specification's page
+//! describes it with a table of labelled rows (`<th>Submittal Deadline:</th><td>...</td>`), followed by its
+//! attachments.
+use {
+    crate::{
+        award::{page_awards, US_DOLLARS},
+        opportunity::{clean_text, parse_date, parse_date_time, text_lines, Contact, DateLocale, Opportunity},
+        soup::{NodeExt, QueryBuilderExt},
+        wa_local::{document_links, links, Listing, Site, PAGINATION},
+        BoxError,
+    },
+    log::*,
+    markup5ever_rcdom::{Handle, RcDom},
+    reqwest::Url,
+    std::collections::HashMap,
+};
+
+pub(super) const SITE: Site = Site {
+    id: "Tacoma",
+    jurisdiction: "City of Tacoma",
+    listing_url: "https://www.cityoftacoma.org/government/city_departments/finance/procurement_and_payables_division/contracting_opportunities",
+    parse_listing,
+    parse_detail,
+    not_found_markers: &["this solicitation has been removed"],
+};
+
+/// Query parameter identifying a specification's content page.
+const OBJECT_ID_PARAM: &str = "objectId";
+
+const LABEL_NUMBER: &str = "Specification No.";
+const LABEL_DEPARTMENT: &str = "Department";
+const LABEL_ISSUED: &str = "Issue Date";
+const LABEL_DEADLINE: &str = "Submittal Deadline";
+const LABEL_CONTACT: &str = "Contact";
+const LABEL_DESCRIPTION: &str = "Description";
+
+/// Parse a page of the listing of open specifications.
+fn parse_listing(document: &RcDom, page_url: &Url) -> Result<Listing, BoxError> {
+    let Some(table) = document.tag("table").attr("class", "contracting-opportunities").find() else {
+        return Err(format!("Tacoma contracting opportunities table not found on {page_url}").into());
+    };
+
+    Ok(Listing {
+        detail_urls: links(&table, page_url, |url| url.query_pairs().any(|(name, _)| name == OBJECT_ID_PARAM)),
+        next_page: PAGINATION.next_url(document, page_url),
+    })
+}
+
+/// Parse a specification's page.
+fn parse_detail(document: &RcDom, page_url: &Url, date_locale: DateLocale) -> Result<Opportunity, BoxError> {
+    let Some(title) = document.tag("h1").find().and_then(|h1| clean_text(&h1.text())) else {
+        return Err(format!("Tacoma specification title not found on {page_url}").into());
+    };
+
+    let fields = document.tag("table").attr("class", "solicitation").find().map(labelled_rows).unwrap_or_default();
+    let text = |label: &str| fields.get(label).and_then(|td| clean_text(&td.text()));
+    let lines = |label: &str| fields.get(label).map(text_lines).unwrap_or_default();
+
+    let due_at = text(LABEL_DEADLINE).and_then(|deadline| {
+        let due_at = parse_date_time(&deadline, date_locale);
+        if due_at.is_none() {
+            warn!("Unparseable Tacoma submittal deadline {deadline:?} on {page_url}");
+        }
+        due_at
+    });
+
+    let description = lines(LABEL_DESCRIPTION);
+
+    Ok(Opportunity {
+        url: page_url.to_string(),
+        title,
+        reference: text(LABEL_NUMBER),
+        organization: text(LABEL_DEPARTMENT),
+        contact: Contact::from_lines(&lines(LABEL_CONTACT)),
+        published_on: text(LABEL_ISSUED).and_then(|issued| parse_date(&issued, date_locale)),
+        due_at,
+        description: if description.is_empty() {
+            None
+        } else {
+            Some(description.join("\n"))
+        },
+        documents: document
+            .tag("div")
+            .attr("class", "attachments")
+            .find()
+            .map(|div| document_links(&div, page_url))
+            .unwrap_or_default(),
+        awards: page_awards(document, date_locale, US_DOLLARS),
+        ..Opportunity::default()
+    })
+}
+
+/// Return the value cells of a table of labelled rows, keyed by their label without the trailing colon.
+fn labelled_rows(table: Handle) -> HashMap<String, Handle> {
+    let mut fields = HashMap::new();
+
+    for tr in table.tag("tr").find_all() {
+        let (Some(th), Some(td)) = (tr.tag("th").find(), tr.tag("td").find()) else {
+            continue;
+        };
+
+        if let Some(label) = clean_text(&th.text()) {
+            fields.insert(label.trim_end_matches(':').to_string(), td);
+        }
+    }
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{parse_detail, parse_listing, SITE},
+        crate::{opportunity::DateLocale, soup::parse_html_str},
+        chrono::NaiveDate,
+        reqwest::Url,
+    };
+
+    #[test]
+    fn listing_page() {
+        let page_url = Url::parse(SITE.listing_url).unwrap();
+        let listing =
+            parse_listing(&parse_html_str(include_str!("tacoma-contracting-opportunities.html")), &page_url).unwrap();
+
+        assert_eq!(listing.detail_urls.len(), 2);
+        assert_eq!(
+            listing.detail_urls[1].as_str(),
+            "https://www.cityoftacoma.org/cms/one.aspx?portalId=169&objectId=211352"
+        );
+        assert_eq!(listing.next_page.unwrap().query(), Some("page=2"));
+
+        assert!(parse_listing(&parse_html_str("<p>Page not found</p>"), &page_url).is_err());
+    }
+
+    #[test]
+    fn detail_page() {
+        let page_url = Url::parse("https://www.cityoftacoma.org/cms/one.aspx?portalId=169&objectId=211345").unwrap();
+        let opportunity =
+            parse_detail(&parse_html_str(include_str!("tacoma-solicitation.html")), &page_url, DateLocale::English)
+                .unwrap();
+
+        assert_eq!(opportunity.title, "Pipe Rehabilitation - South Tacoma Way");
+        assert_eq!(opportunity.reference.as_deref(), Some("PW23-0077F"));
+        assert_eq!(opportunity.organization.as_deref(), Some("Environmental Services"));
+        assert_eq!(opportunity.contact.name.as_deref(), Some("Procurement Specialist Lee Tran"));
+        assert_eq!(opportunity.contact.email.as_deref(), Some("ltran@cityoftacoma.org"));
+        assert_eq!(opportunity.contact.phone.as_deref(), Some("253-591-5000"));
+        assert_eq!(opportunity.published_on, NaiveDate::from_ymd_opt(2023, 3, 21));
+        assert_eq!(opportunity.due_at, NaiveDate::from_ymd_opt(2023, 4, 18).unwrap().and_hms_opt(11, 0, 0));
+        assert_eq!(opportunity.description.unwrap().lines().count(), 2);
+        assert_eq!(opportunity.documents.len(), 2);
+        assert_eq!(opportunity.documents[1].name, "Plan Holders List");
+    }
+}
@@ -1,40 +1,91 @@
 //! Request/response types for the Washington state contracting portal
 //! (WEBS: Washington's Electronic Business Solution)
+mod availability;
 mod home;
 mod login;
-mod search_opportunities;
+mod notifications;
+mod opportunity_detail;
+pub(crate) mod search_opportunities;
+
+pub(crate) use login::load_accounts;
 
 use {
     crate::{
-        httpext::{Form, LogConfig, ResponseExt},
-        shapes::{CrawlParameters, NextRequest, Operation, Request, Response},
+        attachment, closure, contacts,
+        flags::Flag,
+        health,
+        httpext::{Client, CookieStore, Form, HostAllowlist, LogConfig, Response as HttpResponse, ResponseExt},
+        journal::CrawlEvent,
+        lifecycle, mail, metrics,
+        opportunity::parse_with_structured_data,
+        pagination::{is_postback_rejection, FormEvent, PostbackLease},
+        quality,
+        retry::{RetryDelay, RetryableError, MAX_DELAY},
+        session::LoginThrottledError,
+        shadow,
+        shapes::{CrawlParameters, NextRequest, Operation, Request, Response, SUBSYS_WEBS},
         soup::parse_html_str,
+        BoxError,
     },
+    chrono::{TimeDelta, Utc},
     lambda_runtime::{Context, Error as LambdaError},
     lazy_static::lazy_static,
     log::*,
+    markup5ever_rcdom::RcDom,
     reqwest::Url,
     serde::{Deserialize, Serialize},
     std::{
+        collections::{BTreeMap, HashMap, HashSet},
         fmt::{Display, Formatter, Result as FmtResult},
         str::FromStr,
+        time::Duration,
     },
 };
 
-const DEFAULT_WEBS_BASE_URL: &str = "https://pr-webs-vendor.des.wa.gov";
+pub(crate) const DEFAULT_WEBS_BASE_URL: &str = "https://pr-webs-vendor.des.wa.gov";
 const HOME_PATH: &str = "/Home.aspx";
 const LOGIN_PATH: &str = "/LoginPage.aspx";
 const SEARCH_BID_PATH: &str = "/Search_Bid.aspx";
 
 pub(crate) const FORM_NAME_FORM1: &str = "Form1";
-const FORM_FIELD_EVENTTARGET: &str = "__EVENTTARGET";
-const FORM_FIELD_EVENTARGUMENT: &str = "__EVENTARGUMENT";
 
 const OP_START_CRAWL: &str = "StartCrawl";
 const OP_FETCH_OPPORTUNITY_LISTING_PAGE: &str = "FetchOpportunityListingPage";
 const OP_FETCH_OPPORTUNITY_DETAIL_PAGE: &str = "FetchOpportunityDetailPage";
+const OP_INGEST_NOTIFICATIONS: &str = "IngestNotifications";
+
+const CONDITION_LOGIN_THROTTLED: &str = "WebsLoginThrottled";
 const OPPORTUNITIES_INITIAL_SIZE: usize = 4096;
 
+/// Upper bound on listing pages, in case the pager never stops offering a next page.
+const MAX_LISTING_PAGES: usize = 1000;
+
+/// Phrases shown in place of an opportunity the portal no longer publishes; see [`closure`][crate::closure].
+const NOT_FOUND_MARKERS: &[&str] = &["bid opportunity not found", "this bid is no longer available"];
+
+/// How far before the last successful crawl an incremental crawl searches from. WEBS filters by date in Pacific time,
+/// so a day's overlap keeps opportunities posted late in the day from falling between crawls.
+const INCREMENTAL_OVERLAP: TimeDelta = TimeDelta::days(1);
+
+/// Session cookies expiring sooner than this after login are likely to lapse before the crawl finishes.
+const SESSION_COOKIE_EXPIRY_WARNING: Duration = Duration::from_secs(15 * 60);
+/// Version of this subsystem's parsers. Bump it whenever a parser change alters what is extracted.
+pub(crate) const PARSER_VERSION: u32 = 1;
+
+const PARSER_HOME: &str = "Webs:Home";
+const PARSER_OPPORTUNITY_LISTING: &str = "Webs:OpportunityListing";
+const PARSER_OPPORTUNITY_DETAIL: &str = "Webs:OpportunityDetail";
+const PARSER_OPPORTUNITY_DETAIL_STRUCTURED_DATA: &str = "Webs:OpportunityDetailStructuredData";
+const METRIC_LISTING_COUNT_DISCREPANCY: &str = "ListingCountDiscrepancy";
+const DIMENSION_PORTAL: &str = "Portal";
+const CANARY_FIELD_SEARCH_URL: &str = "SearchUrl";
+const CANARY_FIELD_OPPORTUNITIES: &str = "Opportunities";
+const CANARY_FIELD_FIRST_OPPORTUNITY_URL: &str = "FirstOpportunityUrl";
+const CANARY_FIELD_NEXT_PAGES: &str = "NextPages";
+
+/// Checkpoint scope for paging through opportunity listings.
+const CHECKPOINT_OPPORTUNITY_LISTING: &str = "Webs:FetchOpportunityListingPage";
+
 lazy_static! {
     static ref DEFAULT_HOME_URL: String = format!("{DEFAULT_WEBS_BASE_URL}{HOME_PATH}");
     static ref DEFAULT_LOGIN_URL: String = format!("{DEFAULT_WEBS_BASE_URL}{LOGIN_PATH}");
@@ -51,16 +102,32 @@ pub enum WebsOperation {
 
     /// Fetch an opportunity detail page.
     FetchOpportunityDetailPage,
+
+    /// Fetch the detail pages of the bids linked from WEBS notification emails stored in S3.
+    IngestNotifications,
 }
 
-/// Encapuslates an event target and event value for a form submission.
-#[derive(Clone, Debug)]
-pub struct FormEvent {
-    /// The target of the event.
-    pub target: String,
+/// Progress through the pages of an opportunity listing, saved after each page so a retry can resume.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListingCheckpoint {
+    /// The page number of the last page that was fetched and parsed.
+    last_completed_page: usize,
+
+    /// The event that fetches the next page from the last completed page, if there is one.
+    next_page: Option<FormEvent>,
+
+    /// The form fields (including the ASP.NET view state) of the last completed page, which `next_page` must be
+    /// submitted with. Empty if the last completed page is the first page.
+    #[serde(default)]
+    form_fields: HashMap<String, String>,
 
-    /// The value of the event.
-    pub argument: String,
+    /// Detail page URLs found on the completed pages.
+    opportunities: Vec<String>,
+
+    /// The total number of opportunities the portal reported on the first page.
+    #[serde(default)]
+    expected_count: Option<usize>,
 }
 
 impl FromStr for WebsOperation {
@@ -71,6 +138,7 @@ impl FromStr for WebsOperation {
             OP_START_CRAWL => Ok(WebsOperation::StartCrawl),
             OP_FETCH_OPPORTUNITY_LISTING_PAGE => Ok(WebsOperation::FetchOpportunityListingPage),
             OP_FETCH_OPPORTUNITY_DETAIL_PAGE => Ok(WebsOperation::FetchOpportunityDetailPage),
+            OP_INGEST_NOTIFICATIONS => Ok(WebsOperation::IngestNotifications),
             _ => Err(format!("Unknown operation: {value}")),
         }
     }
@@ -88,7 +156,8 @@ impl WebsOperation {
         match self {
             Self::StartCrawl => start_crawl(log_config, req, context).await,
             Self::FetchOpportunityListingPage => fetch_first_opportunity_listing_page(log_config, req, context).await,
-            Self::FetchOpportunityDetailPage => todo!("FetchOpportunityDetailPage not implemented yet"),
+            Self::FetchOpportunityDetailPage => fetch_opportunity_detail_page(log_config, req, context).await,
+            Self::IngestNotifications => ingest_notifications(log_config, req, context).await,
         }
     }
 
@@ -98,19 +167,25 @@ impl WebsOperation {
             Self::StartCrawl => OP_START_CRAWL,
             Self::FetchOpportunityListingPage => OP_FETCH_OPPORTUNITY_LISTING_PAGE,
             Self::FetchOpportunityDetailPage => OP_FETCH_OPPORTUNITY_DETAIL_PAGE,
+            Self::IngestNotifications => OP_INGEST_NOTIFICATIONS,
         }
     }
 }
 
-impl FormEvent {
-    /// Set the corresponding fields on the form.
-    pub fn set_form_fields(&self, form: &mut Form) {
-        form.set(FORM_FIELD_EVENTTARGET, &self.target);
-        form.set(FORM_FIELD_EVENTARGUMENT, &self.argument);
-    }
+/// Return the hosts a WEBS crawl starting at `url` may contact: the portal itself and the default WEBS host.
+fn allowed_hosts(url: &Url) -> HostAllowlist {
+    let default_url = Url::parse(DEFAULT_WEBS_BASE_URL).expect("DEFAULT_WEBS_BASE_URL is a valid URL");
+    HostAllowlist::from_urls([url, &default_url])
 }
 
 /// Start the WEBS crawl by visiting the login page and submitting credentials.
+///
+/// An incremental crawl only searches for opportunities posted or amended since the last successful crawl, unless the
+/// request sets its own [`posted_since`][CrawlParameters::posted_since] date. Without a successful crawl to start from,
+/// it searches all opportunities.
+///
+/// If WEBS reports that the account is locked out, or the account logged in too recently to log in again, the crawl
+/// rotates to the next configured account.
 pub(crate) async fn start_crawl(
     log_config: LogConfig,
     req: Request,
@@ -119,10 +194,170 @@ pub(crate) async fn start_crawl(
     let url_str = req.url.as_deref().unwrap_or(&DEFAULT_LOGIN_URL);
     let url = Url::parse(url_str)?;
 
-    let client = req.crawl.build_client(log_config.clone(), &context).build()?;
+    let mut crawl = req.crawl.clone();
+    if req.incremental && crawl.posted_since.is_none() {
+        match log_config.crawls.last_success(SUBSYS_WEBS).await? {
+            Some(searched_at) => crawl.posted_since = Some((searched_at - INCREMENTAL_OVERLAP).date_naive()),
+            None => info!("No successful WEBS crawl recorded; crawling all opportunities"),
+        }
+    }
+
+    let (client, cookies) = log_in_with_rotation(&log_config, &crawl, &context, &url).await?;
+
+    let start_path = if let Some(host) = url.host() {
+        let scheme = url.scheme();
+        format!("{scheme}://{host}{HOME_PATH}")
+    } else {
+        format!("{DEFAULT_WEBS_BASE_URL}{HOME_PATH}")
+    };
+
+    let next_op = NextRequest {
+        operation: Operation::Webs(WebsOperation::FetchOpportunityListingPage),
+        url: Some(start_path),
+        link_text: None,
+        crawl: crawl.child().with_cookies(cookies).with_account(client.account.clone()),
+        delay_seconds: 0,
+    };
+
+    Ok(Response {
+        next_requests: vec![next_op],
+    })
+}
+
+/// Log in to the WEBS portal at `url` with the first configured account that isn't locked out or throttled, starting
+/// from the crawl's account, returning the logged-in client and the session cookies to schedule requests with.
+///
+/// If no account is locked out but every one logged in too recently, the operation is retried once the login interval
+/// has passed.
+async fn log_in_with_rotation(
+    log_config: &LogConfig,
+    crawl: &CrawlParameters,
+    context: &Context,
+    url: &Url,
+) -> Result<(Client, CookieStore), LambdaError> {
+    let accounts = login::load_accounts(log_config).await?;
+    let crawl_id = crawl.crawl_id.clone().unwrap_or_default();
+    let mut client = None;
+    let mut locked_out = 0;
+
+    for account in login::account_rotation(&accounts, crawl.account.as_deref(), &crawl_id) {
+        match log_in(log_config, crawl, context, url, &account).await {
+            Ok(c) => {
+                client = Some(c);
+                break;
+            }
+            Err(e) if e.is::<LoginThrottledError>() => info!("Trying the next WEBS account: {e}"),
+            Err(e) => {
+                let Some(locked) = e.downcast_ref::<login::AccountLockedError>() else {
+                    return Err(e);
+                };
+
+                locked_out += 1;
+                let event = CrawlEvent::AccountLockedOut {
+                    portal: SUBSYS_WEBS.to_string(),
+                    account: locked.account.clone(),
+                };
+                log_config.journal.record(&crawl_id, event).await;
+            }
+        }
+    }
+
+    let Some(client) = client else {
+        if locked_out == 0 {
+            warn!("All {} WEBS account(s) logged in too recently; deferring the crawl", accounts.len());
+            return Err(RetryableError {
+                condition: CONDITION_LOGIN_THROTTLED,
+                url: url.to_string(),
+                delay: RetryDelay::Fixed(log_config.login_min_interval.to_std().unwrap_or(MAX_DELAY)),
+            }
+            .into());
+        }
+        return Err(format!("All {} WEBS account(s) are locked out or logged in too recently", accounts.len()).into());
+    };
+
+    let mut cookies = client.cookie_store.snapshot();
+    let pruned = cookies.retain_domain(url);
+    if pruned > 0 {
+        info!("Dropped {pruned} cookie(s) not belonging to {}", url.host_str().unwrap_or_default());
+    }
+
+    for cookie in cookies.expiring_within(SESSION_COOKIE_EXPIRY_WARNING) {
+        warn!("WEBS session cookie {} expires soon: {:?}", cookie.name(), cookie.expires);
+    }
+
+    let cookie_str = serde_json::to_string(&cookies)?;
+    debug!("Cookies: {cookie_str}");
+
+    Ok((client, cookies))
+}
+
+/// Log in to the WEBS portal at `url` as the given account, returning the logged-in client.
+///
+/// If the account logged in within the last [`login_min_interval`][LogConfig::login_min_interval], its session is
+/// reused instead; if that session can't be reused, a [`LoginThrottledError`] is returned rather than logging in again.
+async fn log_in(
+    log_config: &LogConfig,
+    crawl: &CrawlParameters,
+    context: &Context,
+    url: &Url,
+    account: &login::WebsAccount,
+) -> Result<Client, BoxError> {
+    let build_client = |crawl: &CrawlParameters| {
+        crawl
+            .build_client(log_config.clone(), context)
+            .portal(SUBSYS_WEBS)
+            .account(account.id.as_str())
+            .allowed_hosts(allowed_hosts(url))
+            .build()
+    };
+
+    let sessions = &log_config.sessions;
+    let interval = log_config.login_min_interval;
+    if let Some(session) = sessions.load(SUBSYS_WEBS, &account.id).await? {
+        if let Some(cookies) = session.reusable_cookies(Utc::now(), interval) {
+            info!("Reusing WEBS session for account {} from {}", account.id, session.last_login_at);
+            return Ok(build_client(&crawl.clone().with_cookies(cookies.clone()))?);
+        }
+    }
+
+    if !sessions.claim_login(SUBSYS_WEBS, &account.id, interval).await? {
+        warn!("WEBS account {} logged in less than {interval} ago; not logging in again", account.id);
+        return Err(LoginThrottledError {
+            portal: SUBSYS_WEBS.to_string(),
+            account: account.id.clone(),
+        }
+        .into());
+    }
+
+    let client = match submit_login(log_config, url, account, build_client(crawl)?).await {
+        Ok(client) => client,
+        Err(e) => {
+            // A failure other than a lockout says nothing about the account, so a retry may log in as it right away.
+            if !e.is::<login::AccountLockedError>() {
+                if let Err(e) = sessions.release_login(SUBSYS_WEBS, &account.id).await {
+                    warn!("Failed to release WEBS login claim for account {}: {e}", account.id);
+                }
+            }
+            return Err(e);
+        }
+    };
+
+    if let Err(e) = sessions.save_cookies(SUBSYS_WEBS, &account.id, &client.cookie_store.snapshot()).await {
+        warn!("Failed to save WEBS session for account {}: {e}", account.id);
+    }
 
+    Ok(client)
+}
+
+/// Fetch the WEBS login page at `url` with `client` and log in as the given account, returning the logged-in client.
+async fn submit_login(
+    log_config: &LogConfig,
+    url: &Url,
+    account: &login::WebsAccount,
+    client: Client,
+) -> Result<Client, BoxError> {
     // Log in to the WEBS portal so we have cookies to identify our session.
-    let response = match client.get(url.clone()).send().await {
+    let response = match client.get(url.clone()).send().await.and_then(availability::check_available) {
         Ok(r) => r,
         Err(e) => {
             error!("Failed to fetch WEBS login page: {e}");
@@ -138,50 +373,50 @@ pub(crate) async fn start_crawl(
         }
     };
 
-    info!("Submitting WEBS login");
-    let _ = login::submit_login(&client, &log_config, response).await?;
+    info!("Submitting WEBS login as account {}", account.id);
+    let _ = login::submit_login(&client, log_config, account, response).await?;
     info!("WEBS login submitted");
 
-    let cookies = client.cookie_store.read().unwrap().clone();
-    let cookie_str = serde_json::to_string(&cookies).unwrap();
-    debug!("Cookies: {cookie_str}");
-
-    let start_path = if let Some(host) = url.host() {
-        let scheme = url.scheme();
-        format!("{scheme}://{host}{HOME_PATH}")
-    } else {
-        format!("{DEFAULT_WEBS_BASE_URL}{HOME_PATH}")
-    };
-
-    let next_op = NextRequest {
-        operation: Operation::Webs(WebsOperation::FetchOpportunityListingPage),
-        url: Some(start_path),
-        crawl: CrawlParameters {
-            crawl_id: Some(client.crawl_id),
-            user_agent: req.crawl.user_agent,
-            cookies,
-        },
-    };
-
-    Ok(Response {
-        next_requests: vec![next_op],
-    })
+    Ok(client)
 }
 
-/// Visit the home page of the WEBS portal, move to the "Search Opportunities" page, then request the first page of
-/// opportunities.
+/// List the crawl's opportunities with [`list_opportunities`], holding the crawl's listing
+/// [postback lease][PostbackLease] while doing so. The search form's view state and the WEBS session can only be used
+/// by one sequence of postbacks at a time, so another delivery of this message waits for the lease and then resumes
+/// from the checkpoint with a freshly fetched form.
 async fn fetch_first_opportunity_listing_page(
     log_config: LogConfig,
     req: Request,
     context: Context,
 ) -> Result<Response, LambdaError> {
+    let Some(crawl_id) = req.crawl.crawl_id.clone() else {
+        return list_opportunities(log_config, req, context).await;
+    };
+
+    let url = req.url.as_deref().unwrap_or(&DEFAULT_HOME_URL).to_string();
+    let lease =
+        PostbackLease::acquire(&log_config.checkpoints, &crawl_id, CHECKPOINT_OPPORTUNITY_LISTING, &url).await?;
+    let result = list_opportunities(log_config, req, context).await;
+    lease.release().await;
+    result
+}
+
+/// Visit the home page of the WEBS portal, move to the "Search Opportunities" page, then request the first page of
+/// opportunities.
+async fn list_opportunities(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
     let url_str = req.url.as_deref().unwrap_or(&DEFAULT_HOME_URL);
     let url = Url::parse(url_str)?;
 
-    let client = req.crawl.build_client(log_config.clone(), &context).build()?;
+    let client = req
+        .crawl
+        .build_client(log_config.clone(), &context)
+        .portal(SUBSYS_WEBS)
+        .allowed_hosts(allowed_hosts(&url))
+        .build()?;
 
     // Visit the home page and find the Search Opportunities link.
-    let response = match client.get(url.clone()).send().await.error_for_status() {
+    let response = match client.get(url.clone()).send().await.and_then(availability::check_available).error_for_status()
+    {
         Ok(r) => r,
         Err(e) => {
             error!("Failed to fetch WEBS home page: {e}");
@@ -200,32 +435,61 @@ async fn fetch_first_opportunity_listing_page(
     let search_url = home::find_search_url(&url, text)?;
 
     // Visit the search opportunities page.
-    let response = match client.get(search_url.clone()).send().await.error_for_status() {
-        Ok(r) => r,
-        Err(e) => {
-            error!("Failed to fetch WEBS search opportunities page: {e}");
-            return Err(e);
-        }
-    };
+    let response =
+        match client.get(search_url.clone()).send().await.and_then(availability::check_available).error_for_status() {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Failed to fetch WEBS search opportunities page: {e}");
+                return Err(e);
+            }
+        };
 
     // Submit the search opportunities link.
-    let response = search_opportunities::submit_search_opps(&client, response).await?;
+    let searched_at = Utc::now();
+    let response = search_opportunities::submit_search_opps(&client, response, req.crawl.posted_since).await?;
     let mut next_requests = Vec::with_capacity(OPPORTUNITIES_INITIAL_SIZE);
 
     // Parse the first page of opportunities.
     let text = response.text()?;
     let document = parse_html_str(text);
-    search_opportunities::parse_opportunity_listing_page(&document, &search_url, &req.crawl, &mut next_requests)?;
+    parse_listing_page(&client, &document, response.url(), &search_url, &req.crawl, &mut next_requests).await?;
 
     // Parse the form element.
-    let form = Form::from_form_name(&search_url, &document, FORM_NAME_FORM1)?;
+    let mut form = Form::from_form_name(&search_url, &document, FORM_NAME_FORM1)?;
+
+    // If a previous attempt was interrupted, pick up after the last page it finished.
+    let checkpoints = &log_config.checkpoints;
+    let mut checkpoint =
+        match checkpoints.load::<ListingCheckpoint>(&client.crawl_id, CHECKPOINT_OPPORTUNITY_LISTING).await? {
+            Some(checkpoint) => {
+                info!("Resuming WEBS opportunity listing after page {}", checkpoint.last_completed_page);
+                for url in checkpoint.opportunities.iter() {
+                    next_requests.push(search_opportunities::detail_page_request(url.clone(), &req.crawl));
+                }
+                if !checkpoint.form_fields.is_empty() {
+                    form.fields = checkpoint.form_fields.clone();
+                }
+                checkpoint
+            }
+            None => ListingCheckpoint {
+                last_completed_page: search_opportunities::current_page(&document).unwrap_or(1),
+                next_page: search_opportunities::PAGINATION.next_postback(&document, &search_url),
+                expected_count: search_opportunities::parse_result_count(&document),
+                ..Default::default()
+            },
+        };
 
-    for form_event in search_opportunities::find_opportunity_next_pages(&document)? {
-        // Visit this search opportunity page by submitting the form with these values.
-        let mut form = form.clone();
-        form_event.set_form_fields(&mut form);
+    // Each page is requested from the page before it, since WEBS only links to a window of pages at a time.
+    let mut complete = true;
+    while let Some(form_event) = checkpoint.next_page.take() {
+        if checkpoint.last_completed_page >= MAX_LISTING_PAGES {
+            warn!("Stopping WEBS opportunity listing after {MAX_LISTING_PAGES} pages");
+            complete = false;
+            break;
+        }
 
-        let response = match client.request(form.method, form.url).form(&form.fields).send().await.error_for_status() {
+        // Visit this search opportunity page by submitting the form with these values.
+        let response = match submit_listing_postback(&client, &form, &form_event, &search_url, &req.crawl).await {
             Ok(r) => r,
             Err(e) => {
                 error!("Failed to submit WEBS search opportunities form: {e}");
@@ -236,10 +500,421 @@ async fn fetch_first_opportunity_listing_page(
         // Parse this page of opportunities.
         let text = response.text()?;
         let document = parse_html_str(text);
-        search_opportunities::parse_opportunity_listing_page(&document, &search_url, &req.crawl, &mut next_requests)?;
+        let before = next_requests.len();
+        parse_listing_page(&client, &document, response.url(), &search_url, &req.crawl, &mut next_requests).await?;
+
+        let page = checkpoint.last_completed_page + 1;
+        match search_opportunities::current_page(&document) {
+            Some(current) if current != page => {
+                warn!("Expected WEBS opportunity listing page {page} but received page {current}; stopping");
+                complete = false;
+                break;
+            }
+            _ => (),
+        }
+
+        form = Form::from_form_name(&search_url, &document, FORM_NAME_FORM1)?;
+        checkpoint.last_completed_page = page;
+        checkpoint.next_page = search_opportunities::PAGINATION.next_postback(&document, &search_url);
+        checkpoint.form_fields = form.fields.clone();
+        checkpoint.opportunities.extend(next_requests[before..].iter().filter_map(|r| r.url.clone()));
+        checkpoints.save(&client.crawl_id, CHECKPOINT_OPPORTUNITY_LISTING, &checkpoint).await?;
+    }
+
+    // Resumed crawls re-parse the first page, so drop any repeated opportunities.
+    let mut seen = HashSet::with_capacity(next_requests.len());
+    next_requests.retain(|r| seen.insert(r.url.clone()));
+
+    if let Err(e) = checkpoints.clear(&client.crawl_id, CHECKPOINT_OPPORTUNITY_LISTING).await {
+        warn!("Failed to clear WEBS opportunity listing checkpoint: {e}");
+    }
+
+    if let Some(expected) = checkpoint.expected_count {
+        validate_listing_count(&log_config, &client.crawl_id, expected, next_requests.len()).await;
+    }
+
+    // Only a listing read to the end can be picked up from by incremental crawls.
+    if complete {
+        if let Err(e) = log_config.crawls.record_success(SUBSYS_WEBS, &client.crawl_id, searched_at).await {
+            warn!("Failed to record WEBS crawl {} as successful: {e}", client.crawl_id);
+        }
+    }
+
+    // This is the end of the WEBS crawl; make sure it looks sane.
+    health::check_crawl_health(
+        &log_config,
+        SUBSYS_WEBS,
+        &client.crawl_id,
+        &client.stats.snapshot(),
+        &client.stats.host_latencies(),
+        next_requests.len(),
+    )
+    .await;
+
+    Ok(Response {
+        next_requests,
+    })
+}
+
+/// Submit a postback of the search opportunities form. If WEBS rejects the form's view state or event validation, as it
+/// often does under load, search again for a fresh copy of the form, reapply the event, and retry once.
+async fn submit_listing_postback(
+    client: &Client,
+    form: &Form,
+    event: &FormEvent,
+    search_url: &Url,
+    crawl: &CrawlParameters,
+) -> Result<HttpResponse, BoxError> {
+    let response = send_postback(client, form, event).await?;
+    if !is_postback_rejection(response.status(), response.text().unwrap_or_default()) {
+        return response.error_for_status();
+    }
+
+    warn!("WEBS rejected the postback to {} as stale; retrying with a fresh search form", event.target);
+    let search_page =
+        client.get(search_url.clone()).send().await.and_then(availability::check_available).error_for_status()?;
+    let results = search_opportunities::submit_search_opps(client, search_page, crawl.posted_since).await?;
+    let fresh_form = Form::from_unparsed_form_name(search_url, results.text()?, FORM_NAME_FORM1)?;
+
+    send_postback(client, &fresh_form, event).await?.error_for_status()
+}
+
+/// Submit a form with a postback event.
+async fn send_postback(client: &Client, form: &Form, event: &FormEvent) -> Result<HttpResponse, BoxError> {
+    let mut form = form.clone();
+    event.set_form_fields(&mut form);
+    client.request(form.method, form.url).form(&form.fields).send().await.and_then(availability::check_available)
+}
+
+/// Fetch an opportunity detail page and parse it into the normalized opportunity model.
+async fn fetch_opportunity_detail_page(
+    log_config: LogConfig,
+    req: Request,
+    context: Context,
+) -> Result<Response, LambdaError> {
+    let Some(url_str) = req.url.as_deref() else {
+        return Err("FetchOpportunityDetailPage requires a URL".into());
+    };
+    let url = Url::parse(url_str)?;
+
+    let client = req
+        .crawl
+        .build_client(log_config.clone(), &context)
+        .portal(SUBSYS_WEBS)
+        .allowed_hosts(allowed_hosts(&url))
+        .build()?;
+
+    let response = match client.get(url.clone()).send().await.and_then(availability::check_available).error_for_status()
+    {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to fetch WEBS opportunity detail page: {e}");
+            return Err(e);
+        }
+    };
+
+    let Some(response) = attachment::html_page(&log_config, &req.crawl, response, Some(url.as_str())).await else {
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    };
+
+    if let Some(reason) =
+        closure::soft_not_found(response.text()?, NOT_FOUND_MARKERS, &req.crawl.locale.not_found_markers)
+    {
+        closure::record_closed(&log_config, &client, SUBSYS_WEBS, url.as_str(), &reason).await?;
+        return Ok(Response {
+            next_requests: vec![],
+        });
+    }
+
+    let document = parse_html_str(response.text()?);
+    let parse = || opportunity_detail::parse_opportunity_detail_page(&document, response.url(), req.crawl.locale.dates);
+    let structured = || parse_with_structured_data(&document, response.url(), req.crawl.locale.dates, parse);
+    let result = if log_config.flags.is_enabled(Flag::WebsStructuredData) {
+        structured()
+    } else {
+        parse()
+    };
+
+    let event = match &result {
+        Ok(_) => CrawlEvent::ParseSucceeded {
+            url: response.url().to_string(),
+            parser: PARSER_OPPORTUNITY_DETAIL.to_string(),
+            items: 1,
+        },
+        Err(e) => CrawlEvent::ParseFailed {
+            url: response.url().to_string(),
+            parser: PARSER_OPPORTUNITY_DETAIL.to_string(),
+            error: e.to_string(),
+        },
+    };
+    log_config.journal.record(&client.crawl_id, event).await;
+
+    if let Ok(stable) = &result {
+        if !log_config.flags.is_enabled(Flag::WebsStructuredData)
+            && log_config.flags.is_enabled(Flag::WebsStructuredDataShadow)
+        {
+            shadow::run_shadow(
+                &log_config,
+                &client.crawl_id,
+                response.url().as_str(),
+                PARSER_OPPORTUNITY_DETAIL,
+                PARSER_OPPORTUNITY_DETAIL_STRUCTURED_DATA,
+                stable,
+                structured,
+            )
+            .await;
+        }
+    }
+
+    let mut opportunity =
+        result?.with_parser(PARSER_OPPORTUNITY_DETAIL, PARSER_VERSION).with_tenant(client.tenant.as_deref());
+    contacts::record_contacts(&log_config, &client, &mut opportunity, response.text()?).await;
+    lifecycle::record_stage(&log_config, &client, &mut opportunity, response.text()?).await;
+    quality::record_quality(&log_config, &client, &mut opportunity).await;
+    closure::record_seen(&log_config, &client, &opportunity).await;
+    info!("WEBS opportunity: {}", serde_json::to_string(&opportunity)?);
+
+    Ok(Response {
+        next_requests: vec![],
+    })
+}
+
+/// Read the WEBS notification emails stored under the request's `s3://bucket/prefix` URL and schedule a fetch of the
+/// detail page of each bid they link to.
+///
+/// Detail pages are only shown to logged-in vendors, so the operation logs in as a crawl would when there are bids to
+/// fetch. Emails are deleted once their bids are scheduled; if logging in fails, they are left for the next run.
+async fn ingest_notifications(log_config: LogConfig, req: Request, context: Context) -> Result<Response, LambdaError> {
+    let Some(url_str) = req.url.as_deref() else {
+        return Err("IngestNotifications requires an s3://bucket/prefix URL".into());
+    };
+    let (bucket, prefix) = mail::parse_location(&Url::parse(url_str)?)?;
+
+    let emails = mail::read_messages(&log_config, &bucket, &prefix).await?;
+    let mut bid_ids: Vec<u64> = vec![];
+    for email in emails.iter() {
+        let ids = notifications::parse_bid_ids(&email.message);
+        if ids.is_empty() {
+            warn!("No WEBS bids found in notification s3://{bucket}/{}", email.key);
+        }
+
+        for id in ids {
+            if !bid_ids.contains(&id) {
+                bid_ids.push(id);
+            }
+        }
+    }
+
+    let mut next_requests = vec![];
+    if !bid_ids.is_empty() {
+        let login_url = Url::parse(&DEFAULT_LOGIN_URL)?;
+        let (client, cookies) = log_in_with_rotation(&log_config, &req.crawl, &context, &login_url).await?;
+        let crawl = req.crawl.clone().with_cookies(cookies).with_account(client.account.clone());
+        next_requests.extend(
+            bid_ids
+                .iter()
+                .map(|id| search_opportunities::detail_page_request(notifications::detail_page_url(*id), &crawl)),
+        );
+    }
+
+    info!("Read {} WEBS notification(s) linking to {} bid(s)", emails.len(), bid_ids.len());
+
+    for email in emails.iter() {
+        if let Err(e) = mail::delete_message(&log_config, &bucket, &email.key).await {
+            warn!("Failed to delete WEBS notification {}: {e}", email.key);
+        }
     }
 
     Ok(Response {
         next_requests,
     })
 }
+
+/// Compare the number of opportunities extracted against the total WEBS reported, recording and alerting on any
+/// discrepancy. A shortfall almost always means the pager or row parsing has broken.
+async fn validate_listing_count(log_config: &LogConfig, crawl_id: &str, expected: usize, extracted: usize) {
+    let discrepancy = expected.abs_diff(extracted);
+    metrics::registry().increment(
+        METRIC_LISTING_COUNT_DISCREPANCY,
+        &[(DIMENSION_PORTAL, SUBSYS_WEBS)],
+        discrepancy as u64,
+    );
+
+    if discrepancy == 0 {
+        info!("Extracted all {expected} WEBS opportunities");
+        return;
+    }
+
+    log_config
+        .journal
+        .record(
+            crawl_id,
+            CrawlEvent::ListingCountMismatch {
+                parser: PARSER_OPPORTUNITY_LISTING.to_string(),
+                expected,
+                extracted,
+            },
+        )
+        .await;
+
+    let message = format!("Crawl {crawl_id}: WEBS reported {expected} opportunities, but {extracted} were extracted");
+    let _ = health::publish_alert(log_config, "GovScout WEBS listing count mismatch", &message).await;
+}
+
+/// Parse an opportunity listing page, recording the outcome in the crawl journal.
+async fn parse_listing_page(
+    client: &Client,
+    document: &RcDom,
+    page_url: &Url,
+    search_url: &Url,
+    crawl_parameters: &CrawlParameters,
+    next_requests: &mut Vec<NextRequest>,
+) -> Result<(), BoxError> {
+    let before = next_requests.len();
+    let result =
+        search_opportunities::parse_opportunity_listing_page(document, search_url, crawl_parameters, next_requests);
+
+    if let Some(log_config) = client.log_config.as_ref() {
+        let event = match &result {
+            Ok(()) => CrawlEvent::ParseSucceeded {
+                url: page_url.to_string(),
+                parser: PARSER_OPPORTUNITY_LISTING.to_string(),
+                items: next_requests.len() - before,
+            },
+            Err(e) => CrawlEvent::ParseFailed {
+                url: page_url.to_string(),
+                parser: PARSER_OPPORTUNITY_LISTING.to_string(),
+                error: e.to_string(),
+            },
+        };
+        log_config.journal.record(&client.crawl_id, event).await;
+    }
+
+    result
+}
+
+/// Extract the key fields the canary checks from a WEBS page.
+///
+/// Supported parsers are `Webs:Home` and `Webs:OpportunityListing`.
+pub(crate) fn extract_canary_fields(
+    parser: &str,
+    page_url: &Url,
+    text: &str,
+) -> Result<BTreeMap<String, String>, BoxError> {
+    let mut fields = BTreeMap::new();
+
+    match parser {
+        PARSER_HOME => {
+            let search_url = home::find_search_url(page_url, text)?;
+            fields.insert(CANARY_FIELD_SEARCH_URL.to_string(), search_url.to_string());
+        }
+        PARSER_OPPORTUNITY_LISTING => {
+            let document = parse_html_str(text);
+            let crawl_parameters = CrawlParameters::default();
+            let mut next_requests = vec![];
+            search_opportunities::parse_opportunity_listing_page(
+                &document,
+                page_url,
+                &crawl_parameters,
+                &mut next_requests,
+            )?;
+            let next_pages = search_opportunities::find_opportunity_next_pages(&document)?;
+
+            fields.insert(CANARY_FIELD_OPPORTUNITIES.to_string(), next_requests.len().to_string());
+            fields.insert(CANARY_FIELD_NEXT_PAGES.to_string(), next_pages.len().to_string());
+            if let Some(url) = next_requests.first().and_then(|r| r.url.clone()) {
+                fields.insert(CANARY_FIELD_FIRST_OPPORTUNITY_URL.to_string(), url);
+            }
+        }
+        _ => return Err(format!("Unknown WEBS parser {parser}").into()),
+    }
+
+    Ok(fields)
+}
+
+/// Parse a WEBS snapshot for golden-file testing. `parser` is the name of the snapshot directory.
+#[cfg(any(test, feature = "test-utils"))]
+pub fn parse_snapshot(parser: &str, text: &str) -> Result<serde_json::Value, BoxError> {
+    use {crate::opportunity::DateLocale, serde_json::json};
+
+    const SNAPSHOT_DETAIL_PATH: &str = "/Search_BidDetails.aspx";
+    let base_url = Url::parse(DEFAULT_WEBS_BASE_URL)?;
+
+    match parser {
+        "home" => {
+            let search_url = home::find_search_url(&base_url, text)?;
+            Ok(json!({ "SearchUrl": search_url.to_string() }))
+        }
+        "opportunity-listing" => {
+            let page_url = base_url.join(SEARCH_BID_PATH)?;
+            let document = parse_html_str(text);
+            let crawl_parameters = CrawlParameters::default();
+            let mut next_requests = vec![];
+            search_opportunities::parse_opportunity_listing_page(
+                &document,
+                &page_url,
+                &crawl_parameters,
+                &mut next_requests,
+            )?;
+            let next_pages = search_opportunities::find_opportunity_next_pages(&document)?;
+
+            Ok(json!({
+                "ResultCount": search_opportunities::parse_result_count(&document),
+                "Opportunities": next_requests.iter().map(|r| r.url.clone()).collect::<Vec<_>>(),
+                "NextPages": next_pages
+                    .iter()
+                    .map(|e| json!({ "Target": e.target, "Argument": e.argument }))
+                    .collect::<Vec<_>>(),
+            }))
+        }
+        "opportunity-detail" => {
+            let page_url = base_url.join(SNAPSHOT_DETAIL_PATH)?;
+            let opportunity = opportunity_detail::parse_opportunity_detail_page(
+                &parse_html_str(text),
+                &page_url,
+                DateLocale::default(),
+            )?;
+            Ok(serde_json::to_value(opportunity)?)
+        }
+        _ => Err(format!("Unknown WEBS snapshot parser {parser}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{parse_snapshot, FormEvent, ListingCheckpoint, SUBSYS_WEBS},
+        crate::golden::{check_snapshots, snapshot_dir},
+        std::collections::HashMap,
+    };
+
+    #[test_log::test]
+    fn golden_snapshots() {
+        let checked = check_snapshots(&snapshot_dir(&SUBSYS_WEBS.to_lowercase()), parse_snapshot)
+            .unwrap_or_else(|e| panic!("Golden snapshot mismatch:\n{e}"));
+        assert!(checked >= 3);
+    }
+
+    #[test]
+    fn listing_checkpoint_format() {
+        let checkpoint = ListingCheckpoint {
+            last_completed_page: 1,
+            next_page: Some(FormEvent {
+                target: "DataGrid1$_ctl104$_ctl1".to_string(),
+                argument: "".to_string(),
+            }),
+            form_fields: HashMap::new(),
+            opportunities: vec!["https://example.com/Opp.aspx?id=1".to_string()],
+            expected_count: Some(253),
+        };
+
+        let json = serde_json::to_string(&checkpoint).unwrap();
+        assert_eq!(
+            json.as_str(),
+            r#"{"LastCompletedPage":1,"NextPage":{"Target":"DataGrid1$_ctl104$_ctl1","Argument":""},"FormFields":{},"Opportunities":["https://example.com/Opp.aspx?id=1"],"ExpectedCount":253}"#
+        );
+        assert_eq!(serde_json::from_str::<ListingCheckpoint>(&json).unwrap(), checkpoint);
+    }
+}
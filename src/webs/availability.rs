@@ -0,0 +1,112 @@
+//! Detection of WEBS pages that mean the portal is temporarily unavailable.
+//!
+//! Some nights WEBS replaces every page with a static scheduled-maintenance notice, and under load ASP.NET answers
+//! with its "Server Too Busy" or "Runtime Error" page, served with either a success or an error status. Parsed as a
+//! listing, any of these looks like a search with no results, so each response is checked before it is parsed.
+use {
+    crate::{
+        httpext::Response as HttpResponse,
+        metrics,
+        retry::{RetryDelay, RetryableError},
+        shapes::SUBSYS_WEBS,
+        BoxError,
+    },
+    log::*,
+    std::time::Duration,
+};
+
+/// Condition name for the scheduled-maintenance notice.
+pub(crate) const CONDITION_MAINTENANCE: &str = "WebsMaintenance";
+
+/// Condition name for the ASP.NET "Server Too Busy" and "Runtime Error" pages.
+pub(crate) const CONDITION_SERVER_BUSY: &str = "WebsServerBusy";
+
+/// How long to wait before retrying after a maintenance notice. Maintenance windows last hours, so this is as long as
+/// the queue allows.
+const MAINTENANCE_RETRY_DELAY: Duration = Duration::from_secs(900);
+
+/// How long to wait before the first retry after a busy page; this doubles with each further attempt.
+const SERVER_BUSY_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+const METRIC_SERVER_BUSY_RESPONSES: &str = "ServerBusyResponses";
+const DIMENSION_PORTAL: &str = "Portal";
+
+/// Phrases found on the maintenance notice.
+const MAINTENANCE_PHRASES: &[&str] =
+    &["scheduled maintenance", "down for maintenance", "undergoing maintenance", "unavailable for maintenance"];
+
+/// Titles of the ASP.NET error pages returned when the server is overloaded.
+const SERVER_BUSY_TITLES: &[&str] = &["<title>server too busy</title>", "<title>runtime error</title>"];
+
+/// Marker of the form every real WEBS page is built around. Its presence means the page is neither the maintenance
+/// notice nor an error page, even if an opportunity description happens to mention maintenance.
+const WEBS_FORM_MARKER: &str = r#"name="form1""#;
+
+/// A reason WEBS is unavailable.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Unavailable {
+    /// The scheduled-maintenance notice.
+    Maintenance,
+
+    /// An ASP.NET "Server Too Busy" or "Runtime Error" page.
+    ServerBusy,
+}
+
+/// Return why the page shows WEBS to be unavailable, if it does.
+pub(crate) fn unavailable(text: &str) -> Option<Unavailable> {
+    let text = text.to_lowercase();
+    if text.contains(WEBS_FORM_MARKER) {
+        return None;
+    }
+
+    if SERVER_BUSY_TITLES.iter().any(|title| text.contains(title)) {
+        Some(Unavailable::ServerBusy)
+    } else if MAINTENANCE_PHRASES.iter().any(|phrase| text.contains(phrase)) {
+        Some(Unavailable::Maintenance)
+    } else {
+        None
+    }
+}
+
+/// Pass a response through unless it shows that WEBS is unavailable, in which case return a [`RetryableError`].
+///
+/// This is checked before the HTTP status, since these pages may be served with either a success or an error status.
+pub(crate) fn check_available(response: HttpResponse) -> Result<HttpResponse, BoxError> {
+    let (condition, delay) = match unavailable(response.text().unwrap_or_default()) {
+        None => return Ok(response),
+        Some(Unavailable::Maintenance) => {
+            warn!("WEBS is down for maintenance at {}", response.url());
+            (CONDITION_MAINTENANCE, RetryDelay::Fixed(MAINTENANCE_RETRY_DELAY))
+        }
+        Some(Unavailable::ServerBusy) => {
+            warn!("WEBS is too busy to serve {} (HTTP {})", response.url(), response.status());
+            metrics::registry().increment(METRIC_SERVER_BUSY_RESPONSES, &[(DIMENSION_PORTAL, SUBSYS_WEBS)], 1);
+            (CONDITION_SERVER_BUSY, RetryDelay::Exponential(SERVER_BUSY_RETRY_DELAY))
+        }
+    };
+
+    Err(RetryableError {
+        condition,
+        url: response.url().to_string(),
+        delay,
+    }
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unavailable, Unavailable};
+
+    #[test]
+    fn unavailable_detection() {
+        assert_eq!(unavailable(include_str!("webs-maintenance.html")), Some(Unavailable::Maintenance));
+        assert_eq!(unavailable(include_str!("aspnet-server-too-busy.html")), Some(Unavailable::ServerBusy));
+        assert_eq!(unavailable(include_str!("aspnet-runtime-error.html")), Some(Unavailable::ServerBusy));
+        assert_eq!(unavailable(include_str!("webs-home.html")), None);
+        assert_eq!(unavailable(include_str!("webs-search-bids-page1.html")), None);
+        assert_eq!(unavailable(include_str!("webs-opp-detail1.html")), None);
+
+        // Real pages that mention maintenance are not the notice.
+        assert_eq!(unavailable(r#"<form name="Form1">Scheduled maintenance of HVAC systems</form>"#), None);
+    }
+}
@@ -1,22 +1,208 @@
 //! WEBS login page handling.
+//!
+//! Credentials are stored in SSM under the SSM prefix, either as numbered accounts
+//! (`Webs/Accounts/<n>/Username` and `Webs/Accounts/<n>/Password`) or, for deployments with a single account, as
+//! `Webs/Username` and `Webs/Password`.
 use {
     crate::{
-        httpext::{Client, Form, LogConfig, Response as HttpResponse, ResponseExt},
-        webs::FORM_NAME_FORM1,
+        httpext::{aws_err_str, Client, Form, LogConfig, Response as HttpResponse, ResponseExt},
+        webs::{availability, FORM_NAME_FORM1},
         BoxError,
     },
     log::*,
+    reqwest::StatusCode,
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
 };
 
 const SSM_WEBS_USERNAME_PARAM: &str = "Webs/Username";
 const SSM_WEBS_PASSWORD_PARAM: &str = "Webs/Password";
+const SSM_WEBS_ACCOUNTS_PATH: &str = "Webs/Accounts/";
+const SSM_USERNAME_LEAF: &str = "Username";
+const SSM_PASSWORD_LEAF: &str = "Password";
 const WEBS_TXT_EMAIL_PARAM: &str = "txtEmail";
 const WEBS_TXT_PASSWORD_PARAM: &str = "txtPassword";
 
-/// Submit the login form to the WEBS portal.
+/// The account id used for the unnumbered `Webs/Username` and `Webs/Password` parameters.
+pub(crate) const DEFAULT_ACCOUNT_ID: &str = "default";
+
+/// Phrases on the page returned by a login attempt that indicate the account has been locked or throttled.
+const LOCKOUT_PHRASES: &[&str] = &["account has been locked", "account is locked", "locked out", "too many attempts"];
+
+/// A WEBS account whose credentials are stored in SSM.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct WebsAccount {
+    /// The account id: the account number, or [`DEFAULT_ACCOUNT_ID`].
+    pub(crate) id: String,
+
+    /// The SSM parameter holding the username, relative to the SSM prefix.
+    username_param: String,
+
+    /// The SSM parameter holding the password, relative to the SSM prefix.
+    password_param: String,
+}
+
+/// WEBS refused a login because the account is locked out or rate limited.
+#[derive(Debug)]
+pub(crate) struct AccountLockedError {
+    /// The account that was refused.
+    pub(crate) account: String,
+}
+
+impl Display for AccountLockedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "WEBS account {} is locked out", self.account)
+    }
+}
+
+impl Error for AccountLockedError {}
+
+impl WebsAccount {
+    /// Return the account stored under `Webs/Accounts/<id>/`.
+    fn numbered(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            username_param: format!("{SSM_WEBS_ACCOUNTS_PATH}{id}/{SSM_USERNAME_LEAF}"),
+            password_param: format!("{SSM_WEBS_ACCOUNTS_PATH}{id}/{SSM_PASSWORD_LEAF}"),
+        }
+    }
+
+    /// Return the account stored in the unnumbered parameters.
+    fn default_account() -> Self {
+        Self {
+            id: DEFAULT_ACCOUNT_ID.to_string(),
+            username_param: SSM_WEBS_USERNAME_PARAM.to_string(),
+            password_param: SSM_WEBS_PASSWORD_PARAM.to_string(),
+        }
+    }
+}
+
+/// Return the ids of the numbered accounts with both a username and a password among the given parameter names
+/// (relative to the accounts path), in numeric order.
+fn account_ids<'a, I: IntoIterator<Item = &'a str>>(names: I) -> Vec<String> {
+    let mut leaves: BTreeMap<u32, BTreeSet<&str>> = BTreeMap::new();
+    for name in names {
+        let Some((id, leaf)) = name.split_once('/') else {
+            continue;
+        };
+
+        match id.parse() {
+            Ok(id) => {
+                leaves.entry(id).or_default().insert(leaf);
+            }
+            Err(_) => warn!("Ignoring WEBS account parameter with a non-numeric account id: {name}"),
+        }
+    }
+
+    leaves
+        .into_iter()
+        .filter(|(_, leaves)| leaves.contains(SSM_USERNAME_LEAF) && leaves.contains(SSM_PASSWORD_LEAF))
+        .map(|(id, _)| id.to_string())
+        .collect()
+}
+
+/// Load the WEBS accounts configured in SSM, falling back to the unnumbered parameters if there are no numbered
+/// accounts. Returns an error if no account is configured.
+pub(crate) async fn load_accounts(log_config: &LogConfig) -> Result<Vec<WebsAccount>, BoxError> {
+    let path = format!("{}{SSM_WEBS_ACCOUNTS_PATH}", log_config.ssm_prefix);
+    let mut names = Vec::new();
+    let mut next_token = None;
+
+    loop {
+        let result = log_config
+            .ssm_client
+            .get_parameters_by_path()
+            .path(&path)
+            .recursive(true)
+            .set_next_token(next_token)
+            .send()
+            .await;
+
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => {
+                error!("GetParametersByPath {path}: {}", aws_err_str(&e));
+                return Err(e.into());
+            }
+        };
+
+        names.extend(output.parameters().iter().filter_map(|p| p.name()?.strip_prefix(&path)).map(str::to_string));
+        next_token = output.next_token;
+        if next_token.is_none() {
+            break;
+        }
+    }
+
+    let accounts: Vec<WebsAccount> =
+        account_ids(names.iter().map(String::as_str)).iter().map(|id| WebsAccount::numbered(id)).collect();
+    if !accounts.is_empty() {
+        debug!("Found {} WEBS account(s) under {path}", accounts.len());
+        return Ok(accounts);
+    }
+
+    // No numbered accounts; check that the unnumbered parameters exist.
+    let account = WebsAccount::default_account();
+    let result = log_config
+        .ssm_client
+        .get_parameters()
+        .names(format!("{}{}", log_config.ssm_prefix, account.username_param))
+        .names(format!("{}{}", log_config.ssm_prefix, account.password_param))
+        .send()
+        .await;
+
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => {
+            error!("GetParameters WEBS credentials: {}", aws_err_str(&e));
+            return Err(e.into());
+        }
+    };
+
+    if !output.invalid_parameters().is_empty() {
+        return Err(format!("No WEBS accounts configured; missing {}", output.invalid_parameters().join(", ")).into());
+    }
+
+    Ok(vec![account])
+}
+
+/// Return the order in which to try the accounts for a crawl: starting with the preferred account if it is
+/// configured, otherwise with one chosen from the crawl id so crawls are spread across accounts, then the rest in turn.
+pub(crate) fn account_rotation(accounts: &[WebsAccount], preferred: Option<&str>, crawl_id: &str) -> Vec<WebsAccount> {
+    if accounts.is_empty() {
+        return vec![];
+    }
+
+    let start = match preferred.and_then(|id| accounts.iter().position(|a| a.id == id)) {
+        Some(start) => start,
+        None => {
+            let hash = crawl_id.bytes().fold(0usize, |hash, b| hash.wrapping_mul(31).wrapping_add(b as usize));
+            hash % accounts.len()
+        }
+    };
+
+    accounts[start..].iter().chain(accounts[..start].iter()).cloned().collect()
+}
+
+/// Indicates whether the page returned by a login attempt says the account is locked out.
+pub(crate) fn is_locked_out(status: StatusCode, text: &str) -> bool {
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return true;
+    }
+
+    let text = text.to_lowercase();
+    LOCKOUT_PHRASES.iter().any(|phrase| text.contains(phrase))
+}
+
+/// Submit the login form to the WEBS portal as the given account.
+///
+/// Returns an [`AccountLockedError`] if WEBS reports that the account is locked out.
 pub(crate) async fn submit_login(
     client: &Client,
     log_config: &LogConfig,
+    account: &WebsAccount,
     response: HttpResponse,
 ) -> Result<HttpResponse, BoxError> {
     let url = response.url().clone();
@@ -38,13 +224,27 @@ pub(crate) async fn submit_login(
         }
     };
 
-    let username = log_config.get_parameter(SSM_WEBS_USERNAME_PARAM).await?;
-    let password = log_config.get_parameter(SSM_WEBS_PASSWORD_PARAM).await?;
+    let username = log_config.get_parameter(&account.username_param).await?;
+    let password = log_config.get_parameter(&account.password_param).await?;
 
     form.set(WEBS_TXT_EMAIL_PARAM, username);
     form.set(WEBS_TXT_PASSWORD_PARAM, password);
 
-    let response = match client.request(form.method, form.url).form(&form.fields).send().await.error_for_status() {
+    let response = client
+        .request(form.method, form.url)
+        .form(&form.fields)
+        .send()
+        .await
+        .and_then(availability::check_available)?;
+    if is_locked_out(response.status(), response.text().unwrap_or_default()) {
+        warn!("WEBS account {} is locked out", account.id);
+        return Err(AccountLockedError {
+            account: account.id.clone(),
+        }
+        .into());
+    }
+
+    let response = match response.error_for_status() {
         Ok(r) => r,
         Err(e) => {
             error!("Failed to submit WEBS login form: {e}");
@@ -54,3 +254,35 @@ pub(crate) async fn submit_login(
 
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{account_ids, account_rotation, is_locked_out, WebsAccount},
+        reqwest::StatusCode,
+    };
+
+    #[test]
+    fn accounts() {
+        let ids = account_ids(["1/Username", "1/Password", "0/Username", "0/Password", "2/Username", "x/Username"]);
+        assert_eq!(ids, vec!["0", "1"]);
+
+        let accounts: Vec<WebsAccount> = ["0", "1", "2"].iter().map(|id| WebsAccount::numbered(id)).collect();
+        assert_eq!(accounts[1].username_param, "Webs/Accounts/1/Username");
+
+        let order: Vec<String> = account_rotation(&accounts, Some("1"), "crawl").into_iter().map(|a| a.id).collect();
+        assert_eq!(order, vec!["1", "2", "0"]);
+
+        // Without a preference, the starting account depends only on the crawl id.
+        let first = account_rotation(&accounts, None, "crawl")[0].id.clone();
+        assert_eq!(account_rotation(&accounts, Some("9"), "crawl")[0].id, first);
+        assert_eq!(account_rotation(&accounts, None, "crawl").len(), 3);
+    }
+
+    #[test]
+    fn lockout_detection() {
+        assert!(is_locked_out(StatusCode::TOO_MANY_REQUESTS, ""));
+        assert!(is_locked_out(StatusCode::OK, "<p>Your Account Has Been Locked.</p>"));
+        assert!(!is_locked_out(StatusCode::OK, "<p>Welcome back</p>"));
+    }
+}
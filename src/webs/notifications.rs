@@ -0,0 +1,78 @@
+//! Bid notification emails sent by WEBS.
+//!
+//! WEBS emails registered vendors when bids matching their commodity codes are posted or amended. Those emails are
+//! received by SES and stored as raw messages in an S3 bucket; the `Webs:IngestNotifications` operation reads them
+//! from the bucket and prefix given as its URL (`s3://bucket/prefix/`) with the [`mail`][crate::mail] subsystem's
+//! functions, picks out the bids they link to, and fetches just those detail pages, so changes show up between
+//! scheduled crawls. Unlike most [mail extractors][crate::mail::MailExtractor], it logs in before scheduling them.
+use {
+    super::{search_opportunities::WEBS_DETAIL_PAGE, DEFAULT_WEBS_BASE_URL},
+    crate::mail::MailMessage,
+};
+
+/// The path of a bid's detail page.
+const DETAIL_PATH: &str = "/Search_BidDetails.aspx";
+
+/// The query parameter of a detail page URL holding the bid's id.
+const WEBS_DETAIL_ID_PARAM: &str = "ID=";
+
+/// Return the ids of the bids a notification email links to, in the order they first appear.
+pub(crate) fn parse_bid_ids(message: &MailMessage) -> Vec<u64> {
+    let page = WEBS_DETAIL_PAGE;
+    let param = WEBS_DETAIL_ID_PARAM.to_ascii_lowercase();
+    let mut bid_ids = vec![];
+
+    for text in message.bodies().map(str::to_ascii_lowercase) {
+        for (start, _) in text.match_indices(page) {
+            let Some(query) = text[start + page.len()..].strip_prefix('?') else {
+                continue;
+            };
+
+            let query =
+                &query[..query.find(|c: char| c.is_whitespace() || "\"'<>#".contains(c)).unwrap_or(query.len())];
+            let id = query
+                .split(['&', ';'])
+                .find_map(|pair| pair.strip_prefix(param.as_str()))
+                .and_then(|id| id.parse().ok());
+
+            if let Some(id) = id {
+                if !bid_ids.contains(&id) {
+                    bid_ids.push(id);
+                }
+            }
+        }
+    }
+
+    bid_ids
+}
+
+/// Return the URL of a bid's detail page on the default WEBS host.
+pub(crate) fn detail_page_url(bid_id: u64) -> String {
+    format!("{DEFAULT_WEBS_BASE_URL}{DETAIL_PATH}?{WEBS_DETAIL_ID_PARAM}{bid_id}")
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{detail_page_url, parse_bid_ids},
+        crate::mail::MailMessage,
+    };
+
+    #[test]
+    fn notification_emails() {
+        const EMAIL: &str = "From: webs-noreply@des.wa.gov\r\n\
+            Subject: New bid opportunities\r\n\
+            Content-Type: text/html; charset=\"utf-8\"\r\n\
+            Content-Transfer-Encoding: quoted-printable\r\n\
+            \r\n\
+            <a href=3D\"https://pr-webs-vendor.des.wa.gov/Search_BidDetails.aspx?ID=3D49=\r\n\
+            115\">Street Paving</a><br>\r\n\
+            <a href=3D\"https://pr-webs-vendor.des.wa.gov/search_biddetails.aspx?id=3D49002&amp;x=3D1\">Plans</a>\r\n\
+            Again: https://pr-webs-vendor.des.wa.gov/Search_BidDetails.aspx?ID=3D49115\r\n\
+            Not a bid: https://pr-webs-vendor.des.wa.gov/Search_BidDetails.aspx?ID=3Dabc\r\n";
+
+        assert_eq!(parse_bid_ids(&MailMessage::parse(EMAIL.as_bytes())), [49115, 49002]);
+        assert!(parse_bid_ids(&MailMessage::parse(b"Subject: Welcome\n\nThank you for registering.\n")).is_empty());
+        assert_eq!(detail_page_url(49115), "https://pr-webs-vendor.des.wa.gov/Search_BidDetails.aspx?ID=49115");
+    }
+}
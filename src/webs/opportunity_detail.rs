@@ -0,0 +1,152 @@
+//! WEBS opportunity detail page handling.
+//!
+//! Each field of the detail page is a `<span>` with a fixed id inside the details table. Commodity codes are listed
+//! one per line (`952-43 - Family and Social Services`), separated by `<br>` tags; counties are a single
+//! comma-separated list.
+use {
+    crate::{
+        award::{page_awards, US_DOLLARS},
+        opportunity::{clean_text, parse_date, parse_date_time, text_lines, Contact, DateLocale, Opportunity},
+        shapes::SUBSYS_WEBS,
+        soup::{NodeExt, QueryBuilderExt},
+        BoxError,
+    },
+    log::*,
+    markup5ever_rcdom::{Handle, RcDom},
+    reqwest::Url,
+};
+
+const WEBS_ID_REFERENCE_NUMBER: &str = "txtReferenceNumber";
+const WEBS_ID_ORG_NAME: &str = "txtOrgName";
+const WEBS_ID_TITLE: &str = "txtTitle";
+const WEBS_ID_DESCRIPTION: &str = "txtDescription";
+const WEBS_ID_ACTIVE_DATE: &str = "txtActiveDate";
+const WEBS_ID_INACTIVE_DATE: &str = "txtInactiveDate";
+const WEBS_ID_CONTACT_NAME: &str = "txtContactName";
+const WEBS_ID_CONTACT_PHONE: &str = "txtContactPhone";
+const WEBS_ID_EMAIL: &str = "txtEmail";
+const WEBS_ID_COMM_CODES: &str = "labelCommCodes";
+const WEBS_ID_COUNTIES: &str = "labelCounties";
+
+/// Separator between a commodity code and its description.
+const COMMODITY_CODE_SEPARATOR: &str = " - ";
+
+/// Parse an opportunity detail page into the normalized model.
+///
+/// Fields other than the title are optional, since WEBS leaves them blank when the issuer didn't fill them in. A page
+/// without a title is not a detail page (usually a login or error page) and is an error.
+pub(crate) fn parse_opportunity_detail_page(
+    document: &RcDom,
+    page_url: &Url,
+    date_locale: DateLocale,
+) -> Result<Opportunity, BoxError> {
+    let Some(title) = span_text(document, WEBS_ID_TITLE) else {
+        return Err(format!("WEBS opportunity title (<span id=\"{WEBS_ID_TITLE}\">) not found on {page_url}").into());
+    };
+
+    let due_at = span_text(document, WEBS_ID_INACTIVE_DATE).and_then(|text| {
+        let due_at = parse_date_time(&text, date_locale);
+        if due_at.is_none() {
+            warn!("Unparseable WEBS close date {text:?} on {page_url}");
+        }
+        due_at
+    });
+
+    let commodity_codes = span_lines(document, WEBS_ID_COMM_CODES)
+        .into_iter()
+        .map(|line| match line.split_once(COMMODITY_CODE_SEPARATOR) {
+            Some((code, _)) => code.to_string(),
+            None => line,
+        })
+        .collect();
+
+    let counties = span_text(document, WEBS_ID_COUNTIES)
+        .map(|text| text.split(',').filter_map(clean_text).collect())
+        .unwrap_or_default();
+
+    let description = span_lines(document, WEBS_ID_DESCRIPTION);
+
+    Ok(Opportunity {
+        portal: SUBSYS_WEBS.to_string(),
+        url: page_url.to_string(),
+        title,
+        reference: span_text(document, WEBS_ID_REFERENCE_NUMBER),
+        organization: span_text(document, WEBS_ID_ORG_NAME),
+        contact: Contact {
+            name: span_text(document, WEBS_ID_CONTACT_NAME),
+            email: span_text(document, WEBS_ID_EMAIL),
+            phone: span_text(document, WEBS_ID_CONTACT_PHONE),
+        },
+        published_on: span_text(document, WEBS_ID_ACTIVE_DATE)
+            .as_deref()
+            .and_then(|text| parse_date(text, date_locale)),
+        due_at,
+        description: if description.is_empty() {
+            None
+        } else {
+            Some(description.join("\n"))
+        },
+        commodity_codes,
+        counties,
+        documents: vec![],
+        awards: page_awards(document, date_locale, US_DOLLARS),
+        ..Opportunity::default()
+    })
+}
+
+/// Return the `<span>` with the given id.
+fn find_span(document: &RcDom, id: &str) -> Option<Handle> {
+    let span = document.tag("span").attr("id", id).find();
+    if span.is_none() {
+        debug!("WEBS <span id=\"{id}\"> not found");
+    }
+    span
+}
+
+/// Return the cleaned text of the `<span>` with the given id, if it exists and isn't blank.
+fn span_text(document: &RcDom, id: &str) -> Option<String> {
+    find_span(document, id).and_then(|span| clean_text(&span.text()))
+}
+
+/// Return the lines of text in the `<span>` with the given id, split at `<br>` tags, omitting blank lines.
+fn span_lines(document: &RcDom, id: &str) -> Vec<String> {
+    find_span(document, id).map(|span| text_lines(&span)).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::parse_opportunity_detail_page,
+        crate::{opportunity::DateLocale, soup::parse_html_str},
+        chrono::NaiveDate,
+        reqwest::Url,
+    };
+
+    #[test]
+    fn detail_page() {
+        let page = include_str!("webs-opp-detail1.html");
+        let url = Url::parse("https://pr-webs-vendor.des.wa.gov/Search_BidDetails.aspx?ID=49115").unwrap();
+        let opportunity = parse_opportunity_detail_page(&parse_html_str(page), &url, DateLocale::English).unwrap();
+
+        assert_eq!(opportunity.title, "Alternate Payment Options for the DSHS/Division of Child Support");
+        assert_eq!(opportunity.reference.as_deref(), Some("1745-662-REPOST"));
+        assert_eq!(opportunity.organization.as_deref(), Some("Social and Health Services, Department of"));
+        assert_eq!(opportunity.contact.name.as_deref(), Some("Mario Sosa"));
+        assert_eq!(opportunity.contact.email.as_deref(), Some("mario.sosa@dshs.wa.gov"));
+        assert_eq!(opportunity.contact.phone.as_deref(), Some("(360) 764-9666"));
+        assert_eq!(opportunity.published_on, NaiveDate::from_ymd_opt(2022, 11, 16));
+        assert_eq!(opportunity.due_at, NaiveDate::from_ymd_opt(2027, 11, 15).unwrap().and_hms_opt(23, 59, 59));
+        assert!(opportunity.description.unwrap().starts_with("The Division of Child Support(DCS) is seeking Vendors"));
+        assert_eq!(opportunity.commodity_codes, vec!["952-43", "946-10", "946-35"]);
+        assert_eq!(opportunity.counties.len(), 39);
+        assert_eq!(opportunity.counties[0], "Adams");
+        assert_eq!(opportunity.counties[38], "Yakima");
+
+        let error = parse_opportunity_detail_page(
+            &parse_html_str("<html><body>Login</body></html>"),
+            &url,
+            DateLocale::English,
+        );
+        assert!(error.is_err());
+    }
+}
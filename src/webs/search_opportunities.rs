@@ -2,11 +2,13 @@
 use {
     crate::{
         httpext::{Client, Form, Response as HttpResponse, ResponseExt},
+        pagination::{self, FormEvent, Pagination},
         shapes::{CrawlParameters, NextRequest, Operation},
         soup::{NodeExt, QueryBuilderExt},
-        webs::{FormEvent, WebsOperation},
+        webs::{availability, WebsOperation},
         BoxError,
     },
+    chrono::NaiveDate,
     log::*,
     markup5ever_rcdom::RcDom,
     reqwest::Url,
@@ -14,15 +16,65 @@ use {
 
 const WEBS_RAD_COMM_CODES_PARAM: &str = "radCommCodes";
 const WEBS_RAD_COUNTIES_PARAM: &str = "radCounties";
+const WEBS_POSTED_FROM_PARAM: &str = "textBoxPostedDateFrom";
+const WEBS_DATE_FORMAT: &str = "%m/%d/%Y";
 
-const WEBS_CLASS_GRID3FILE1: &str = "Grid3File1";
-const WEBS_CLASS_GRID3FILE2: &str = "Grid3File2";
 const WEBS_CLASS_GRID3PAGER: &str = "Grid3Pager";
-const WEBS_OPPORTUNITY_CLASSES: &[&str] = &[WEBS_CLASS_GRID3FILE1, WEBS_CLASS_GRID3FILE2];
-const WEBS_CLASS_CTEXT_HYPERLINK: &str = "ctext-hyperlink";
+const WEBS_ID_BID_COUNT: &str = "lblBidCount";
 
-/// Submit the search opportunities form to the WEBS portal.
-pub(crate) async fn submit_search_opps(client: &Client, response: HttpResponse) -> Result<HttpResponse, BoxError> {
+/// Search results are paged by postbacks from a numbered pager, showing a window of pages at a time.
+pub(crate) const PAGINATION: Pagination = Pagination::Postback {
+    pager_class: WEBS_CLASS_GRID3PAGER,
+};
+
+/// The page opportunity detail links lead to, used to spot them when no layout matches.
+pub(crate) const WEBS_DETAIL_PAGE: &str = "search_biddetails.aspx";
+
+/// Name logged when opportunities are found by the fallback heuristic rather than a registered layout.
+const WEBS_HEURISTIC_LAYOUT: &str = "Heuristic";
+
+/// How opportunities are laid out in a search results grid.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ListingLayout {
+    /// Name of the layout, for logging.
+    pub name: &'static str,
+
+    /// Class names of the `<tr>` elements holding one opportunity each.
+    pub row_classes: &'static [&'static str],
+
+    /// Class name of the detail page link within a row, or `None` for any link.
+    pub link_class: Option<&'static str>,
+
+    /// Index of the `<td>` holding the detail page link, or `None` to search the whole row.
+    pub link_cell: Option<usize>,
+}
+
+/// The results grid layouts WEBS is known to serve, in the order they're tried.
+///
+/// WEBS occasionally serves an alternate grid with different class names in place of the usual one; each is listed
+/// here so neither yields an empty listing.
+pub(crate) const LISTING_LAYOUTS: &[ListingLayout] = &[
+    ListingLayout {
+        name: "Grid3",
+        row_classes: &["Grid3File1", "Grid3File2"],
+        link_class: Some("ctext-hyperlink"),
+        link_cell: Some(0),
+    },
+    ListingLayout {
+        name: "ResultsGrid",
+        row_classes: &["ResultsGridRow", "ResultsGridAltRow"],
+        link_class: Some("results-link"),
+        link_cell: None,
+    },
+];
+
+/// Submit the search opportunities form to the WEBS portal, limited to opportunities posted or amended on or after
+/// `posted_since` if it's set.
+pub(crate) async fn submit_search_opps(
+    client: &Client,
+    response: HttpResponse,
+    posted_since: Option<NaiveDate>,
+) -> Result<HttpResponse, BoxError> {
     let url = response.url().clone();
     debug!("WEBS search opps form URL: {url}");
 
@@ -46,7 +98,22 @@ pub(crate) async fn submit_search_opps(client: &Client, response: HttpResponse)
     form.set(WEBS_RAD_COMM_CODES_PARAM, "1");
     form.set(WEBS_RAD_COUNTIES_PARAM, "1");
 
-    let response = match client.request(form.method, form.url).form(&form.fields).send().await.error_for_status() {
+    if let Some(posted_since) = posted_since {
+        if set_posted_since(&mut form, posted_since) {
+            info!("Searching WEBS opportunities posted or amended since {posted_since}");
+        } else {
+            warn!("WEBS search form has no {WEBS_POSTED_FROM_PARAM} field; searching all opportunities");
+        }
+    }
+
+    let response = match client
+        .request(form.method, form.url)
+        .form(&form.fields)
+        .send()
+        .await
+        .and_then(availability::check_available)
+        .error_for_status()
+    {
         Ok(r) => r,
         Err(e) => {
             error!("Failed to submit WEBS search opps form: {e}");
@@ -57,102 +124,164 @@ pub(crate) async fn submit_search_opps(client: &Client, response: HttpResponse)
     Ok(response)
 }
 
+/// Fill in the search form's posted date filter, returning `false` if the form doesn't have one.
+fn set_posted_since(form: &mut Form, posted_since: NaiveDate) -> bool {
+    if !form.fields.contains_key(WEBS_POSTED_FROM_PARAM) {
+        return false;
+    }
+
+    form.set(WEBS_POSTED_FROM_PARAM, posted_since.format(WEBS_DATE_FORMAT).to_string());
+    true
+}
+
 /// Parse an opportunity listing page and insert next requests for each opportunity detail page.
+///
+/// Each of the [`LISTING_LAYOUTS`] is tried in turn and the first to yield any opportunities is used. If none does,
+/// every table row linking to a detail page is taken instead, so an unfamiliar grid still yields opportunities; the
+/// layout that matched is logged either way.
 pub(crate) fn parse_opportunity_listing_page(
     document: &RcDom,
     page_url: &Url,
     crawl_parameters: &CrawlParameters,
     next_requests: &mut Vec<NextRequest>,
 ) -> Result<(), BoxError> {
-    // Each opportunity is in a <tr> with class name Grid3File1 or Grid3File2.
-    for opp_class in WEBS_OPPORTUNITY_CLASSES {
-        for opp_tr in document.tag("tr").class(*opp_class).find_all() {
-            let Some(a) = opp_tr.tag("a").class(WEBS_CLASS_CTEXT_HYPERLINK).find() else {
-                warn!("No hyperlink found for opportunity: {opp_tr:?}");
-                continue;
-            };
+    let (layout, hrefs) = match LISTING_LAYOUTS.iter().find_map(|layout| {
+        let hrefs = layout_links(document, layout);
+        (!hrefs.is_empty()).then_some((layout.name, hrefs))
+    }) {
+        Some(found) => found,
+        None => (WEBS_HEURISTIC_LAYOUT, heuristic_links(document)),
+    };
 
-            let Some(href) = a.get("href") else {
-                warn!("Opportunity link missing href attribute: {a:?}");
-                continue;
-            };
+    if hrefs.is_empty() {
+        warn!("No opportunities found on WEBS listing page {page_url}");
+        return Ok(());
+    }
 
-            let Ok(opp_url) = page_url.join(&href) else {
-                warn!("Failed to parse opportunity URL: {href}");
-                continue;
-            };
+    info!("WEBS listing page {page_url} matched layout {layout} with {} opportunities", hrefs.len());
+
+    for href in hrefs {
+        let Ok(opp_url) = page_url.join(&href) else {
+            warn!("Failed to parse opportunity URL: {href}");
+            continue;
+        };
 
-            next_requests.push(NextRequest {
-                operation: Operation::Webs(WebsOperation::FetchOpportunityDetailPage),
-                url: Some(opp_url.to_string()),
-                crawl: crawl_parameters.clone(),
-            })
+        if opp_url.host_str() != page_url.host_str() {
+            warn!("Ignoring opportunity link to another host: {opp_url}");
+            continue;
         }
+
+        next_requests.push(detail_page_request(opp_url.to_string(), crawl_parameters))
     }
 
     Ok(())
 }
 
-pub(crate) fn find_opportunity_next_pages(document: &RcDom) -> Result<Vec<FormEvent>, BoxError> {
-    let mut next_pages = vec![];
+/// Return the detail page links of the opportunities laid out as described by `layout`.
+fn layout_links(document: &RcDom, layout: &ListingLayout) -> Vec<String> {
+    let mut hrefs = vec![];
 
-    // The pager links are within a <tr> with class Grid3Pager, and are <a> elements with an href
-    // similar to "javascript:__doPostBack(&#39;DataGrid1$_ctl104$_ctl2&#39;,&#39;&#39;)"
-    for tr in document.tag("tr").class(WEBS_CLASS_GRID3PAGER).find_all() {
-        for a in tr.tag("a").find_all() {
-            let Some(href) = a.get("href") else {
-                warn!("No href attribute found for pager link: {a:?}");
-                continue;
+    for row_class in layout.row_classes {
+        for opp_tr in document.tag("tr").class(*row_class).find_all() {
+            let container = match layout.link_cell {
+                Some(index) => match opp_tr.tag("td").find_all().nth(index) {
+                    Some(td) => td,
+                    None => {
+                        warn!("Opportunity row has no cell {index} in layout {}: {opp_tr:?}", layout.name);
+                        continue;
+                    }
+                },
+                None => opp_tr.clone(),
             };
 
-            let post_back_args = match href.strip_prefix("javascript:__doPostBack(").and_then(|s| s.strip_suffix(')')) {
-                Some(s) => s,
-                None => {
-                    warn!("Unexpected pager link href: {href}");
-                    continue;
-                }
+            let link = match layout.link_class {
+                Some(link_class) => container.tag("a").class(link_class).find(),
+                None => container.tag("a").find(),
             };
 
-            // If this is still URL encoded, decode it.
-            let post_back_args = post_back_args.replace("&#39;", "'");
-
-            let parts: Vec<&str> = post_back_args.split(',').collect();
-            if parts.len() != 2 {
-                warn!("Unexpected pager link format: {post_back_args}");
-                continue;
-            }
-
-            let event_target = parts[0].strip_prefix('\'').and_then(|s| s.strip_suffix('\''));
-            let event_argument = parts[1].strip_prefix('\'').and_then(|s| s.strip_suffix('\''));
-
-            let Some(event_target) = event_target else {
-                warn!("Unexpected pager link format: {post_back_args}");
+            let Some(a) = link else {
+                warn!("No hyperlink found for opportunity: {opp_tr:?}");
                 continue;
             };
 
-            let Some(event_argument) = event_argument else {
-                warn!("Unexpected pager link format: {post_back_args}");
+            let Some(href) = a.get("href") else {
+                warn!("Opportunity link missing href attribute: {a:?}");
                 continue;
             };
-            next_pages.push(FormEvent {
-                target: event_target.to_string(),
-                argument: event_argument.to_string(),
-            });
+
+            hrefs.push(href);
+        }
+    }
+
+    hrefs
+}
+
+/// Return the links to opportunity detail pages found in any table row, in document order and without duplicates.
+fn heuristic_links(document: &RcDom) -> Vec<String> {
+    let mut hrefs: Vec<String> = vec![];
+
+    for a in document.tag("tr").find_all().flat_map(|tr| tr.tag("a").find_all().collect::<Vec<_>>()) {
+        let Some(href) = a.get("href") else {
+            continue;
+        };
+
+        if href.to_ascii_lowercase().contains(WEBS_DETAIL_PAGE) && !hrefs.contains(&href) {
+            hrefs.push(href);
+        }
+    }
+
+    hrefs
+}
+
+/// Parse the total number of records the search returned ("Total number of records returned = N").
+///
+/// Returns `None` if the count is missing or not a number.
+pub(crate) fn parse_result_count(document: &RcDom) -> Option<usize> {
+    let Some(span) = document.tag("span").attr("id", WEBS_ID_BID_COUNT).find() else {
+        warn!("WEBS result count (<span id=\"{WEBS_ID_BID_COUNT}\">) not found");
+        return None;
+    };
+
+    let text = span.text();
+    match text.trim().replace(',', "").parse() {
+        Ok(count) => Some(count),
+        Err(_) => {
+            warn!("WEBS result count is not a number: {text:?}");
+            None
         }
     }
+}
+
+/// Create the next request for an opportunity detail page.
+pub(crate) fn detail_page_request(url: String, crawl_parameters: &CrawlParameters) -> NextRequest {
+    NextRequest {
+        operation: Operation::Webs(WebsOperation::FetchOpportunityDetailPage),
+        url: Some(url),
+        link_text: None,
+        crawl: crawl_parameters.child(),
+        delay_seconds: 0,
+    }
+}
+
+/// Return the postback events for the pager links visible on a results page.
+pub(crate) fn find_opportunity_next_pages(document: &RcDom) -> Result<Vec<FormEvent>, BoxError> {
+    Ok(pagination::pager_links(document, WEBS_CLASS_GRID3PAGER).into_iter().map(|link| link.event).collect())
+}
 
-    Ok(next_pages)
+/// Return the current page number, shown as unlinked text in the pager.
+pub(crate) fn current_page(document: &RcDom) -> Option<usize> {
+    pagination::current_page(document, WEBS_CLASS_GRID3PAGER)
 }
 
 #[cfg(test)]
 mod tests {
     use {
-        super::{find_opportunity_next_pages, parse_opportunity_listing_page},
-        crate::{
-            httpext::CookieStore,
-            shapes::{default_user_agent, CrawlParameters},
-            soup::parse_html_str,
+        super::{
+            current_page, find_opportunity_next_pages, parse_opportunity_listing_page, parse_result_count,
+            set_posted_since, PAGINATION,
         },
+        crate::{httpext::Form, shapes::CrawlParameters, soup::parse_html_str},
+        chrono::NaiveDate,
         reqwest::Url,
     };
 
@@ -164,12 +293,12 @@ mod tests {
         let url = Url::parse("https://pr-webs-vendor.des.wa.gov/Search_Bid.aspx").unwrap();
         let crawl_parameters = CrawlParameters {
             crawl_id: Some("test".to_string()),
-            user_agent: default_user_agent(),
-            cookies: CookieStore::default(),
+            ..CrawlParameters::default()
         };
 
         parse_opportunity_listing_page(&document, &url, &crawl_parameters, &mut next_requests).unwrap();
         assert_eq!(next_requests.len(), 100);
+        assert_eq!(parse_result_count(&document), Some(253));
 
         let form_events = find_opportunity_next_pages(&document).unwrap();
         assert_eq!(form_events.len(), 2);
@@ -178,5 +307,58 @@ mod tests {
         assert_eq!(form_events[0].argument, "");
         assert_eq!(form_events[1].target, "DataGrid1$_ctl104$_ctl2");
         assert_eq!(form_events[1].argument, "");
+
+        assert_eq!(current_page(&document), Some(1));
+        assert_eq!(PAGINATION.next_postback(&document, &url), Some(form_events[0].clone()));
+    }
+
+    #[test_log::test]
+    fn alternate_layouts() {
+        let url = Url::parse("https://pr-webs-vendor.des.wa.gov/Search_Bid.aspx").unwrap();
+        let crawl_parameters = CrawlParameters::default();
+        let parse = |html: &str| {
+            let mut next_requests = vec![];
+            parse_opportunity_listing_page(&parse_html_str(html), &url, &crawl_parameters, &mut next_requests).unwrap();
+            next_requests.into_iter().map(|request| request.url.unwrap()).collect::<Vec<_>>()
+        };
+
+        let results_grid = r#"<table>
+            <tr class="ResultsGridRow"><td>Bid 1</td><td><a class="results-link" href="Search_BidDetails.aspx?ID=1">View</a></td></tr>
+            <tr class="ResultsGridAltRow"><td>Bid 2</td><td><a class="results-link" href="Search_BidDetails.aspx?ID=2">View</a></td></tr>
+        </table>"#;
+        assert_eq!(
+            parse(results_grid),
+            vec![
+                "https://pr-webs-vendor.des.wa.gov/Search_BidDetails.aspx?ID=1",
+                "https://pr-webs-vendor.des.wa.gov/Search_BidDetails.aspx?ID=2",
+            ]
+        );
+
+        // An unknown grid falls back to any row linking to a detail page, ignoring other links and duplicates.
+        let unknown = r#"<table>
+            <tr class="Row"><td><a href="search_biddetails.aspx?ID=3">Bid 3</a></td><td><a href="search_biddetails.aspx?ID=3">View</a></td></tr>
+            <tr class="Row"><td><a href="Help.aspx">Help</a></td><td><a href="https://other.gov/Search_BidDetails.aspx?ID=4">Bid 4</a></td></tr>
+        </table>"#;
+        assert_eq!(parse(unknown), vec!["https://pr-webs-vendor.des.wa.gov/search_biddetails.aspx?ID=3"]);
+
+        assert!(parse("<table><tr><td>No results</td></tr></table>").is_empty());
+    }
+
+    #[test_log::test]
+    fn posted_since() {
+        let url = Url::parse("https://pr-webs-vendor.des.wa.gov/Search_Bid.aspx").unwrap();
+        let since = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+
+        let html = r#"<form name="Form1" method="post"><input type="text" name="textBoxPostedDateFrom"></form>"#;
+        let mut form = Form::from_unparsed_form_name(&url, html, "Form1").unwrap();
+        assert!(set_posted_since(&mut form, since));
+        assert_eq!(form.fields["textBoxPostedDateFrom"], "05/01/2024");
+
+        // Forms without the filter are left alone, so the search falls back to every opportunity.
+        const START: &str = include_str!("webs-search-bids-start.html");
+        let mut form = Form::from_unparsed_form_name(&url, START, "Form1").unwrap();
+        let fields = form.fields.clone();
+        assert!(!set_posted_since(&mut form, since));
+        assert_eq!(form.fields, fields);
     }
 }
@@ -0,0 +1,48 @@
+//! Tests of the library's public surface, as companion tools use it.
+use {
+    chrono::NaiveDate,
+    govscout_backend::{
+        opportunity::{parse_date, DateLocale},
+        shapes::normalize_url,
+        soup::{parse_html_str, structured_data::structured_data, NodeExt, QueryBuilderExt},
+        Operation, Opportunity, Request,
+    },
+    reqwest::Url,
+    std::str::FromStr,
+};
+
+/// Requests and operations read and written the way the queue carries them.
+#[test]
+fn requests_and_operations() {
+    let request: Request =
+        serde_json::from_str(r#"{"Operation": "WaLocal:StartCrawl", "Url": "https://example.gov/bids"}"#).unwrap();
+    assert_eq!(request.url.as_deref(), Some("https://example.gov/bids"));
+
+    let operation = Operation::from_str(&request.operation).unwrap();
+    assert_eq!(operation.subsystem(), "WaLocal");
+    assert_eq!(operation.operation(), "StartCrawl");
+    assert_eq!(serde_json::to_string(&operation).unwrap(), r#""WaLocal:StartCrawl""#);
+    assert!(Operation::from_str("Nowhere:StartCrawl").is_err());
+
+    assert_eq!(normalize_url("https://Example.gov/bids/#top").unwrap(), "https://example.gov/bids/");
+}
+
+/// Pages parsed into opportunities with the crawler's HTML and date helpers.
+#[test]
+fn parsing_pages() {
+    let document = parse_html_str(
+        r#"<div itemscope itemtype="https://schema.org/Demand">
+             <h1 itemprop="name">Street Paving</h1>
+             <meta itemprop="validThrough" content="2022-11-30T14:00:00">
+           </div>"#,
+    );
+    assert_eq!(document.document.tag("h1").find().map(|h1| h1.text()).as_deref(), Some("Street Paving"));
+
+    let url = Url::parse("https://example.gov/bids/22-015").unwrap();
+    let opportunity =
+        Opportunity::from_structured_data(&structured_data(&document), &url, DateLocale::English).unwrap();
+    assert_eq!(opportunity.title, "Street Paving");
+    assert_eq!(opportunity.due_at, NaiveDate::from_ymd_opt(2022, 11, 30).and_then(|date| date.and_hms_opt(14, 0, 0)));
+
+    assert_eq!(parse_date("2 novembre 2022", DateLocale::French), NaiveDate::from_ymd_opt(2022, 11, 2));
+}